@@ -16,10 +16,31 @@ pub mod proxy_pool;
 // 导出 user_token 命令
 pub mod user_token;
 
+/// 账号列表项：附带按当前预热策略计算的剩余预热时间 (仅返回给前端，不持久化)
+#[derive(serde::Serialize)]
+pub struct AccountListItem {
+    #[serde(flatten)]
+    pub account: Account,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warmup_remaining_secs: Option<i64>,
+}
+
 /// 列出所有账号
 #[tauri::command]
-pub async fn list_accounts() -> Result<Vec<Account>, String> {
-    modules::list_accounts()
+pub async fn list_accounts() -> Result<Vec<AccountListItem>, String> {
+    let warmup = crate::proxy::config::get_account_warmup_config();
+    let now = chrono::Utc::now().timestamp();
+    Ok(modules::list_accounts()?
+        .into_iter()
+        .map(|account| AccountListItem {
+            warmup_remaining_secs: crate::proxy::account_warmup::remaining_secs(
+                &warmup,
+                account.created_at,
+                now,
+            ),
+            account,
+        })
+        .collect())
 }
 
 /// 添加账号
@@ -313,6 +334,20 @@ pub async fn delete_device_version(account_id: String, version_id: String) -> Re
     modules::delete_device_version(&account_id, &version_id)
 }
 
+/// 检查设备指纹一致性 (被多个账号共用的指纹)
+#[tauri::command]
+pub async fn check_device_profile_consistency(
+) -> Result<modules::device_consistency::DeviceConsistencyReport, String> {
+    modules::device_consistency::check_and_persist()
+}
+
+/// 为所有指纹冲突的账号重新生成唯一指纹 (需用户显式触发)
+#[tauri::command]
+pub async fn regenerate_collided_device_profiles(
+) -> Result<modules::device_consistency::RegenerationResult, String> {
+    modules::device_consistency::regenerate_collided_profiles()
+}
+
 /// 打开设备存储目录
 #[tauri::command]
 pub async fn open_device_folder(app: tauri::AppHandle) -> Result<(), String> {
@@ -344,6 +379,9 @@ pub async fn save_config(
     // 通知托盘配置已更新
     let _ = app.emit("config://updated", ());
 
+    // [NEW] 实时日志视图的缓冲区大小 (日志文件轮转与格式在重启后生效)
+    modules::log_bridge::set_buffer_capacity(config.logging.buffer_size);
+
     // 热更新正在运行的服务
     let instance_lock = proxy_state.instance.read().await;
     if let Some(instance) = instance_lock.as_ref() {
@@ -370,12 +408,76 @@ pub async fn save_config(
             .await;
         // [NEW] 更新 User-Agent 配置
         instance.axum_server.update_user_agent(&config.proxy).await;
+        // [NEW] 更新上游基础 URL 覆盖
+        instance.axum_server.update_upstream_base_url(&config.proxy).await;
+        // [NEW] 同步监听配置档 (额外端口)
+        instance.axum_server.update_listener_profiles(&config.proxy).await;
         // 更新 Thinking Budget 配置
         crate::proxy::update_thinking_budget_config(config.proxy.thinking_budget.clone());
         // [NEW] 更新全局系统提示词配置
         crate::proxy::update_global_system_prompt_config(config.proxy.global_system_prompt.clone());
         // [NEW] 更新全局图像思维模式配置
         crate::proxy::update_image_thinking_mode(config.proxy.image_thinking_mode.clone());
+        // [NEW] 更新图像模型误映射回退配置
+        crate::proxy::update_image_text_fallback_model(config.proxy.image_text_fallback_model.clone());
+        // [NEW] 更新强制回复语言
+        crate::proxy::update_forced_response_language(config.proxy.forced_response_language.clone());
+        // [NEW] 更新图像多模态输出配置
+        crate::proxy::update_image_multimodal_output(config.proxy.image_multimodal_output);
+        // [NEW] 更新角色交替 (消息合并) 配置
+        crate::proxy::update_strict_role_alternation(config.proxy.strict_role_alternation);
+        // [NEW] 更新代码执行 part 处理配置
+        crate::proxy::update_drop_code_execution_parts(config.proxy.drop_code_execution_parts);
+        // [NEW] 更新联网搜索引文渲染样式
+        crate::proxy::update_citation_style(config.proxy.citation_style);
+        // [NEW] 更新工具数量上限配置
+        crate::proxy::update_tool_limit_config(config.proxy.tool_limit.clone());
+        // [NEW] 更新信封字段清洗策略
+        crate::proxy::update_envelope_sanitize_action(config.proxy.envelope_sanitize_action);
+        // [NEW] 更新首字延迟 SLO 配置
+        crate::proxy::update_latency_slo_config(config.proxy.latency_slo);
+        // [NEW] 更新账号轮换次数配置
+        crate::proxy::update_max_account_rotations(config.proxy.max_account_rotations);
+        // [NEW] 更新每账号并发流上限
+        crate::proxy::update_max_concurrent_streams_per_account(config.proxy.max_concurrent_streams_per_account);
+        // [NEW] 更新会话空闲回收 TTL
+        crate::proxy::update_session_idle_ttl_secs(config.proxy.session_idle_ttl_secs);
+        // [NEW] 更新流式中断续写开关
+        crate::proxy::update_stream_resumption(config.proxy.stream_resumption);
+        // [NEW] 更新思考耗尽追问开关
+        crate::proxy::update_thinking_nudge(config.proxy.thinking_loop_nudge);
+        // [NEW] 更新毒消息隔离开关
+        crate::proxy::update_poison_quarantine(config.proxy.poison_quarantine);
+        // [NEW] 更新历史思考剥离模型列表
+        crate::proxy::update_strip_historical_thinking_models(config.proxy.strip_historical_thinking_models.clone());
+        // [NEW] 更新 JSON 递归清理深度上限
+        crate::proxy::update_max_json_clean_depth(config.proxy.max_json_clean_depth);
+        // [NEW] 更新 token 预刷新提前量
+        crate::proxy::update_token_refresh_ahead_secs(config.proxy.token_refresh_ahead_secs);
+        // [NEW] 更新近期失败账号回避窗口
+        crate::proxy::update_recent_failure_window_secs(config.proxy.recent_failure_window_secs);
+        // [NEW] 更新新账号预热策略
+        crate::proxy::update_account_warmup_config(config.proxy.account_warmup.clone());
+        // [NEW] 更新占位思考块注入配置
+        crate::proxy::update_dummy_thought_config(config.proxy.dummy_thought.clone());
+        // [NEW] 更新重复任务文本去重配置
+        crate::proxy::update_task_echo_dedup_config(config.proxy.task_echo_dedup);
+        // [NEW] 更新联网搜索 usage 上报开关
+        crate::proxy::update_report_web_search_usage(config.proxy.report_web_search_usage);
+        // [NEW] 更新流式 delta 合并配置
+        crate::proxy::update_delta_coalescing_config(config.proxy.delta_coalescing);
+        // [NEW] 更新 requestId 前缀
+        crate::proxy::update_request_id_prefix_config(config.proxy.request_id_prefix.clone());
+        // [NEW] 更新保护性停止序列
+        crate::proxy::update_protective_stop_sequences(config.proxy.protective_stop_sequences.clone());
+        // [NEW] 更新流式脱敏规则
+        crate::proxy::update_stream_redactions(config.proxy.stream_redactions.clone());
+        // [NEW] 更新自定义客户端配置档
+        crate::proxy::update_client_profiles(config.proxy.client_profiles.clone());
+        // [NEW] 更新功能开关
+        crate::proxy::update_feature_flags(config.proxy.feature_flags.clone());
+        // [NEW] 更新 API Key -> 账号分组映射
+        crate::proxy::update_api_key_groups(config.proxy.api_key_groups.clone());
         // 更新代理池配置
         instance
             .axum_server
@@ -415,6 +517,14 @@ pub async fn start_oauth_login(app_handle: tauri::AppHandle) -> Result<Account,
     Ok(account)
 }
 
+/// [NEW] 准备重新授权 (如 OAuth scope 不足)，下一次生成的授权链接会预填该账号邮箱
+#[tauri::command]
+pub async fn prepare_reauth_oauth(email: String) -> Result<(), String> {
+    modules::logger::log_info(&format!("准备重新授权账号: {}", email));
+    modules::oauth_server::set_reauth_login_hint(Some(email));
+    Ok(())
+}
+
 /// 完成 OAuth 授权（不自动打开浏览器）
 #[tauri::command]
 pub async fn complete_oauth_login(app_handle: tauri::AppHandle) -> Result<Account, String> {
@@ -529,6 +639,32 @@ pub async fn import_custom_db(
     Ok(account)
 }
 
+/// 从 CSV / JSON 文件批量导入 refresh_token
+#[tauri::command]
+pub async fn import_refresh_tokens(
+    app: tauri::AppHandle,
+    proxy_state: tauri::State<'_, crate::commands::proxy::ProxyServiceState>,
+    path: String,
+    format: Option<String>,
+) -> Result<modules::token_import::ImportReport, String> {
+    let report = modules::token_import::import_refresh_tokens(&path, format.as_deref()).await?;
+
+    if !report.imported.is_empty() || !report.updated.is_empty() {
+        crate::modules::tray::update_tray_menus(&app);
+        let _ = crate::commands::proxy::reload_proxy_accounts(proxy_state).await;
+    }
+
+    Ok(report)
+}
+
+/// 清理残留临时文件，并报告未被索引引用的账号文件
+#[tauri::command]
+pub async fn cleanup_orphan_files(
+    dry_run: Option<bool>,
+) -> Result<modules::account::OrphanCleanupReport, String> {
+    modules::account::cleanup_orphans(dry_run.unwrap_or(false))
+}
+
 #[tauri::command]
 pub async fn sync_account_from_db(
     app: tauri::AppHandle,
@@ -787,41 +923,23 @@ pub async fn toggle_proxy_status(
         if enable { "启用" } else { "禁用" }
     ));
 
-    // 1. 读取账号文件
-    let data_dir = modules::account::get_data_dir()?;
-    let account_path = data_dir
-        .join("accounts")
-        .join(format!("{}.json", account_id));
-
-    if !account_path.exists() {
-        return Err(format!("账号文件不存在: {}", account_id));
-    }
-
-    let content =
-        std::fs::read_to_string(&account_path).map_err(|e| format!("读取账号文件失败: {}", e))?;
-
-    let mut account_json: serde_json::Value =
-        serde_json::from_str(&content).map_err(|e| format!("解析账号文件失败: {}", e))?;
-
-    // 2. 更新 proxy_disabled 字段
-    if enable {
-        // 启用反代
-        account_json["proxy_disabled"] = serde_json::Value::Bool(false);
-        account_json["proxy_disabled_reason"] = serde_json::Value::Null;
-        account_json["proxy_disabled_at"] = serde_json::Value::Null;
-    } else {
-        // 禁用反代
-        let now = chrono::Utc::now().timestamp();
-        account_json["proxy_disabled"] = serde_json::Value::Bool(true);
-        account_json["proxy_disabled_at"] = serde_json::Value::Number(now.into());
-        account_json["proxy_disabled_reason"] =
-            serde_json::Value::String(reason.unwrap_or_else(|| "用户手动禁用".to_string()));
-    }
-
-    // 3. 保存到磁盘
-    let json_str = serde_json::to_string_pretty(&account_json)
-        .map_err(|e| format!("序列化账号数据失败: {}", e))?;
-    std::fs::write(&account_path, json_str).map_err(|e| format!("写入账号文件失败: {}", e))?;
+    // 1-3. 在账号锁内读取、更新 proxy_disabled 字段并原子写回
+    modules::account::update_account(&account_id, |account| {
+        if enable {
+            // 启用反代
+            account.proxy_disabled = false;
+            account.proxy_disabled_reason = None;
+            account.proxy_disabled_at = None;
+        } else {
+            // 禁用反代
+            account.proxy_disabled = true;
+            account.proxy_disabled_at = Some(chrono::Utc::now().timestamp());
+            account.proxy_disabled_reason =
+                Some(reason.unwrap_or_else(|| "用户手动禁用".to_string()));
+        }
+        Ok(())
+    })
+    .map_err(|e| format!("更新账号文件失败: {}", e))?;
 
     modules::logger::log_info(&format!(
         "账号反代状态已更新: {} ({})",
@@ -888,33 +1006,12 @@ pub async fn update_account_label(account_id: String, label: String) -> Result<(
         if label.is_empty() { "无" } else { &label }
     ));
 
-    // 1. 读取账号文件
-    let data_dir = modules::account::get_data_dir()?;
-    let account_path = data_dir
-        .join("accounts")
-        .join(format!("{}.json", account_id));
-
-    if !account_path.exists() {
-        return Err(format!("账号文件不存在: {}", account_id));
-    }
-
-    let content =
-        std::fs::read_to_string(&account_path).map_err(|e| format!("读取账号文件失败: {}", e))?;
-
-    let mut account_json: serde_json::Value =
-        serde_json::from_str(&content).map_err(|e| format!("解析账号文件失败: {}", e))?;
-
-    // 2. 更新 custom_label 字段
-    if label.is_empty() {
-        account_json["custom_label"] = serde_json::Value::Null;
-    } else {
-        account_json["custom_label"] = serde_json::Value::String(label.clone());
-    }
-
-    // 3. 保存到磁盘
-    let json_str = serde_json::to_string_pretty(&account_json)
-        .map_err(|e| format!("序列化账号数据失败: {}", e))?;
-    std::fs::write(&account_path, json_str).map_err(|e| format!("写入账号文件失败: {}", e))?;
+    // 1-3. 在账号锁内更新 custom_label 字段并原子写回
+    modules::account::update_account(&account_id, |account| {
+        account.custom_label = (!label.is_empty()).then(|| label.clone());
+        Ok(())
+    })
+    .map_err(|e| format!("更新账号文件失败: {}", e))?;
 
     modules::logger::log_info(&format!(
         "账号标签已更新: {} ({})",
@@ -929,6 +1026,89 @@ pub async fn update_account_label(account_id: String, label: String) -> Result<(
     Ok(())
 }
 
+/// 更新账号 v1internal 信封覆盖项 (userAgent / requestType)
+/// 传入空字符串或 None 表示清除覆盖，恢复默认行为
+#[tauri::command]
+pub async fn update_account_envelope_overrides(
+    account_id: String,
+    user_agent: Option<String>,
+    request_type: Option<String>,
+) -> Result<(), String> {
+    use crate::proxy::mappers::common_utils::validate_envelope_override;
+
+    let normalize = |field: &str, value: Option<String>| -> Result<Option<String>, String> {
+        match value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty()) {
+            Some(v) => {
+                validate_envelope_override(&v).map_err(|e| format!("{}: {}", field, e))?;
+                Ok(Some(v))
+            }
+            None => Ok(None),
+        }
+    };
+    let user_agent = normalize("user_agent_override", user_agent)?;
+    let request_type = normalize("request_type_override", request_type)?;
+
+    modules::account::update_account(&account_id, |account| {
+        account.user_agent_override = user_agent.clone();
+        account.request_type_override = request_type.clone();
+        Ok(())
+    })?;
+
+    modules::logger::log_info(&format!(
+        "账号信封覆盖已更新: {} (userAgent: {:?}, requestType: {:?})",
+        account_id, user_agent, request_type
+    ));
+
+    // 通知反代服务热加载该账号，无需重启
+    crate::proxy::server::trigger_account_reload(&account_id);
+
+    Ok(())
+}
+
+/// 设置账号分组 (多团队部署下按分组隔离选号)
+/// 传入 None 或空字符串表示取消分组
+#[tauri::command]
+pub async fn update_account_group(account_id: String, group: Option<String>) -> Result<(), String> {
+    let group = match group {
+        Some(g) => crate::proxy::account_groups::normalize_group(&g)?,
+        None => None,
+    };
+
+    modules::account::set_account_group(&account_id, group.clone())?;
+
+    modules::logger::log_info(&format!("账号分组已更新: {} ({:?})", account_id, group));
+
+    // 通知反代服务热加载该账号，无需重启
+    crate::proxy::server::trigger_account_reload(&account_id);
+
+    Ok(())
+}
+
+/// 设置账号每月 token 预算
+/// 传入 None 或 0 表示取消预算限制
+#[tauri::command]
+pub async fn update_account_token_budget(
+    account_id: String,
+    monthly_token_budget: Option<u64>,
+) -> Result<(), String> {
+    let budget = monthly_token_budget.filter(|b| *b > 0);
+
+    modules::account::update_account(&account_id, |account| {
+        account.monthly_token_budget = budget;
+        Ok(())
+    })?;
+
+    modules::logger::log_info(&format!(
+        "账号月度 token 预算已更新: {} ({:?})",
+        account_id, budget
+    ));
+
+    // 通知反代服务热加载该账号，无需重启
+    crate::proxy::server::trigger_account_reload(&account_id);
+
+    Ok(())
+}
+
 // ============================================================================
 // HTTP API 设置命令
 // ============================================================================
@@ -1012,3 +1192,111 @@ pub async fn get_token_stats_account_trend_daily(
 ) -> Result<Vec<crate::modules::token_stats::AccountTrendPoint>, String> {
     crate::modules::token_stats::get_account_trend_daily(days)
 }
+
+/// 最近 N 天 (缺省 7 天) 上游实际服务的模型版本
+#[tauri::command]
+pub async fn get_model_versions(
+    days: Option<i64>,
+) -> Result<Vec<crate::modules::token_stats::ModelVersionUsage>, String> {
+    crate::modules::token_stats::get_model_versions(days.unwrap_or(7).max(1))
+}
+
+/// 解密调试抓包文件用于本地查看 (path 为输出目录内的文件)
+#[tauri::command]
+pub async fn decrypt_debug_capture(path: String) -> Result<String, String> {
+    let cfg = crate::modules::config::load_app_config()?.proxy.debug_logging;
+    tokio::task::spawn_blocking(move || crate::proxy::debug_logger::decrypt_capture(&cfg, &path))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// 轮换调试抓包加密密钥，返回新密钥 id
+#[tauri::command]
+pub async fn rotate_debug_capture_key() -> Result<u32, String> {
+    crate::proxy::debug_logger::rotate_capture_key()
+}
+
+/// 离线回放流式录制文件 (path 为输出目录内的文件)，返回客户端收到的 SSE 输出
+#[tauri::command]
+pub async fn replay_stream_recording(path: String) -> Result<String, String> {
+    let cfg = crate::modules::config::load_app_config()?.proxy.debug_logging;
+    crate::proxy::stream_recording::replay_file(&cfg, &path).await
+}
+
+/// 最近的截断工具调用处理记录 (参数修复 / 降级为文本)
+#[tauri::command]
+pub async fn get_truncated_tool_use_diagnostics(
+) -> Result<Vec<crate::proxy::mappers::claude::diagnostics::TruncatedToolUseRecord>, String> {
+    Ok(crate::proxy::mappers::claude::diagnostics::recent_truncated_tool_uses())
+}
+
+/// 最近的毒消息隔离决策记录 (预先替换 / 二分隔离 / 未找到问题消息)
+#[tauri::command]
+pub async fn get_poison_quarantine_diagnostics(
+) -> Result<Vec<crate::proxy::mappers::claude::diagnostics::PoisonQuarantineRecord>, String> {
+    Ok(crate::proxy::mappers::claude::diagnostics::recent_poison_quarantines())
+}
+
+/// 列出功能开关 (内置开关未配置时以默认值补全)
+#[tauri::command]
+pub async fn get_feature_flags() -> Result<Vec<crate::proxy::config::FeatureFlagConfig>, String> {
+    Ok(crate::proxy::flags::list_flags())
+}
+
+/// 设置功能开关并持久化，立即热更新
+#[tauri::command]
+pub async fn set_feature_flag(
+    name: String,
+    enabled: bool,
+    rollout_percent: Option<u8>,
+) -> Result<(), String> {
+    let mut app_config = crate::modules::config::load_app_config()?;
+    app_config.proxy.feature_flags = crate::proxy::flags::upsert_flag(
+        app_config.proxy.feature_flags,
+        crate::proxy::config::FeatureFlagConfig { name, enabled, rollout_percent },
+    )?;
+    crate::modules::config::save_app_config(&app_config)?;
+    crate::proxy::update_feature_flags(app_config.proxy.feature_flags);
+    Ok(())
+}
+
+/// 按账号+模型返回配额消耗速率与预计耗尽时间 (统计窗口 hours 缺省为 6 小时)
+#[tauri::command]
+pub async fn get_quota_forecasts(
+    hours: Option<i64>,
+) -> Result<Vec<crate::modules::quota_forecast::QuotaForecast>, String> {
+    crate::modules::quota_forecast::get_quota_forecasts(hours)
+}
+
+/// 立即执行代理数据库维护 (备份、按保留期清理、VACUUM / ANALYZE)，返回前后大小与行数
+#[tauri::command]
+pub async fn run_db_maintenance() -> Result<crate::modules::db_maintenance::MaintenanceReport, String> {
+    let config = crate::modules::config::load_app_config()?.db_maintenance;
+    tokio::task::spawn_blocking(move || crate::modules::db_maintenance::run_maintenance(&config))
+        .await
+        .map_err(|e| format!("Maintenance task failed: {}", e))?
+}
+
+/// 最近一次数据库维护报告
+#[tauri::command]
+pub async fn get_db_maintenance_report(
+) -> Result<Option<crate::modules::db_maintenance::MaintenanceReport>, String> {
+    Ok(crate::modules::db_maintenance::load_last_report())
+}
+
+/// 按需导出用量统计 (CSV/JSON)，返回写入的文件路径
+#[tauri::command]
+pub async fn export_usage(
+    range: crate::modules::usage_export::UsageExportRange,
+    format: crate::modules::usage_export::UsageExportFormat,
+    path: String,
+) -> Result<String, String> {
+    let mask_emails = crate::modules::config::load_app_config()?.usage_export.mask_emails;
+    let written = crate::modules::usage_export::export_usage(
+        &range,
+        format,
+        std::path::Path::new(&path),
+        mask_emails,
+    )?;
+    Ok(written.to_string_lossy().to_string())
+}
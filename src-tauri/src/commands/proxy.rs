@@ -14,6 +14,9 @@ pub struct ProxyStatus {
     pub port: u16,
     pub base_url: String,
     pub active_accounts: usize,
+    pub latency_slo: crate::proxy::latency_slo::LatencySloStatus, // [NEW] 首字延迟 SLO 状态
+    #[serde(default)]
+    pub preferred_override: Option<crate::proxy::token_manager::PreferredAccountOverride>, // [NEW] 临时优先账号
 }
 
 /// 反代服务全局状态
@@ -144,6 +147,8 @@ pub async fn internal_start_proxy_service(
 
     // 同步配置到运行中的 TokenManager
     token_manager.start_auto_cleanup().await;
+    // [NEW] 粘性绑定参与空闲会话回收
+    crate::proxy::session_registry::register_store(token_manager.clone());
     token_manager
         .update_sticky_config(config.scheduling.clone())
         .await;
@@ -176,6 +181,8 @@ pub async fn internal_start_proxy_service(
                 port: config.port,
                 base_url: format!("http://127.0.0.1:{}", config.port),
                 active_accounts: 0,
+                latency_slo: crate::proxy::latency_slo::status(),
+                preferred_override: None,
             });
         }
     }
@@ -204,6 +211,8 @@ pub async fn internal_start_proxy_service(
         port: config.port,
         base_url: format!("http://127.0.0.1:{}", config.port),
         active_accounts,
+        latency_slo: crate::proxy::latency_slo::status(),
+        preferred_override: token_manager.get_preferred_override(),
     })
 }
 
@@ -262,6 +271,10 @@ pub async fn ensure_admin_server(
         Ok((server, handle)) => (server, handle),
         Err(e) => return Err(format!("启动管理服务器失败: {}", e)),
     };
+    // [NEW] 初始化上游基础 URL 覆盖
+    axum_server.update_upstream_base_url(&config).await;
+    // [NEW] 启动监听配置档 (额外端口)
+    axum_server.update_listener_profiles(&config).await;
 
     *admin_lock = Some(AdminServerInstance {
         axum_server,
@@ -274,6 +287,66 @@ pub async fn ensure_admin_server(
     crate::proxy::update_global_system_prompt_config(config.global_system_prompt.clone());
     // [NEW] 初始化全局图像思维模式配置
     crate::proxy::update_image_thinking_mode(config.image_thinking_mode.clone());
+    // [NEW] 初始化图像模型误映射回退配置
+    crate::proxy::update_image_text_fallback_model(config.image_text_fallback_model.clone());
+    // [NEW] 初始化强制回复语言
+    crate::proxy::update_forced_response_language(config.forced_response_language.clone());
+    // [NEW] 初始化图像多模态输出配置
+    crate::proxy::update_image_multimodal_output(config.image_multimodal_output);
+    // [NEW] 初始化角色交替 (消息合并) 配置
+    crate::proxy::update_strict_role_alternation(config.strict_role_alternation);
+    // [NEW] 初始化代码执行 part 处理配置
+    crate::proxy::update_drop_code_execution_parts(config.drop_code_execution_parts);
+    // [NEW] 初始化联网搜索引文渲染样式
+    crate::proxy::update_citation_style(config.citation_style);
+    // [NEW] 初始化工具数量上限配置
+    crate::proxy::update_tool_limit_config(config.tool_limit.clone());
+    // [NEW] 初始化信封字段清洗策略
+    crate::proxy::update_envelope_sanitize_action(config.envelope_sanitize_action);
+    // [NEW] 初始化首字延迟 SLO 配置
+    crate::proxy::update_latency_slo_config(config.latency_slo);
+    // [NEW] 初始化账号轮换次数配置
+    crate::proxy::update_max_account_rotations(config.max_account_rotations);
+    // [NEW] 初始化每账号并发流上限
+    crate::proxy::update_max_concurrent_streams_per_account(config.max_concurrent_streams_per_account);
+    // [NEW] 初始化会话空闲回收 TTL
+    crate::proxy::update_session_idle_ttl_secs(config.session_idle_ttl_secs);
+    // [NEW] 初始化流式中断续写开关
+    crate::proxy::update_stream_resumption(config.stream_resumption);
+    // [NEW] 初始化思考耗尽追问开关
+    crate::proxy::update_thinking_nudge(config.thinking_loop_nudge);
+    // [NEW] 初始化毒消息隔离开关
+    crate::proxy::update_poison_quarantine(config.poison_quarantine);
+    // [NEW] 初始化历史思考剥离模型列表
+    crate::proxy::update_strip_historical_thinking_models(config.strip_historical_thinking_models.clone());
+    // [NEW] 初始化 JSON 递归清理深度上限
+    crate::proxy::update_max_json_clean_depth(config.max_json_clean_depth);
+    // [NEW] 初始化 token 预刷新提前量
+    crate::proxy::update_token_refresh_ahead_secs(config.token_refresh_ahead_secs);
+    // [NEW] 初始化近期失败账号回避窗口
+    crate::proxy::update_recent_failure_window_secs(config.recent_failure_window_secs);
+    // [NEW] 初始化新账号预热策略
+    crate::proxy::update_account_warmup_config(config.account_warmup.clone());
+    // [NEW] 初始化占位思考块注入配置
+    crate::proxy::update_dummy_thought_config(config.dummy_thought.clone());
+    // [NEW] 初始化重复任务文本去重配置
+    crate::proxy::update_task_echo_dedup_config(config.task_echo_dedup);
+    // [NEW] 初始化联网搜索 usage 上报开关
+    crate::proxy::update_report_web_search_usage(config.report_web_search_usage);
+    // [NEW] 初始化流式 delta 合并配置
+    crate::proxy::update_delta_coalescing_config(config.delta_coalescing);
+    // [NEW] 初始化 requestId 前缀
+    crate::proxy::update_request_id_prefix_config(config.request_id_prefix.clone());
+    // [NEW] 初始化保护性停止序列
+    crate::proxy::update_protective_stop_sequences(config.protective_stop_sequences.clone());
+    // [NEW] 初始化流式脱敏规则
+    crate::proxy::update_stream_redactions(config.stream_redactions.clone());
+    // [NEW] 初始化自定义客户端配置档
+    crate::proxy::update_client_profiles(config.client_profiles.clone());
+    // [NEW] 初始化功能开关
+    crate::proxy::update_feature_flags(config.feature_flags.clone());
+    // [NEW] 初始化 API Key -> 账号分组映射
+    crate::proxy::update_api_key_groups(config.api_key_groups.clone());
 
     Ok(())
 }
@@ -287,6 +360,9 @@ pub async fn stop_proxy_service(state: State<'_, ProxyServiceState>) -> Result<(
         return Err("服务未运行".to_string());
     }
 
+    // [NEW] 停止期间拒绝开始数据库维护 (与应用退出一致)
+    let _drain = crate::modules::db_maintenance::begin_drain();
+
     // 停止 Axum 服务器 (仅逻辑停止，不杀死进程)
     if let Some(instance) = instance_lock.take() {
         instance.token_manager.abort_background_tasks().await;
@@ -307,6 +383,8 @@ pub async fn get_proxy_status(state: State<'_, ProxyServiceState>) -> Result<Pro
             port: 0,
             base_url: "starting".to_string(), // 给前端标识
             active_accounts: 0,
+            latency_slo: crate::proxy::latency_slo::status(),
+            preferred_override: None,
         });
     }
 
@@ -320,12 +398,16 @@ pub async fn get_proxy_status(state: State<'_, ProxyServiceState>) -> Result<Pro
                 port: instance.config.port,
                 base_url: format!("http://127.0.0.1:{}", instance.config.port),
                 active_accounts: instance.token_manager.len(),
+                latency_slo: crate::proxy::latency_slo::status(),
+                preferred_override: instance.token_manager.get_preferred_override(),
             }),
             None => Ok(ProxyStatus {
                 running: false,
                 port: 0,
                 base_url: String::new(),
                 active_accounts: 0,
+                latency_slo: crate::proxy::latency_slo::status(),
+                preferred_override: None,
             }),
         },
         Err(_) => {
@@ -335,6 +417,8 @@ pub async fn get_proxy_status(state: State<'_, ProxyServiceState>) -> Result<Pro
                 port: 0,
                 base_url: "busy".to_string(),
                 active_accounts: 0,
+                latency_slo: crate::proxy::latency_slo::status(),
+                preferred_override: None,
             })
         }
     }
@@ -351,6 +435,12 @@ pub async fn get_proxy_stats(state: State<'_, ProxyServiceState>) -> Result<Prox
     }
 }
 
+/// [NEW] 获取空闲会话回收的累计指标
+#[tauri::command]
+pub async fn get_session_janitor_metrics() -> Result<crate::proxy::session_registry::JanitorMetrics, String> {
+    Ok(crate::proxy::session_registry::metrics())
+}
+
 /// 获取反代请求日志
 #[tauri::command]
 pub async fn get_proxy_logs(
@@ -715,6 +805,54 @@ pub async fn set_preferred_account(
     }
 }
 
+/// [NEW] 到期后清除临时优先账号并通知前端
+pub(crate) fn schedule_preferred_override_expiry(
+    token_manager: Arc<TokenManager>,
+    monitor: Option<Arc<ProxyMonitor>>,
+    expires_at: i64,
+) {
+    tokio::spawn(async move {
+        let wait = (expires_at - chrono::Utc::now().timestamp()).max(0) as u64;
+        tokio::time::sleep(Duration::from_secs(wait)).await;
+        // 期间若被重新设置为更晚的时间，这里不会清除 (由新的计时任务处理)
+        if let Some(expired) = token_manager.take_expired_override(chrono::Utc::now().timestamp()) {
+            if let Some(monitor) = monitor {
+                monitor.emit_event("proxy://preferred-account-expired", expired);
+            }
+        }
+    });
+}
+
+/// [NEW] 临时优先使用指定账号 duration_secs 秒 (非独占，仍遵守限流与配额保护)
+/// 传入 null/空字符串立即清除；不持久化，重启后失效
+#[tauri::command]
+pub async fn set_proxy_preferred_account(
+    state: State<'_, ProxyServiceState>,
+    account_id: Option<String>,
+    duration_secs: u64,
+) -> Result<Option<crate::proxy::token_manager::PreferredAccountOverride>, String> {
+    let instance_lock = state.instance.read().await;
+    let Some(instance) = instance_lock.as_ref() else {
+        return Err("服务未运行".to_string());
+    };
+
+    let Some(account_id) = account_id.filter(|s| !s.trim().is_empty()) else {
+        instance.token_manager.clear_preferred_override();
+        return Ok(None);
+    };
+    if duration_secs == 0 {
+        return Err("duration_secs must be greater than 0".to_string());
+    }
+
+    let expires_at = chrono::Utc::now().timestamp() + duration_secs as i64;
+    let entry = instance
+        .token_manager
+        .set_preferred_override(&account_id, expires_at)?;
+    let monitor = state.monitor.read().await.clone();
+    schedule_preferred_override_expiry(instance.token_manager.clone(), monitor, expires_at);
+    Ok(Some(entry))
+}
+
 /// 获取当前优先使用的账号ID
 #[tauri::command]
 pub async fn get_preferred_account(
@@ -20,6 +20,19 @@ pub struct UpdateTokenRequest {
     pub max_ips: Option<i32>,
     pub curfew_start: Option<Option<String>>,
     pub curfew_end: Option<Option<String>>,
+    /// [NEW] 每分钟请求数 / token 数上限 (0 表示不限)
+    #[serde(default)]
+    pub rate_limit_rpm: Option<Option<i64>>,
+    #[serde(default)]
+    pub rate_limit_tpm: Option<Option<i64>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MintScopedTokenRequest {
+    pub name: String,
+    pub scope: String, // "read" | "admin"
+    pub description: Option<String>,
+    pub expires_at: Option<i64>, // 过期时间戳 (秒)，None = 永不过期
 }
 
 // 命令实现
@@ -55,6 +68,8 @@ pub async fn update_user_token(id: String, request: UpdateTokenRequest) -> Resul
         request.max_ips,
         request.curfew_start,
         request.curfew_end,
+        request.rate_limit_rpm,
+        request.rate_limit_tpm,
     )
 }
 
@@ -70,6 +85,27 @@ pub async fn renew_user_token(id: String, expires_type: String) -> Result<(), St
     user_token_db::renew_token(&id, &expires_type)
 }
 
+/// 签发带权限范围的管理接口令牌
+#[tauri::command]
+pub async fn mint_scoped_token(request: MintScopedTokenRequest) -> Result<UserToken, String> {
+    user_token_db::create_scoped_token(
+        request.name,
+        &request.scope,
+        request.description,
+        request.expires_at,
+    )
+}
+
+/// 吊销管理接口令牌
+#[tauri::command]
+pub async fn revoke_scoped_token(id: String) -> Result<(), String> {
+    match user_token_db::get_token_by_id(&id)? {
+        Some(token) if token.admin_api_access => user_token_db::delete_token(&id),
+        Some(_) => Err(format!("Token {} is not a scoped admin token", id)),
+        None => Err(format!("Token {} not found", id)),
+    }
+}
+
 /// 获取令牌 IP 绑定
 #[tauri::command]
 pub async fn get_token_ip_bindings(token_id: String) -> Result<Vec<TokenIpBinding>, String> {
@@ -71,6 +71,35 @@ pub fn run() {
         error!("Failed to initialize user token database: {}", e);
     }
 
+    // [NEW] 批量导入 refresh_token 后退出
+    // 用法: --import-refresh-tokens <path> [--import-format csv|json]
+    if let Some(pos) = args.iter().position(|arg| arg == "--import-refresh-tokens") {
+        let Some(path) = args.get(pos + 1) else {
+            error!("--import-refresh-tokens requires a file path");
+            std::process::exit(2);
+        };
+        let format = args
+            .iter()
+            .position(|arg| arg == "--import-format")
+            .and_then(|i| args.get(i + 1))
+            .map(|s| s.as_str());
+
+        let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
+        match rt.block_on(modules::token_import::import_refresh_tokens(path, format)) {
+            Ok(report) => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).unwrap_or_default()
+                );
+                std::process::exit(if report.failed.is_empty() { 0 } else { 1 });
+            }
+            Err(e) => {
+                error!("Refresh token import failed: {}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+
     if is_headless {
         info!("Starting in HEADLESS mode...");
 
@@ -352,6 +381,8 @@ pub fn run() {
             commands::list_device_versions,
             commands::restore_device_version,
             commands::delete_device_version,
+            commands::check_device_profile_consistency,
+            commands::regenerate_collided_device_profiles,
             commands::open_device_folder,
             commands::get_current_account,
             // Quota commands
@@ -363,12 +394,15 @@ pub fn run() {
             // Additional commands
             commands::prepare_oauth_url,
             commands::start_oauth_login,
+            commands::prepare_reauth_oauth,
             commands::complete_oauth_login,
             commands::cancel_oauth_login,
             commands::submit_oauth_code,
             commands::import_v1_accounts,
             commands::import_from_db,
             commands::import_custom_db,
+            commands::import_refresh_tokens,
+            commands::cleanup_orphan_files,
             commands::sync_account_from_db,
             commands::save_text_file,
             commands::read_text_file,
@@ -394,6 +428,7 @@ pub fn run() {
             commands::proxy::stop_proxy_service,
             commands::proxy::get_proxy_status,
             commands::proxy::get_proxy_stats,
+            commands::proxy::get_session_janitor_metrics,
             commands::proxy::get_proxy_logs,
             commands::proxy::get_proxy_logs_paginated,
             commands::proxy::get_proxy_log_detail,
@@ -415,6 +450,7 @@ pub fn run() {
             commands::proxy::clear_proxy_session_bindings,
             commands::proxy::set_preferred_account,
             commands::proxy::get_preferred_account,
+            commands::proxy::set_proxy_preferred_account,
             commands::proxy::clear_proxy_rate_limit,
             commands::proxy::clear_all_proxy_rate_limits,
             commands::proxy::check_proxy_health,
@@ -430,6 +466,9 @@ pub fn run() {
             commands::warm_up_all_accounts,
             commands::warm_up_account,
             commands::update_account_label,
+            commands::update_account_envelope_overrides,
+            commands::update_account_token_budget,
+            commands::update_account_group,
             // HTTP API settings commands
             commands::get_http_api_settings,
             commands::save_http_api_settings,
@@ -444,6 +483,18 @@ pub fn run() {
             commands::get_token_stats_model_trend_daily,
             commands::get_token_stats_account_trend_hourly,
             commands::get_token_stats_account_trend_daily,
+            commands::get_model_versions,
+            commands::get_quota_forecasts,
+            commands::get_feature_flags,
+            commands::set_feature_flag,
+            commands::decrypt_debug_capture,
+            commands::rotate_debug_capture_key,
+            commands::replay_stream_recording,
+            commands::get_truncated_tool_use_diagnostics,
+            commands::get_poison_quarantine_diagnostics,
+            commands::export_usage,
+            commands::run_db_maintenance,
+            commands::get_db_maintenance_report,
             proxy::cli_sync::get_cli_sync_status,
             proxy::cli_sync::execute_cli_sync,
             proxy::cli_sync::execute_cli_restore,
@@ -492,6 +543,8 @@ pub fn run() {
             commands::user_token::update_user_token,
             commands::user_token::delete_user_token,
             commands::user_token::renew_user_token,
+            commands::user_token::mint_scoped_token,
+            commands::user_token::revoke_scoped_token,
             commands::user_token::get_token_ip_bindings,
             commands::user_token::get_user_token_summary,
         ])
@@ -502,6 +555,7 @@ pub fn run() {
                 // Handle app exit - cleanup background tasks
                 tauri::RunEvent::Exit => {
                     tracing::info!("Application exiting, cleaning up background tasks...");
+                    crate::modules::db_maintenance::begin_shutdown();
                     if let Some(state) = app_handle.try_state::<crate::commands::proxy::ProxyServiceState>() {
                         tauri::async_runtime::block_on(async {
                             // Use timeout-based read() instead of try_read() to handle lock contention
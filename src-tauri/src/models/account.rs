@@ -57,6 +57,21 @@ pub struct Account {
     /// 用户自定义标签
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub custom_label: Option<String>,
+    /// [NEW] 自定义 v1internal 信封 userAgent (None = "antigravity")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent_override: Option<String>,
+    /// [NEW] 自定义 v1internal 信封 requestType (None = 按请求自动计算)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_type_override: Option<String>,
+    /// [NEW] 该账号可访问的其他 project (用于校验 X-Antigravity-Project 覆盖)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_project_ids: Vec<String>,
+    /// [NEW] 每月 token 预算 (None = 不限制)，超出后反代调度跳过该账号，每月 1 日 (UTC) 自动重置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_token_budget: Option<u64>,
+    /// [NEW] 账号分组 (多团队部署下按分组隔离选号，None = 未分组，仅服务未指定分组的请求)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
 }
 
 impl Account {
@@ -85,6 +100,11 @@ impl Account {
             proxy_id: None,
             proxy_bound_at: None,
             custom_label: None,
+            user_agent_override: None,
+            request_type_override: None,
+            additional_project_ids: Vec::new(),
+            monthly_token_budget: None,
+            group: None,
         }
     }
 
@@ -118,6 +138,9 @@ pub struct AccountSummary {
     /// 受保护的模型列表 [NEW] 供 UI 显示锁定图标
     #[serde(default, skip_serializing_if = "HashSet::is_empty")]
     pub protected_models: HashSet<String>,
+    /// 账号分组 [NEW] 供 UI 按分组筛选
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
     pub created_at: i64,
     pub last_used: i64,
 }
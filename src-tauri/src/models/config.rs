@@ -30,6 +30,12 @@ pub struct AppConfig {
     pub hidden_menu_items: Vec<String>, // Hidden menu item path list
     #[serde(default)]
     pub cloudflared: CloudflaredConfig, // [NEW] Cloudflared configuration
+    #[serde(default)]
+    pub usage_export: UsageExportConfig, // [NEW] Scheduled usage export configuration
+    #[serde(default)]
+    pub db_maintenance: DbMaintenanceConfig, // [NEW] Proxy database pruning / vacuum configuration
+    #[serde(default)]
+    pub logging: LogConfig, // [NEW] Log file rotation / format and live view buffer
 }
 
 /// Scheduled warmup configuration
@@ -168,6 +174,175 @@ impl Default for CircuitBreakerConfig {
     }
 }
 
+/// Scheduled usage export configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageExportConfig {
+    /// Whether the daily export job is enabled
+    pub enabled: bool,
+
+    /// Daily run time in local time (HH:MM); exports the previous UTC day
+    #[serde(default = "default_usage_export_run_at")]
+    pub run_at: String,
+
+    /// Export directory (None = <data_dir>/usage_exports)
+    #[serde(default)]
+    pub export_dir: Option<String>,
+
+    /// Days to keep scheduled export files (0 = keep forever)
+    #[serde(default = "default_usage_export_retention_days")]
+    pub retention_days: u32,
+
+    /// Mask account emails in exported files
+    #[serde(default = "default_usage_export_mask_emails")]
+    pub mask_emails: bool,
+}
+
+fn default_usage_export_run_at() -> String {
+    "00:30".to_string()
+}
+
+fn default_usage_export_retention_days() -> u32 {
+    90
+}
+
+fn default_usage_export_mask_emails() -> bool {
+    true
+}
+
+impl UsageExportConfig {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            run_at: default_usage_export_run_at(),
+            export_dir: None,
+            retention_days: default_usage_export_retention_days(),
+            mask_emails: default_usage_export_mask_emails(),
+        }
+    }
+}
+
+impl Default for UsageExportConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Proxy database maintenance configuration (retention windows, 0 = keep forever)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DbMaintenanceConfig {
+    /// Whether the scheduled maintenance job is enabled (manual runs are always allowed)
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Days between scheduled runs
+    #[serde(default = "default_db_maintenance_interval_days")]
+    pub interval_days: u32,
+
+    /// token_stats.db: per-request usage rows and hourly aggregates
+    #[serde(default = "default_token_stats_retention_days")]
+    pub token_stats_retention_days: u32,
+
+    /// proxy_logs.db: request monitor logs
+    #[serde(default = "default_request_log_retention_days")]
+    pub request_log_retention_days: u32,
+
+    /// security.db: IP access logs
+    #[serde(default = "default_request_log_retention_days")]
+    pub ip_log_retention_days: u32,
+
+    /// user_tokens.db: per-token usage logs
+    #[serde(default = "default_token_usage_log_retention_days")]
+    pub token_usage_log_retention_days: u32,
+}
+
+fn default_db_maintenance_interval_days() -> u32 {
+    7
+}
+
+fn default_token_stats_retention_days() -> u32 {
+    180
+}
+
+fn default_request_log_retention_days() -> u32 {
+    30
+}
+
+fn default_token_usage_log_retention_days() -> u32 {
+    90
+}
+
+impl DbMaintenanceConfig {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            interval_days: default_db_maintenance_interval_days(),
+            token_stats_retention_days: default_token_stats_retention_days(),
+            request_log_retention_days: default_request_log_retention_days(),
+            ip_log_retention_days: default_request_log_retention_days(),
+            token_usage_log_retention_days: default_token_usage_log_retention_days(),
+        }
+    }
+}
+
+impl Default for DbMaintenanceConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Log file line format
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable text lines
+    #[default]
+    Text,
+    /// One JSON object per line (level, target, message, trace_id, fields)
+    Json,
+}
+
+/// Log output configuration (file settings take effect after restart)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogConfig {
+    /// Rotate app.log once it would exceed this size
+    #[serde(default = "default_log_max_file_size_mb")]
+    pub max_file_size_mb: u64,
+
+    /// Rotated files to keep (app.log.1 .. app.log.N)
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
+
+    #[serde(default)]
+    pub format: LogFormat,
+
+    /// Recent events kept in memory for the live log view
+    #[serde(default = "default_log_buffer_size")]
+    pub buffer_size: usize,
+}
+
+fn default_log_max_file_size_mb() -> u64 {
+    20
+}
+
+fn default_log_max_files() -> usize {
+    5
+}
+
+fn default_log_buffer_size() -> usize {
+    5000
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            max_file_size_mb: default_log_max_file_size_mb(),
+            max_files: default_log_max_files(),
+            format: LogFormat::default(),
+            buffer_size: default_log_buffer_size(),
+        }
+    }
+}
+
 impl AppConfig {
     pub fn new() -> Self {
         Self {
@@ -188,6 +363,9 @@ impl AppConfig {
             circuit_breaker: CircuitBreakerConfig::default(),
             hidden_menu_items: Vec::new(),
             cloudflared: CloudflaredConfig::default(),
+            usage_export: UsageExportConfig::default(),
+            db_maintenance: DbMaintenanceConfig::default(),
+            logging: LogConfig::default(),
         }
     }
 }
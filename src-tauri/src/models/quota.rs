@@ -1,11 +1,124 @@
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::{Mutex, OnceLock};
 
 /// 模型配额信息
+/// [NEW] 反序列化兼容平铺结构 ({name, percentage}) 与嵌套窗口结构 ({name, windows: [...]})
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "Value")]
 pub struct ModelQuota {
     pub name: String,
     pub percentage: i32,  // 剩余百分比 0-100
     pub reset_time: String,
+    /// [NEW] 剩余绝对 token 数 (仅当上游配额接口提供时)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining_tokens: Option<u64>,
+    /// [NEW] 当前窗口的 token 总额
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit_tokens: Option<u64>,
+    /// [NEW] 配额重置窗口长度 (秒)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reset_window_secs: Option<u64>,
+    /// [NEW] 无法从上游结构解析出剩余百分比 (此时 percentage 无意义，不参与配额保护)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unparsed: bool,
+}
+
+/// 解析数字字段 (数字或数字字符串)
+fn number_like(value: &Value) -> Option<f64> {
+    value
+        .as_f64()
+        .or_else(|| value.as_str().and_then(|s| s.trim().trim_end_matches('%').parse().ok()))
+        .filter(|v| v.is_finite())
+}
+
+/// 单个窗口 (或平铺条目) 的剩余百分比: percentage 优先，其次 remaining_fraction，
+/// 最后由剩余 / 总额 token 推导 (账号 JSON 与上游 quotaInfo 两种字段命名均兼容)
+pub fn window_percentage(entry: &Value) -> Option<i32> {
+    if let Some(pct) = entry.get("percentage").and_then(number_like) {
+        return Some(pct.clamp(0.0, 100.0) as i32);
+    }
+    let field = |keys: &[&str]| keys.iter().find_map(|k| entry.get(*k).and_then(number_like));
+    if let Some(f) = field(&["remaining_fraction", "remainingFraction"]) {
+        return Some((f.clamp(0.0, 1.0) * 100.0) as i32);
+    }
+    let remaining = field(&["remaining_tokens", "remainingTokens", "remainingAmount"])?;
+    let limit = field(&["limit_tokens", "totalTokens", "tokenLimit"]).filter(|l| *l > 0.0)?;
+    Some(((remaining.clamp(0.0, limit) / limit) * 100.0) as i32)
+}
+
+/// 嵌套窗口结构中最严格 (剩余百分比最低) 的窗口
+fn most_restrictive_window(entry: &Value) -> Option<(&Value, i32)> {
+    entry
+        .get("windows")
+        .or_else(|| entry.get("quotaWindows"))
+        .and_then(|w| w.as_array())?
+        .iter()
+        .filter_map(|w| window_percentage(w).map(|pct| (w, pct)))
+        .min_by_key(|(_, pct)| *pct)
+}
+
+/// 从账号 JSON 的 quota.models[] 条目读取剩余百分比
+/// 兼容平铺与嵌套窗口结构 (取最严格的窗口)；无法解析或已标记 unparsed 时返回 None，
+/// 调用方应将其视为"未知"，不得据此触发配额保护
+pub fn percentage_from_json(entry: &Value) -> Option<i32> {
+    if entry.get("unparsed").and_then(|v| v.as_bool()) == Some(true) {
+        return None;
+    }
+    let pct = window_percentage(entry).or_else(|| most_restrictive_window(entry).map(|(_, pct)| pct));
+    if pct.is_none() {
+        log_unparsed_once(entry.get("name").and_then(|v| v.as_str()).unwrap_or(""), entry);
+    }
+    pct
+}
+
+/// 无法解析的配额结构按模型名只记录一次原始内容，避免刷屏
+pub fn log_unparsed_once(model: &str, raw: &Value) {
+    static LOGGED: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+    let first = LOGGED
+        .get_or_init(|| Mutex::new(HashSet::new()))
+        .lock()
+        .map(|mut logged| logged.insert(model.to_string()))
+        .unwrap_or(false);
+    if first {
+        tracing::warn!(
+            "[Quota] Unrecognized quota structure for model '{}', treating remaining quota as unknown: {}",
+            model,
+            raw
+        );
+    }
+}
+
+impl TryFrom<Value> for ModelQuota {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let name = value
+            .get("name")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| "model quota entry is missing 'name'".to_string())?
+            .to_string();
+        let percentage = percentage_from_json(&value);
+        // 嵌套结构时 reset_time 等字段取自最严格的窗口
+        let window = most_restrictive_window(&value).map(|(w, _)| w);
+        let field = |key: &str| value.get(key).or_else(|| window.and_then(|w| w.get(key)));
+        let as_u64 = |key: &str| field(key).and_then(|v| v.as_u64());
+
+        Ok(Self {
+            name,
+            percentage: percentage.unwrap_or(0),
+            reset_time: field("reset_time")
+                .or_else(|| field("resetTime"))
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            remaining_tokens: as_u64("remaining_tokens"),
+            limit_tokens: as_u64("limit_tokens"),
+            reset_window_secs: as_u64("reset_window_secs"),
+            unparsed: percentage.is_none(),
+        })
+    }
 }
 
 /// 配额数据结构
@@ -35,8 +148,17 @@ impl QuotaData {
             name,
             percentage,
             reset_time,
+            remaining_tokens: None,
+            limit_tokens: None,
+            reset_window_secs: None,
+            unparsed: false,
         });
     }
+
+    /// [NEW] 是否存在无法解析剩余百分比的模型
+    pub fn is_unparsed(&self) -> bool {
+        self.models.iter().any(|m| m.unparsed)
+    }
 }
 
 impl Default for QuotaData {
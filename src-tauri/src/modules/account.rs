@@ -12,7 +12,7 @@ use crate::models::{
 };
 use crate::modules;
 use once_cell::sync::Lazy;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 #[cfg(test)]
 mod tests {
@@ -214,6 +214,91 @@ mod tests {
         println!("Missing index with existing accounts: successfully recovered {} accounts", index.accounts.len());
     }
 
+    #[test]
+    fn test_cleanup_orphans_removes_temp_files() {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let dir = TestDataDir::new();
+        create_account_file(dir.path(), "acc-1", "user1@example.com");
+        let index = rebuild_index_from_accounts_in_dir(dir.path()).unwrap();
+        save_account_index_in_dir(dir.path(), &index).unwrap();
+
+        let index_tmp = dir.path().join("accounts.json.tmp.3f2a");
+        let account_tmp = dir.path().join("accounts").join("acc-1.json.tmp.9c1d");
+        fs::write(&index_tmp, "{").unwrap();
+        fs::write(&account_tmp, "{").unwrap();
+
+        // 未达到最小存在时长的临时文件视为仍在写入，不处理
+        let report = cleanup_orphans_in_dir(dir.path(), false, std::time::Duration::from_secs(3600)).unwrap();
+        assert!(report.temp_files.is_empty());
+        assert!(index_tmp.exists());
+
+        // dry run 只报告
+        let report = cleanup_orphans_in_dir(dir.path(), true, std::time::Duration::ZERO).unwrap();
+        assert_eq!(report.temp_files.len(), 2);
+        assert!(index_tmp.exists() && account_tmp.exists());
+
+        let report = cleanup_orphans_in_dir(dir.path(), false, std::time::Duration::ZERO).unwrap();
+        assert_eq!(report.temp_files.len(), 2);
+        assert!(!index_tmp.exists());
+        assert!(!account_tmp.exists());
+
+        // 正常文件不受影响
+        assert!(dir.path().join("accounts.json").exists());
+        assert!(dir.path().join("accounts").join("acc-1.json").exists());
+        assert!(report.orphaned_accounts.is_empty());
+    }
+
+    #[test]
+    fn test_cleanup_orphans_reports_unindexed_accounts_without_deleting() {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let dir = TestDataDir::new();
+        create_account_file(dir.path(), "acc-1", "user1@example.com");
+        let index = rebuild_index_from_accounts_in_dir(dir.path()).unwrap();
+        save_account_index_in_dir(dir.path(), &index).unwrap();
+
+        // 索引之外的账号文件
+        create_account_file(dir.path(), "acc-orphan", "orphan@example.com");
+
+        let report = cleanup_orphans_in_dir(dir.path(), false, std::time::Duration::ZERO).unwrap();
+        assert_eq!(report.orphaned_accounts, vec!["acc-orphan".to_string()]);
+        assert!(
+            dir.path().join("accounts").join("acc-orphan.json").exists(),
+            "[FIX #929] orphaned account files must not be deleted"
+        );
+    }
+
+    #[test]
+    fn test_upsert_clears_insufficient_scope_flag() {
+        let mut account = Account::new(
+            "scope-id".to_string(),
+            "scope@example.com".to_string(),
+            TokenData::new("old_access".to_string(), "old_refresh".to_string(), 3600, None, None, None),
+        );
+        account.disabled = true;
+        account.disabled_reason = Some(INSUFFICIENT_SCOPE_REASON.to_string());
+        account.disabled_at = Some(chrono::Utc::now().timestamp());
+
+        // 重新授权后即使 refresh_token 未变化也应恢复
+        apply_upserted_token(
+            &mut account,
+            None,
+            TokenData::new("old_access".to_string(), "old_refresh".to_string(), 3600, None, None, None),
+        );
+        assert!(!account.disabled);
+        assert!(account.disabled_reason.is_none());
+        assert!(account.disabled_at.is_none());
+
+        // 其他原因禁用且 token 未变化时保持禁用
+        account.disabled = true;
+        account.disabled_reason = Some("invalid_grant: revoked".to_string());
+        apply_upserted_token(
+            &mut account,
+            None,
+            TokenData::new("old_access".to_string(), "old_refresh".to_string(), 3600, None, None, None),
+        );
+        assert!(account.disabled);
+    }
+
     #[test]
     fn test_save_account_index_roundtrip() {
         let _guard = TEST_MUTEX.lock().unwrap();
@@ -231,6 +316,7 @@ mod tests {
                     disabled: false,
                     proxy_disabled: false,
                     protected_models: HashSet::new(),
+                    group: None,
                     created_at: now,
                     last_used: now,
                 },
@@ -241,6 +327,7 @@ mod tests {
                     disabled: true,
                     proxy_disabled: true,
                     protected_models: HashSet::new(),
+                    group: None,
                     created_at: now - 100,
                     last_used: now - 50,
                 },
@@ -317,16 +404,196 @@ mod tests {
 
         println!("Backup creation on parse failure: successfully created backup");
     }
+
+    #[test]
+    fn test_concurrent_quota_updates_are_serialized() {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let dir = TestDataDir::new();
+        let accounts_dir = dir.path().join("accounts");
+        create_account_file(dir.path(), "concurrent-acc", "concurrent@example.com");
+
+        let initial_last_used = load_account_at_path(&accounts_dir.join("concurrent-acc.json"))
+            .unwrap()
+            .last_used;
+
+        let protection = crate::models::QuotaProtectionConfig {
+            enabled: true,
+            threshold_percentage: 20,
+            monitored_models: vec!["gemini-3-flash".to_string()],
+        };
+
+        // 20 parallel quota updates with different percentages against the same account
+        std::thread::scope(|scope| {
+            for i in 0..20 {
+                let accounts_dir = &accounts_dir;
+                let protection = &protection;
+                scope.spawn(move || {
+                    let mut quota = QuotaData::new();
+                    quota.add_model("gemini-3-flash".to_string(), i * 5, String::new());
+                    update_account_in_dir(accounts_dir, "concurrent-acc", |account| {
+                        apply_quota_update(account, quota, Some(protection));
+                        // Counter proves every mutation was applied on top of the previous one
+                        account.last_used += 1;
+                        Ok(())
+                    })
+                    .expect("quota update should succeed");
+                });
+            }
+        });
+
+        // File must parse (no torn writes) and reflect all 20 serialized updates
+        let account = load_account_at_path(&accounts_dir.join("concurrent-acc.json"))
+            .expect("account file should not be corrupted");
+        assert_eq!(account.last_used, initial_last_used + 20, "lost update detected");
+
+        let quota = account.quota.expect("quota should be set");
+        assert_eq!(quota.models.len(), 1, "quota must come from exactly one update");
+        let final_pct = quota.models[0].percentage;
+        assert!(final_pct % 5 == 0 && (0..100).contains(&final_pct));
+
+        // Protection state must be consistent with the last applied quota
+        assert_eq!(
+            account.protected_models.contains("gemini-3-flash"),
+            final_pct <= 20
+        );
+
+        // No temp files left behind by atomic writes
+        let leftovers: Vec<_> = fs::read_dir(&accounts_dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().contains(".tmp."))
+            .collect();
+        assert!(leftovers.is_empty(), "temp files left behind: {:?}", leftovers.len());
+    }
+
+    #[test]
+    fn test_json_and_typed_updates_share_the_account_lock() {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let dir = TestDataDir::new();
+        let accounts_dir = dir.path().join("accounts");
+        create_account_file(dir.path(), "mixed-acc", "mixed@example.com");
+        let account_path = accounts_dir.join("mixed-acc.json");
+        let initial_last_used = load_account_at_path(&account_path).unwrap().last_used;
+
+        // Proxy-side raw JSON writes race with app-side typed writes on the same file
+        std::thread::scope(|scope| {
+            for i in 0..20 {
+                let accounts_dir = &accounts_dir;
+                let account_path = &account_path;
+                scope.spawn(move || {
+                    if i % 2 == 0 {
+                        update_account_json_at(account_path, |json| {
+                            let last_used = json["last_used"].as_i64().unwrap_or(0);
+                            json["last_used"] = serde_json::json!(last_used + 1);
+                            json["token"]["project_id"] = serde_json::json!("proj-json");
+                            Ok(())
+                        })
+                        .expect("json update should succeed");
+                    } else {
+                        update_account_in_dir(accounts_dir, "mixed-acc", |account| {
+                            account.last_used += 1;
+                            account.custom_label = Some("typed".to_string());
+                            Ok(())
+                        })
+                        .expect("typed update should succeed");
+                    }
+                });
+            }
+        });
+
+        let account = load_account_at_path(&account_path).expect("account file should not be corrupted");
+        assert_eq!(account.last_used, initial_last_used + 20, "lost update detected");
+        assert_eq!(account.token.project_id.as_deref(), Some("proj-json"));
+        assert_eq!(account.custom_label.as_deref(), Some("typed"));
+    }
+
+    /// 账号 JSON 中保存的配额结构
+    fn quota_fixture(models: serde_json::Value) -> QuotaData {
+        serde_json::from_value(serde_json::json!({ "models": models, "last_updated": 0 }))
+            .expect("quota fixture should deserialize")
+    }
+
+    #[test]
+    fn test_quota_protection_only_triggers_on_parsed_low_percentage() {
+        let protection = crate::models::QuotaProtectionConfig {
+            enabled: true,
+            threshold_percentage: 10,
+            monitored_models: vec!["gemini-3-flash".to_string()],
+        };
+        let mut account = Account::new(
+            "shape-id".to_string(),
+            "shape@example.com".to_string(),
+            TokenData::new("access".to_string(), "refresh".to_string(), 3600, None, None, None),
+        );
+        let flat = |pct: i32| {
+            quota_fixture(serde_json::json!([
+                { "name": "gemini-3-flash", "percentage": pct, "reset_time": "" }
+            ]))
+        };
+        let malformed = || {
+            quota_fixture(serde_json::json!([
+                { "name": "gemini-3-flash", "quota": { "left": "plenty" } }
+            ]))
+        };
+
+        // 无法解析的结构: 标记为 unparsed，不触发保护
+        let quota = malformed();
+        assert!(quota.is_unparsed());
+        apply_quota_update(&mut account, quota, Some(&protection));
+        assert!(account.protected_models.is_empty());
+
+        // 平铺结构: 高于阈值不保护
+        apply_quota_update(&mut account, flat(50), Some(&protection));
+        assert!(account.protected_models.is_empty());
+
+        // 嵌套窗口结构: 取最严格的窗口 (每周 70%，5 小时 8%)
+        let windowed = quota_fixture(serde_json::json!([{
+            "name": "gemini-3-flash",
+            "windows": [
+                { "percentage": 70, "reset_time": "2026-01-07T00:00:00Z" },
+                { "remaining_fraction": 0.08, "reset_time": "2026-01-01T05:00:00Z" }
+            ]
+        }]));
+        assert_eq!(windowed.models[0].percentage, 8);
+        assert_eq!(windowed.models[0].reset_time, "2026-01-01T05:00:00Z");
+        assert!(!windowed.is_unparsed());
+        apply_quota_update(&mut account, windowed, Some(&protection));
+        assert!(account.protected_models.contains("gemini-3-flash"));
+
+        // 未知值也不会解除已有的保护
+        apply_quota_update(&mut account, malformed(), Some(&protection));
+        assert!(account.protected_models.contains("gemini-3-flash"));
+
+        apply_quota_update(&mut account, flat(90), Some(&protection));
+        assert!(account.protected_models.is_empty());
+    }
 }
 
 /// Global account write lock to prevent corruption during concurrent operations
 static ACCOUNT_INDEX_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
 
+/// Per-account file locks: all account JSON mutations (load -> mutate -> save) funnel through these
+/// to avoid lost updates when several requests finish at once for the same account.
+/// Lock order: ACCOUNT_INDEX_LOCK (if needed) -> account file lock, never the reverse.
+static ACCOUNT_FILE_LOCKS: Lazy<Mutex<HashMap<String, Arc<Mutex<()>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn account_file_lock(account_id: &str) -> Arc<Mutex<()>> {
+    let mut locks = ACCOUNT_FILE_LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+    locks
+        .entry(account_id.to_string())
+        .or_insert_with(|| Arc::new(Mutex::new(())))
+        .clone()
+}
+
 // ... existing constants ...
 const DATA_DIR: &str = ".antigravity_tools";
 const ACCOUNTS_INDEX: &str = "accounts.json";
 const ACCOUNTS_DIR: &str = "accounts";
 
+/// [NEW] 上游提示 OAuth scope 不足时写入的禁用原因 (需重新授权)
+pub const INSUFFICIENT_SCOPE_REASON: &str = "insufficient_scope";
+
 /// Get data directory path
 pub fn get_data_dir() -> Result<PathBuf, String> {
     // [NEW] Support custom data directory via environment variable
@@ -471,6 +738,7 @@ fn rebuild_index_from_accounts_in_dir(data_dir: &PathBuf) -> Result<AccountIndex
                                         disabled: account.disabled,
                                         proxy_disabled: account.proxy_disabled,
                                         protected_models: account.protected_models,
+                                        group: account.group,
                                         created_at: account.created_at,
                                         last_used: account.last_used,
                                     });
@@ -595,9 +863,121 @@ pub fn save_account_index(index: &AccountIndex) -> Result<(), String> {
     save_account_index_in_dir(&data_dir, index)
 }
 
+/// 临时文件至少存在多久才视为孤儿 (避免误删正在进行的原子写入)
+const ORPHAN_TEMP_MIN_AGE: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// 孤儿文件清理结果
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct OrphanCleanupReport {
+    pub dry_run: bool,
+    /// 已删除 (dry_run 时为待删除) 的残留临时文件
+    pub temp_files: Vec<String>,
+    /// 未被索引引用的账号文件 (仅报告，不删除)
+    pub orphaned_accounts: Vec<String>,
+}
+
+/// 原子写入残留的临时文件: accounts.json.tmp.<uuid> / <id>.json.tmp.<uuid>
+fn is_orphan_temp_file(file_name: &str) -> bool {
+    file_name.contains(".json.tmp")
+}
+
+fn collect_orphan_temp_files(
+    dir: &PathBuf,
+    min_age: std::time::Duration,
+    out: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    let entries = fs::read_dir(dir).map_err(|e| format!("failed_to_read_dir: {}", e))?;
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if !path.is_file() || !is_orphan_temp_file(&entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        let age = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.elapsed().ok())
+            .unwrap_or_default();
+        if age >= min_age {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Cleanup orphans in a specific data directory (internal helper)
+fn cleanup_orphans_in_dir(
+    data_dir: &PathBuf,
+    dry_run: bool,
+    min_age: std::time::Duration,
+) -> Result<OrphanCleanupReport, String> {
+    let accounts_dir = data_dir.join(ACCOUNTS_DIR);
+    let mut report = OrphanCleanupReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    let mut temp_files = Vec::new();
+    collect_orphan_temp_files(data_dir, min_age, &mut temp_files)?;
+    collect_orphan_temp_files(&accounts_dir, min_age, &mut temp_files)?;
+    for path in temp_files {
+        if !dry_run {
+            if let Err(e) = fs::remove_file(&path) {
+                modules::logger::log_warn(&format!(
+                    "Failed to remove orphan temp file {:?}: {}",
+                    path, e
+                ));
+                continue;
+            }
+        }
+        report.temp_files.push(path.to_string_lossy().to_string());
+    }
+
+    // [FIX #929] 未被索引引用的账号文件只报告、不删除，由用户自行决定
+    if accounts_dir.exists() && data_dir.join(ACCOUNTS_INDEX).exists() {
+        let index = load_account_index_in_dir(data_dir)?;
+        let indexed: HashSet<&str> = index.accounts.iter().map(|a| a.id.as_str()).collect();
+        let entries = fs::read_dir(&accounts_dir)
+            .map_err(|e| format!("failed_to_read_accounts_dir: {}", e))?;
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+            if let Some(id) = path.file_stem().and_then(|s| s.to_str()) {
+                if !indexed.contains(id) {
+                    report.orphaned_accounts.push(id.to_string());
+                }
+            }
+        }
+        report.orphaned_accounts.sort();
+    }
+
+    Ok(report)
+}
+
+/// 清理原子写入残留的临时文件，并报告未被索引引用的账号文件
+pub fn cleanup_orphans(dry_run: bool) -> Result<OrphanCleanupReport, String> {
+    let _lock = ACCOUNT_INDEX_LOCK
+        .lock()
+        .map_err(|e| format!("failed_to_acquire_lock: {}", e))?;
+    let data_dir = get_data_dir()?;
+    let report = cleanup_orphans_in_dir(&data_dir, dry_run, ORPHAN_TEMP_MIN_AGE)?;
+    modules::logger::log_info(&format!(
+        "Orphan cleanup{}: {} temp file(s), {} unindexed account file(s)",
+        if dry_run { " (dry run)" } else { "" },
+        report.temp_files.len(),
+        report.orphaned_accounts.len()
+    ));
+    Ok(report)
+}
+
 /// Platform-specific atomic file replacement
 #[cfg(target_os = "windows")]
-fn atomic_replace_file(src: &PathBuf, dst: &PathBuf) -> Result<(), String> {
+pub(crate) fn atomic_replace_file(src: &PathBuf, dst: &PathBuf) -> Result<(), String> {
     use std::os::windows::ffi::OsStrExt;
 
     type Bool = i32;
@@ -638,7 +1018,7 @@ fn atomic_replace_file(src: &PathBuf, dst: &PathBuf) -> Result<(), String> {
 
 /// Non-Windows: use standard rename
 #[cfg(not(target_os = "windows"))]
-fn atomic_replace_file(src: &PathBuf, dst: &PathBuf) -> Result<(), String> {
+pub(crate) fn atomic_replace_file(src: &PathBuf, dst: &PathBuf) -> Result<(), String> {
     fs::rename(src, dst).map_err(|e| format!("rename failed: {}", e))
 }
 
@@ -649,15 +1029,101 @@ pub fn load_account(account_id: &str) -> Result<Account, String> {
     load_account_at_path(&account_path)
 }
 
-/// Save account data
-pub fn save_account(account: &Account) -> Result<(), String> {
+/// Write account file atomically (tmp + rename), caller must hold the account file lock
+fn write_account_at_path(account_path: &PathBuf, account: &Account) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(account)
+        .map_err(|e| format!("failed_to_serialize_account_data: {}", e))?;
+    write_account_content_at_path(account_path, content)
+}
+
+/// Write serialized account content atomically (tmp + rename), caller must hold the account file lock
+fn write_account_content_at_path(account_path: &PathBuf, content: String) -> Result<(), String> {
+    let file_name = account_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("account.json");
+    // Use unique temp file name per write to avoid collision
+    let temp_path = account_path.with_file_name(format!("{}.tmp.{}", file_name, Uuid::new_v4()));
+
+    if let Err(e) = fs::write(&temp_path, content) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(format!("failed_to_save_account_data: {}", e));
+    }
+
+    if let Err(e) = atomic_replace_file(&temp_path, account_path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(format!("failed_to_replace_account_file: {}", e));
+    }
+
+    Ok(())
+}
+
+/// Load -> mutate -> save an account file in a specific directory under its file lock (internal helper)
+fn update_account_in_dir<F, R>(accounts_dir: &PathBuf, account_id: &str, mutate: F) -> Result<R, String>
+where
+    F: FnOnce(&mut Account) -> Result<R, String>,
+{
+    let lock = account_file_lock(account_id);
+    let _guard = lock
+        .lock()
+        .map_err(|e| format!("failed_to_acquire_account_lock: {}", e))?;
+
+    let account_path = accounts_dir.join(format!("{}.json", account_id));
+    let mut account = load_account_at_path(&account_path)?;
+    let result = mutate(&mut account)?;
+    write_account_at_path(&account_path, &account)?;
+    Ok(result)
+}
+
+/// Atomically update an account: the closure runs on a freshly loaded copy while the
+/// per-account lock is held, so concurrent mutations are serialized instead of overwriting each other.
+/// The closure must not call `save_account` / `update_account` for the same account.
+pub fn update_account<F, R>(account_id: &str, mutate: F) -> Result<R, String>
+where
+    F: FnOnce(&mut Account) -> Result<R, String>,
+{
     let accounts_dir = get_accounts_dir()?;
-    let account_path = accounts_dir.join(format!("{}.json", account.id));
+    update_account_in_dir(&accounts_dir, account_id, mutate)
+}
 
-    let content = serde_json::to_string_pretty(account)
+/// Raw-JSON variant of `update_account` for account files addressed by path (the proxy's
+/// TokenManager works on its own data dir and keeps unknown fields intact).
+/// Shares the per-account lock (keyed by file stem) and the atomic write with `update_account`.
+pub fn update_account_json_at<F, R>(account_path: &PathBuf, mutate: F) -> Result<R, String>
+where
+    F: FnOnce(&mut serde_json::Value) -> Result<R, String>,
+{
+    let account_id = account_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| format!("invalid_account_path: {:?}", account_path))?;
+    let lock = account_file_lock(account_id);
+    let _guard = lock
+        .lock()
+        .map_err(|e| format!("failed_to_acquire_account_lock: {}", e))?;
+
+    let content = fs::read_to_string(account_path)
+        .map_err(|e| format!("failed_to_read_account_data: {}", e))?;
+    let mut account: serde_json::Value = serde_json::from_str(&content)
+        .map_err(|e| format!("failed_to_parse_account_data: {}", e))?;
+    let result = mutate(&mut account)?;
+    let content = serde_json::to_string_pretty(&account)
         .map_err(|e| format!("failed_to_serialize_account_data: {}", e))?;
+    write_account_content_at_path(account_path, content)?;
+    Ok(result)
+}
 
-    fs::write(&account_path, content).map_err(|e| format!("failed_to_save_account_data: {}", e))
+/// Save account data (full overwrite, atomic)
+/// Prefer `update_account` for read-modify-write sequences.
+pub fn save_account(account: &Account) -> Result<(), String> {
+    let accounts_dir = get_accounts_dir()?;
+    let account_path = accounts_dir.join(format!("{}.json", account.id));
+
+    let lock = account_file_lock(&account.id);
+    let _guard = lock
+        .lock()
+        .map_err(|e| format!("failed_to_acquire_account_lock: {}", e))?;
+    write_account_at_path(&account_path, account)
 }
 
 /// List all accounts
@@ -716,6 +1182,7 @@ pub fn add_account(
         disabled: account.disabled,
         proxy_disabled: account.proxy_disabled,
         protected_models: account.protected_models.clone(),
+        group: account.group.clone(),
         created_at: account.created_at,
         last_used: account.last_used,
     });
@@ -730,6 +1197,28 @@ pub fn add_account(
     Ok(account)
 }
 
+/// 将 upsert 的新 token 写入已有账号
+///
+/// If an account was previously disabled (e.g. invalid_grant), any explicit token upsert
+/// should re-enable it (user manually updated credentials in the UI).
+/// [NEW] 因 scope 不足被禁用的账号在重新授权 (re-consent) 后总是恢复。
+fn apply_upserted_token(account: &mut Account, name: Option<String>, token: TokenData) {
+    let old_access_token = account.token.access_token.clone();
+    let old_refresh_token = account.token.refresh_token.clone();
+    account.token = token;
+    account.name = name;
+
+    let tokens_changed = account.token.refresh_token != old_refresh_token
+        || account.token.access_token != old_access_token;
+    let reauthorized_scope =
+        account.disabled_reason.as_deref() == Some(INSUFFICIENT_SCOPE_REASON);
+    if account.disabled && (tokens_changed || reauthorized_scope) {
+        account.disabled = false;
+        account.disabled_reason = None;
+        account.disabled_at = None;
+    }
+}
+
 /// Add or update account
 pub fn upsert_account(
     email: String,
@@ -752,20 +1241,7 @@ pub fn upsert_account(
         // Update existing account
         match load_account(&account_id) {
             Ok(mut account) => {
-                let old_access_token = account.token.access_token.clone();
-                let old_refresh_token = account.token.refresh_token.clone();
-                account.token = token;
-                account.name = name.clone();
-                // If an account was previously disabled (e.g. invalid_grant), any explicit token upsert
-                // should re-enable it (user manually updated credentials in the UI).
-                if account.disabled
-                    && (account.token.refresh_token != old_refresh_token
-                        || account.token.access_token != old_access_token)
-                {
-                    account.disabled = false;
-                    account.disabled_reason = None;
-                    account.disabled_at = None;
-                }
+                apply_upserted_token(&mut account, name.clone(), token);
                 account.update_last_used();
                 save_account(&account)?;
 
@@ -953,7 +1429,10 @@ pub async fn switch_account(
     // If Token updated, save back to account file
     if fresh_token.access_token != account.token.access_token {
         account.token = fresh_token.clone();
-        save_account(&account)?;
+        update_account(account_id, |acc| {
+            acc.token = fresh_token.clone();
+            Ok(())
+        })?;
     }
 
     // [FIX] Ensure account has a device profile for isolation
@@ -963,12 +1442,15 @@ pub async fn switch_account(
             account.email
         ));
         let new_profile = modules::device::generate_profile();
-        apply_profile_to_account(
-            &mut account,
-            new_profile.clone(),
-            Some("auto_generated".to_string()),
-            true,
-        )?;
+        account = update_account(account_id, |acc| {
+            apply_profile_to_account(
+                acc,
+                new_profile.clone(),
+                Some("auto_generated".to_string()),
+                true,
+            );
+            Ok(acc.clone())
+        })?;
     }
 
     // 3. Execute platform-specific system integration (Close proc, Inject DB, Start proc, etc.)
@@ -984,8 +1466,10 @@ pub async fn switch_account(
         save_account_index(&index)?;
     }
 
-    account.update_last_used();
-    save_account(&account)?;
+    update_account(account_id, |acc| {
+        acc.update_last_used();
+        Ok(())
+    })?;
 
     crate::modules::logger::log_info(&format!(
         "Account switch core logic completed: {}",
@@ -1028,10 +1512,14 @@ pub fn bind_device_profile(account_id: &str, mode: &str) -> Result<DeviceProfile
         _ => return Err("mode must be 'capture' or 'generate'".to_string()),
     };
 
-    let mut account = load_account(account_id)?;
+    // Verify account exists before touching the global original
+    load_account(account_id)?;
     let _ = device::save_global_original(&profile);
-    apply_profile_to_account(
-        &mut account, profile.clone(), Some(mode.to_string()), true)?;
+    update_account(account_id, |account| {
+        apply_profile_to_account(account, profile.clone(), Some(mode.to_string()), true);
+        Ok(())
+    })?;
+    crate::modules::device_consistency::check_after_change();
 
     Ok(profile)
 }
@@ -1042,19 +1530,24 @@ pub fn bind_device_profile_with_profile(
     profile: DeviceProfile,
     label: Option<String>,
 ) -> Result<DeviceProfile, String> {
-    let mut account = load_account(account_id)?;
+    load_account(account_id)?;
     let _ = crate::modules::device::save_global_original(&profile);
-    apply_profile_to_account(&mut account, profile.clone(), label, true)?;
+    update_account(account_id, |account| {
+        apply_profile_to_account(account, profile.clone(), label, true);
+        Ok(())
+    })?;
+    crate::modules::device_consistency::check_after_change();
 
     Ok(profile)
 }
 
-fn apply_profile_to_account(
+/// Bind profile on an in-memory account (caller persists via `update_account`)
+pub(crate) fn apply_profile_to_account(
     account: &mut Account,
     profile: DeviceProfile,
     label: Option<String>,
     add_history: bool,
-) -> Result<(), String> {
+) {
     account.device_profile = Some(profile.clone());
     if add_history {
         // Clear 'current' flag
@@ -1069,8 +1562,6 @@ fn apply_profile_to_account(
             is_current: true,
         });
     }
-    save_account(account)?;
-    Ok(())
 }
 
 /// List available device profile versions for an account (including baseline)
@@ -1080,27 +1571,29 @@ pub fn list_device_versions(account_id: &str) -> Result<DeviceProfiles, String>
 
 /// Restore device profile by version ID ("baseline" for global original, "current" for current bound)
 pub fn restore_device_version(account_id: &str, version_id: &str) -> Result<DeviceProfile, String> {
-    let mut account = load_account(account_id)?;
-
-    let target_profile = if version_id == "baseline" {
-        crate::modules::device::load_global_original().ok_or("Global original profile not found")?
-    } else if let Some(v) = account.device_history.iter().find(|v| v.id == version_id) {
-        v.profile.clone()
-    } else if version_id == "current" {
-        account
-            .device_profile
-            .clone()
-            .ok_or("No currently bound profile")?
-    } else {
-        return Err("Device profile version not found".to_string());
-    };
+    let profile = update_account(account_id, |account| {
+        let target_profile = if version_id == "baseline" {
+            crate::modules::device::load_global_original().ok_or("Global original profile not found")?
+        } else if let Some(v) = account.device_history.iter().find(|v| v.id == version_id) {
+            v.profile.clone()
+        } else if version_id == "current" {
+            account
+                .device_profile
+                .clone()
+                .ok_or("No currently bound profile")?
+        } else {
+            return Err("Device profile version not found".to_string());
+        };
 
-    account.device_profile = Some(target_profile.clone());
-    for h in account.device_history.iter_mut() {
-        h.is_current = h.id == version_id;
-    }
-    save_account(&account)?;
-    Ok(target_profile)
+        account.device_profile = Some(target_profile.clone());
+        for h in account.device_history.iter_mut() {
+            h.is_current = h.id == version_id;
+        }
+        Ok(target_profile)
+    })?;
+    // 恢复 baseline 等共享指纹时可能产生冲突
+    crate::modules::device_consistency::check_after_change();
+    Ok(profile)
 }
 
 /// Delete specific historical device profile (baseline cannot be deleted)
@@ -1108,47 +1601,51 @@ pub fn delete_device_version(account_id: &str, version_id: &str) -> Result<(), S
     if version_id == "baseline" {
         return Err("Original profile cannot be deleted".to_string());
     }
-    let mut account = load_account(account_id)?;
-    if account
-        .device_history
-        .iter()
-        .any(|v| v.id == version_id && v.is_current)
-    {
-        return Err("Currently bound profile cannot be deleted".to_string());
-    }
-    let before = account.device_history.len();
-    account.device_history.retain(|v| v.id != version_id);
-    if account.device_history.len() == before {
-        return Err("Historical device profile not found".to_string());
-    }
-    save_account(&account)?;
-    Ok(())
+    update_account(account_id, |account| {
+        if account
+            .device_history
+            .iter()
+            .any(|v| v.id == version_id && v.is_current)
+        {
+            return Err("Currently bound profile cannot be deleted".to_string());
+        }
+        let before = account.device_history.len();
+        account.device_history.retain(|v| v.id != version_id);
+        if account.device_history.len() == before {
+            return Err("Historical device profile not found".to_string());
+        }
+        Ok(())
+    })
 }
 /// Apply account bound device profile to storage.json
 pub fn apply_device_profile(account_id: &str) -> Result<DeviceProfile, String> {
     use crate::modules::device;
-    let mut account = load_account(account_id)?;
+    let account = load_account(account_id)?;
     let profile = account
         .device_profile
         .clone()
         .ok_or("Account has no bound device profile")?;
     let storage_path = device::get_storage_path()?;
     device::write_profile(&storage_path, &profile)?;
-    account.update_last_used();
-    save_account(&account)?;
+    update_account(account_id, |account| {
+        account.update_last_used();
+        Ok(())
+    })?;
     Ok(profile)
 }
 
 /// Restore earliest storage.json backup (approximate "original" state)
 pub fn restore_original_device() -> Result<String, String> {
     if let Some(current_id) = get_current_account_id()? {
-        if let Ok(mut account) = load_account(&current_id) {
+        if load_account(&current_id).is_ok() {
             if let Some(original) = crate::modules::device::load_global_original() {
-                account.device_profile = Some(original);
-                for h in account.device_history.iter_mut() {
-                    h.is_current = false;
-                }
-                save_account(&account)?;
+                update_account(&current_id, |account| {
+                    account.device_profile = Some(original);
+                    for h in account.device_history.iter_mut() {
+                        h.is_current = false;
+                    }
+                    Ok(())
+                })?;
                 return Ok(
                     "Reset current account bound profile to original (not applied to storage)"
                         .to_string(),
@@ -1184,23 +1681,32 @@ pub fn set_current_account_id(account_id: &str) -> Result<(), String> {
     save_account_index(&index)
 }
 
-/// Update account quota
-pub fn update_account_quota(account_id: &str, quota: QuotaData) -> Result<(), String> {
-    let mut account = load_account(account_id)?;
+/// Apply a quota update plus model-level quota protection on an in-memory account
+fn apply_quota_update(
+    account: &mut Account,
+    quota: QuotaData,
+    protection: Option<&crate::models::QuotaProtectionConfig>,
+) {
     account.update_quota(quota);
 
     // --- Quota protection logic start ---
-    if let Ok(config) = crate::modules::config::load_app_config() {
-        if config.quota_protection.enabled {
+    if let Some(protection) = protection {
+        if protection.enabled {
             if let Some(ref q) = account.quota {
-                let threshold = config.quota_protection.threshold_percentage as i32;
+                let threshold = protection.threshold_percentage as i32;
 
                 let mut group_min_percentage: HashMap<String, i32> = HashMap::new();
+                // [NEW] 剩余百分比未知的分组: 既不触发也不解除保护
+                let mut unknown_groups: HashSet<String> = HashSet::new();
 
                 for model in &q.models {
                     if let Some(std_id) =
                         crate::proxy::common::model_mapping::normalize_to_standard_id(&model.name)
                     {
+                        if model.unparsed {
+                            unknown_groups.insert(std_id);
+                            continue;
+                        }
                         let entry = group_min_percentage.entry(std_id).or_insert(100);
                         if model.percentage < *entry {
                             *entry = model.percentage;
@@ -1208,7 +1714,10 @@ pub fn update_account_quota(account_id: &str, quota: QuotaData) -> Result<(), St
                     }
                 }
 
-                for std_id in &config.quota_protection.monitored_models {
+                for std_id in &protection.monitored_models {
+                    if !group_min_percentage.contains_key(std_id) && unknown_groups.contains(std_id) {
+                        continue;
+                    }
                     let min_pct = group_min_percentage.get(std_id).cloned().unwrap_or(100);
 
                     if min_pct <= threshold {
@@ -1249,9 +1758,19 @@ pub fn update_account_quota(account_id: &str, quota: QuotaData) -> Result<(), St
         }
     }
     // --- Quota protection logic end ---
+}
 
-    // Save account first
-    save_account(&account)?;
+/// Update account quota
+pub fn update_account_quota(account_id: &str, quota: QuotaData) -> Result<(), String> {
+    let protection = crate::modules::config::load_app_config()
+        .ok()
+        .map(|config| config.quota_protection);
+
+    // Serialized load -> mutate -> save under the per-account lock
+    let protected_models = update_account(account_id, |account| {
+        apply_quota_update(account, quota, protection.as_ref());
+        Ok(account.protected_models.clone())
+    })?;
 
     // [FIX] 同时更新索引文件中的摘要信息，确保列表页图标即时刷新
     {
@@ -1260,7 +1779,7 @@ pub fn update_account_quota(account_id: &str, quota: QuotaData) -> Result<(), St
             .map_err(|e| format!("failed_to_acquire_lock: {}", e))?;
         if let Ok(mut index) = load_account_index() {
             if let Some(summary) = index.accounts.iter_mut().find(|a| a.id == account_id) {
-                summary.protected_models = account.protected_models.clone();
+                summary.protected_models = protected_models;
                 let _ = save_account_index(&index);
             }
         }
@@ -1283,26 +1802,46 @@ pub fn toggle_proxy_status(
         .lock()
         .map_err(|e| format!("failed_to_acquire_lock: {}", e))?;
 
-    let mut account = load_account(account_id)?;
+    update_account(account_id, |account| {
+        account.proxy_disabled = !enable;
+        account.proxy_disabled_reason = if !enable {
+            reason.map(|s| s.to_string())
+        } else {
+            None
+        };
+        account.proxy_disabled_at = if !enable {
+            Some(chrono::Utc::now().timestamp())
+        } else {
+            None
+        };
+        Ok(())
+    })?;
 
-    account.proxy_disabled = !enable;
-    account.proxy_disabled_reason = if !enable {
-        reason.map(|s| s.to_string())
-    } else {
-        None
-    };
-    account.proxy_disabled_at = if !enable {
-        Some(chrono::Utc::now().timestamp())
-    } else {
-        None
-    };
+    // Also update index summary
+    let mut index = load_account_index()?;
+    if let Some(summary) = index.accounts.iter_mut().find(|a| a.id == account_id) {
+        summary.proxy_disabled = !enable;
+        save_account_index(&index)?;
+    }
 
-    save_account(&account)?;
+    Ok(())
+}
+
+/// Set (or clear with None) the account group used for group-scoped proxy selection
+pub fn set_account_group(account_id: &str, group: Option<String>) -> Result<(), String> {
+    let _lock = ACCOUNT_INDEX_LOCK
+        .lock()
+        .map_err(|e| format!("failed_to_acquire_lock: {}", e))?;
+
+    update_account(account_id, |account| {
+        account.group = group.clone();
+        Ok(())
+    })?;
 
     // Also update index summary
     let mut index = load_account_index()?;
     if let Some(summary) = index.accounts.iter_mut().find(|a| a.id == account_id) {
-        summary.proxy_disabled = !enable;
+        summary.group = group;
         save_account_index(&index)?;
     }
 
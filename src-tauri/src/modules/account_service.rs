@@ -75,6 +75,7 @@ impl AccountService {
             "[Service] Added/Updated account: {}",
             account.email
         ));
+        modules::device_consistency::check_after_change();
         Ok(account)
     }
 
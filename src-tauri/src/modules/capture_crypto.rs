@@ -0,0 +1,379 @@
+// 调试抓包 / 会话存档的静态加密 (encryption at rest)
+// 文件格式: MAGIC(6) | key_id(u32 BE) | nonce_prefix(7) | { len(u32 BE) | ciphertext }*
+// 明文按 64 KiB 分块流式加密 (AES-256-GCM)，nonce = prefix || 块序号(u32 BE) || 末块标记(u8)，
+// 可防止截断与块重排，大文件无需整体缓冲。
+// 密钥按安装随机生成，经设备密钥再加密后保存在 security_db；轮换后旧密钥保留，
+// 旧文件在下次读取时自动用新密钥重新加密。密钥不可用时拒绝写入明文。
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use base64::{engine::general_purpose, Engine as _};
+use rand::RngCore;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// 加密文件头魔数
+pub const CAPTURE_MAGIC: &[u8; 6] = b"AGENC1";
+/// 加密文件扩展名 (追加在原扩展名之后)
+pub const ENCRYPTED_EXTENSION: &str = "enc";
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const NONCE_PREFIX_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+const HEADER_LEN: usize = CAPTURE_MAGIC.len() + 4 + NONCE_PREFIX_LEN;
+
+/// 抓包加密密钥存储
+pub trait CaptureKeyStore {
+    /// 当前用于写入的密钥 (不存在时创建)
+    fn active_key(&self) -> Result<(u32, [u8; 32]), String>;
+
+    /// 按 id 获取密钥 (用于读取旧文件)
+    fn key(&self, key_id: u32) -> Result<[u8; 32], String>;
+
+    /// 生成新密钥并设为启用，返回新密钥 id
+    fn rotate(&self) -> Result<u32, String>;
+}
+
+/// 基于 security_db 的密钥存储 (密钥材料经设备密钥加密后落库)
+pub struct SecurityDbKeyStore;
+
+fn generate_key() -> [u8; 32] {
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    key
+}
+
+fn seal_key(key: &[u8; 32]) -> Result<String, String> {
+    crate::utils::crypto::encrypt_string(&general_purpose::STANDARD.encode(key))
+}
+
+fn open_key(material: &str) -> Result<[u8; 32], String> {
+    let encoded = crate::utils::crypto::decrypt_string(material)?;
+    let bytes = general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("Invalid capture key encoding: {}", e))?;
+    bytes
+        .try_into()
+        .map_err(|_| "Invalid capture key length".to_string())
+}
+
+impl CaptureKeyStore for SecurityDbKeyStore {
+    fn active_key(&self) -> Result<(u32, [u8; 32]), String> {
+        if let Some((id, material)) = crate::modules::security_db::get_active_capture_key()? {
+            return Ok((id, open_key(&material)?));
+        }
+        let key = generate_key();
+        let id = crate::modules::security_db::insert_capture_key(&seal_key(&key)?)?;
+        crate::modules::logger::log_info(&format!("[Capture-Crypto] Generated capture key #{}", id));
+        Ok((id, key))
+    }
+
+    fn key(&self, key_id: u32) -> Result<[u8; 32], String> {
+        let material = crate::modules::security_db::get_capture_key(key_id)?
+            .ok_or_else(|| format!("Capture key #{} not found", key_id))?;
+        open_key(&material)
+    }
+
+    fn rotate(&self) -> Result<u32, String> {
+        let id = crate::modules::security_db::insert_capture_key(&seal_key(&generate_key())?)?;
+        crate::modules::logger::log_info(&format!("[Capture-Crypto] Rotated capture key, now #{}", id));
+        Ok(id)
+    }
+}
+
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_LEN], counter: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+/// 流式加密写入器，内存中最多缓冲一个分块
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    cipher: Aes256Gcm,
+    header: [u8; HEADER_LEN],
+    prefix: [u8; NONCE_PREFIX_LEN],
+    counter: u32,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    pub fn new(mut inner: W, key_id: u32, key: &[u8; 32]) -> io::Result<Self> {
+        let mut prefix = [0u8; NONCE_PREFIX_LEN];
+        rand::thread_rng().fill_bytes(&mut prefix);
+
+        let mut header = [0u8; HEADER_LEN];
+        header[..CAPTURE_MAGIC.len()].copy_from_slice(CAPTURE_MAGIC);
+        header[CAPTURE_MAGIC.len()..CAPTURE_MAGIC.len() + 4].copy_from_slice(&key_id.to_be_bytes());
+        header[CAPTURE_MAGIC.len() + 4..].copy_from_slice(&prefix);
+        inner.write_all(&header)?;
+
+        Ok(Self {
+            inner,
+            cipher: Aes256Gcm::new(&(*key).into()),
+            header,
+            prefix,
+            counter: 0,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+
+    fn emit_chunk(&mut self, len: usize, last: bool) -> io::Result<()> {
+        let nonce = chunk_nonce(&self.prefix, self.counter, last);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload { msg: &self.buf[..len], aad: &self.header },
+            )
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Encryption failed: {}", e)))?;
+        self.inner.write_all(&(ciphertext.len() as u32).to_be_bytes())?;
+        self.inner.write_all(&ciphertext)?;
+        self.buf.drain(..len);
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Capture too large"))?;
+        Ok(())
+    }
+
+    /// 写出末块并返回底层写入器
+    pub fn finish(mut self) -> io::Result<W> {
+        let len = self.buf.len();
+        self.emit_chunk(len, true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        // 保留最后一块直到 finish，以便为其打上末块标记
+        while self.buf.len() > CHUNK_SIZE {
+            self.emit_chunk(CHUNK_SIZE, false)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn read_chunk_len<R: Read>(reader: &mut R) -> Result<Option<usize>, String> {
+    let mut len = [0u8; 4];
+    let mut filled = 0;
+    while filled < len.len() {
+        match reader.read(&mut len[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err("Truncated capture file".to_string()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len < TAG_LEN || len > CHUNK_SIZE + TAG_LEN {
+        return Err(format!("Invalid chunk length {}", len));
+    }
+    Ok(Some(len))
+}
+
+/// 流式解密，返回文件使用的密钥 id
+pub fn decrypt_stream<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    store: &dyn CaptureKeyStore,
+) -> Result<u32, String> {
+    let mut header = [0u8; HEADER_LEN];
+    reader
+        .read_exact(&mut header)
+        .map_err(|_| "Not an encrypted capture file".to_string())?;
+    if &header[..CAPTURE_MAGIC.len()] != CAPTURE_MAGIC {
+        return Err("Not an encrypted capture file".to_string());
+    }
+    let mut key_id = [0u8; 4];
+    key_id.copy_from_slice(&header[CAPTURE_MAGIC.len()..CAPTURE_MAGIC.len() + 4]);
+    let key_id = u32::from_be_bytes(key_id);
+    let mut prefix = [0u8; NONCE_PREFIX_LEN];
+    prefix.copy_from_slice(&header[CAPTURE_MAGIC.len() + 4..]);
+
+    let cipher = Aes256Gcm::new(&store.key(key_id)?.into());
+    let mut counter: u32 = 0;
+    let mut next_len = read_chunk_len(&mut reader)?;
+    let mut ciphertext = Vec::with_capacity(CHUNK_SIZE + TAG_LEN);
+    while let Some(len) = next_len {
+        ciphertext.resize(len, 0);
+        reader
+            .read_exact(&mut ciphertext)
+            .map_err(|_| "Truncated capture file".to_string())?;
+        // 预读下一块长度以判断当前块是否为末块
+        next_len = read_chunk_len(&mut reader)?;
+        let nonce = chunk_nonce(&prefix, counter, next_len.is_none());
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: &header })
+            .map_err(|_| "Capture decryption failed (wrong key or corrupted file)".to_string())?;
+        writer.write_all(&plaintext).map_err(|e| e.to_string())?;
+        counter = counter.checked_add(1).ok_or("Capture too large")?;
+    }
+    if counter == 0 {
+        return Err("Truncated capture file".to_string());
+    }
+    Ok(key_id)
+}
+
+/// 加密写入文件；无法获取密钥时拒绝写入 (不会生成明文文件)
+pub fn write_encrypted_file(
+    path: &Path,
+    store: &dyn CaptureKeyStore,
+    write_body: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> Result<(), String> {
+    let (key_id, key) = store
+        .active_key()
+        .map_err(|e| format!("Capture key store unavailable, refusing to write plaintext: {}", e))?;
+
+    let tmp_path = path.with_extension("tmp");
+    let result = (|| -> io::Result<()> {
+        let file = BufWriter::new(File::create(&tmp_path)?);
+        let mut writer = EncryptingWriter::new(file, key_id, &key)?;
+        write_body(&mut writer)?;
+        writer.finish()?.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&tmp_path, path)
+    })();
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(format!("Failed to write encrypted capture: {}", e));
+    }
+    Ok(())
+}
+
+/// 解密读取文件；若文件使用的是已轮换的旧密钥，读取后用当前密钥重新加密
+pub fn read_encrypted_file(path: &Path, store: &dyn CaptureKeyStore) -> Result<Vec<u8>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut plaintext = Vec::new();
+    let key_id = decrypt_stream(BufReader::new(file), &mut plaintext, store)?;
+
+    match store.active_key() {
+        Ok((active_id, _)) if active_id != key_id => {
+            match write_encrypted_file(path, store, |w| w.write_all(&plaintext)) {
+                Ok(()) => tracing::info!(
+                    "[Capture-Crypto] Re-encrypted {} from key #{} to #{}",
+                    path.display(),
+                    key_id,
+                    active_id
+                ),
+                Err(e) => tracing::warn!("[Capture-Crypto] Lazy re-encryption failed: {}", e),
+            }
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("[Capture-Crypto] Skipping lazy re-encryption: {}", e),
+    }
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+
+    /// 内存密钥存储；available=false 模拟 security_db 不可用
+    struct MemoryKeyStore {
+        keys: RefCell<BTreeMap<u32, [u8; 32]>>,
+        available: bool,
+    }
+
+    impl MemoryKeyStore {
+        fn new(available: bool) -> Self {
+            Self { keys: RefCell::new(BTreeMap::new()), available }
+        }
+    }
+
+    impl CaptureKeyStore for MemoryKeyStore {
+        fn active_key(&self) -> Result<(u32, [u8; 32]), String> {
+            if !self.available {
+                return Err("security.db is not accessible".to_string());
+            }
+            if let Some((id, key)) = self.keys.borrow().iter().next_back() {
+                return Ok((*id, *key));
+            }
+            let id = self.rotate()?;
+            self.key(id).map(|key| (id, key))
+        }
+
+        fn key(&self, key_id: u32) -> Result<[u8; 32], String> {
+            self.keys.borrow().get(&key_id).copied().ok_or_else(|| format!("Capture key #{} not found", key_id))
+        }
+
+        fn rotate(&self) -> Result<u32, String> {
+            let id = self.keys.borrow().len() as u32 + 1;
+            self.keys.borrow_mut().insert(id, generate_key());
+            Ok(id)
+        }
+    }
+
+    fn temp_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("capture_crypto_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn archive_body() -> Vec<u8> {
+        // 跨越多个分块的存档内容
+        let turn = br#"{"role":"user","content":"my secret prompt"},"#;
+        turn.iter().copied().cycle().take(CHUNK_SIZE * 2 + 123).collect()
+    }
+
+    #[test]
+    fn test_archive_round_trip() {
+        let dir = temp_dir();
+        let path = dir.join("archive.json.enc");
+        let store = MemoryKeyStore::new(true);
+        let body = archive_body();
+
+        write_encrypted_file(&path, &store, |w| w.write_all(&body)).unwrap();
+        let raw = std::fs::read(&path).unwrap();
+        assert!(raw.starts_with(CAPTURE_MAGIC));
+        assert!(!raw.windows(16).any(|w| w == b"my secret prompt"));
+
+        assert_eq!(read_encrypted_file(&path, &store).unwrap(), body);
+
+        // 截断末块会被检测出来
+        std::fs::write(&path, &raw[..raw.len() - 40]).unwrap();
+        assert!(read_encrypted_file(&path, &store).is_err());
+    }
+
+    #[test]
+    fn test_refuses_to_write_without_key() {
+        let dir = temp_dir();
+        let path = dir.join("trace.json.enc");
+        let store = MemoryKeyStore::new(false);
+
+        let err = write_encrypted_file(&path, &store, |w| w.write_all(b"plaintext")).unwrap_err();
+        assert!(err.contains("refusing to write plaintext"));
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_rotation_keeps_old_files_readable_and_reencrypts_lazily() {
+        let dir = temp_dir();
+        let path = dir.join("old.json.enc");
+        let store = MemoryKeyStore::new(true);
+
+        write_encrypted_file(&path, &store, |w| w.write_all(b"captured before rotation")).unwrap();
+        let new_id = store.rotate().unwrap();
+
+        assert_eq!(read_encrypted_file(&path, &store).unwrap(), b"captured before rotation");
+        // 读取后已用新密钥重新加密
+        let raw = std::fs::read(&path).unwrap();
+        assert_eq!(&raw[CAPTURE_MAGIC.len()..CAPTURE_MAGIC.len() + 4], &new_id.to_be_bytes());
+        assert_eq!(read_encrypted_file(&path, &store).unwrap(), b"captured before rotation");
+    }
+}
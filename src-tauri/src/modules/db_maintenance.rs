@@ -0,0 +1,492 @@
+// 代理数据库维护
+// token_stats / 请求日志 / IP 访问日志 / 令牌用量日志长期累积后文件可达数百 MB，查询变慢。
+// 一次维护依次处理各数据库: 在线备份 (VACUUM INTO，保留到下一次维护) → 按保留期清理 → VACUUM / ANALYZE，
+// 并报告前后的文件大小与行数。维护期间持有写入闸门的独占锁，各记录写入持有共享锁，避免与进行中的写入交错；
+// 应用退出流程开始后，或反代停止 (排空后台任务) 期间拒绝执行。
+
+use chrono::{DateTime, Utc};
+use parking_lot::{RwLock, RwLockReadGuard};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::models::DbMaintenanceConfig;
+use crate::modules::{account, logger, proxy_db, security_db, token_stats, user_token_db};
+
+/// 最近一次维护报告的持久化文件 (同时用于计划任务判断是否到期)
+const REPORT_FILE: &str = "db_maintenance_report.json";
+/// 备份文件后缀 (位于数据库同目录)
+const BACKUP_SUFFIX: &str = ".maintenance.bak";
+
+/// 写入闸门: 记录写入持有共享锁，维护持有独占锁
+static WRITE_GATE: RwLock<()> = RwLock::new(());
+/// 应用正在退出 (不再开始新的维护)
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+/// 正在停止 (排空) 的反代实例数
+static DRAINING: AtomicUsize = AtomicUsize::new(0);
+/// 维护正在进行 (不允许并发执行)
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// 写入数据库前获取共享锁 (维护进行中时等待其完成)
+/// 会阻塞当前线程: 异步上下文中的调用方须经 `tokio::task::spawn_blocking` 执行
+pub fn write_guard() -> RwLockReadGuard<'static, ()> {
+    WRITE_GATE.read()
+}
+
+/// 标记应用进入退出流程
+pub fn begin_shutdown() {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+}
+
+/// 反代停止期间持有 (排空后台任务与进行中的写入)，Drop 后重新允许维护
+pub struct DrainGuard;
+
+/// 标记反代进入停止 / 排空流程
+pub fn begin_drain() -> DrainGuard {
+    DRAINING.fetch_add(1, Ordering::SeqCst);
+    DrainGuard
+}
+
+impl Drop for DrainGuard {
+    fn drop(&mut self) {
+        DRAINING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// 应用退出或反代排空进行中
+fn shutdown_in_progress() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst) || DRAINING.load(Ordering::SeqCst) > 0
+}
+
+/// 时间列的存储格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TimeColumn {
+    /// Unix 秒
+    Seconds,
+    /// Unix 毫秒
+    Millis,
+    /// "%Y-%m-%d %H:00" 文本 (UTC)
+    HourBucket,
+}
+
+#[derive(Debug, Clone)]
+struct PruneRule {
+    table: &'static str,
+    column: &'static str,
+    format: TimeColumn,
+    /// 0 = 永久保留
+    retention_days: u32,
+}
+
+#[derive(Debug, Clone)]
+struct DatabaseSpec {
+    name: &'static str,
+    path: PathBuf,
+    rules: Vec<PruneRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TableReport {
+    pub table: String,
+    pub rows_before: u64,
+    pub rows_after: u64,
+    pub pruned: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DatabaseReport {
+    pub name: String,
+    pub path: String,
+    /// 数据库文件 + WAL 的字节数
+    pub size_before: u64,
+    pub size_after: u64,
+    pub backup_path: Option<String>,
+    pub tables: Vec<TableReport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MaintenanceReport {
+    pub started_at: i64,
+    pub duration_ms: u64,
+    pub databases: Vec<DatabaseReport>,
+    /// 单个数据库失败时记录错误并继续处理其余数据库
+    pub errors: Vec<String>,
+}
+
+fn database_specs(config: &DbMaintenanceConfig) -> Result<Vec<DatabaseSpec>, String> {
+    let rule = |table, column, format, retention_days| PruneRule {
+        table,
+        column,
+        format,
+        retention_days,
+    };
+    Ok(vec![
+        DatabaseSpec {
+            name: "token_stats",
+            path: token_stats::get_db_path()?,
+            rules: vec![
+                rule(
+                    "token_usage",
+                    "timestamp",
+                    TimeColumn::Seconds,
+                    config.token_stats_retention_days,
+                ),
+                rule(
+                    "token_stats_hourly",
+                    "hour_bucket",
+                    TimeColumn::HourBucket,
+                    config.token_stats_retention_days,
+                ),
+            ],
+        },
+        DatabaseSpec {
+            name: "proxy_logs",
+            path: proxy_db::get_proxy_db_path()?,
+            rules: vec![rule(
+                "request_logs",
+                "timestamp",
+                TimeColumn::Millis,
+                config.request_log_retention_days,
+            )],
+        },
+        DatabaseSpec {
+            name: "security",
+            path: security_db::get_security_db_path()?,
+            rules: vec![rule(
+                "ip_access_logs",
+                "timestamp",
+                TimeColumn::Seconds,
+                config.ip_log_retention_days,
+            )],
+        },
+        DatabaseSpec {
+            name: "user_tokens",
+            path: user_token_db::get_db_path()?,
+            rules: vec![rule(
+                "token_usage_logs",
+                "request_time",
+                TimeColumn::Seconds,
+                config.token_usage_log_retention_days,
+            )],
+        },
+    ])
+}
+
+/// 早于该值的行被清理 (恰好等于截止时间的行保留)
+fn cutoff_value(rule: &PruneRule, now: DateTime<Utc>) -> rusqlite::types::Value {
+    let cutoff = now - chrono::Duration::days(rule.retention_days as i64);
+    match rule.format {
+        TimeColumn::Seconds => cutoff.timestamp().into(),
+        TimeColumn::Millis => cutoff.timestamp_millis().into(),
+        TimeColumn::HourBucket => cutoff.format("%Y-%m-%d %H:00").to_string().into(),
+    }
+}
+
+fn file_size(path: &Path) -> u64 {
+    let wal = PathBuf::from(format!("{}-wal", path.display()));
+    [path, wal.as_path()]
+        .iter()
+        .filter_map(|p| fs::metadata(p).ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+fn count_rows(conn: &Connection, table: &str) -> Result<u64, String> {
+    conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+        row.get::<_, i64>(0)
+    })
+    .map(|n| n as u64)
+    .map_err(|e| format!("count {} failed: {}", table, e))
+}
+
+fn table_exists(conn: &Connection, table: &str) -> bool {
+    conn.query_row(
+        "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        [table],
+        |_| Ok(()),
+    )
+    .is_ok()
+}
+
+/// 在线备份: 上一次维护的备份在此时删除 (备份保留一个维护周期)
+fn backup_database(conn: &Connection, path: &Path) -> Result<PathBuf, String> {
+    let backup = PathBuf::from(format!("{}{}", path.display(), BACKUP_SUFFIX));
+    if backup.exists() {
+        fs::remove_file(&backup).map_err(|e| format!("remove old backup failed: {}", e))?;
+    }
+    conn.execute("VACUUM INTO ?1", [backup.to_string_lossy().as_ref()])
+        .map_err(|e| format!("backup failed: {}", e))?;
+    Ok(backup)
+}
+
+/// 对单个数据库执行维护 (调用方负责持有写入闸门)
+fn maintain_database(spec: &DatabaseSpec, now: DateTime<Utc>) -> Result<DatabaseReport, String> {
+    let size_before = file_size(&spec.path);
+    let conn = Connection::open(&spec.path).map_err(|e| e.to_string())?;
+    conn.pragma_update(None, "busy_timeout", 5000)
+        .map_err(|e| e.to_string())?;
+
+    let rules: Vec<&PruneRule> = spec
+        .rules
+        .iter()
+        .filter(|r| table_exists(&conn, r.table))
+        .collect();
+    let mut tables = Vec::with_capacity(rules.len());
+    for rule in &rules {
+        let rows_before = count_rows(&conn, rule.table)?;
+        tables.push(TableReport {
+            table: rule.table.to_string(),
+            rows_before,
+            rows_after: rows_before,
+            pruned: 0,
+        });
+    }
+
+    let backup = backup_database(&conn, &spec.path)?;
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    for (rule, report) in rules.iter().zip(tables.iter_mut()) {
+        if rule.retention_days == 0 {
+            continue;
+        }
+        let deleted = tx
+            .execute(
+                &format!("DELETE FROM {} WHERE {} < ?1", rule.table, rule.column),
+                [cutoff_value(rule, now)],
+            )
+            .map_err(|e| format!("prune {} failed: {}", rule.table, e))?;
+        report.pruned = deleted as u64;
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+
+    conn.execute_batch("VACUUM; ANALYZE;")
+        .map_err(|e| format!("vacuum failed: {}", e))?;
+    // 将 WAL 合并回主文件，使报告的大小反映实际占用
+    let _ = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()));
+
+    for report in tables.iter_mut() {
+        report.rows_after = count_rows(&conn, &report.table)?;
+    }
+    drop(conn);
+
+    Ok(DatabaseReport {
+        name: spec.name.to_string(),
+        path: spec.path.to_string_lossy().to_string(),
+        size_before,
+        size_after: file_size(&spec.path),
+        backup_path: Some(backup.to_string_lossy().to_string()),
+        tables,
+    })
+}
+
+struct RunningGuard;
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        RUNNING.store(false, Ordering::SeqCst);
+    }
+}
+
+/// 执行一次完整维护并持久化报告
+pub fn run_maintenance(config: &DbMaintenanceConfig) -> Result<MaintenanceReport, String> {
+    if shutdown_in_progress() {
+        return Err("Application is shutting down or the proxy is draining, maintenance refused".to_string());
+    }
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return Err("Database maintenance is already running".to_string());
+    }
+    let _running = RunningGuard;
+
+    let specs = database_specs(config)?;
+    let started = std::time::Instant::now();
+    let now = Utc::now();
+    let mut report = MaintenanceReport {
+        started_at: now.timestamp(),
+        duration_ms: 0,
+        databases: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    {
+        let _gate = WRITE_GATE.write();
+        for spec in specs.iter().filter(|s| s.path.exists()) {
+            // 等待闸门期间可能已开始退出或停止反代
+            if shutdown_in_progress() {
+                report.errors.push(
+                    "Application is shutting down or the proxy is draining, remaining databases skipped"
+                        .to_string(),
+                );
+                break;
+            }
+            match maintain_database(spec, now) {
+                Ok(db) => {
+                    logger::log_info(&format!(
+                        "[DB-Maintenance] {}: {} -> {} bytes, pruned {} rows",
+                        db.name,
+                        db.size_before,
+                        db.size_after,
+                        db.tables.iter().map(|t| t.pruned).sum::<u64>()
+                    ));
+                    report.databases.push(db);
+                }
+                Err(e) => {
+                    logger::log_error(&format!("[DB-Maintenance] {} failed: {}", spec.name, e));
+                    report.errors.push(format!("{}: {}", spec.name, e));
+                }
+            }
+        }
+    }
+
+    report.duration_ms = started.elapsed().as_millis() as u64;
+    let path = account::get_data_dir()?.join(REPORT_FILE);
+    let content =
+        serde_json::to_string_pretty(&report).map_err(|e| format!("serialize_failed: {}", e))?;
+    fs::write(&path, content).map_err(|e| format!("write_failed: {}", e))?;
+    Ok(report)
+}
+
+/// 最近一次维护报告
+pub fn load_last_report() -> Option<MaintenanceReport> {
+    let path = account::get_data_dir().ok()?.join(REPORT_FILE);
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// 计划任务是否到期: 从未执行过，或距上次执行已超过 interval_days
+pub fn maintenance_due(config: &DbMaintenanceConfig, last_run: Option<i64>, now: i64) -> bool {
+    config.enabled
+        && last_run.map_or(true, |last| {
+            now - last >= config.interval_days.max(1) as i64 * 24 * 3600
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_db() -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("db_maintenance_test_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("token_stats.db")
+    }
+
+    fn seed(path: &Path, now: DateTime<Utc>) {
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE token_usage (id INTEGER PRIMARY KEY, timestamp INTEGER NOT NULL);
+             CREATE TABLE token_stats_hourly (hour_bucket TEXT PRIMARY KEY);",
+        )
+        .unwrap();
+        let day = 24 * 3600;
+        let cutoff = now.timestamp() - 30 * day;
+        // 恰好等于截止时间的行保留，早 1 秒的行被清理
+        for ts in [
+            cutoff - 10 * day,
+            cutoff - 1,
+            cutoff,
+            cutoff + 1,
+            now.timestamp(),
+        ] {
+            conn.execute("INSERT INTO token_usage (timestamp) VALUES (?1)", [ts])
+                .unwrap();
+        }
+        for days_ago in [31, 30, 29, 0] {
+            let bucket = (now - chrono::Duration::days(days_ago))
+                .format("%Y-%m-%d %H:00")
+                .to_string();
+            conn.execute(
+                "INSERT INTO token_stats_hourly (hour_bucket) VALUES (?1)",
+                [bucket],
+            )
+            .unwrap();
+        }
+    }
+
+    fn spec(path: PathBuf, retention_days: u32) -> DatabaseSpec {
+        DatabaseSpec {
+            name: "token_stats",
+            path,
+            rules: vec![
+                PruneRule {
+                    table: "token_usage",
+                    column: "timestamp",
+                    format: TimeColumn::Seconds,
+                    retention_days,
+                },
+                PruneRule {
+                    table: "token_stats_hourly",
+                    column: "hour_bucket",
+                    format: TimeColumn::HourBucket,
+                    retention_days,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_prune_boundaries_and_backup() {
+        let now = Utc::now();
+        let path = temp_db();
+        seed(&path, now);
+
+        let report = maintain_database(&spec(path.clone(), 30), now).unwrap();
+        assert_eq!(
+            report.tables[0],
+            TableReport {
+                table: "token_usage".to_string(),
+                rows_before: 5,
+                rows_after: 3,
+                pruned: 2,
+            }
+        );
+        // 30 天前同一小时的桶保留
+        assert_eq!(report.tables[1].rows_before, 4);
+        assert_eq!(report.tables[1].pruned, 1);
+        assert!(report.size_before > 0 && report.size_after > 0);
+
+        // 备份包含清理前的全部数据
+        let backup = PathBuf::from(report.backup_path.unwrap());
+        assert!(backup.exists());
+        let conn = Connection::open(&backup).unwrap();
+        assert_eq!(count_rows(&conn, "token_usage").unwrap(), 5);
+        drop(conn);
+
+        // 再次维护时替换上一次的备份
+        let report = maintain_database(&spec(path.clone(), 30), now).unwrap();
+        assert_eq!(report.tables[0].pruned, 0);
+        let conn = Connection::open(&backup).unwrap();
+        assert_eq!(count_rows(&conn, "token_usage").unwrap(), 3);
+    }
+
+    #[test]
+    fn test_zero_retention_keeps_rows_and_due_check() {
+        let now = Utc::now();
+        let path = temp_db();
+        seed(&path, now);
+
+        let report = maintain_database(&spec(path, 0), now).unwrap();
+        assert!(report.tables.iter().all(|t| t.pruned == 0));
+        assert!(report.backup_path.is_some());
+
+        let mut config = DbMaintenanceConfig::default();
+        let now = now.timestamp();
+        assert!(!maintenance_due(&config, None, now));
+        config.enabled = true;
+        assert!(maintenance_due(&config, None, now));
+        assert!(!maintenance_due(&config, Some(now - 24 * 3600), now));
+        assert!(maintenance_due(&config, Some(now - 7 * 24 * 3600), now));
+    }
+
+    #[test]
+    fn test_refuses_while_proxy_draining() {
+        let drain = begin_drain();
+        let err = run_maintenance(&DbMaintenanceConfig::default()).unwrap_err();
+        assert!(err.contains("draining"), "{}", err);
+
+        // 停止流程结束后重新允许维护
+        drop(drain);
+        assert!(!shutdown_in_progress());
+    }
+}
@@ -0,0 +1,276 @@
+// 设备指纹一致性检查
+// 批量导入账号时常见所有账号共用同一个采集到的指纹，违背 device_profile 的隔离初衷。
+// 按指纹标识字段计算哈希，检测被多个账号共用的指纹并持久化告警 (账号新增 / 绑定后合并延迟检查)；
+// 仅在用户显式触发时才为冲突账号重新生成指纹，绝不自动轮换。
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::models::{Account, DeviceProfile};
+use crate::modules::{account, device, logger};
+
+/// 最近一次检查结果的持久化文件
+const REPORT_FILE: &str = "device_profile_warnings.json";
+/// 批量重新生成时写入历史记录的标签
+pub const REGENERATED_LABEL: &str = "dedup_regenerated";
+/// 账号变更后的检查合并窗口: 批量导入数十个账号时只全量扫描一次
+const CHECK_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// 是否已有待执行的延迟检查
+static CHECK_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// 被多个账号共用的指纹
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeviceProfileCollision {
+    pub fingerprint: String,
+    pub account_ids: Vec<String>,
+    pub emails: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct DeviceConsistencyReport {
+    pub checked_at: i64,
+    /// 已绑定指纹的账号数
+    pub bound_accounts: usize,
+    /// 按共用账号数降序
+    pub collisions: Vec<DeviceProfileCollision>,
+}
+
+/// 批量重新生成的结果
+#[derive(Debug, Clone, Serialize)]
+pub struct RegenerationResult {
+    pub regenerated_account_ids: Vec<String>,
+    pub report: DeviceConsistencyReport,
+}
+
+/// 指纹哈希 (仅标识字段，前 16 位十六进制)
+pub fn profile_fingerprint(profile: &DeviceProfile) -> String {
+    let mut hasher = Sha256::new();
+    for field in [
+        &profile.machine_id,
+        &profile.mac_machine_id,
+        &profile.dev_device_id,
+        &profile.sqm_id,
+    ] {
+        hasher.update(field.trim().to_lowercase().as_bytes());
+        hasher.update([0u8]);
+    }
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+/// 检测被两个及以上账号共用的指纹 (未绑定指纹的账号不参与)
+pub fn find_collisions(accounts: &[Account]) -> Vec<DeviceProfileCollision> {
+    let mut groups: HashMap<String, Vec<&Account>> = HashMap::new();
+    for acc in accounts {
+        if let Some(profile) = &acc.device_profile {
+            groups
+                .entry(profile_fingerprint(profile))
+                .or_default()
+                .push(acc);
+        }
+    }
+    let mut collisions: Vec<DeviceProfileCollision> = groups
+        .into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(fingerprint, members)| DeviceProfileCollision {
+            fingerprint,
+            account_ids: members.iter().map(|a| a.id.clone()).collect(),
+            emails: members.iter().map(|a| a.email.clone()).collect(),
+        })
+        .collect();
+    collisions.sort_by(|a, b| {
+        b.account_ids
+            .len()
+            .cmp(&a.account_ids.len())
+            .then_with(|| a.fingerprint.cmp(&b.fingerprint))
+    });
+    collisions
+}
+
+pub fn build_report(accounts: &[Account]) -> DeviceConsistencyReport {
+    DeviceConsistencyReport {
+        checked_at: chrono::Utc::now().timestamp(),
+        bound_accounts: accounts
+            .iter()
+            .filter(|a| a.device_profile.is_some())
+            .count(),
+        collisions: find_collisions(accounts),
+    }
+}
+
+/// 需要重新生成指纹的账号: 每组保留一个 (优先 keep_account_id，即当前账号)，其余全部重新生成
+pub fn accounts_to_regenerate(
+    collisions: &[DeviceProfileCollision],
+    keep_account_id: Option<&str>,
+) -> Vec<String> {
+    collisions
+        .iter()
+        .flat_map(|c| {
+            let keep = keep_account_id
+                .filter(|id| c.account_ids.iter().any(|a| a == id))
+                .unwrap_or(&c.account_ids[0]);
+            c.account_ids.iter().filter(move |id| *id != keep).cloned()
+        })
+        .collect()
+}
+
+/// 执行检查并持久化结果，存在冲突时记录告警
+pub fn check_and_persist() -> Result<DeviceConsistencyReport, String> {
+    let report = build_report(&account::list_accounts()?);
+    for c in &report.collisions {
+        logger::log_warn(&format!(
+            "[Device-Consistency] Device profile {} is shared by {} accounts: {}",
+            c.fingerprint,
+            c.account_ids.len(),
+            c.emails.join(", ")
+        ));
+    }
+    let path = account::get_data_dir()?.join(REPORT_FILE);
+    let content =
+        serde_json::to_string_pretty(&report).map_err(|e| format!("serialize_failed: {}", e))?;
+    fs::write(&path, content).map_err(|e| format!("write_failed: {}", e))?;
+    Ok(report)
+}
+
+/// 账号新增 / 绑定后的检查 (失败仅记录日志，不影响原操作)
+/// 合并窗口内的多次变更只触发一次检查，避免批量导入时每个账号都重新读取全部账号文件
+pub fn check_after_change() {
+    if CHECK_PENDING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    tauri::async_runtime::spawn(async {
+        tokio::time::sleep(CHECK_DEBOUNCE).await;
+        // 先清除标记: 检查期间的新变更会再排一次检查，不会漏掉
+        CHECK_PENDING.store(false, Ordering::Release);
+        let result = tauri::async_runtime::spawn_blocking(check_and_persist).await;
+        match result {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => logger::log_warn(&format!("[Device-Consistency] Check failed: {}", e)),
+            Err(e) => logger::log_warn(&format!("[Device-Consistency] Check task failed: {}", e)),
+        }
+    });
+}
+
+/// 为所有冲突账号重新生成唯一指纹 (每组保留一个账号不变)，并写入指纹历史
+pub fn regenerate_collided_profiles() -> Result<RegenerationResult, String> {
+    let collisions = find_collisions(&account::list_accounts()?);
+    let current_id = account::get_current_account_id()?;
+    let targets = accounts_to_regenerate(&collisions, current_id.as_deref());
+
+    let mut regenerated = Vec::with_capacity(targets.len());
+    for id in targets {
+        account::update_account(&id, |acc| {
+            account::apply_profile_to_account(
+                acc,
+                device::generate_profile(),
+                Some(REGENERATED_LABEL.to_string()),
+                true,
+            );
+            Ok(())
+        })?;
+        regenerated.push(id);
+    }
+    logger::log_info(&format!(
+        "[Device-Consistency] Regenerated device profiles for {} accounts",
+        regenerated.len()
+    ));
+
+    Ok(RegenerationResult {
+        regenerated_account_ids: regenerated,
+        report: check_and_persist()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TokenData;
+
+    fn account_with_profile(id: &str, profile: Option<DeviceProfile>) -> Account {
+        let mut acc = Account::new(
+            id.to_string(),
+            format!("{}@example.com", id),
+            TokenData::new("at".to_string(), "rt".to_string(), 3600, None, None, None),
+        );
+        acc.device_profile = profile;
+        acc
+    }
+
+    #[test]
+    fn test_collision_detection_across_three_accounts() {
+        let shared = device::generate_profile();
+        let accounts = vec![
+            account_with_profile("a", Some(shared.clone())),
+            account_with_profile("b", Some(device::generate_profile())),
+            account_with_profile("c", Some(shared.clone())),
+            account_with_profile("d", Some(shared.clone())),
+            account_with_profile("e", None),
+        ];
+
+        let collisions = find_collisions(&accounts);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].fingerprint, profile_fingerprint(&shared));
+        assert_eq!(collisions[0].account_ids, vec!["a", "c", "d"]);
+
+        let report = build_report(&accounts);
+        assert_eq!(report.bound_accounts, 4);
+        assert_eq!(report.collisions, collisions);
+    }
+
+    #[test]
+    fn test_bulk_regeneration_produces_distinct_profiles_with_history() {
+        let data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
+
+        let shared = device::generate_profile();
+        let mut index = crate::models::AccountIndex::new();
+        for id in ["a", "b", "c"] {
+            let acc = account_with_profile(id, Some(shared.clone()));
+            account::save_account(&acc).unwrap();
+            index.accounts.push(crate::models::AccountSummary {
+                id: acc.id.clone(),
+                email: acc.email.clone(),
+                name: None,
+                disabled: false,
+                proxy_disabled: false,
+                protected_models: Default::default(),
+                group: None,
+                created_at: acc.created_at,
+                last_used: acc.last_used,
+            });
+        }
+        // 当前账号保留原指纹
+        index.current_account_id = Some("b".to_string());
+        account::save_account_index(&index).unwrap();
+
+        let result = regenerate_collided_profiles();
+        let accounts = account::list_accounts();
+        let persisted_report = fs::read_to_string(data_dir.path().join(REPORT_FILE));
+        drop(data_dir);
+
+        let result = result.unwrap();
+        assert_eq!(result.regenerated_account_ids, vec!["a", "c"]);
+        assert!(result.report.collisions.is_empty());
+        assert_eq!(result.report.bound_accounts, 3);
+        let persisted: DeviceConsistencyReport = serde_json::from_str(&persisted_report.unwrap()).unwrap();
+        assert_eq!(persisted, result.report);
+
+        let accounts = accounts.unwrap();
+        let hashes: std::collections::HashSet<String> = accounts
+            .iter()
+            .map(|a| profile_fingerprint(a.device_profile.as_ref().unwrap()))
+            .collect();
+        assert_eq!(hashes.len(), 3);
+        let current = accounts.iter().find(|a| a.id == "b").unwrap();
+        assert_eq!(profile_fingerprint(current.device_profile.as_ref().unwrap()), profile_fingerprint(&shared));
+        assert!(current.device_history.is_empty());
+        for acc in accounts.iter().filter(|a| a.id != "b") {
+            assert_eq!(acc.device_history.len(), 1);
+            assert_eq!(acc.device_history[0].label, REGENERATED_LABEL);
+            assert!(acc.device_history[0].is_current);
+        }
+    }
+}
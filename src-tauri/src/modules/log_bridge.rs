@@ -4,7 +4,7 @@
 use parking_lot::RwLock;
 use serde::Serialize;
 use std::collections::VecDeque;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, OnceLock};
 use tauri::Emitter;
 use tracing::field::{Field, Visit};
@@ -12,8 +12,11 @@ use tracing::{Event, Level, Subscriber};
 use tracing_subscriber::layer::Context;
 use tracing_subscriber::Layer;
 
-/// Maximum logs to keep in buffer
-const MAX_BUFFER_SIZE: usize = 5000;
+/// Default number of logs to keep in buffer
+const DEFAULT_BUFFER_SIZE: usize = 5000;
+
+/// Maximum logs to keep in buffer (config: logging.buffer_size)
+static BUFFER_CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_BUFFER_SIZE);
 
 /// Global flag to enable/disable log bridging
 static LOG_BRIDGE_ENABLED: AtomicBool = AtomicBool::new(false);
@@ -28,7 +31,21 @@ static APP_HANDLE: OnceLock<tauri::AppHandle> = OnceLock::new();
 static LOG_BUFFER: OnceLock<Arc<RwLock<VecDeque<LogEntry>>>> = OnceLock::new();
 
 fn get_log_buffer() -> &'static Arc<RwLock<VecDeque<LogEntry>>> {
-    LOG_BUFFER.get_or_init(|| Arc::new(RwLock::new(VecDeque::with_capacity(MAX_BUFFER_SIZE))))
+    LOG_BUFFER.get_or_init(|| {
+        Arc::new(RwLock::new(VecDeque::with_capacity(
+            BUFFER_CAPACITY.load(Ordering::Relaxed),
+        )))
+    })
+}
+
+/// Set the live view buffer size, dropping the oldest entries if it shrinks
+pub fn set_buffer_capacity(capacity: usize) {
+    let capacity = capacity.max(1);
+    BUFFER_CAPACITY.store(capacity, Ordering::Relaxed);
+    let mut buffer = get_log_buffer().write();
+    while buffer.len() > capacity {
+        buffer.pop_front();
+    }
 }
 
 /// Log entry sent to frontend
@@ -86,13 +103,13 @@ pub fn clear_log_buffer() {
 }
 
 /// Visitor to extract fields from tracing events
-struct FieldVisitor {
-    message: Option<String>,
-    fields: std::collections::HashMap<String, String>,
+pub(crate) struct FieldVisitor {
+    pub(crate) message: Option<String>,
+    pub(crate) fields: std::collections::HashMap<String, String>,
 }
 
 impl FieldVisitor {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             message: None,
             fields: std::collections::HashMap::new(),
@@ -195,7 +212,8 @@ where
         // Add to buffer
         {
             let mut buffer = get_log_buffer().write();
-            if buffer.len() >= MAX_BUFFER_SIZE {
+            let capacity = BUFFER_CAPACITY.load(Ordering::Relaxed);
+            while buffer.len() >= capacity {
                 buffer.pop_front();
             }
             buffer.push_back(entry.clone());
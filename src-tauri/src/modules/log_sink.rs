@@ -0,0 +1,288 @@
+//! Log File Sink - size-based rotation for app.log and an optional JSON-lines format.
+//! app.log rotates to app.log.1 .. app.log.N once it would exceed the configured size.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+use crate::modules::log_bridge::FieldVisitor;
+
+/// Longest message prefix treated as a trace id ("[req_123] ...")
+const MAX_TRACE_ID_LEN: usize = 64;
+
+/// File writer with size-based rotation; clones share the same file so rotation is serialized
+#[derive(Clone)]
+pub struct RotatingFileWriter {
+    state: Arc<Mutex<RotatingState>>,
+}
+
+struct RotatingState {
+    path: PathBuf,
+    file: Option<File>,
+    size: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingFileWriter {
+    pub fn new(path: PathBuf, max_bytes: u64, max_files: usize) -> Self {
+        let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        Self {
+            state: Arc::new(Mutex::new(RotatingState {
+                path,
+                file: None,
+                size,
+                max_bytes: max_bytes.max(1),
+                max_files,
+            })),
+        }
+    }
+}
+
+/// Path of the n-th rotated file (app.log.1, app.log.2, ...)
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+impl RotatingState {
+    fn rotate(&mut self) -> io::Result<()> {
+        // Close the active file before renaming it (required on Windows)
+        self.file = None;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path).or_else(ignore_not_found)?;
+        } else {
+            fs::remove_file(rotated_path(&self.path, self.max_files)).or_else(ignore_not_found)?;
+            for index in (1..self.max_files).rev() {
+                fs::rename(
+                    rotated_path(&self.path, index),
+                    rotated_path(&self.path, index + 1),
+                )
+                .or_else(ignore_not_found)?;
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1)).or_else(ignore_not_found)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A single record larger than the limit still goes into one (fresh) file
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        if self.file.is_none() {
+            self.file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(buf)?;
+        }
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+}
+
+fn ignore_not_found(e: io::Error) -> io::Result<()> {
+    if e.kind() == io::ErrorKind::NotFound {
+        Ok(())
+    } else {
+        Err(e)
+    }
+}
+
+impl Write for RotatingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "log writer lock poisoned"))?;
+        state.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "log writer lock poisoned"))?;
+        match state.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Trace id of a log line: the `trace_id` field, or a "[req_123] ..." style message prefix
+fn extract_trace_id(fields: &mut std::collections::HashMap<String, String>, message: &str) -> Option<String> {
+    if let Some(id) = fields.remove("trace_id") {
+        return Some(id.trim_matches('"').to_string());
+    }
+    // Handler logs prefix messages with lowercase ids; "[Quota]"-style module tags are not ids
+    let rest = message.strip_prefix('[')?;
+    let id = &rest[..rest.find(']')?];
+    let is_id = !id.is_empty()
+        && id.len() <= MAX_TRACE_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    is_id.then(|| id.to_string())
+}
+
+/// Format an event as one JSON line
+fn json_line(event: &Event<'_>) -> String {
+    let metadata = event.metadata();
+    let mut visitor = FieldVisitor::new();
+    event.record(&mut visitor);
+    let message = visitor.message.unwrap_or_default();
+    let trace_id = extract_trace_id(&mut visitor.fields, &message);
+
+    let mut line = serde_json::json!({
+        "timestamp": chrono::Local::now().to_rfc3339(),
+        "level": metadata.level().to_string(),
+        "target": metadata.target(),
+        "message": message,
+    });
+    if let Some(trace_id) = trace_id {
+        line["trace_id"] = serde_json::Value::String(trace_id);
+    }
+    if !visitor.fields.is_empty() {
+        line["fields"] = serde_json::json!(visitor.fields);
+    }
+    format!("{}\n", line)
+}
+
+/// Tracing layer writing one JSON object per event
+pub struct JsonLinesLayer<W> {
+    make_writer: W,
+}
+
+impl<W> JsonLinesLayer<W> {
+    pub fn new(make_writer: W) -> Self {
+        Self { make_writer }
+    }
+}
+
+impl<S, W> Layer<S> for JsonLinesLayer<W>
+where
+    S: Subscriber,
+    W: for<'a> MakeWriter<'a> + 'static,
+{
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let line = json_line(event);
+        let _ = self.make_writer.make_writer().write_all(line.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn temp_log_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("log_sink_test_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_rotation_triggers_at_threshold_and_keeps_count() {
+        let dir = temp_log_dir();
+        let path = dir.join("app.log");
+        let writer = RotatingFileWriter::new(path.clone(), 100, 2);
+
+        // 8 threads x 10 lines of 19 bytes: many rotations under concurrent writes
+        std::thread::scope(|scope| {
+            for t in 0..8 {
+                let mut writer = writer.clone();
+                scope.spawn(move || {
+                    for i in 0..10 {
+                        writer
+                            .write_all(format!("thread-{} line-{:04}\n", t, i).as_bytes())
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["app.log", "app.log.1", "app.log.2"]);
+
+        for name in &files {
+            let content = fs::read_to_string(dir.join(name)).unwrap();
+            assert!(content.len() <= 100, "{} exceeds the size limit", name);
+            // Lines are never torn or interleaved
+            assert!(content
+                .lines()
+                .all(|l| l.len() == 18 && l.starts_with("thread-")));
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[derive(Clone, Default)]
+    struct BufferWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for BufferWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for BufferWriter {
+        type Writer = BufferWriter;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn test_json_lines_parse_and_carry_trace_id() {
+        let buffer = BufferWriter::default();
+        let subscriber =
+            tracing_subscriber::registry().with(JsonLinesLayer::new(buffer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("[ab12cd] Stream collected");
+            tracing::warn!(trace_id = "req_42", attempt = 2, "Retrying upstream");
+            tracing::info!("[Quota] Refreshed");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|l| serde_json::from_str(l).expect("each line is a JSON object"))
+            .collect();
+        assert_eq!(lines.len(), 3);
+
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["trace_id"], "ab12cd");
+        assert_eq!(lines[0]["message"], "[ab12cd] Stream collected");
+
+        assert_eq!(lines[1]["level"], "WARN");
+        assert_eq!(lines[1]["trace_id"], "req_42");
+        assert_eq!(lines[1]["fields"]["attempt"], "2");
+        assert!(lines[1]["target"].as_str().unwrap().contains("log_sink"));
+
+        // Module tags are not trace ids
+        assert!(lines[2].get("trace_id").is_none());
+    }
+}
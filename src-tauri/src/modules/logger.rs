@@ -38,9 +38,18 @@ pub fn init_logger() {
         }
     };
     
-    // 1. Set up file Appender (using tracing-appender for rolling logs)
-    // Using a daily rolling strategy here
-    let file_appender = tracing_appender::rolling::daily(log_dir, "app.log");
+    // [NEW] Log output settings (file rotation / format take effect at startup)
+    let log_config = crate::modules::config::load_app_config()
+        .map(|c| c.logging)
+        .unwrap_or_default();
+    crate::modules::log_bridge::set_buffer_capacity(log_config.buffer_size);
+
+    // 1. Set up file Appender (size-based rotation: app.log -> app.log.1 .. app.log.N)
+    let file_appender = crate::modules::log_sink::RotatingFileWriter::new(
+        log_dir.join("app.log"),
+        log_config.max_file_size_mb.max(1) * 1024 * 1024,
+        log_config.max_files,
+    );
     let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
     
     // 2. Console output layer (using local timezone)
@@ -50,13 +59,18 @@ pub fn init_logger() {
         .with_level(true)
         .with_timer(LocalTimer);
         
-    // 3. File output layer (disable ANSI formatting, use local timezone)
-    let file_layer = fmt::Layer::new()
-        .with_writer(non_blocking)
-        .with_ansi(false)
-        .with_target(true)
-        .with_level(true)
-        .with_timer(LocalTimer);
+    // 3. File output layer (text or JSON lines; disable ANSI formatting, use local timezone)
+    let json_mode = log_config.format == crate::models::LogFormat::Json;
+    let file_layer = (!json_mode).then(|| {
+        fmt::Layer::new()
+            .with_writer(non_blocking.clone())
+            .with_ansi(false)
+            .with_target(true)
+            .with_level(true)
+            .with_timer(LocalTimer)
+    });
+    let json_file_layer =
+        json_mode.then(|| crate::modules::log_sink::JsonLinesLayer::new(non_blocking));
 
     // 4. Set filtering layer (default to INFO level to reduce log size)
     let filter_layer = EnvFilter::try_from_default_env()
@@ -70,6 +84,7 @@ pub fn init_logger() {
         .with(filter_layer)
         .with(console_layer)
         .with(file_layer)
+        .with(json_file_layer)
         .with(bridge_layer)
         .try_init();
 
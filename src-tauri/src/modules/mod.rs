@@ -11,9 +11,13 @@ pub mod tray;
 pub mod i18n;
 pub mod proxy_db;
 pub mod device;
+pub mod device_consistency;
 pub mod update_checker;
 pub mod scheduler;
 pub mod token_stats;
+pub mod usage_export;
+pub mod db_maintenance;
+pub mod quota_forecast;
 pub mod cloudflared;
 pub mod integration;
 pub mod account_service;
@@ -21,9 +25,12 @@ pub mod account_service;
 pub mod http_api;
 pub mod cache;
 pub mod log_bridge;
+pub mod log_sink;
 pub mod security_db;
+pub mod capture_crypto;
 pub mod user_token_db;
 pub mod version;
+pub mod token_import;
 
 use crate::models;
 
@@ -50,6 +50,11 @@ impl UserInfo {
 
 /// Generate OAuth authorization URL
 pub fn get_auth_url(redirect_uri: &str, state: &str) -> String {
+    get_auth_url_with_hint(redirect_uri, state, None)
+}
+
+/// [NEW] 生成授权链接，可选预填账号邮箱 (login_hint，用于重新授权)
+pub fn get_auth_url_with_hint(redirect_uri: &str, state: &str, login_hint: Option<&str>) -> String {
     let scopes = vec![
         "https://www.googleapis.com/auth/cloud-platform",
         "https://www.googleapis.com/auth/userinfo.email",
@@ -58,7 +63,7 @@ pub fn get_auth_url(redirect_uri: &str, state: &str) -> String {
         "https://www.googleapis.com/auth/experimentsandconfigs"
     ].join(" ");
 
-    let params = vec![
+    let mut params = vec![
         ("client_id", CLIENT_ID),
         ("redirect_uri", redirect_uri),
         ("response_type", "code"),
@@ -68,7 +73,10 @@ pub fn get_auth_url(redirect_uri: &str, state: &str) -> String {
         ("include_granted_scopes", "true"),
         ("state", state),
     ];
-    
+    if let Some(hint) = login_hint {
+        params.push(("login_hint", hint));
+    }
+
     let url = url::Url::parse_with_params(AUTH_URL, &params).expect("Invalid Auth URL");
     url.to_string()
 }
@@ -248,5 +256,9 @@ mod tests {
         assert!(url.contains("state=test-state-123456"));
         assert!(url.contains("redirect_uri=http%3A%2F%2Flocalhost%3A8080%2Fcallback"));
         assert!(url.contains("response_type=code"));
+        assert!(!url.contains("login_hint"));
+
+        let url = get_auth_url_with_hint(redirect_uri, state, Some("user@example.com"));
+        assert!(url.contains("login_hint=user%40example.com"));
     }
 }
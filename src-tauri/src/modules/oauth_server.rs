@@ -22,6 +22,26 @@ fn get_oauth_flow_state() -> &'static Mutex<Option<OAuthFlowState>> {
     OAUTH_FLOW_STATE.get_or_init(|| Mutex::new(None))
 }
 
+/// [NEW] 重新授权时预填的账号邮箱，仅对下一次生成的授权链接生效
+static REAUTH_LOGIN_HINT: OnceLock<Mutex<Option<String>>> = OnceLock::new();
+
+fn get_reauth_login_hint() -> &'static Mutex<Option<String>> {
+    REAUTH_LOGIN_HINT.get_or_init(|| Mutex::new(None))
+}
+
+/// 设置重新授权的预填邮箱 (如 scope 不足的账号)
+/// 会丢弃尚未使用的授权链接，确保下一次生成的链接带上 login_hint
+pub fn set_reauth_login_hint(email: Option<String>) {
+    if let Ok(mut hint) = get_reauth_login_hint().lock() {
+        *hint = email;
+    }
+    if let Ok(mut state) = get_oauth_flow_state().lock() {
+        if let Some(s) = state.take() {
+            let _ = s.cancel_tx.send(true);
+        }
+    }
+}
+
 fn oauth_success_html() -> &'static str {
     "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n\r\n\
     <html>\
@@ -121,7 +141,8 @@ async fn ensure_oauth_flow_prepared(app_handle: Option<tauri::AppHandle>) -> Res
     };
 
     let state_str = uuid::Uuid::new_v4().to_string();
-    let auth_url = oauth::get_auth_url(&redirect_uri, &state_str);
+    let login_hint = get_reauth_login_hint().lock().ok().and_then(|mut h| h.take());
+    let auth_url = oauth::get_auth_url_with_hint(&redirect_uri, &state_str, login_hint.as_deref());
 
     // Cancellation signal (supports multiple consumers)
     let (cancel_tx, cancel_rx) = watch::channel(false);
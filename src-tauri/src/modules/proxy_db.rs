@@ -51,6 +51,8 @@ pub fn init_db() -> Result<(), String> {
     let _ = conn.execute("ALTER TABLE request_logs ADD COLUMN protocol TEXT", []);
     let _ = conn.execute("ALTER TABLE request_logs ADD COLUMN client_ip TEXT", []);
     let _ = conn.execute("ALTER TABLE request_logs ADD COLUMN username TEXT", []);
+    let _ = conn.execute("ALTER TABLE request_logs ADD COLUMN model_version TEXT", []);
+    let _ = conn.execute("ALTER TABLE request_logs ADD COLUMN query_overrides TEXT", []);
 
     conn.execute(
         "CREATE INDEX IF NOT EXISTS idx_timestamp ON request_logs (timestamp DESC)",
@@ -67,11 +69,12 @@ pub fn init_db() -> Result<(), String> {
 }
 
 pub fn save_log(log: &ProxyRequestLog) -> Result<(), String> {
+    let _gate = crate::modules::db_maintenance::write_guard();
     let conn = connect_db()?;
 
     conn.execute(
-        "INSERT INTO request_logs (id, timestamp, method, url, status, duration, model, error, request_body, response_body, input_tokens, output_tokens, account_email, mapped_model, protocol, client_ip, username)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+        "INSERT INTO request_logs (id, timestamp, method, url, status, duration, model, error, request_body, response_body, input_tokens, output_tokens, account_email, mapped_model, protocol, client_ip, username, model_version, query_overrides)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)",
         params![
             log.id,
             log.timestamp,
@@ -90,6 +93,8 @@ pub fn save_log(log: &ProxyRequestLog) -> Result<(), String> {
             log.protocol,
             log.client_ip,
             log.username,
+            log.model_version,
+            log.query_overrides,
         ],
     ).map_err(|e| e.to_string())?;
 
@@ -125,9 +130,14 @@ pub fn get_logs_summary(limit: usize, offset: usize) -> Result<Vec<ProxyRequestL
             response_body: None, // Don't query large fields for list view
             input_tokens: row.get(10).unwrap_or(None),
             output_tokens: row.get(11).unwrap_or(None),
+            cached_tokens: None,
             protocol: row.get(14).unwrap_or(None),
             client_ip: row.get(15).unwrap_or(None),
             username: row.get(16).unwrap_or(None),
+            request_hash: None,
+            output_breakdown: None,
+            model_version: None,
+            query_overrides: None,
         })
 
     }).map_err(|e| e.to_string())?;
@@ -173,7 +183,7 @@ pub fn get_log_detail(log_id: &str) -> Result<ProxyRequestLog, String> {
     let mut stmt = conn.prepare(
         "SELECT id, timestamp, method, url, status, duration, model, error,
                 request_body, response_body, input_tokens, output_tokens,
-                account_email, mapped_model, protocol, client_ip, username
+                account_email, mapped_model, protocol, client_ip, username, model_version, query_overrides
          FROM request_logs
          WHERE id = ?1"
     ).map_err(|e| e.to_string())?;
@@ -194,9 +204,14 @@ pub fn get_log_detail(log_id: &str) -> Result<ProxyRequestLog, String> {
             response_body: row.get(9).unwrap_or(None),
             input_tokens: row.get(10).unwrap_or(None),
             output_tokens: row.get(11).unwrap_or(None),
+            cached_tokens: None,
             protocol: row.get(14).unwrap_or(None),
             client_ip: row.get(15).unwrap_or(None),
             username: row.get(16).unwrap_or(None),
+            request_hash: None,
+            output_breakdown: None,
+            model_version: row.get(17).unwrap_or(None),
+            query_overrides: row.get(18).unwrap_or(None),
         })
     }).map_err(|e| e.to_string())
 }
@@ -333,9 +348,14 @@ pub fn get_logs_filtered(filter: &str, errors_only: bool, limit: usize, offset:
                 response_body: None,
                 input_tokens: row.get(10).unwrap_or(None),
                 output_tokens: row.get(11).unwrap_or(None),
+                cached_tokens: None,
                 protocol: row.get(14).unwrap_or(None),
                 client_ip: row.get(15).unwrap_or(None),
                 username: row.get(16).unwrap_or(None),
+                request_hash: None,
+                output_breakdown: None,
+                model_version: None,
+                query_overrides: None,
             })
 
         }).map_err(|e| e.to_string())?;
@@ -358,9 +378,14 @@ pub fn get_logs_filtered(filter: &str, errors_only: bool, limit: usize, offset:
                 response_body: None,
                 input_tokens: row.get(10).unwrap_or(None),
                 output_tokens: row.get(11).unwrap_or(None),
+                cached_tokens: None,
                 protocol: row.get(14).unwrap_or(None),
                 client_ip: row.get(15).unwrap_or(None),
                 username: row.get(16).unwrap_or(None),
+                request_hash: None,
+                output_breakdown: None,
+                model_version: None,
+                query_overrides: None,
             })
 
         }).map_err(|e| e.to_string())?;
@@ -383,9 +408,14 @@ pub fn get_logs_filtered(filter: &str, errors_only: bool, limit: usize, offset:
                 response_body: None,
                 input_tokens: row.get(10).unwrap_or(None),
                 output_tokens: row.get(11).unwrap_or(None),
+                cached_tokens: None,
                 protocol: row.get(14).unwrap_or(None),
                 client_ip: row.get(15).unwrap_or(None),
                 username: row.get(16).unwrap_or(None),
+                request_hash: None,
+                output_breakdown: None,
+                model_version: None,
+                query_overrides: None,
             })
 
         }).map_err(|e| e.to_string())?;
@@ -423,9 +453,14 @@ pub fn get_all_logs_for_export() -> Result<Vec<ProxyRequestLog>, String> {
             response_body: row.get(9).unwrap_or(None),
             input_tokens: row.get(10).unwrap_or(None),
             output_tokens: row.get(11).unwrap_or(None),
+            cached_tokens: None,
             protocol: row.get(14).unwrap_or(None),
             client_ip: row.get(15).unwrap_or(None),
             username: row.get(16).unwrap_or(None),
+            request_hash: None,
+            output_breakdown: None,
+            model_version: None,
+            query_overrides: None,
         })
 
     }).map_err(|e| e.to_string())?;
@@ -1,7 +1,7 @@
 use reqwest;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use crate::models::QuotaData;
+use crate::models::{ModelQuota, QuotaData};
 use crate::modules::config;
 
 const QUOTA_API_URL: &str = "https://daily-cloudcode-pa.sandbox.googleapis.com/v1internal:fetchAvailableModels";
@@ -18,16 +18,102 @@ struct QuotaResponse {
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ModelInfo {
+    /// [NEW] 保留原始结构，单个模型的 quotaInfo 形状变化不影响整个响应的解析
     #[serde(rename = "quotaInfo")]
-    quota_info: Option<QuotaInfo>,
+    quota_info: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct QuotaInfo {
-    #[serde(rename = "remainingFraction")]
-    remaining_fraction: Option<f64>,
+    #[serde(rename = "remainingFraction", default)]
+    remaining_fraction: Option<serde_json::Value>,
     #[serde(rename = "resetTime")]
     reset_time: Option<String>,
+    /// [NEW] 绝对额度 (部分账号/模型提供，int64 可能以字符串形式返回)
+    #[serde(rename = "remainingTokens", alias = "remainingAmount", default)]
+    remaining_tokens: Option<serde_json::Value>,
+    #[serde(rename = "totalTokens", alias = "tokenLimit", default)]
+    total_tokens: Option<serde_json::Value>,
+    /// [NEW] 重置窗口长度 (Duration 格式如 "18000s"，或秒数)
+    #[serde(rename = "resetWindow", alias = "windowDuration", default)]
+    reset_window: Option<serde_json::Value>,
+    /// [NEW] 嵌套的多窗口配额 (如同时存在 5 小时与每周窗口)
+    #[serde(alias = "quotaWindows", default)]
+    windows: Vec<QuotaInfo>,
+}
+
+/// 解析 int64 字段 (数字或数字字符串)
+fn value_as_u64(value: &serde_json::Value) -> Option<u64> {
+    value
+        .as_u64()
+        .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+}
+
+/// 解析 Duration 字段 ("18000s" / "18000" / 18000)
+fn value_as_duration_secs(value: &serde_json::Value) -> Option<u64> {
+    value.as_u64().or_else(|| {
+        let s = value.as_str()?.trim();
+        let secs = s.strip_suffix('s').unwrap_or(s);
+        secs.parse::<f64>().ok().filter(|v| *v >= 0.0).map(|v| v as u64)
+    })
+}
+
+/// 生效的配额窗口: 自身与所有嵌套窗口中剩余百分比最低 (最严格) 的一个
+/// 单个窗口的百分比与账号 JSON 共用 models::quota::window_percentage
+fn effective_window(info: &QuotaInfo) -> Option<(&QuotaInfo, i32)> {
+    serde_json::to_value(info)
+        .ok()
+        .and_then(|raw| crate::models::quota::window_percentage(&raw))
+        .map(|pct| (info, pct))
+        .into_iter()
+        .chain(info.windows.iter().filter_map(effective_window))
+        .min_by_key(|(_, pct)| *pct)
+}
+
+/// 将单个模型的 quotaInfo 转为 ModelQuota (绝对额度缺失时仅保留百分比)
+/// 无法解析出剩余百分比时标记为 unparsed，而不是视为 0%
+fn parse_model_quota(name: String, raw: &serde_json::Value) -> ModelQuota {
+    let info = serde_json::from_value::<QuotaInfo>(raw.clone()).ok();
+    let Some((window, percentage)) = info.as_ref().and_then(effective_window) else {
+        crate::models::quota::log_unparsed_once(&name, raw);
+        return ModelQuota {
+            name,
+            percentage: 0,
+            reset_time: info.as_ref().and_then(|i| i.reset_time.clone()).unwrap_or_default(),
+            remaining_tokens: None,
+            limit_tokens: None,
+            reset_window_secs: None,
+            unparsed: true,
+        };
+    };
+
+    ModelQuota {
+        name,
+        percentage,
+        reset_time: window
+            .reset_time
+            .clone()
+            .or_else(|| info.as_ref().and_then(|i| i.reset_time.clone()))
+            .unwrap_or_default(),
+        remaining_tokens: window.remaining_tokens.as_ref().and_then(value_as_u64),
+        limit_tokens: window.total_tokens.as_ref().and_then(value_as_u64),
+        reset_window_secs: window.reset_window.as_ref().and_then(value_as_duration_secs),
+        unparsed: false,
+    }
+}
+
+/// 解析 fetchAvailableModels 响应，只保留关心的模型
+fn parse_quota_response(quota_response: QuotaResponse) -> QuotaData {
+    let mut quota_data = QuotaData::new();
+    for (name, info) in quota_response.models {
+        if let Some(quota_info) = info.quota_info {
+            // Only keep models we care about
+            if name.contains("gemini") || name.contains("claude") {
+                quota_data.models.push(parse_model_quota(name, &quota_info));
+            }
+        }
+    }
+    quota_data
 }
 
 #[derive(Debug, Deserialize)]
@@ -194,25 +280,11 @@ pub async fn fetch_quota_with_cache(
                     .await
                     .map_err(|e| AppError::Network(e))?;
                 
-                let mut quota_data = QuotaData::new();
-                
                 // Use debug level for detailed info to avoid console noise
                 tracing::debug!("Quota API returned {} models", quota_response.models.len());
 
-                for (name, info) in quota_response.models {
-                    if let Some(quota_info) = info.quota_info {
-                        let percentage = quota_info.remaining_fraction
-                            .map(|f| (f * 100.0) as i32)
-                            .unwrap_or(0);
-                        
-                        let reset_time = quota_info.reset_time.clone().unwrap_or_default();
-                        
-                        // Only keep models we care about
-                        if name.contains("gemini") || name.contains("claude") {
-                            quota_data.add_model(name, percentage, reset_time);
-                        }
-                    }
-                }
+                // [NEW] 同时解析绝对额度与重置窗口 (若上游提供)
+                let mut quota_data = parse_quota_response(quota_response);
                 
                 // Set subscription tier
                 quota_data.subscription_tier = subscription_tier.clone();
@@ -542,3 +614,108 @@ pub async fn warm_up_account(account_id: &str) -> Result<String, String> {
 
     Ok(format!("Successfully triggered warmup for {} model series", warmed_count))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(body: serde_json::Value) -> QuotaData {
+        parse_quota_response(serde_json::from_value(body).unwrap())
+    }
+
+    #[test]
+    fn test_parse_absolute_quota_fields() {
+        let data = parse(json!({
+            "models": {
+                "gemini-3-flash": { "quotaInfo": {
+                    "remainingFraction": 0.25,
+                    "resetTime": "2026-01-01T05:00:00Z",
+                    "remainingTokens": "250000",
+                    "totalTokens": 1000000,
+                    "resetWindow": "18000s"
+                }},
+                "claude-sonnet-4-5": { "quotaInfo": {
+                    "remainingTokens": 300,
+                    "tokenLimit": "1200"
+                }},
+                "chat_20706": { "quotaInfo": { "remainingFraction": 1.0 } }
+            }
+        }));
+        assert_eq!(data.models.len(), 2);
+
+        let flash = data.models.iter().find(|m| m.name == "gemini-3-flash").unwrap();
+        assert_eq!(flash.percentage, 25);
+        assert_eq!(flash.remaining_tokens, Some(250_000));
+        assert_eq!(flash.limit_tokens, Some(1_000_000));
+        assert_eq!(flash.reset_window_secs, Some(18_000));
+
+        // 缺少 remainingFraction 时由绝对额度推导百分比
+        let claude = data.models.iter().find(|m| m.name == "claude-sonnet-4-5").unwrap();
+        assert_eq!(claude.percentage, 25);
+        assert_eq!(claude.reset_window_secs, None);
+
+        // 账号 JSON 与上游响应共用同一窗口百分比解析
+        let stored = json!({ "name": "claude", "remaining_tokens": 300, "limit_tokens": 1200 });
+        assert_eq!(crate::models::quota::window_percentage(&stored), Some(25));
+    }
+
+    #[test]
+    fn test_parse_percentage_only_payload() {
+        let data = parse(json!({
+            "models": {
+                "gemini-3-pro-high": { "quotaInfo": {
+                    "remainingFraction": 0.6,
+                    "resetTime": "2026-01-01T05:00:00Z"
+                }}
+            }
+        }));
+        let pro = &data.models[0];
+        assert_eq!(pro.percentage, 60);
+        assert_eq!(pro.remaining_tokens, None);
+        assert_eq!(pro.limit_tokens, None);
+
+        // 旧账号 JSON (无绝对额度字段) 仍可反序列化，且序列化时不输出空字段
+        let stored = serde_json::to_value(pro).unwrap();
+        assert!(stored.get("remaining_tokens").is_none());
+        let legacy: ModelQuota = serde_json::from_value(json!({
+            "name": "claude", "percentage": 40, "reset_time": ""
+        }))
+        .unwrap();
+        assert_eq!(legacy.remaining_tokens, None);
+    }
+
+    #[test]
+    fn test_parse_windowed_and_malformed_payloads() {
+        let data = parse(json!({
+            "models": {
+                "gemini-3-flash": { "quotaInfo": {
+                    "quotaWindows": [
+                        { "remainingFraction": 0.9, "resetTime": "2026-01-07T00:00:00Z", "resetWindow": "604800s" },
+                        { "remainingFraction": 0.05, "resetTime": "2026-01-01T05:00:00Z", "resetWindow": "18000s" }
+                    ]
+                }},
+                "gemini-3-pro-high": { "quotaInfo": { "remainingFraction": "n/a" } },
+                "claude-sonnet-4-5": { "quotaInfo": { "windows": "unexpected" } }
+            }
+        }));
+
+        // 嵌套窗口取最严格的窗口 (包括其重置时间与窗口长度)
+        let flash = data.models.iter().find(|m| m.name == "gemini-3-flash").unwrap();
+        assert_eq!(flash.percentage, 5);
+        assert!(!flash.unparsed);
+        assert_eq!(flash.reset_time, "2026-01-01T05:00:00Z");
+        assert_eq!(flash.reset_window_secs, Some(18_000));
+
+        // 无法解析的结构标记为 unparsed，而不是 0%
+        for name in ["gemini-3-pro-high", "claude-sonnet-4-5"] {
+            let model = data.models.iter().find(|m| m.name == name).unwrap();
+            assert!(model.unparsed, "{} should be unparsed", name);
+        }
+        assert!(data.is_unparsed());
+
+        // 标记在账号 JSON 中往返保留
+        let stored = serde_json::to_value(&data.models).unwrap();
+        let reloaded: Vec<ModelQuota> = serde_json::from_value(stored).unwrap();
+        assert_eq!(reloaded.iter().filter(|m| m.unparsed).count(), 2);
+    }
+}
@@ -0,0 +1,197 @@
+// 配额消耗速率预测
+// 基于 token_stats 最近 N 小时的用量计算每个 (账号, 模型) 的消耗速率 (tokens/hour)，
+// 当配额接口提供绝对剩余额度时，据此估算耗尽时间；仅有百分比时只返回速率。
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::models::Account;
+use crate::modules::token_stats;
+
+/// 默认统计窗口 (小时)
+pub const DEFAULT_FORECAST_WINDOW_HOURS: i64 = 6;
+/// 统计窗口上限 (小时)
+pub const MAX_FORECAST_WINDOW_HOURS: i64 = 24 * 7;
+
+/// 单个 (账号, 模型) 的配额预测
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QuotaForecast {
+    pub account_id: String,
+    pub email: String,
+    pub model: String,
+    pub percentage: i32,
+    pub remaining_tokens: Option<u64>,
+    pub limit_tokens: Option<u64>,
+    pub reset_time: String,
+    /// 最近窗口内的平均消耗速率
+    pub tokens_per_hour: f64,
+    /// 预计耗尽时间 (Unix 秒)；缺少绝对额度或无消耗时为 None
+    pub estimated_exhaustion_at: Option<i64>,
+    /// 是否会在配额重置前耗尽 (无法判断时为 None)
+    pub exhausts_before_reset: Option<bool>,
+}
+
+/// 统计窗口内的平均消耗速率
+pub fn burn_rate_per_hour(tokens_used: u64, window_hours: i64) -> f64 {
+    if window_hours <= 0 {
+        return 0.0;
+    }
+    tokens_used as f64 / window_hours as f64
+}
+
+/// 按当前速率估算剩余额度的耗尽时间 (Unix 秒)
+pub fn estimate_exhaustion_at(remaining_tokens: u64, tokens_per_hour: f64, now: i64) -> Option<i64> {
+    if tokens_per_hour <= 0.0 {
+        return None;
+    }
+    let hours_left = remaining_tokens as f64 / tokens_per_hour;
+    Some(now + (hours_left * 3600.0).round() as i64)
+}
+
+/// 模型分组键 (与 TokenManager 的配额缓存一致)
+fn model_group(name: &str) -> String {
+    crate::proxy::common::model_mapping::normalize_to_standard_id(name).unwrap_or_else(|| name.to_string())
+}
+
+/// 根据账号配额与用量记录构建预测
+/// `usage`: (account_email, model, total_tokens)，模型名按分组归并
+pub fn build_forecasts(
+    accounts: &[Account],
+    usage: &[(String, String, u64)],
+    window_hours: i64,
+    now: i64,
+) -> Vec<QuotaForecast> {
+    let mut used_by_group: HashMap<(String, String), u64> = HashMap::new();
+    for (email, model, tokens) in usage {
+        *used_by_group.entry((email.clone(), model_group(model))).or_default() += tokens;
+    }
+
+    let mut forecasts = Vec::new();
+    for account in accounts {
+        let Some(quota) = &account.quota else { continue };
+        for model in &quota.models {
+            let used = used_by_group
+                .get(&(account.email.clone(), model_group(&model.name)))
+                .copied()
+                .unwrap_or(0);
+            let tokens_per_hour = burn_rate_per_hour(used, window_hours);
+            let estimated_exhaustion_at = model
+                .remaining_tokens
+                .and_then(|remaining| estimate_exhaustion_at(remaining, tokens_per_hour, now));
+            let reset_at = chrono::DateTime::parse_from_rfc3339(&model.reset_time)
+                .ok()
+                .map(|dt| dt.timestamp());
+            let exhausts_before_reset = match (model.remaining_tokens, estimated_exhaustion_at, reset_at) {
+                (Some(_), Some(exhaust), Some(reset)) => Some(exhaust < reset),
+                // 有绝对额度但无消耗: 不会耗尽
+                (Some(_), None, _) => Some(false),
+                _ => None,
+            };
+
+            forecasts.push(QuotaForecast {
+                account_id: account.id.clone(),
+                email: account.email.clone(),
+                model: model.name.clone(),
+                percentage: model.percentage,
+                remaining_tokens: model.remaining_tokens,
+                limit_tokens: model.limit_tokens,
+                reset_time: model.reset_time.clone(),
+                tokens_per_hour,
+                estimated_exhaustion_at,
+                exhausts_before_reset,
+            });
+        }
+    }
+    forecasts
+}
+
+/// 计算所有账号的配额预测 (window_hours 缺省为 6 小时)
+pub fn get_quota_forecasts(window_hours: Option<i64>) -> Result<Vec<QuotaForecast>, String> {
+    let window_hours = window_hours
+        .unwrap_or(DEFAULT_FORECAST_WINDOW_HOURS)
+        .clamp(1, MAX_FORECAST_WINDOW_HOURS);
+    let now = chrono::Utc::now().timestamp();
+    let accounts = crate::modules::account::list_accounts()?;
+    let usage = token_stats::get_account_model_totals_since(now - window_hours * 3600)?;
+    Ok(build_forecasts(&accounts, &usage, window_hours, now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ModelQuota, QuotaData, TokenData};
+
+    fn account(email: &str, models: Vec<ModelQuota>) -> Account {
+        let token = TokenData::new("at".to_string(), "rt".to_string(), 3600, None, None, None);
+        let mut account = Account::new(email.to_string(), email.to_string(), token);
+        let mut quota = QuotaData::new();
+        quota.models = models;
+        account.quota = Some(quota);
+        account
+    }
+
+    fn model(name: &str, percentage: i32, remaining_tokens: Option<u64>, reset_time: &str) -> ModelQuota {
+        ModelQuota {
+            name: name.to_string(),
+            percentage,
+            reset_time: reset_time.to_string(),
+            remaining_tokens,
+            limit_tokens: remaining_tokens.map(|_| 1_000_000),
+            reset_window_secs: None,
+            unparsed: false,
+        }
+    }
+
+    #[test]
+    fn test_burn_rate_and_exhaustion_math() {
+        assert_eq!(burn_rate_per_hour(60_000, 6), 10_000.0);
+        assert_eq!(burn_rate_per_hour(60_000, 0), 0.0);
+
+        let now = 1_767_225_600; // 2026-01-01 00:00:00 UTC
+        // 25,000 剩余 / 10,000 每小时 = 2.5 小时
+        assert_eq!(estimate_exhaustion_at(25_000, 10_000.0, now), Some(now + 9_000));
+        assert_eq!(estimate_exhaustion_at(25_000, 0.0, now), None);
+    }
+
+    #[test]
+    fn test_forecast_from_synthetic_usage_history() {
+        let now = 1_767_225_600; // 2026-01-01 00:00:00 UTC
+        let accounts = vec![account(
+            "a@test.com",
+            vec![
+                model("claude-sonnet-4-5", 40, Some(50_000), "2026-01-01T05:00:00Z"),
+                model("gemini-3-flash", 80, Some(900_000), "2026-01-01T05:00:00Z"),
+                model("gemini-3-pro-high", 70, None, "2026-01-01T05:00:00Z"),
+            ],
+        )];
+        // 最近 4 小时: claude 分组 (含 opus 与 sonnet) 共 80k，flash 4k，pro 8k
+        let usage = vec![
+            ("a@test.com".to_string(), "claude-sonnet-4-5".to_string(), 60_000),
+            ("a@test.com".to_string(), "claude-opus-4-6-thinking".to_string(), 20_000),
+            ("a@test.com".to_string(), "gemini-3-flash".to_string(), 4_000),
+            ("a@test.com".to_string(), "gemini-3-pro-high".to_string(), 8_000),
+            ("b@test.com".to_string(), "claude-sonnet-4-5".to_string(), 999_999),
+        ];
+
+        let forecasts = build_forecasts(&accounts, &usage, 4, now);
+        let get = |name: &str| forecasts.iter().find(|f| f.model == name).unwrap();
+
+        // claude: 20k/h，50k 剩余 -> 2.5 小时后耗尽，早于 5 小时后的重置
+        let claude = get("claude-sonnet-4-5");
+        assert_eq!(claude.tokens_per_hour, 20_000.0);
+        assert_eq!(claude.estimated_exhaustion_at, Some(now + 9_000));
+        assert_eq!(claude.exhausts_before_reset, Some(true));
+
+        // flash: 1k/h，900k 剩余 -> 重置前不会耗尽
+        let flash = get("gemini-3-flash");
+        assert_eq!(flash.tokens_per_hour, 1_000.0);
+        assert_eq!(flash.exhausts_before_reset, Some(false));
+
+        // pro: 仅有百分比，只返回速率
+        let pro = get("gemini-3-pro-high");
+        assert_eq!(pro.percentage, 70);
+        assert_eq!(pro.tokens_per_hour, 2_000.0);
+        assert_eq!(pro.estimated_exhaustion_at, None);
+        assert_eq!(pro.exhausts_before_reset, None);
+    }
+}
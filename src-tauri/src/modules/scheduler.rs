@@ -3,7 +3,7 @@ use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use std::sync::Mutex;
 use tokio::time::{self, Duration};
-use crate::modules::{config, logger, quota, account};
+use crate::modules::{config, logger, quota, account, usage_export, db_maintenance};
 use crate::models::Account;
 use std::path::PathBuf;
 
@@ -52,6 +52,10 @@ pub fn check_cooldown(key: &str, cooldown_seconds: i64) -> bool {
 }
 
 pub fn start_scheduler(app_handle: Option<tauri::AppHandle>, proxy_state: crate::commands::proxy::ProxyServiceState) {
+    start_usage_export_job();
+    start_db_maintenance_job();
+    start_session_janitor();
+
     tauri::async_runtime::spawn(async move {
         logger::log_info("Smart Warmup Scheduler started. Monitoring quota at 100%...");
         
@@ -117,6 +121,10 @@ pub fn start_scheduler(app_handle: Option<tauri::AppHandle>, proxy_state: crate:
                 let now_ts = Utc::now().timestamp();
 
                 for model in fresh_quota.models {
+                    // [NEW] 剩余配额未知时不改变预热状态
+                    if model.unparsed {
+                        continue;
+                    }
                     // Core logic: detect 100% quota
                     if model.percentage == 100 {
                         let model_to_ping = model.name.clone();
@@ -274,6 +282,112 @@ pub fn start_scheduler(app_handle: Option<tauri::AppHandle>, proxy_state: crate:
     });
 }
 
+// Local date on which the usage export job last ran
+static LAST_USAGE_EXPORT: Lazy<Mutex<Option<chrono::NaiveDate>>> = Lazy::new(|| Mutex::new(None));
+
+/// Whether the daily usage export is due: past the configured time and not yet run today
+fn usage_export_due(run_at: &str, now: chrono::NaiveDateTime, last_run: Option<chrono::NaiveDate>) -> bool {
+    let run_at = chrono::NaiveTime::parse_from_str(run_at.trim(), "%H:%M")
+        .unwrap_or_else(|_| chrono::NaiveTime::from_hms_opt(0, 30, 0).unwrap());
+    now.time() >= run_at && last_run != Some(now.date())
+}
+
+/// Periodic job: reap per-session proxy state idle longer than the configured TTL
+fn start_session_janitor() {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(600));
+
+        loop {
+            interval.tick().await;
+            crate::proxy::session_registry::run_janitor();
+        }
+    });
+}
+
+/// Daily job: export the previous UTC day's token usage to CSV + JSON
+fn start_usage_export_job() {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(60));
+
+        loop {
+            interval.tick().await;
+
+            let Ok(app_config) = config::load_app_config() else {
+                continue;
+            };
+            let export_config = app_config.usage_export;
+            if !export_config.enabled {
+                continue;
+            }
+
+            let now = chrono::Local::now().naive_local();
+            {
+                let mut last_run = LAST_USAGE_EXPORT.lock().unwrap();
+                if !usage_export_due(&export_config.run_at, now, *last_run) {
+                    continue;
+                }
+                *last_run = Some(now.date());
+            }
+
+            let target = Utc::now().date_naive() - chrono::Duration::days(1);
+            if usage_export::daily_export_exists(&export_config, target) {
+                continue;
+            }
+
+            let result = tokio::task::spawn_blocking(move || {
+                usage_export::run_daily_export(&export_config, target)
+            })
+            .await;
+
+            match result {
+                Ok(Ok(paths)) => logger::log_info(&format!(
+                    "[UsageExport] Exported usage for {} ({} files)",
+                    target,
+                    paths.len()
+                )),
+                Ok(Err(e)) => logger::log_error(&format!("[UsageExport] Export for {} failed: {}", target, e)),
+                Err(e) => logger::log_error(&format!("[UsageExport] Export task panicked: {}", e)),
+            }
+        }
+    });
+}
+
+/// Periodic job: prune / vacuum the proxy databases every `interval_days`
+fn start_db_maintenance_job() {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(3600));
+
+        loop {
+            interval.tick().await;
+
+            let Ok(app_config) = config::load_app_config() else {
+                continue;
+            };
+            let maintenance_config = app_config.db_maintenance;
+            let last_run = db_maintenance::load_last_report().map(|r| r.started_at);
+            if !db_maintenance::maintenance_due(&maintenance_config, last_run, Utc::now().timestamp()) {
+                continue;
+            }
+
+            let result = tokio::task::spawn_blocking(move || {
+                db_maintenance::run_maintenance(&maintenance_config)
+            })
+            .await;
+
+            match result {
+                Ok(Ok(report)) => logger::log_info(&format!(
+                    "[DB-Maintenance] Scheduled run finished in {}ms ({} databases, {} errors)",
+                    report.duration_ms,
+                    report.databases.len(),
+                    report.errors.len()
+                )),
+                Ok(Err(e)) => logger::log_warn(&format!("[DB-Maintenance] Scheduled run skipped: {}", e)),
+                Err(e) => logger::log_error(&format!("[DB-Maintenance] Maintenance task panicked: {}", e)),
+            }
+        }
+    });
+}
+
 /// Trigger immediate smart warmup check for a single account
 pub async fn trigger_warmup_for_account(account: &Account) {
     if account.disabled || account.proxy_disabled {
@@ -310,6 +424,9 @@ pub async fn trigger_warmup_for_account(account: &Account) {
     let mut tasks_to_run = Vec::new();
 
     for model in fresh_quota.models {
+        if model.unparsed {
+            continue;
+        }
         let model_name = model.name.clone();
         let history_key = format!("{}:{}:100", account.email, model_name);
 
@@ -1,7 +1,7 @@
 //! Security Database Module
 //! 安全监控相关的数据库操作
 
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -167,15 +167,72 @@ pub fn init_db() -> Result<(), String> {
     // Migration: Add username column to ip_access_logs
     let _ = conn.execute("ALTER TABLE ip_access_logs ADD COLUMN username TEXT", []);
 
+    // [NEW] 抓包/存档静态加密密钥表 (轮换后旧密钥保留用于读取)
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS capture_keys (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            key_material TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            active INTEGER NOT NULL DEFAULT 0
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+
     Ok(())
 }
 
+// ============================================================================
+// 抓包加密密钥操作
+// ============================================================================
+
+/// 获取当前启用的加密密钥 (id, 密钥材料)
+pub fn get_active_capture_key() -> Result<Option<(u32, String)>, String> {
+    let conn = connect_db()?;
+    conn.query_row(
+        "SELECT id, key_material FROM capture_keys WHERE active = 1 ORDER BY id DESC LIMIT 1",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+/// 按 id 获取加密密钥材料 (包含已轮换的旧密钥)
+pub fn get_capture_key(id: u32) -> Result<Option<String>, String> {
+    let conn = connect_db()?;
+    conn.query_row(
+        "SELECT key_material FROM capture_keys WHERE id = ?1",
+        [id],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+/// 写入新密钥并设为启用 (其余密钥转为只读)，返回新密钥 id
+pub fn insert_capture_key(key_material: &str) -> Result<u32, String> {
+    let mut conn = connect_db()?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    tx.execute("UPDATE capture_keys SET active = 0 WHERE active = 1", [])
+        .map_err(|e| e.to_string())?;
+    tx.execute(
+        "INSERT INTO capture_keys (key_material, created_at, active) VALUES (?1, ?2, 1)",
+        params![key_material, chrono::Utc::now().timestamp()],
+    )
+    .map_err(|e| e.to_string())?;
+    let id = tx.last_insert_rowid() as u32;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(id)
+}
+
 // ============================================================================
 // IP 访问日志操作
 // ============================================================================
 
 /// 保存 IP 访问日志
 pub fn save_ip_access_log(log: &IpAccessLog) -> Result<(), String> {
+    let _gate = crate::modules::db_maintenance::write_guard();
     let conn = connect_db()?;
 
     conn.execute(
@@ -0,0 +1,406 @@
+// 批量导入 refresh_token
+// 支持 `email,refresh_token` CSV (每行也可只写 refresh_token) 与导出的 AccountExportResponse JSON。
+// 每一行先通过 OAuth 刷新校验 token，成功后 upsert 账号；结果汇总为结构化报告并写入日志目录。
+
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::models::{AccountExportResponse, TokenData};
+use crate::modules;
+
+/// 同时进行校验的最大行数
+const IMPORT_CONCURRENCY: usize = 4;
+/// 单行校验超时 (刷新 token + 获取用户信息)
+const ROW_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Csv,
+    Json,
+}
+
+impl ImportFormat {
+    /// 解析格式参数，未指定时按扩展名推断 (默认 CSV)
+    pub fn resolve(format: Option<&str>, path: &Path) -> Result<Self, String> {
+        let format = format
+            .map(|f| f.trim().to_lowercase())
+            .filter(|f| !f.is_empty() && f != "auto");
+        match format.as_deref() {
+            Some("csv") | Some("txt") | Some("text") => Ok(Self::Csv),
+            Some("json") => Ok(Self::Json),
+            Some(other) => Err(format!("unsupported_import_format: {}", other)),
+            None => {
+                let is_json = path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+                Ok(if is_json { Self::Json } else { Self::Csv })
+            }
+        }
+    }
+}
+
+/// 待导入的一行
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportRow {
+    /// 源文件行号 (JSON 为数组下标 + 1)
+    pub line: usize,
+    pub email: Option<String>,
+    pub refresh_token: String,
+}
+
+/// 导入失败 / 跳过的行
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ImportFailure {
+    pub line: usize,
+    pub email: Option<String>,
+    pub reason: String,
+}
+
+/// 导入报告
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// 新增的账号邮箱
+    pub imported: Vec<String>,
+    /// 已存在并被更新的账号邮箱
+    pub updated: Vec<String>,
+    /// 校验或保存失败的行
+    pub failed: Vec<ImportFailure>,
+    /// 格式错误被跳过的行
+    pub skipped: Vec<ImportFailure>,
+    /// 报告日志文件路径
+    pub log_path: Option<String>,
+}
+
+/// 校验通过的账号信息
+#[derive(Debug, Clone)]
+pub struct ValidatedAccount {
+    pub email: String,
+    pub name: Option<String>,
+    pub token: TokenData,
+}
+
+/// 解析 CSV / 纯文本
+/// - `email,refresh_token` 或仅 `refresh_token`
+/// - 空行、`#` 注释行与表头行忽略
+pub fn parse_csv(content: &str) -> (Vec<ImportRow>, Vec<ImportFailure>) {
+    let mut rows = Vec::new();
+    let mut skipped = Vec::new();
+
+    for (idx, raw) in content.lines().enumerate() {
+        let line = idx + 1;
+        let trimmed = raw.trim().trim_start_matches('\u{feff}');
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = trimmed
+            .split(',')
+            .map(|f| f.trim().trim_matches('"').trim())
+            .collect();
+
+        if line == 1 && fields.iter().any(|f| f.eq_ignore_ascii_case("refresh_token")) {
+            continue;
+        }
+
+        let malformed = |reason: &str| ImportFailure {
+            line,
+            email: None,
+            reason: reason.to_string(),
+        };
+
+        match fields.as_slice() {
+            [token] if !token.is_empty() => rows.push(ImportRow {
+                line,
+                email: None,
+                refresh_token: token.to_string(),
+            }),
+            [email, token] => {
+                if !email.contains('@') {
+                    skipped.push(malformed("invalid email"));
+                } else if token.is_empty() {
+                    skipped.push(malformed("missing refresh_token"));
+                } else {
+                    rows.push(ImportRow {
+                        line,
+                        email: Some(email.to_string()),
+                        refresh_token: token.to_string(),
+                    });
+                }
+            }
+            _ => skipped.push(malformed("expected `email,refresh_token`")),
+        }
+    }
+
+    (rows, skipped)
+}
+
+/// 解析导出的 AccountExportResponse JSON
+pub fn parse_json(content: &str) -> Result<(Vec<ImportRow>, Vec<ImportFailure>), String> {
+    let export: AccountExportResponse =
+        serde_json::from_str(content).map_err(|e| format!("invalid_export_json: {}", e))?;
+
+    let mut rows = Vec::new();
+    let mut skipped = Vec::new();
+    for (idx, item) in export.accounts.into_iter().enumerate() {
+        let line = idx + 1;
+        if item.refresh_token.trim().is_empty() {
+            skipped.push(ImportFailure {
+                line,
+                email: Some(item.email),
+                reason: "missing refresh_token".to_string(),
+            });
+            continue;
+        }
+        rows.push(ImportRow {
+            line,
+            email: Some(item.email).filter(|e| !e.trim().is_empty()),
+            refresh_token: item.refresh_token.trim().to_string(),
+        });
+    }
+    Ok((rows, skipped))
+}
+
+/// 执行导入
+/// `validate` 负责校验 refresh_token 并返回账号信息，`persist` 负责保存账号 (便于测试替换)
+pub async fn run_import<V, Fut, P>(
+    rows: Vec<ImportRow>,
+    skipped: Vec<ImportFailure>,
+    existing_emails: HashSet<String>,
+    validate: V,
+    mut persist: P,
+) -> ImportReport
+where
+    V: Fn(ImportRow) -> Fut,
+    Fut: Future<Output = Result<ValidatedAccount, String>>,
+    P: FnMut(ValidatedAccount) -> Result<(), String>,
+{
+    let mut report = ImportReport {
+        skipped,
+        ..Default::default()
+    };
+    let mut known: HashSet<String> = existing_emails.into_iter().map(|e| e.to_lowercase()).collect();
+
+    let mut results: Vec<(ImportRow, Result<ValidatedAccount, String>)> =
+        futures::stream::iter(rows)
+            .map(|row| {
+                let fut = validate(row.clone());
+                async move {
+                    let result = match tokio::time::timeout(ROW_TIMEOUT, fut).await {
+                        Ok(r) => r,
+                        Err(_) => Err(format!("validation timed out after {}s", ROW_TIMEOUT.as_secs())),
+                    };
+                    (row, result)
+                }
+            })
+            .buffer_unordered(IMPORT_CONCURRENCY)
+            .collect()
+            .await;
+    // 按行号顺序保存，保证同一邮箱的重复行以最后一行为准
+    results.sort_by_key(|(row, _)| row.line);
+
+    for (row, result) in results {
+        let validated = match result {
+            Ok(v) => v,
+            Err(reason) => {
+                report.failed.push(ImportFailure {
+                    line: row.line,
+                    email: row.email,
+                    reason,
+                });
+                continue;
+            }
+        };
+
+        if let Some(expected) = &row.email {
+            if !expected.eq_ignore_ascii_case(&validated.email) {
+                report.failed.push(ImportFailure {
+                    line: row.line,
+                    email: row.email.clone(),
+                    reason: format!("refresh_token belongs to {}", validated.email),
+                });
+                continue;
+            }
+        }
+
+        let email = validated.email.clone();
+        match persist(validated) {
+            Ok(()) => {
+                if known.insert(email.to_lowercase()) {
+                    report.imported.push(email);
+                } else {
+                    report.updated.push(email);
+                }
+            }
+            Err(reason) => report.failed.push(ImportFailure {
+                line: row.line,
+                email: Some(email),
+                reason,
+            }),
+        }
+    }
+
+    report
+}
+
+/// 通过 OAuth 刷新校验 refresh_token 并获取账号信息
+async fn validate_refresh_token(row: ImportRow) -> Result<ValidatedAccount, String> {
+    let temp_account_id = uuid::Uuid::new_v4().to_string();
+    let token_res = modules::oauth::refresh_access_token(&row.refresh_token, Some(&temp_account_id)).await?;
+    let user_info = modules::oauth::get_user_info(&token_res.access_token, Some(&temp_account_id)).await?;
+    let project_id = crate::proxy::project_resolver::fetch_project_id(&token_res.access_token)
+        .await
+        .ok();
+
+    Ok(ValidatedAccount {
+        email: user_info.email.clone(),
+        name: user_info.get_display_name(),
+        token: TokenData::new(
+            token_res.access_token,
+            row.refresh_token,
+            token_res.expires_in,
+            Some(user_info.email),
+            project_id,
+            None,
+        ),
+    })
+}
+
+/// 将报告写入日志目录
+fn write_report_log(report: &ImportReport) -> Result<String, String> {
+    let log_dir = modules::logger::get_log_dir()?;
+    let path = log_dir.join(format!(
+        "import_refresh_tokens_{}.json",
+        chrono::Local::now().format("%Y%m%d_%H%M%S")
+    ));
+    let content = serde_json::to_string_pretty(report).map_err(|e| e.to_string())?;
+    std::fs::write(&path, content).map_err(|e| format!("failed_to_write_import_report: {}", e))?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// 从文件批量导入 refresh_token
+pub async fn import_refresh_tokens(path: &str, format: Option<&str>) -> Result<ImportReport, String> {
+    let file_path = Path::new(path);
+    let format = ImportFormat::resolve(format, file_path)?;
+    let content = std::fs::read_to_string(file_path)
+        .map_err(|e| format!("failed_to_read_import_file: {}", e))?;
+
+    let (rows, skipped) = match format {
+        ImportFormat::Csv => parse_csv(&content),
+        ImportFormat::Json => parse_json(&content)?,
+    };
+    modules::logger::log_info(&format!(
+        "[Import] {} rows to import from {} ({} malformed)",
+        rows.len(),
+        path,
+        skipped.len()
+    ));
+
+    let existing_emails = modules::account::list_accounts()?
+        .into_iter()
+        .map(|a| a.email)
+        .collect();
+
+    let mut report = run_import(rows, skipped, existing_emails, validate_refresh_token, |validated| {
+        modules::account::upsert_account(validated.email, validated.name, validated.token).map(|_| ())
+    })
+    .await;
+
+    match write_report_log(&report) {
+        Ok(log_path) => report.log_path = Some(log_path),
+        Err(e) => modules::logger::log_warn(&format!("[Import] Failed to write report: {}", e)),
+    }
+    modules::logger::log_info(&format!(
+        "[Import] Done: {} imported, {} updated, {} failed, {} skipped",
+        report.imported.len(),
+        report.updated.len(),
+        report.failed.len(),
+        report.skipped.len()
+    ));
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = "email,refresh_token\n\
+        alice@example.com,1//good-alice\n\
+        \n\
+        alice@example.com,1//good-alice-rotated\n\
+        not-an-email,1//whatever\n\
+        bob@example.com,1//revoked\n\
+        1//token-only-carol\n\
+        a,b,c\n";
+
+    // 模拟 OAuth 刷新: "revoked" 失败，其余按 token 推导邮箱
+    async fn mock_validate(row: ImportRow) -> Result<ValidatedAccount, String> {
+        if row.refresh_token.contains("revoked") {
+            return Err("Refresh failed: invalid_grant".to_string());
+        }
+        let email = row
+            .email
+            .clone()
+            .unwrap_or_else(|| "carol@example.com".to_string());
+        Ok(ValidatedAccount {
+            email: email.clone(),
+            name: None,
+            token: TokenData::new("access".to_string(), row.refresh_token, 3600, Some(email), None, None),
+        })
+    }
+
+    #[test]
+    fn test_parse_csv_reports_malformed_lines() {
+        let (rows, skipped) = parse_csv(FIXTURE);
+        assert_eq!(rows.iter().map(|r| r.line).collect::<Vec<_>>(), vec![2, 4, 6, 7]);
+        assert_eq!(rows[3].email, None);
+        assert_eq!(skipped.iter().map(|s| s.line).collect::<Vec<_>>(), vec![5, 8]);
+    }
+
+    #[tokio::test]
+    async fn test_import_fixture_with_duplicate_and_malformed_rows() {
+        let (rows, skipped) = parse_csv(FIXTURE);
+        let existing: HashSet<String> = ["Carol@example.com".to_string()].into_iter().collect();
+
+        let mut saved = Vec::new();
+        let report = run_import(rows, skipped, existing, mock_validate, |v| {
+            saved.push((v.email, v.token.refresh_token));
+            Ok(())
+        })
+        .await;
+
+        assert_eq!(report.imported, vec!["alice@example.com"]);
+        // 文件内重复行与已有账号均视为更新
+        assert_eq!(report.updated, vec!["alice@example.com", "carol@example.com"]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].line, 6);
+        assert!(report.failed[0].reason.contains("invalid_grant"));
+        assert_eq!(report.skipped.len(), 2);
+
+        // 重复行按行号顺序保存，最终保留最后一行的 token
+        let alice_tokens: Vec<&str> = saved
+            .iter()
+            .filter(|(email, _)| email == "alice@example.com")
+            .map(|(_, token)| token.as_str())
+            .collect();
+        assert_eq!(alice_tokens, vec!["1//good-alice", "1//good-alice-rotated"]);
+    }
+
+    #[test]
+    fn test_parse_export_json() {
+        let json = r#"{"accounts":[{"email":"a@example.com","refresh_token":"1//a"},{"email":"b@example.com","refresh_token":" "}]}"#;
+        let (rows, skipped) = parse_json(json).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].email.as_deref(), Some("a@example.com"));
+        assert_eq!(skipped[0].line, 2);
+
+        assert_eq!(ImportFormat::resolve(None, Path::new("export.JSON")).unwrap(), ImportFormat::Json);
+        assert_eq!(ImportFormat::resolve(None, Path::new("tokens.txt")).unwrap(), ImportFormat::Csv);
+        assert!(ImportFormat::resolve(Some("xml"), Path::new("a")).is_err());
+    }
+}
@@ -1,7 +1,22 @@
 use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 
+/// 单次响应的输出 token 按内容类型拆分 (思考 / 可见文本 / 工具参数)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputTokenBreakdown {
+    pub thinking_tokens: u32,
+    pub text_tokens: u32,
+    pub tool_input_tokens: u32,
+}
+
+impl OutputTokenBreakdown {
+    pub fn total(&self) -> u32 {
+        self.thinking_tokens + self.text_tokens + self.tool_input_tokens
+    }
+}
+
 /// Aggregated token statistics
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TokenStatsAggregated {
@@ -30,6 +45,14 @@ pub struct TokenStatsSummary {
     pub total_tokens: u64,
     pub total_requests: u64,
     pub unique_accounts: u64,
+    /// [NEW] 剔除客户端重试 (retry_of 非空) 后的统计
+    pub deduped_input_tokens: u64,
+    pub deduped_output_tokens: u64,
+    pub deduped_total_tokens: u64,
+    pub deduped_requests: u64,
+    /// [NEW] 各账号/模型的配额消耗预测 (概览中一并返回)
+    #[serde(default)]
+    pub quota_forecasts: Vec<crate::modules::quota_forecast::QuotaForecast>,
 }
 
 /// Per-model token statistics
@@ -42,6 +65,18 @@ pub struct ModelTokenStats {
     pub request_count: u64,
 }
 
+/// Per-(model, account, client key) usage breakdown, used for exports
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UsageBreakdownRow {
+    pub model: String,
+    pub account_email: String,
+    pub client_key: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cached_tokens: u64,
+    pub request_count: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelTrendPoint {
     pub period: String,
@@ -55,6 +90,19 @@ pub struct AccountTrendPoint {
     pub account_data: std::collections::HashMap<String, u64>,
 }
 
+/// 上游实际服务的模型版本统计 (按客户端模型 + modelVersion 分组)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ModelVersionUsage {
+    pub model: String,
+    pub model_version: String,
+    pub first_seen: i64,
+    pub last_seen: i64,
+    pub request_count: u64,
+}
+
+/// 相同请求指纹在该时间窗口 (秒) 内再次出现时，视为客户端重试
+pub const RETRY_DEDUP_WINDOW_SECS: i64 = 120;
+
 pub(crate) fn get_db_path() -> Result<PathBuf, String> {
     let data_dir = crate::modules::account::get_data_dir()?;
     Ok(data_dir.join("token_stats.db"))
@@ -78,7 +126,10 @@ fn connect_db() -> Result<Connection, String> {
 /// Initialize the token stats database
 pub fn init_db() -> Result<(), String> {
     let conn = connect_db()?;
+    init_schema(&conn)
+}
 
+fn init_schema(conn: &Connection) -> Result<(), String> {
     // Create main usage table
     conn.execute(
         "CREATE TABLE IF NOT EXISTS token_usage (
@@ -122,6 +173,28 @@ pub fn init_db() -> Result<(), String> {
     )
     .map_err(|e| e.to_string())?;
 
+    // Migration: per-request cached tokens and client key (used by usage exports)
+    let _ = conn.execute("ALTER TABLE token_usage ADD COLUMN cached_tokens INTEGER NOT NULL DEFAULT 0", []);
+    let _ = conn.execute("ALTER TABLE token_usage ADD COLUMN client_key TEXT", []);
+
+    // Migration: request replay hash and the original row a retry links to
+    let _ = conn.execute("ALTER TABLE token_usage ADD COLUMN request_hash TEXT", []);
+    let _ = conn.execute("ALTER TABLE token_usage ADD COLUMN retry_of INTEGER", []);
+
+    // Migration: per-block-type output token breakdown (NULL when unknown)
+    let _ = conn.execute("ALTER TABLE token_usage ADD COLUMN thinking_tokens INTEGER", []);
+    let _ = conn.execute("ALTER TABLE token_usage ADD COLUMN text_tokens INTEGER", []);
+    let _ = conn.execute("ALTER TABLE token_usage ADD COLUMN tool_input_tokens INTEGER", []);
+
+    // Migration: concrete upstream model version that served the request
+    let _ = conn.execute("ALTER TABLE token_usage ADD COLUMN model_version TEXT", []);
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_token_request_hash ON token_usage (request_hash, timestamp DESC)",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+
     Ok(())
 }
 
@@ -131,19 +204,124 @@ pub fn record_usage(
     model: &str,
     input_tokens: u32,
     output_tokens: u32,
+    cached_tokens: u32,
+    client_key: Option<&str>,
+    request_hash: Option<&str>,
+    breakdown: Option<&OutputTokenBreakdown>,
+    model_version: Option<&str>,
 ) -> Result<(), String> {
+    let _gate = crate::modules::db_maintenance::write_guard();
     let conn = connect_db()?;
-    let timestamp = chrono::Utc::now().timestamp();
+    let row_id = insert_usage(
+        &conn,
+        chrono::Utc::now().timestamp(),
+        account_email,
+        model,
+        input_tokens,
+        output_tokens,
+        cached_tokens,
+        client_key,
+        request_hash,
+    )?;
+    if let Some(breakdown) = breakdown {
+        set_output_breakdown(&conn, row_id, breakdown)?;
+    }
+    if let Some(version) = model_version {
+        set_model_version(&conn, row_id, version)?;
+    }
+    Ok(())
+}
+
+/// 写入单条记录的上游模型版本
+fn set_model_version(conn: &Connection, row_id: i64, model_version: &str) -> Result<(), String> {
+    conn.execute(
+        "UPDATE token_usage SET model_version = ?1 WHERE id = ?2",
+        params![model_version, row_id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// 写入单条记录的输出 token 拆分
+fn set_output_breakdown(
+    conn: &Connection,
+    row_id: i64,
+    breakdown: &OutputTokenBreakdown,
+) -> Result<(), String> {
+    conn.execute(
+        "UPDATE token_usage SET thinking_tokens = ?1, text_tokens = ?2, tool_input_tokens = ?3 WHERE id = ?4",
+        params![
+            breakdown.thinking_tokens,
+            breakdown.text_tokens,
+            breakdown.tool_input_tokens,
+            row_id
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// 查找窗口内具有相同请求指纹的原始记录 (同一客户端 Key)
+fn find_retry_origin(
+    conn: &Connection,
+    timestamp: i64,
+    client_key: Option<&str>,
+    request_hash: &str,
+) -> Result<Option<i64>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id FROM token_usage
+             WHERE request_hash = ?1 AND client_key IS ?2 AND retry_of IS NULL
+               AND timestamp BETWEEN ?3 AND ?4
+             ORDER BY timestamp DESC, id DESC
+             LIMIT 1",
+        )
+        .map_err(|e| e.to_string())?;
+    let mut rows = stmt
+        .query(params![request_hash, client_key, timestamp - RETRY_DEDUP_WINDOW_SECS, timestamp])
+        .map_err(|e| e.to_string())?;
+    match rows.next().map_err(|e| e.to_string())? {
+        Some(row) => Ok(Some(row.get(0).map_err(|e| e.to_string())?)),
+        None => Ok(None),
+    }
+}
+
+fn insert_usage(
+    conn: &Connection,
+    timestamp: i64,
+    account_email: &str,
+    model: &str,
+    input_tokens: u32,
+    output_tokens: u32,
+    cached_tokens: u32,
+    client_key: Option<&str>,
+    request_hash: Option<&str>,
+) -> Result<i64, String> {
     let total_tokens = input_tokens + output_tokens;
 
+    // 客户端以相同请求体重试时，关联到原始记录 (原始计数保留，去重统计时剔除)
+    let retry_of = match request_hash {
+        Some(hash) => find_retry_origin(conn, timestamp, client_key, hash)?,
+        None => None,
+    };
+
     // Insert into raw usage table
     conn.execute(
-        "INSERT INTO token_usage (timestamp, account_email, model, input_tokens, output_tokens, total_tokens)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        params![timestamp, account_email, model, input_tokens, output_tokens, total_tokens],
+        "INSERT INTO token_usage (timestamp, account_email, model, input_tokens, output_tokens, total_tokens, cached_tokens, client_key, request_hash, retry_of)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![timestamp, account_email, model, input_tokens, output_tokens, total_tokens, cached_tokens, client_key, request_hash, retry_of],
     ).map_err(|e| e.to_string())?;
+    let row_id = conn.last_insert_rowid();
+
+    // 小时聚合表只累计去重后的请求，重试记录仅保留在原始表中
+    if retry_of.is_some() {
+        return Ok(row_id);
+    }
 
-    let hour_bucket = chrono::Utc::now().format("%Y-%m-%d %H:00").to_string();
+    let hour_bucket = chrono::DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_else(chrono::Utc::now)
+        .format("%Y-%m-%d %H:00")
+        .to_string();
     conn.execute(
         "INSERT INTO token_stats_hourly (hour_bucket, account_email, total_input_tokens, total_output_tokens, total_tokens, request_count)
          VALUES (?1, ?2, ?3, ?4, ?5, 1)
@@ -155,7 +333,7 @@ pub fn record_usage(
         params![hour_bucket, account_email, input_tokens, output_tokens, total_tokens],
     ).map_err(|e| e.to_string())?;
 
-    Ok(())
+    Ok(row_id)
 }
 
 /// Get hourly aggregated stats for a time range
@@ -250,7 +428,7 @@ pub fn get_weekly_stats(weeks: i64) -> Result<Vec<TokenStatsAggregated>, String>
                 SUM(total_tokens) as total,
                 COUNT(*) as count
          FROM token_usage 
-         WHERE timestamp >= ?1
+         WHERE timestamp >= ?1 AND retry_of IS NULL
          GROUP BY week_bucket
          ORDER BY week_bucket ASC",
         )
@@ -318,26 +496,46 @@ pub fn get_account_stats(hours: i64) -> Result<Vec<AccountTokenStats>, String> {
 pub fn get_summary_stats(hours: i64) -> Result<TokenStatsSummary, String> {
     let conn = connect_db()?;
     let cutoff = chrono::Utc::now() - chrono::Duration::hours(hours);
-    let cutoff_bucket = cutoff.format("%Y-%m-%d %H:00").to_string();
+    let mut summary = query_summary_stats(&conn, cutoff)?;
+    // 预测失败不影响概览本身
+    match crate::modules::quota_forecast::get_quota_forecasts(None) {
+        Ok(forecasts) => summary.quota_forecasts = forecasts,
+        Err(e) => tracing::warn!("[TokenStats] Failed to compute quota forecasts: {}", e),
+    }
+    Ok(summary)
+}
 
-    let (total_input, total_output, total, requests): (u64, u64, u64, u64) = conn
+fn query_summary_stats(
+    conn: &Connection,
+    cutoff: chrono::DateTime<chrono::Utc>,
+) -> Result<TokenStatsSummary, String> {
+    // 小时聚合表已剔除重试，原始统计直接从明细表计算 (按小时对齐，与其它概览口径一致)
+    let bucket_start = cutoff.timestamp() - cutoff.timestamp().rem_euclid(3600);
+    let (total_input, total_output, total, requests, unique_accounts): (u64, u64, u64, u64, u64) = conn
         .query_row(
-            "SELECT COALESCE(SUM(total_input_tokens), 0),
-                COALESCE(SUM(total_output_tokens), 0),
+            "SELECT COALESCE(SUM(input_tokens), 0),
+                COALESCE(SUM(output_tokens), 0),
                 COALESCE(SUM(total_tokens), 0),
-                COALESCE(SUM(request_count), 0)
-         FROM token_stats_hourly 
-         WHERE hour_bucket >= ?1",
-            [&cutoff_bucket],
-            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+                COUNT(*),
+                COUNT(DISTINCT account_email)
+         FROM token_usage
+         WHERE timestamp >= ?1",
+            [bucket_start],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)),
         )
         .map_err(|e| e.to_string())?;
 
-    let unique_accounts: u64 = conn
+    // 去重统计: 剔除被标记为重试的记录
+    let (deduped_input, deduped_output, deduped_total, deduped_requests): (u64, u64, u64, u64) = conn
         .query_row(
-            "SELECT COUNT(DISTINCT account_email) FROM token_stats_hourly WHERE hour_bucket >= ?1",
-            [&cutoff_bucket],
-            |row| row.get(0),
+            "SELECT COALESCE(SUM(input_tokens), 0),
+                COALESCE(SUM(output_tokens), 0),
+                COALESCE(SUM(total_tokens), 0),
+                COUNT(*)
+         FROM token_usage
+         WHERE timestamp >= ?1 AND retry_of IS NULL",
+            [bucket_start],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
         )
         .map_err(|e| e.to_string())?;
 
@@ -347,6 +545,11 @@ pub fn get_summary_stats(hours: i64) -> Result<TokenStatsSummary, String> {
         total_tokens: total,
         total_requests: requests,
         unique_accounts,
+        deduped_input_tokens: deduped_input,
+        deduped_output_tokens: deduped_output,
+        deduped_total_tokens: deduped_total,
+        deduped_requests,
+        quota_forecasts: Vec::new(),
     })
 }
 
@@ -362,7 +565,7 @@ pub fn get_model_stats(hours: i64) -> Result<Vec<ModelTokenStats>, String> {
                 SUM(total_tokens) as total,
                 COUNT(*) as count
          FROM token_usage
-         WHERE timestamp >= ?1
+         WHERE timestamp >= ?1 AND retry_of IS NULL
          GROUP BY model
          ORDER BY total DESC",
         )
@@ -397,7 +600,7 @@ pub fn get_model_trend_hourly(hours: i64) -> Result<Vec<ModelTrendPoint>, String
                 model,
                 SUM(total_tokens) as total
          FROM token_usage
-         WHERE timestamp >= ?1
+         WHERE timestamp >= ?1 AND retry_of IS NULL
          GROUP BY hour_bucket, model
          ORDER BY hour_bucket ASC",
         )
@@ -437,7 +640,7 @@ pub fn get_model_trend_daily(days: i64) -> Result<Vec<ModelTrendPoint>, String>
                 model,
                 SUM(total_tokens) as total
          FROM token_usage
-         WHERE timestamp >= ?1
+         WHERE timestamp >= ?1 AND retry_of IS NULL
          GROUP BY day_bucket, model
          ORDER BY day_bucket ASC",
         )
@@ -477,7 +680,7 @@ pub fn get_account_trend_hourly(hours: i64) -> Result<Vec<AccountTrendPoint>, St
                 account_email,
                 SUM(total_tokens) as total
          FROM token_usage
-         WHERE timestamp >= ?1
+         WHERE timestamp >= ?1 AND retry_of IS NULL
          GROUP BY hour_bucket, account_email
          ORDER BY hour_bucket ASC",
         )
@@ -520,7 +723,7 @@ pub fn get_account_trend_daily(days: i64) -> Result<Vec<AccountTrendPoint>, Stri
                 account_email,
                 SUM(total_tokens) as total
          FROM token_usage
-         WHERE timestamp >= ?1
+         WHERE timestamp >= ?1 AND retry_of IS NULL
          GROUP BY day_bucket, account_email
          ORDER BY day_bucket ASC",
         )
@@ -553,6 +756,165 @@ pub fn get_account_trend_daily(days: i64) -> Result<Vec<AccountTrendPoint>, Stri
         .collect())
 }
 
+/// Start of the calendar month (UTC) containing `now`, used as the budget window start
+pub fn month_start_ts(now: chrono::DateTime<chrono::Utc>) -> i64 {
+    use chrono::{Datelike, TimeZone};
+    chrono::Utc
+        .with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+        .single()
+        .map(|dt| dt.timestamp())
+        .unwrap_or_else(|| now.timestamp())
+}
+
+/// Get total tokens per account since `since_ts` (used for monthly budget enforcement)
+/// 预算按实际上游消耗计算，包含客户端重试
+pub fn get_account_totals_since(since_ts: i64) -> Result<HashMap<String, u64>, String> {
+    let conn = connect_db()?;
+    query_account_totals_since(&conn, since_ts)
+}
+
+fn query_account_totals_since(conn: &Connection, since_ts: i64) -> Result<HashMap<String, u64>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT account_email, SUM(total_tokens) as total
+         FROM token_usage
+         WHERE timestamp >= ?1
+         GROUP BY account_email",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map([since_ts], |row| Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?)))
+        .map_err(|e| e.to_string())?;
+
+    let mut result = HashMap::new();
+    for row in rows {
+        let (email, total) = row.map_err(|e| e.to_string())?;
+        result.insert(email, total);
+    }
+    Ok(result)
+}
+
+/// Get total tokens per (account, model) since `since_ts` (used for quota burn-rate forecasts)
+pub fn get_account_model_totals_since(since_ts: i64) -> Result<Vec<(String, String, u64)>, String> {
+    let conn = connect_db()?;
+    query_account_model_totals_since(&conn, since_ts)
+}
+
+fn query_account_model_totals_since(
+    conn: &Connection,
+    since_ts: i64,
+) -> Result<Vec<(String, String, u64)>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT account_email, model, SUM(total_tokens) as total
+         FROM token_usage
+         WHERE timestamp >= ?1
+         GROUP BY account_email, model",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map([since_ts], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, u64>(2)?))
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut result = Vec::new();
+    for row in rows {
+        result.push(row.map_err(|e| e.to_string())?);
+    }
+    Ok(result)
+}
+
+/// Get usage grouped by model, account and client key for [start_ts, end_ts)
+pub fn get_usage_breakdown(start_ts: i64, end_ts: i64) -> Result<Vec<UsageBreakdownRow>, String> {
+    let conn = connect_db()?;
+    query_usage_breakdown(&conn, start_ts, end_ts)
+}
+
+fn query_usage_breakdown(
+    conn: &Connection,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<Vec<UsageBreakdownRow>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT model,
+                account_email,
+                COALESCE(client_key, '') as key,
+                SUM(input_tokens) as input,
+                SUM(output_tokens) as output,
+                SUM(cached_tokens) as cached,
+                COUNT(*) as count
+         FROM token_usage
+         WHERE timestamp >= ?1 AND timestamp < ?2
+         GROUP BY model, account_email, key
+         ORDER BY model ASC, account_email ASC, key ASC",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map(params![start_ts, end_ts], |row| {
+            Ok(UsageBreakdownRow {
+                model: row.get(0)?,
+                account_email: row.get(1)?,
+                client_key: row.get(2)?,
+                input_tokens: row.get(3)?,
+                output_tokens: row.get(4)?,
+                cached_tokens: row.get(5)?,
+                request_count: row.get(6)?,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut result = Vec::new();
+    for row in rows {
+        result.push(row.map_err(|e| e.to_string())?);
+    }
+    Ok(result)
+}
+
+/// Get the upstream model versions seen in the last N days
+pub fn get_model_versions(days: i64) -> Result<Vec<ModelVersionUsage>, String> {
+    let conn = connect_db()?;
+    query_model_versions_since(&conn, chrono::Utc::now().timestamp() - days * 86_400)
+}
+
+fn query_model_versions_since(conn: &Connection, since_ts: i64) -> Result<Vec<ModelVersionUsage>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT model,
+                model_version,
+                MIN(timestamp) as first_seen,
+                MAX(timestamp) as last_seen,
+                COUNT(*) as count
+         FROM token_usage
+         WHERE timestamp >= ?1 AND model_version IS NOT NULL
+         GROUP BY model, model_version
+         ORDER BY model ASC, first_seen ASC",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map([since_ts], |row| {
+            Ok(ModelVersionUsage {
+                model: row.get(0)?,
+                model_version: row.get(1)?,
+                first_seen: row.get(2)?,
+                last_seen: row.get(3)?,
+                request_count: row.get(4)?,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut result = Vec::new();
+    for row in rows {
+        result.push(row.map_err(|e| e.to_string())?);
+    }
+    Ok(result)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -563,4 +925,201 @@ mod tests {
         // For now, just verify the module compiles
         assert!(true);
     }
+
+    #[test]
+    fn test_usage_breakdown_aggregation() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+
+        let day_start = 1_767_225_600; // 2026-01-01 00:00:00 UTC
+        let next_day = day_start + 86_400;
+
+        insert_usage(&conn, day_start + 10, "a@test.com", "gemini-3-flash", 100, 20, 5, Some("team-a"), None).unwrap();
+        insert_usage(&conn, day_start + 20, "a@test.com", "gemini-3-flash", 50, 30, 0, Some("team-a"), None).unwrap();
+        insert_usage(&conn, day_start + 30, "a@test.com", "gemini-3-flash", 7, 3, 1, None, None).unwrap();
+        insert_usage(&conn, day_start + 40, "b@test.com", "claude-sonnet-4-5", 1000, 200, 800, Some("team-b"), None).unwrap();
+        // 区间外的记录不计入 (左闭右开)
+        insert_usage(&conn, day_start - 1, "a@test.com", "gemini-3-flash", 9999, 9999, 0, Some("team-a"), None).unwrap();
+        insert_usage(&conn, next_day, "b@test.com", "claude-sonnet-4-5", 9999, 9999, 0, Some("team-b"), None).unwrap();
+
+        let rows = query_usage_breakdown(&conn, day_start, next_day).unwrap();
+        assert_eq!(rows.len(), 3);
+
+        assert_eq!(
+            rows[0],
+            UsageBreakdownRow {
+                model: "claude-sonnet-4-5".to_string(),
+                account_email: "b@test.com".to_string(),
+                client_key: "team-b".to_string(),
+                input_tokens: 1000,
+                output_tokens: 200,
+                cached_tokens: 800,
+                request_count: 1,
+            }
+        );
+        // 未带 client key 的请求单独归为一组
+        assert_eq!(rows[1].client_key, "");
+        assert_eq!((rows[1].input_tokens, rows[1].output_tokens, rows[1].request_count), (7, 3, 1));
+        assert_eq!(rows[2].client_key, "team-a");
+        assert_eq!(
+            (rows[2].input_tokens, rows[2].output_tokens, rows[2].cached_tokens, rows[2].request_count),
+            (150, 50, 5, 2)
+        );
+    }
+
+    #[test]
+    fn test_monthly_totals_reset_at_month_boundary() {
+        use chrono::TimeZone;
+
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+
+        let jan_end = chrono::Utc.with_ymd_and_hms(2026, 1, 31, 23, 59, 59).unwrap();
+        let feb_start = chrono::Utc.with_ymd_and_hms(2026, 2, 1, 0, 0, 0).unwrap();
+        assert_eq!(month_start_ts(jan_end), chrono::Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap().timestamp());
+        assert_eq!(month_start_ts(feb_start), feb_start.timestamp());
+
+        insert_usage(&conn, jan_end.timestamp() - 60, "a@test.com", "gemini-3-flash", 800, 200, 0, None, None).unwrap();
+        insert_usage(&conn, jan_end.timestamp(), "a@test.com", "claude-sonnet-4-5", 50, 50, 0, None, None).unwrap();
+
+        // 一月窗口内累计 1100 tokens
+        let jan_totals = query_account_totals_since(&conn, month_start_ts(jan_end)).unwrap();
+        assert_eq!(jan_totals.get("a@test.com"), Some(&1100));
+
+        // 跨月后窗口重置，历史用量不再计入
+        let feb_totals = query_account_totals_since(&conn, month_start_ts(feb_start)).unwrap();
+        assert!(feb_totals.get("a@test.com").is_none());
+    }
+
+    #[test]
+    fn test_identical_retries_are_deduped() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+
+        let now = chrono::Utc::now().timestamp();
+        // 同一请求体重试两次 (重试可能落到其它账号)
+        insert_usage(&conn, now - 30, "a@test.com", "claude-sonnet-4-5", 1000, 0, 0, Some("team-a"), Some("hash-1")).unwrap();
+        insert_usage(&conn, now - 10, "b@test.com", "claude-sonnet-4-5", 1000, 200, 0, Some("team-a"), Some("hash-1")).unwrap();
+        // 不同请求不受影响
+        insert_usage(&conn, now - 5, "a@test.com", "claude-sonnet-4-5", 300, 50, 0, Some("team-a"), Some("hash-2")).unwrap();
+        insert_usage(&conn, now - 5, "a@test.com", "gemini-3-flash", 10, 5, 0, None, None).unwrap();
+
+        let retry_of: Option<i64> = conn
+            .query_row("SELECT retry_of FROM token_usage WHERE account_email = 'b@test.com'", [], |row| row.get(0))
+            .unwrap();
+        let origin: i64 = conn
+            .query_row(
+                "SELECT id FROM token_usage WHERE request_hash = 'hash-1' AND account_email = 'a@test.com'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(retry_of, Some(origin));
+
+        let summary = query_summary_stats(&conn, chrono::Utc::now() - chrono::Duration::hours(1)).unwrap();
+        assert_eq!(summary.total_requests, 4);
+        assert_eq!(summary.total_input_tokens, 2310);
+        assert_eq!(summary.deduped_requests, 3);
+        assert_eq!(summary.deduped_input_tokens, 1310);
+
+        let hash_1_deduped: u64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM token_usage WHERE request_hash = 'hash-1' AND retry_of IS NULL",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(hash_1_deduped, 1);
+
+        // 小时聚合 (账号/小时/日统计) 不计入重试，预算仍按实际消耗计算
+        let (hourly_requests, hourly_input): (u64, u64) = conn
+            .query_row(
+                "SELECT SUM(request_count), SUM(total_input_tokens) FROM token_stats_hourly",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((hourly_requests, hourly_input), (3, 1310));
+        let budget_totals = query_account_totals_since(&conn, now - 3600).unwrap();
+        assert_eq!(budget_totals.get("b@test.com"), Some(&1200));
+    }
+
+    #[test]
+    fn test_retry_window_expires() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+
+        let start = 1_767_225_600;
+        insert_usage(&conn, start, "a@test.com", "claude-sonnet-4-5", 100, 10, 0, None, Some("hash-1")).unwrap();
+        // 超出窗口的相同请求视为新的用户操作
+        insert_usage(&conn, start + RETRY_DEDUP_WINDOW_SECS + 1, "a@test.com", "claude-sonnet-4-5", 100, 10, 0, None, Some("hash-1")).unwrap();
+
+        let retries: u64 = conn
+            .query_row("SELECT COUNT(*) FROM token_usage WHERE retry_of IS NOT NULL", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(retries, 0);
+    }
+
+    #[test]
+    fn test_output_breakdown_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+
+        let start = 1_767_225_600;
+        let with = insert_usage(&conn, start, "a@test.com", "claude-sonnet-4-5", 100, 60, 0, None, None).unwrap();
+        let breakdown = OutputTokenBreakdown { thinking_tokens: 30, text_tokens: 20, tool_input_tokens: 10 };
+        set_output_breakdown(&conn, with, &breakdown).unwrap();
+        let without = insert_usage(&conn, start + 1, "a@test.com", "claude-sonnet-4-5", 100, 60, 0, None, None).unwrap();
+
+        let row = |id: i64| -> (Option<u32>, Option<u32>, Option<u32>) {
+            conn.query_row(
+                "SELECT thinking_tokens, text_tokens, tool_input_tokens FROM token_usage WHERE id = ?1",
+                [id],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+            )
+            .unwrap()
+        };
+        assert_eq!(row(with), (Some(30), Some(20), Some(10)));
+        // 未知拆分保持 NULL
+        assert_eq!(row(without), (None, None, None));
+    }
+
+    #[test]
+    fn test_model_versions_recorded_per_request() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+
+        let start = 1_767_225_600;
+        let first = insert_usage(&conn, start, "a@test.com", "gemini-3-pro", 100, 10, 0, None, None).unwrap();
+        set_model_version(&conn, first, "gemini-3-pro-preview-1105").unwrap();
+        let second = insert_usage(&conn, start + 60, "b@test.com", "gemini-3-pro", 100, 10, 0, None, None).unwrap();
+        set_model_version(&conn, second, "gemini-3-pro-preview-1201").unwrap();
+        let third = insert_usage(&conn, start + 120, "a@test.com", "gemini-3-pro", 100, 10, 0, None, None).unwrap();
+        set_model_version(&conn, third, "gemini-3-pro-preview-1201").unwrap();
+        // 未知版本与窗口外的记录不计入
+        insert_usage(&conn, start + 180, "a@test.com", "gemini-3-pro", 100, 10, 0, None, None).unwrap();
+        let old = insert_usage(&conn, start - 1, "a@test.com", "gemini-3-pro", 100, 10, 0, None, None).unwrap();
+        set_model_version(&conn, old, "gemini-3-pro-preview-0901").unwrap();
+
+        let versions = query_model_versions_since(&conn, start).unwrap();
+        assert_eq!(
+            versions,
+            vec![
+                ModelVersionUsage {
+                    model: "gemini-3-pro".to_string(),
+                    model_version: "gemini-3-pro-preview-1105".to_string(),
+                    first_seen: start,
+                    last_seen: start,
+                    request_count: 1,
+                },
+                ModelVersionUsage {
+                    model: "gemini-3-pro".to_string(),
+                    model_version: "gemini-3-pro-preview-1201".to_string(),
+                    first_seen: start + 60,
+                    last_seen: start + 120,
+                    request_count: 2,
+                },
+            ]
+        );
+    }
 }
@@ -0,0 +1,344 @@
+use chrono::{Duration, NaiveDate, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::models::UsageExportConfig;
+use crate::modules::token_stats::{self, UsageBreakdownRow};
+
+const EXPORT_FILE_PREFIX: &str = "usage-";
+
+/// Export file format
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageExportFormat {
+    Csv,
+    Json,
+}
+
+impl UsageExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// Inclusive date range (YYYY-MM-DD, UTC — same buckets as token_stats)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UsageExportRange {
+    pub start_date: String,
+    pub end_date: String,
+}
+
+impl UsageExportRange {
+    pub fn single_day(date: NaiveDate) -> Self {
+        let day = date.format("%Y-%m-%d").to_string();
+        Self {
+            start_date: day.clone(),
+            end_date: day,
+        }
+    }
+
+    /// Convert to a half-open timestamp range [start 00:00, end + 1 day 00:00)
+    fn to_timestamps(&self) -> Result<(i64, i64), String> {
+        let parse = |s: &str| {
+            NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d")
+                .map_err(|e| format!("Invalid date '{}': {}", s, e))
+        };
+        let start = parse(&self.start_date)?;
+        let end = parse(&self.end_date)?;
+        if end < start {
+            return Err(format!(
+                "Invalid range: end date {} is before start date {}",
+                self.end_date, self.start_date
+            ));
+        }
+
+        let start_ts = start.and_time(NaiveTime::MIN).and_utc().timestamp();
+        let end_ts = (end + Duration::days(1)).and_time(NaiveTime::MIN).and_utc().timestamp();
+        Ok((start_ts, end_ts))
+    }
+}
+
+/// Column totals over all exported rows
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct UsageTotals {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cached_tokens: u64,
+    pub request_count: u64,
+}
+
+#[derive(Serialize)]
+struct UsageExportDocument<'a> {
+    range: &'a UsageExportRange,
+    generated_at: String,
+    totals: UsageTotals,
+    rows: &'a [UsageBreakdownRow],
+}
+
+fn compute_totals(rows: &[UsageBreakdownRow]) -> UsageTotals {
+    rows.iter().fold(UsageTotals::default(), |mut acc, row| {
+        acc.input_tokens += row.input_tokens;
+        acc.output_tokens += row.output_tokens;
+        acc.cached_tokens += row.cached_tokens;
+        acc.request_count += row.request_count;
+        acc
+    })
+}
+
+/// Apply the email masking setting to exported rows
+fn mask_rows(mut rows: Vec<UsageBreakdownRow>, mask_emails: bool) -> Vec<UsageBreakdownRow> {
+    if mask_emails {
+        for row in &mut rows {
+            row.account_email = crate::proxy::upstream::client::mask_email(&row.account_email);
+        }
+    }
+    rows
+}
+
+/// Quote a CSV field when it contains a delimiter, quote, line break or edge whitespace (RFC 4180)
+fn csv_escape(field: &str) -> String {
+    let needs_quotes = field.contains(|c: char| matches!(c, ',' | '"' | '\n' | '\r'))
+        || field.trim() != field;
+    if needs_quotes {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn render_csv(rows: &[UsageBreakdownRow]) -> String {
+    let mut out = String::from(
+        "model,account_email,client_key,input_tokens,output_tokens,cached_tokens,request_count\n",
+    );
+    for row in rows {
+        out.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            csv_escape(&row.model),
+            csv_escape(&row.account_email),
+            csv_escape(&row.client_key),
+            row.input_tokens,
+            row.output_tokens,
+            row.cached_tokens,
+            row.request_count
+        ));
+    }
+    out
+}
+
+fn render(
+    rows: &[UsageBreakdownRow],
+    range: &UsageExportRange,
+    format: UsageExportFormat,
+) -> Result<String, String> {
+    match format {
+        UsageExportFormat::Csv => Ok(render_csv(rows)),
+        UsageExportFormat::Json => serde_json::to_string_pretty(&UsageExportDocument {
+            range,
+            generated_at: chrono::Utc::now().to_rfc3339(),
+            totals: compute_totals(rows),
+            rows,
+        })
+        .map_err(|e| format!("Failed to serialize usage export: {}", e)),
+    }
+}
+
+/// Write a file atomically (temp file in the same directory + rename)
+fn write_atomic(path: &Path, content: &str) -> Result<(), String> {
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| format!("Invalid export path: {}", path.display()))?;
+    let temp_path = path.with_file_name(format!(".{}.tmp.{}", file_name, Uuid::new_v4()));
+
+    if let Err(e) = fs::write(&temp_path, content) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(format!("Failed to write {}: {}", temp_path.display(), e));
+    }
+    if let Err(e) = crate::modules::account::atomic_replace_file(&temp_path, &path.to_path_buf()) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Export usage for a date range to `path`
+pub fn export_usage(
+    range: &UsageExportRange,
+    format: UsageExportFormat,
+    path: &Path,
+    mask_emails: bool,
+) -> Result<PathBuf, String> {
+    let (start_ts, end_ts) = range.to_timestamps()?;
+    let rows = mask_rows(token_stats::get_usage_breakdown(start_ts, end_ts)?, mask_emails);
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create export directory: {}", e))?;
+    }
+    write_atomic(path, &render(&rows, range, format)?)?;
+    Ok(path.to_path_buf())
+}
+
+/// Resolve the scheduled export directory
+pub fn resolve_export_dir(config: &UsageExportConfig) -> Result<PathBuf, String> {
+    match config.export_dir.as_deref().map(str::trim).filter(|d| !d.is_empty()) {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => Ok(crate::modules::account::get_data_dir()?.join("usage_exports")),
+    }
+}
+
+fn export_file_name(date: NaiveDate, format: UsageExportFormat) -> String {
+    format!("{}{}.{}", EXPORT_FILE_PREFIX, date.format("%Y-%m-%d"), format.extension())
+}
+
+/// Whether the scheduled export for `date` has already been written
+pub fn daily_export_exists(config: &UsageExportConfig, date: NaiveDate) -> bool {
+    resolve_export_dir(config)
+        .map(|dir| {
+            [UsageExportFormat::Csv, UsageExportFormat::Json]
+                .iter()
+                .all(|f| dir.join(export_file_name(date, *f)).exists())
+        })
+        .unwrap_or(false)
+}
+
+/// Scheduled job: export one day into CSV + JSON and prune old exports
+pub fn run_daily_export(config: &UsageExportConfig, date: NaiveDate) -> Result<Vec<PathBuf>, String> {
+    let dir = resolve_export_dir(config)?;
+    let range = UsageExportRange::single_day(date);
+
+    let mut written = Vec::new();
+    for format in [UsageExportFormat::Csv, UsageExportFormat::Json] {
+        let path = dir.join(export_file_name(date, format));
+        written.push(export_usage(&range, format, &path, config.mask_emails)?);
+    }
+
+    let removed = prune_old_exports(&dir, config.retention_days, date)?;
+    if removed > 0 {
+        crate::modules::logger::log_info(&format!(
+            "[UsageExport] Pruned {} export files older than {} days",
+            removed, config.retention_days
+        ));
+    }
+    Ok(written)
+}
+
+/// Remove scheduled export files whose date is older than `retention_days` before `today`.
+/// Only files named `usage-YYYY-MM-DD.{csv,json}` are considered.
+fn prune_old_exports(dir: &Path, retention_days: u32, today: NaiveDate) -> Result<usize, String> {
+    if retention_days == 0 || !dir.exists() {
+        return Ok(0);
+    }
+    let cutoff = today - Duration::days(retention_days as i64);
+
+    let mut removed = 0;
+    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read export directory: {}", e))?;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(stem) = name
+            .strip_prefix(EXPORT_FILE_PREFIX)
+            .and_then(|rest| rest.strip_suffix(".csv").or_else(|| rest.strip_suffix(".json")))
+        else {
+            continue;
+        };
+        let Ok(file_date) = NaiveDate::parse_from_str(stem, "%Y-%m-%d") else {
+            continue;
+        };
+        if file_date < cutoff && fs::remove_file(entry.path()).is_ok() {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(model: &str, email: &str, key: &str, input: u64, output: u64, cached: u64, count: u64) -> UsageBreakdownRow {
+        UsageBreakdownRow {
+            model: model.to_string(),
+            account_email: email.to_string(),
+            client_key: key.to_string(),
+            input_tokens: input,
+            output_tokens: output,
+            cached_tokens: cached,
+            request_count: count,
+        }
+    }
+
+    #[test]
+    fn test_totals_and_range() {
+        let rows = vec![
+            row("gemini-3-flash", "a@test.com", "team-a", 150, 50, 5, 2),
+            row("claude-sonnet-4-5", "b@test.com", "", 1000, 200, 800, 1),
+        ];
+        assert_eq!(
+            compute_totals(&rows),
+            UsageTotals {
+                input_tokens: 1150,
+                output_tokens: 250,
+                cached_tokens: 805,
+                request_count: 3,
+            }
+        );
+
+        let range = UsageExportRange {
+            start_date: "2026-01-01".to_string(),
+            end_date: "2026-01-31".to_string(),
+        };
+        let (start, end) = range.to_timestamps().unwrap();
+        assert_eq!(start, 1_767_225_600);
+        assert_eq!(end - start, 31 * 86_400);
+
+        let reversed = UsageExportRange {
+            start_date: "2026-02-01".to_string(),
+            end_date: "2026-01-01".to_string(),
+        };
+        assert!(reversed.to_timestamps().is_err());
+    }
+
+    #[test]
+    fn test_csv_escaping_of_weird_account_names() {
+        assert_eq!(csv_escape("plain@test.com"), "plain@test.com");
+        assert_eq!(csv_escape("doe, john@test.com"), "\"doe, john@test.com\"");
+        assert_eq!(csv_escape("the \"boss\"@test.com"), "\"the \"\"boss\"\"@test.com\"");
+        assert_eq!(csv_escape("line\nbreak@test.com"), "\"line\nbreak@test.com\"");
+        assert_eq!(csv_escape(" padded@test.com"), "\" padded@test.com\"");
+
+        let csv = render_csv(&[row("gemini-3-flash", "doe, \"jd\"@test.com", "key,1", 1, 2, 0, 1)]);
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "model,account_email,client_key,input_tokens,output_tokens,cached_tokens,request_count"
+        );
+        assert_eq!(
+            lines.next().unwrap(),
+            "gemini-3-flash,\"doe, \"\"jd\"\"@test.com\",\"key,1\",1,2,0,1"
+        );
+    }
+
+    #[test]
+    fn test_masking_and_pruning() {
+        let rows = mask_rows(vec![row("m", "someone@example.com", "", 1, 1, 0, 1)], true);
+        assert_eq!(rows[0].account_email, "som***@ex***");
+
+        let dir = std::env::temp_dir().join(format!("usage_export_test_{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["usage-2026-01-01.csv", "usage-2026-01-01.json", "usage-2026-03-01.csv", "notes.txt"] {
+            fs::write(dir.join(name), "x").unwrap();
+        }
+
+        let today = NaiveDate::from_ymd_opt(2026, 3, 10).unwrap();
+        assert_eq!(prune_old_exports(&dir, 30, today).unwrap(), 2);
+        assert!(dir.join("usage-2026-03-01.csv").exists());
+        assert!(dir.join("notes.txt").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -28,6 +28,53 @@ pub struct UserToken {
     pub last_used_at: Option<i64>,
     pub total_requests: i64,
     pub total_tokens_used: i64,
+    /// 管理接口权限范围: "read" (只读状态/统计) 或 "admin" (全部)
+    #[serde(default = "default_scope")]
+    pub scope: String,
+    /// 是否允许访问管理接口 (/api/*)，仅通过 mint_scoped_token 签发的令牌为 true
+    #[serde(default)]
+    pub admin_api_access: bool,
+    /// [NEW] 每分钟请求数上限 (None 或 0 表示不限)
+    #[serde(default)]
+    pub rate_limit_rpm: Option<i64>,
+    /// [NEW] 每分钟 token 数上限 (None 或 0 表示不限)
+    #[serde(default)]
+    pub rate_limit_tpm: Option<i64>,
+}
+
+/// 只读权限: 可访问状态 / 统计 / 日志等只读管理接口
+pub const SCOPE_READ: &str = "read";
+/// 管理员权限: 可访问全部管理接口 (旧令牌迁移后的默认值)
+pub const SCOPE_ADMIN: &str = "admin";
+
+fn default_scope() -> String {
+    SCOPE_ADMIN.to_string()
+}
+
+/// 管理接口令牌被拒绝的原因 (对外返回的机器可读错误码)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminTokenDenial {
+    Disabled,
+    Expired,
+    InsufficientScope,
+}
+
+impl AdminTokenDenial {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AdminTokenDenial::Disabled => "token_disabled",
+            AdminTokenDenial::Expired => "token_expired",
+            AdminTokenDenial::InsufficientScope => "insufficient_scope",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            AdminTokenDenial::Disabled => "This token has been disabled.",
+            AdminTokenDenial::Expired => "This token has expired.",
+            AdminTokenDenial::InsufficientScope => "This token's scope does not allow this operation; an admin-scoped token is required.",
+        }
+    }
 }
 
 /// 令牌 IP 绑定结构体
@@ -73,7 +120,11 @@ pub fn connect_db() -> Result<Connection, String> {
 /// 初始化数据库
 pub fn init_db() -> Result<(), String> {
     let conn = connect_db()?;
-    
+    init_schema(&conn)
+}
+
+/// 建表并迁移旧数据库结构
+fn init_schema(conn: &Connection) -> Result<(), String> {
     // 创建 user_tokens 表
     conn.execute(
         "CREATE TABLE IF NOT EXISTS user_tokens (
@@ -91,7 +142,11 @@ pub fn init_db() -> Result<(), String> {
             total_requests INTEGER NOT NULL DEFAULT 0,
             total_tokens_used INTEGER NOT NULL DEFAULT 0,
             curfew_start TEXT,
-            curfew_end TEXT
+            curfew_end TEXT,
+            scope TEXT NOT NULL DEFAULT 'admin',
+            admin_api_access BOOLEAN NOT NULL DEFAULT 0,
+            rate_limit_rpm INTEGER,
+            rate_limit_tpm INTEGER
         )",
         [],
     ).map_err(|e| format!("Failed to create user_tokens table: {}", e))?;
@@ -105,6 +160,12 @@ pub fn init_db() -> Result<(), String> {
     let _ = conn.execute("ALTER TABLE user_tokens ADD COLUMN last_used_at INTEGER", []);
     let _ = conn.execute("ALTER TABLE user_tokens ADD COLUMN curfew_start TEXT", []);
     let _ = conn.execute("ALTER TABLE user_tokens ADD COLUMN curfew_end TEXT", []);
+    // [NEW] 权限范围: 旧令牌一律迁移为 admin，保持原有行为
+    let _ = conn.execute("ALTER TABLE user_tokens ADD COLUMN scope TEXT DEFAULT 'admin'", []);
+    let _ = conn.execute("ALTER TABLE user_tokens ADD COLUMN admin_api_access BOOLEAN DEFAULT 0", []);
+    // [NEW] 按令牌限流
+    let _ = conn.execute("ALTER TABLE user_tokens ADD COLUMN rate_limit_rpm INTEGER", []);
+    let _ = conn.execute("ALTER TABLE user_tokens ADD COLUMN rate_limit_tpm INTEGER", []);
 
     // 创建 token_ip_bindings 表
     conn.execute(
@@ -149,6 +210,8 @@ pub fn init_db() -> Result<(), String> {
     let _ = conn.execute("UPDATE user_tokens SET total_requests = 0 WHERE total_requests IS NULL", []);
     let _ = conn.execute("UPDATE user_tokens SET total_tokens_used = 0 WHERE total_tokens_used IS NULL", []);
     let _ = conn.execute("UPDATE user_tokens SET enabled = 1 WHERE enabled IS NULL", []);
+    let _ = conn.execute("UPDATE user_tokens SET scope = 'admin' WHERE scope IS NULL OR scope = ''", []);
+    let _ = conn.execute("UPDATE user_tokens SET admin_api_access = 0 WHERE admin_api_access IS NULL", []);
 
     Ok(())
 }
@@ -192,14 +255,64 @@ pub fn create_token(
         last_used_at: None,
         total_requests: 0,
         total_tokens_used: 0,
+        scope: default_scope(),
+        admin_api_access: false,
+        rate_limit_rpm: None,
+        rate_limit_tpm: None,
     };
 
+    insert_token(&conn, &user_token)?;
+    Ok(user_token)
+}
+
+/// [NEW] 签发管理接口令牌 (带权限范围与可选过期时间)
+/// 用于把只读的状态 / 统计接口开放给监控系统 (如 Grafana)，而不暴露管理员密码
+pub fn create_scoped_token(
+    name: String,
+    scope: &str,
+    description: Option<String>,
+    expires_at: Option<i64>, // 过期时间戳 (秒)，None = 永不过期
+) -> Result<UserToken, String> {
+    if scope != SCOPE_READ && scope != SCOPE_ADMIN {
+        return Err(format!("Invalid token scope: {} (expected \"read\" or \"admin\")", scope));
+    }
+    let conn = connect_db()?;
+    let now = Utc::now().timestamp();
+
+    let user_token = UserToken {
+        id: Uuid::new_v4().to_string(),
+        token: format!("sk-{}", Uuid::new_v4().to_string().replace("-", "")),
+        username: name,
+        description,
+        enabled: true,
+        expires_type: if expires_at.is_some() { "custom" } else { "never" }.to_string(),
+        expires_at,
+        max_ips: 0,
+        curfew_start: None,
+        curfew_end: None,
+        created_at: now,
+        updated_at: now,
+        last_used_at: None,
+        total_requests: 0,
+        total_tokens_used: 0,
+        scope: scope.to_string(),
+        admin_api_access: true,
+        rate_limit_rpm: None,
+        rate_limit_tpm: None,
+    };
+
+    insert_token(&conn, &user_token)?;
+    Ok(user_token)
+}
+
+fn insert_token(conn: &Connection, user_token: &UserToken) -> Result<(), String> {
     conn.execute(
         "INSERT INTO user_tokens (
             id, token, username, description, enabled, expires_type, expires_at, max_ips,
             curfew_start, curfew_end,
-            created_at, updated_at, total_requests, total_tokens_used
-        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            created_at, updated_at, total_requests, total_tokens_used, scope, admin_api_access,
+            rate_limit_rpm, rate_limit_tpm
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
         params![
             user_token.id,
             user_token.token,
@@ -215,10 +328,14 @@ pub fn create_token(
             user_token.updated_at,
             user_token.total_requests,
             user_token.total_tokens_used,
+            user_token.scope,
+            user_token.admin_api_access,
+            user_token.rate_limit_rpm,
+            user_token.rate_limit_tpm,
         ],
     ).map_err(|e| format!("Failed to insert user token: {}", e))?;
 
-    Ok(user_token)
+    Ok(())
 }
 
 /// 列出所有令牌
@@ -244,6 +361,10 @@ pub fn list_tokens() -> Result<Vec<UserToken>, String> {
             last_used_at: row.get("last_used_at").unwrap_or(None),
             total_requests: row.get("total_requests").unwrap_or(0),
             total_tokens_used: row.get("total_tokens_used").unwrap_or(0),
+            scope: row.get("scope").unwrap_or_else(|_| default_scope()),
+            admin_api_access: row.get("admin_api_access").unwrap_or(false),
+            rate_limit_rpm: row.get("rate_limit_rpm").unwrap_or(None),
+            rate_limit_tpm: row.get("rate_limit_tpm").unwrap_or(None),
         })
     }).map_err(|e| format!("Failed to query tokens: {}", e))?;
 
@@ -278,6 +399,10 @@ pub fn get_token_by_id(id: &str) -> Result<Option<UserToken>, String> {
             last_used_at: row.get("last_used_at")?,
             total_requests: row.get("total_requests")?,
             total_tokens_used: row.get("total_tokens_used")?,
+            scope: row.get("scope").unwrap_or_else(|_| default_scope()),
+            admin_api_access: row.get("admin_api_access").unwrap_or(false),
+            rate_limit_rpm: row.get("rate_limit_rpm").unwrap_or(None),
+            rate_limit_tpm: row.get("rate_limit_tpm").unwrap_or(None),
         })
     }).optional().map_err(|e| format!("Failed to query token: {}", e))?;
     
@@ -307,6 +432,10 @@ pub fn get_token_by_value(token: &str) -> Result<Option<UserToken>, String> {
             last_used_at: row.get("last_used_at")?,
             total_requests: row.get("total_requests")?,
             total_tokens_used: row.get("total_tokens_used")?,
+            scope: row.get("scope").unwrap_or_else(|_| default_scope()),
+            admin_api_access: row.get("admin_api_access").unwrap_or(false),
+            rate_limit_rpm: row.get("rate_limit_rpm").unwrap_or(None),
+            rate_limit_tpm: row.get("rate_limit_tpm").unwrap_or(None),
         })
     }).optional().map_err(|e| format!("Failed to query token: {}", e))?;
     
@@ -321,7 +450,9 @@ pub fn update_token(
     enabled: Option<bool>,
     max_ips: Option<i32>,
     curfew_start: Option<Option<String>>,
-    curfew_end: Option<Option<String>>
+    curfew_end: Option<Option<String>>,
+    rate_limit_rpm: Option<Option<i64>>,
+    rate_limit_tpm: Option<Option<i64>>,
 ) -> Result<(), String> {
     let conn = connect_db()?;
     let now = Utc::now().timestamp();
@@ -366,6 +497,18 @@ pub fn update_token(
         param_idx += 1;
     }
 
+    if let Some(rpm) = rate_limit_rpm {
+        query.push_str(&format!(", rate_limit_rpm = ?{}", param_idx));
+        params_vec.push(Box::new(rpm));
+        param_idx += 1;
+    }
+
+    if let Some(tpm) = rate_limit_tpm {
+        query.push_str(&format!(", rate_limit_tpm = ?{}", param_idx));
+        params_vec.push(Box::new(tpm));
+        param_idx += 1;
+    }
+
     query.push_str(&format!(" WHERE id = ?{}", param_idx));
     params_vec.push(Box::new(id.to_string()));
 
@@ -442,6 +585,7 @@ pub fn record_token_usage_and_ip(
     status: u16,
     user_agent: Option<String>
 ) -> Result<(), String> {
+    let _gate = crate::modules::db_maintenance::write_guard();
     let mut conn = connect_db()?;
     let tx = conn.transaction().map_err(|e| format!("Failed to create transaction: {}", e))?;
     let now = Utc::now().timestamp();
@@ -498,73 +642,126 @@ pub fn record_token_usage_and_ip(
     Ok(())
 }
 
+/// [NEW] 令牌自 since (秒) 以来消耗的 token 总数及最早一条记录的时间 (用于 TPM 滑动窗口)
+pub fn get_token_usage_since(token_id: &str, since: i64) -> Result<(i64, Option<i64>), String> {
+    let conn = connect_db()?;
+    conn.query_row(
+        "SELECT COALESCE(SUM(COALESCE(input_tokens, 0) + COALESCE(output_tokens, 0)), 0), MIN(request_time)
+         FROM token_usage_logs WHERE token_id = ?1 AND request_time >= ?2",
+        params![token_id, since],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .map_err(|e| format!("Failed to query token usage: {}", e))
+}
+
 /// 检查 Token 是否有效 (包含过期时间检查和 IP 限制检查)
 /// 返回: (是否有效, 拒绝原因)
 pub fn validate_token(token_str: &str, ip: &str) -> Result<(bool, Option<String>), String> {
-    let token_opt = get_token_by_value(token_str)?;
+    match get_token_by_value(token_str)? {
+        Some(token) => check_token_access(&token, ip),
+        None => Ok((false, Some("Invalid token. Please check your API key.".to_string()))),
+    }
+}
 
-    if let Some(token) = token_opt {
-        // 1. 检查过期时间
-        if let Some(expires_at) = token.expires_at {
-            if expires_at < Utc::now().timestamp() {
-                return Ok((false, Some("Your token has expired. Please contact the administrator to renew it.".to_string())));
-            }
-        }
+/// [NEW] 对已查询到的 Token 做访问检查 (启用状态、过期时间、IP 限制、宵禁)，避免重复查询令牌库
+/// 返回: (是否有效, 拒绝原因)
+pub fn check_token_access(token: &UserToken, ip: &str) -> Result<(bool, Option<String>), String> {
+    // 0. 检查是否被禁用
+    if !token.enabled {
+        return Ok((false, Some("Your token has been disabled. Please contact the administrator.".to_string())));
+    }
 
-        // 2. 检查 IP 限制
-        if token.max_ips > 0 {
-            let conn = connect_db()?;
+    // [NEW] 管理令牌 (mint_scoped_token 签发) 仅用于管理接口，不能调用代理接口
+    if token.admin_api_access {
+        return Ok((false, Some("This token is only valid for the management API.".to_string())));
+    }
 
-            // 检查当前 IP 是否已绑定
-            let is_bound: bool = conn.query_row(
-                "SELECT EXISTS(SELECT 1 FROM token_ip_bindings WHERE token_id = ?1 AND ip_address = ?2)",
-                params![token.id, ip],
+    // 1. 检查过期时间
+    if let Some(expires_at) = token.expires_at {
+        if expires_at < Utc::now().timestamp() {
+            return Ok((false, Some("Your token has expired. Please contact the administrator to renew it.".to_string())));
+        }
+    }
+
+    // 2. 检查 IP 限制
+    if token.max_ips > 0 {
+        let conn = connect_db()?;
+
+        // 检查当前 IP 是否已绑定
+        let is_bound: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM token_ip_bindings WHERE token_id = ?1 AND ip_address = ?2)",
+            params![token.id, ip],
+            |row| row.get(0)
+        ).unwrap_or(false);
+
+        if !is_bound {
+            // 如果未绑定，检查是否达到上限
+            let current_ip_count: i32 = conn.query_row(
+                "SELECT COUNT(*) FROM token_ip_bindings WHERE token_id = ?1",
+                params![token.id],
                 |row| row.get(0)
-            ).unwrap_or(false);
-
-            if !is_bound {
-                // 如果未绑定，检查是否达到上限
-                let current_ip_count: i32 = conn.query_row(
-                    "SELECT COUNT(*) FROM token_ip_bindings WHERE token_id = ?1",
-                    params![token.id],
-                    |row| row.get(0)
-                ).unwrap_or(0);
-
-                if current_ip_count >= token.max_ips {
-                    return Ok((false, Some(format!("IP limit reached ({}/{}). Please contact the administrator to increase the limit.", current_ip_count, token.max_ips))));
-                }
+            ).unwrap_or(0);
+
+            if current_ip_count >= token.max_ips {
+                return Ok((false, Some(format!("IP limit reached ({}/{}). Please contact the administrator to increase the limit.", current_ip_count, token.max_ips))));
             }
         }
+    }
 
-        // 3. 检查宵禁时间 (Curfew)
-        // 逻辑：如果当前北京时间在 start 和 end 之间，则拒绝
-        // 格式：HH:MM
-        // 使用固定 UTC+8 (北京时间)，不依赖服务器本地时区
-        if let (Some(start_str), Some(end_str)) = (&token.curfew_start, &token.curfew_end) {
-            if !start_str.is_empty() && !end_str.is_empty() {
-                let beijing_offset = FixedOffset::east_opt(8 * 3600).unwrap();
-                let now_beijing = Utc::now().with_timezone(&beijing_offset);
-                let current_time_str = format!("{:02}:{:02}", now_beijing.hour(), now_beijing.minute());
-
-                // 跨午夜处理: start > end (e.g. 23:00 to 06:00)
-                // 正常: start < end (e.g. 09:00 to 18:00)
-                let is_curfew = if start_str > end_str {
-                    current_time_str >= *start_str || current_time_str < *end_str
-                } else {
-                    current_time_str >= *start_str && current_time_str < *end_str
-                };
-
-                if is_curfew {
-                     return Ok((false, Some(format!("Service is not available between {} and {} Beijing Time (Curfew enabled). Current Beijing time: {}", start_str, end_str, current_time_str))));
-                }
+    // 3. 检查宵禁时间 (Curfew)
+    // 逻辑：如果当前北京时间在 start 和 end 之间，则拒绝
+    // 格式：HH:MM
+    // 使用固定 UTC+8 (北京时间)，不依赖服务器本地时区
+    if let (Some(start_str), Some(end_str)) = (&token.curfew_start, &token.curfew_end) {
+        if !start_str.is_empty() && !end_str.is_empty() {
+            let beijing_offset = FixedOffset::east_opt(8 * 3600).unwrap();
+            let now_beijing = Utc::now().with_timezone(&beijing_offset);
+            let current_time_str = format!("{:02}:{:02}", now_beijing.hour(), now_beijing.minute());
+
+            // 跨午夜处理: start > end (e.g. 23:00 to 06:00)
+            // 正常: start < end (e.g. 09:00 to 18:00)
+            let is_curfew = if start_str > end_str {
+                current_time_str >= *start_str || current_time_str < *end_str
+            } else {
+                current_time_str >= *start_str && current_time_str < *end_str
+            };
+
+            if is_curfew {
+                 return Ok((false, Some(format!("Service is not available between {} and {} Beijing Time (Curfew enabled). Current Beijing time: {}", start_str, end_str, current_time_str))));
             }
         }
+    }
 
-        // 一切正常，Token 有效
-        Ok((true, None))
-    } else {
-        Ok((false, Some("Invalid token. Please check your API key.".to_string())))
+    // 一切正常，Token 有效
+    Ok((true, None))
+}
+
+/// [NEW] 校验管理接口令牌
+/// 返回 Ok(None) 表示该值不是可用于管理接口的令牌 (调用方按未认证处理)
+pub fn authorize_admin_token(
+    token_str: &str,
+    requires_admin: bool,
+) -> Result<Option<Result<UserToken, AdminTokenDenial>>, String> {
+    let token = match get_token_by_value(token_str)? {
+        Some(token) if token.admin_api_access => token,
+        _ => return Ok(None),
+    };
+    let verdict = check_admin_scope(&token, requires_admin, Utc::now().timestamp());
+    Ok(Some(verdict.map(|_| token)))
+}
+
+/// 检查令牌状态、过期时间与权限范围是否满足管理接口要求
+pub fn check_admin_scope(token: &UserToken, requires_admin: bool, now: i64) -> Result<(), AdminTokenDenial> {
+    if !token.enabled {
+        return Err(AdminTokenDenial::Disabled);
     }
+    if token.expires_at.map(|t| t < now).unwrap_or(false) {
+        return Err(AdminTokenDenial::Expired);
+    }
+    if requires_admin && token.scope != SCOPE_ADMIN {
+        return Err(AdminTokenDenial::InsufficientScope);
+    }
+    Ok(())
 }
 
 /// 获取 IP 关联的用户名 (用于 IP 管理页面)
@@ -591,6 +788,7 @@ mod tests {
 
     #[test]
     fn test_create_and_query_token() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db(); // Ensure DB is initialized
         
         // Use a random username to avoid collisions in existing DB runs during dev
@@ -606,4 +804,107 @@ mod tests {
         assert!(fetched.is_ok());
         assert_eq!(fetched.unwrap().unwrap().username, username);
     }
+
+    fn scoped_token(scope: &str, expires_at: Option<i64>) -> UserToken {
+        UserToken {
+            id: "id".to_string(),
+            token: "sk-test".to_string(),
+            username: "grafana".to_string(),
+            description: None,
+            enabled: true,
+            expires_type: "never".to_string(),
+            expires_at,
+            max_ips: 0,
+            curfew_start: None,
+            curfew_end: None,
+            created_at: 0,
+            updated_at: 0,
+            last_used_at: None,
+            total_requests: 0,
+            total_tokens_used: 0,
+            scope: scope.to_string(),
+            admin_api_access: true,
+            rate_limit_rpm: None,
+            rate_limit_tpm: None,
+        }
+    }
+
+    #[test]
+    fn test_admin_tokens_are_rejected_on_proxy_routes() {
+        let admin = scoped_token(SCOPE_ADMIN, None);
+        let (allowed, reason) = check_token_access(&admin, "10.0.0.1").unwrap();
+        assert!(!allowed);
+        assert!(reason.unwrap().contains("management API"));
+
+        // 普通代理令牌不受影响
+        let proxy_token = UserToken {
+            admin_api_access: false,
+            ..scoped_token(SCOPE_ADMIN, None)
+        };
+        assert_eq!(check_token_access(&proxy_token, "10.0.0.1").unwrap(), (true, None));
+    }
+
+    #[test]
+    fn test_scope_limits_admin_operations() {
+        let read = scoped_token(SCOPE_READ, None);
+        // 只读令牌可访问状态接口，不能执行清理缓存等操作
+        assert_eq!(check_admin_scope(&read, false, 1000), Ok(()));
+        assert_eq!(
+            check_admin_scope(&read, true, 1000),
+            Err(AdminTokenDenial::InsufficientScope)
+        );
+        assert_eq!(AdminTokenDenial::InsufficientScope.code(), "insufficient_scope");
+
+        let admin = scoped_token(SCOPE_ADMIN, None);
+        assert_eq!(check_admin_scope(&admin, true, 1000), Ok(()));
+    }
+
+    #[test]
+    fn test_expired_scoped_token_rejected() {
+        let token = scoped_token(SCOPE_ADMIN, Some(999));
+        assert_eq!(check_admin_scope(&token, false, 1000), Err(AdminTokenDenial::Expired));
+        let token = scoped_token(SCOPE_READ, Some(2000));
+        assert_eq!(check_admin_scope(&token, false, 1000), Ok(()));
+    }
+
+    #[test]
+    fn test_legacy_tokens_migrate_to_admin_scope() {
+        let conn = Connection::open_in_memory().unwrap();
+        // 旧版本的表结构 (无 scope / admin_api_access 列)
+        conn.execute(
+            "CREATE TABLE user_tokens (
+                id TEXT PRIMARY KEY,
+                token TEXT UNIQUE NOT NULL,
+                username TEXT NOT NULL,
+                description TEXT,
+                enabled BOOLEAN NOT NULL DEFAULT 1,
+                expires_type TEXT NOT NULL,
+                expires_at INTEGER,
+                max_ips INTEGER NOT NULL DEFAULT 0,
+                created_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL
+            )",
+            [],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO user_tokens (id, token, username, expires_type, created_at, updated_at)
+             VALUES ('legacy', 'sk-legacy', 'old', 'never', 0, 0)",
+            [],
+        )
+        .unwrap();
+
+        init_schema(&conn).unwrap();
+
+        let (scope, admin_api_access): (String, bool) = conn
+            .query_row(
+                "SELECT scope, admin_api_access FROM user_tokens WHERE id = 'legacy'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(scope, SCOPE_ADMIN);
+        // 旧的代理令牌不会因迁移获得管理接口访问权
+        assert!(!admin_api_access);
+    }
 }
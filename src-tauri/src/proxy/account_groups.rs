@@ -0,0 +1,123 @@
+// 账号分组 (多团队部署下的租户隔离)
+// 账号可归属一个分组 (Account.group)；请求的分组由 API Key 映射 (proxy.api_key_groups) 或
+// X-Antigravity-Group 请求头解析，选号时只考虑该分组的账号。未解析出分组的请求使用全部账号。
+// 映射到分组的 API Key 不能通过请求头切换到其他分组。
+// 分组通过 task-local 传递给 TokenManager，避免修改所有 get_token 调用点。
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+/// 请求头: 指定请求使用的账号分组
+pub const GROUP_HEADER: &str = "x-antigravity-group";
+/// 分组名长度上限
+const MAX_GROUP_LEN: usize = 64;
+
+tokio::task_local! {
+    static CURRENT_GROUP: Arc<str>;
+}
+
+/// 规范化分组名 (去除首尾空白并转为小写)，空值返回 Ok(None)，包含非法字符时返回错误
+pub fn normalize_group(raw: &str) -> Result<Option<String>, String> {
+    let group = raw.trim().to_lowercase();
+    if group.is_empty() {
+        return Ok(None);
+    }
+    let valid = group.len() <= MAX_GROUP_LEN
+        && group
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(format!(
+            "Invalid account group '{}': expected up to {} characters of [a-z0-9-_.]",
+            raw.trim(),
+            MAX_GROUP_LEN
+        ));
+    }
+    Ok(Some(group))
+}
+
+/// API Key 映射到的分组 (未映射时返回 Ok(None))
+pub fn mapped_group(
+    api_key: Option<&str>,
+    key_groups: &HashMap<String, String>,
+) -> Result<Option<String>, String> {
+    match api_key.and_then(|key| key_groups.get(key)) {
+        Some(group) => normalize_group(group),
+        None => Ok(None),
+    }
+}
+
+/// 解析请求所属分组: API Key 映射优先，其次为请求头
+/// 已映射分组的 Key 携带了不同分组的请求头时返回错误
+pub fn resolve(
+    api_key: Option<&str>,
+    header: Option<&str>,
+    key_groups: &HashMap<String, String>,
+) -> Result<Option<String>, String> {
+    let mapped = mapped_group(api_key, key_groups)?;
+    let requested = match header {
+        Some(value) => normalize_group(value)?,
+        None => None,
+    };
+
+    match (mapped, requested) {
+        (Some(mapped), Some(requested)) if mapped != requested => Err(format!(
+            "Account group '{}' is not permitted for this API key",
+            requested
+        )),
+        (Some(group), _) | (None, Some(group)) => Ok(Some(group)),
+        (None, None) => Ok(None),
+    }
+}
+
+/// 当前请求的账号分组 (未指定分组时返回 None)
+pub fn current() -> Option<Arc<str>> {
+    CURRENT_GROUP.try_with(|g| g.clone()).ok()
+}
+
+/// 在指定分组上下文中执行 future (None 时沿用外层上下文)
+/// 在 tokio::spawn 或响应流中选号时需要显式传入 `current()` 的结果
+pub async fn scope<F: Future>(group: Option<Arc<str>>, fut: F) -> F::Output {
+    match group {
+        Some(group) => CURRENT_GROUP.scope(group, fut).await,
+        None => fut.await,
+    }
+}
+
+/// 账号是否属于指定分组
+pub fn account_in_group(account_group: Option<&str>, group: &str) -> bool {
+    account_group == Some(group)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_group() {
+        let mut key_groups = HashMap::new();
+        key_groups.insert("sk-team-a".to_string(), "Team-A".to_string());
+
+        // 未映射的 Key 且无请求头: 使用全部账号
+        assert_eq!(resolve(Some("sk-other"), None, &key_groups), Ok(None));
+        // 请求头指定分组 (规范化为小写)
+        assert_eq!(
+            resolve(None, Some(" Team-B "), &key_groups),
+            Ok(Some("team-b".to_string()))
+        );
+        // Key 映射优先，且不能通过请求头切换分组
+        assert_eq!(
+            resolve(Some("sk-team-a"), None, &key_groups),
+            Ok(Some("team-a".to_string()))
+        );
+        assert_eq!(
+            resolve(Some("sk-team-a"), Some("team-a"), &key_groups),
+            Ok(Some("team-a".to_string()))
+        );
+        assert!(resolve(Some("sk-team-a"), Some("team-b"), &key_groups).is_err());
+
+        assert!(normalize_group("team a").is_err());
+        assert_eq!(normalize_group("  "), Ok(None));
+    }
+}
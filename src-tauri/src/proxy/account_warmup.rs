@@ -0,0 +1,84 @@
+// 新账号预热策略
+// 新添加的账号立即承接大量请求容易被上游标记。预热窗口内 (自账号 created_at 起算) 仅允许服务低成本模型组、
+// 降低并发流上限，且不作为会话的粘性绑定账号；窗口结束后按当前时间自动解除，无需重启或重新加载账号。
+
+use crate::proxy::common::model_mapping::model_cost_tier;
+use crate::proxy::config::AccountWarmupConfig;
+
+/// 账号剩余预热时间 (秒)；未开启预热、created_at 未知或已过预热期时返回 None
+pub fn remaining_secs(config: &AccountWarmupConfig, created_at: i64, now: i64) -> Option<i64> {
+    if !config.enabled || config.hours == 0 || created_at <= 0 {
+        return None;
+    }
+    let window = i64::try_from(config.hours.saturating_mul(3600)).unwrap_or(i64::MAX);
+    let remaining = created_at.saturating_add(window).saturating_sub(now);
+    (remaining > 0).then_some(remaining)
+}
+
+pub fn is_warming_up(config: &AccountWarmupConfig, created_at: i64, now: i64) -> bool {
+    remaining_secs(config, created_at, now).is_some()
+}
+
+/// 预热期账号是否允许服务该模型组 (normalized_model 为标准模型 ID，按成本档位表判定)
+pub fn allows_model(config: &AccountWarmupConfig, normalized_model: &str) -> bool {
+    model_cost_tier(normalized_model) <= config.max_cost_tier
+}
+
+/// 预热期账号的并发流上限：常规上限的 concurrency_percent%，至少 1 路
+/// 常规上限为 0 (不限制) 时以默认上限为基准，预热期账号始终受限
+pub fn stream_limit(config: &AccountWarmupConfig, max_streams: usize) -> usize {
+    let base = if max_streams == 0 {
+        crate::proxy::config::default_max_concurrent_streams_per_account()
+    } else {
+        max_streams
+    };
+    (base * config.concurrency_percent.min(100) as usize / 100).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::common::model_mapping::ModelCostTier;
+
+    fn config() -> AccountWarmupConfig {
+        AccountWarmupConfig {
+            enabled: true,
+            hours: 24,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_remaining_time_and_limits() {
+        let cfg = config();
+        let created_at = 1_767_225_600;
+
+        assert_eq!(remaining_secs(&cfg, created_at, created_at + 3600), Some(23 * 3600));
+        assert_eq!(remaining_secs(&cfg, created_at, created_at + 24 * 3600), None);
+        // created_at 缺失 (旧账号文件) 不受限
+        assert_eq!(remaining_secs(&cfg, 0, created_at), None);
+        assert_eq!(remaining_secs(&AccountWarmupConfig::default(), created_at, created_at), None);
+
+        assert!(allows_model(&cfg, "gemini-3-flash"));
+        assert!(!allows_model(&cfg, "claude"));
+        assert!(!allows_model(&cfg, "gemini-3-pro-high"));
+        // 成本档位表未收录的模型组按高成本处理
+        assert!(!allows_model(&cfg, "some-unknown-model"));
+
+        let relaxed = AccountWarmupConfig {
+            max_cost_tier: ModelCostTier::High,
+            ..cfg.clone()
+        };
+        assert!(allows_model(&relaxed, "claude"));
+
+        assert_eq!(stream_limit(&cfg, 4), 1);
+        assert_eq!(stream_limit(&cfg, 16), 4);
+        // 常规上限不限制时预热期账号仍受限
+        assert_eq!(stream_limit(&cfg, 0), 1);
+        let generous = AccountWarmupConfig {
+            concurrency_percent: 50,
+            ..cfg
+        };
+        assert_eq!(stream_limit(&generous, 0), 2);
+    }
+}
@@ -0,0 +1,262 @@
+// 大体积 base64 数据驻留 (Blob Interning)
+// 多张大图的请求在 mapper 流水线中会被反复克隆 (原始 body 副本、ClaudeRequest 克隆、inlineData JSON、最终序列化)，
+// 内存峰值可达载荷的数倍。这里在反序列化为 ClaudeRequest 之前，将超过阈值的 base64 数据移入共享的 Arc<str> 表，
+// 流水线中只流转短占位符；最终序列化时通过 serialize_str 直接从共享缓冲区写出，线上字节与原实现完全一致。
+
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde_json::Value;
+use std::sync::Arc;
+
+/// 超过该长度 (字节) 的 base64 数据才会被驻留
+pub const BLOB_INTERN_THRESHOLD: usize = 64 * 1024;
+
+/// 占位符前缀 (含 NUL 字符，不会出现在合法 base64 中)
+const PLACEHOLDER_PREFIX: &str = "\u{0}abv-blob:";
+
+/// 驻留表: 占位符 → 共享的 base64 数据
+#[derive(Debug, Clone, Default)]
+pub struct BlobTable {
+    blobs: Vec<Arc<str>>,
+    /// 每个 blob 的内容指纹，写入占位符以保证相同内容得到相同占位符 (会话指纹保持稳定)
+    fingerprints: Vec<u64>,
+}
+
+/// 采样计算内容指纹 (长度 + 首尾 + 等距采样)，避免对数 MB 数据做全量哈希
+fn fingerprint(data: &str) -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    let bytes = data.as_bytes();
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    bytes.len().hash(&mut hasher);
+    bytes[..bytes.len().min(1024)].hash(&mut hasher);
+    bytes[bytes.len().saturating_sub(1024)..].hash(&mut hasher);
+    for b in bytes.iter().step_by(4096) {
+        b.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+impl BlobTable {
+    /// 将 Claude 请求体中 image / document 的 base64 source.data 移入驻留表
+    ///
+    /// 仅处理顶层消息内容块 (tool_result 内嵌图片会被 mapper 转为文本，不参与驻留)
+    pub fn intern_claude_request(body: &mut Value, threshold: usize) -> Self {
+        let mut table = Self::default();
+
+        let Some(messages) = body.get_mut("messages").and_then(|m| m.as_array_mut()) else {
+            return table;
+        };
+
+        for msg in messages.iter_mut() {
+            let Some(blocks) = msg.get_mut("content").and_then(|c| c.as_array_mut()) else {
+                continue;
+            };
+            for block in blocks.iter_mut() {
+                let is_media = matches!(
+                    block.get("type").and_then(|t| t.as_str()),
+                    Some("image") | Some("document")
+                );
+                if !is_media {
+                    continue;
+                }
+                let Some(source) = block.get_mut("source") else {
+                    continue;
+                };
+                if source.get("type").and_then(|t| t.as_str()) != Some("base64") {
+                    continue;
+                }
+                if let Some(Value::String(data)) = source.get_mut("data") {
+                    if data.len() >= threshold {
+                        let fp = fingerprint(data);
+                        let placeholder = format!("{}{:016x}:{}", PLACEHOLDER_PREFIX, fp, table.blobs.len());
+                        let owned = std::mem::replace(data, placeholder);
+                        table.blobs.push(Arc::from(owned));
+                        table.fingerprints.push(fp);
+                    }
+                }
+            }
+        }
+
+        table
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blobs.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blobs.len()
+    }
+
+    /// 驻留数据总字节数
+    pub fn total_bytes(&self) -> usize {
+        self.blobs.iter().map(|b| b.len()).sum()
+    }
+
+    /// 查找占位符对应的共享数据
+    pub fn lookup(&self, s: &str) -> Option<&Arc<str>> {
+        if self.blobs.is_empty() {
+            return None;
+        }
+        let rest = s.strip_prefix(PLACEHOLDER_PREFIX)?;
+        let (fp, index) = rest.split_once(':')?;
+        let index = index.parse::<usize>().ok()?;
+        if *self.fingerprints.get(index)? != u64::from_str_radix(fp, 16).ok()? {
+            return None;
+        }
+        self.blobs.get(index)
+    }
+
+    /// 将占位符还原为完整数据 (用于 z.ai 透传、调试日志等需要完整 Value 的场景)
+    pub fn restore(&self, value: &mut Value) {
+        if self.blobs.is_empty() {
+            return;
+        }
+        match value {
+            Value::String(s) => {
+                if let Some(blob) = self.lookup(s) {
+                    *s = blob.to_string();
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|v| self.restore(v)),
+            Value::Object(map) => map.values_mut().for_each(|v| self.restore(v)),
+            _ => {}
+        }
+    }
+
+    /// 序列化为最终请求体，占位符在写出时直接替换为共享数据 (不产生中间副本)
+    pub fn to_vec(&self, value: &Value) -> serde_json::Result<Vec<u8>> {
+        if self.blobs.is_empty() {
+            return serde_json::to_vec(value);
+        }
+        // 预分配，避免扩容时的额外拷贝
+        let mut buf = Vec::with_capacity(self.total_bytes() + 64 * 1024);
+        serde_json::to_writer(&mut buf, &Resolved { value, table: self })?;
+        Ok(buf)
+    }
+}
+
+/// 序列化视图: 遇到占位符时写出驻留数据
+struct Resolved<'a> {
+    value: &'a Value,
+    table: &'a BlobTable,
+}
+
+impl Serialize for Resolved<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.value {
+            Value::String(s) => match self.table.lookup(s) {
+                Some(blob) => serializer.serialize_str(blob),
+                None => serializer.serialize_str(s),
+            },
+            Value::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(&Resolved {
+                        value: item,
+                        table: self.table,
+                    })?;
+                }
+                seq.end()
+            }
+            Value::Object(map) => {
+                let mut m = serializer.serialize_map(Some(map.len()))?;
+                for (k, v) in map {
+                    m.serialize_entry(
+                        k,
+                        &Resolved {
+                            value: v,
+                            table: self.table,
+                        },
+                    )?;
+                }
+                m.end()
+            }
+            other => other.serialize(serializer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::mappers::claude::{transform_claude_request_in, ClaudeRequest};
+    use crate::proxy::mappers::common_utils::EnvelopeParams;
+    use serde_json::json;
+
+    fn fake_base64(len: usize, seed: u8) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        (0..len)
+            .map(|i| ALPHABET[(i * 7 + seed as usize) % ALPHABET.len()] as char)
+            .collect()
+    }
+
+    fn request_with_images(sizes: &[usize]) -> Value {
+        let mut content: Vec<Value> = sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &size)| {
+                json!({
+                    "type": "image",
+                    "source": { "type": "base64", "media_type": "image/png", "data": fake_base64(size, i as u8) }
+                })
+            })
+            .collect();
+        content.push(json!({ "type": "text", "text": "describe these images" }));
+        json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "messages": [{ "role": "user", "content": content }]
+        })
+    }
+
+    fn serialize_upstream(body: Value, table: &BlobTable) -> Vec<u8> {
+        let req: ClaudeRequest = serde_json::from_value(body).unwrap();
+        let mut gemini_body = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        // requestId 每次随机生成，固定后再比较字节
+        gemini_body["requestId"] = json!("agent-golden");
+        table.to_vec(&gemini_body).unwrap()
+    }
+
+    #[test]
+    fn test_interned_pipeline_is_bit_identical() {
+        let original = request_with_images(&[200 * 1024, 10, 150 * 1024]);
+        let expected = serialize_upstream(original.clone(), &BlobTable::default());
+
+        let mut interned = original;
+        let table = BlobTable::intern_claude_request(&mut interned, BLOB_INTERN_THRESHOLD);
+        // 小图不驻留
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.total_bytes(), 350 * 1024);
+        assert!(interned.to_string().len() < 4 * 1024);
+
+        let actual = serialize_upstream(interned, &table);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_restore_and_foreign_placeholders() {
+        let original = request_with_images(&[100 * 1024]);
+        let mut interned = original.clone();
+        let table = BlobTable::intern_claude_request(&mut interned, BLOB_INTERN_THRESHOLD);
+        assert_ne!(interned, original);
+
+        let mut restored = interned.clone();
+        table.restore(&mut restored);
+        assert_eq!(restored, original);
+
+        let placeholder = interned["messages"][0]["content"][0]["source"]["data"]
+            .as_str()
+            .unwrap();
+        assert!(table.lookup(placeholder).is_some());
+
+        // 相同内容得到相同占位符 (会话指纹稳定)
+        let mut same = request_with_images(&[100 * 1024]);
+        BlobTable::intern_claude_request(&mut same, BLOB_INTERN_THRESHOLD);
+        assert_eq!(same, interned);
+
+        // 不同内容的驻留表不会解析本请求的占位符
+        let other = BlobTable::intern_claude_request(&mut request_with_images(&[120 * 1024]), BLOB_INTERN_THRESHOLD);
+        assert!(other.lookup(placeholder).is_none());
+    }
+}
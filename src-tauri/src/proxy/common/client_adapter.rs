@@ -1,7 +1,7 @@
 use axum::http::HeaderMap;
 use once_cell::sync::Lazy;
 use std::sync::Arc; // [NEW] Import Arc
-use super::client_adapters::OpencodeAdapter;
+use super::client_adapters::{OpencodeAdapter, ProfileAdapter, ZedAdapter};
 
 /// 客户端适配器 trait
 /// 
@@ -21,6 +21,11 @@ pub trait ClientAdapter: Send + Sync {
     /// # Returns
     /// 如果匹配返回 true，否则返回 false
     fn matches(&self, headers: &HeaderMap) -> bool;
+
+    /// 适配器名称 (客户端可通过 x-abv-client-profile 请求头按名称显式选用)
+    fn name(&self) -> &str {
+        "default"
+    }
     
     /// 是否绕过签名校验
     /// 
@@ -51,6 +56,38 @@ pub trait ClientAdapter: Send + Sync {
         // 默认不注入
     }
     
+    /// 是否将空字符串 system prompt 视为未提供
+    /// 
+    /// 某些客户端（如 Zed）总是发送 system 字段，即使内容为空
+    fn normalize_empty_system_prompt(&self) -> bool {
+        false
+    }
+    
+    /// 流式 text_delta 的最小合并长度
+    /// 
+    /// 0 表示不做处理（默认）；大于 0 时强制开启 delta 合并（丢弃空 delta，短 delta 合并后再发送），
+    /// 合并器按 UTF-8 字节数判断阈值
+    fn min_text_delta_chars(&self) -> usize {
+        0
+    }
+    
+    /// 是否支持 Anthropic citations 格式
+    /// 
+    /// 为 true 时，流式响应会将 Gemini groundingSupports 映射为文本块上的行内引用 (citations_delta)
+    fn supports_citations(&self) -> bool {
+        false
+    }
+    
+    /// 是否在流式结尾追加联网搜索 (grounding) 来源文本块
+    fn emits_grounding_text(&self) -> bool {
+        true
+    }
+    
+    /// OpenAI 协议是否输出 reasoning_content 字段 (部分客户端会把未知字段渲染为正文)
+    fn emits_reasoning_content(&self) -> bool {
+        true
+    }
+    
     /// 声明支持的协议
     /// 
     /// 用于多协议客户端（如 opencode）
@@ -88,11 +125,46 @@ pub enum Protocol {
 pub static CLIENT_ADAPTERS: Lazy<Vec<Arc<dyn ClientAdapter>>> = Lazy::new(|| {
     vec![
         Arc::new(OpencodeAdapter),
+        Arc::new(ZedAdapter),
         // 未来可以轻松添加更多适配器:
         // Arc::new(CherryStudioAdapter),
     ]
 });
 
+/// [NEW] 客户端自声明配置档请求头
+/// 格式: `<名称>[; flag[=value]]...`，例如 `cherry-studio; citations=0; reasoning_content=1`
+pub const CLIENT_PROFILE_HEADER: &str = "x-abv-client-profile";
+
+/// 解析请求的客户端适配器，优先级: 显式请求头 -> User-Agent 识别 -> 默认 (None)
+pub fn resolve_client_adapter(headers: &HeaderMap) -> Option<Arc<dyn ClientAdapter>> {
+    resolve_client_adapter_with(headers, &crate::proxy::config::get_client_profiles())
+}
+
+pub fn resolve_client_adapter_with(
+    headers: &HeaderMap,
+    custom_profiles: &[crate::proxy::config::ClientProfileConfig],
+) -> Option<Arc<dyn ClientAdapter>> {
+    if let Some(raw) = headers.get(CLIENT_PROFILE_HEADER).and_then(|v| v.to_str().ok()) {
+        match ProfileAdapter::from_header(raw, custom_profiles) {
+            Some(adapter) => return Some(Arc::new(adapter)),
+            None => tracing::warn!(
+                "[Client-Profile] Unknown profile in {}: {:?}, falling back to User-Agent detection",
+                CLIENT_PROFILE_HEADER,
+                raw
+            ),
+        }
+    }
+    CLIENT_ADAPTERS.iter().find(|a| a.matches(headers)).cloned()
+}
+
+/// 按名称查找内置适配器
+pub fn find_builtin_adapter(name: &str) -> Option<Arc<dyn ClientAdapter>> {
+    CLIENT_ADAPTERS
+        .iter()
+        .find(|a| a.name().eq_ignore_ascii_case(name))
+        .cloned()
+}
+
 /// 辅助函数：从 HeaderMap 中提取 User-Agent
 pub fn get_user_agent(headers: &HeaderMap) -> Option<String> {
     headers
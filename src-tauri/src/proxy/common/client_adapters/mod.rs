@@ -2,5 +2,9 @@
 // 存放各种客户端的适配器实现
 
 pub mod opencode;
+pub mod profile;
+pub mod zed;
 
 pub use opencode::OpencodeAdapter;
+pub use profile::ProfileAdapter;
+pub use zed::ZedAdapter;
@@ -0,0 +1,238 @@
+use super::super::client_adapter::{find_builtin_adapter, ClientAdapter, Protocol, SignatureBufferStrategy};
+use crate::proxy::config::{ClientCapabilities, ClientProfileConfig};
+use axum::http::HeaderMap;
+use std::sync::Arc;
+
+/// 显式声明的客户端配置档适配器
+///
+/// 由 `x-abv-client-profile` 请求头解析得到 (不参与 User-Agent 识别)：
+/// - 名称可指向内置适配器 (zed / opencode)、配置中的自定义配置档，或 `default`
+/// - 请求头中的能力标记覆盖配置档中的同名标记
+/// - 未设置的能力沿用基础适配器 (内置适配器或自定义配置档的 extends)，再回退到默认行为
+pub struct ProfileAdapter {
+    name: String,
+    capabilities: ClientCapabilities,
+    base: Option<Arc<dyn ClientAdapter>>,
+}
+
+/// 请求头中可用的能力标记
+pub const PROFILE_FLAGS: &[&str] = &[
+    "citations",
+    "grounding_text",
+    "reasoning_content",
+    "normalize_empty_system_prompt",
+    "min_text_delta_chars",
+];
+
+fn parse_flag_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "" | "1" | "true" | "on" | "yes" => Some(true),
+        "0" | "false" | "off" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// 将单个 flag 写入能力集，未知 flag 或非法值返回 false
+fn apply_flag(capabilities: &mut ClientCapabilities, key: &str, value: &str) -> bool {
+    match key {
+        "citations" => parse_flag_bool(value).map(|v| capabilities.citations = Some(v)).is_some(),
+        "grounding_text" => parse_flag_bool(value).map(|v| capabilities.grounding_text = Some(v)).is_some(),
+        "reasoning_content" => parse_flag_bool(value)
+            .map(|v| capabilities.reasoning_content = Some(v))
+            .is_some(),
+        "normalize_empty_system_prompt" => parse_flag_bool(value)
+            .map(|v| capabilities.normalize_empty_system_prompt = Some(v))
+            .is_some(),
+        "min_text_delta_chars" => value
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .map(|v| capabilities.min_text_delta_chars = Some(v))
+            .is_some(),
+        _ => false,
+    }
+}
+
+impl ProfileAdapter {
+    /// 解析 `<名称>[; flag[=value]]...`；名称未知且没有任何有效 flag 时返回 None
+    pub fn from_header(raw: &str, custom_profiles: &[ClientProfileConfig]) -> Option<Self> {
+        let mut segments = raw.split(|c| c == ';' || c == ',').map(str::trim);
+        let name = segments.next().filter(|n| !n.is_empty())?.to_string();
+
+        let (mut capabilities, base, known) =
+            match custom_profiles.iter().find(|p| p.name.eq_ignore_ascii_case(&name)) {
+                Some(profile) => (
+                    profile.capabilities.clone(),
+                    profile.extends.as_deref().and_then(find_builtin_adapter),
+                    true,
+                ),
+                None => match find_builtin_adapter(&name) {
+                    Some(adapter) => (ClientCapabilities::default(), Some(adapter), true),
+                    None => (ClientCapabilities::default(), None, name.eq_ignore_ascii_case("default")),
+                },
+            };
+
+        let mut has_flags = false;
+        for segment in segments.filter(|s| !s.is_empty()) {
+            let (key, value) = segment.split_once('=').unwrap_or((segment, ""));
+            if apply_flag(&mut capabilities, key.trim(), value) {
+                has_flags = true;
+            } else {
+                tracing::debug!("[Client-Profile] Ignoring unknown or invalid flag: {}", segment);
+            }
+        }
+
+        if !known && !has_flags {
+            return None;
+        }
+        Some(Self { name, capabilities, base })
+    }
+}
+
+impl ClientAdapter for ProfileAdapter {
+    fn matches(&self, _headers: &HeaderMap) -> bool {
+        // 仅通过请求头显式选用
+        false
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn bypass_signature_matching(&self) -> bool {
+        self.base.as_ref().map_or(false, |b| b.bypass_signature_matching())
+    }
+
+    fn let_it_crash(&self) -> bool {
+        self.base.as_ref().map_or(false, |b| b.let_it_crash())
+    }
+
+    fn signature_buffer_strategy(&self) -> SignatureBufferStrategy {
+        self.base
+            .as_ref()
+            .map_or(SignatureBufferStrategy::Default, |b| b.signature_buffer_strategy())
+    }
+
+    fn inject_beta_headers(&self, headers: &mut HeaderMap) {
+        if let Some(base) = &self.base {
+            base.inject_beta_headers(headers);
+        }
+    }
+
+    fn normalize_empty_system_prompt(&self) -> bool {
+        self.capabilities
+            .normalize_empty_system_prompt
+            .unwrap_or_else(|| self.base.as_ref().map_or(false, |b| b.normalize_empty_system_prompt()))
+    }
+
+    fn min_text_delta_chars(&self) -> usize {
+        self.capabilities
+            .min_text_delta_chars
+            .unwrap_or_else(|| self.base.as_ref().map_or(0, |b| b.min_text_delta_chars()))
+    }
+
+    fn supports_citations(&self) -> bool {
+        self.capabilities
+            .citations
+            .unwrap_or_else(|| self.base.as_ref().map_or(false, |b| b.supports_citations()))
+    }
+
+    fn emits_grounding_text(&self) -> bool {
+        self.capabilities
+            .grounding_text
+            .unwrap_or_else(|| self.base.as_ref().map_or(true, |b| b.emits_grounding_text()))
+    }
+
+    fn emits_reasoning_content(&self) -> bool {
+        self.capabilities
+            .reasoning_content
+            .unwrap_or_else(|| self.base.as_ref().map_or(true, |b| b.emits_reasoning_content()))
+    }
+
+    fn supported_protocols(&self) -> Vec<Protocol> {
+        self.base
+            .as_ref()
+            .map_or_else(|| vec![Protocol::Anthropic], |b| b.supported_protocols())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::common::client_adapter::{resolve_client_adapter_with, CLIENT_PROFILE_HEADER};
+    use axum::http::HeaderValue;
+
+    fn headers(ua: Option<&'static str>, profile: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(ua) = ua {
+            headers.insert("user-agent", HeaderValue::from_static(ua));
+        }
+        if let Some(profile) = profile {
+            headers.insert(CLIENT_PROFILE_HEADER, HeaderValue::from_static(profile));
+        }
+        headers
+    }
+
+    fn custom_profile() -> ClientProfileConfig {
+        ClientProfileConfig {
+            name: "cherry-studio".to_string(),
+            extends: None,
+            capabilities: ClientCapabilities {
+                citations: Some(true),
+                grounding_text: Some(false),
+                reasoning_content: Some(false),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_resolution_priority() {
+        let custom = vec![custom_profile()];
+
+        // 显式请求头优先于 User-Agent
+        let adapter = resolve_client_adapter_with(&headers(Some("Zed/0.170.4"), Some("opencode")), &custom).unwrap();
+        assert_eq!(adapter.name(), "opencode");
+        assert!(adapter.let_it_crash());
+
+        // 无请求头时按 User-Agent 识别
+        let adapter = resolve_client_adapter_with(&headers(Some("Zed/0.170.4"), None), &custom).unwrap();
+        assert_eq!(adapter.name(), "zed");
+
+        // 未知名称且无 flag 时回退到 User-Agent，再回退到默认 (None)
+        let adapter = resolve_client_adapter_with(&headers(Some("Zed/0.170.4"), Some("mystery")), &custom).unwrap();
+        assert_eq!(adapter.name(), "zed");
+        assert!(resolve_client_adapter_with(&headers(None, Some("mystery")), &custom).is_none());
+        assert!(resolve_client_adapter_with(&headers(Some("curl/8.0"), None), &custom).is_none());
+
+        // UA 被清空的客户端可通过 flag 自声明能力；显式 default 可屏蔽 UA 识别
+        let adapter = resolve_client_adapter_with(&headers(None, Some("my-tool; citations; min_text_delta_chars=8")), &custom).unwrap();
+        assert!(adapter.supports_citations());
+        assert_eq!(adapter.min_text_delta_chars(), 8);
+        let adapter = resolve_client_adapter_with(&headers(Some("Zed/0.170.4"), Some("default")), &custom).unwrap();
+        assert_eq!(adapter.min_text_delta_chars(), 0);
+    }
+
+    #[test]
+    fn test_config_defined_profile_and_header_flags() {
+        let mut extends_zed = custom_profile();
+        extends_zed.name = "zed-lite".to_string();
+        extends_zed.extends = Some("zed".to_string());
+        let custom = vec![custom_profile(), extends_zed];
+
+        let adapter = resolve_client_adapter_with(&headers(None, Some("Cherry-Studio")), &custom).unwrap();
+        assert!(adapter.supports_citations());
+        assert!(!adapter.emits_grounding_text());
+        assert!(!adapter.emits_reasoning_content());
+
+        // 请求头 flag 覆盖配置档
+        let adapter = resolve_client_adapter_with(&headers(None, Some("cherry-studio; reasoning_content=1")), &custom).unwrap();
+        assert!(adapter.emits_reasoning_content());
+
+        // 继承内置适配器时，未设置的能力沿用其行为
+        let adapter = resolve_client_adapter_with(&headers(None, Some("zed-lite")), &custom).unwrap();
+        assert!(adapter.normalize_empty_system_prompt());
+        assert!(adapter.min_text_delta_chars() > 0);
+        assert!(!adapter.emits_grounding_text());
+    }
+}
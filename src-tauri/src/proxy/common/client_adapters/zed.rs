@@ -0,0 +1,87 @@
+use super::super::client_adapter::{ClientAdapter, get_user_agent};
+use axum::http::HeaderMap;
+
+/// Zed 编辑器内置 Assistant 的最小 text_delta 长度
+const ZED_MIN_TEXT_DELTA_CHARS: usize = 10;
+
+/// Zed 编辑器 Assistant 客户端适配器
+///
+/// Zed 通过 Anthropic 协议接入，存在以下协议特性：
+/// - 会发送空字符串形式的 system prompt
+/// - tool_result 的 content 可能是裸字符串而非数组 (核心转换已兼容)
+/// - 渲染空 `content_block_delta` 文本时会崩溃，且大量细碎 delta 渲染较慢
+///
+/// 该适配器提供以下定制策略：
+/// 1. 将空 system prompt 规范化为未提供
+/// 2. 流式输出时丢弃空 text_delta，并将短于 10 字符的 delta 合并发送
+pub struct ZedAdapter;
+
+impl ClientAdapter for ZedAdapter {
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        // 典型 UA: "Zed/0.170.4 (macos; aarch64)"
+        get_user_agent(headers)
+            .map(|ua| {
+                ua.to_lowercase()
+                    .split_whitespace()
+                    .any(|token| token == "zed" || token.starts_with("zed/"))
+            })
+            .unwrap_or(false)
+    }
+
+    fn name(&self) -> &str {
+        "zed"
+    }
+
+    fn normalize_empty_system_prompt(&self) -> bool {
+        true
+    }
+
+    fn min_text_delta_chars(&self) -> usize {
+        ZED_MIN_TEXT_DELTA_CHARS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::common::client_adapter::CLIENT_ADAPTERS;
+    use axum::http::HeaderValue;
+
+    fn headers_with_ua(ua: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("user-agent", HeaderValue::from_static(ua));
+        headers
+    }
+
+    #[test]
+    fn test_zed_adapter_matches() {
+        let adapter = ZedAdapter;
+
+        assert!(adapter.matches(&headers_with_ua("Zed/0.170.4 (macos; aarch64)")));
+        assert!(adapter.matches(&headers_with_ua("zed/0.171.0")));
+        assert!(!adapter.matches(&headers_with_ua("curl/7.68.0")));
+        assert!(!adapter.matches(&headers_with_ua("optimized-client/1.0")));
+    }
+
+    #[test]
+    fn test_zed_adapter_selected_from_registry() {
+        let selected = CLIENT_ADAPTERS
+            .iter()
+            .find(|a| a.matches(&headers_with_ua("Zed/0.170.4 (linux; x86_64)")))
+            .expect("Zed adapter should be selected");
+        assert_eq!(selected.min_text_delta_chars(), ZED_MIN_TEXT_DELTA_CHARS);
+        assert!(selected.normalize_empty_system_prompt());
+
+        // 其他客户端不应命中 Zed 策略
+        let opencode = CLIENT_ADAPTERS
+            .iter()
+            .find(|a| a.matches(&headers_with_ua("opencode/1.0.0")))
+            .expect("Opencode adapter should be selected");
+        assert_eq!(opencode.min_text_delta_chars(), 0);
+        assert!(!opencode.normalize_empty_system_prompt());
+
+        assert!(CLIENT_ADAPTERS
+            .iter()
+            .all(|a| !a.matches(&headers_with_ua("claude-cli/2.0.0"))));
+    }
+}
@@ -0,0 +1,87 @@
+// 递归 JSON 清理器的深度 / 规模保护
+// 客户端可能发送嵌套极深或体积巨大的工具输入与 schema。递归清理器在超出上限时
+// 停止深入 (超出部分保持原样) 并告警一次，而不是栈溢出或长时间占用 CPU。
+
+/// 单次清理最多访问的 JSON 节点数
+pub const MAX_JSON_CLEAN_NODES: usize = 1_000_000;
+
+/// 单次递归清理的深度 / 节点预算
+pub struct JsonWalkGuard {
+    label: &'static str,
+    max_depth: usize,
+    remaining_nodes: usize,
+    warned: bool,
+}
+
+impl JsonWalkGuard {
+    /// 使用全局配置的最大深度
+    pub fn new(label: &'static str) -> Self {
+        Self::with_limits(
+            label,
+            crate::proxy::config::get_max_json_clean_depth(),
+            MAX_JSON_CLEAN_NODES,
+        )
+    }
+
+    pub fn with_limits(label: &'static str, max_depth: usize, max_nodes: usize) -> Self {
+        Self {
+            label,
+            max_depth,
+            remaining_nodes: max_nodes,
+            warned: false,
+        }
+    }
+
+    /// 进入 depth 层的节点；超出深度或节点预算时返回 false，调用方应停止深入
+    pub fn enter(&mut self, depth: usize) -> bool {
+        if depth > self.max_depth {
+            self.warn_once(|| format!("depth {} exceeds limit {}", depth, self.max_depth));
+            return false;
+        }
+        if self.remaining_nodes == 0 {
+            self.warn_once(|| format!("node budget of {} exhausted", MAX_JSON_CLEAN_NODES));
+            return false;
+        }
+        self.remaining_nodes -= 1;
+        true
+    }
+
+    /// 是否曾经因超限而停止深入
+    #[allow(dead_code)]
+    pub fn bailed(&self) -> bool {
+        self.warned
+    }
+
+    fn warn_once(&mut self, reason: impl FnOnce() -> String) {
+        if !self.warned {
+            self.warned = true;
+            tracing::warn!(
+                "[Json-Guard] {} stopped descending: {}, deeper content left untouched",
+                self.label,
+                reason()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guard_caps_depth_and_node_budget() {
+        let mut guard = JsonWalkGuard::with_limits("test", 2, 10);
+        assert!(guard.enter(0));
+        assert!(guard.enter(2));
+        assert!(!guard.bailed());
+        assert!(!guard.enter(3));
+        assert!(guard.bailed());
+        // 深度超限不影响较浅的兄弟节点
+        assert!(guard.enter(1));
+
+        let mut guard = JsonWalkGuard::with_limits("test", 100, 2);
+        assert!(guard.enter(0));
+        assert!(guard.enter(1));
+        assert!(!guard.enter(1));
+    }
+}
@@ -2,6 +2,7 @@ use serde_json::{json, Value};
 use once_cell::sync::Lazy;
 use super::tool_adapter::ToolAdapter;
 use super::tool_adapters::PencilAdapter;
+use super::json_guard::JsonWalkGuard;
 
 /// 不被 Gemini 支持但包含重要语义信息的约束字段
 /// 这些字段将在删除前被转化为 description 提示
@@ -14,8 +15,6 @@ const CONSTRAINT_FIELDS: &[(&str, &str)] = &[
     ("multipleOf", "multipleOf"),
     ("exclusiveMinimum", "exclMin"),
     ("exclusiveMaximum", "exclMax"),
-    ("minItems", "minItems"),
-    ("maxItems", "maxItems"),
     ("format", "format"),
 ];
 
@@ -31,6 +30,7 @@ static TOOL_ADAPTERS: Lazy<Vec<Box<dyn ToolAdapter>>> = Lazy::new(|| {
     ]
 });
 
+/// $ref 展开的最大深度 (防止循环引用无限展开)
 const MAX_RECURSION_DEPTH: usize = 10;
 
 /// 递归清理 JSON Schema 以符合 Gemini 接口要求
@@ -41,11 +41,13 @@ const MAX_RECURSION_DEPTH: usize = 10;
 /// 4. [NEW] 处理 anyOf 联合类型: anyOf: [{"type": "string"}, {"type": "null"}] -> "type": "string"
 /// 5. 将 type 字段的值转换为小写 (Gemini v1internal 要求)
 /// 6. 移除数字校验字段: multipleOf, exclusiveMinimum, exclusiveMaximum 等
+/// 7. [NEW] const: X -> enum: [X]；由 const/enum 分支组成的 anyOf/oneOf 合并为单个 enum
+/// 8. [NEW] 数组节点保留 minItems/maxItems，其余情况转为描述文本
 pub fn clean_json_schema(value: &mut Value) {
     // 0. 预处理：展开 $ref (Schema Flattening)
     // [FIX #952] 递归收集所有层级的 $defs/definitions，而非仅从根层级提取
     let mut all_defs = serde_json::Map::new();
-    collect_all_defs(value, &mut all_defs);
+    collect_all_defs(value, &mut all_defs, 0, &mut JsonWalkGuard::new("collect_all_defs"));
 
     // 移除根层级的 $defs/definitions (保持向后兼容)
     if let Value::Object(map) = value {
@@ -59,8 +61,8 @@ pub fn clean_json_schema(value: &mut Value) {
         flatten_refs(map, &all_defs, 0);
     }
 
-    // 递归清理
-    clean_json_schema_recursive(value, true, 0);
+    // 递归清理 (超出配置深度的部分保持原样)
+    clean_json_schema_recursive(value, true, 0, &mut JsonWalkGuard::new("clean_json_schema"));
 }
 
 /// 带工具适配器支持的 Schema 清洗
@@ -99,7 +101,15 @@ pub fn clean_json_schema_for_tool(value: &mut Value, tool_name: &str) {
 ///
 /// MCP 工具的 schema 可能在任意嵌套层级定义 $defs，而非仅在根层级。
 /// 此函数深度遍历整个 schema，收集所有定义到统一的 map 中。
-fn collect_all_defs(value: &Value, defs: &mut serde_json::Map<String, Value>) {
+fn collect_all_defs(
+    value: &Value,
+    defs: &mut serde_json::Map<String, Value>,
+    depth: usize,
+    guard: &mut JsonWalkGuard,
+) {
+    if !guard.enter(depth) {
+        return;
+    }
     if let Value::Object(map) = value {
         // 收集当前层级的 $defs
         if let Some(Value::Object(d)) = map.get("$defs") {
@@ -118,12 +128,12 @@ fn collect_all_defs(value: &Value, defs: &mut serde_json::Map<String, Value>) {
         for (key, v) in map {
             // 跳过 $defs/definitions 本身，避免重复处理
             if key != "$defs" && key != "definitions" {
-                collect_all_defs(v, defs);
+                collect_all_defs(v, defs, depth + 1, guard);
             }
         }
     } else if let Value::Array(arr) = value {
         for item in arr {
-            collect_all_defs(item, defs);
+            collect_all_defs(item, defs, depth + 1, guard);
         }
     }
 }
@@ -190,9 +200,13 @@ fn flatten_refs(
     }
 }
 
-fn clean_json_schema_recursive(value: &mut Value, is_schema_node: bool, depth: usize) -> bool {
-    if depth > MAX_RECURSION_DEPTH {
-        debug_assert!(false, "Max recursion depth reached in clean_json_schema_recursive");
+fn clean_json_schema_recursive(
+    value: &mut Value,
+    is_schema_node: bool,
+    depth: usize,
+    guard: &mut JsonWalkGuard,
+) -> bool {
+    if !guard.enter(depth) {
         return false;
     }
     let mut is_effectively_nullable = false;
@@ -202,6 +216,10 @@ fn clean_json_schema_recursive(value: &mut Value, is_schema_node: bool, depth: u
             // 0. [NEW] 合并 allOf
             merge_all_of(map);
 
+            // 0.2 [NEW] const 转为单值 enum，anyOf/oneOf 字面量联合合并为 enum
+            translate_const_to_enum(map);
+            collapse_literal_union(map);
+
             // 0.5 [NEW] 结构归一化 (Normalization)
             // 针对某些 MCP 工具（如 pencil）误用 items 定义对象属性的情况进行修复。
             // 如果 type=object 或包含 properties，但又定义了 items，Gemini 会因为 items 只能出现在 array 中而报错。
@@ -226,7 +244,7 @@ fn clean_json_schema_recursive(value: &mut Value, is_schema_node: bool, depth: u
                 let mut nullable_keys = std::collections::HashSet::new();
                 for (k, v) in props {
                     // properties 的每一个值都必须是一个独立的 Schema 节点
-                    if clean_json_schema_recursive(v, true, depth + 1) {
+                    if clean_json_schema_recursive(v, true, depth + 1, guard) {
                         nullable_keys.insert(k.clone());
                     }
                 }
@@ -253,7 +271,7 @@ fn clean_json_schema_recursive(value: &mut Value, is_schema_node: bool, depth: u
             // 处理 items (数组)
             if let Some(items) = map.get_mut("items") {
                 // items 的内容必须是一个独立的 Schema 节点
-                clean_json_schema_recursive(items, true, depth + 1);
+                clean_json_schema_recursive(items, true, depth + 1, guard);
 
                 // [NEW] 隐式类型注入：如果有 items 但没 type，补全为 array
                 if !map.contains_key("type") {
@@ -266,7 +284,7 @@ fn clean_json_schema_recursive(value: &mut Value, is_schema_node: bool, depth: u
                 for (k, v) in map.iter_mut() {
                     // 排除掉关键字
                     if k != "anyOf" && k != "oneOf" && k != "allOf" && k != "enum" && k != "type" {
-                        clean_json_schema_recursive(v, false, depth + 1);
+                        clean_json_schema_recursive(v, false, depth + 1, guard);
                     }
                 }
             }
@@ -275,12 +293,12 @@ fn clean_json_schema_recursive(value: &mut Value, is_schema_node: bool, depth: u
             // 必须在合并逻辑之前执行，确保合并的分支已经被清洗
             if let Some(Value::Array(any_of)) = map.get_mut("anyOf") {
                 for branch in any_of.iter_mut() {
-                    clean_json_schema_recursive(branch, true, depth + 1);
+                    clean_json_schema_recursive(branch, true, depth + 1, guard);
                 }
             }
             if let Some(Value::Array(one_of)) = map.get_mut("oneOf") {
                 for branch in one_of.iter_mut() {
-                    clean_json_schema_recursive(branch, true, depth + 1);
+                    clean_json_schema_recursive(branch, true, depth + 1, guard);
                 }
             }
 
@@ -375,7 +393,7 @@ fn clean_json_schema_recursive(value: &mut Value, is_schema_node: bool, depth: u
                 // 递归清理刚刚移动进去的属性
                 if let Some(Value::Object(props_map)) = map.get_mut("properties") {
                     for v in props_map.values_mut() {
-                        clean_json_schema_recursive(v, true, depth + 1); 
+                        clean_json_schema_recursive(v, true, depth + 1, guard); 
                     }
                 }
             }
@@ -386,6 +404,7 @@ fn clean_json_schema_recursive(value: &mut Value, is_schema_node: bool, depth: u
                 // 4. [ROBUST] 约束迁移：在被白名单过滤前，将校验项转为描述 Hint
                 // [NEW] 使用统一的约束回填函数
                 move_constraints_to_description(map);
+                let kept_item_bounds = take_item_bounds(map);
 
                 // 5. [CRITICAL] 白名单过滤：彻底物理移除 Gemini 不支持的内容，防止 400 错误
                 let keys_to_remove: Vec<String> = map
@@ -396,6 +415,9 @@ fn clean_json_schema_recursive(value: &mut Value, is_schema_node: bool, depth: u
                 for k in keys_to_remove {
                     map.remove(&k);
                 }
+                for (k, v) in kept_item_bounds {
+                    map.insert(k, v);
+                }
 
                 // 6. [SAFETY] 处理空 Object
                 // [FIX] 移除 reason 字段注入逻辑
@@ -475,6 +497,18 @@ fn clean_json_schema_recursive(value: &mut Value, is_schema_node: bool, depth: u
                         Value::String(selected_type.unwrap_or_else(|| fallback.to_string()));
                 }
 
+                // [NEW] enum 中的 null 成员转为 nullable 标记 (Gemini enum 只接受字符串)
+                if let Some(Value::Array(arr)) = map.get_mut("enum") {
+                    let before = arr.len();
+                    arr.retain(|v| !v.is_null());
+                    if arr.len() != before {
+                        is_effectively_nullable = true;
+                    }
+                    if arr.is_empty() {
+                        map.remove("enum");
+                    }
+                }
+
                 if is_effectively_nullable {
                     let desc_val = map
                         .entry("description".to_string())
@@ -507,7 +541,7 @@ fn clean_json_schema_recursive(value: &mut Value, is_schema_node: bool, depth: u
             // [FIX] 递归清理数组中的每个元素
             // 这确保了所有数组类型的值（包括但不限于 anyOf、oneOf、items、enum 等）都会被递归处理
             for item in arr.iter_mut() {
-                clean_json_schema_recursive(item, is_schema_node, depth + 1);
+                clean_json_schema_recursive(item, is_schema_node, depth + 1, guard);
             }
         }
         _ => {}
@@ -631,6 +665,162 @@ fn move_constraints_to_description(map: &mut serde_json::Map<String, Value>) {
     }
 }
 
+/// [NEW] 将 const 转换为单值 enum，让模型仍能看到唯一合法值
+/// Gemini 的 enum 只适用于字符串，数字/布尔值保留原类型并转为描述文本；
+/// 对象/数组类型的 const 无法用 enum 表达，直接丢弃
+fn translate_const_to_enum(map: &mut serde_json::Map<String, Value>) {
+    let is_object_like = map.contains_key("properties")
+        || map.get("type").and_then(|t| t.as_str()) == Some("object");
+    let Some(const_val) = map.remove("const") else {
+        return;
+    };
+    if is_object_like || const_val.is_object() || const_val.is_array() {
+        return;
+    }
+
+    if !map.contains_key("type") {
+        if let Some(type_name) = literal_type_name(&const_val) {
+            map.insert("type".to_string(), Value::String(type_name.to_string()));
+        }
+    }
+    if const_val.is_string() && !map.contains_key("enum") {
+        map.insert("enum".to_string(), Value::Array(vec![const_val]));
+    } else if const_val.is_number() || const_val.is_boolean() {
+        append_hint_to_description(map, format!("[Constraint: const: {}]", const_val));
+    }
+}
+
+/// 字面量对应的 JSON Schema 类型
+fn literal_type_name(val: &Value) -> Option<&'static str> {
+    match val {
+        Value::String(_) => Some("string"),
+        Value::Bool(_) => Some("boolean"),
+        Value::Number(n) if n.is_i64() || n.is_u64() => Some("integer"),
+        Value::Number(_) => Some("number"),
+        Value::Null => Some("null"),
+        _ => None,
+    }
+}
+
+/// [NEW] 合并仅由字面量 (const / enum / null) 分支组成的 anyOf/oneOf
+/// 例如 anyOf: [{"const": "a"}, {"const": "b"}, {"type": "null"}] -> type: ["string", "null"], enum: ["a", "b"]
+/// 否则择优合并只会保留第一个分支的取值
+fn collapse_literal_union(map: &mut serde_json::Map<String, Value>) {
+    if map.contains_key("type") || map.contains_key("properties") {
+        return;
+    }
+    let union_key = if map.contains_key("anyOf") {
+        "anyOf"
+    } else if map.contains_key("oneOf") {
+        "oneOf"
+    } else {
+        return;
+    };
+    let Some(Value::Array(branches)) = map.get(union_key) else {
+        return;
+    };
+
+    let mut values: Vec<Value> = Vec::new();
+    let mut nullable = false;
+    let mut literal_type: Option<&'static str> = None;
+    for branch in branches {
+        let Value::Object(b) = branch else {
+            return;
+        };
+        if b.get("type").and_then(|t| t.as_str()) == Some("null") && !b.contains_key("enum") {
+            nullable = true;
+            continue;
+        }
+        if b.contains_key("properties") || b.contains_key("items") {
+            return;
+        }
+        let branch_values: Vec<Value> = match (b.get("const"), b.get("enum")) {
+            (Some(c), _) => vec![c.clone()],
+            (None, Some(Value::Array(e))) => e.clone(),
+            _ => return,
+        };
+        for v in branch_values {
+            if v.is_null() {
+                nullable = true;
+                continue;
+            }
+            let Some(t) = literal_type_name(&v) else {
+                return;
+            };
+            // 混合类型的字面量按字符串处理
+            literal_type = match literal_type {
+                None => Some(t),
+                Some(prev) if prev == t => Some(prev),
+                Some(_) => Some("string"),
+            };
+            if !values.contains(&v) {
+                values.push(v);
+            }
+        }
+    }
+    let Some(literal_type) = literal_type else {
+        return;
+    };
+
+    map.remove(union_key);
+    map.insert(
+        "type".to_string(),
+        if nullable {
+            json!([literal_type, "null"])
+        } else {
+            Value::String(literal_type.to_string())
+        },
+    );
+    // 非字符串字面量不能作为 Gemini enum，取值范围转为描述文本
+    if literal_type == "string" {
+        map.insert("enum".to_string(), Value::Array(values));
+    } else {
+        let allowed: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        append_hint_to_description(map, format!("[Constraint: one of: {}]", allowed.join(", ")));
+    }
+}
+
+/// [NEW] 处理 minItems/maxItems
+/// 数组节点上的非负整数边界原样保留 (Gemini Schema 支持)，返回需在白名单过滤后放回的字段；
+/// 其余情况 (非数组节点、非整数值) 转为描述文本，如 "must contain between 1 and 5 items"
+fn take_item_bounds(map: &mut serde_json::Map<String, Value>) -> Vec<(String, Value)> {
+    let min = map.remove("minItems").filter(|v| !v.is_null());
+    let max = map.remove("maxItems").filter(|v| !v.is_null());
+    if min.is_none() && max.is_none() {
+        return Vec::new();
+    }
+
+    let is_array = map.contains_key("items")
+        || match map.get("type") {
+            Some(Value::String(t)) => t.eq_ignore_ascii_case("array"),
+            Some(Value::Array(types)) => types
+                .iter()
+                .any(|t| t.as_str().is_some_and(|t| t.eq_ignore_ascii_case("array"))),
+            _ => false,
+        };
+    let is_valid = |v: &Option<Value>| v.as_ref().map_or(true, |v| v.as_u64().is_some());
+    if is_array && is_valid(&min) && is_valid(&max) {
+        let mut kept = Vec::new();
+        if let Some(v) = min {
+            kept.push(("minItems".to_string(), v));
+        }
+        if let Some(v) = max {
+            kept.push(("maxItems".to_string(), v));
+        }
+        return kept;
+    }
+
+    let display = |v: Value| v.as_str().map(|s| s.to_string()).unwrap_or_else(|| v.to_string());
+    let hint = match (min.map(display), max.map(display)) {
+        (Some(lo), Some(hi)) => format!("(must contain between {} and {} items)", lo, hi),
+        (Some(lo), None) => format!("(must contain at least {} items)", lo),
+        (None, Some(hi)) => format!("(must contain at most {} items)", hi),
+        (None, None) => return Vec::new(),
+    };
+    append_hint_to_description(map, hint);
+    Vec::new()
+}
+
 /// [NEW] 计算 Schema 分支的复杂度得分 (用于 anyOf/oneOf 择优)
 /// 评分标准: Object (3) > Array (2) > Scalar (1) > Null (0)
 fn score_schema_option(val: &Value) -> i32 {
@@ -713,11 +903,12 @@ fn get_schema_type_name(schema: &Value) -> Option<String> {
 /// * `args` - 工具调用的参数对象 (会被原地修改)
 /// * `schema` - 工具的参数 schema 定义 (通常是 parameters 对象)
 pub fn fix_tool_call_args(args: &mut Value, schema: &Value) {
+    let mut guard = JsonWalkGuard::new("fix_tool_call_args");
     if let Some(properties) = schema.get("properties").and_then(|p| p.as_object()) {
         if let Some(args_obj) = args.as_object_mut() {
             for (key, value) in args_obj.iter_mut() {
                 if let Some(prop_schema) = properties.get(key) {
-                    fix_single_arg_recursive(value, prop_schema);
+                    fix_single_arg_recursive(value, prop_schema, 1, &mut guard);
                 }
             }
         }
@@ -725,13 +916,16 @@ pub fn fix_tool_call_args(args: &mut Value, schema: &Value) {
 }
 
 /// 递归修正单个参数的类型
-fn fix_single_arg_recursive(value: &mut Value, schema: &Value) {
+fn fix_single_arg_recursive(value: &mut Value, schema: &Value, depth: usize, guard: &mut JsonWalkGuard) {
+    if !guard.enter(depth) {
+        return;
+    }
     // 1. 处理嵌套对象 (properties)
     if let Some(nested_props) = schema.get("properties").and_then(|p| p.as_object()) {
         if let Some(value_obj) = value.as_object_mut() {
             for (key, nested_value) in value_obj.iter_mut() {
                 if let Some(nested_schema) = nested_props.get(key) {
-                    fix_single_arg_recursive(nested_value, nested_schema);
+                    fix_single_arg_recursive(nested_value, nested_schema, depth + 1, guard);
                 }
             }
         }
@@ -748,7 +942,7 @@ fn fix_single_arg_recursive(value: &mut Value, schema: &Value) {
         if let Some(items_schema) = schema.get("items") {
             if let Some(arr) = value.as_array_mut() {
                 for item in arr {
-                    fix_single_arg_recursive(item, items_schema);
+                    fix_single_arg_recursive(item, items_schema, depth + 1, guard);
                 }
             }
         }
@@ -1553,4 +1747,158 @@ mod tests {
         assert_eq!(schema["properties"]["start"]["type"], "object");
         assert!(schema["properties"]["start"]["properties"].get("toB").is_some());
     }
+
+    #[test]
+    fn test_array_of_enum_items_preserved() {
+        let mut schema = json!({
+            "type": "object",
+            "properties": {
+                "tags": {
+                    "type": "array",
+                    "items": { "enum": ["bug", "feature", "docs"], "default": "bug" },
+                    "minItems": 1,
+                    "maxItems": 3
+                }
+            }
+        });
+
+        clean_json_schema(&mut schema);
+
+        assert_eq!(
+            schema["properties"]["tags"],
+            json!({
+                "type": "array",
+                "items": { "type": "string", "enum": ["bug", "feature", "docs"] },
+                "minItems": 1,
+                "maxItems": 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_const_becomes_single_value_enum() {
+        let mut schema = json!({
+            "type": "object",
+            "properties": {
+                "kind": { "const": "search" },
+                "version": { "const": 2 },
+                "items": {
+                    "type": "array",
+                    "items": { "type": "string", "const": "fixed" }
+                }
+            }
+        });
+
+        clean_json_schema(&mut schema);
+
+        assert_eq!(schema["properties"]["kind"], json!({ "type": "string", "enum": ["search"] }));
+        assert_eq!(
+            schema["properties"]["version"],
+            json!({ "type": "integer", "description": "[Constraint: const: 2]" })
+        );
+        assert_eq!(
+            schema["properties"]["items"]["items"],
+            json!({ "type": "string", "enum": ["fixed"] })
+        );
+    }
+
+    #[test]
+    fn test_literal_union_collapses_to_enum() {
+        let mut schema = json!({
+            "type": "object",
+            "properties": {
+                "mode": {
+                    "anyOf": [{ "const": "fast" }, { "const": "slow" }, { "type": "null" }],
+                    "description": "Run mode"
+                }
+            },
+            "required": ["mode"]
+        });
+
+        clean_json_schema(&mut schema);
+
+        let mode = &schema["properties"]["mode"];
+        assert!(mode.get("anyOf").is_none());
+        assert_eq!(mode["type"], "string");
+        assert_eq!(mode["enum"], json!(["fast", "slow"]));
+        assert_eq!(mode["description"], "Run mode (nullable)");
+        // nullable 字段不再是必填
+        assert!(schema.get("required").is_none());
+
+        // 数字字面量联合保留数字类型，取值转为描述
+        let mut schema = json!({
+            "type": "object",
+            "properties": { "level": { "oneOf": [{ "const": 1 }, { "const": 2 }] } }
+        });
+        clean_json_schema(&mut schema);
+        assert_eq!(
+            schema["properties"]["level"],
+            json!({ "type": "integer", "description": "[Constraint: one of: 1, 2]" })
+        );
+    }
+
+    #[test]
+    fn test_nullable_enum_member_degrades_to_description() {
+        let mut schema = json!({
+            "type": "object",
+            "properties": {
+                "level": { "type": ["string", "null"], "enum": ["low", "high", null] }
+            },
+            "required": ["level"]
+        });
+
+        clean_json_schema(&mut schema);
+
+        let level = &schema["properties"]["level"];
+        assert_eq!(level["type"], "string");
+        assert_eq!(level["enum"], json!(["low", "high"]));
+        assert_eq!(level["description"], "(nullable)");
+    }
+
+    #[test]
+    fn test_item_bounds_moved_to_description_when_not_applicable() {
+        let mut schema = json!({
+            "type": "object",
+            "properties": {
+                // 非数组节点上的 minItems/maxItems 无法保留
+                "ids": { "type": "string", "description": "Comma separated ids", "minItems": 1, "maxItems": 5 },
+                "at_least": { "type": "array", "items": { "type": "string" }, "minItems": "2" },
+                "at_most": { "type": "string", "maxItems": 4 }
+            }
+        });
+
+        clean_json_schema(&mut schema);
+
+        let ids = &schema["properties"]["ids"];
+        assert!(ids.get("minItems").is_none() && ids.get("maxItems").is_none());
+        assert_eq!(ids["description"], "Comma separated ids (must contain between 1 and 5 items)");
+
+        let at_least = &schema["properties"]["at_least"];
+        assert!(at_least.get("minItems").is_none());
+        assert_eq!(at_least["description"], "(must contain at least 2 items)");
+
+        assert_eq!(
+            schema["properties"]["at_most"]["description"],
+            "(must contain at most 4 items)"
+        );
+    }
+
+    #[test]
+    fn test_pattern_and_min_length_kept_in_description() {
+        let mut schema = json!({
+            "type": "object",
+            "properties": {
+                "slug": { "type": "string", "description": "URL slug", "pattern": "^[a-z-]+$", "minLength": 3 }
+            }
+        });
+
+        clean_json_schema(&mut schema);
+
+        let slug = &schema["properties"]["slug"];
+        assert!(slug.get("pattern").is_none() && slug.get("minLength").is_none());
+        assert_eq!(
+            slug["description"],
+            "URL slug [Constraint: minLen: 3, pattern: ^[a-z-]+$]"
+        );
+    }
 }
@@ -10,3 +10,5 @@ pub mod tool_adapters;
 pub mod schema_cache;
 pub mod client_adapter;
 pub mod client_adapters;
+pub mod blob_intern;
+pub mod json_guard;
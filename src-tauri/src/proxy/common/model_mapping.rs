@@ -1,6 +1,7 @@
 // 模型名称映射
 use std::collections::HashMap;
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 
 static CLAUDE_TO_GEMINI: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
     let mut m = HashMap::new();
@@ -296,6 +297,31 @@ pub fn normalize_to_standard_id(model_name: &str) -> Option<String> {
     None
 }
 
+/// 模型组成本档位 (按成本限制可用模型时使用，如新账号预热)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelCostTier {
+    Low,
+    High,
+}
+
+/// 模型组成本档位表 (键为 normalize_to_standard_id 的结果)
+const MODEL_COST_TIERS: &[(&str, ModelCostTier)] = &[
+    ("gemini-3-flash", ModelCostTier::Low),
+    ("gemini-3-pro-high", ModelCostTier::High),
+    ("gemini-3-pro-image", ModelCostTier::High),
+    ("claude", ModelCostTier::High),
+];
+
+/// 查询模型组的成本档位；未收录的模型组按高成本处理
+pub fn model_cost_tier(normalized_model: &str) -> ModelCostTier {
+    MODEL_COST_TIERS
+        .iter()
+        .find(|(group, _)| group.eq_ignore_ascii_case(normalized_model))
+        .map(|(_, tier)| *tier)
+        .unwrap_or(ModelCostTier::High)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -122,6 +122,883 @@ pub fn update_image_thinking_mode(mode: Option<String>) {
     }
 }
 
+// ============================================================================
+// 全局图像模型误映射回退配置存储
+// ============================================================================
+static GLOBAL_IMAGE_TEXT_FALLBACK_MODEL: OnceLock<RwLock<Option<String>>> = OnceLock::new();
+
+/// 文本请求被映射到图像模型时使用的回退文本模型 (None 表示仅告警不回退)
+pub fn get_image_text_fallback_model() -> Option<String> {
+    GLOBAL_IMAGE_TEXT_FALLBACK_MODEL
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .and_then(|m| m.clone())
+}
+
+pub fn update_image_text_fallback_model(model: Option<String>) {
+    let val = model.map(|m| m.trim().to_string()).filter(|m| !m.is_empty());
+    if let Some(lock) = GLOBAL_IMAGE_TEXT_FALLBACK_MODEL.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != val {
+                *cfg = val.clone();
+                tracing::info!("[Image-Fallback] Global config updated: {:?}", val);
+            }
+        }
+    } else {
+        let _ = GLOBAL_IMAGE_TEXT_FALLBACK_MODEL.set(RwLock::new(val.clone()));
+        tracing::info!("[Image-Fallback] Global config initialized: {:?}", val);
+    }
+}
+
+// ============================================================================
+// 全局强制回复语言配置存储
+// ============================================================================
+static GLOBAL_FORCED_RESPONSE_LANGUAGE: OnceLock<RwLock<Option<String>>> = OnceLock::new();
+
+/// 强制回复语言 (BCP-47 标签，None 表示不注入)
+pub fn get_forced_response_language() -> Option<String> {
+    GLOBAL_FORCED_RESPONSE_LANGUAGE
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .and_then(|l| l.clone())
+}
+
+pub fn update_forced_response_language(language: Option<String>) {
+    let val = language.map(|l| l.trim().to_string()).filter(|l| !l.is_empty());
+    let val = match val {
+        Some(tag) if !crate::proxy::mappers::common::system_builder::is_valid_language_tag(&tag) => {
+            tracing::warn!("[Response-Language] Ignoring invalid BCP-47 tag {:?}", tag);
+            None
+        }
+        other => other,
+    };
+    if let Some(lock) = GLOBAL_FORCED_RESPONSE_LANGUAGE.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != val {
+                *cfg = val.clone();
+                tracing::info!("[Response-Language] Global config updated: {:?}", val);
+            }
+        }
+    } else {
+        let _ = GLOBAL_FORCED_RESPONSE_LANGUAGE.set(RwLock::new(val.clone()));
+        tracing::info!("[Response-Language] Global config initialized: {:?}", val);
+    }
+}
+
+// ============================================================================
+// 全局图像多模态输出配置存储
+// ============================================================================
+static GLOBAL_IMAGE_MULTIMODAL_OUTPUT: OnceLock<RwLock<bool>> = OnceLock::new();
+
+/// 图像生成请求是否设置 responseModalities = ["TEXT", "IMAGE"] (默认关闭，移除该字段)
+pub fn get_image_multimodal_output() -> bool {
+    GLOBAL_IMAGE_MULTIMODAL_OUTPUT
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or(false)
+}
+
+pub fn update_image_multimodal_output(enabled: bool) {
+    if let Some(lock) = GLOBAL_IMAGE_MULTIMODAL_OUTPUT.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != enabled {
+                *cfg = enabled;
+                tracing::info!("[Image-Modalities] Global config updated: {}", enabled);
+            }
+        }
+    } else {
+        let _ = GLOBAL_IMAGE_MULTIMODAL_OUTPUT.set(RwLock::new(enabled));
+        tracing::info!("[Image-Modalities] Global config initialized: {}", enabled);
+    }
+}
+
+// ============================================================================
+// 全局角色交替 (同角色消息合并) 配置存储
+// ============================================================================
+static GLOBAL_STRICT_ROLE_ALTERNATION: OnceLock<RwLock<bool>> = OnceLock::new();
+
+/// 是否合并连续的同角色消息以满足严格的角色交替 (默认开启)
+pub fn get_strict_role_alternation() -> bool {
+    GLOBAL_STRICT_ROLE_ALTERNATION
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or(true)
+}
+
+pub fn update_strict_role_alternation(enabled: bool) {
+    if let Some(lock) = GLOBAL_STRICT_ROLE_ALTERNATION.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != enabled {
+                *cfg = enabled;
+                tracing::info!("[Role-Alternation] Global config updated: {}", enabled);
+            }
+        }
+    } else {
+        let _ = GLOBAL_STRICT_ROLE_ALTERNATION.set(RwLock::new(enabled));
+        tracing::info!("[Role-Alternation] Global config initialized: {}", enabled);
+    }
+}
+
+// ============================================================================
+// 全局联网搜索引文渲染样式配置存储
+// ============================================================================
+static GLOBAL_CITATION_STYLE: OnceLock<RwLock<CitationStyle>> = OnceLock::new();
+
+/// 获取联网搜索 (groundingMetadata) 引文渲染样式
+pub fn get_citation_style() -> CitationStyle {
+    GLOBAL_CITATION_STYLE
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or_default()
+}
+
+pub fn update_citation_style(style: CitationStyle) {
+    if let Some(lock) = GLOBAL_CITATION_STYLE.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != style {
+                *cfg = style;
+                tracing::info!("[Citation-Style] Global config updated: {:?}", style);
+            }
+        }
+    } else {
+        let _ = GLOBAL_CITATION_STYLE.set(RwLock::new(style));
+        tracing::info!("[Citation-Style] Global config initialized: {:?}", style);
+    }
+}
+
+// ============================================================================
+// 全局代码执行 part 处理配置存储
+// ============================================================================
+static GLOBAL_DROP_CODE_EXECUTION_PARTS: OnceLock<RwLock<bool>> = OnceLock::new();
+
+/// 是否丢弃 executableCode / codeExecutionResult part (默认 false，渲染为文本)
+pub fn get_drop_code_execution_parts() -> bool {
+    GLOBAL_DROP_CODE_EXECUTION_PARTS
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or(false)
+}
+
+pub fn update_drop_code_execution_parts(enabled: bool) {
+    if let Some(lock) = GLOBAL_DROP_CODE_EXECUTION_PARTS.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != enabled {
+                *cfg = enabled;
+                tracing::info!("[Code-Execution] Global config updated: drop={}", enabled);
+            }
+        }
+    } else {
+        let _ = GLOBAL_DROP_CODE_EXECUTION_PARTS.set(RwLock::new(enabled));
+        tracing::info!("[Code-Execution] Global config initialized: drop={}", enabled);
+    }
+}
+
+// ============================================================================
+// 全局账号轮换次数配置存储
+// ============================================================================
+static GLOBAL_MAX_ACCOUNT_ROTATIONS: OnceLock<RwLock<usize>> = OnceLock::new();
+
+/// 单个请求因 429 / 账号级错误最多轮换账号的次数 (默认 2，即最多尝试 3 次)
+pub fn get_max_account_rotations() -> usize {
+    GLOBAL_MAX_ACCOUNT_ROTATIONS
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or_else(default_max_account_rotations)
+}
+
+pub fn update_max_account_rotations(rotations: usize) {
+    if let Some(lock) = GLOBAL_MAX_ACCOUNT_ROTATIONS.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != rotations {
+                *cfg = rotations;
+                tracing::info!("[Account-Rotation] Global config updated: max_rotations={}", rotations);
+            }
+        }
+    } else {
+        let _ = GLOBAL_MAX_ACCOUNT_ROTATIONS.set(RwLock::new(rotations));
+        tracing::info!("[Account-Rotation] Global config initialized: max_rotations={}", rotations);
+    }
+}
+
+// ============================================================================
+// 全局每账号并发流上限配置存储
+// ============================================================================
+static GLOBAL_MAX_CONCURRENT_STREAMS: OnceLock<RwLock<usize>> = OnceLock::new();
+
+/// 单个账号同时进行的上游流数量上限 (默认 4，0 表示不限制)
+pub fn get_max_concurrent_streams_per_account() -> usize {
+    GLOBAL_MAX_CONCURRENT_STREAMS
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or_else(default_max_concurrent_streams_per_account)
+}
+
+pub fn update_max_concurrent_streams_per_account(max_streams: usize) {
+    if let Some(lock) = GLOBAL_MAX_CONCURRENT_STREAMS.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != max_streams {
+                *cfg = max_streams;
+                tracing::info!("[Stream-Limit] Global config updated: max_per_account={}", max_streams);
+            }
+        }
+    } else {
+        let _ = GLOBAL_MAX_CONCURRENT_STREAMS.set(RwLock::new(max_streams));
+        tracing::info!("[Stream-Limit] Global config initialized: max_per_account={}", max_streams);
+    }
+}
+
+// ============================================================================
+// 全局思考耗尽追问配置存储
+// ============================================================================
+static GLOBAL_THINKING_NUDGE: OnceLock<RwLock<bool>> = OnceLock::new();
+
+/// 上游只输出思考即以 MAX_TOKENS 结束时，是否追问一次最终答案 (默认 false)
+pub fn get_thinking_nudge_enabled() -> bool {
+    GLOBAL_THINKING_NUDGE
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or(false)
+}
+
+pub fn update_thinking_nudge(enabled: bool) {
+    if let Some(lock) = GLOBAL_THINKING_NUDGE.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != enabled {
+                *cfg = enabled;
+                tracing::info!("[Thinking-Nudge] Global config updated: enabled={}", enabled);
+            }
+        }
+    } else {
+        let _ = GLOBAL_THINKING_NUDGE.set(RwLock::new(enabled));
+        tracing::info!("[Thinking-Nudge] Global config initialized: enabled={}", enabled);
+    }
+}
+
+// ============================================================================
+// 全局毒消息隔离配置存储
+// ============================================================================
+static GLOBAL_POISON_QUARANTINE: OnceLock<RwLock<bool>> = OnceLock::new();
+
+/// 重试耗尽后的 400 是否二分探测问题历史消息 (会额外消耗上游请求，默认 false)
+pub fn get_poison_quarantine_enabled() -> bool {
+    GLOBAL_POISON_QUARANTINE
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or(false)
+}
+
+pub fn update_poison_quarantine(enabled: bool) {
+    if let Some(lock) = GLOBAL_POISON_QUARANTINE.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != enabled {
+                *cfg = enabled;
+                tracing::info!("[Poison-Quarantine] Global config updated: enabled={}", enabled);
+            }
+        }
+    } else {
+        let _ = GLOBAL_POISON_QUARANTINE.set(RwLock::new(enabled));
+        tracing::info!("[Poison-Quarantine] Global config initialized: enabled={}", enabled);
+    }
+}
+
+// ============================================================================
+// 全局流式中断续写配置存储
+// ============================================================================
+static GLOBAL_STREAM_RESUMPTION: OnceLock<RwLock<bool>> = OnceLock::new();
+
+/// 上游流在已转发内容后中断时，是否自动发起一次续写并拼接到同一条消息 (默认 false)
+pub fn get_stream_resumption_enabled() -> bool {
+    GLOBAL_STREAM_RESUMPTION
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or(false)
+}
+
+pub fn update_stream_resumption(enabled: bool) {
+    if let Some(lock) = GLOBAL_STREAM_RESUMPTION.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != enabled {
+                *cfg = enabled;
+                tracing::info!("[Stream-Resume] Global config updated: enabled={}", enabled);
+            }
+        }
+    } else {
+        let _ = GLOBAL_STREAM_RESUMPTION.set(RwLock::new(enabled));
+        tracing::info!("[Stream-Resume] Global config initialized: enabled={}", enabled);
+    }
+}
+
+// ============================================================================
+// 全局 JSON 递归清理深度上限配置存储
+// ============================================================================
+static GLOBAL_MAX_JSON_CLEAN_DEPTH: OnceLock<RwLock<usize>> = OnceLock::new();
+
+/// 递归 JSON 清理器 (schema 清洗、cache_control / undefined / 思考字段清理) 的最大深度，
+/// 超出后停止深入并告警，避免异常嵌套的工具输入导致栈溢出
+pub fn get_max_json_clean_depth() -> usize {
+    GLOBAL_MAX_JSON_CLEAN_DEPTH
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or_else(default_max_json_clean_depth)
+}
+
+pub fn update_max_json_clean_depth(max_depth: usize) {
+    if let Some(lock) = GLOBAL_MAX_JSON_CLEAN_DEPTH.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != max_depth {
+                *cfg = max_depth;
+                tracing::info!("[Json-Guard] Global config updated: max_depth={}", max_depth);
+            }
+        }
+    } else {
+        let _ = GLOBAL_MAX_JSON_CLEAN_DEPTH.set(RwLock::new(max_depth));
+        tracing::info!("[Json-Guard] Global config initialized: max_depth={}", max_depth);
+    }
+}
+
+// ============================================================================
+// 全局 token 预刷新提前量配置存储
+// ============================================================================
+static GLOBAL_TOKEN_REFRESH_AHEAD_SECS: OnceLock<RwLock<u64>> = OnceLock::new();
+
+/// 选中账号的 access_token 若在该窗口 (秒) 内到期，则在使用前先刷新，
+/// 避免长请求 / 流式请求中途遇到 401
+pub fn get_token_refresh_ahead_secs() -> u64 {
+    GLOBAL_TOKEN_REFRESH_AHEAD_SECS
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or_else(default_token_refresh_ahead_secs)
+}
+
+pub fn update_token_refresh_ahead_secs(secs: u64) {
+    if let Some(lock) = GLOBAL_TOKEN_REFRESH_AHEAD_SECS.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != secs {
+                *cfg = secs;
+                tracing::info!("[Token-Refresh] Global config updated: refresh_ahead_secs={}", secs);
+            }
+        }
+    } else {
+        let _ = GLOBAL_TOKEN_REFRESH_AHEAD_SECS.set(RwLock::new(secs));
+        tracing::info!("[Token-Refresh] Global config initialized: refresh_ahead_secs={}", secs);
+    }
+}
+
+// ============================================================================
+// 全局近期失败账号回避窗口配置存储
+// ============================================================================
+static GLOBAL_RECENT_FAILURE_WINDOW_SECS: OnceLock<RwLock<u64>> = OnceLock::new();
+
+/// 账号请求失败后的短期回避窗口 (秒)：窗口内选号优先跳过该账号 (0 = 关闭)。
+/// 与熔断 / 限流锁定相互独立，只影响候选顺序，不会阻止兜底使用
+pub fn get_recent_failure_window_secs() -> u64 {
+    GLOBAL_RECENT_FAILURE_WINDOW_SECS
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or_else(default_recent_failure_window_secs)
+}
+
+pub fn update_recent_failure_window_secs(secs: u64) {
+    if let Some(lock) = GLOBAL_RECENT_FAILURE_WINDOW_SECS.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != secs {
+                *cfg = secs;
+                tracing::info!("[Recent-Failure] Global config updated: window_secs={}", secs);
+            }
+        }
+    } else {
+        let _ = GLOBAL_RECENT_FAILURE_WINDOW_SECS.set(RwLock::new(secs));
+        tracing::info!("[Recent-Failure] Global config initialized: window_secs={}", secs);
+    }
+}
+
+// ============================================================================
+// 全局 usage.server_tool_use (web search 次数) 上报开关
+// ============================================================================
+static GLOBAL_REPORT_WEB_SEARCH_USAGE: OnceLock<RwLock<bool>> = OnceLock::new();
+
+/// 联网搜索 (grounding) 触发时是否在 Claude usage 中上报 server_tool_use.web_search_requests
+pub fn get_report_web_search_usage() -> bool {
+    GLOBAL_REPORT_WEB_SEARCH_USAGE
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or(true)
+}
+
+pub fn update_report_web_search_usage(enabled: bool) {
+    if let Some(lock) = GLOBAL_REPORT_WEB_SEARCH_USAGE.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != enabled {
+                *cfg = enabled;
+                tracing::info!("[Web-Search-Usage] Global config updated: enabled={}", enabled);
+            }
+        }
+    } else {
+        let _ = GLOBAL_REPORT_WEB_SEARCH_USAGE.set(RwLock::new(enabled));
+        tracing::info!("[Web-Search-Usage] Global config initialized: enabled={}", enabled);
+    }
+}
+
+// ============================================================================
+// 全局历史思考剥离配置存储
+// ============================================================================
+static GLOBAL_STRIP_HISTORICAL_THINKING_MODELS: OnceLock<RwLock<Vec<String>>> = OnceLock::new();
+
+/// 仅校验最后一个思考块的模型列表 (按子串匹配，忽略大小写)
+pub fn get_strip_historical_thinking_models() -> Vec<String> {
+    GLOBAL_STRIP_HISTORICAL_THINKING_MODELS
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| v.clone())
+        .unwrap_or_default()
+}
+
+/// 该模型是否需要剥离历史 assistant 消息中的思考内容
+pub fn should_strip_historical_thinking(model: &str) -> bool {
+    let model = model.to_lowercase();
+    get_strip_historical_thinking_models()
+        .iter()
+        .any(|pattern| !pattern.trim().is_empty() && model.contains(&pattern.trim().to_lowercase()))
+}
+
+pub fn update_strip_historical_thinking_models(models: Vec<String>) {
+    if let Some(lock) = GLOBAL_STRIP_HISTORICAL_THINKING_MODELS.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != models {
+                tracing::info!("[Thinking-Strip] Global config updated: models={:?}", models);
+                *cfg = models;
+            }
+        }
+    } else {
+        tracing::info!("[Thinking-Strip] Global config initialized: models={:?}", models);
+        let _ = GLOBAL_STRIP_HISTORICAL_THINKING_MODELS.set(RwLock::new(models));
+    }
+}
+
+// ============================================================================
+// 全局会话空闲回收 TTL 配置存储
+// ============================================================================
+static GLOBAL_SESSION_IDLE_TTL_SECS: OnceLock<RwLock<u64>> = OnceLock::new();
+
+/// 会话空闲多久后回收其所有会话级状态 (秒，默认 6 小时)
+pub fn get_session_idle_ttl_secs() -> u64 {
+    GLOBAL_SESSION_IDLE_TTL_SECS
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or_else(default_session_idle_ttl_secs)
+}
+
+pub fn update_session_idle_ttl_secs(ttl_secs: u64) {
+    if let Some(lock) = GLOBAL_SESSION_IDLE_TTL_SECS.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != ttl_secs {
+                *cfg = ttl_secs;
+                tracing::info!("[Session-Janitor] Global config updated: idle_ttl={}s", ttl_secs);
+            }
+        }
+    } else {
+        let _ = GLOBAL_SESSION_IDLE_TTL_SECS.set(RwLock::new(ttl_secs));
+        tracing::info!("[Session-Janitor] Global config initialized: idle_ttl={}s", ttl_secs);
+    }
+}
+
+// ============================================================================
+// 全局流式 delta 合并配置存储
+// ============================================================================
+static GLOBAL_DELTA_COALESCING: OnceLock<RwLock<DeltaCoalescingConfig>> = OnceLock::new();
+
+/// 获取全局流式 delta 合并配置 (监听配置档可单独覆盖)
+pub fn get_delta_coalescing_config() -> DeltaCoalescingConfig {
+    GLOBAL_DELTA_COALESCING
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|cfg| *cfg)
+        .unwrap_or_default()
+}
+
+/// 更新全局流式 delta 合并配置
+pub fn update_delta_coalescing_config(config: DeltaCoalescingConfig) {
+    if let Some(lock) = GLOBAL_DELTA_COALESCING.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != config {
+                *cfg = config;
+                tracing::info!("[Delta-Coalesce] Global config updated: {:?}", config);
+            }
+        }
+    } else {
+        let _ = GLOBAL_DELTA_COALESCING.set(RwLock::new(config));
+        tracing::info!("[Delta-Coalesce] Global config initialized: {:?}", config);
+    }
+}
+
+// ============================================================================
+// 全局 requestId 前缀配置存储
+// ============================================================================
+static GLOBAL_REQUEST_ID_PREFIX: OnceLock<RwLock<RequestIdPrefixConfig>> = OnceLock::new();
+
+/// 测试中修改 requestId 前缀的用例需持有此锁，避免并行测试互相覆盖
+#[cfg(test)]
+pub(crate) static REQUEST_ID_PREFIX_TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// 获取各协议的 requestId 前缀
+pub fn get_request_id_prefix_config() -> RequestIdPrefixConfig {
+    GLOBAL_REQUEST_ID_PREFIX
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|cfg| cfg.clone())
+        .unwrap_or_default()
+}
+
+/// 更新各协议的 requestId 前缀
+pub fn update_request_id_prefix_config(config: RequestIdPrefixConfig) {
+    if let Some(lock) = GLOBAL_REQUEST_ID_PREFIX.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != config {
+                tracing::info!("[Request-Id] Global config updated: {:?}", config);
+                *cfg = config;
+            }
+        }
+    } else {
+        tracing::info!("[Request-Id] Global config initialized: {:?}", config);
+        let _ = GLOBAL_REQUEST_ID_PREFIX.set(RwLock::new(config));
+    }
+}
+
+// ============================================================================
+// 全局保护性停止序列配置存储
+// ============================================================================
+static GLOBAL_PROTECTIVE_STOP_SEQUENCES: OnceLock<RwLock<Vec<String>>> = OnceLock::new();
+
+/// 始终注入的保护性停止序列 (防止模型幻觉出对话标记)
+pub fn get_protective_stop_sequences() -> Vec<String> {
+    GLOBAL_PROTECTIVE_STOP_SEQUENCES
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| v.clone())
+        .unwrap_or_else(default_protective_stop_sequences)
+}
+
+pub fn update_protective_stop_sequences(sequences: Vec<String>) {
+    if let Some(lock) = GLOBAL_PROTECTIVE_STOP_SEQUENCES.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != sequences {
+                tracing::info!("[Stop-Sequences] Global config updated: {:?}", sequences);
+                *cfg = sequences;
+            }
+        }
+    } else {
+        tracing::info!("[Stop-Sequences] Global config initialized: {:?}", sequences);
+        let _ = GLOBAL_PROTECTIVE_STOP_SEQUENCES.set(RwLock::new(sequences));
+    }
+}
+
+// ============================================================================
+// 全局流式脱敏规则存储
+// ============================================================================
+static GLOBAL_STREAM_REDACTIONS: OnceLock<RwLock<Vec<StreamRedactionRule>>> = OnceLock::new();
+
+/// 获取流式输出脱敏规则 (默认无规则)
+pub fn get_stream_redactions() -> Vec<StreamRedactionRule> {
+    GLOBAL_STREAM_REDACTIONS
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| v.clone())
+        .unwrap_or_default()
+}
+
+pub fn update_stream_redactions(rules: Vec<StreamRedactionRule>) {
+    if let Some(lock) = GLOBAL_STREAM_REDACTIONS.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != rules {
+                tracing::info!("[Stream-Redaction] Global config updated: {} rule(s)", rules.len());
+                *cfg = rules;
+            }
+        }
+    } else {
+        tracing::info!("[Stream-Redaction] Global config initialized: {} rule(s)", rules.len());
+        let _ = GLOBAL_STREAM_REDACTIONS.set(RwLock::new(rules));
+    }
+}
+
+// ============================================================================
+// 全局自定义客户端配置档存储
+// ============================================================================
+static GLOBAL_CLIENT_PROFILES: OnceLock<RwLock<Vec<ClientProfileConfig>>> = OnceLock::new();
+
+/// 配置中定义的客户端配置档 (供 x-abv-client-profile 按名称解析)
+pub fn get_client_profiles() -> Vec<ClientProfileConfig> {
+    GLOBAL_CLIENT_PROFILES
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| v.clone())
+        .unwrap_or_default()
+}
+
+pub fn update_client_profiles(profiles: Vec<ClientProfileConfig>) {
+    if let Some(lock) = GLOBAL_CLIENT_PROFILES.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != profiles {
+                tracing::info!("[Client-Profile] Global config updated: {} custom profile(s)", profiles.len());
+                *cfg = profiles;
+            }
+        }
+    } else {
+        tracing::info!("[Client-Profile] Global config initialized: {} custom profile(s)", profiles.len());
+        let _ = GLOBAL_CLIENT_PROFILES.set(RwLock::new(profiles));
+    }
+}
+
+// ============================================================================
+// 全局功能开关存储
+// ============================================================================
+static GLOBAL_FEATURE_FLAGS: OnceLock<RwLock<Vec<FeatureFlagConfig>>> = OnceLock::new();
+
+/// 配置中显式设置的功能开关 (未出现的内置开关使用默认值，见 proxy::flags)
+pub fn get_feature_flags() -> Vec<FeatureFlagConfig> {
+    GLOBAL_FEATURE_FLAGS
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| v.clone())
+        .unwrap_or_default()
+}
+
+pub fn update_feature_flags(flags: Vec<FeatureFlagConfig>) {
+    if let Some(lock) = GLOBAL_FEATURE_FLAGS.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != flags {
+                tracing::info!("[Feature-Flags] Global config updated: {:?}", flags);
+                *cfg = flags;
+            }
+        }
+    } else {
+        tracing::info!("[Feature-Flags] Global config initialized: {:?}", flags);
+        let _ = GLOBAL_FEATURE_FLAGS.set(RwLock::new(flags));
+    }
+}
+
+// ============================================================================
+// 全局 API Key -> 账号分组映射存储
+// ============================================================================
+static GLOBAL_API_KEY_GROUPS: OnceLock<RwLock<HashMap<String, String>>> = OnceLock::new();
+
+/// API Key (含 User Token) 到账号分组的映射 (见 proxy::account_groups)
+pub fn get_api_key_groups() -> HashMap<String, String> {
+    GLOBAL_API_KEY_GROUPS
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| v.clone())
+        .unwrap_or_default()
+}
+
+pub fn update_api_key_groups(groups: HashMap<String, String>) {
+    if let Some(lock) = GLOBAL_API_KEY_GROUPS.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != groups {
+                tracing::info!("[Account-Groups] Global config updated: {} API key mapping(s)", groups.len());
+                *cfg = groups;
+            }
+        }
+    } else {
+        tracing::info!("[Account-Groups] Global config initialized: {} API key mapping(s)", groups.len());
+        let _ = GLOBAL_API_KEY_GROUPS.set(RwLock::new(groups));
+    }
+}
+
+// ============================================================================
+// 全局首字延迟 SLO 配置存储
+// ============================================================================
+static GLOBAL_LATENCY_SLO_CONFIG: OnceLock<RwLock<LatencySloConfig>> = OnceLock::new();
+
+/// 获取当前首字延迟 SLO 配置
+pub fn get_latency_slo_config() -> LatencySloConfig {
+    GLOBAL_LATENCY_SLO_CONFIG
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|cfg| *cfg)
+        .unwrap_or_default()
+}
+
+/// 更新全局首字延迟 SLO 配置
+pub fn update_latency_slo_config(config: LatencySloConfig) {
+    if let Some(lock) = GLOBAL_LATENCY_SLO_CONFIG.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != config {
+                *cfg = config;
+                tracing::info!("[Latency-SLO] Global config updated: {:?}", config);
+            }
+        }
+    } else {
+        let _ = GLOBAL_LATENCY_SLO_CONFIG.set(RwLock::new(config));
+        tracing::info!("[Latency-SLO] Global config initialized: {:?}", config);
+    }
+}
+
+// ============================================================================
+// 全局新账号预热策略配置存储
+// ============================================================================
+static GLOBAL_ACCOUNT_WARMUP_CONFIG: OnceLock<RwLock<AccountWarmupConfig>> = OnceLock::new();
+
+/// 获取当前新账号预热策略
+pub fn get_account_warmup_config() -> AccountWarmupConfig {
+    GLOBAL_ACCOUNT_WARMUP_CONFIG
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|cfg| cfg.clone())
+        .unwrap_or_default()
+}
+
+/// 更新全局新账号预热策略
+pub fn update_account_warmup_config(config: AccountWarmupConfig) {
+    if let Some(lock) = GLOBAL_ACCOUNT_WARMUP_CONFIG.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != config {
+                tracing::info!("[Account-Warmup] Global config updated: {:?}", config);
+                *cfg = config;
+            }
+        }
+    } else {
+        tracing::info!("[Account-Warmup] Global config initialized: {:?}", config);
+        let _ = GLOBAL_ACCOUNT_WARMUP_CONFIG.set(RwLock::new(config));
+    }
+}
+
+// ============================================================================
+// 全局历史 assistant 消息占位思考块注入配置存储
+// ============================================================================
+static GLOBAL_DUMMY_THOUGHT_CONFIG: OnceLock<RwLock<DummyThoughtConfig>> = OnceLock::new();
+
+/// 获取当前占位思考块注入配置
+pub fn get_dummy_thought_config() -> DummyThoughtConfig {
+    GLOBAL_DUMMY_THOUGHT_CONFIG
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|cfg| cfg.clone())
+        .unwrap_or_default()
+}
+
+/// 更新全局占位思考块注入配置
+pub fn update_dummy_thought_config(config: DummyThoughtConfig) {
+    if let Some(lock) = GLOBAL_DUMMY_THOUGHT_CONFIG.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != config {
+                tracing::info!("[Dummy-Thought] Global config updated: {:?}", config);
+                *cfg = config;
+            }
+        }
+    } else {
+        tracing::info!("[Dummy-Thought] Global config initialized: {:?}", config);
+        let _ = GLOBAL_DUMMY_THOUGHT_CONFIG.set(RwLock::new(config));
+    }
+}
+
+// ============================================================================
+// 全局重复任务文本去重配置存储
+// ============================================================================
+static GLOBAL_TASK_ECHO_DEDUP_CONFIG: OnceLock<RwLock<TaskEchoDedupConfig>> = OnceLock::new();
+
+/// 获取当前重复任务文本去重配置
+pub fn get_task_echo_dedup_config() -> TaskEchoDedupConfig {
+    GLOBAL_TASK_ECHO_DEDUP_CONFIG
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|cfg| *cfg)
+        .unwrap_or_default()
+}
+
+/// 更新全局重复任务文本去重配置
+pub fn update_task_echo_dedup_config(config: TaskEchoDedupConfig) {
+    if let Some(lock) = GLOBAL_TASK_ECHO_DEDUP_CONFIG.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != config {
+                tracing::info!("[Task-Echo-Dedup] Global config updated: {:?}", config);
+                *cfg = config;
+            }
+        }
+    } else {
+        tracing::info!("[Task-Echo-Dedup] Global config initialized: {:?}", config);
+        let _ = GLOBAL_TASK_ECHO_DEDUP_CONFIG.set(RwLock::new(config));
+    }
+}
+
+// ============================================================================
+// 全局工具数量上限配置存储
+// ============================================================================
+static GLOBAL_TOOL_LIMIT_CONFIG: OnceLock<RwLock<ToolLimitConfig>> = OnceLock::new();
+
+/// 获取当前工具数量上限配置
+pub fn get_tool_limit_config() -> ToolLimitConfig {
+    GLOBAL_TOOL_LIMIT_CONFIG
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|cfg| cfg.clone())
+        .unwrap_or_default()
+}
+
+/// 更新全局工具数量上限配置
+pub fn update_tool_limit_config(config: ToolLimitConfig) {
+    if let Some(lock) = GLOBAL_TOOL_LIMIT_CONFIG.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != config {
+                *cfg = config.clone();
+                tracing::info!(
+                    "[Tool-Limit] Global config updated: max_tools={:?}, on_exceed={:?}",
+                    config.max_tools,
+                    config.on_exceed
+                );
+            }
+        }
+    } else {
+        let _ = GLOBAL_TOOL_LIMIT_CONFIG.set(RwLock::new(config.clone()));
+        tracing::info!(
+            "[Tool-Limit] Global config initialized: max_tools={:?}, on_exceed={:?}",
+            config.max_tools,
+            config.on_exceed
+        );
+    }
+}
+
+// ============================================================================
+// 全局信封字段清洗策略
+// ============================================================================
+static GLOBAL_ENVELOPE_SANITIZE_ACTION: OnceLock<RwLock<EnvelopeSanitizeAction>> = OnceLock::new();
+
+/// 获取客户端字段 (metadata.user_id 等) 写入信封时的违规处理方式
+pub fn get_envelope_sanitize_action() -> EnvelopeSanitizeAction {
+    GLOBAL_ENVELOPE_SANITIZE_ACTION
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|v| *v)
+        .unwrap_or_default()
+}
+
+pub fn update_envelope_sanitize_action(action: EnvelopeSanitizeAction) {
+    if let Some(lock) = GLOBAL_ENVELOPE_SANITIZE_ACTION.get() {
+        if let Ok(mut cfg) = lock.write() {
+            if *cfg != action {
+                *cfg = action;
+                tracing::info!("[Envelope] Global config updated: sanitize_action={:?}", action);
+            }
+        }
+    } else {
+        let _ = GLOBAL_ENVELOPE_SANITIZE_ACTION.set(RwLock::new(action));
+        tracing::info!("[Envelope] Global config initialized: sanitize_action={:?}", action);
+    }
+}
+
 /// 全局系统提示词配置
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GlobalSystemPromptConfig {
@@ -211,183 +1088,734 @@ pub struct ZaiMcpConfig {
     pub vision_enabled: bool,
 }
 
-impl Default for ZaiMcpConfig {
+impl Default for ZaiMcpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            web_search_enabled: false,
+            web_reader_enabled: false,
+            vision_enabled: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZaiConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_zai_base_url")]
+    pub base_url: String,
+    #[serde(default)]
+    pub api_key: String,
+    #[serde(default)]
+    pub dispatch_mode: ZaiDispatchMode,
+    /// Optional per-model mapping overrides for Anthropic/Claude model ids.
+    /// Key: incoming `model` string, Value: upstream z.ai model id (e.g. `glm-4.7`).
+    #[serde(default)]
+    pub model_mapping: HashMap<String, String>,
+    #[serde(default)]
+    pub models: ZaiModelDefaults,
+    #[serde(default)]
+    pub mcp: ZaiMcpConfig,
+}
+
+impl Default for ZaiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            base_url: default_zai_base_url(),
+            api_key: String::new(),
+            dispatch_mode: ZaiDispatchMode::Off,
+            model_mapping: HashMap::new(),
+            models: ZaiModelDefaults::default(),
+            mcp: ZaiMcpConfig::default(),
+        }
+    }
+}
+
+/// 实验性功能配置 (Feature Flags)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExperimentalConfig {
+    /// 启用双层签名缓存 (Signature Cache)
+    #[serde(default = "default_true")]
+    pub enable_signature_cache: bool,
+
+    /// 启用工具循环自动恢复 (Tool Loop Recovery)
+    #[serde(default = "default_true")]
+    pub enable_tool_loop_recovery: bool,
+
+    /// 启用跨模型兼容性检查 (Cross-Model Checks)
+    #[serde(default = "default_true")]
+    pub enable_cross_model_checks: bool,
+
+    /// 启用上下文用量缩放 (Context Usage Scaling)
+    /// 激进模式: 缩放用量并激活自动压缩以突破 200k 限制
+    /// 默认关闭以保持透明度,让客户端能触发原生压缩指令
+    #[serde(default = "default_false")]
+    pub enable_usage_scaling: bool,
+
+    /// 上下文压缩阈值 L1 (Tool Trimming)
+    #[serde(default = "default_threshold_l1")]
+    pub context_compression_threshold_l1: f32,
+
+    /// 上下文压缩阈值 L2 (Thinking Compression)
+    #[serde(default = "default_threshold_l2")]
+    pub context_compression_threshold_l2: f32,
+
+    /// 上下文压缩阈值 L3 (Fork + Summary)
+    #[serde(default = "default_threshold_l3")]
+    pub context_compression_threshold_l3: f32,
+
+    /// 延迟发送 message_start (Deferred Message Start)
+    /// 直到收到首个真实内容或 finishReason 才发送，避免空流/立即报错的流产生孤立的 message_start
+    /// 默认关闭 (会改变流式事件时序，需显式开启)
+    #[serde(default)]
+    pub defer_message_start: bool,
+}
+
+impl Default for ExperimentalConfig {
+    fn default() -> Self {
+        Self {
+            enable_signature_cache: true,
+            enable_tool_loop_recovery: true,
+            enable_cross_model_checks: true,
+            enable_usage_scaling: false, // 默认关闭,回归透明模式
+            context_compression_threshold_l1: 0.4,
+            context_compression_threshold_l2: 0.55,
+            context_compression_threshold_l3: 0.7,
+            defer_message_start: false,
+        }
+    }
+}
+
+fn default_threshold_l1() -> f32 {
+    0.4
+}
+fn default_threshold_l2() -> f32 {
+    0.55
+}
+fn default_threshold_l3() -> f32 {
+    0.7
+}
+
+/// Thinking Budget 模式
+/// 控制如何处理调用方传入的 thinking_budget 参数
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ThinkingBudgetMode {
+    /// 自动限制：对特定模型（Flash/Thinking）应用 24576 上限
+    Auto,
+    /// 透传：完全使用调用方传入的值，不做任何修改
+    Passthrough,
+    /// 自定义：使用用户设定的固定值覆盖所有请求
+    Custom,
+    /// 自适应：使用 effort 参数控制思考强度 (Claude 4.6+)
+    Adaptive,
+}
+
+impl Default for ThinkingBudgetMode {
+    fn default() -> Self {
+        Self::Auto
+    }
+}
+
+/// Thinking Budget 配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThinkingBudgetConfig {
+    /// 模式选择
+    #[serde(default)]
+    pub mode: ThinkingBudgetMode,
+    /// 自定义固定值（仅在 mode=Custom 时生效）
+    #[serde(default = "default_thinking_budget_custom_value")]
+    pub custom_value: u32,
+    /// 思考强度 (仅在 mode=Adaptive 时生效) : low, medium, high
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effort: Option<String>,
+    /// [NEW] effort -> thinkingBudget 映射 (客户端开启 thinking 但未显式指定 budget_tokens 时使用)
+    #[serde(default)]
+    pub effort_budgets: EffortBudgetMap,
+    /// [NEW] 客户端未指定 budget_tokens 且无法从 effort 推导时的默认预算 (Claude / OpenAI 协议共用)
+    /// 未配置时沿用各协议原有默认值 (Claude 16000，OpenAI 24576)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_thinking_budget: Option<u32>,
+    /// [NEW] 开启 thinking 时的最小预算，低于该值 (如 0) 的预算会被抬升 (0 表示不限制)
+    #[serde(default = "default_min_thinking_budget")]
+    pub min_thinking_budget: u32,
+}
+
+impl Default for ThinkingBudgetConfig {
+    fn default() -> Self {
+        Self {
+            mode: ThinkingBudgetMode::Auto,
+            custom_value: default_thinking_budget_custom_value(),
+            effort: None,
+            effort_budgets: EffortBudgetMap::default(),
+            default_thinking_budget: None,
+            min_thinking_budget: default_min_thinking_budget(),
+        }
+    }
+}
+
+impl ThinkingBudgetConfig {
+    /// 将非负预算抬升到最小值；负数 (如 -1 动态预算) 保持不变
+    pub fn apply_budget_floor(&self, budget: i64) -> i64 {
+        let floor = self.min_thinking_budget as i64;
+        if budget >= 0 && budget < floor {
+            tracing::debug!("[Thinking-Budget] Raising thinking budget {} to floor {}", budget, floor);
+            floor
+        } else {
+            budget
+        }
+    }
+}
+
+fn default_thinking_budget_custom_value() -> u32 {
+    24576
+}
+
+fn default_min_thinking_budget() -> u32 {
+    256
+}
+
+/// effort 等级到 thinking budget 的映射
+/// 默认值按比例分布在 Gemini 的 24576 上限以内
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EffortBudgetMap {
+    #[serde(default = "default_effort_budget_low")]
+    pub low: u32,
+    #[serde(default = "default_effort_budget_medium")]
+    pub medium: u32,
+    #[serde(default = "default_effort_budget_high")]
+    pub high: u32,
+}
+
+impl Default for EffortBudgetMap {
+    fn default() -> Self {
+        Self {
+            low: default_effort_budget_low(),
+            medium: default_effort_budget_medium(),
+            high: default_effort_budget_high(),
+        }
+    }
+}
+
+impl EffortBudgetMap {
+    /// 根据 effort 字符串查找对应预算 ("max" 视为 "high")，未知等级返回 None
+    pub fn budget_for(&self, effort: &str) -> Option<u32> {
+        match effort.to_lowercase().as_str() {
+            "low" => Some(self.low),
+            "medium" => Some(self.medium),
+            "high" | "max" => Some(self.high),
+            _ => None,
+        }
+    }
+}
+
+fn default_effort_budget_low() -> u32 {
+    4096
+}
+
+fn default_effort_budget_medium() -> u32 {
+    12288
+}
+
+fn default_effort_budget_high() -> u32 {
+    24576
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_false() -> bool {
+    false
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebugLoggingConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub output_dir: Option<String>,
+    /// [NEW] 抓包文件静态加密 (默认开启；密钥不可用时拒绝写入明文)
+    #[serde(default = "default_true")]
+    pub encrypt_at_rest: bool,
+    /// [NEW] 允许通过 URL 查询参数覆盖 thinking / temperature / top_p / safety / model (默认关闭)
+    #[serde(default)]
+    pub allow_query_overrides: bool,
+    /// [NEW] 录制所有 Claude 流式请求的上游原始 SSE (签名脱敏)，可离线回放 (默认关闭)
+    #[serde(default)]
+    pub record_streams: bool,
+    /// [NEW] x-abv-include-raw 附带的原始 Gemini 响应 (_abv_raw_gemini) 序列化后的大小上限 (字节)
+    #[serde(default = "default_raw_passthrough_max_bytes")]
+    pub raw_passthrough_max_bytes: usize,
+}
+
+fn default_raw_passthrough_max_bytes() -> usize {
+    256 * 1024
+}
+
+impl Default for DebugLoggingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            output_dir: None,
+            encrypt_at_rest: true,
+            allow_query_overrides: false,
+            record_streams: false,
+            raw_passthrough_max_bytes: default_raw_passthrough_max_bytes(),
+        }
+    }
+}
+
+/// 工具数量超出上限时的处理方式
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolLimitAction {
+    /// 截断：仅保留前 N 个工具声明并记录警告
+    Truncate,
+    /// 报错：直接以 400 拒绝请求
+    Error,
+}
+
+impl Default for ToolLimitAction {
+    fn default() -> Self {
+        Self::Truncate
+    }
+}
+
+/// 客户端字段写入 v1internal 信封时的违规处理方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvelopeSanitizeAction {
+    /// 截断超长值；疑似结构注入的值直接丢弃 (默认)
+    Truncate,
+    /// 以 400 拒绝请求
+    Reject,
+}
+
+impl Default for EnvelopeSanitizeAction {
+    fn default() -> Self {
+        Self::Truncate
+    }
+}
+
+/// 工具数量上限配置
+/// 部分 Gemini 模型在工具数量过多时会拒绝请求或效果明显下降 (Claude Code 等客户端常携带 30+ 工具)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct ToolLimitConfig {
+    /// 最大 functionDeclarations 数量 (None 表示不限制)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tools: Option<usize>,
+    /// 超出上限时的处理方式
+    #[serde(default)]
+    pub on_exceed: ToolLimitAction,
+}
+
+/// 流式首字延迟 (TTFB) SLO 监控配置
+/// 按模型统计最近 window_size 个流式请求的 p90 首字延迟，超过阈值时发出通知
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LatencySloConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// p90 阈值 (毫秒)
+    #[serde(default = "default_latency_slo_threshold_ms")]
+    pub threshold_ms: u64,
+    /// 滚动窗口大小 (请求数)
+    #[serde(default = "default_latency_slo_window_size")]
+    pub window_size: usize,
+    /// 同一模型两次降级告警的最小间隔 (秒)
+    #[serde(default = "default_latency_slo_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+fn default_max_account_rotations() -> usize {
+    2
+}
+
+pub(crate) fn default_max_concurrent_streams_per_account() -> usize {
+    4
+}
+
+fn default_protective_stop_sequences() -> Vec<String> {
+    vec![
+        "<|user|>".to_string(),
+        "<|end_of_turn|>".to_string(),
+        "\n\nHuman:".to_string(),
+    ]
+}
+
+fn default_max_json_clean_depth() -> usize {
+    64
+}
+
+fn default_token_refresh_ahead_secs() -> u64 {
+    300
+}
+
+fn default_recent_failure_window_secs() -> u64 {
+    10
+}
+
+fn default_session_idle_ttl_secs() -> u64 {
+    6 * 60 * 60
+}
+
+fn default_latency_slo_threshold_ms() -> u64 {
+    8000
+}
+
+fn default_latency_slo_window_size() -> usize {
+    20
+}
+
+fn default_latency_slo_cooldown_secs() -> u64 {
+    600
+}
+
+impl Default for LatencySloConfig {
     fn default() -> Self {
         Self {
-            enabled: false,
-            web_search_enabled: false,
-            web_reader_enabled: false,
-            vision_enabled: false,
+            enabled: true,
+            threshold_ms: default_latency_slo_threshold_ms(),
+            window_size: default_latency_slo_window_size(),
+            cooldown_secs: default_latency_slo_cooldown_secs(),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ZaiConfig {
+/// 新账号预热策略
+/// 账号添加后 hours 小时内：只允许服务成本档位不高于 max_cost_tier 的模型组 (见 model_mapping 成本档位表)，
+/// 并发流上限降为常规上限的 concurrency_percent%，且不作为长会话的粘性绑定账号。窗口结束后自动解除。
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AccountWarmupConfig {
     #[serde(default)]
     pub enabled: bool,
-    #[serde(default = "default_zai_base_url")]
-    pub base_url: String,
-    #[serde(default)]
-    pub api_key: String,
-    #[serde(default)]
-    pub dispatch_mode: ZaiDispatchMode,
-    /// Optional per-model mapping overrides for Anthropic/Claude model ids.
-    /// Key: incoming `model` string, Value: upstream z.ai model id (e.g. `glm-4.7`).
-    #[serde(default)]
-    pub model_mapping: HashMap<String, String>,
-    #[serde(default)]
-    pub models: ZaiModelDefaults,
-    #[serde(default)]
-    pub mcp: ZaiMcpConfig,
+    /// 预热时长 (小时)
+    #[serde(default = "default_account_warmup_hours")]
+    pub hours: u64,
+    /// 预热期允许的最高模型成本档位 (默认 low，即仅 flash 组)
+    #[serde(default = "default_account_warmup_max_cost_tier")]
+    pub max_cost_tier: crate::proxy::common::model_mapping::ModelCostTier,
+    /// 预热期并发流上限占常规上限的百分比 (至少保留 1 路；常规上限为 0 即不限制时按默认上限计算)
+    #[serde(default = "default_account_warmup_concurrency_percent")]
+    pub concurrency_percent: u8,
 }
 
-impl Default for ZaiConfig {
+fn default_account_warmup_hours() -> u64 {
+    48
+}
+
+fn default_account_warmup_max_cost_tier() -> crate::proxy::common::model_mapping::ModelCostTier {
+    crate::proxy::common::model_mapping::ModelCostTier::Low
+}
+
+fn default_account_warmup_concurrency_percent() -> u8 {
+    25
+}
+
+impl Default for AccountWarmupConfig {
     fn default() -> Self {
         Self {
             enabled: false,
-            base_url: default_zai_base_url(),
-            api_key: String::new(),
-            dispatch_mode: ZaiDispatchMode::Off,
-            model_mapping: HashMap::new(),
-            models: ZaiModelDefaults::default(),
-            mcp: ZaiMcpConfig::default(),
+            hours: default_account_warmup_hours(),
+            max_cost_tier: default_account_warmup_max_cost_tier(),
+            concurrency_percent: default_account_warmup_concurrency_percent(),
         }
     }
 }
 
-/// 实验性功能配置 (Feature Flags)
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ExperimentalConfig {
-    /// 启用双层签名缓存 (Signature Cache)
-    #[serde(default = "default_true")]
-    pub enable_signature_cache: bool,
-
-    /// 启用工具循环自动恢复 (Tool Loop Recovery)
-    #[serde(default = "default_true")]
-    pub enable_tool_loop_recovery: bool,
+/// 历史 assistant 消息占位思考块文本
+/// 开启 thinking 时，为缺少思考块的历史 assistant 消息补一个占位思考块。
+/// 是否注入由 dummy_thought_injection 功能开关控制 (默认关闭: Vertex AI 会拒绝没有有效签名的思考块)，
+/// 这里只配置占位文本。
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DummyThoughtConfig {
+    /// 占位思考块的文本
+    #[serde(default = "default_dummy_thought_text")]
+    pub text: String,
+}
 
-    /// 启用跨模型兼容性检查 (Cross-Model Checks)
-    #[serde(default = "default_true")]
-    pub enable_cross_model_checks: bool,
+fn default_dummy_thought_text() -> String {
+    "Thinking...".to_string()
+}
 
-    /// 启用上下文用量缩放 (Context Usage Scaling)
-    /// 激进模式: 缩放用量并激活自动压缩以突破 200k 限制
-    /// 默认关闭以保持透明度,让客户端能触发原生压缩指令
-    #[serde(default = "default_false")]
-    pub enable_usage_scaling: bool,
+impl Default for DummyThoughtConfig {
+    fn default() -> Self {
+        Self {
+            text: default_dummy_thought_text(),
+        }
+    }
+}
 
-    /// 上下文压缩阈值 L1 (Tool Trimming)
-    #[serde(default = "default_threshold_l1")]
-    pub context_compression_threshold_l1: f32,
+impl DummyThoughtConfig {
+    /// 注入使用的占位文本 (空文本回退到默认值)
+    pub fn injection_text(&self) -> String {
+        let text = self.text.trim();
+        if text.is_empty() {
+            default_dummy_thought_text()
+        } else {
+            text.to_string()
+        }
+    }
+}
 
-    /// 上下文压缩阈值 L2 (Thinking Compression)
-    #[serde(default = "default_threshold_l2")]
-    pub context_compression_threshold_l2: f32,
+/// 重复任务文本去重
+/// Claude Code 在工具结果之后会重复发送上一轮的任务描述，开启时丢弃与上一轮任务文本完全一致
+/// (忽略空白) 的 user 文本。短文本 (如 "continue") 常被用户有意重复，低于 min_length 时保留。
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TaskEchoDedupConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// 参与去重的最小文本长度 (去除空白后的字符数)
+    #[serde(default = "default_task_echo_min_length")]
+    pub min_length: usize,
+}
 
-    /// 上下文压缩阈值 L3 (Fork + Summary)
-    #[serde(default = "default_threshold_l3")]
-    pub context_compression_threshold_l3: f32,
+fn default_task_echo_min_length() -> usize {
+    20
 }
 
-impl Default for ExperimentalConfig {
+impl Default for TaskEchoDedupConfig {
     fn default() -> Self {
         Self {
-            enable_signature_cache: true,
-            enable_tool_loop_recovery: true,
-            enable_cross_model_checks: true,
-            enable_usage_scaling: false, // 默认关闭,回归透明模式
-            context_compression_threshold_l1: 0.4,
-            context_compression_threshold_l2: 0.55,
-            context_compression_threshold_l3: 0.7,
+            enabled: true,
+            min_length: default_task_echo_min_length(),
         }
     }
 }
 
-fn default_threshold_l1() -> f32 {
-    0.4
+impl TaskEchoDedupConfig {
+    /// 当前文本是否为上一轮任务文本的回显 (两者均为去除空白后的文本)
+    pub fn is_echo(&self, current_normalized: &str, last_task_normalized: &str) -> bool {
+        self.enabled
+            && current_normalized.chars().count() >= self.min_length.max(1)
+            && current_normalized == last_task_normalized
+    }
 }
-fn default_threshold_l2() -> f32 {
-    0.55
+
+/// v1internal 请求体 requestId 前缀 (按协议，生成格式 `<prefix>-<uuid>`)
+/// 上游若校验前缀，可在此调整；非法值回退到默认前缀
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RequestIdPrefixConfig {
+    #[serde(default = "default_claude_request_id_prefix")]
+    pub claude: String,
+    #[serde(default = "default_openai_request_id_prefix")]
+    pub openai: String,
+    #[serde(default = "default_gemini_request_id_prefix")]
+    pub gemini: String,
 }
-fn default_threshold_l3() -> f32 {
-    0.7
+
+fn default_claude_request_id_prefix() -> String {
+    "agent".to_string()
 }
 
-/// Thinking Budget 模式
-/// 控制如何处理调用方传入的 thinking_budget 参数
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "snake_case")]
-pub enum ThinkingBudgetMode {
-    /// 自动限制：对特定模型（Flash/Thinking）应用 24576 上限
-    Auto,
-    /// 透传：完全使用调用方传入的值，不做任何修改
-    Passthrough,
-    /// 自定义：使用用户设定的固定值覆盖所有请求
-    Custom,
-    /// 自适应：使用 effort 参数控制思考强度 (Claude 4.6+)
-    Adaptive,
+fn default_openai_request_id_prefix() -> String {
+    "openai".to_string()
 }
 
-impl Default for ThinkingBudgetMode {
+fn default_gemini_request_id_prefix() -> String {
+    "agent".to_string()
+}
+
+impl Default for RequestIdPrefixConfig {
     fn default() -> Self {
-        Self::Auto
+        Self {
+            claude: default_claude_request_id_prefix(),
+            openai: default_openai_request_id_prefix(),
+            gemini: default_gemini_request_id_prefix(),
+        }
     }
 }
 
-/// Thinking Budget 配置
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ThinkingBudgetConfig {
-    /// 模式选择
+/// 流式文本 delta 合并配置
+/// 缓冲相邻的细碎文本 / 思考 delta，按字节或时间阈值合并为一个 SSE 事件发送
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DeltaCoalescingConfig {
     #[serde(default)]
-    pub mode: ThinkingBudgetMode,
-    /// 自定义固定值（仅在 mode=Custom 时生效）
-    #[serde(default = "default_thinking_budget_custom_value")]
-    pub custom_value: u32,
-    /// 思考强度 (仅在 mode=Adaptive 时生效) : low, medium, high
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub effort: Option<String>,
+    pub enabled: bool,
+    /// 可见文本缓冲达到该字节数时立即发送
+    #[serde(default = "default_coalesce_max_bytes")]
+    pub max_bytes: usize,
+    /// 思考内容缓冲达到该字节数时立即发送 (思考内容对延迟不敏感，可使用更大的阈值)
+    #[serde(default = "default_coalesce_thinking_max_bytes")]
+    pub thinking_max_bytes: usize,
+    /// 缓冲内容最长等待时间 (毫秒)
+    #[serde(default = "default_coalesce_max_delay_ms")]
+    pub max_delay_ms: u64,
 }
 
-impl Default for ThinkingBudgetConfig {
+fn default_coalesce_max_bytes() -> usize {
+    64
+}
+
+fn default_coalesce_thinking_max_bytes() -> usize {
+    256
+}
+
+fn default_coalesce_max_delay_ms() -> u64 {
+    30
+}
+
+impl DeltaCoalescingConfig {
+    /// 叠加客户端适配器要求的最小 text_delta 长度 (如 Zed，0 表示无要求)
+    /// 未开启合并时以该长度作为可见文本阈值开启；已开启时阈值不低于该长度
+    pub fn with_min_text_delta(mut self, min_len: usize) -> Self {
+        if min_len == 0 {
+            return self;
+        }
+        if self.enabled {
+            self.max_bytes = self.max_bytes.max(min_len);
+        } else {
+            self.enabled = true;
+            self.max_bytes = min_len;
+        }
+        self
+    }
+}
+
+impl Default for DeltaCoalescingConfig {
     fn default() -> Self {
         Self {
-            mode: ThinkingBudgetMode::Auto,
-            custom_value: default_thinking_budget_custom_value(),
-            effort: None,
+            enabled: false,
+            max_bytes: default_coalesce_max_bytes(),
+            thinking_max_bytes: default_coalesce_thinking_max_bytes(),
+            max_delay_ms: default_coalesce_max_delay_ms(),
         }
     }
 }
 
-fn default_thinking_budget_custom_value() -> u32 {
-    24576
+/// 流式脱敏规则作用的 delta 类别
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactionClass {
+    /// 思考内容 (thinking_delta)
+    Thinking,
+    /// 用户可见文本 (text_delta)
+    Visible,
+    /// 工具参数 (input_json_delta)
+    ToolInput,
 }
 
-fn default_true() -> bool {
-    true
+/// 流式输出脱敏规则 (正则替换，按 delta 类别生效)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StreamRedactionRule {
+    pub pattern: String,
+    #[serde(default = "default_redaction_replacement")]
+    pub replacement: String,
+    /// 生效的 delta 类别，默认只处理用户可见文本 (不处理通常很长的思考内容)
+    #[serde(default = "default_redaction_classes")]
+    pub classes: Vec<RedactionClass>,
 }
 
-fn default_false() -> bool {
-    false
+fn default_redaction_replacement() -> String {
+    "[REDACTED]".to_string()
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DebugLoggingConfig {
-    #[serde(default)]
-    pub enabled: bool,
-    #[serde(default)]
-    pub output_dir: Option<String>,
+fn default_redaction_classes() -> Vec<RedactionClass> {
+    vec![RedactionClass::Visible]
 }
 
-impl Default for DebugLoggingConfig {
+/// 联网搜索结果 (搜索词 + 来源) 的文本渲染样式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CitationStyle {
+    /// 分隔线 + 搜索词 + 编号来源列表 (默认)
+    #[default]
+    Markdown,
+    /// Markdown 脚注定义 `[^1]: [title](url)`
+    Footnotes,
+    /// 单行内联链接 `Sources: [title](url), ...`
+    InlineLinks,
+    /// 不输出
+    Off,
+}
+
+/// Antigravity 身份指令注入模式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IdentityInjectionMode {
+    /// 用户未提供身份时自动注入 (默认行为)
+    Auto,
+    /// 从不注入
+    Off,
+}
+
+impl Default for IdentityInjectionMode {
     fn default() -> Self {
-        Self {
-            enabled: false,
-            output_dir: None,
-        }
+        Self::Auto
     }
 }
 
+/// 监听配置档 (Listener Profile)
+/// 在独立端口上提供反代服务，拥有各自的默认行为，但共享账号池/TokenManager/统计
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ListenerProfile {
+    /// 配置档名称 (唯一标识)
+    pub name: String,
+    /// 监听端口 (与主服务使用相同的绑定地址)
+    pub port: u16,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// 客户端未指定 thinking 时的默认值 (None = 按模型默认)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_thinking: Option<bool>,
+    /// 安全过滤阈值: OFF / LOW / MEDIUM / HIGH / NONE (None = 使用 GEMINI_SAFETY_THRESHOLD)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safety_threshold: Option<String>,
+    /// 身份指令注入模式
+    #[serde(default)]
+    pub identity_injection: IdentityInjectionMode,
+    /// 允许访问该端口的客户端 Key (为空则不额外限制)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_client_keys: Vec<String>,
+    /// 流式 delta 合并配置 (None = 使用全局配置)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta_coalescing: Option<DeltaCoalescingConfig>,
+}
+
+/// 客户端能力标记 (None = 沿用基础适配器 / 默认行为)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ClientCapabilities {
+    /// 流式输出 Anthropic 行内引用 (citations_delta)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub citations: Option<bool>,
+    /// 流式结尾追加联网搜索来源文本块
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grounding_text: Option<bool>,
+    /// OpenAI 协议输出 reasoning_content 字段
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<bool>,
+    /// 空 system prompt 视为未提供
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normalize_empty_system_prompt: Option<bool>,
+    /// 流式 text_delta 最小合并长度 (0 = 不合并)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_text_delta_chars: Option<usize>,
+}
+
+/// 功能开关 (灰度发布)：风险较高的转换逻辑可先对部分会话开启
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FeatureFlagConfig {
+    pub name: String,
+    #[serde(default)]
+    pub enabled: bool,
+    /// 灰度百分比 (0-100，按会话 ID 哈希分桶)；未设置表示对所有会话生效
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollout_percent: Option<u8>,
+}
+
+/// 自定义客户端配置档 (名称 + 能力标记)，无需改代码即可扩展适配器注册表
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ClientProfileConfig {
+    pub name: String,
+    /// 继承的内置适配器 (如 "zed" / "opencode")，未设置的能力沿用其行为
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    #[serde(default)]
+    pub capabilities: ClientCapabilities,
+}
+
 /// IP 黑名单配置
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IpBlacklistConfig {
@@ -515,6 +1943,11 @@ pub struct ProxyConfig {
     #[serde(default)]
     pub user_agent_override: Option<String>,
 
+    /// [NEW] 上游 v1internal 基础 URL 覆盖 (用于 Mock 服务器或区域端点)
+    /// None 时使用内置的 Sandbox → Daily → Prod 降级链
+    #[serde(default)]
+    pub upstream_base_url: Option<String>,
+
     /// 账号调度配置 (粘性会话/限流重试)
     #[serde(default)]
     pub scheduling: crate::proxy::sticky_config::StickySessionConfig,
@@ -553,6 +1986,142 @@ pub struct ProxyConfig {
     #[serde(default)]
     pub image_thinking_mode: Option<String>,
 
+    /// [NEW] 文本请求被误映射到图像模型时的回退文本模型
+    /// - None: 仅记录警告，仍按图像生成处理 (默认)
+    /// - Some(model): 回退到该文本模型
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_text_fallback_model: Option<String>,
+
+    /// [NEW] 强制回复语言 (BCP-47 标签，如 ja)：设置后在 systemInstruction 中用户系统提示词之后追加语言指令
+    #[serde(default)]
+    pub forced_response_language: Option<String>,
+
+    /// [NEW] 图像模型同时输出文本与图片
+    /// - false: 移除 responseModalities，非流式响应中图片以 Markdown 内联返回 (默认)
+    /// - true: 设置 responseModalities = ["TEXT", "IMAGE"]，非流式响应中图片以 image 内容块返回
+    /// 流式响应始终以 image 内容块返回图片
+    #[serde(default)]
+    pub image_multimodal_output: bool,
+
+    /// [NEW] 严格角色交替
+    /// - true: 合并连续的同角色消息 (默认，Gemini 要求)
+    /// - false: 保留原始消息结构，适用于允许连续同角色的实验端点
+    #[serde(default = "default_true")]
+    pub strict_role_alternation: bool,
+
+    /// [NEW] 响应中的 executableCode / codeExecutionResult part
+    /// - false: 渲染为 Markdown 代码块 (默认)
+    /// - true: 直接丢弃
+    #[serde(default)]
+    pub drop_code_execution_parts: bool,
+
+    /// [NEW] 联网搜索引文渲染样式 (markdown / footnotes / inline_links / off)
+    #[serde(default)]
+    pub citation_style: CitationStyle,
+
+    /// [NEW] 工具数量上限配置
+    #[serde(default)]
+    pub tool_limit: ToolLimitConfig,
+
+    /// [NEW] 客户端字段写入 v1internal 信封时的违规处理 (截断 / 拒绝)
+    #[serde(default)]
+    pub envelope_sanitize_action: EnvelopeSanitizeAction,
+
+    /// [NEW] 流式首字延迟 SLO 告警配置
+    #[serde(default)]
+    pub latency_slo: LatencySloConfig,
+
+    /// [NEW] 单个请求遇到 429 / 配额耗尽等账号级错误时最多轮换账号的次数
+    #[serde(default = "default_max_account_rotations")]
+    pub max_account_rotations: usize,
+
+    /// [NEW] 单个账号同时进行的上游流数量上限，超出时优先选择其他账号，全部饱和时排队等待 (最多 30 秒，超时返回 429；0 = 不限制)
+    #[serde(default = "default_max_concurrent_streams_per_account")]
+    pub max_concurrent_streams_per_account: usize,
+
+    /// [NEW] 会话空闲超过该时长 (秒) 后，由后台清理任务回收其签名缓存、粘性绑定等会话级状态
+    #[serde(default = "default_session_idle_ttl_secs")]
+    pub session_idle_ttl_secs: u64,
+
+    /// [NEW] 上游流中途断开 (已向客户端转发内容) 时，携带已生成文本重新请求一次并无缝拼接续写
+    #[serde(default)]
+    pub stream_resumption: bool,
+
+    /// [NEW] 上游思考耗尽输出上限 (MAX_TOKENS 且无可见内容) 时，在同一账号上关闭思考追问一次最终答案，
+    /// 并拼接到同一条消息中 (失败时回退到原有的中断提示)
+    #[serde(default)]
+    pub thinking_loop_nudge: bool,
+
+    /// [NEW] 重试耗尽后仍为 400 时，二分探测并替换导致错误的历史消息 (每次最多额外发起约 10 个上游请求)
+    #[serde(default)]
+    pub poison_quarantine: bool,
+
+    /// [NEW] 只校验最后一个思考块的模型 (子串匹配)：对这些模型剥离除最后一条以外
+    /// 所有 assistant 消息中的 thought / thoughtSignature (空列表 = 关闭)
+    #[serde(default)]
+    pub strip_historical_thinking_models: Vec<String>,
+
+    /// [NEW] 递归 JSON 清理器的最大深度，超出后停止深入并告警 (防止超深嵌套的工具输入导致栈溢出)
+    #[serde(default = "default_max_json_clean_depth")]
+    pub max_json_clean_depth: usize,
+
+    /// [NEW] token 预刷新提前量 (秒)：选中账号的 token 在该窗口内到期时先刷新再使用
+    #[serde(default = "default_token_refresh_ahead_secs")]
+    pub token_refresh_ahead_secs: u64,
+
+    /// [NEW] 近期失败账号回避窗口 (秒)：失败后的账号在窗口内不参与优先选号 (0 = 关闭)
+    #[serde(default = "default_recent_failure_window_secs")]
+    pub recent_failure_window_secs: u64,
+
+    /// [NEW] 新账号预热策略 (默认关闭)
+    #[serde(default)]
+    pub account_warmup: AccountWarmupConfig,
+
+    /// [NEW] 历史 assistant 消息占位思考块注入 (默认关闭，Vertex AI 会拒绝无签名的思考块)
+    #[serde(default)]
+    pub dummy_thought: DummyThoughtConfig,
+
+    /// [NEW] 工具结果之后重复的任务文本去重 (默认开启，短于 min_length 的文本保留)
+    #[serde(default)]
+    pub task_echo_dedup: TaskEchoDedupConfig,
+
+    /// [NEW] 联网搜索时在 usage 中上报 server_tool_use.web_search_requests (默认开启)
+    #[serde(default = "default_true")]
+    pub report_web_search_usage: bool,
+
+    /// [NEW] 流式 delta 合并: 合并高速模型产生的细碎文本片段，减少 SSE 事件数量 (默认关闭)
+    #[serde(default)]
+    pub delta_coalescing: DeltaCoalescingConfig,
+
+    /// [NEW] v1internal requestId 前缀 (按协议，默认 claude/gemini = agent，openai = openai)
+    #[serde(default)]
+    pub request_id_prefix: RequestIdPrefixConfig,
+
+    /// [NEW] 始终注入的保护性停止序列 (防止模型幻觉出对话标记)，与客户端停止序列合并
+    /// 部分模型会正常输出 "\n\nHuman:" 等内容，可按部署调整或清空
+    #[serde(default = "default_protective_stop_sequences")]
+    pub protective_stop_sequences: Vec<String>,
+
+    /// [NEW] 流式输出脱敏规则 (正则替换，可按 delta 类别配置：thinking / visible / tool_input)
+    #[serde(default)]
+    pub stream_redactions: Vec<StreamRedactionRule>,
+
+    /// [NEW] 额外的监听配置档 (每个配置档独立端口，共享账号池)
+    #[serde(default)]
+    pub listener_profiles: Vec<ListenerProfile>,
+
+    /// [NEW] 自定义客户端配置档 (通过 x-abv-client-profile 请求头按名称选用)
+    #[serde(default)]
+    pub client_profiles: Vec<ClientProfileConfig>,
+
+    /// [NEW] 功能开关 (支持按会话百分比灰度，热更新)
+    #[serde(default)]
+    pub feature_flags: Vec<FeatureFlagConfig>,
+
+    /// [NEW] API Key -> 账号分组映射 (映射到分组的 Key 只使用该分组的账号)
+    #[serde(default)]
+    pub api_key_groups: HashMap<String, String>,
+
     /// 代理池配置
     #[serde(default)]
     pub proxy_pool: ProxyPoolConfig,
@@ -588,11 +2157,43 @@ impl Default for ProxyConfig {
             security_monitor: SecurityMonitorConfig::default(),
             preferred_account_id: None, // 默认使用轮询模式
             user_agent_override: None,
+            upstream_base_url: None,
             saved_user_agent: None,
             thinking_budget: ThinkingBudgetConfig::default(),
             global_system_prompt: GlobalSystemPromptConfig::default(),
             proxy_pool: ProxyPoolConfig::default(),
             image_thinking_mode: None,
+            image_text_fallback_model: None,
+            forced_response_language: None,
+            image_multimodal_output: false,
+            strict_role_alternation: true,
+            drop_code_execution_parts: false,
+            citation_style: CitationStyle::default(),
+            tool_limit: ToolLimitConfig::default(),
+            envelope_sanitize_action: EnvelopeSanitizeAction::default(),
+            latency_slo: LatencySloConfig::default(),
+            max_account_rotations: default_max_account_rotations(),
+            max_concurrent_streams_per_account: default_max_concurrent_streams_per_account(),
+            session_idle_ttl_secs: default_session_idle_ttl_secs(),
+            stream_resumption: false,
+            thinking_loop_nudge: false,
+            poison_quarantine: false,
+            strip_historical_thinking_models: Vec::new(),
+            max_json_clean_depth: default_max_json_clean_depth(),
+            token_refresh_ahead_secs: default_token_refresh_ahead_secs(),
+            recent_failure_window_secs: default_recent_failure_window_secs(),
+            account_warmup: AccountWarmupConfig::default(),
+            dummy_thought: DummyThoughtConfig::default(),
+            task_echo_dedup: TaskEchoDedupConfig::default(),
+            report_web_search_usage: true,
+            delta_coalescing: DeltaCoalescingConfig::default(),
+            request_id_prefix: RequestIdPrefixConfig::default(),
+            protective_stop_sequences: default_protective_stop_sequences(),
+            stream_redactions: Vec::new(),
+            listener_profiles: Vec::new(),
+            client_profiles: Vec::new(),
+            feature_flags: Vec::new(),
+            api_key_groups: HashMap::new(),
         }
     }
 }
@@ -722,4 +2323,20 @@ mod tests {
         assert_eq!(normalize_proxy_url(""), "");
         assert_eq!(normalize_proxy_url("   "), "");
     }
+
+    #[test]
+    fn test_thinking_budget_floor() {
+        let cfg = ThinkingBudgetConfig::default();
+        assert_eq!(cfg.apply_budget_floor(0), 256);
+        assert_eq!(cfg.apply_budget_floor(100), 256);
+        assert_eq!(cfg.apply_budget_floor(4096), 4096);
+        // 动态预算 (-1) 不受影响
+        assert_eq!(cfg.apply_budget_floor(-1), -1);
+
+        let disabled = ThinkingBudgetConfig {
+            min_thinking_budget: 0,
+            ..Default::default()
+        };
+        assert_eq!(disabled.apply_budget_floor(0), 0);
+    }
 }
@@ -3,6 +3,7 @@ use tokio::fs;
 use std::path::PathBuf;
 use futures::StreamExt;
 
+use crate::modules::capture_crypto;
 use crate::proxy::config::DebugLoggingConfig;
 
 fn build_filename(prefix: &str, trace_id: Option<&str>) -> String {
@@ -30,7 +31,16 @@ pub async fn write_debug_payload(
     if !cfg.enabled {
         return;
     }
+    write_capture(cfg, trace_id, prefix, payload).await;
+}
 
+/// 写入抓包文件 (不检查 enabled，由调用方决定是否写入；遵循 encrypt_at_rest)
+pub(crate) async fn write_capture(
+    cfg: &DebugLoggingConfig,
+    trace_id: Option<&str>,
+    prefix: &str,
+    payload: &Value,
+) {
     let output_dir = match resolve_output_dir(cfg) {
         Some(dir) => dir,
         None => {
@@ -47,6 +57,24 @@ pub async fn write_debug_payload(
     let filename = build_filename(prefix, trace_id);
     let path = output_dir.join(filename);
 
+    // [NEW] 静态加密: 流式序列化进加密写入器；密钥不可用时拒绝写入明文
+    if cfg.encrypt_at_rest {
+        let path = path.with_extension(format!("json.{}", capture_crypto::ENCRYPTED_EXTENSION));
+        let payload = payload.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            capture_crypto::write_encrypted_file(&path, &capture_crypto::SecurityDbKeyStore, |w| {
+                serde_json::to_writer_pretty(w, &payload).map_err(std::io::Error::from)
+            })
+        })
+        .await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::warn!("[Debug-Log] Capture not written: {}", e),
+            Err(e) => tracing::warn!("[Debug-Log] Encrypted write task failed: {}", e),
+        }
+        return;
+    }
+
     match serde_json::to_vec_pretty(payload) {
         Ok(bytes) => {
             if let Err(e) = fs::write(&path, bytes).await {
@@ -63,6 +91,39 @@ pub fn is_enabled(cfg: &DebugLoggingConfig) -> bool {
     cfg.enabled
 }
 
+/// [NEW] 解密抓包文件用于本地查看 (仅允许输出目录内的文件)
+/// 使用旧密钥加密的文件会在读取后用当前密钥重新加密
+pub fn decrypt_capture(cfg: &DebugLoggingConfig, path: &str) -> Result<String, String> {
+    let path = resolve_capture_path(cfg, path)?;
+    let bytes = capture_crypto::read_encrypted_file(&path, &capture_crypto::SecurityDbKeyStore)?;
+    String::from_utf8(bytes).map_err(|e| format!("Capture is not valid UTF-8: {}", e))
+}
+
+/// [NEW] 读取抓包文件 (按扩展名自动解密，未加密的文件直接读取)
+pub fn read_capture(cfg: &DebugLoggingConfig, path: &str) -> Result<String, String> {
+    if path.ends_with(&format!(".{}", capture_crypto::ENCRYPTED_EXTENSION)) {
+        return decrypt_capture(cfg, path);
+    }
+    let path = resolve_capture_path(cfg, path)?;
+    std::fs::read_to_string(&path).map_err(|e| format!("Failed to read capture: {}", e))
+}
+
+fn resolve_capture_path(cfg: &DebugLoggingConfig, path: &str) -> Result<PathBuf, String> {
+    let output_dir = resolve_output_dir(cfg).ok_or("Debug log output dir is not available")?;
+    let output_dir = std::fs::canonicalize(&output_dir).map_err(|e| e.to_string())?;
+    let path = std::fs::canonicalize(output_dir.join(path)).map_err(|e| e.to_string())?;
+    if !path.starts_with(&output_dir) {
+        return Err("Capture file must be inside the debug log directory".to_string());
+    }
+    Ok(path)
+}
+
+/// [NEW] 轮换抓包加密密钥 (旧文件在下次读取时重新加密)
+pub fn rotate_capture_key() -> Result<u32, String> {
+    use capture_crypto::CaptureKeyStore;
+    capture_crypto::SecurityDbKeyStore.rotate()
+}
+
 /// 解析 SSE 流式数据，提取 thinking 和正文内容
 fn parse_sse_stream(raw: &str) -> (String, String) {
     let mut thinking_parts: Vec<String> = Vec::new();
@@ -0,0 +1,193 @@
+// 功能开关 (灰度发布)
+// 风险较高的转换逻辑 (signature / thinking 相关) 以命名开关形式发布，可按会话百分比灰度、热更新。
+// 分桶使用 SHA256(开关名 + 会话 ID)，同一会话在同一开关下的结果稳定，不同开关之间相互独立。
+
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use crate::proxy::config::FeatureFlagConfig;
+
+/// [FIX #709] assistant 消息按 [Thinking, Text, ToolUse] 三段重排
+pub const TRIPLE_PARTITION_REORDER: &str = "triple_partition_reorder";
+/// thinking 开启时为缺少思考块的历史 assistant 消息注入占位思考块
+pub const DUMMY_THOUGHT_INJECTION: &str = "dummy_thought_injection";
+
+/// 内置开关及其默认值 (配置中未出现时使用)
+pub const BUILTIN_FLAGS: &[(&str, bool)] = &[
+    (TRIPLE_PARTITION_REORDER, true),
+    (DUMMY_THOUGHT_INJECTION, false),
+];
+
+/// 会话在某个开关下的稳定分桶 (0-99)
+pub fn rollout_bucket(name: &str, session_id: &str) -> u8 {
+    let mut hasher = Sha256::new();
+    hasher.update(name.as_bytes());
+    hasher.update(b":");
+    hasher.update(session_id.as_bytes());
+    let digest = hasher.finalize();
+    let value = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+    (value % 100) as u8
+}
+
+/// 计算单个开关对指定会话是否生效；灰度开关在缺少会话 ID 时视为关闭
+pub fn evaluate(flag: &FeatureFlagConfig, session_id: Option<&str>) -> bool {
+    if !flag.enabled {
+        return false;
+    }
+    match flag.rollout_percent {
+        None => true,
+        Some(percent) if percent >= 100 => true,
+        Some(percent) => session_id.map_or(false, |sid| rollout_bucket(&flag.name, sid) < percent),
+    }
+}
+
+fn is_enabled_in(flags: &[FeatureFlagConfig], name: &str, session_id: Option<&str>) -> bool {
+    match flags.iter().find(|f| f.name == name) {
+        Some(flag) => evaluate(flag, session_id),
+        None => BUILTIN_FLAGS
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
+            .map_or(false, |(_, default)| *default),
+    }
+}
+
+/// 开关是否对当前会话生效 (读取热更新后的全局配置)
+pub fn is_enabled(name: &str, session_id: Option<&str>) -> bool {
+    is_enabled_in(&crate::proxy::config::get_feature_flags(), name, session_id)
+}
+
+fn active_flags_in(flags: &[FeatureFlagConfig], session_id: Option<&str>) -> BTreeMap<String, bool> {
+    let mut result: BTreeMap<String, bool> = BUILTIN_FLAGS
+        .iter()
+        .map(|(name, _)| (name.to_string(), is_enabled_in(flags, name, session_id)))
+        .collect();
+    for flag in flags {
+        result.insert(flag.name.clone(), evaluate(flag, session_id));
+    }
+    result
+}
+
+/// 当前会话下所有开关的取值，写入调试日志的 v1internal_request 记录
+pub fn active_flags(session_id: Option<&str>) -> BTreeMap<String, bool> {
+    active_flags_in(&crate::proxy::config::get_feature_flags(), session_id)
+}
+
+/// 所有开关的当前配置 (内置开关未配置时以默认值补全)
+pub fn list_flags() -> Vec<FeatureFlagConfig> {
+    let mut flags = crate::proxy::config::get_feature_flags();
+    for (name, default) in BUILTIN_FLAGS {
+        if !flags.iter().any(|f| f.name == *name) {
+            flags.push(FeatureFlagConfig {
+                name: name.to_string(),
+                enabled: *default,
+                rollout_percent: None,
+            });
+        }
+    }
+    flags
+}
+
+/// 新增或更新一个开关，返回更新后的完整列表 (调用方负责持久化与热更新)
+pub fn upsert_flag(
+    mut flags: Vec<FeatureFlagConfig>,
+    flag: FeatureFlagConfig,
+) -> Result<Vec<FeatureFlagConfig>, String> {
+    let valid_name = !flag.name.is_empty()
+        && flag.name.len() <= 64
+        && flag.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid_name {
+        return Err(format!("Invalid feature flag name '{}'", flag.name));
+    }
+    if flag.rollout_percent.map_or(false, |p| p > 100) {
+        return Err("rollout_percent must be between 0 and 100".to_string());
+    }
+    match flags.iter_mut().find(|f| f.name == flag.name) {
+        Some(existing) => *existing = flag,
+        None => flags.push(flag),
+    }
+    Ok(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flag(name: &str, enabled: bool, rollout_percent: Option<u8>) -> FeatureFlagConfig {
+        FeatureFlagConfig {
+            name: name.to_string(),
+            enabled,
+            rollout_percent,
+        }
+    }
+
+    #[test]
+    fn test_rollout_bucketing_is_stable_per_session() {
+        let rollout = flag("new_reorder", true, Some(30));
+        let sessions: Vec<String> = (0..1000).map(|i| format!("sid-{:016x}", i)).collect();
+
+        let first: Vec<bool> = sessions.iter().map(|s| evaluate(&rollout, Some(s))).collect();
+        let second: Vec<bool> = sessions.iter().map(|s| evaluate(&rollout, Some(s))).collect();
+        assert_eq!(first, second);
+
+        // 大致按百分比分布
+        let enabled = first.iter().filter(|e| **e).count();
+        assert!((200..400).contains(&enabled), "enabled for {} of 1000 sessions", enabled);
+
+        // 提高百分比只会新增会话，已开启的会话保持开启
+        let wider = flag("new_reorder", true, Some(60));
+        assert!(sessions
+            .iter()
+            .zip(&first)
+            .all(|(s, was_on)| !was_on || evaluate(&wider, Some(s))));
+
+        assert!(!evaluate(&rollout, None));
+        assert!(evaluate(&flag("x", true, Some(100)), None));
+        assert!(!evaluate(&flag("x", false, None), Some("sid-1")));
+    }
+
+    #[test]
+    fn test_hot_toggle_and_builtin_defaults() {
+        assert!(is_enabled_in(&[], TRIPLE_PARTITION_REORDER, None));
+        assert!(!is_enabled_in(&[], DUMMY_THOUGHT_INJECTION, None));
+        assert!(!is_enabled_in(&[], "unknown_flag", None));
+
+        // 使用独立的开关名，避免影响并行运行的转换器测试
+        let name = "test_hot_toggle_flag";
+        assert!(!is_enabled(name, Some("sid-a")));
+        crate::proxy::config::update_feature_flags(vec![flag(name, true, None)]);
+        assert!(is_enabled(name, Some("sid-a")));
+        crate::proxy::config::update_feature_flags(vec![flag(name, false, None)]);
+        assert!(!is_enabled(name, Some("sid-a")));
+        crate::proxy::config::update_feature_flags(Vec::new());
+
+        let flags = upsert_flag(Vec::new(), flag(DUMMY_THOUGHT_INJECTION, true, None)).unwrap();
+        let flags = upsert_flag(flags, flag(DUMMY_THOUGHT_INJECTION, true, Some(10))).unwrap();
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].rollout_percent, Some(10));
+        assert!(upsert_flag(Vec::new(), flag("bad name", true, None)).is_err());
+        assert!(upsert_flag(Vec::new(), flag("ok", true, Some(101))).is_err());
+    }
+
+    #[test]
+    fn test_decision_log_records_active_flags() {
+        let flags = vec![
+            flag(TRIPLE_PARTITION_REORDER, false, None),
+            flag(DUMMY_THOUGHT_INJECTION, true, None),
+            flag("half_rollout", true, Some(50)),
+        ];
+        let sid = "sid-decision-log";
+        let active = active_flags_in(&flags, Some(sid));
+
+        assert_eq!(active.get(TRIPLE_PARTITION_REORDER), Some(&false));
+        assert_eq!(active.get(DUMMY_THOUGHT_INJECTION), Some(&true));
+        assert_eq!(
+            active.get("half_rollout"),
+            Some(&(rollout_bucket("half_rollout", sid) < 50))
+        );
+
+        // 未配置时记录内置默认值
+        let defaults = active_flags_in(&[], Some(sid));
+        assert_eq!(defaults.len(), BUILTIN_FLAGS.len());
+        assert_eq!(defaults.get(TRIPLE_PARTITION_REORDER), Some(&true));
+    }
+}
@@ -2,7 +2,7 @@
 
 use axum::{
     body::Body,
-    extract::{Json, State},
+    extract::{Extension, Json, State},
     http::{header, StatusCode},
     response::{IntoResponse, Response},
 };
@@ -12,6 +12,10 @@ use serde_json::{json, Value};
 use tokio::time::Duration;
 use tracing::{debug, error, info};
 
+use crate::proxy::mappers::claude::prefill::{detect_prefill, trim_response_echo};
+use crate::proxy::mappers::claude::resume::{
+    build_continuation_body, build_nudge_body, GeminiByteStream, StreamResumer, ThinkingNudger,
+};
 use crate::proxy::mappers::claude::{
     transform_claude_request_in, transform_response, create_claude_sse_stream, ClaudeRequest,
     filter_invalid_thinking_blocks_with_family, close_tool_loop_for_thinking,
@@ -23,7 +27,16 @@ use crate::proxy::mappers::context_manager::ContextManager;
 use crate::proxy::mappers::estimation_calibrator::get_calibrator;
 use crate::proxy::debug_logger;
 use crate::proxy::upstream::client::mask_email;
-use crate::proxy::common::client_adapter::CLIENT_ADAPTERS; // [NEW] Import Adapter Registry
+use crate::proxy::common::client_adapter::resolve_client_adapter; // [NEW] Import Adapter Registry
+use crate::proxy::common::blob_intern::{BlobTable, BLOB_INTERN_THRESHOLD};
+use crate::proxy::middleware::auth::AdminScope;
+use crate::proxy::middleware::monitor::ReplayHashSlot;
+use crate::proxy::raw_passthrough::{self, RawAggregate};
+use crate::proxy::signature_sentinel;
+use crate::proxy::mappers::common_utils::{EnvelopeParams, RequestContext};
+use crate::proxy::mappers::common::delta_coalescer::{coalesce_sse_stream, SseDialect};
+use crate::proxy::mappers::claude::diagnostics::{record_poison_quarantine, PoisonQuarantineOutcome};
+use crate::proxy::poison_quarantine::{bisect, message_hash, quarantine_indices, suspect_reason, PoisonCache, ProbeVerdict};
 use axum::http::HeaderMap;
 use std::sync::{atomic::Ordering, Arc};
 
@@ -154,8 +167,6 @@ fn apply_thinking_hints(
     }
 }
 
-const MAX_RETRY_ATTEMPTS: usize = 3;
-
 // ===== Model Constants for Background Tasks =====
 // These can be adjusted for performance/cost optimization or overridden by custom_mapping
 const INTERNAL_BACKGROUND_TASK: &str = "internal-background-task";  // Unified virtual ID for all background tasks
@@ -232,7 +243,7 @@ The structure MUST be as follows:
 
 // ===== 统一退避策略模块 =====
 // 移除本地重复定义，使用 common 中的统一实现
-use super::common::{determine_retry_strategy, apply_retry_strategy, should_rotate_account, RetryStrategy};
+use super::common::{determine_retry_strategy, apply_retry_strategy, should_rotate_account, extract_project_override, request_context, pin_session_generation_override, RetryStrategy, is_insufficient_scope_error, handle_insufficient_scope, max_retry_attempts, is_rate_limit_error, block_rate_limited_account, AccountAttempts};
 
 // ===== 退避策略模块结束 =====
 
@@ -242,8 +253,16 @@ use super::common::{determine_retry_strategy, apply_retry_strategy, should_rotat
 pub async fn handle_messages(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(body): Json<Value>,
+    replay_hash_slot: Option<Extension<ReplayHashSlot>>,
+    admin_scope: Option<Extension<AdminScope>>,
+    Json(mut body): Json<Value>,
 ) -> Response {
+    // [NEW] 大体积 base64 图片/文档移入共享驻留表，后续流水线只流转占位符，最终序列化时再写出
+    let blobs = BlobTable::intern_claude_request(&mut body, BLOB_INTERN_THRESHOLD);
+    if !blobs.is_empty() {
+        debug!("Interned {} large media blobs ({} bytes)", blobs.len(), blobs.total_bytes());
+    }
+
     // [FIX] 保存原始请求体的完整副本，用于日志记录
     // 这确保了即使结构体定义遗漏字段，日志也能完整记录所有参数
     let original_body = body.clone();
@@ -256,13 +275,20 @@ pub async fn handle_messages(
         .map(char::from)
         .collect::<String>().to_lowercase();
     let debug_cfg = state.debug_logging.read().await.clone();
+    // [NEW] 上游流录制 (debug_logging.record_streams 或 x-abv-record-stream 请求头)
+    let record_upstream_stream = crate::proxy::stream_recording::should_record(&debug_cfg, &headers);
+    // [NEW] 原始 Gemini 响应透传 (x-abv-include-raw，仅 admin 权限范围)
+    let include_raw = raw_passthrough::raw_requested(&headers, admin_scope.is_some());
     
     // [NEW] Detect Client Adapter
-    // 检查是否有匹配的客户端适配器（如 opencode）
-    let client_adapter = CLIENT_ADAPTERS.iter().find(|a| a.matches(&headers)).cloned();
+    // 检查是否有匹配的客户端适配器（显式 x-abv-client-profile 优先，其次 User-Agent）
+    let client_adapter = resolve_client_adapter(&headers);
     if let Some(_adapter) = &client_adapter {
         tracing::debug!("[{}] Client Adapter detected: Applying custom strategies", trace_id);
     }
+
+    // [NEW] 请求级 project 覆盖 (X-Antigravity-Project)
+    let project_override = extract_project_override(&headers);
         
     // Decide whether this request should be handled by z.ai (Anthropic passthrough) or the existing Google flow.
     let zai = state.zai.read().await.clone();
@@ -286,17 +312,52 @@ pub async fn handle_messages(
         }
     };
 
+    // [NEW] 转换前校验请求不变量 (消息非空、角色合法、tool_result 引用有效)
+    // [NEW] 并展开 mcp_servers 内联声明的工具 (mcp__{server}__{tool})，远程获取工具列表以 400 拒绝
+    let validation = crate::proxy::mappers::request_validation::validate_claude_request(&request)
+        .and_then(|_| crate::proxy::mappers::claude::mcp_servers::flatten_mcp_servers(&mut request));
+    if let Err(e) = validation {
+        tracing::warn!("[{}] Rejected invalid Claude request: {}", trace_id, e);
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "type": "error",
+                "error": {
+                    "type": "invalid_request_error",
+                    "message": e.to_string()
+                }
+            }))
+        ).into_response();
+    }
+
     // [Task #6] Apply OpenCode variants thinking hints from raw JSON
     let thinking_hint = extract_thinking_hint(&original_body);
     apply_thinking_hints(&mut request, &thinking_hint, &trace_id);
 
+    // [NEW] 客户端适配: 空 system prompt 视为未提供 (Zed 等)
+    if client_adapter.as_ref().map_or(false, |a| a.normalize_empty_system_prompt()) {
+        use crate::proxy::mappers::claude::models::SystemPrompt;
+        let is_empty = match &request.system {
+            Some(SystemPrompt::String(text)) => text.trim().is_empty(),
+            Some(SystemPrompt::Array(blocks)) => blocks.iter().all(|b| b.text.trim().is_empty()),
+            None => false,
+        };
+        if is_empty {
+            debug!("[{}] Normalizing empty system prompt to None", trace_id);
+            request.system = None;
+        }
+    }
+
     if debug_logger::is_enabled(&debug_cfg) {
         // [FIX] 使用原始 body 副本记录日志，确保不丢失任何字段
+        let mut original_body = original_body.clone();
+        blobs.restore(&mut original_body);
         let original_payload = json!({
             "kind": "original_request",
             "protocol": "anthropic",
             "trace_id": trace_id,
             "original_model": request.model,
+            "query_overrides": crate::proxy::query_overrides::current().map(|o| o.summary()),
             "request": original_body,  // 使用原始请求体，不是结构体序列化
         });
         debug_logger::write_debug_payload(&debug_cfg, Some(&trace_id), "original_request", &original_payload).await;
@@ -346,7 +407,15 @@ pub async fn handle_messages(
 
     // [FIX #813] 合并连续的同角色消息 (Consecutive User Messages)
     // 这对于 z.ai (Anthropic 直接转发) 路径至关重要，因为原始结构必须符合协议
-    merge_consecutive_messages(&mut request.messages);
+    // [NEW] strict_role_alternation 关闭时保留原始消息结构
+    if crate::proxy::config::get_strict_role_alternation() {
+        merge_consecutive_messages(&mut request.messages);
+    }
+
+    // [NEW] 请求重放指纹 (清洗后计算)，供 token 统计识别客户端的相同请求重试
+    if let Some(Extension(slot)) = &replay_hash_slot {
+        slot.set(crate::proxy::session_manager::SessionManager::compute_replay_hash(&request));
+    }
 
     // Get model family for signature validation
     let target_family = if use_zai {
@@ -382,13 +451,15 @@ pub async fn handle_messages(
 
     if use_zai {
         // 重新序列化修复后的请求体
-        let new_body = match serde_json::to_value(&request) {
+        let mut new_body = match serde_json::to_value(&request) {
             Ok(v) => v,
             Err(e) => {
                 tracing::error!("Failed to serialize fixed request for z.ai: {}", e);
                 return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
         };
+        // 透传给 z.ai 前还原驻留的图片数据
+        blobs.restore(&mut new_body);
 
         return crate::proxy::providers::zai_anthropic::forward_anthropic_json(
             &state,
@@ -410,6 +481,7 @@ pub async fn handle_messages(
     let threshold_l1 = experimental.context_compression_threshold_l1;
     let threshold_l2 = experimental.context_compression_threshold_l2;
     let threshold_l3 = experimental.context_compression_threshold_l3;
+    let defer_message_start = experimental.defer_message_start;
 
     // 获取最新一条“有意义”的消息内容（用于日志记录和后台任务检测）
     // 策略：反向遍历，首先筛选出所有角色为 "user" 的消息，然后从中找到第一条非 "Warmup" 且非空的文本消息
@@ -512,19 +584,51 @@ pub async fn handle_messages(
     // 3. 准备闭包
     let mut request_for_body = request.clone();
     let token_manager = state.token_manager;
+
+    // [NEW] 毒消息隔离: 预先替换该会话中已确认会导致上游 400 的历史消息
+    let poison_session_id = crate::proxy::session_manager::SessionManager::extract_session_id(&request_for_body);
+    let pre_quarantined = PoisonCache::global().apply(&poison_session_id, &mut request_for_body.messages);
+    if pre_quarantined > 0 {
+        info!("[{}] [Poison-Quarantine] Pre-applied {} cached message replacement(s)", trace_id, pre_quarantined);
+        record_poison_quarantine(&trace_id, &poison_session_id, PoisonQuarantineOutcome::PreApplied, &[], pre_quarantined, None);
+    }
+    let mut poison_bisected = false;
+    let mut quarantined_culprits: Vec<usize> = Vec::new();
+
+    // [NEW] 会话级生成参数覆盖 (X-Session-Generation-Config)
+    if let Err(e) = pin_session_generation_override(&headers, || poison_session_id.clone()) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "type": "error",
+                "error": { "type": "invalid_request_error", "message": e }
+            }))
+        ).into_response();
+    }
     
     let pool_size = token_manager.len();
     // [FIX] Ensure max_attempts is at least 2 to allow for internal retries (e.g. stripping signatures)
-    // even if the user has only 1 account.
-    let max_attempts = MAX_RETRY_ATTEMPTS.min(pool_size.saturating_add(1)).max(2);
+    // even if the user has only 1 account. 上限由 max_account_rotations 配置决定
+    let max_attempts = max_retry_attempts(pool_size);
 
     let mut last_error = String::new();
     let retried_without_thinking = false;
     let mut last_email: Option<String> = None;
     let mut last_mapped_model: Option<String> = None;
     let mut last_status = StatusCode::SERVICE_UNAVAILABLE; // Default to 503 if no response reached
-    
-    for attempt in 0..max_attempts {
+    // [NEW] 毒消息隔离成功后追加一次重试
+    let mut attempt_budget = max_attempts;
+    let mut next_attempt = 0;
+    // [NEW] 单个请求尝试的账号数上限 (1 + max_account_rotations)，失败时汇总各账号错误
+    let mut account_attempts = AccountAttempts::from_config();
+
+    while next_attempt < attempt_budget {
+        let attempt = next_attempt;
+        next_attempt += 1;
+        // 进入下一次尝试说明上一次失败
+        if let Some(prev) = &last_email {
+            account_attempts.record_failure(prev, &last_error);
+        }
         // 2. 模型路由解析
         let mut mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
             &request_for_body.model,
@@ -551,6 +655,8 @@ pub async fn handle_messages(
         // 使用 SessionManager 生成稳定的会话指纹
         let session_id_str = crate::proxy::session_manager::SessionManager::extract_session_id(&request_for_body);
         let session_id = Some(session_id_str.as_str());
+        // [NEW] 先标记会话活跃，避免空闲回收与本次请求竞争
+        crate::proxy::session_registry::touch(&session_id_str);
 
         let force_rotate_token = attempt > 0;
         let (access_token, project_id, email, account_id, _wait_ms) = match token_manager.get_token(&config.request_type, force_rotate_token, session_id, &config.final_model).await {
@@ -578,7 +684,44 @@ pub async fn handle_messages(
             }
         };
 
+        if !account_attempts.admit(&email) {
+            break;
+        }
         last_email = Some(email.clone());
+
+        // [NEW] 占用该账号的并发流名额，随响应流一起释放；全部账号饱和时排队，等待超时返回 429
+        let stream_permit = match token_manager.acquire_stream_slot(&account_id).await {
+            Ok(permit) => permit,
+            Err(e) => {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    Json(json!({
+                        "type": "error",
+                        "error": {
+                            "type": "rate_limit_error",
+                            "message": e
+                        }
+                    }))
+                ).into_response();
+            }
+        };
+
+        // [NEW] 应用请求级 project 覆盖，非法 project 直接返回 400
+        let project_id = match token_manager.resolve_project_override(&account_id, &project_id, project_override.as_deref()) {
+            Ok(p) => p,
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "type": "error",
+                        "error": {
+                            "type": "invalid_request_error",
+                            "message": e
+                        }
+                    }))
+                ).into_response();
+            }
+        };
         info!("✓ Using account: {} (type: {})", email, config.request_type);
         
         
@@ -790,31 +933,36 @@ pub async fn handle_messages(
         // 生成 Trace ID (简单用时间戳后缀)
         // let _trace_id = format!("req_{}", chrono::Utc::now().timestamp_subsec_millis());
 
-        let gemini_body = match transform_claude_request_in(&request_with_mapped, &project_id, retried_without_thinking) {
-            Ok(b) => {
-                debug!("[{}] Transformed Gemini Body: {}", trace_id, serde_json::to_string_pretty(&b).unwrap_or_default());
-                b
-            },
+        let envelope = token_manager.get_envelope_params(&account_id);
+        let request_ctx = request_context(&headers);
+        let gemini_body = match transform_claude_request_in(&request_with_mapped, &project_id, retried_without_thinking, &envelope, &request_ctx) {
+            // 完整请求体仅在携带 x-abv-debug 时以 trace 级别脱敏记录 (见 trace_transformed_body)
+            Ok(b) => b,
             Err(e) => {
-                 let headers = [
+                // [NEW] 按错误类型区分 400 (客户端请求非法) / 500 (内部错误)
+                error!("[{}] Transform failed: {}", trace_id, e);
+                let headers = [
                     ("X-Mapped-Model", request_with_mapped.model.as_str()),
                     ("X-Account-Email", email.as_str()),
                 ];
-                 return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
+                return (
+                    e.status_code(),
                     headers,
-                    Json(json!({
-                        "type": "error",
-                        "error": {
-                            "type": "api_error",
-                            "message": format!("Transform error: {}", e)
-                        }
-                    }))
+                    Json(e.to_anthropic_body())
                 ).into_response();
             }
         };
 
+        // [NEW] 实际服务的模型 (映射 + 联网降级之后)，用于 message_start / 响应体的 model 字段
+        let served_model = gemini_body
+            .get("model")
+            .and_then(|v| v.as_str())
+            .unwrap_or(request_with_mapped.model.as_str())
+            .to_string();
+
         if debug_logger::is_enabled(&debug_cfg) {
+            let mut logged_body = gemini_body.clone();
+            blobs.restore(&mut logged_body);
             let payload = json!({
                 "kind": "v1internal_request",
                 "protocol": "anthropic",
@@ -823,7 +971,14 @@ pub async fn handle_messages(
                 "mapped_model": request_with_mapped.model,
                 "request_type": config.request_type,
                 "attempt": attempt,
-                "v1internal_request": gemini_body.clone(),
+                "feature_flags": crate::proxy::flags::active_flags(Some(
+                    &crate::proxy::session_manager::SessionManager::extract_session_id(&request_with_mapped),
+                )),
+                "poison_quarantine": {
+                    "pre_applied": pre_quarantined,
+                    "culprits": quarantined_culprits,
+                },
+                "v1internal_request": logged_body,
             });
             debug_logger::write_debug_payload(&debug_cfg, Some(&trace_id), "v1internal_request", &payload).await;
         }
@@ -863,8 +1018,19 @@ pub async fn handle_messages(
 
         // Upstream call configuration continued...
 
+        // [NEW] 记录本次请求中签名哨兵值的位置，按上游结果学习该模型是否接受
+        let sentinel_probe = signature_sentinel::probe(&gemini_body);
+
+        // [NEW] 单次序列化: 占位符在写出时直接替换为驻留数据
+        let payload = match blobs.to_vec(&gemini_body) {
+            Ok(p) => Bytes::from(p),
+            Err(e) => {
+                error!("[{}] Failed to serialize upstream body: {}", trace_id, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize request: {}", e)).into_response();
+            }
+        };
         let call_result = match upstream
-            .call_v1_internal_with_headers(method, &access_token, gemini_body, query, extra_headers.clone(), Some(account_id.as_str()))
+            .call_v1_internal_raw(method, &access_token, payload, query, extra_headers.clone(), Some(account_id.as_str()))
             .await {
             Ok(r) => r,
             Err(e) => {
@@ -906,9 +1072,14 @@ pub async fn handle_messages(
         if status.is_success() {
             // [智能限流] 请求成功，重置该账号的连续失败计数
             token_manager.mark_account_success(&email);
+            if let Some(probe) = &sentinel_probe {
+                signature_sentinel::observe_success(&probe.model);
+            }
             
                 // Determine context limit based on model
                 let context_limit = crate::proxy::mappers::claude::utils::get_context_limit_for_model(&request_with_mapped.model);
+            // [NEW] 以 assistant 消息结尾的请求为预填充，响应中裁掉模型对预填充文本的复述
+            let prefill = detect_prefill(&request_with_mapped.messages);
 
             // 处理流式响应
             if actual_stream {
@@ -922,20 +1093,80 @@ pub async fn handle_messages(
                     "status": status.as_u16(),
                     "upstream_url": upstream_url,
                 });
+                let current_message_count = request_with_mapped.messages.len();
+                let tool_schemas = crate::proxy::mappers::claude::utils::collect_tool_schemas(&request_with_mapped.tools);
+
+                let mut upstream_stream =
+                    crate::proxy::model_versions::tap_stream(Box::pin(response.bytes_stream()));
+                // [NEW] 透传原始响应: 流式写入调试目录 (不受调试日志开关限制)，非流式保留聚合结果
+                let raw_aggregate = (include_raw && !client_wants_stream).then(RawAggregate::new);
+                if let Some(aggregate) = &raw_aggregate {
+                    upstream_stream = aggregate.tap(upstream_stream);
+                }
+                if record_upstream_stream || (include_raw && client_wants_stream) {
+                    // [NEW] 录制 sink: 记录转换参数，可通过 replay_stream_recording 离线回放
+                    let replay_context = crate::proxy::stream_recording::ReplayContext {
+                        served_model: Some(served_model.clone()),
+                        requested_model: Some(request.model.clone()),
+                        scaling_enabled,
+                        context_limit,
+                        estimated_prompt_tokens: Some(raw_estimated),
+                        message_count: current_message_count,
+                        client_adapter: client_adapter.as_ref().map(|a| a.name().to_string()),
+                        defer_message_start,
+                        tool_schemas: tool_schemas.clone(),
+                        prefill: prefill.clone(),
+                        stop_sequences: request.stop_sequences.clone().unwrap_or_default(),
+                    };
+                    upstream_stream = crate::proxy::stream_recording::record_stream(
+                        upstream_stream,
+                        debug_cfg.clone(),
+                        trace_id.clone(),
+                        replay_context,
+                    );
+                }
                 let gemini_stream = debug_logger::wrap_reqwest_stream_with_debug(
-                    Box::pin(response.bytes_stream()),
+                    upstream_stream,
                     debug_cfg.clone(),
                     trace_id.clone(),
                     "upstream_response",
                     meta,
                 );
 
-                let current_message_count = request_with_mapped.messages.len();
+                // [NEW] 中断续写 (opt-in): 上游在转发内容后断开时，携带已生成文本重新请求一次
+                // [NEW] 思考耗尽追问 (opt-in): 只输出思考即 MAX_TOKENS 时，在原账号上追问一次最终答案
+                let resumption_enabled = crate::proxy::config::get_stream_resumption_enabled();
+                let nudge_enabled = crate::proxy::config::get_thinking_nudge_enabled();
+                let resume_context = if resumption_enabled || nudge_enabled {
+                    Some(StreamResumeContext {
+                        upstream: upstream.clone(),
+                        token_manager: token_manager.clone(),
+                        gemini_body: gemini_body.clone(),
+                        blobs: blobs.clone(),
+                        access_token: access_token.clone(),
+                        account_id: account_id.clone(),
+                        request_type: config.request_type.clone(),
+                        model: config.final_model.clone(),
+                        session_id: session_id_str.clone(),
+                        extra_headers: extra_headers.clone(),
+                        trace_id: trace_id.clone(),
+                        account_group: crate::proxy::account_groups::current(),
+                    })
+                } else {
+                    None
+                };
+                let stream_resumer = resume_context
+                    .clone()
+                    .filter(|_| resumption_enabled)
+                    .map(build_stream_resumer);
+                let thinking_nudger = resume_context
+                    .filter(|_| nudge_enabled)
+                    .map(build_thinking_nudger);
 
                 // [FIX #530/#529/#859] Enhanced Peek logic to handle heartbeats and slow start
                 // We must pre-read until we find a MEANINGFUL content block (like message_start).
                 // If we only get heartbeats (ping) and then the stream dies, we should rotate account.
-                let mut claude_stream = create_claude_sse_stream(
+                let claude_stream = create_claude_sse_stream(
                     gemini_stream,
                     trace_id.clone(),
                     email.clone(),
@@ -945,6 +1176,21 @@ pub async fn handle_messages(
                     Some(raw_estimated), // [FIX] Pass estimated tokens for calibrator learning
                     current_message_count, // [NEW v4.0.0] Pass message count for rewind detection
                     client_adapter.clone(), // [NEW] Pass client adapter
+                    Some(served_model.clone()), // [NEW] Report the actually-served model
+                    Some(request.model.clone()), // [NEW] Client-requested model (extension field)
+                    defer_message_start, // [NEW] 空流不发送孤立的 message_start，交由 peek 逻辑换号重试
+                    tool_schemas, // [NEW] 工具参数类型修正
+                    stream_resumer, // [NEW] 上游中途断开时续写 (opt-in)
+                    prefill.clone(), // [NEW] 预填充复述裁剪
+                    request.stop_sequences.clone().unwrap_or_default(), // [NEW] 停止序列只作用于可见文本
+                    thinking_nudger, // [NEW] 思考耗尽追问 (opt-in)
+                );
+                // [NEW] 合并细碎的文本 delta (opt-in，可按监听配置档覆盖；客户端适配器可要求最小 delta 长度)
+                let min_text_delta = client_adapter.as_ref().map_or(0, |a| a.min_text_delta_chars());
+                let mut claude_stream = coalesce_sse_stream(
+                    claude_stream,
+                    SseDialect::Claude,
+                    crate::proxy::listener_profile::delta_coalescing().with_min_text_delta(min_text_delta),
                 );
 
                 let mut first_data_chunk = None;
@@ -1008,7 +1254,10 @@ pub async fn handle_messages(
 
                         // 判断客户端期望的格式
                         if client_wants_stream {
-                            // 客户端本就要 Stream，直接返回 SSE
+                            // 客户端本就要 Stream，直接返回 SSE (内部扩展字段仅供收集器使用，不下发)
+                            let combined_stream = combined_stream.map(|chunk| {
+                                chunk.map(crate::proxy::mappers::claude::streaming::strip_internal_fields)
+                            });
                             return Response::builder()
                                 .status(StatusCode::OK)
                                 .header(header::CONTENT_TYPE, "text/event-stream")
@@ -1018,7 +1267,7 @@ pub async fn handle_messages(
                                 .header("X-Account-Email", &email)
                                 .header("X-Mapped-Model", &request_with_mapped.model)
                                 .header("X-Context-Purified", if is_purified { "true" } else { "false" })
-                                .body(Body::from_stream(combined_stream))
+                                .body(Body::from_stream(stream_permit.attach(combined_stream)))
                                 .unwrap();
                         } else {
                             // 客户端要非 Stream，需要收集完整响应并转换为 JSON
@@ -1027,13 +1276,17 @@ pub async fn handle_messages(
                             match collect_stream_to_json(combined_stream).await {
                                 Ok(full_response) => {
                                     info!("[{}] ✓ Stream collected and converted to JSON", trace_id);
+                                    let mut response_body = serde_json::to_value(&full_response).unwrap_or_default();
+                                    if let Some(aggregate) = &raw_aggregate {
+                                        raw_passthrough::attach_raw(&mut response_body, aggregate.collected(), debug_cfg.raw_passthrough_max_bytes);
+                                    }
                                     return Response::builder()
                                         .status(StatusCode::OK)
                                         .header(header::CONTENT_TYPE, "application/json")
                                         .header("X-Account-Email", &email)
                                         .header("X-Mapped-Model", &request_with_mapped.model)
                                         .header("X-Context-Purified", if is_purified { "true" } else { "false" })
-                                        .body(Body::from(serde_json::to_string(&full_response).unwrap()))
+                                        .body(Body::from(serde_json::to_string(&response_body).unwrap()))
                                         .unwrap();
                                 }
                                 Err(e) => {
@@ -1060,6 +1313,7 @@ pub async fn handle_messages(
                 if let Ok(text) = String::from_utf8(bytes.to_vec()) {
                     debug!("Upstream Response for Claude request: {}", text);
                 }
+                crate::proxy::model_versions::record_from_bytes(&bytes);
 
                 let gemini_resp: Value = match serde_json::from_slice(&bytes) {
                     Ok(v) => v,
@@ -1082,18 +1336,24 @@ pub async fn handle_messages(
                 // [FIX #765] Pass session_id and model_name for signature caching
                 let s_id_owned = session_id.map(|s| s.to_string());
                 // 转换
-                let claude_response = match transform_response(
+                let mut claude_response = match transform_response(
                     &gemini_response,
                     scaling_enabled,
                     context_limit,
                     s_id_owned,
-                    request_with_mapped.model.clone(),
+                    served_model.clone(), // [NEW] Report the actually-served model
                     request_with_mapped.messages.len(), // [NEW v4.0.0] Pass message count for rewind detection
+                    Some(request.model.clone()), // [NEW] Client-requested model (extension field)
+                    crate::proxy::mappers::claude::utils::collect_tool_schemas(&request_with_mapped.tools), // [NEW] 工具参数类型修正
                 ) {
                     Ok(r) => r,
-                    Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Transform error: {}", e)).into_response(),
+                    Err(e) => return (e.status_code(), Json(e.to_anthropic_body())).into_response(),
                 };
 
+                if let Some(prefill) = &prefill {
+                    trim_response_echo(&mut claude_response, prefill);
+                }
+
                 // [Optimization] 记录闭环日志：消耗情况
                 let cache_info = if let Some(cached) = claude_response.usage.cache_read_input_tokens {
                     format!(", Cached: {}", cached)
@@ -1110,6 +1370,11 @@ pub async fn handle_messages(
                     cache_info
                 );
 
+                if include_raw {
+                    let mut response_body = serde_json::to_value(&claude_response).unwrap_or_default();
+                    raw_passthrough::attach_raw(&mut response_body, gemini_resp.get("response").unwrap_or(&gemini_resp).clone(), debug_cfg.raw_passthrough_max_bytes);
+                    return (StatusCode::OK, [("X-Account-Email", email.as_str()), ("X-Mapped-Model", request_with_mapped.model.as_str())], Json(response_body)).into_response();
+                }
                 return (StatusCode::OK, [("X-Account-Email", email.as_str()), ("X-Mapped-Model", request_with_mapped.model.as_str())], Json(claude_response)).into_response();
             }
         }
@@ -1146,6 +1411,24 @@ pub async fn handle_messages(
             token_manager.mark_rate_limited_async(&email, status_code, retry_after.as_deref(), &error_text, Some(&request_with_mapped.model)).await;
         }
 
+        // [NEW] 429 / 配额耗尽: 临时屏蔽当前账号并立即轮换到下一个账号 (跳过退避)
+        if is_rate_limit_error(status_code, &error_text)
+            && attempt + 1 < max_attempts
+            && block_rate_limited_account(&token_manager, &account_id, &email, &request_with_mapped.model, retry_after.as_deref(), &error_text)
+        {
+            continue;
+        }
+
+        // [NEW] 哨兵值被拒绝: 记录后立即重试，下一次转换不再注入哨兵值
+        if status_code == 400
+            && sentinel_probe
+                .as_ref()
+                .map_or(false, |probe| signature_sentinel::observe_rejection(probe, &error_text))
+            && attempt + 1 < max_attempts
+        {
+            continue;
+        }
+
         // 4. 处理 400 错误 (Thinking 签名失效 或 块顺序错误)
         if status_code == 400
             && !retried_without_thinking
@@ -1259,7 +1542,11 @@ pub async fn handle_messages(
         // 原逻辑会在第一个账号配额耗尽时直接返回,导致"平衡"模式无法切换账号
 
         // [FIX] 403 时设置 is_forbidden 状态，避免账号被重复选中
-        if status_code == 403 {
+        if status_code == 403 && is_insufficient_scope_error(status_code, &error_text) {
+            // [NEW] OAuth scope 不足: 禁用账号并提示 UI 发起重新授权
+            tracing::warn!("[Claude] Insufficient OAuth scopes on account {}, re-authorization required", email);
+            handle_insufficient_scope(&token_manager, &state.monitor, &account_id, &email).await;
+        } else if status_code == 403 {
             // Check for VALIDATION_REQUIRED error - temporarily block account
             if error_text.contains("VALIDATION_REQUIRED") ||
                error_text.contains("verify your account") ||
@@ -1312,6 +1599,89 @@ pub async fn handle_messages(
                 ).into_response();
             }
 
+            // [NEW] 重试耗尽后的 400: 二分探测问题历史消息，替换后再重试一次 (需开启 poison_quarantine)
+            if status_code == 400
+                && !poison_bisected
+                && request_for_body.messages.len() > 1
+                && crate::proxy::config::get_poison_quarantine_enabled()
+            {
+                poison_bisected = true;
+                let probe_ctx = PoisonProbeContext {
+                    upstream: upstream.clone(),
+                    template: request_with_mapped.clone(),
+                    blobs: blobs.clone(),
+                    access_token: access_token.clone(),
+                    project_id: project_id.clone(),
+                    account_id: account_id.clone(),
+                    envelope: envelope.clone(),
+                    request_ctx: request_ctx.clone(),
+                    extra_headers: extra_headers.clone(),
+                    retried_without_thinking,
+                };
+                if let Some(outcome) = bisect(&request_for_body.messages, |messages| probe_ctx.probe(messages)).await {
+                    let hashes: Vec<u64> = outcome
+                        .culprits
+                        .iter()
+                        .map(|&i| message_hash(&request_for_body.messages[i]))
+                        .collect();
+                    tracing::warn!(
+                        "[{}] [Poison-Quarantine] Isolated {} culprit message(s) {:?} in {} probe(s) (session: {}), retrying",
+                        trace_id,
+                        outcome.culprits.len(),
+                        outcome.culprits,
+                        outcome.probes,
+                        poison_session_id
+                    );
+                    record_poison_quarantine(
+                        &trace_id,
+                        &poison_session_id,
+                        PoisonQuarantineOutcome::Isolated,
+                        &outcome.culprits,
+                        outcome.culprits.len(),
+                        Some(outcome.probes),
+                    );
+                    if debug_logger::is_enabled(&debug_cfg) {
+                        let payload = json!({
+                            "kind": "poison_quarantine",
+                            "outcome": PoisonQuarantineOutcome::Isolated,
+                            "protocol": "anthropic",
+                            "trace_id": trace_id,
+                            "session_id": poison_session_id,
+                            "attempt": attempt,
+                            "status": status_code,
+                            "error_text": error_text,
+                            "probes": outcome.probes,
+                            "culprits": outcome.culprits.iter().map(|&i| json!({
+                                "index": i,
+                                "role": request_for_body.messages[i].role,
+                                "reason": suspect_reason(&request_for_body.messages[i]),
+                            })).collect::<Vec<_>>(),
+                        });
+                        debug_logger::write_debug_payload(&debug_cfg, Some(&trace_id), "poison_quarantine", &payload).await;
+                    }
+                    PoisonCache::global().remember(&poison_session_id, hashes);
+                    request_for_body.messages = quarantine_indices(&request_for_body.messages, &outcome.culprits);
+                    quarantined_culprits = outcome.culprits;
+                    attempt_budget = attempt_budget.max(attempt + 2);
+                    continue;
+                }
+                tracing::warn!("[{}] [Poison-Quarantine] Bisection found no culprit message, returning original error", trace_id);
+                record_poison_quarantine(&trace_id, &poison_session_id, PoisonQuarantineOutcome::NoCulprit, &[], 0, None);
+                if debug_logger::is_enabled(&debug_cfg) {
+                    let payload = json!({
+                        "kind": "poison_quarantine",
+                        "outcome": PoisonQuarantineOutcome::NoCulprit,
+                        "protocol": "anthropic",
+                        "trace_id": trace_id,
+                        "session_id": poison_session_id,
+                        "attempt": attempt,
+                        "status": status_code,
+                        "error_text": error_text,
+                    });
+                    debug_logger::write_debug_payload(&debug_cfg, Some(&trace_id), "poison_quarantine", &payload).await;
+                }
+            }
+
             // 不可重试的错误，直接返回
             error!("[{}] Non-retryable error {}: {}", trace_id, status_code, error_text);
             return (status, [
@@ -1322,6 +1692,10 @@ pub async fn handle_messages(
     }
     
     
+    if let Some(prev) = &last_email {
+        account_attempts.record_failure(prev, &last_error);
+    }
+
     if let Some(email) = last_email {
         // [FIX] Include X-Mapped-Model in exhaustion error
         let mut headers = HeaderMap::new();
@@ -1353,7 +1727,10 @@ pub async fn handle_messages(
             "error": {
                 "id": "err_retry_exhausted",
                 "type": error_type,
-                "message": format!("All {} attempts failed. Last status: {}. Error: {}", max_attempts, last_status, last_error)
+                "message": format!(
+                    "All {} attempts failed across {} account(s). Last status: {}. Errors: {}",
+                    max_attempts, account_attempts.accounts_tried(), last_status, account_attempts.summary()
+                )
             }
         }))).into_response()
     } else {
@@ -1415,7 +1792,7 @@ pub async fn handle_list_models(State(state): State<AppState>) -> impl IntoRespo
     }))
 }
 
-/// 计算 tokens (占位符)
+/// 计算 tokens: 按实际转发的 v1internal 请求体估算 (含注入的系统提示词与转换后的工具声明)，并应用校准系数
 pub async fn handle_count_tokens(
     State(state): State<AppState>,
     headers: HeaderMap,
@@ -1436,11 +1813,202 @@ pub async fn handle_count_tokens(
         .await;
     }
 
-    Json(json!({
-        "input_tokens": 0,
-        "output_tokens": 0
-    }))
-    .into_response()
+    let mut request: ClaudeRequest = match serde_json::from_value(body) {
+        Ok(r) => r,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "type": "error",
+                    "error": {
+                        "type": "invalid_request_error",
+                        "message": format!("Invalid request body: {}", e)
+                    }
+                })),
+            )
+                .into_response();
+        }
+    };
+    request.model = crate::proxy::common::model_mapping::resolve_model_route(
+        &request.model,
+        &*state.custom_mapping.read().await,
+    );
+
+    // [NEW] 与 /v1/messages 一致展开 mcp_servers 内联工具，使其声明计入输入 token
+    if let Err(e) = crate::proxy::mappers::claude::mcp_servers::flatten_mcp_servers(&mut request) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "type": "error",
+                "error": {
+                    "type": "invalid_request_error",
+                    "message": e.to_string()
+                }
+            })),
+        )
+            .into_response();
+    }
+
+    match estimate_input_tokens(&request) {
+        Ok(raw) => Json(json!({ "input_tokens": get_calibrator().calibrate(raw) })).into_response(),
+        Err(e) => {
+            debug!("[Count-Tokens] Transform failed for {}: {}", request.model, e);
+            (e.status_code(), Json(e.to_anthropic_body())).into_response()
+        }
+    }
+}
+
+/// 转换请求并估算输入 token 数 (未校准)
+fn estimate_input_tokens(
+    request: &ClaudeRequest,
+) -> Result<u32, crate::proxy::mappers::error::MapperError> {
+    let body = transform_claude_request_in(request, "count-tokens", false, &EnvelopeParams::default(), &Default::default())?;
+    Ok(ContextManager::estimate_gemini_request_tokens(&body["request"]))
+}
+
+/// 中断续写 / 思考耗尽追问请求所需的上下文
+#[derive(Clone)]
+struct StreamResumeContext {
+    upstream: Arc<crate::proxy::upstream::client::UpstreamClient>,
+    token_manager: Arc<crate::proxy::TokenManager>,
+    gemini_body: Value,
+    blobs: BlobTable,
+    access_token: String,
+    account_id: String,
+    request_type: String,
+    model: String,
+    session_id: String,
+    extra_headers: std::collections::HashMap<String, String>,
+    trace_id: String,
+    /// 请求的账号分组 (续写在响应流中执行，不在请求的分组上下文内)
+    account_group: Option<Arc<str>>,
+}
+
+/// 毒消息二分探测所需的上游调用上下文 (固定使用出错的账号)
+struct PoisonProbeContext {
+    upstream: Arc<crate::proxy::upstream::client::UpstreamClient>,
+    /// 已完成模型映射的请求，探测时只替换 messages
+    template: ClaudeRequest,
+    blobs: BlobTable,
+    access_token: String,
+    project_id: String,
+    account_id: String,
+    envelope: EnvelopeParams,
+    request_ctx: RequestContext,
+    extra_headers: std::collections::HashMap<String, String>,
+    retried_without_thinking: bool,
+}
+
+impl PoisonProbeContext {
+    /// 以替换后的历史消息发起一次请求: 2xx 为通过，400 为拒绝，其他状态无法判断
+    fn probe(&self, messages: Vec<Message>) -> impl std::future::Future<Output = ProbeVerdict> + '_ {
+        async move {
+            let mut request = self.template.clone();
+            request.messages = messages;
+            let Ok(body) = transform_claude_request_in(&request, &self.project_id, self.retried_without_thinking, &self.envelope, &self.request_ctx) else {
+                return ProbeVerdict::Unknown;
+            };
+            let Ok(payload) = self.blobs.to_vec(&body) else {
+                return ProbeVerdict::Unknown;
+            };
+            match self
+                .upstream
+                .call_v1_internal_raw(
+                    "streamGenerateContent",
+                    &self.access_token,
+                    Bytes::from(payload),
+                    Some("alt=sse"),
+                    self.extra_headers.clone(),
+                    Some(self.account_id.as_str()),
+                )
+                .await
+            {
+                // 仅需状态码，丢弃响应体即中止生成
+                Ok(result) => match result.response.status() {
+                    status if status.is_success() => ProbeVerdict::Accepted,
+                    StatusCode::BAD_REQUEST => ProbeVerdict::Rejected,
+                    _ => ProbeVerdict::Unknown,
+                },
+                Err(_) => ProbeVerdict::Unknown,
+            }
+        }
+    }
+}
+
+/// 构造思考耗尽追问发起器: 只在原账号上请求一次，失败时由调用方回退到中断提示
+fn build_thinking_nudger(ctx: StreamResumeContext) -> ThinkingNudger {
+    Box::new(move |thinking: String| {
+        Box::pin(async move {
+            let body = build_nudge_body(&ctx.gemini_body, &thinking);
+            let payload = ctx.blobs.to_vec(&body).map_err(|e| format!("Failed to serialize request: {}", e))?;
+            let result = ctx
+                .upstream
+                .call_v1_internal_raw(
+                    "streamGenerateContent",
+                    &ctx.access_token,
+                    Bytes::from(payload),
+                    Some("alt=sse"),
+                    ctx.extra_headers.clone(),
+                    Some(ctx.account_id.as_str()),
+                )
+                .await?;
+            let status = result.response.status();
+            if !status.is_success() {
+                return Err(format!("HTTP {}", status));
+            }
+            info!("[{}] Thinking nudge accepted, splicing the final answer", ctx.trace_id);
+            Ok(Box::pin(result.response.bytes_stream()) as GeminiByteStream)
+        })
+    })
+}
+
+/// 构造续写请求发起器: 先用原账号，失败后换下一个账号再试一次
+fn build_stream_resumer(ctx: StreamResumeContext) -> StreamResumer {
+    Box::new(move |partial: String| {
+        Box::pin(async move {
+            let body = build_continuation_body(&ctx.gemini_body, &partial);
+            let mut last_error = String::new();
+
+            for attempt in 0..2 {
+                let mut body = body.clone();
+                let (access_token, account_id) = if attempt == 0 {
+                    (ctx.access_token.clone(), ctx.account_id.clone())
+                } else {
+                    let (token, project_id, email, account_id, _wait_ms) = crate::proxy::account_groups::scope(
+                        ctx.account_group.clone(),
+                        ctx.token_manager
+                            .get_token(&ctx.request_type, true, Some(ctx.session_id.as_str()), &ctx.model),
+                    )
+                    .await?;
+                    info!("[{}] Resuming stream on next account: {}", ctx.trace_id, mask_email(&email));
+                    body["project"] = json!(project_id);
+                    (token, account_id)
+                };
+
+                let payload = ctx.blobs.to_vec(&body).map_err(|e| format!("Failed to serialize request: {}", e))?;
+                match ctx
+                    .upstream
+                    .call_v1_internal_raw(
+                        "streamGenerateContent",
+                        &access_token,
+                        Bytes::from(payload),
+                        Some("alt=sse"),
+                        ctx.extra_headers.clone(),
+                        Some(account_id.as_str()),
+                    )
+                    .await
+                {
+                    Ok(result) if result.response.status().is_success() => {
+                        return Ok(Box::pin(result.response.bytes_stream()) as GeminiByteStream);
+                    }
+                    Ok(result) => last_error = format!("HTTP {}", result.response.status()),
+                    Err(e) => last_error = e,
+                }
+                debug!("[{}] Resume attempt {} failed: {}", ctx.trace_id, attempt + 1, last_error);
+            }
+            Err(last_error)
+        })
+    })
 }
 
 // 移除已失效的简单单元测试，后续将补全完整的集成测试
@@ -1742,12 +2310,13 @@ async fn call_gemini_sync(
     trace_id: &str,
 ) -> Result<String, String> {
     // Get token and transform request
-    let (access_token, project_id, _, _, _wait_ms) = token_manager
+    let (access_token, project_id, _, account_id, _wait_ms) = token_manager
         .get_token("gemini", false, None, model)
         .await
         .map_err(|e| format!("Failed to get account: {}", e))?;
     
-    let gemini_body = crate::proxy::mappers::claude::transform_claude_request_in(request, &project_id, false)
+    let envelope = token_manager.get_envelope_params(&account_id);
+    let gemini_body = crate::proxy::mappers::claude::transform_claude_request_in(request, &project_id, false, &envelope, &Default::default())
         .map_err(|e| format!("Failed to transform request: {}", e))?;
     
     // Call Gemini API
@@ -1854,6 +2423,8 @@ async fn try_compress_with_summary(
         output_config: None,
         size: None,
         quality: None,
+        stop_sequences: None,
+        mcp_servers: None,
     };
     
     debug!("[{}] [Layer-3] Calling {} for summary generation", trace_id, INTERNAL_BACKGROUND_TASK);
@@ -1918,5 +2489,7 @@ async fn try_compress_with_summary(
         output_config: original_request.output_config.clone(),
         size: original_request.size.clone(),
         quality: original_request.quality.clone(),
+        stop_sequences: None,
+        mcp_servers: None,
     })
 }
@@ -3,6 +3,148 @@ use tracing::{debug, info};
 use axum::{http::StatusCode, response::{IntoResponse, Response}, Json, extract::State};
 use serde_json::{json, Value};
 use crate::proxy::server::AppState;
+use axum::http::HeaderMap;
+use crate::proxy::mappers::common_utils::RequestContext;
+
+/// [NEW] 请求级 project 覆盖头 (与账号固定对称，用于指定账号下的某个 project)
+pub const PROJECT_OVERRIDE_HEADER: &str = "x-antigravity-project";
+
+/// 从请求头中提取 project 覆盖值 (空值视为未提供)
+pub fn extract_project_override(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(PROJECT_OVERRIDE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// [NEW] 请求级关闭 googleSearch 自动注入 (覆盖 -online 后缀 / 联网工具触发的注入)
+pub const DISABLE_GOOGLE_SEARCH_HEADER: &str = "x-abv-disable-google-search";
+
+/// 布尔型请求头是否开启 (1 / true / on / yes，不区分大小写)
+pub fn header_flag_enabled(headers: &HeaderMap, name: &str) -> bool {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| matches!(v.trim().to_lowercase().as_str(), "1" | "true" | "on" | "yes"))
+}
+
+/// 请求头是否要求关闭 googleSearch 自动注入
+pub fn google_search_disabled(headers: &HeaderMap) -> bool {
+    header_flag_enabled(headers, DISABLE_GOOGLE_SEARCH_HEADER)
+}
+
+/// 从请求头构造请求级转换选项 (与账号级 EnvelopeParams 分开传递)
+pub fn request_context(headers: &HeaderMap) -> RequestContext {
+    RequestContext {
+        disable_google_search: google_search_disabled(headers),
+        trace_body: debug_trace_requested(headers),
+    }
+}
+
+/// [NEW] 请求级调试标记: 在 trace 级别记录完整的转换后请求体 (签名已脱敏)
+pub const DEBUG_TRACE_HEADER: &str = "x-abv-debug";
+
+/// 请求头是否开启转换后请求体的 trace 日志
+pub fn debug_trace_requested(headers: &HeaderMap) -> bool {
+    header_flag_enabled(headers, DEBUG_TRACE_HEADER)
+}
+
+/// [NEW] 会话级生成参数覆盖头 (JSON，例如 {"temperature":0,"seed":42})
+pub const SESSION_GENERATION_HEADER: &str = "x-session-generation-config";
+
+/// 解析会话生成参数覆盖头；未提供时返回 Ok(None)，格式错误时返回错误信息
+pub fn extract_session_generation_override(
+    headers: &HeaderMap,
+) -> Result<Option<crate::proxy::session_manager::GenerationOverride>, String> {
+    let Some(raw) = headers.get(SESSION_GENERATION_HEADER) else {
+        return Ok(None);
+    };
+    let raw = raw
+        .to_str()
+        .map_err(|_| format!("{} must be valid UTF-8 JSON", SESSION_GENERATION_HEADER))?;
+    serde_json::from_str(raw.trim())
+        .map(Some)
+        .map_err(|e| format!("Invalid {} header: {}", SESSION_GENERATION_HEADER, e))
+}
+
+/// 若请求携带覆盖头，则为该会话固定生成参数 (后续请求无需再携带)
+pub fn pin_session_generation_override(
+    headers: &HeaderMap,
+    session_id: impl FnOnce() -> String,
+) -> Result<(), String> {
+    if let Some(overrides) = extract_session_generation_override(headers)? {
+        let session_id = session_id();
+        info!("[Session-Generation] Pinned generation override for {}: {:?}", session_id, overrides);
+        crate::proxy::session_manager::SessionGenerationOverrides::global().set(&session_id, overrides);
+    }
+    Ok(())
+}
+
+// ===== OAuth scope 不足检测 =====
+
+/// 前端监听该事件以发起重新授权流程
+pub const REAUTH_REQUIRED_EVENT: &str = "proxy://reauth-required";
+
+/// [NEW] 判断上游 403 是否因 OAuth scope 不足 (Google 新增 scope 要求后出现)
+/// 同时兼容 JSON 错误体与 HTML 错误页
+pub fn is_insufficient_scope_error(status_code: u16, error_text: &str) -> bool {
+    if status_code != 403 {
+        return false;
+    }
+
+    let trimmed = error_text.trim_start();
+    // HTML 错误页: 去掉标签后再匹配，避免文本被标签或换行打断
+    let text = if trimmed.starts_with('<') {
+        let mut plain = String::with_capacity(trimmed.len());
+        let mut in_tag = false;
+        for c in trimmed.chars() {
+            match c {
+                '<' => in_tag = true,
+                '>' => {
+                    in_tag = false;
+                    plain.push(' ');
+                }
+                _ if !in_tag => plain.push(c),
+                _ => {}
+            }
+        }
+        plain
+    } else {
+        trimmed.to_string()
+    };
+    let normalized = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+
+    normalized.contains("access_token_scope_insufficient")
+        || normalized.contains("insufficient authentication scopes")
+        || normalized.contains("insufficient_scope")
+        || (normalized.contains("permission_denied") && normalized.contains("scope"))
+}
+
+/// [NEW] 处理 scope 不足: 禁用账号并通知前端发起重新授权 (预填该账号邮箱)
+pub async fn handle_insufficient_scope(
+    token_manager: &crate::proxy::TokenManager,
+    monitor: &crate::proxy::monitor::ProxyMonitor,
+    account_id: &str,
+    email: &str,
+) {
+    if let Err(e) = token_manager.mark_insufficient_scope(account_id).await {
+        tracing::error!("Failed to mark insufficient scope for {}: {}", email, e);
+        return;
+    }
+    monitor.emit_event(
+        REAUTH_REQUIRED_EVENT,
+        json!({
+            "account_id": account_id,
+            "email": email,
+            "reason": crate::modules::account::INSUFFICIENT_SCOPE_REASON,
+        }),
+    );
+}
 
 // ===== 统一重试与退避策略 =====
 
@@ -145,6 +287,166 @@ pub fn should_rotate_account(status_code: u16) -> bool {
     }
 }
 
+// ===== 429 账号轮换 =====
+
+/// 429 轮换时账号的默认屏蔽时长 (秒)，上游给出重试时间时优先使用
+const RATE_LIMIT_BLOCK_DEFAULT_SECS: i64 = 60;
+/// 屏蔽时长上限，避免异常的 Retry-After 长期移除账号
+const RATE_LIMIT_BLOCK_MAX_SECS: i64 = 600;
+
+/// 根据配置的最大轮换次数计算单个请求的最大尝试次数
+/// 至少 2 次，以保留单账号下的内部重试 (如清理签名后重试)
+pub fn max_retry_attempts(pool_size: usize) -> usize {
+    crate::proxy::config::get_max_account_rotations()
+        .saturating_add(1)
+        .min(pool_size.saturating_add(1))
+        .max(2)
+}
+
+// ===== 单请求账号尝试上限 =====
+
+/// 汇总错误中每个账号的错误信息最大长度 (字符)
+const ACCOUNT_ERROR_SUMMARY_MAX_CHARS: usize = 300;
+
+/// [NEW] 单个请求的账号尝试记录
+/// 限制同一请求尝试的不同账号数 (1 + max_account_rotations)；同一账号上的内部重试 (如清理签名) 不额外占用名额。
+/// 全部失败时按尝试顺序汇总每个账号最后一次返回的错误。
+#[derive(Debug)]
+pub struct AccountAttempts {
+    max_accounts: usize,
+    /// (账号, 最后一次错误)，按首次尝试顺序
+    accounts: Vec<(String, Option<String>)>,
+}
+
+impl AccountAttempts {
+    pub fn new(max_accounts: usize) -> Self {
+        Self {
+            max_accounts: max_accounts.max(1),
+            accounts: Vec::new(),
+        }
+    }
+
+    /// 最多尝试的账号数 = 首个账号 + max_account_rotations 次轮换
+    pub fn from_config() -> Self {
+        Self::new(crate::proxy::config::get_max_account_rotations().saturating_add(1))
+    }
+
+    /// 登记本次尝试使用的账号；新账号超出上限时返回 false，调用方应停止重试
+    pub fn admit(&mut self, email: &str) -> bool {
+        if self.accounts.iter().any(|(e, _)| e == email) {
+            return true;
+        }
+        if self.accounts.len() >= self.max_accounts {
+            info!(
+                "Account retry limit reached ({} accounts), not trying {}",
+                self.max_accounts, email
+            );
+            return false;
+        }
+        self.accounts.push((email.to_string(), None));
+        true
+    }
+
+    /// 记录账号的一次失败 (同一账号多次失败时保留最后一次)
+    pub fn record_failure(&mut self, email: &str, error: &str) {
+        if error.is_empty() {
+            return;
+        }
+        let error: String = error.chars().take(ACCOUNT_ERROR_SUMMARY_MAX_CHARS).collect();
+        match self.accounts.iter_mut().find(|(e, _)| e == email) {
+            Some((_, last)) => *last = Some(error),
+            None => self.accounts.push((email.to_string(), Some(error))),
+        }
+    }
+
+    pub fn accounts_tried(&self) -> usize {
+        self.accounts.len()
+    }
+
+    /// 汇总各账号的错误: "[1] use***@gm***: HTTP 429: ...; [2] ..."
+    pub fn summary(&self) -> String {
+        self.accounts
+            .iter()
+            .filter_map(|(email, error)| error.as_ref().map(|e| (email, e)))
+            .enumerate()
+            .map(|(i, (email, error))| {
+                format!("[{}] {}: {}", i + 1, crate::proxy::upstream::client::mask_email(email), error)
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// 判断上游错误是否为限流 / 配额耗尽
+pub fn is_rate_limit_error(status_code: u16, error_text: &str) -> bool {
+    status_code == 429 || (status_code >= 400 && error_text.contains("QUOTA_EXHAUSTED"))
+}
+
+/// 计算账号屏蔽时长: Retry-After 头 > 错误体中的重试延迟 > 默认值
+fn rate_limit_block_secs(retry_after: Option<&str>, error_text: &str) -> u64 {
+    let secs = retry_after
+        .and_then(|v| v.trim().parse::<i64>().ok())
+        .or_else(|| {
+            crate::proxy::upstream::retry::parse_retry_delay(error_text)
+                .map(|ms| (ms as i64 + 999) / 1000)
+        })
+        .unwrap_or(RATE_LIMIT_BLOCK_DEFAULT_SECS);
+    secs.clamp(1, RATE_LIMIT_BLOCK_MAX_SECS) as u64
+}
+
+/// [NEW] 429 / 配额耗尽: 在限流跟踪器中临时锁定该账号的当前模型 (仅内存，不影响其他模型)，
+/// 以便下一次尝试立即选中其他账号
+/// 返回 true 表示已锁定，调用方应跳过退避直接重试；单账号池不锁定，保留原有的退避重试
+pub fn block_rate_limited_account(
+    token_manager: &crate::proxy::TokenManager,
+    account_id: &str,
+    email: &str,
+    model: &str,
+    retry_after: Option<&str>,
+    error_text: &str,
+) -> bool {
+    if token_manager.len() <= 1 {
+        return false;
+    }
+
+    let block_secs = rate_limit_block_secs(retry_after, error_text);
+    token_manager.lock_model_for_rotation(account_id, model, block_secs);
+    info!(
+        "Account {} rate limited on {}, locked for {}s and rotating to next account",
+        email, model, block_secs
+    );
+    true
+}
+
+/// [NEW] 代理能力握手，客户端据此决定通过 x-abv-client-profile 声明哪些能力
+/// GET /v1/abv/capabilities
+pub async fn handle_capabilities() -> Json<Value> {
+    use crate::proxy::common::client_adapter::{CLIENT_ADAPTERS, CLIENT_PROFILE_HEADER};
+    use crate::proxy::common::client_adapters::profile::PROFILE_FLAGS;
+
+    let mut profiles: Vec<String> = CLIENT_ADAPTERS.iter().map(|a| a.name().to_string()).collect();
+    profiles.extend(crate::proxy::config::get_client_profiles().into_iter().map(|p| p.name));
+
+    Json(json!({
+        "object": "abv.capabilities",
+        "version": env!("CARGO_PKG_VERSION"),
+        "features": {
+            // 联网搜索结果以文本 / 行内引用形式返回，不输出 server_tool_use 内容块
+            "server_tool_blocks": false,
+            "citations": true,
+            "grounding_text": true,
+            "reasoning_content": true,
+            "ws_transport": false,
+            "stream_resumption": crate::proxy::config::get_stream_resumption_enabled(),
+            "thinking_loop_nudge": crate::proxy::config::get_thinking_nudge_enabled(),
+            "web_search_usage": crate::proxy::config::get_report_web_search_usage(),
+        },
+        "client_profile_header": CLIENT_PROFILE_HEADER,
+        "profile_flags": PROFILE_FLAGS,
+        "profiles": profiles,
+    }))
+}
+
 /// Detects model capabilities and configuration
 /// POST /v1/models/detect
 pub async fn handle_detect_model(
@@ -193,3 +495,57 @@ pub async fn handle_detect_model(
 
     Json(response).into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insufficient_scope_detected_in_json_body() {
+        let body = r#"{
+          "error": {
+            "code": 403,
+            "message": "Request had insufficient authentication scopes.",
+            "status": "PERMISSION_DENIED",
+            "details": [{
+              "@type": "type.googleapis.com/google.rpc.ErrorInfo",
+              "reason": "ACCESS_TOKEN_SCOPE_INSUFFICIENT",
+              "domain": "googleapis.com"
+            }]
+          }
+        }"#;
+        assert!(is_insufficient_scope_error(403, body));
+        // 状态码不是 403 时不触发
+        assert!(!is_insufficient_scope_error(401, body));
+    }
+
+    #[test]
+    fn test_insufficient_scope_detected_in_html_body() {
+        let body = "<!DOCTYPE html>\n<html><head><title>Error 403 (Forbidden)</title></head>\n<body><p><b>403.</b> Request had insufficient\n  authentication <i>scopes</i>.</p></body></html>";
+        assert!(is_insufficient_scope_error(403, body));
+    }
+
+    #[test]
+    fn test_other_permission_errors_not_treated_as_scope() {
+        let body = r#"{"error":{"code":403,"message":"The caller does not have permission","status":"PERMISSION_DENIED"}}"#;
+        assert!(!is_insufficient_scope_error(403, body));
+        let validation = r#"{"error":{"code":403,"message":"VALIDATION_REQUIRED: verify your account"}}"#;
+        assert!(!is_insufficient_scope_error(403, validation));
+    }
+
+    #[test]
+    fn test_same_account_retry_does_not_consume_account_budget() {
+        let mut attempts = AccountAttempts::new(1);
+        assert!(attempts.admit("alice@example.com"));
+        attempts.record_failure("alice@example.com", "HTTP 400: Invalid `signature`");
+        // 清理签名后在同一账号重试
+        assert!(attempts.admit("alice@example.com"));
+        attempts.record_failure("alice@example.com", "HTTP 503: overloaded");
+        assert!(!attempts.admit("bob@example.com"));
+
+        assert_eq!(attempts.accounts_tried(), 1);
+        assert_eq!(attempts.summary(), "[1] ali***@ex***: HTTP 503: overloaded");
+        // 0 视为 1，至少尝试一个账号
+        assert!(AccountAttempts::new(0).admit("alice@example.com"));
+    }
+}
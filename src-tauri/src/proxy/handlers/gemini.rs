@@ -8,10 +8,11 @@ use axum::{
 use serde_json::{json, Value};
 use tracing::{debug, error, info};
 
-use crate::proxy::common::client_adapter::CLIENT_ADAPTERS;
+use crate::proxy::common::client_adapter::resolve_client_adapter;
 use crate::proxy::debug_logger;
 use crate::proxy::handlers::common::{
-    apply_retry_strategy, determine_retry_strategy, should_rotate_account,
+    apply_retry_strategy, determine_retry_strategy, extract_project_override,
+    should_rotate_account, AccountAttempts,
 };
 use crate::proxy::mappers::gemini::{unwrap_response, wrap_request};
 use crate::proxy::server::AppState;
@@ -44,10 +45,7 @@ pub async fn handle_generate(
     let debug_cfg = state.debug_logging.read().await.clone();
 
     // [NEW] Detect Client Adapter
-    let client_adapter = CLIENT_ADAPTERS
-        .iter()
-        .find(|a| a.matches(&headers))
-        .cloned();
+    let client_adapter = resolve_client_adapter(&headers);
     if client_adapter.is_some() {
         debug!("[{}] Client Adapter detected", trace_id);
     }
@@ -93,8 +91,14 @@ pub async fn handle_generate(
 
     let mut last_error = String::new();
     let mut last_email: Option<String> = None;
+    // [NEW] 单个请求尝试的账号数上限 (1 + max_account_rotations)，失败时汇总各账号错误
+    let mut account_attempts = AccountAttempts::from_config();
 
     for attempt in 0..max_attempts {
+        // 进入下一次尝试说明上一次失败
+        if let Some(prev) = &last_email {
+            account_attempts.record_failure(prev, &last_error);
+        }
         // 3. 模型路由解析
         let mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
             &model_name,
@@ -130,6 +134,8 @@ pub async fn handle_generate(
         // 4. 获取 Token (使用准确的 request_type)
         // 提取 SessionId (粘性指纹)
         let session_id = SessionManager::extract_gemini_session_id(&body, &model_name);
+        // [NEW] 先标记会话活跃，避免空闲回收与本次请求竞争
+        crate::proxy::session_registry::touch(&session_id);
 
         // 关键：在重试尝试 (attempt > 0) 时强制轮换账号
         let (access_token, project_id, email, account_id, _wait_ms) = match token_manager
@@ -150,9 +156,27 @@ pub async fn handle_generate(
             }
         };
 
+        if !account_attempts.admit(&email) {
+            break;
+        }
         last_email = Some(email.clone());
         info!("✓ Using account: {} (type: {})", email, config.request_type);
 
+        // [NEW] 占用该账号的并发流名额，随响应流一起释放；全部账号饱和时排队，等待超时返回 429
+        let stream_permit = token_manager
+            .acquire_stream_slot(&account_id)
+            .await
+            .map_err(|e| (StatusCode::TOO_MANY_REQUESTS, e))?;
+
+        // [NEW] 应用请求级 project 覆盖 (X-Antigravity-Project)，非法 project 直接返回 400
+        let project_id = token_manager
+            .resolve_project_override(
+                &account_id,
+                &project_id,
+                extract_project_override(&headers).as_deref(),
+            )
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
         // 5. 包装请求 (project injection)
         // [FIX #765] Pass session_id to wrap_request for signature injection
         let wrapped_body = wrap_request(&body, &project_id, &mapped_model, Some(&session_id));
@@ -274,7 +298,7 @@ pub async fn handle_generate(
                     "upstream_url": upstream_url,
                 });
                 let mut response_stream = debug_logger::wrap_reqwest_stream_with_debug(
-                    Box::pin(response.bytes_stream()),
+                    crate::proxy::model_versions::tap_stream(Box::pin(response.bytes_stream())),
                     debug_cfg.clone(),
                     trace_id.clone(),
                     "upstream_response",
@@ -413,7 +437,7 @@ pub async fn handle_generate(
                 };
 
                 if client_wants_stream {
-                    let body = Body::from_stream(stream);
+                    let body = Body::from_stream(stream_permit.attach(stream));
                     return Ok(Response::builder()
                         .header("Content-Type", "text/event-stream")
                         .header("Cache-Control", "no-cache")
@@ -620,11 +644,18 @@ pub async fn handle_generate(
             .into_response());
     }
 
+    if let Some(prev) = &last_email {
+        account_attempts.record_failure(prev, &last_error);
+    }
     if let Some(email) = last_email {
         Ok((
             StatusCode::TOO_MANY_REQUESTS,
             [("X-Account-Email", email)],
-            format!("All accounts exhausted. Last error: {}", last_error),
+            format!(
+                "All accounts exhausted after {} account(s). Errors: {}",
+                account_attempts.accounts_tried(),
+                account_attempts.summary()
+            ),
         )
             .into_response())
     } else {
@@ -1,6 +1,7 @@
 // OpenAI Handler
 use axum::{
     extract::Json, extract::State, http::StatusCode, response::IntoResponse, response::Response,
+    Extension,
 };
 use base64::Engine as _;
 use bytes::Bytes;
@@ -11,15 +12,23 @@ use crate::proxy::mappers::openai::{
     transform_openai_request, transform_openai_response, OpenAIRequest,
 };
 // use crate::proxy::upstream::client::UpstreamClient; // 通过 state 获取
+use crate::proxy::account_groups;
 use crate::proxy::debug_logger;
+use crate::proxy::mappers::common::delta_coalescer::{coalesce_sse_stream, SseDialect};
+use crate::proxy::middleware::auth::AdminScope;
+use crate::proxy::raw_passthrough::{self, RawAggregate};
+use crate::proxy::signature_sentinel;
 use crate::proxy::server::AppState;
 use crate::proxy::upstream::client::mask_email;
 
 const MAX_RETRY_ATTEMPTS: usize = 3;
 use super::common::{
-    apply_retry_strategy, determine_retry_strategy, should_rotate_account, RetryStrategy,
+    apply_retry_strategy, determine_retry_strategy, extract_project_override, request_context, handle_insufficient_scope,
+    pin_session_generation_override,
+    is_insufficient_scope_error, should_rotate_account, RetryStrategy, max_retry_attempts,
+    is_rate_limit_error, block_rate_limited_account, AccountAttempts,
 };
-use crate::proxy::common::client_adapter::CLIENT_ADAPTERS; // [NEW] Adapter Registry
+use crate::proxy::common::client_adapter::resolve_client_adapter; // [NEW] Adapter Registry
 use crate::proxy::session_manager::SessionManager;
 use axum::http::HeaderMap;
 use tokio::time::Duration;
@@ -27,6 +36,7 @@ use tokio::time::Duration;
 pub async fn handle_chat_completions(
     State(state): State<AppState>,
     headers: HeaderMap, // [CHANGED] Extract headers
+    admin_scope: Option<Extension<AdminScope>>,
     Json(mut body): Json<Value>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     // [FIX] 保存原始请求体的完整副本，用于日志记录
@@ -85,6 +95,25 @@ pub async fn handle_chat_completions(
     let mut openai_req: OpenAIRequest = serde_json::from_value(body)
         .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid request: {}", e)))?;
 
+    // [NEW] 标准 Chat 请求在转换前校验不变量 (Responses 格式仍沿用下方的空消息兜底)
+    if !is_responses_format {
+        if let Err(e) = crate::proxy::mappers::request_validation::validate_openai_request(&openai_req) {
+            debug!("Rejected invalid OpenAI request: {}", e);
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": {
+                        "message": e.to_string(),
+                        "type": "invalid_request_error",
+                        "param": e.param,
+                        "code": null
+                    }
+                })),
+            )
+                .into_response());
+        }
+    }
+
     // Safety: Ensure messages is not empty
     if openai_req.messages.is_empty() {
         debug!("Received request with empty messages, injecting fallback...");
@@ -111,6 +140,8 @@ pub async fn handle_chat_completions(
         openai_req.stream
     );
     let debug_cfg = state.debug_logging.read().await.clone();
+    // [NEW] 原始 Gemini 响应透传 (x-abv-include-raw，仅 admin 权限范围)
+    let include_raw = raw_passthrough::raw_requested(&headers, admin_scope.is_some());
     if debug_logger::is_enabled(&debug_cfg) {
         // [FIX] 使用原始 body 副本记录日志，确保不丢失任何字段
         let original_payload = json!({
@@ -118,6 +149,7 @@ pub async fn handle_chat_completions(
             "protocol": "openai",
             "trace_id": trace_id,
             "original_model": openai_req.model,
+            "query_overrides": crate::proxy::query_overrides::current().map(|o| o.summary()),
             "request": original_body,  // 使用原始请求体，不是结构体序列化
         });
         debug_logger::write_debug_payload(
@@ -130,23 +162,32 @@ pub async fn handle_chat_completions(
     }
 
     // [NEW] Detect Client Adapter
-    let client_adapter = CLIENT_ADAPTERS
-        .iter()
-        .find(|a| a.matches(&headers))
-        .cloned();
+    let client_adapter = resolve_client_adapter(&headers);
     if client_adapter.is_some() {
         debug!("[{}] Client Adapter detected", trace_id);
     }
+    let emit_reasoning_content = client_adapter
+        .as_ref()
+        .map_or(true, |a| a.emits_reasoning_content());
+
+    // [NEW] 请求级 project 覆盖 (X-Antigravity-Project)
+    let project_override = extract_project_override(&headers);
+
+    // [NEW] 会话级生成参数覆盖 (X-Session-Generation-Config)
+    pin_session_generation_override(&headers, || SessionManager::extract_openai_session_id(&openai_req))
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
 
     // 1. 获取 UpstreamClient (Clone handle)
     let upstream = state.upstream.clone();
     let token_manager = state.token_manager;
     let pool_size = token_manager.len();
     // [FIX] Ensure max_attempts is at least 2 to allow for internal retries
-    let max_attempts = MAX_RETRY_ATTEMPTS.min(pool_size.saturating_add(1)).max(2);
+    let max_attempts = max_retry_attempts(pool_size);
 
     let mut last_error = String::new();
     let mut last_email: Option<String> = None;
+    // [NEW] 单个请求尝试的账号数上限 (1 + max_account_rotations)，失败时汇总各账号错误
+    let mut account_attempts = AccountAttempts::from_config();
 
     // 2. 模型路由解析 (移到循环外以支持在所有路径返回 X-Mapped-Model)
     let mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
@@ -155,6 +196,10 @@ pub async fn handle_chat_completions(
     );
 
     for attempt in 0..max_attempts {
+        // 进入下一次尝试说明上一次失败
+        if let Some(prev) = &last_email {
+            account_attempts.record_failure(prev, &last_error);
+        }
         // 将 OpenAI 工具转为 Value 数组以便探测联网
         let tools_val: Option<Vec<Value>> = openai_req
             .tools
@@ -172,6 +217,8 @@ pub async fn handle_chat_completions(
 
         // 3. 提取 SessionId (粘性指纹)
         let session_id = SessionManager::extract_openai_session_id(&openai_req);
+        // [NEW] 先标记会话活跃，避免空闲回收与本次请求竞争
+        crate::proxy::session_registry::touch(&session_id);
 
         // 4. 获取 Token (使用准确的 request_type)
         // 关键：在重试尝试 (attempt > 0) 时强制轮换账号
@@ -197,12 +244,81 @@ pub async fn handle_chat_completions(
             }
         };
 
+        if !account_attempts.admit(&email) {
+            break;
+        }
         last_email = Some(email.clone());
         info!("✓ Using account: {} (type: {})", email, config.request_type);
 
+        // [NEW] 占用该账号的并发流名额，随响应流一起释放；全部账号饱和时排队，等待超时返回 429
+        let stream_permit = match token_manager.acquire_stream_slot(&account_id).await {
+            Ok(permit) => permit,
+            Err(e) => {
+                return Ok((
+                    StatusCode::TOO_MANY_REQUESTS,
+                    Json(json!({
+                        "error": {
+                            "message": e,
+                            "type": "rate_limit_error",
+                            "param": null,
+                            "code": "concurrent_stream_limit"
+                        }
+                    })),
+                )
+                    .into_response());
+            }
+        };
+
+        // [NEW] 应用请求级 project 覆盖，非法 project 直接返回 400
+        let project_id = match token_manager.resolve_project_override(
+            &account_id,
+            &project_id,
+            project_override.as_deref(),
+        ) {
+            Ok(p) => p,
+            Err(e) => {
+                return Ok((
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": {
+                            "message": e,
+                            "type": "invalid_request_error",
+                            "param": null,
+                            "code": null
+                        }
+                    })),
+                )
+                    .into_response());
+            }
+        };
+
         // 4. 转换请求 (返回内容包含 session_id 和 message_count)
-        let (gemini_body, session_id, message_count) =
-            transform_openai_request(&openai_req, &project_id, &mapped_model);
+        let (gemini_body, session_id, message_count) = match transform_openai_request(
+            &openai_req,
+            &project_id,
+            &mapped_model,
+            &token_manager.get_envelope_params(&account_id),
+            &request_context(&headers),
+        ) {
+            Ok(r) => r,
+            Err(e) => {
+                // [NEW] 按错误类型区分 400 (客户端请求非法) / 500 (内部错误)
+                error!("[OpenAI-Request] Transform failed: {}", e);
+                return Ok((
+                    e.status_code(),
+                    [("X-Mapped-Model", mapped_model.as_str())],
+                    Json(e.to_openai_body()),
+                )
+                    .into_response());
+            }
+        };
+
+        // [NEW] 实际服务的模型 (映射 + 联网降级之后)，用于响应体的 model 字段
+        let served_model = gemini_body
+            .get("model")
+            .and_then(|v| v.as_str())
+            .unwrap_or(mapped_model.as_str())
+            .to_string();
 
         if debug_logger::is_enabled(&debug_cfg) {
             let payload = json!({
@@ -213,6 +329,7 @@ pub async fn handle_chat_completions(
                 "mapped_model": mapped_model,
                 "request_type": config.request_type,
                 "attempt": attempt,
+                "feature_flags": crate::proxy::flags::active_flags(Some(&session_id)),
                 "v1internal_request": gemini_body.clone(),
             });
             debug_logger::write_debug_payload(
@@ -224,11 +341,6 @@ pub async fn handle_chat_completions(
             .await;
         }
 
-        // [New] 打印转换后的报文 (Gemini Body) 供调试
-        if let Ok(body_json) = serde_json::to_string_pretty(&gemini_body) {
-            debug!("[OpenAI-Request] Transformed Gemini Body:\n{}", body_json);
-        }
-
         // 5. 发送请求
         let client_wants_stream = openai_req.stream;
         let force_stream_internally = !client_wants_stream;
@@ -261,6 +373,9 @@ pub async fn handle_chat_completions(
             );
         }
 
+        // [NEW] 记录本次请求中签名哨兵值的位置，按上游结果学习该模型是否接受
+        let sentinel_probe = signature_sentinel::probe(&gemini_body);
+
         let call_result = match upstream
             .call_v1_internal_with_headers(
                 method,
@@ -322,6 +437,9 @@ pub async fn handle_chat_completions(
         let upstream_url = response.url().to_string();
         let status = response.status();
         if status.is_success() {
+            if let Some(probe) = &sentinel_probe {
+                signature_sentinel::observe_success(&probe.model);
+            }
             // 5. 处理流式 vs 非流式
             if actual_stream {
                 use axum::body::Body;
@@ -338,8 +456,28 @@ pub async fn handle_chat_completions(
                     "status": status.as_u16(),
                     "upstream_url": upstream_url,
                 });
+                let mut upstream_stream =
+                    crate::proxy::model_versions::tap_stream(Box::pin(response.bytes_stream()));
+                // [NEW] 透传原始响应: 流式写入调试目录 (不受调试日志开关限制)，非流式保留聚合结果
+                let raw_aggregate = (include_raw && !client_wants_stream).then(RawAggregate::new);
+                if let Some(aggregate) = &raw_aggregate {
+                    upstream_stream = aggregate.tap(upstream_stream);
+                } else if include_raw {
+                    let replay_context = crate::proxy::stream_recording::ReplayContext {
+                        served_model: Some(served_model.clone()),
+                        requested_model: Some(openai_req.model.clone()),
+                        message_count,
+                        ..Default::default()
+                    };
+                    upstream_stream = crate::proxy::stream_recording::record_stream(
+                        upstream_stream,
+                        debug_cfg.clone(),
+                        trace_id.clone(),
+                        replay_context,
+                    );
+                }
                 let gemini_stream = debug_logger::wrap_reqwest_stream_with_debug(
-                    Box::pin(response.bytes_stream()),
+                    upstream_stream,
                     debug_cfg.clone(),
                     trace_id.clone(),
                     "upstream_response",
@@ -349,11 +487,19 @@ pub async fn handle_chat_completions(
                 // [P1 FIX] Enhanced Peek logic to handle heartbeats and slow start
                 // Pre-read until we find meaningful content, skip heartbeats
                 use crate::proxy::mappers::openai::streaming::create_openai_sse_stream;
-                let mut openai_stream = create_openai_sse_stream(
+                let openai_stream = create_openai_sse_stream(
                     gemini_stream,
-                    openai_req.model.clone(),
+                    served_model.clone(), // [NEW] Report the actually-served model
                     session_id,
                     message_count,
+                    emit_reasoning_content,
+                    crate::proxy::mappers::openai::request::collect_tool_schemas(&openai_req.tools), // [NEW] 工具参数类型修正
+                );
+                // [NEW] 合并细碎的文本 delta (opt-in，可按监听配置档覆盖)
+                let mut openai_stream = coalesce_sse_stream(
+                    openai_stream,
+                    SseDialect::OpenAi,
+                    crate::proxy::listener_profile::delta_coalescing(),
                 );
 
                 let mut first_data_chunk = None;
@@ -429,7 +575,7 @@ pub async fn handle_chat_completions(
 
                 if client_wants_stream {
                     // 客户端请求流式，返回 SSE
-                    let body = Body::from_stream(combined_stream);
+                    let body = Body::from_stream(stream_permit.attach(combined_stream));
                     return Ok(Response::builder()
                         .header("Content-Type", "text/event-stream")
                         .header("Cache-Control", "no-cache")
@@ -446,8 +592,27 @@ pub async fn handle_chat_completions(
                     use crate::proxy::mappers::openai::collector::collect_stream_to_json;
 
                     match collect_stream_to_json(Box::pin(combined_stream)).await {
-                        Ok(full_response) => {
+                        Ok(mut full_response) => {
                             info!("[{}] ✓ Stream collected and converted to JSON", trace_id);
+                            full_response.requested_model = Some(openai_req.model.clone());
+                            if let Some(aggregate) = &raw_aggregate {
+                                let mut response_body =
+                                    serde_json::to_value(&full_response).unwrap_or_default();
+                                raw_passthrough::attach_raw(
+                                    &mut response_body,
+                                    aggregate.collected(),
+                                    debug_cfg.raw_passthrough_max_bytes,
+                                );
+                                return Ok((
+                                    StatusCode::OK,
+                                    [
+                                        ("X-Account-Email", email.as_str()),
+                                        ("X-Mapped-Model", mapped_model.as_str()),
+                                    ],
+                                    Json(response_body),
+                                )
+                                    .into_response());
+                            }
                             return Ok((
                                 StatusCode::OK,
                                 [
@@ -475,8 +640,38 @@ pub async fn handle_chat_completions(
                 .await
                 .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Parse error: {}", e)))?;
 
-            let openai_response =
-                transform_openai_response(&gemini_resp, Some(&session_id), message_count);
+            let mut openai_response =
+                transform_openai_response(
+                    &gemini_resp,
+                    Some(&session_id),
+                    message_count,
+                    &crate::proxy::mappers::openai::request::collect_tool_schemas(&openai_req.tools),
+                );
+            // [NEW] model 报告实际服务的模型，原始请求名放入扩展字段
+            openai_response.model = served_model.clone();
+            openai_response.requested_model = Some(openai_req.model.clone());
+            if !emit_reasoning_content {
+                for choice in &mut openai_response.choices {
+                    choice.message.reasoning_content = None;
+                }
+            }
+            if include_raw {
+                let mut response_body = serde_json::to_value(&openai_response).unwrap_or_default();
+                raw_passthrough::attach_raw(
+                    &mut response_body,
+                    gemini_resp.get("response").unwrap_or(&gemini_resp).clone(),
+                    debug_cfg.raw_passthrough_max_bytes,
+                );
+                return Ok((
+                    StatusCode::OK,
+                    [
+                        ("X-Account-Email", email.as_str()),
+                        ("X-Mapped-Model", mapped_model.as_str()),
+                    ],
+                    Json(response_body),
+                )
+                    .into_response());
+            }
             return Ok((
                 StatusCode::OK,
                 [
@@ -530,6 +725,16 @@ pub async fn handle_chat_completions(
             .await;
         }
 
+        // [NEW] 哨兵值被拒绝: 记录后立即重试，下一次转换不再注入哨兵值
+        if status_code == 400
+            && sentinel_probe
+                .as_ref()
+                .map_or(false, |probe| signature_sentinel::observe_rejection(probe, &error_text))
+            && attempt + 1 < max_attempts
+        {
+            continue;
+        }
+
         // 确定重试策略
         let strategy = determine_retry_strategy(status_code, &error_text, false);
 
@@ -547,6 +752,16 @@ pub async fn handle_chat_completions(
                 .await;
         }
 
+        // [NEW] 429 / 配额耗尽: 临时屏蔽当前账号并立即轮换 (let_it_crash 客户端仍按原逻辑快速失败)
+        let let_it_crash = client_adapter.as_ref().is_some_and(|a| a.let_it_crash());
+        if !let_it_crash
+            && is_rate_limit_error(status_code, &error_text)
+            && attempt + 1 < max_attempts
+            && block_rate_limited_account(&token_manager, &account_id, &email, &mapped_model, _retry_after.as_deref(), &error_text)
+        {
+            continue;
+        }
+
         // 执行退避
         if apply_retry_strategy(strategy, attempt, max_attempts, status_code, &trace_id).await {
             // [NEW] Apply Client Adapter "let_it_crash" strategy
@@ -654,7 +869,13 @@ pub async fn handle_chat_completions(
         // 只有 403 (权限/地区限制) 和 401 (认证失效) 触发账号轮换
         if status_code == 403 || status_code == 401 {
             // [NEW] 403 时设置 is_forbidden 状态，避免 Claude Code 会话退出
-            if status_code == 403 {
+            if status_code == 403 && is_insufficient_scope_error(status_code, &error_text) {
+                // [NEW] OAuth scope 不足: 禁用账号并提示 UI 发起重新授权
+                if let Some(acc_id) = token_manager.get_account_id_by_email(&email) {
+                    tracing::warn!("[OpenAI] Insufficient OAuth scopes on account {}, re-authorization required", email);
+                    handle_insufficient_scope(&token_manager, &state.monitor, &acc_id, &email).await;
+                }
+            } else if status_code == 403 {
                 if let Some(acc_id) = token_manager.get_account_id_by_email(&email) {
                     // Check for VALIDATION_REQUIRED error - temporarily block account
                     if error_text.contains("VALIDATION_REQUIRED")
@@ -721,11 +942,18 @@ pub async fn handle_chat_completions(
     }
 
     // 所有尝试均失败
+    if let Some(prev) = &last_email {
+        account_attempts.record_failure(prev, &last_error);
+    }
     if let Some(email) = last_email {
         Ok((
             StatusCode::TOO_MANY_REQUESTS,
             [("X-Account-Email", email), ("X-Mapped-Model", mapped_model)],
-            format!("All accounts exhausted. Last error: {}", last_error),
+            format!(
+                "All accounts exhausted after {} account(s). Errors: {}",
+                account_attempts.accounts_tried(),
+                account_attempts.summary()
+            ),
         )
             .into_response())
     } else {
@@ -742,6 +970,7 @@ pub async fn handle_chat_completions(
 /// 将 Prompt 转换为 Chat Message 格式，复用 handle_chat_completions
 pub async fn handle_completions(
     State(state): State<AppState>,
+    headers: HeaderMap, // [NEW] For X-Antigravity-Project override
     Json(mut body): Json<Value>,
 ) -> Response {
     debug!(
@@ -1116,14 +1345,26 @@ pub async fn handle_completions(
             });
     }
 
+    // [NEW] 客户端能力: reasoning_content 输出与 chat 接口一致，按适配器 / 客户端配置档决定
+    let emit_reasoning_content = resolve_client_adapter(&headers)
+        .as_ref()
+        .map_or(true, |a| a.emits_reasoning_content());
+
+    // [NEW] 会话级生成参数覆盖 (X-Session-Generation-Config)
+    if let Err(e) = pin_session_generation_override(&headers, || SessionManager::extract_openai_session_id(&openai_req)) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
     let upstream = state.upstream.clone();
     let token_manager = state.token_manager;
     let pool_size = token_manager.len();
     // [FIX] Ensure max_attempts is at least 2 to allow for internal retries
-    let max_attempts = MAX_RETRY_ATTEMPTS.min(pool_size.saturating_add(1)).max(2);
+    let max_attempts = max_retry_attempts(pool_size);
 
     let mut last_error = String::new();
     let mut last_email: Option<String> = None;
+    // [NEW] 单个请求尝试的账号数上限 (1 + max_account_rotations)，失败时汇总各账号错误
+    let mut account_attempts = AccountAttempts::from_config();
 
     // 2. 模型路由解析 (移到循环外以支持在所有路径返回 X-Mapped-Model)
     let mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
@@ -1133,6 +1374,10 @@ pub async fn handle_completions(
     let trace_id = format!("req_{}", chrono::Utc::now().timestamp_subsec_millis());
 
     for attempt in 0..max_attempts {
+        // 进入下一次尝试说明上一次失败
+        if let Some(prev) = &last_email {
+            account_attempts.record_failure(prev, &last_error);
+        }
         // 3. 模型配置解析
         // 将 OpenAI 工具转为 Value 数组以便探测联网
         let tools_val: Option<Vec<Value>> = openai_req
@@ -1153,6 +1398,8 @@ pub async fn handle_completions(
         // [New] 使用 TokenManager 内部逻辑提取 session_id，支持粘性调度
         let session_id_str = SessionManager::extract_openai_session_id(&openai_req);
         let session_id = Some(session_id_str.as_str());
+        // [NEW] 先标记会话活跃，避免空闲回收与本次请求竞争
+        crate::proxy::session_registry::touch(&session_id_str);
 
         // 重试时强制轮换，除非只是简单的网络抖动但 Claude 逻辑里 attempt > 0 总是 force_rotate
         let force_rotate = attempt > 0;
@@ -1177,12 +1424,81 @@ pub async fn handle_completions(
             }
         };
 
+        if !account_attempts.admit(&email) {
+            break;
+        }
         last_email = Some(email.clone());
 
         info!("✓ Using account: {} (type: {})", email, config.request_type);
 
-        let (gemini_body, session_id, message_count) =
-            transform_openai_request(&openai_req, &project_id, &mapped_model);
+        // [NEW] 占用该账号的并发流名额，随响应流一起释放；全部账号饱和时排队，等待超时返回 429
+        let stream_permit = match token_manager.acquire_stream_slot(&account_id).await {
+            Ok(permit) => permit,
+            Err(e) => {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    Json(json!({
+                        "error": {
+                            "message": e,
+                            "type": "rate_limit_error",
+                            "param": null,
+                            "code": "concurrent_stream_limit"
+                        }
+                    })),
+                )
+                    .into_response();
+            }
+        };
+
+        // [NEW] 应用请求级 project 覆盖，非法 project 直接返回 400
+        let project_id = match token_manager.resolve_project_override(
+            &account_id,
+            &project_id,
+            extract_project_override(&headers).as_deref(),
+        ) {
+            Ok(p) => p,
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": {
+                            "message": e,
+                            "type": "invalid_request_error",
+                            "param": null,
+                            "code": null
+                        }
+                    })),
+                )
+                    .into_response();
+            }
+        };
+
+        let (gemini_body, session_id, message_count) = match transform_openai_request(
+            &openai_req,
+            &project_id,
+            &mapped_model,
+            &token_manager.get_envelope_params(&account_id),
+            &request_context(&headers),
+        ) {
+            Ok(r) => r,
+            Err(e) => {
+                // [NEW] 按错误类型区分 400 (客户端请求非法) / 500 (内部错误)
+                error!("[Codex-Request] Transform failed: {}", e);
+                return (
+                    e.status_code(),
+                    [("X-Mapped-Model", mapped_model)],
+                    Json(e.to_openai_body()),
+                )
+                    .into_response();
+            }
+        };
+
+        // [NEW] 实际服务的模型 (映射 + 联网降级之后)
+        let served_model = gemini_body
+            .get("model")
+            .and_then(|v| v.as_str())
+            .unwrap_or(mapped_model.as_str())
+            .to_string();
 
         // [New] 打印转换后的报文 (Gemini Body) 供调试 (Codex 路径) ———— 缩减为 simple debug
         debug!(
@@ -1205,6 +1521,8 @@ pub async fn handle_completions(
         };
         let query_string = if list_response { Some("alt=sse") } else { None };
 
+        let sentinel_probe = signature_sentinel::probe(&gemini_body);
+
         let call_result = match upstream
             .call_v1_internal(
                 method,
@@ -1233,13 +1551,17 @@ pub async fn handle_completions(
         if status.is_success() {
             // [智能限流] 请求成功，重置该账号的连续失败计数
             token_manager.mark_account_success(&email);
+            if let Some(probe) = &sentinel_probe {
+                signature_sentinel::observe_success(&probe.model);
+            }
 
             if list_response {
                 use axum::body::Body;
                 use axum::response::Response;
                 use futures::StreamExt;
 
-                let gemini_stream = response.bytes_stream();
+                let gemini_stream =
+                    crate::proxy::model_versions::tap_stream(Box::pin(response.bytes_stream()));
 
                 // DECISION: Which stream to create?
                 // If client wants stream: give them what they asked (Legacy/Codex SSE).
@@ -1251,7 +1573,7 @@ pub async fn handle_completions(
                         use crate::proxy::mappers::openai::streaming::create_codex_sse_stream;
                         create_codex_sse_stream(
                             Box::pin(gemini_stream),
-                            openai_req.model.clone(),
+                            served_model.clone(),
                             session_id,
                             message_count,
                         )
@@ -1259,7 +1581,7 @@ pub async fn handle_completions(
                         use crate::proxy::mappers::openai::streaming::create_legacy_sse_stream;
                         create_legacy_sse_stream(
                             Box::pin(gemini_stream),
-                            openai_req.model.clone(),
+                            served_model.clone(),
                             session_id,
                             message_count,
                         )
@@ -1327,7 +1649,7 @@ pub async fn handle_completions(
                         .header("Connection", "keep-alive")
                         .header("X-Account-Email", &email)
                         .header("X-Mapped-Model", &mapped_model)
-                        .body(Body::from_stream(combined_stream))
+                        .body(Body::from_stream(stream_permit.attach(combined_stream)))
                         .unwrap()
                         .into_response();
                 } else {
@@ -1338,9 +1660,11 @@ pub async fn handle_completions(
                     // because we just want the content aggregation which chat stream does well.
                     let mut openai_stream = create_openai_sse_stream(
                         Box::pin(gemini_stream),
-                        openai_req.model.clone(),
+                        served_model.clone(),
                         session_id,
                         message_count,
+                        emit_reasoning_content,
+                        crate::proxy::mappers::openai::request::collect_tool_schemas(&openai_req.tools),
                     );
 
                     // Peek Logic (Repeated for safety/correctness on this stream type)
@@ -1445,7 +1769,10 @@ pub async fn handle_completions(
             }
 
             let gemini_resp: Value = match response.json().await {
-                Ok(json) => json,
+                Ok(json) => {
+                    crate::proxy::model_versions::record_from_json(&json);
+                    json
+                }
                 Err(e) => {
                     return (
                         StatusCode::BAD_GATEWAY,
@@ -1456,7 +1783,12 @@ pub async fn handle_completions(
                 }
             };
 
-            let chat_resp = transform_openai_response(&gemini_resp, Some("session-123"), 1);
+            let chat_resp = transform_openai_response(
+                &gemini_resp,
+                Some("session-123"),
+                1,
+                &crate::proxy::mappers::openai::request::collect_tool_schemas(&openai_req.tools),
+            );
 
             // Map Chat Response -> Legacy Completions Response
             let choices = chat_resp.choices.iter().map(|c| {
@@ -1523,6 +1855,23 @@ pub async fn handle_completions(
                 .await;
         }
 
+        if status_code == 400
+            && sentinel_probe
+                .as_ref()
+                .map_or(false, |probe| signature_sentinel::observe_rejection(probe, &error_text))
+            && attempt + 1 < max_attempts
+        {
+            continue;
+        }
+
+        // [NEW] 429 / 配额耗尽: 临时屏蔽当前账号并立即轮换
+        if is_rate_limit_error(status_code, &error_text)
+            && attempt + 1 < max_attempts
+            && block_rate_limited_account(&token_manager, &account_id, &email, &mapped_model, retry_after.as_deref(), &error_text)
+        {
+            continue;
+        }
+
         // 确定重试策略
         let strategy = determine_retry_strategy(status_code, &error_text, false);
 
@@ -1544,11 +1893,18 @@ pub async fn handle_completions(
     }
 
     // 所有尝试均失败
+    if let Some(prev) = &last_email {
+        account_attempts.record_failure(prev, &last_error);
+    }
     if let Some(email) = last_email {
         (
             StatusCode::TOO_MANY_REQUESTS,
             [("X-Account-Email", email), ("X-Mapped-Model", mapped_model)],
-            format!("All accounts exhausted. Last error: {}", last_error),
+            format!(
+                "All accounts exhausted after {} account(s). Errors: {}",
+                account_attempts.accounts_tried(),
+                account_attempts.summary()
+            ),
         )
             .into_response()
     } else {
@@ -1671,6 +2027,7 @@ pub async fn handle_images_generations(
     for _ in 0..n {
         let upstream = upstream.clone();
         let token_manager = token_manager.clone();
+        let account_group = account_groups::current();
         let final_prompt = final_prompt.clone();
         let image_config = image_config.clone(); // 使用解析后的完整配置
         let _response_format = response_format.to_string();
@@ -1682,9 +2039,12 @@ pub async fn handle_images_generations(
 
             for attempt in 0..max_attempts {
                 // 4.1 获取 Token
-                let (access_token, project_id, email, account_id, _wait_ms) = match token_manager
-                    .get_token("image_gen", attempt > 0, None, "gemini-3-pro-image")
-                    .await
+                // spawn 的任务不继承请求的分组上下文，需显式传入
+                let (access_token, project_id, email, account_id, _wait_ms) = match account_groups::scope(
+                    account_group.clone(),
+                    token_manager.get_token("image_gen", attempt > 0, None, "gemini-3-pro-image"),
+                )
+                .await
                 {
                     Ok(t) => t,
                     Err(e) => {
@@ -2070,6 +2430,7 @@ pub async fn handle_images_edits(
     for _ in 0..n {
         let upstream = upstream.clone();
         let token_manager = token_manager.clone();
+        let account_group = account_groups::current();
         let contents_parts = contents_parts.clone();
         let image_config = image_config.clone();
         let response_format = response_format.clone();
@@ -2080,9 +2441,12 @@ pub async fn handle_images_edits(
 
             for attempt in 0..max_attempts {
                 // 4.1 获取 Token
-                let (access_token, project_id, email, account_id, _wait_ms) = match token_manager
-                    .get_token("image_gen", attempt > 0, None, "gemini-3-pro-image")
-                    .await
+                // spawn 的任务不继承请求的分组上下文，需显式传入
+                let (access_token, project_id, email, account_id, _wait_ms) = match account_groups::scope(
+                    account_group.clone(),
+                    token_manager.get_token("image_gen", attempt > 0, None, "gemini-3-pro-image"),
+                )
+                .await
                 {
                     Ok(t) => t,
                     Err(e) => {
@@ -98,6 +98,13 @@ pub async fn handle_warmup(
             }
         };
 
+    // [NEW] 与正常流量使用同一账号的 envelope 覆盖 (userAgent / requestType)
+    let envelope = if account_id.is_empty() {
+        state.token_manager.get_envelope_params_by_email(&req.email)
+    } else {
+        state.token_manager.get_envelope_params(&account_id)
+    };
+
     // ===== 步骤 2: 根据模型类型构建请求体 =====
     let is_claude = req.model.to_lowercase().contains("claude");
     let is_image = req.model.to_lowercase().contains("image");
@@ -131,22 +138,26 @@ pub async fn handle_warmup(
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
         match crate::proxy::mappers::claude::transform_claude_request_in(
             &claude_request,
             &project_id,
             false,
+            &envelope,
+            &Default::default(),
         ) {
             Ok(transformed) => transformed,
             Err(e) => {
                 warn!("[Warmup-API] Step 2 FAILED: Claude transform error: {}", e);
                 return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
+                    e.status_code(),
                     Json(WarmupResponse {
                         success: false,
                         message: format!("Transform error: {}", e),
-                        error: Some(e),
+                        error: Some(e.to_string()),
                     }),
                 )
                     .into_response();
@@ -182,7 +193,11 @@ pub async fn handle_warmup(
             })
         };
 
-        wrap_request(&base_request, &project_id, &req.model, Some(&session_id))
+        let mut wrapped = wrap_request(&base_request, &project_id, &req.model, Some(&session_id));
+        let request_type = wrapped["requestType"].as_str().unwrap_or_default().to_string();
+        wrapped["userAgent"] = json!(envelope.user_agent());
+        wrapped["requestType"] = json!(envelope.request_type(&request_type));
+        wrapped
     };
 
     // ===== 步骤 3: 调用 UpstreamClient =====
@@ -253,8 +268,13 @@ pub async fn handle_warmup(
                 response_body: None,
                 input_tokens: Some(0),
                 output_tokens: Some(0),
+                cached_tokens: None,
                 protocol: Some("warmup".to_string()),
                 username: None,
+                request_hash: None,
+                output_breakdown: None,
+                model_version: None,
+                query_overrides: None,
             };
             state.monitor.log_request(log).await;
 
@@ -333,8 +353,13 @@ pub async fn handle_warmup(
                 response_body: None,
                 input_tokens: None,
                 output_tokens: None,
+                cached_tokens: None,
                 protocol: Some("warmup".to_string()),
                 username: None,
+                request_hash: None,
+                output_breakdown: None,
+                model_version: None,
+                query_overrides: None,
             };
             state.monitor.log_request(log).await;
 
@@ -0,0 +1,316 @@
+// 流式首字延迟 (TTFB) SLO 监控
+// 按模型维护最近 N 个流式请求首字延迟的环形缓冲区，p90 超过阈值时标记降级并发送通知，
+// 恢复后清除标记并再次通知。降级告警按模型限频 (cooldown)。
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::proxy::config::LatencySloConfig;
+
+/// SLO 状态变化事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SloEvent {
+    Degraded { model: String, p90_ms: u64 },
+    Recovered { model: String, p90_ms: u64 },
+}
+
+/// 降级中的模型
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct DegradedModel {
+    pub model: String,
+    pub p90_ms: u64,
+}
+
+/// 状态接口返回的 SLO 信息
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct LatencySloStatus {
+    pub degraded: bool,
+    pub threshold_ms: u64,
+    pub models: Vec<DegradedModel>,
+}
+
+struct ModelWindow {
+    /// 环形缓冲区，容量为 window_size
+    samples: Vec<u64>,
+    next: usize,
+    p90_ms: u64,
+    degraded: bool,
+    /// 当前降级期间是否已发出告警 (决定恢复时是否通知)
+    alerted: bool,
+    last_alert: Option<Instant>,
+}
+
+impl ModelWindow {
+    fn new(capacity: usize) -> Self {
+        Self {
+            samples: Vec::with_capacity(capacity),
+            next: 0,
+            p90_ms: 0,
+            degraded: false,
+            alerted: false,
+            last_alert: None,
+        }
+    }
+
+    fn push(&mut self, capacity: usize, value: u64) {
+        if self.samples.capacity() != capacity {
+            // 窗口大小变更后重新统计
+            self.samples = Vec::with_capacity(capacity);
+            self.next = 0;
+        }
+        if self.samples.len() < capacity {
+            self.samples.push(value);
+        } else {
+            self.samples[self.next] = value;
+        }
+        self.next = (self.next + 1) % capacity;
+    }
+}
+
+#[derive(Default)]
+struct SloState {
+    models: HashMap<String, ModelWindow>,
+    /// 计算分位数用的复用缓冲区，避免每次请求分配
+    scratch: Vec<u64>,
+}
+
+#[derive(Default)]
+pub struct LatencySloMonitor {
+    state: Mutex<SloState>,
+}
+
+impl LatencySloMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录一次首字延迟，返回需要通知的状态变化
+    pub fn record(
+        &self,
+        config: &LatencySloConfig,
+        model: &str,
+        ttfb_ms: u64,
+        now: Instant,
+    ) -> Option<SloEvent> {
+        if !config.enabled || config.window_size == 0 {
+            return None;
+        }
+        let capacity = config.window_size;
+
+        let mut guard = self.state.lock();
+        let SloState { models, scratch } = &mut *guard;
+        if !models.contains_key(model) {
+            models.insert(model.to_string(), ModelWindow::new(capacity));
+        }
+        let window = models.get_mut(model)?;
+        window.push(capacity, ttfb_ms);
+
+        // 窗口填满前不做判断，避免少量样本误报
+        if window.samples.len() < capacity {
+            return None;
+        }
+
+        scratch.clear();
+        scratch.extend_from_slice(&window.samples);
+        scratch.sort_unstable();
+        let rank = (scratch.len() * 9).div_ceil(10).max(1);
+        window.p90_ms = scratch[rank - 1];
+
+        if !window.degraded && window.p90_ms > config.threshold_ms {
+            window.degraded = true;
+            let cooldown = Duration::from_secs(config.cooldown_secs);
+            let can_alert = window
+                .last_alert
+                .map_or(true, |t| now.saturating_duration_since(t) >= cooldown);
+            window.alerted = can_alert;
+            if can_alert {
+                window.last_alert = Some(now);
+                return Some(SloEvent::Degraded {
+                    model: model.to_string(),
+                    p90_ms: window.p90_ms,
+                });
+            }
+        } else if window.degraded && window.p90_ms <= config.threshold_ms {
+            window.degraded = false;
+            if std::mem::take(&mut window.alerted) {
+                return Some(SloEvent::Recovered {
+                    model: model.to_string(),
+                    p90_ms: window.p90_ms,
+                });
+            }
+        }
+        None
+    }
+
+    pub fn status(&self, config: &LatencySloConfig) -> LatencySloStatus {
+        let guard = self.state.lock();
+        let mut models: Vec<DegradedModel> = guard
+            .models
+            .iter()
+            .filter(|(_, w)| w.degraded)
+            .map(|(model, w)| DegradedModel {
+                model: model.clone(),
+                p90_ms: w.p90_ms,
+            })
+            .collect();
+        models.sort_by(|a, b| a.model.cmp(&b.model));
+        LatencySloStatus {
+            degraded: !models.is_empty(),
+            threshold_ms: config.threshold_ms,
+            models,
+        }
+    }
+}
+
+static GLOBAL_MONITOR: OnceLock<LatencySloMonitor> = OnceLock::new();
+
+fn global() -> &'static LatencySloMonitor {
+    GLOBAL_MONITOR.get_or_init(LatencySloMonitor::new)
+}
+
+/// 记录流式请求首字延迟，并在状态变化时通过系统集成层发送通知
+pub fn observe(
+    integration: &crate::modules::integration::SystemManager,
+    model: &str,
+    ttfb_ms: u64,
+) {
+    let config = crate::proxy::config::get_latency_slo_config();
+    match global().record(&config, model, ttfb_ms, Instant::now()) {
+        Some(SloEvent::Degraded { model, p90_ms }) => {
+            tracing::warn!(
+                "[Latency-SLO] {} degraded: p90 TTFB {}ms > {}ms",
+                model,
+                p90_ms,
+                config.threshold_ms
+            );
+            integration.show_notification(
+                "Upstream latency degraded",
+                &format!(
+                    "{}: p90 time-to-first-token {:.1}s exceeds {:.1}s",
+                    model,
+                    p90_ms as f64 / 1000.0,
+                    config.threshold_ms as f64 / 1000.0
+                ),
+            );
+        }
+        Some(SloEvent::Recovered { model, p90_ms }) => {
+            tracing::info!("[Latency-SLO] {} recovered: p90 TTFB {}ms", model, p90_ms);
+            integration.show_notification(
+                "Upstream latency recovered",
+                &format!(
+                    "{}: p90 time-to-first-token back to {:.1}s",
+                    model,
+                    p90_ms as f64 / 1000.0
+                ),
+            );
+        }
+        None => {}
+    }
+}
+
+/// 当前 SLO 状态 (供状态接口使用)
+pub fn status() -> LatencySloStatus {
+    global().status(&crate::proxy::config::get_latency_slo_config())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> LatencySloConfig {
+        LatencySloConfig {
+            enabled: true,
+            threshold_ms: 8000,
+            window_size: 10,
+            cooldown_secs: 300,
+        }
+    }
+
+    fn feed(
+        monitor: &LatencySloMonitor,
+        cfg: &LatencySloConfig,
+        latencies: &[u64],
+        now: Instant,
+    ) -> Vec<SloEvent> {
+        latencies
+            .iter()
+            .filter_map(|&ms| monitor.record(cfg, "gemini-3-pro", ms, now))
+            .collect()
+    }
+
+    #[test]
+    fn test_degrades_and_recovers_across_threshold() {
+        let monitor = LatencySloMonitor::new();
+        let cfg = config();
+        let t0 = Instant::now();
+
+        // 健康基线: 不告警
+        assert!(feed(&monitor, &cfg, &[1000; 10], t0).is_empty());
+        assert!(!monitor.status(&cfg).degraded);
+
+        // 一个慢请求不足以推高 p90
+        assert!(feed(&monitor, &cfg, &[20_000], t0).is_empty());
+
+        // 第二个慢请求使 p90 (10 个样本中第 9 个) 超过阈值
+        let events = feed(&monitor, &cfg, &[20_000], t0);
+        assert_eq!(
+            events,
+            vec![SloEvent::Degraded {
+                model: "gemini-3-pro".to_string(),
+                p90_ms: 20_000
+            }]
+        );
+        let status = monitor.status(&cfg);
+        assert!(status.degraded);
+        assert_eq!(status.models[0].model, "gemini-3-pro");
+        assert_eq!(status.models[0].p90_ms, 20_000);
+
+        // 快请求挤出慢样本后恢复
+        let events = feed(&monitor, &cfg, &[1000; 10], t0);
+        assert_eq!(
+            events,
+            vec![SloEvent::Recovered {
+                model: "gemini-3-pro".to_string(),
+                p90_ms: 1000
+            }]
+        );
+        assert!(!monitor.status(&cfg).degraded);
+    }
+
+    #[test]
+    fn test_alerts_rate_limited_by_cooldown() {
+        let monitor = LatencySloMonitor::new();
+        let cfg = config();
+        let t0 = Instant::now();
+
+        assert_eq!(feed(&monitor, &cfg, &[9000; 10], t0).len(), 1);
+        assert_eq!(feed(&monitor, &cfg, &[1000; 10], t0).len(), 1);
+
+        // 冷却期内再次降级: 标记降级但不通知，恢复时也不通知
+        let t1 = t0 + Duration::from_secs(60);
+        assert!(feed(&monitor, &cfg, &[9000; 10], t1).is_empty());
+        assert!(monitor.status(&cfg).degraded);
+        assert!(feed(&monitor, &cfg, &[1000; 10], t1).is_empty());
+
+        // 冷却期过后再次告警
+        let t2 = t0 + Duration::from_secs(301);
+        assert_eq!(feed(&monitor, &cfg, &[9000; 10], t2).len(), 1);
+    }
+
+    #[test]
+    fn test_waits_for_full_window_and_respects_disabled() {
+        let monitor = LatencySloMonitor::new();
+        let mut cfg = config();
+        let t0 = Instant::now();
+
+        assert!(feed(&monitor, &cfg, &[30_000; 9], t0).is_empty());
+        assert!(!monitor.status(&cfg).degraded);
+
+        cfg.enabled = false;
+        assert!(feed(&monitor, &cfg, &[30_000; 5], t0).is_empty());
+        assert!(!monitor.status(&cfg).degraded);
+    }
+}
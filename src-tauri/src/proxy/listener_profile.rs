@@ -0,0 +1,254 @@
+// 监听配置档 (Listener Profiles)
+// 在额外端口上运行代理监听器，每个端口拥有独立的默认行为 (thinking / 安全阈值 / 身份注入 / 允许的客户端 Key)，
+// 所有监听器共享同一个账号池 (TokenManager)。
+// 请求级配置通过 task-local 传递给协议转换器，避免修改所有 mapper 调用点。
+
+use axum::Router;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{oneshot, Mutex};
+
+use crate::proxy::config::{DeltaCoalescingConfig, IdentityInjectionMode, ListenerProfile};
+use crate::proxy::middleware::listener_profile_middleware;
+
+tokio::task_local! {
+    static CURRENT_PROFILE: Arc<ListenerProfile>;
+}
+
+/// 当前请求所属的监听配置档 (主端口请求返回 None)
+pub fn current() -> Option<Arc<ListenerProfile>> {
+    CURRENT_PROFILE.try_with(|p| p.clone()).ok()
+}
+
+/// 在指定配置档上下文中执行 future
+pub async fn scope<F: Future>(profile: Arc<ListenerProfile>, fut: F) -> F::Output {
+    CURRENT_PROFILE.scope(profile, fut).await
+}
+
+/// 是否注入 Antigravity 身份 (默认注入)
+pub fn identity_injection_enabled() -> bool {
+    current()
+        .map(|p| p.identity_injection != IdentityInjectionMode::Off)
+        .unwrap_or(true)
+}
+
+/// 配置档指定的默认 thinking 开关 (None 表示沿用模型默认策略)
+pub fn default_thinking() -> Option<bool> {
+    current().and_then(|p| p.default_thinking)
+}
+
+/// 当前请求生效的流式 delta 合并配置 (配置档覆盖全局配置)
+pub fn delta_coalescing() -> DeltaCoalescingConfig {
+    current()
+        .and_then(|p| p.delta_coalescing)
+        .unwrap_or_else(crate::proxy::config::get_delta_coalescing_config)
+}
+
+/// 配置档指定的安全阈值 (OFF / LOW / MEDIUM / HIGH / NONE)
+pub fn safety_threshold_override() -> Option<String> {
+    current().and_then(|p| p.safety_threshold.clone())
+}
+
+type RouterFactory = Arc<dyn Fn(Arc<ListenerProfile>) -> Router + Send + Sync>;
+
+struct RunningListener {
+    profile: ListenerProfile,
+    shutdown_tx: oneshot::Sender<()>,
+    handle: tokio::task::JoinHandle<()>,
+    local_addr: SocketAddr,
+}
+
+/// 监听配置档管理器
+/// 负责按配置启动/停止额外端口的监听器，配置变更时热更新
+pub struct ProfileListenerManager {
+    host: String,
+    make_router: RouterFactory,
+    running: Mutex<HashMap<String, RunningListener>>,
+}
+
+impl ProfileListenerManager {
+    pub fn new<F>(host: String, make_router: F) -> Self
+    where
+        F: Fn(Arc<ListenerProfile>) -> Router + Send + Sync + 'static,
+    {
+        Self {
+            host,
+            make_router: Arc::new(make_router),
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 将运行中的监听器与配置同步
+    /// 已删除、已禁用或配置有变化的监听器会停止接收新连接 (已建立的连接处理完毕后自然结束)，
+    /// 新增或变化的配置档随后重新绑定端口
+    pub async fn sync(&self, profiles: &[ListenerProfile]) {
+        let mut running = self.running.lock().await;
+
+        let desired: HashMap<&str, &ListenerProfile> = profiles
+            .iter()
+            .filter(|p| p.enabled)
+            .map(|p| (p.name.as_str(), p))
+            .collect();
+
+        let stale: Vec<String> = running
+            .iter()
+            .filter(|(name, r)| desired.get(name.as_str()).map_or(true, |p| **p != r.profile))
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for name in stale {
+            if let Some(listener) = running.remove(&name) {
+                Self::shutdown(listener).await;
+                tracing::info!("[ListenerProfile] 监听配置档 {} 已停止", name);
+            }
+        }
+
+        for profile in desired.values() {
+            if running.contains_key(&profile.name) {
+                continue;
+            }
+            match self.spawn_listener(profile).await {
+                Ok(listener) => {
+                    tracing::info!(
+                        "[ListenerProfile] 监听配置档 {} 启动在 http://{}",
+                        profile.name,
+                        listener.local_addr
+                    );
+                    running.insert(profile.name.clone(), listener);
+                }
+                Err(e) => {
+                    tracing::error!("[ListenerProfile] 监听配置档 {} 启动失败: {}", profile.name, e);
+                }
+            }
+        }
+    }
+
+    /// 停止所有监听配置档
+    pub async fn stop_all(&self) {
+        let mut running = self.running.lock().await;
+        for (_, listener) in running.drain() {
+            Self::shutdown(listener).await;
+        }
+    }
+
+    /// 查询配置档实际绑定的端口 (配置端口为 0 时由系统分配)
+    pub async fn bound_port(&self, name: &str) -> Option<u16> {
+        self.running
+            .lock()
+            .await
+            .get(name)
+            .map(|r| r.local_addr.port())
+    }
+
+    async fn spawn_listener(&self, profile: &ListenerProfile) -> Result<RunningListener, String> {
+        let addr = format!("{}:{}", self.host, profile.port);
+        let listener = tokio::net::TcpListener::bind(&addr)
+            .await
+            .map_err(|e| format!("地址 {} 绑定失败: {}", addr, e))?;
+        let local_addr = listener
+            .local_addr()
+            .map_err(|e| format!("获取监听地址失败: {}", e))?;
+
+        let shared = Arc::new(profile.clone());
+        let app = (self.make_router)(shared.clone()).layer(
+            axum::middleware::from_fn_with_state(shared, listener_profile_middleware),
+        );
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let handle = crate::proxy::server::spawn_accept_loop(listener, app, shutdown_rx);
+
+        Ok(RunningListener {
+            profile: profile.clone(),
+            shutdown_tx,
+            handle,
+            local_addr,
+        })
+    }
+
+    async fn shutdown(listener: RunningListener) {
+        let _ = listener.shutdown_tx.send(());
+        // 等待接收循环退出以释放端口，便于同端口立即重新绑定
+        let _ = listener.handle.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::mappers::claude::{transform_claude_request_in, ClaudeRequest};
+    use crate::proxy::mappers::common_utils::EnvelopeParams;
+    use axum::{routing::post, Json};
+    use serde_json::{json, Value};
+
+    fn profile(name: &str, safety: &str) -> ListenerProfile {
+        ListenerProfile {
+            name: name.to_string(),
+            port: 0,
+            enabled: true,
+            default_thinking: None,
+            safety_threshold: Some(safety.to_string()),
+            identity_injection: IdentityInjectionMode::Auto,
+            allowed_client_keys: Vec::new(),
+            delta_coalescing: None,
+        }
+    }
+
+    // 探针路由: 直接返回转换后的上游请求体
+    fn probe_router(_profile: Arc<ListenerProfile>) -> Router {
+        Router::new().route(
+            "/v1/messages",
+            post(|Json(req): Json<ClaudeRequest>| async move {
+                let body = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default())
+                    .unwrap();
+                Json(body)
+            }),
+        )
+    }
+
+    async fn post_probe(port: u16) -> Value {
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        client
+            .post(format!("http://127.0.0.1:{}/v1/messages", port))
+            .json(&json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 64,
+                "messages": [{ "role": "user", "content": "hello" }]
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json::<Value>()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_profiles_apply_different_safety_settings() {
+        let manager = ProfileListenerManager::new("127.0.0.1".to_string(), probe_router);
+        manager
+            .sync(&[profile("strict", "HIGH"), profile("relaxed", "OFF")])
+            .await;
+
+        let strict_port = manager.bound_port("strict").await.unwrap();
+        let relaxed_port = manager.bound_port("relaxed").await.unwrap();
+        assert_ne!(strict_port, relaxed_port);
+
+        let strict = post_probe(strict_port).await;
+        let relaxed = post_probe(relaxed_port).await;
+        assert_eq!(
+            strict["request"]["safetySettings"][0]["threshold"],
+            "BLOCK_ONLY_HIGH"
+        );
+        assert_eq!(relaxed["request"]["safetySettings"][0]["threshold"], "OFF");
+
+        // 移除配置档后对应监听器停止
+        manager.sync(&[profile("relaxed", "OFF")]).await;
+        assert!(manager.bound_port("strict").await.is_none());
+        assert_eq!(manager.bound_port("relaxed").await, Some(relaxed_port));
+
+        manager.stop_all().await;
+        assert!(manager.bound_port("relaxed").await.is_none());
+    }
+}
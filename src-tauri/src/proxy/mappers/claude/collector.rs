@@ -2,6 +2,8 @@
 // 用于非 Stream 请求的自动转换
 
 use super::models::*;
+use crate::modules::token_stats::OutputTokenBreakdown;
+use crate::proxy::mappers::context_manager::estimate_tokens_from_str;
 use bytes::Bytes;
 use futures::StreamExt;
 use serde_json::{json, Value};
@@ -25,6 +27,72 @@ fn parse_sse_line(line: &str) -> Option<(String, String)> {
     }
 }
 
+/// 按内容类型拆分输出 token
+///
+/// 各类型先按字符估算，再按比例分摊上游报告的 output_tokens，使拆分之和与总数一致。
+/// 上游给出 thoughtsTokenCount 时思考部分直接采用该值 (Gemini 的 candidatesTokenCount
+/// 不含思考，此时 output_tokens 只分摊给可见文本与工具参数)。
+fn build_usage_breakdown(
+    estimated: OutputTokenBreakdown,
+    upstream_thoughts: Option<u32>,
+    output_tokens: u32,
+) -> OutputTokenBreakdown {
+    // 按估算比例分摊 total，取整余数归入占比最大的一项，保证总和不变
+    fn apportion(total: u32, weights: &[u32]) -> Vec<u32> {
+        let sum: u64 = weights.iter().map(|&w| w as u64).sum();
+        if sum == 0 {
+            let mut shares = vec![0; weights.len()];
+            shares[0] = total;
+            return shares;
+        }
+        let mut shares: Vec<u32> = weights
+            .iter()
+            .map(|&w| (total as u64 * w as u64 / sum) as u32)
+            .collect();
+        let rest = total - shares.iter().sum::<u32>();
+        let largest = (0..weights.len()).max_by_key(|&i| weights[i]).unwrap_or(0);
+        shares[largest] += rest;
+        shares
+    }
+
+    if output_tokens == 0 {
+        // 没有上游 usage 时直接使用估算值
+        return OutputTokenBreakdown {
+            thinking_tokens: upstream_thoughts.unwrap_or(estimated.thinking_tokens),
+            ..estimated
+        };
+    }
+
+    match upstream_thoughts {
+        Some(thinking_tokens) => {
+            let shares = apportion(
+                output_tokens,
+                &[estimated.text_tokens, estimated.tool_input_tokens],
+            );
+            OutputTokenBreakdown {
+                thinking_tokens,
+                text_tokens: shares[0],
+                tool_input_tokens: shares[1],
+            }
+        }
+        None => {
+            let shares = apportion(
+                output_tokens,
+                &[
+                    estimated.text_tokens,
+                    estimated.thinking_tokens,
+                    estimated.tool_input_tokens,
+                ],
+            );
+            OutputTokenBreakdown {
+                text_tokens: shares[0],
+                thinking_tokens: shares[1],
+                tool_input_tokens: shares[2],
+            }
+        }
+    }
+}
+
 /// 将 SSE Stream 收集为完整的 Claude Response
 ///
 /// 此函数接收一个 SSE 字节流，解析所有事件，并重建完整的 ClaudeResponse 对象。
@@ -83,6 +151,8 @@ where
             cache_creation_input_tokens: None,
             server_tool_use: None,
         },
+        requested_model: None,
+        usage_breakdown: None,
     };
 
     // 用于累积内容块
@@ -91,6 +161,10 @@ where
     let mut current_signature: Option<String> = None;
     let mut current_tool_use: Option<Value> = None;
     let mut current_tool_input = String::new();
+    let mut current_image: Option<ImageSource> = None;
+    // [NEW] 各类型内容的 token 估算与上游思考 token 数
+    let mut estimated = OutputTokenBreakdown::default();
+    let mut upstream_thoughts: Option<u32> = None;
 
     for event in events {
         match event.event_type.as_str() {
@@ -103,6 +177,9 @@ where
                     if let Some(model) = message.get("model").and_then(|v| v.as_str()) {
                         response.model = model.to_string();
                     }
+                    if let Some(requested) = message.get("requested_model").and_then(|v| v.as_str()) {
+                        response.requested_model = Some(requested.to_string());
+                    }
                     if let Some(usage) = message.get("usage") {
                         if let Ok(u) = serde_json::from_value::<Usage>(usage.clone()) {
                             response.usage = u;
@@ -127,6 +204,12 @@ where
                                 current_tool_use = Some(content_block.clone());
                                 current_tool_input.clear();
                             }
+                            // [NEW] 多模态输出的图片块 (数据完整包含在 content_block_start 中)
+                            "image" => {
+                                current_image = content_block
+                                    .get("source")
+                                    .and_then(|s| serde_json::from_value(s.clone()).ok());
+                            }
                             _ => {}
                         }
                     }
@@ -165,11 +248,13 @@ where
             "content_block_stop" => {
                 // 完成当前块
                 if !current_text.is_empty() {
+                    estimated.text_tokens += estimate_tokens_from_str(&current_text);
                     response.content.push(ContentBlock::Text {
                         text: current_text.clone(),
                     });
                     current_text.clear();
                 } else if !current_thinking.is_empty() {
+                    estimated.thinking_tokens += estimate_tokens_from_str(&current_thinking);
                     response.content.push(ContentBlock::Thinking {
                         thinking: current_thinking.clone(),
                         signature: current_signature.take(),
@@ -180,6 +265,7 @@ where
                     // 构建 tool_use 块
                     let id = tool_use.get("id").and_then(|v| v.as_str()).unwrap_or("unknown").to_string();
                     let name = tool_use.get("name").and_then(|v| v.as_str()).unwrap_or("unknown").to_string();
+                    estimated.tool_input_tokens += estimate_tokens_from_str(&current_tool_input);
                     let input = if !current_tool_input.is_empty() {
                         serde_json::from_str(&current_tool_input).unwrap_or(json!({}))
                     } else {
@@ -194,6 +280,11 @@ where
                         cache_control: None,
                     });
                     current_tool_input.clear();
+                } else if let Some(source) = current_image.take() {
+                    response.content.push(ContentBlock::Image {
+                        source,
+                        cache_control: None,
+                    });
                 }
             }
 
@@ -202,12 +293,20 @@ where
                     if let Some(stop_reason) = delta.get("stop_reason").and_then(|v| v.as_str()) {
                         response.stop_reason = stop_reason.to_string();
                     }
+                    // [NEW] 命中的客户端停止序列
+                    response.stop_sequence = delta
+                        .get("stop_sequence")
+                        .and_then(|v| v.as_str())
+                        .map(|s| s.to_string());
                 }
                 if let Some(usage) = event.data.get("usage") {
                     if let Ok(u) = serde_json::from_value::<Usage>(usage.clone()) {
                         response.usage = u;
                     }
                 }
+                if let Some(thoughts) = event.data.get(super::streaming::THOUGHTS_TOKENS_FIELD).and_then(|v| v.as_u64()) {
+                    upstream_thoughts = Some(thoughts as u32);
+                }
             }
 
             "message_stop" => {
@@ -228,6 +327,14 @@ where
         }
     }
 
+    if estimated.total() > 0 || upstream_thoughts.is_some() {
+        response.usage_breakdown = Some(build_usage_breakdown(
+            estimated,
+            upstream_thoughts,
+            response.usage.output_tokens,
+        ));
+    }
+
     Ok(response)
 }
 
@@ -299,4 +406,87 @@ mod tests {
             panic!("Expected Thinking block");
         }
     }
+
+    #[tokio::test]
+    async fn test_collect_text_and_image_blocks() {
+        let sse_data = vec![
+            "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_img\",\"type\":\"message\",\"role\":\"assistant\",\"model\":\"gemini-3-pro-image\",\"content\":[],\"stop_reason\":null,\"usage\":{\"input_tokens\":10,\"output_tokens\":0}}}\n\n",
+            "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"A cat\"}}\n\n",
+            "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+            "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"image\",\"source\":{\"type\":\"base64\",\"media_type\":\"image/png\",\"data\":\"iVBORw0KGgo=\"}}}\n\n",
+            "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":1}\n\n",
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+        ];
+
+        let byte_stream = stream::iter(
+            sse_data.into_iter().map(|s| Ok::<Bytes, io::Error>(Bytes::from(s)))
+        );
+
+        let response = collect_stream_to_json(byte_stream).await.unwrap();
+        assert_eq!(response.content.len(), 2);
+        assert!(matches!(&response.content[0], ContentBlock::Text { text } if text == "A cat"));
+        if let ContentBlock::Image { source, .. } = &response.content[1] {
+            assert_eq!(source.media_type, "image/png");
+            assert_eq!(source.data, "iVBORw0KGgo=");
+        } else {
+            panic!("Expected Image block");
+        }
+    }
+
+    fn mixed_block_stream(message_delta: &'static str) -> Vec<&'static str> {
+        vec![
+            "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_mix\",\"type\":\"message\",\"role\":\"assistant\",\"model\":\"claude-sonnet-4-5\",\"content\":[],\"stop_reason\":null,\"usage\":{\"input_tokens\":10,\"output_tokens\":0}}}\n\n",
+            "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"thinking\",\"thinking\":\"\"}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"thinking_delta\",\"thinking\":\"The user wants the weather, so I should call the weather tool for Paris first.\"}}\n\n",
+            "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+            "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"text_delta\",\"text\":\"Let me check the weather for you.\"}}\n\n",
+            "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":1}\n\n",
+            "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":2,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"weather\",\"input\":{}}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":2,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"city\\\":\\\"Paris\\\"}\"}}\n\n",
+            "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":2}\n\n",
+            message_delta,
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+        ]
+    }
+
+    async fn collect(sse_data: Vec<&'static str>) -> ClaudeResponse {
+        let byte_stream = stream::iter(
+            sse_data.into_iter().map(|s| Ok::<Bytes, io::Error>(Bytes::from(s)))
+        );
+        collect_stream_to_json(byte_stream).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_usage_breakdown_sums_to_output_tokens() {
+        let response = collect(mixed_block_stream(
+            "event: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"input_tokens\":10,\"output_tokens\":40}}\n\n",
+        ))
+        .await;
+
+        let breakdown = response.usage_breakdown.expect("breakdown attached");
+        assert!(breakdown.thinking_tokens > 0);
+        assert!(breakdown.text_tokens > 0);
+        assert!(breakdown.tool_input_tokens > 0);
+        assert_eq!(breakdown.total(), response.usage.output_tokens);
+
+        // 扩展字段随消息序列化
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["_abv_usage_breakdown"]["thinking_tokens"], breakdown.thinking_tokens);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_thoughts_count_takes_precedence() {
+        let response = collect(mixed_block_stream(
+            "event: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"input_tokens\":10,\"output_tokens\":20},\"_abv_thoughts_tokens\":333}\n\n",
+        ))
+        .await;
+
+        let breakdown = response.usage_breakdown.unwrap();
+        assert_eq!(breakdown.thinking_tokens, 333);
+        // candidatesTokenCount 不含思考: 可见文本与工具参数分摊 output_tokens
+        assert_eq!(breakdown.text_tokens + breakdown.tool_input_tokens, 20);
+        assert_eq!(breakdown.total(), 333 + response.usage.output_tokens);
+    }
 }
@@ -0,0 +1,327 @@
+// 流式 delta 后处理管线
+// PartProcessor 产出的每个 delta 先按类别 (Thinking / Visible / ToolInput) 分类，
+// 再按类别路由到对应的后处理器:
+// - stop sequence 检测只作用于用户可见文本，思考内容中出现停止序列不会截断流
+// - 可能是停止序列开头的可见文本先暂存，确认不是停止序列后再发送 (块结束时补发)
+// - 脱敏规则可按类别配置，标记为 visible-only 的规则不会处理 (通常很长的) 思考内容
+// - 输出 token 估算统计所有类别
+// - assistant 预填充时，先裁掉模型对预填充文本的复述，再做停止序列检测
+
+use super::prefill::PrefillEcho;
+use crate::proxy::config::RedactionClass;
+use crate::proxy::mappers::context_manager::estimate_tokens_from_str;
+use regex::Regex;
+
+/// delta 类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeltaClass {
+    /// thinking_delta (用户不可见的推理内容)
+    Thinking,
+    /// text_delta (用户可见文本)
+    Visible,
+    /// input_json_delta (工具参数)
+    ToolInput,
+}
+
+impl DeltaClass {
+    /// 根据 Claude delta 类型分类，返回 (类别, 承载文本的字段名)
+    /// signature_delta / citations_delta 等不参与后处理，返回 None
+    pub fn from_delta_type(delta_type: &str) -> Option<(Self, &'static str)> {
+        match delta_type {
+            "thinking_delta" => Some((Self::Thinking, "thinking")),
+            "text_delta" => Some((Self::Visible, "text")),
+            "input_json_delta" => Some((Self::ToolInput, "partial_json")),
+            _ => None,
+        }
+    }
+}
+
+impl From<RedactionClass> for DeltaClass {
+    fn from(class: RedactionClass) -> Self {
+        match class {
+            RedactionClass::Thinking => Self::Thinking,
+            RedactionClass::Visible => Self::Visible,
+            RedactionClass::ToolInput => Self::ToolInput,
+        }
+    }
+}
+
+/// 脱敏规则
+#[derive(Debug, Clone)]
+pub struct RedactionRule {
+    pattern: Regex,
+    replacement: String,
+    classes: Vec<DeltaClass>,
+}
+
+impl RedactionRule {
+    pub fn new(pattern: &str, replacement: &str, classes: &[DeltaClass]) -> Result<Self, String> {
+        let pattern = Regex::new(pattern).map_err(|e| format!("Invalid redaction pattern: {}", e))?;
+        Ok(Self {
+            pattern,
+            replacement: replacement.to_string(),
+            classes: classes.to_vec(),
+        })
+    }
+
+    /// 仅作用于用户可见文本的规则
+    pub fn visible_only(pattern: &str, replacement: &str) -> Result<Self, String> {
+        Self::new(pattern, replacement, &[DeltaClass::Visible])
+    }
+
+    fn applies_to(&self, class: DeltaClass) -> bool {
+        self.classes.contains(&class)
+    }
+}
+
+/// 编译配置中的流式脱敏规则 (stream_redactions)，无效的正则跳过并告警
+pub fn configured_redactions() -> Vec<RedactionRule> {
+    crate::proxy::config::get_stream_redactions()
+        .iter()
+        .filter_map(|rule| {
+            let classes: Vec<DeltaClass> = rule.classes.iter().map(|c| DeltaClass::from(*c)).collect();
+            RedactionRule::new(&rule.pattern, &rule.replacement, &classes)
+                .map_err(|e| tracing::warn!("[Stream-Redaction] Skipping rule '{}': {}", rule.pattern, e))
+                .ok()
+        })
+        .collect()
+}
+
+/// 类型化 delta 后处理管线 (每个流一个实例)
+#[derive(Debug, Default)]
+pub struct DeltaPipeline {
+    /// 客户端请求的 stop_sequences
+    stop_sequences: Vec<String>,
+    /// 按类别生效的脱敏规则
+    redactions: Vec<RedactionRule>,
+    /// 尚未发送的可见文本 (可能是某个停止序列的开头)
+    pending_visible: String,
+    /// 命中的停止序列 (命中后不再发送任何内容 delta)
+    stop_sequence: Option<String>,
+    output_tokens: u32,
+    /// 预填充复述裁剪 (仅请求以 assistant 消息结尾时存在)
+    prefill_echo: Option<PrefillEcho>,
+}
+
+impl DeltaPipeline {
+    pub fn new(stop_sequences: Vec<String>) -> Self {
+        Self {
+            stop_sequences: stop_sequences.into_iter().filter(|s| !s.is_empty()).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// 启用脱敏规则
+    pub fn with_redactions(mut self, redactions: Vec<RedactionRule>) -> Self {
+        self.redactions = redactions;
+        self
+    }
+
+    /// 启用预填充复述裁剪
+    pub fn with_prefill(mut self, prefill: Option<String>) -> Self {
+        self.prefill_echo = prefill.map(PrefillEcho::new);
+        self
+    }
+
+    /// 命中的停止序列
+    pub fn stop_sequence(&self) -> Option<&str> {
+        self.stop_sequence.as_deref()
+    }
+
+    /// 已发送内容的输出 token 估算 (包含思考、可见文本与工具参数)
+    pub fn estimated_output_tokens(&self) -> u32 {
+        self.output_tokens
+    }
+
+    /// 处理一个 delta，返回应发送的文本；流已因停止序列结束时返回 None
+    pub fn process(&mut self, class: DeltaClass, text: &str) -> Option<String> {
+        if self.stop_sequence.is_some() {
+            return None;
+        }
+
+        let mut out = match (&mut self.prefill_echo, class) {
+            (Some(echo), DeltaClass::Visible) => echo.process(text),
+            _ => text.to_string(),
+        };
+        out = self.apply_redactions(class, out);
+        if class == DeltaClass::Visible {
+            out = self.apply_stop_sequences(out);
+        }
+
+        self.output_tokens += estimate_tokens_from_str(&out);
+        Some(out)
+    }
+
+    /// 文本块结束时取出暂存的可见文本 (已命中停止序列时丢弃)
+    /// 预填充裁剪中仍在判断的文本先经过停止序列检测，再与停止序列阶段暂存的文本一起发送
+    pub fn flush_visible(&mut self) -> Option<String> {
+        if self.stop_sequence.is_some() {
+            self.pending_visible.clear();
+            return None;
+        }
+        let released = self
+            .prefill_echo
+            .as_mut()
+            .map(PrefillEcho::flush)
+            .unwrap_or_default();
+        let released = self.apply_redactions(DeltaClass::Visible, released);
+        let mut out = self.apply_stop_sequences(released);
+        let pending = std::mem::take(&mut self.pending_visible);
+        if self.stop_sequence.is_none() {
+            out.push_str(&pending);
+        }
+        if out.is_empty() {
+            return None;
+        }
+        self.output_tokens += estimate_tokens_from_str(&out);
+        Some(out)
+    }
+
+    /// 依次应用作用于该类别的脱敏规则
+    fn apply_redactions(&self, class: DeltaClass, mut text: String) -> String {
+        for rule in self.redactions.iter().filter(|r| r.applies_to(class)) {
+            if let std::borrow::Cow::Owned(replaced) =
+                rule.pattern.replace_all(&text, rule.replacement.as_str())
+            {
+                text = replaced;
+            }
+        }
+        text
+    }
+
+    /// 在可见文本中检测停止序列，命中时截断到停止序列之前 (不含停止序列本身)；
+    /// 末尾可能是停止序列开头的部分暂存到下一个 delta
+    fn apply_stop_sequences(&mut self, text: String) -> String {
+        if self.stop_sequences.is_empty() {
+            return text;
+        }
+
+        let mut combined = std::mem::take(&mut self.pending_visible);
+        combined.push_str(&text);
+        let hit = self
+            .stop_sequences
+            .iter()
+            .filter_map(|seq| combined.find(seq.as_str()).map(|pos| (pos, seq)))
+            .min_by_key(|(pos, _)| *pos);
+
+        if let Some((pos, seq)) = hit {
+            self.stop_sequence = Some(seq.clone());
+            combined.truncate(pos);
+            return combined;
+        }
+
+        let held = self
+            .stop_sequences
+            .iter()
+            .filter_map(|seq| {
+                (1..seq.len())
+                    .rev()
+                    .filter(|&k| seq.is_char_boundary(k))
+                    .find(|&k| combined.ends_with(&seq[..k]))
+            })
+            .max()
+            .unwrap_or(0);
+        self.pending_visible = combined.split_off(combined.len() - held);
+        combined
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::config::StreamRedactionRule;
+
+    #[test]
+    fn test_stop_sequence_inside_thought_does_not_stop_stream() {
+        let mut pipeline = DeltaPipeline::new(vec!["\n\nHuman:".to_string()]);
+
+        let thought = pipeline.process(DeltaClass::Thinking, "maybe reply with\n\nHuman: hi");
+        assert_eq!(thought.as_deref(), Some("maybe reply with\n\nHuman: hi"));
+        assert_eq!(pipeline.stop_sequence(), None);
+
+        // 可见文本跨 delta 命中停止序列: 可能的开头先暂存，命中后截断并结束后续内容
+        assert_eq!(pipeline.process(DeltaClass::Visible, "Done.\n\nHu").as_deref(), Some("Done."));
+        assert_eq!(pipeline.process(DeltaClass::Visible, "man: next").as_deref(), Some(""));
+        assert_eq!(pipeline.stop_sequence(), Some("\n\nHuman:"));
+        assert_eq!(pipeline.process(DeltaClass::Visible, "more"), None);
+        assert_eq!(pipeline.process(DeltaClass::Thinking, "more"), None);
+        assert_eq!(pipeline.flush_visible(), None);
+
+        // 输出 token 估算统计所有类别
+        assert!(pipeline.estimated_output_tokens() > estimate_tokens_from_str("Done."));
+    }
+
+    #[test]
+    fn test_visible_only_redaction_leaves_thoughts_untouched() {
+        let rule = RedactionRule::visible_only(r"sk-[A-Za-z0-9]+", "[REDACTED]").unwrap();
+        let mut pipeline = DeltaPipeline::new(vec![]).with_redactions(vec![rule]);
+
+        assert_eq!(
+            pipeline.process(DeltaClass::Thinking, "the key sk-abc123 looks valid").as_deref(),
+            Some("the key sk-abc123 looks valid")
+        );
+        assert_eq!(
+            pipeline.process(DeltaClass::Visible, "use sk-abc123").as_deref(),
+            Some("use [REDACTED]")
+        );
+        assert_eq!(
+            pipeline.process(DeltaClass::ToolInput, r#"{"key":"sk-abc123"}"#).as_deref(),
+            Some(r#"{"key":"sk-abc123"}"#)
+        );
+
+        // 输出 token 估算统计所有类别
+        assert!(pipeline.estimated_output_tokens() > estimate_tokens_from_str("use [REDACTED]"));
+    }
+
+    #[test]
+    fn test_configured_redaction_classes() {
+        let rules: Vec<StreamRedactionRule> = serde_json::from_value(serde_json::json!([
+            { "pattern": "secret", "classes": ["thinking", "tool_input"] },
+            { "pattern": "(unclosed" }
+        ]))
+        .unwrap();
+        assert_eq!(rules[1].classes, vec![RedactionClass::Visible]);
+
+        let rule = &rules[0];
+        let classes: Vec<DeltaClass> = rule.classes.iter().map(|c| DeltaClass::from(*c)).collect();
+        let compiled = RedactionRule::new(&rule.pattern, &rule.replacement, &classes).unwrap();
+        assert!(RedactionRule::new(&rules[1].pattern, &rules[1].replacement, &[]).is_err());
+
+        let mut pipeline = DeltaPipeline::new(vec![]).with_redactions(vec![compiled]);
+        assert_eq!(pipeline.process(DeltaClass::Thinking, "a secret").as_deref(), Some("a [REDACTED]"));
+        assert_eq!(pipeline.process(DeltaClass::ToolInput, "secret").as_deref(), Some("[REDACTED]"));
+        assert_eq!(pipeline.process(DeltaClass::Visible, "secret").as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn test_held_prefix_is_released_when_not_a_stop_sequence() {
+        let mut pipeline = DeltaPipeline::new(vec!["END".to_string()]);
+
+        assert_eq!(pipeline.process(DeltaClass::Visible, "the E").as_deref(), Some("the "));
+        assert_eq!(pipeline.process(DeltaClass::Visible, "N").as_deref(), Some(""));
+        assert_eq!(pipeline.process(DeltaClass::Visible, "D").as_deref(), Some(""));
+        assert_eq!(pipeline.stop_sequence(), Some("END"));
+
+        let mut pipeline = DeltaPipeline::new(vec!["END".to_string()]);
+        assert_eq!(pipeline.process(DeltaClass::Visible, "the E").as_deref(), Some("the "));
+        assert_eq!(pipeline.process(DeltaClass::Visible, "Nd").as_deref(), Some("ENd"));
+        assert_eq!(pipeline.process(DeltaClass::Visible, " of E").as_deref(), Some(" of "));
+        // 块结束时补发仍在暂存的文本
+        assert_eq!(pipeline.flush_visible().as_deref(), Some("E"));
+        assert_eq!(pipeline.stop_sequence(), None);
+    }
+
+    #[test]
+    fn test_prefill_held_text_is_flushed_at_block_end() {
+        // 输出停在预填充中途: 块结束时仍要发送，且经过停止序列检测
+        let mut pipeline = DeltaPipeline::new(vec![]).with_prefill(Some("Answer: yes".to_string()));
+        assert_eq!(pipeline.process(DeltaClass::Visible, "Answer").as_deref(), Some(""));
+        assert_eq!(pipeline.flush_visible().as_deref(), Some("Answer"));
+        assert!(pipeline.estimated_output_tokens() > 0);
+
+        let mut pipeline = DeltaPipeline::new(vec!["wer".to_string()]).with_prefill(Some("Answer: yes".to_string()));
+        assert_eq!(pipeline.process(DeltaClass::Visible, "Answer").as_deref(), Some(""));
+        assert_eq!(pipeline.flush_visible().as_deref(), Some("Ans"));
+        assert_eq!(pipeline.stop_sequence(), Some("wer"));
+    }
+}
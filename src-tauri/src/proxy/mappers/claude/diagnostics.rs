@@ -0,0 +1,160 @@
+// 流式转换诊断记录
+// 保存最近若干次流式转换中的异常处理路径 (如截断的工具调用参数被修复或降级为文本)
+// 以及毒消息隔离决策，用于排查客户端 agent 循环意外中断的原因。仅保存在内存中。
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+/// 保留的最近记录条数
+const MAX_RECORDS: usize = 200;
+
+/// 截断的工具调用的处理结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncatedToolUseOutcome {
+    /// 参数 JSON 修复成功，按 tool_use 发送
+    Repaired,
+    /// 无法修复，原始片段以文本块发送
+    ConvertedToText,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TruncatedToolUseRecord {
+    pub timestamp: i64,
+    pub tool_name: String,
+    pub outcome: TruncatedToolUseOutcome,
+    /// 截断时已累积的参数长度 (字节)
+    pub fragment_len: usize,
+}
+
+/// 毒消息隔离的处理结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PoisonQuarantineOutcome {
+    /// 按会话缓存预先替换已知问题消息
+    PreApplied,
+    /// 二分探测找到问题消息，替换后重试
+    Isolated,
+    /// 二分探测未找到问题消息，返回原始错误
+    NoCulprit,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PoisonQuarantineRecord {
+    pub timestamp: i64,
+    pub trace_id: String,
+    pub session_id: String,
+    pub outcome: PoisonQuarantineOutcome,
+    /// 被替换的消息下标 (仅 Isolated)
+    pub culprits: Vec<usize>,
+    /// 被替换的消息数量
+    pub replaced: usize,
+    /// 发起的探测请求数 (仅 Isolated)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probes: Option<usize>,
+}
+
+static RECORDS: OnceLock<Mutex<VecDeque<TruncatedToolUseRecord>>> = OnceLock::new();
+static QUARANTINE_RECORDS: OnceLock<Mutex<VecDeque<PoisonQuarantineRecord>>> = OnceLock::new();
+
+fn records() -> &'static Mutex<VecDeque<TruncatedToolUseRecord>> {
+    RECORDS.get_or_init(|| Mutex::new(VecDeque::new()))
+}
+
+fn quarantine_records() -> &'static Mutex<VecDeque<PoisonQuarantineRecord>> {
+    QUARANTINE_RECORDS.get_or_init(|| Mutex::new(VecDeque::new()))
+}
+
+fn push_bounded<T>(records: &Mutex<VecDeque<T>>, record: T) {
+    let mut records = records.lock();
+    if records.len() >= MAX_RECORDS {
+        records.pop_front();
+    }
+    records.push_back(record);
+}
+
+pub fn record_truncated_tool_use(
+    tool_name: &str,
+    outcome: TruncatedToolUseOutcome,
+    fragment_len: usize,
+) {
+    push_bounded(
+        records(),
+        TruncatedToolUseRecord {
+            timestamp: chrono::Utc::now().timestamp(),
+            tool_name: tool_name.to_string(),
+            outcome,
+            fragment_len,
+        },
+    );
+}
+
+/// 最近的截断工具调用记录 (按时间顺序)
+pub fn recent_truncated_tool_uses() -> Vec<TruncatedToolUseRecord> {
+    records().lock().iter().cloned().collect()
+}
+
+/// 记录一次毒消息隔离决策，返回该记录 (同时写入调试日志)
+pub fn record_poison_quarantine(
+    trace_id: &str,
+    session_id: &str,
+    outcome: PoisonQuarantineOutcome,
+    culprits: &[usize],
+    replaced: usize,
+    probes: Option<usize>,
+) -> PoisonQuarantineRecord {
+    let record = PoisonQuarantineRecord {
+        timestamp: chrono::Utc::now().timestamp(),
+        trace_id: trace_id.to_string(),
+        session_id: session_id.to_string(),
+        outcome,
+        culprits: culprits.to_vec(),
+        replaced,
+        probes,
+    };
+    push_bounded(quarantine_records(), record.clone());
+    record
+}
+
+/// 最近的毒消息隔离记录 (按时间顺序)
+pub fn recent_poison_quarantines() -> Vec<PoisonQuarantineRecord> {
+    quarantine_records().lock().iter().cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poison_quarantine_decisions_are_recorded() {
+        // 使用独立的会话 ID，避免与并行运行的测试互相干扰
+        let session_id = "sid-diagnostics-quarantine";
+        let isolated = record_poison_quarantine(
+            "trace-isolated",
+            session_id,
+            PoisonQuarantineOutcome::Isolated,
+            &[11],
+            1,
+            Some(5),
+        );
+        record_poison_quarantine("trace-pre", session_id, PoisonQuarantineOutcome::PreApplied, &[], 1, None);
+
+        let recorded: Vec<_> = recent_poison_quarantines()
+            .into_iter()
+            .filter(|r| r.session_id == session_id)
+            .collect();
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[0].outcome, PoisonQuarantineOutcome::Isolated);
+        assert_eq!(recorded[0].culprits, vec![11]);
+        assert_eq!(recorded[1].outcome, PoisonQuarantineOutcome::PreApplied);
+        assert_eq!(recorded[1].replaced, 1);
+
+        let json = serde_json::to_value(&isolated).unwrap();
+        assert_eq!(json["outcome"], "isolated");
+        assert_eq!(json["probes"], 5);
+        let pre = serde_json::to_value(&recorded[1]).unwrap();
+        assert!(pre.get("probes").is_none());
+    }
+}
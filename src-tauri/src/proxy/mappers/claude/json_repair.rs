@@ -0,0 +1,259 @@
+// 截断 JSON 的有限修复
+// 上游在输出工具参数途中断开时，累积的参数 JSON 不完整。这里只做有限的修复:
+// 闭合未结束的字符串、补齐括号，必要时回退到最后一个完整值之后的位置 (丢弃残缺的键 / 尾随逗号)。
+
+use serde_json::Value;
+
+/// 可修复的最大输入长度
+const MAX_REPAIR_INPUT: usize = 1024 * 1024;
+/// 可修复的最大嵌套深度
+const MAX_DEPTH: usize = 128;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Container {
+    Object,
+    Array,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Expect {
+    Key,
+    Colon,
+    Value,
+    CommaOrEnd,
+}
+
+struct Frame {
+    container: Container,
+    expect: Expect,
+}
+
+fn closers(stack: &[Frame]) -> String {
+    stack
+        .iter()
+        .rev()
+        .map(|f| match f.container {
+            Container::Object => '}',
+            Container::Array => ']',
+        })
+        .collect()
+}
+
+fn value_done(stack: &mut [Frame]) {
+    if let Some(top) = stack.last_mut() {
+        top.expect = Expect::CommaOrEnd;
+    }
+}
+
+/// 去掉字符串末尾不完整的转义序列 (`\` 或 `\u12`)
+fn trim_partial_escape(s: &str) -> &str {
+    let bytes = s.as_bytes();
+    let mut end = bytes.len();
+    // 末尾连续反斜杠为奇数个时，最后一个是悬空的转义符
+    let trailing = bytes.iter().rev().take_while(|b| **b == b'\\').count();
+    if trailing % 2 == 1 {
+        return &s[..end - 1];
+    }
+    if let Some(pos) = s.rfind("\\u") {
+        let hex = &s[pos + 2..];
+        let backslashes = bytes[..pos]
+            .iter()
+            .rev()
+            .take_while(|b| **b == b'\\')
+            .count();
+        if backslashes % 2 == 0 && hex.len() < 4 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            end = pos;
+        }
+    }
+    &s[..end]
+}
+
+/// 修复被截断的 JSON 对象 / 数组；无法在保留内容的前提下修复时返回 None
+pub fn repair_truncated_json(partial: &str) -> Option<Value> {
+    let input = partial.trim_start();
+    if input.is_empty() || input.len() > MAX_REPAIR_INPUT {
+        return None;
+    }
+    if let Ok(value) = serde_json::from_str::<Value>(input) {
+        return Some(value);
+    }
+    if !input.starts_with('{') && !input.starts_with('[') {
+        return None;
+    }
+
+    let mut stack: Vec<Frame> = Vec::new();
+    // 最后一个可安全截断的位置 (该位置之后补齐括号即为合法 JSON)
+    let mut safe: Option<(usize, String)> = None;
+    let mut in_string = false;
+    let mut string_is_key = false;
+    let mut escaped = false;
+    let mut scalar_pending = false;
+
+    for (i, c) in input.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+                continue;
+            }
+            match c {
+                '\\' => escaped = true,
+                '"' => {
+                    in_string = false;
+                    if string_is_key {
+                        stack.last_mut()?.expect = Expect::Colon;
+                    } else {
+                        value_done(&mut stack);
+                        safe = Some((i + 1, closers(&stack)));
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '{' | '[' => {
+                if stack.len() >= MAX_DEPTH {
+                    return None;
+                }
+                let (container, expect) = if c == '{' {
+                    (Container::Object, Expect::Key)
+                } else {
+                    (Container::Array, Expect::Value)
+                };
+                stack.push(Frame { container, expect });
+                safe = Some((i + 1, closers(&stack)));
+            }
+            '}' | ']' => {
+                let expected = if c == '}' {
+                    Container::Object
+                } else {
+                    Container::Array
+                };
+                if stack.pop()?.container != expected {
+                    return None;
+                }
+                scalar_pending = false;
+                value_done(&mut stack);
+                safe = Some((i + 1, closers(&stack)));
+            }
+            '"' => {
+                in_string = true;
+                string_is_key = stack.last().map_or(false, |f| {
+                    f.container == Container::Object && f.expect == Expect::Key
+                });
+            }
+            ':' => stack.last_mut()?.expect = Expect::Value,
+            ',' => {
+                if scalar_pending {
+                    // 数字 / 字面量在逗号处结束
+                    value_done(&mut stack);
+                    safe = Some((i, closers(&stack)));
+                    scalar_pending = false;
+                }
+                let top = stack.last_mut()?;
+                top.expect = match top.container {
+                    Container::Object => Expect::Key,
+                    Container::Array => Expect::Value,
+                };
+            }
+            c if c.is_whitespace() => {}
+            _ => scalar_pending = true,
+        }
+    }
+
+    // 修复结果中所有内容都被丢弃时视为失败
+    let parse = |candidate: String| {
+        serde_json::from_str::<Value>(&candidate)
+            .ok()
+            .filter(|value| match value {
+                Value::Object(map) => !map.is_empty(),
+                Value::Array(items) => !items.is_empty(),
+                _ => true,
+            })
+    };
+
+    // 1. 截断在字符串值中: 保留已有内容并闭合字符串
+    if in_string && !string_is_key {
+        let body = trim_partial_escape(input);
+        if let Some(value) = parse(format!("{}\"{}", body, closers(&stack))) {
+            return Some(value);
+        }
+    }
+    // 2. 截断在完整的数字 / 字面量之后: 直接补齐括号
+    if !in_string {
+        if let Some(value) = parse(format!("{}{}", input, closers(&stack))) {
+            return Some(value);
+        }
+    }
+    // 3. 回退到最后一个完整值之后
+    let (len, suffix) = safe?;
+    parse(format!("{}{}", &input[..len], suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_repair_truncation_points() {
+        // 字符串中间截断
+        assert_eq!(
+            repair_truncated_json(
+                r#"{"file_path": "/src/main.rs", "content": "fn main() {\n    println!(\"hi"#
+            ),
+            Some(
+                json!({ "file_path": "/src/main.rs", "content": "fn main() {\n    println!(\"hi" })
+            )
+        );
+        // 悬空的转义符被丢弃
+        assert_eq!(
+            repair_truncated_json(r#"{"content": "line\"#),
+            Some(json!({ "content": "line" }))
+        );
+        assert_eq!(
+            repair_truncated_json(r#"{"content": "caf\u00"#),
+            Some(json!({ "content": "caf" }))
+        );
+
+        // 对象键之间截断
+        assert_eq!(
+            repair_truncated_json(r#"{"command": "ls -la", "#),
+            Some(json!({ "command": "ls -la" }))
+        );
+        assert_eq!(
+            repair_truncated_json(r#"{"command": "ls -la", "timeo"#),
+            Some(json!({ "command": "ls -la" }))
+        );
+        assert_eq!(
+            repair_truncated_json(r#"{"command": "ls -la", "timeout": "#),
+            Some(json!({ "command": "ls -la" }))
+        );
+
+        // 数组中间截断
+        assert_eq!(
+            repair_truncated_json(r#"{"paths": ["a.rs", "b.rs", "#),
+            Some(json!({ "paths": ["a.rs", "b.rs"] }))
+        );
+        assert_eq!(
+            repair_truncated_json(r#"{"edits": [{"line": 1, "text": "x"}, {"line": 2"#),
+            Some(json!({ "edits": [{ "line": 1, "text": "x" }, { "line": 2 }] }))
+        );
+        assert_eq!(
+            repair_truncated_json(r#"{"limits": [10, 20, tr"#),
+            Some(json!({ "limits": [10, 20] }))
+        );
+    }
+
+    #[test]
+    fn test_unrepairable_inputs() {
+        // 无任何完整内容可保留
+        assert_eq!(repair_truncated_json(r#"{"file_pa"#), None);
+        assert_eq!(repair_truncated_json("{"), None);
+        assert_eq!(repair_truncated_json(""), None);
+        // 非对象 / 数组或结构错误
+        assert_eq!(repair_truncated_json("not json"), None);
+        assert_eq!(repair_truncated_json(r#"{"a": [1, 2}"#), None);
+    }
+}
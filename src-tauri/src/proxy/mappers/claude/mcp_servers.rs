@@ -0,0 +1,300 @@
+// MCP 服务器声明展开 (mcp_servers / mcp_toolset)
+// 新版 Claude Code beta 会在请求中用 mcp_servers 声明远程 MCP 服务器，而不是预先展开好的 tools，
+// serde 忽略该字段时模型看不到这些工具。Gemini 只接受函数声明，这里把每个服务器内联声明的工具
+// 展开为普通工具，名称为 `mcp__{server}__{tool}` (与 Claude Code 的 MCP 工具命名一致)。
+// 模型返回的 tool_use 沿用该名称，客户端据此把调用路由回对应服务器；下一轮历史中的同名
+// tool_use 原样回传，与声明保持一致。服务器名与工具名必须已是合法的 Gemini 函数名片段
+// ([A-Za-z0-9_-])，否则以 400 拒绝: 替换字符会让客户端收到与声明不一致的名称而无法路由，
+// 因此展开过程不改写名称，也就无需额外的名称映射表。
+// 代理不连接远程服务器: 未内联 tools 的服务器以 400 拒绝。tools 中的 mcp_toolset 条目由展开结果取代。
+
+use super::models::{ClaudeRequest, Tool};
+use crate::proxy::mappers::request_validation::RequestValidationError;
+use std::collections::HashSet;
+
+/// Gemini 函数名长度上限
+const MAX_FUNCTION_NAME_LEN: usize = 64;
+
+/// 名称片段只允许 Gemini 函数名字符，返回不合法字符供报错
+fn invalid_segment_chars(raw: &str) -> Option<String> {
+    let invalid: String = raw
+        .chars()
+        .filter(|c| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '-'))
+        .collect();
+    (!invalid.is_empty()).then_some(invalid)
+}
+
+/// 校验名称片段，不合法时返回指向具体字段的 400
+fn check_segment(raw: &str, param: String, kind: &str) -> Result<(), RequestValidationError> {
+    match invalid_segment_chars(raw) {
+        Some(invalid) => Err(RequestValidationError::new(
+            param,
+            format!(
+                "MCP {} name '{}' contains characters not allowed in tool names ({:?}); only letters, digits, '_' and '-' are supported",
+                kind, raw, invalid
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// MCP 工具展开后的名称: mcp__{server}__{tool}
+pub fn mcp_tool_name(server: &str, tool: &str) -> String {
+    format!("mcp__{}__{}", server, tool)
+}
+
+/// 把 mcp_servers 声明的工具合并到 tools (校验之后、协议转换之前调用)
+/// 客户端已预先展开的同名工具保持不变
+pub fn flatten_mcp_servers(request: &mut ClaudeRequest) -> Result<(), RequestValidationError> {
+    let Some(servers) = request.mcp_servers.take() else {
+        return Ok(());
+    };
+    let had_tools = request.tools.is_some();
+    let mut tools = request.tools.take().unwrap_or_default();
+    tools.retain(|t| t.type_.as_deref() != Some("mcp_toolset"));
+    let mut seen: HashSet<String> = tools.iter().filter_map(|t| t.name.clone()).collect();
+    let mut added = 0;
+
+    for (i, server) in servers.iter().enumerate() {
+        let Some(server_name) = server.name.as_deref().filter(|n| !n.trim().is_empty()) else {
+            return Err(RequestValidationError::new(
+                format!("mcp_servers[{}].name", i),
+                "MCP server name is required",
+            ));
+        };
+        let config = server.tool_configuration.as_ref();
+        if config.and_then(|c| c.enabled) == Some(false) {
+            continue;
+        }
+        let Some(server_tools) = &server.tools else {
+            return Err(RequestValidationError::new(
+                format!("mcp_servers[{}].tools", i),
+                format!(
+                    "MCP server '{}' declares no inline tools; fetching tool lists from remote MCP servers is not supported, include the server's tools in the request",
+                    server_name
+                ),
+            ));
+        };
+        check_segment(server_name, format!("mcp_servers[{}].name", i), "server")?;
+        let allowed = config.and_then(|c| c.allowed_tools.as_ref());
+
+        for (j, tool) in server_tools.iter().enumerate() {
+            let Some(tool_name) = tool.name.as_deref().filter(|n| !n.trim().is_empty()) else {
+                return Err(RequestValidationError::new(
+                    format!("mcp_servers[{}].tools[{}].name", i, j),
+                    "MCP tool name is required",
+                ));
+            };
+            if allowed.map_or(false, |list| !list.iter().any(|a| a == tool_name)) {
+                continue;
+            }
+            check_segment(tool_name, format!("mcp_servers[{}].tools[{}].name", i, j), "tool")?;
+            let name = mcp_tool_name(server_name, tool_name);
+            if name.len() > MAX_FUNCTION_NAME_LEN {
+                return Err(RequestValidationError::new(
+                    format!("mcp_servers[{}].tools[{}].name", i, j),
+                    format!(
+                        "flattened tool name '{}' exceeds {} characters",
+                        name, MAX_FUNCTION_NAME_LEN
+                    ),
+                ));
+            }
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            tools.push(Tool {
+                type_: None,
+                name: Some(name),
+                description: tool.description.clone(),
+                input_schema: tool.input_schema.clone(),
+            });
+            added += 1;
+        }
+    }
+
+    tracing::debug!(
+        "[MCP-Servers] Flattened {} tools from {} MCP servers",
+        added,
+        servers.len()
+    );
+    request.tools = (had_tools || !tools.is_empty()).then_some(tools);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::mappers::claude::models::{ContentBlock, GeminiResponse};
+    use crate::proxy::mappers::claude::{transform_claude_request_in, transform_response};
+    use crate::proxy::mappers::common_utils::EnvelopeParams;
+    use serde_json::{json, Value};
+
+    fn request_with_servers(messages: Value) -> ClaudeRequest {
+        serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "messages": messages,
+            "tools": [
+                { "name": "read_file", "input_schema": { "type": "object", "properties": { "path": { "type": "string" } } } },
+                { "type": "mcp_toolset", "mcp_server_name": "github" }
+            ],
+            "mcp_servers": [{
+                "type": "url",
+                "url": "https://mcp.example.com/github",
+                "name": "github",
+                "authorization_token": "secret",
+                "tools": [
+                    {
+                        "name": "create_issue",
+                        "description": "Create a GitHub issue",
+                        "inputSchema": { "type": "object", "properties": { "title": { "type": "string" } }, "required": ["title"] }
+                    },
+                    { "name": "delete_repo", "input_schema": { "type": "object" } }
+                ],
+                "tool_configuration": { "allowed_tools": ["create_issue"] }
+            }]
+        }))
+        .unwrap()
+    }
+
+    fn function_declarations(body: &Value) -> Vec<Value> {
+        body["request"]["tools"][0]["functionDeclarations"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_inline_server_tools_are_declared_with_prefix() {
+        let mut request =
+            request_with_servers(json!([{ "role": "user", "content": "Open an issue about the crash" }]));
+        flatten_mcp_servers(&mut request).unwrap();
+
+        let names: Vec<_> = request
+            .tools
+            .iter()
+            .flatten()
+            .filter_map(|t| t.name.as_deref())
+            .collect();
+        assert_eq!(names, vec!["read_file", "mcp__github__create_issue"]);
+        assert!(request.mcp_servers.is_none());
+
+        let body = transform_claude_request_in(&request, "test-project", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        let declarations = function_declarations(&body);
+        let issue = declarations
+            .iter()
+            .find(|d| d["name"] == "mcp__github__create_issue")
+            .expect("MCP tool is declared upstream");
+        assert_eq!(issue["description"], "Create a GitHub issue");
+        assert!(issue["parameters"]["properties"]["title"].is_object());
+        assert!(!body.to_string().contains("secret"));
+    }
+
+    #[test]
+    fn test_mcp_tool_call_round_trips_prefixed_name() {
+        // 上游返回展开后的函数名，客户端收到同名 tool_use
+        let gemini: GeminiResponse = serde_json::from_value(json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [
+                    { "functionCall": { "name": "mcp__github__create_issue", "args": { "title": "Crash" } } }
+                ]},
+                "finishReason": "STOP"
+            }]
+        }))
+        .unwrap();
+        let response = transform_response(&gemini, false, 1_000_000, None, "gemini-3-flash".to_string(), 1, None, Default::default()).unwrap();
+        let (id, name, input) = response
+            .content
+            .iter()
+            .find_map(|block| match block {
+                ContentBlock::ToolUse { id, name, input, .. } => Some((id.clone(), name.clone(), input.clone())),
+                _ => None,
+            })
+            .expect("tool_use block");
+        assert_eq!(name, "mcp__github__create_issue");
+
+        // 客户端回传调用结果 (再次携带 mcp_servers)，历史中的调用与声明同名
+        let mut follow_up = request_with_servers(json!([
+            { "role": "user", "content": "Open an issue about the crash" },
+            { "role": "assistant", "content": [{ "type": "tool_use", "id": id, "name": name, "input": input }] },
+            { "role": "user", "content": [{ "type": "tool_result", "tool_use_id": id, "content": "Issue #7 created" }] }
+        ]));
+        flatten_mcp_servers(&mut follow_up).unwrap();
+        let body = transform_claude_request_in(&follow_up, "test-project", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+
+        let parts: Vec<&Value> = body["request"]["contents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|c| c["parts"].as_array().into_iter().flatten())
+            .collect();
+        assert!(parts
+            .iter()
+            .any(|p| p["functionCall"]["name"] == "mcp__github__create_issue"));
+        assert!(parts
+            .iter()
+            .any(|p| p["functionResponse"]["name"] == "mcp__github__create_issue"));
+        assert!(function_declarations(&body)
+            .iter()
+            .any(|d| d["name"] == "mcp__github__create_issue"));
+    }
+
+    #[test]
+    fn test_remote_only_server_is_rejected() {
+        let mut request: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "mcp_servers": [{ "type": "url", "url": "https://mcp.example.com/sse", "name": "remote" }]
+        }))
+        .unwrap();
+        let err = flatten_mcp_servers(&mut request).unwrap_err();
+        assert_eq!(err.param, "mcp_servers[0].tools");
+        assert!(err.message.contains("not supported"));
+
+        // 缺少 name 时指明具体字段 (即使服务器已禁用)
+        let mut request: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "mcp_servers": [
+                { "type": "url", "url": "https://mcp.example.com/a", "name": "a", "tools": [] },
+                { "type": "url", "url": "https://mcp.example.com/b", "tool_configuration": { "enabled": false } }
+            ]
+        }))
+        .unwrap();
+        let err = flatten_mcp_servers(&mut request).unwrap_err();
+        assert_eq!(err.param, "mcp_servers[1].name");
+    }
+
+    #[test]
+    fn test_names_needing_rewrite_are_rejected() {
+        // 改写后的名称无法路由回客户端声明的服务器/工具，直接拒绝
+        let mut request: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "mcp_servers": [{
+                "type": "url", "url": "https://mcp.example.com/sse", "name": "my server",
+                "tools": [{ "name": "list_files", "input_schema": { "type": "object" } }]
+            }]
+        }))
+        .unwrap();
+        let err = flatten_mcp_servers(&mut request).unwrap_err();
+        assert_eq!(err.param, "mcp_servers[0].name");
+        assert!(err.message.contains("my server"));
+
+        let mut request: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "mcp_servers": [{
+                "type": "url", "url": "https://mcp.example.com/sse", "name": "github",
+                "tools": [
+                    { "name": "list-repos", "input_schema": { "type": "object" } },
+                    { "name": "create.issue", "input_schema": { "type": "object" } }
+                ]
+            }]
+        }))
+        .unwrap();
+        let err = flatten_mcp_servers(&mut request).unwrap_err();
+        assert_eq!(err.param, "mcp_servers[0].tools[1].name");
+        assert!(err.message.contains("create.issue"));
+    }
+}
@@ -8,6 +8,13 @@ pub mod streaming;
 pub mod utils;
 pub mod thinking_utils;
 pub mod collector;
+pub mod delta_filter;
+pub mod diagnostics;
+pub mod json_repair;
+pub mod mcp_servers;
+pub mod partial_args;
+pub mod prefill;
+pub mod resume;
 
 pub use models::*;
 pub use request::{transform_claude_request_in, clean_cache_control_from_messages, merge_consecutive_messages};
@@ -32,6 +39,14 @@ pub fn create_claude_sse_stream(
     estimated_prompt_tokens: Option<u32>, // [FIX] Estimated tokens for calibrator learning
     message_count: usize, // [NEW v4.0.0] Message count for rewind detection
     client_adapter: Option<std::sync::Arc<dyn ClientAdapter>>, // [NEW] Adapter reference
+    served_model: Option<String>, // [NEW] Final resolved model reported in message_start
+    requested_model: Option<String>, // [NEW] Client-requested model (extension field)
+    defer_message_start: bool, // [NEW] Defer message_start until real content or finish arrives
+    tool_schemas: std::collections::HashMap<String, serde_json::Value>, // [NEW] Client tool schemas for args type fixing
+    resumer: Option<resume::StreamResumer>, // [NEW] 上游中途断开时的续写请求发起器 (opt-in)
+    prefill: Option<String>, // [NEW] assistant 预填充文本 (裁剪模型对其的复述)
+    stop_sequences: Vec<String>, // [NEW] 客户端 stop_sequences (仅作用于可见文本)
+    thinking_nudger: Option<resume::ThinkingNudger>, // [NEW] 思考耗尽 MAX_TOKENS 时追问最终答案 (opt-in)
 ) -> Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>> {
     use async_stream::stream;
     use bytes::BytesMut;
@@ -45,7 +60,31 @@ pub fn create_claude_sse_stream(
         state.context_limit = context_limit;
         state.estimated_prompt_tokens = estimated_prompt_tokens; // [FIX] Pass estimated tokens
         state.set_client_adapter(client_adapter); // [NEW] Set adapter
+        state.served_model = served_model;
+        state.requested_model = requested_model;
+        state.defer_message_start = defer_message_start;
+        state.tool_schemas = tool_schemas;
+        let redactions = delta_filter::configured_redactions();
+        if prefill.is_some() || !stop_sequences.is_empty() || !redactions.is_empty() {
+            state.set_delta_pipeline(
+                delta_filter::DeltaPipeline::new(stop_sequences)
+                    .with_redactions(redactions)
+                    .with_prefill(prefill),
+            );
+        }
         let mut buffer = BytesMut::new();
+        // [NEW] 中断续写: 最多一次，续写内容经拼接器去重后并入当前消息
+        let mut resumer = resumer;
+        if resumer.is_some() {
+            state.resume_transcript = Some(String::new());
+        }
+        let mut splicer: Option<resume::ContinuationSplicer> = None;
+        let mut resume_error: Option<String> = None;
+        // [NEW] 思考耗尽追问: 最多一次，追问结果作为新的文本块并入当前消息
+        let mut thinking_nudger = thinking_nudger;
+        if thinking_nudger.is_some() {
+            state.thinking_transcript = Some(String::new());
+        }
 
         loop {
             // [NEW] 60秒心跳保活: 延长超时时间以增加网络抖动容错
@@ -58,15 +97,69 @@ pub fn create_claude_sse_stream(
                 Ok(Some(chunk_result)) => {
                     match chunk_result {
                         Ok(chunk) => {
+                            if let Some(sid) = state.session_id.as_deref() {
+                                crate::proxy::session_registry::touch(sid);
+                            }
                             buffer.extend_from_slice(&chunk);
 
                             // Process complete lines
+                            let mut splice_failed = false;
+                            let mut nudged = false;
                             while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
                                 let line_raw = buffer.split_to(pos + 1);
                                 if let Ok(line_str) = std::str::from_utf8(&line_raw) {
                                     let line = line_str.trim();
                                     if line.is_empty() { continue; }
 
+                                    let spliced;
+                                    let line = match splicer.as_mut() {
+                                        Some(sp) => match resume::splice_sse_line(line, sp) {
+                                            resume::SplicedLine::Line(l) => { spliced = l; spliced.as_str() }
+                                            resume::SplicedLine::Restarted => { splice_failed = true; break; }
+                                        },
+                                        None => line,
+                                    };
+
+                                    // [NEW] 只输出了思考即以 MAX_TOKENS 结束: 先转发思考内容，再追问一次最终答案
+                                    let split = if thinking_nudger.is_some() && splicer.is_none() {
+                                        resume::split_max_tokens_finish(line)
+                                    } else {
+                                        None
+                                    };
+                                    if let Some((content_line, finish_line)) = split {
+                                        if let Some(sse_chunks) = process_sse_line(&content_line, &mut state, &trace_id, &email) {
+                                            for sse_chunk in sse_chunks {
+                                                yield Ok(sse_chunk);
+                                            }
+                                        }
+                                        if let Some(nudge_fn) = thinking_nudger.take().filter(|_| needs_thinking_nudge(&state)) {
+                                            let thinking = state.thinking_transcript.take().unwrap_or_default();
+                                            tracing::warn!(
+                                                "[{}] Upstream hit MAX_TOKENS after {} chars of thinking without an answer, requesting a final answer once",
+                                                trace_id, thinking.chars().count()
+                                            );
+                                            match nudge_fn(thinking).await {
+                                                Ok(stream) => {
+                                                    // 首个请求的 usage (含思考 token) 累加到追问结束时的 usage
+                                                    state.carried_usage = resume::finish_usage(&finish_line);
+                                                    state.last_usage_metadata = None;
+                                                    gemini_stream = stream;
+                                                    nudged = true;
+                                                    break;
+                                                }
+                                                Err(e) => {
+                                                    tracing::warn!("[{}] Thinking nudge request failed: {}", trace_id, e);
+                                                }
+                                            }
+                                        }
+                                        if let Some(sse_chunks) = process_sse_line(&finish_line, &mut state, &trace_id, &email) {
+                                            for sse_chunk in sse_chunks {
+                                                yield Ok(sse_chunk);
+                                            }
+                                        }
+                                        continue;
+                                    }
+
                                     if let Some(sse_chunks) = process_sse_line(line, &mut state, &trace_id, &email) {
                                         for sse_chunk in sse_chunks {
                                             yield Ok(sse_chunk);
@@ -74,9 +167,48 @@ pub fn create_claude_sse_stream(
                                     }
                                 }
                             }
+                            if nudged {
+                                // 追问流只保留文本 (丢弃思考)，作为新的文本块接在已关闭的思考块之后
+                                buffer.clear();
+                                splicer = Some(resume::ContinuationSplicer::new(String::new()));
+                                continue;
+                            }
+                            if splice_failed {
+                                tracing::warn!("[{}] Continuation restarted the answer, abandoning resume", trace_id);
+                                buffer.clear();
+                                yield Err(resume_error.take().unwrap_or_else(|| "Stream error: resume failed".to_string()));
+                                break;
+                            }
+                            // [NEW] 命中客户端停止序列: 不再读取上游，由下方 emit_force_stop 回报 stop_sequence
+                            if state.stop_sequence_hit() {
+                                tracing::debug!("[{}] Client stop sequence matched, ending upstream stream early", trace_id);
+                                buffer.clear();
+                                break;
+                            }
                         }
                         Err(e) => {
-                            yield Err(format!("Stream error: {}", e));
+                            let error = format!("Stream error: {}", e);
+                            // [NEW] 已转发纯文本内容时尝试续写一次 (工具调用 / 思考中断不续写)
+                            if let Some(resume_fn) = resumer.take().filter(|_| can_resume(&state)) {
+                                let partial = state.resume_transcript.clone().unwrap_or_default();
+                                tracing::warn!(
+                                    "[{}] {} after {} chars, resuming on a new upstream request",
+                                    trace_id, error, partial.chars().count()
+                                );
+                                match resume_fn(partial.clone()).await {
+                                    Ok(stream) => {
+                                        gemini_stream = stream;
+                                        buffer.clear();
+                                        splicer = Some(resume::ContinuationSplicer::new(partial));
+                                        resume_error = Some(error);
+                                        continue;
+                                    }
+                                    Err(re) => {
+                                        tracing::warn!("[{}] Resume request failed: {}", trace_id, re);
+                                    }
+                                }
+                            }
+                            yield Err(error);
                             break;
                         }
                     }
@@ -109,7 +241,7 @@ pub fn create_claude_sse_stream(
         // [FIX #859] Post-thinking interruption recovery
         // If we have sent thinking but NO content (text/tool_use) and the stream ended (or timed out without DONE),
         // we must provide a fallback to prevent 0-token errors on client side.
-        if state.has_thinking && !state.has_content {
+        if state.has_thinking && !state.has_content && !state.stop_sequence_hit() {
             tracing::warn!("[{}] Stream interrupted after thinking (No Content). Triggering recovery...", trace_id);
             
             // 1. Force close thinking block if open
@@ -135,15 +267,25 @@ pub fn create_claude_sse_stream(
             // 3. Mark as content received so we don't trigger this again (though loop is done)
             state.has_content = true;
 
-            // 4. Send a simulated usage update to ensure we have > 0 output tokens
-            // Estimate based on some default if we didn't get any usage
-            let recovery_usage = crate::proxy::mappers::claude::models::Usage {
-                input_tokens: 0, // We don't know input, but output is critical
-                output_tokens: 100, // Arbitrary small number to satisfy client
-                cache_read_input_tokens: None,
-                cache_creation_input_tokens: None,
-                server_tool_use: None,
+            // 4. Send a usage update to ensure we have > 0 output tokens
+            // [NEW] 优先使用上游最近一次返回的 usage (含输入与缓存 token)，缺失时才回退到估算值
+            let mut recovery_usage = match state.last_usage_metadata.as_ref() {
+                Some(u) => crate::proxy::mappers::claude::utils::to_claude_usage(
+                    u,
+                    state.scaling_enabled,
+                    state.context_limit,
+                ),
+                None => crate::proxy::mappers::claude::models::Usage {
+                    input_tokens: 0, // We don't know input, but output is critical
+                    output_tokens: 0,
+                    cache_read_input_tokens: None,
+                    cache_creation_input_tokens: None,
+                    server_tool_use: None,
+                },
             };
+            if recovery_usage.output_tokens == 0 {
+                recovery_usage.output_tokens = 100; // Arbitrary small number to satisfy client
+            }
 
             let delta = serde_json::json!({
                 "type": "message_delta",
@@ -161,6 +303,21 @@ pub fn create_claude_sse_stream(
     })
 }
 
+/// 是否可以在中断点续写: 已开始消息且只转发了文本 (无工具调用、未结束、不在思考块中)
+fn can_resume(state: &StreamingState) -> bool {
+    state.message_start_sent
+        && state.has_content
+        && !state.used_tool
+        && !state.message_stop_sent
+        && state.current_block_type() != crate::proxy::mappers::claude::streaming::BlockType::Thinking
+        && state.resume_transcript.as_deref().map_or(false, |t| !t.is_empty())
+}
+
+/// 是否需要追问最终答案: 只输出了思考，没有文本或工具调用，消息尚未结束
+fn needs_thinking_nudge(state: &StreamingState) -> bool {
+    state.has_thinking && !state.has_content && !state.message_stop_sent
+}
+
 /// 处理单行 SSE 数据
 fn process_sse_line(line: &str, state: &mut StreamingState, trace_id: &str, email: &str) -> Option<Vec<Bytes>> {
     if !line.starts_with("data: ") {
@@ -188,11 +345,21 @@ fn process_sse_line(line: &str, state: &mut StreamingState, trace_id: &str, emai
 
     let mut chunks = Vec::new();
 
-    // 解包 response 字段 (如果存在)
-    let raw_json = json_value.get("response").unwrap_or(&json_value);
+    // 解包 response 字段 (如果存在)，并补入位于包装外的 usageMetadata / groundingMetadata
+    let raw_json = &*unwrap_response(&json_value);
+
+    // [NEW] 记录最近的 usage (含 cachedContentTokenCount)，供结束事件回退使用
+    if let Some(u) = raw_json
+        .get("usageMetadata")
+        .and_then(|u| serde_json::from_value::<UsageMetadata>(u.clone()).ok())
+        .filter(|u| u.prompt_token_count.is_some())
+    {
+        state.last_usage_metadata = Some(u);
+    }
 
     // 发送 message_start
-    if !state.message_start_sent {
+    // [NEW] 延迟模式下，纯 keepalive 块 (无内容且无 finishReason) 不触发 message_start
+    if !state.message_start_sent && (!state.defer_message_start || has_real_content(raw_json)) {
         chunks.push(state.emit_message_start(raw_json));
     }
 
@@ -200,13 +367,16 @@ fn process_sse_line(line: &str, state: &mut StreamingState, trace_id: &str, emai
     if let Some(candidate) = raw_json.get("candidates").and_then(|c| c.get(0)) {
         if let Some(grounding) = candidate.get("groundingMetadata") {
             // 提取搜索词
-            if let Some(query) = grounding.get("webSearchQueries")
-                .and_then(|v| v.as_array())
+            let queries = grounding.get("webSearchQueries").and_then(|v| v.as_array());
+            if let Some(query) = queries
                 .and_then(|arr| arr.get(0))
                 .and_then(|v| v.as_str())
             {
                 state.web_search_query = Some(query.to_string());
             }
+            // [NEW] 搜索次数按查询词数量计 (grounding 块可能在多个 chunk 中重复出现，取最大值)
+            let requests = queries.map_or(0, |arr| arr.len() as u32).max(1);
+            state.web_search_requests = state.web_search_requests.max(requests);
 
             // 提取结果块
             if let Some(chunks_arr) = grounding.get("groundingChunks").and_then(|v| v.as_array()) {
@@ -214,6 +384,11 @@ fn process_sse_line(line: &str, state: &mut StreamingState, trace_id: &str, emai
             } else if let Some(chunks_arr) = grounding.get("grounding_metadata").and_then(|m| m.get("groundingChunks")).and_then(|v| v.as_array()) {
                 state.grounding_chunks = Some(chunks_arr.clone());
             }
+
+            // [NEW] 提取文本片段与来源的对应关系 (用于行内引用)
+            if let Some(supports) = grounding.get("groundingSupports").and_then(|v| v.as_array()) {
+                state.grounding_supports = Some(supports.clone());
+            }
         }
     }
 
@@ -289,8 +464,60 @@ fn process_sse_line(line: &str, state: &mut StreamingState, trace_id: &str, emai
     }
 }
 
+/// 解包 v1internal 的 response 字段
+/// 部分端点把 usageMetadata 放在包装外的顶层，groundingMetadata 也可能不在 candidate 内:
+/// 缺失时从这些位置补入，保证 usage 与引用不受包装方式影响
+fn unwrap_response(json_value: &serde_json::Value) -> std::borrow::Cow<'_, serde_json::Value> {
+    let raw = json_value.get("response").unwrap_or(json_value);
+    let usage = json_value
+        .get("usageMetadata")
+        .filter(|_| raw.get("usageMetadata").is_none());
+    let grounding = raw
+        .get("groundingMetadata")
+        .or_else(|| json_value.get("groundingMetadata"))
+        .filter(|_| raw.pointer("/candidates/0").map_or(false, |c| c.get("groundingMetadata").is_none()));
+    if usage.is_none() && grounding.is_none() {
+        return std::borrow::Cow::Borrowed(raw);
+    }
+
+    let mut merged = raw.clone();
+    if let Some(usage) = usage {
+        merged["usageMetadata"] = usage.clone();
+    }
+    if let (Some(grounding), Some(candidate)) = (grounding, merged.pointer_mut("/candidates/0")) {
+        candidate["groundingMetadata"] = grounding.clone();
+    }
+    std::borrow::Cow::Owned(merged)
+}
+
+/// 判断 Gemini 块是否包含真实内容 (非空 part 或 finishReason)
+fn has_real_content(raw_json: &serde_json::Value) -> bool {
+    let Some(candidate) = raw_json.get("candidates").and_then(|c| c.get(0)) else {
+        return false;
+    };
+    if candidate.get("finishReason").is_some() {
+        return true;
+    }
+    candidate
+        .get("content")
+        .and_then(|content| content.get("parts"))
+        .and_then(|p| p.as_array())
+        .map_or(false, |parts| {
+            parts.iter().any(|part| {
+                part.get("text").and_then(|t| t.as_str()).map_or(false, |t| !t.is_empty())
+                    || part.get("functionCall").is_some()
+                    || part.get("inlineData").is_some()
+                    || part.get("thoughtSignature").is_some()
+            })
+        })
+}
+
 /// 发送强制结束事件
 pub fn emit_force_stop(state: &mut StreamingState) -> Vec<Bytes> {
+    // [NEW] 延迟模式下若从未开始消息，则不补发结束事件 (避免孤立的 message_delta/message_stop)
+    if state.defer_message_start && !state.message_start_sent {
+        return vec![];
+    }
     if !state.message_stop_sent {
         let mut chunks = state.emit_finish(None, None);
         if chunks.is_empty() {
@@ -468,6 +695,66 @@ mod tests {
         assert!(all_text.contains("Hello"));
     }
 
+    #[test]
+    fn test_cached_content_tokens_reported_in_usage() {
+        let mut state = StreamingState::new();
+        let usage = r#""usageMetadata":{"promptTokenCount":1000,"candidatesTokenCount":5,"cachedContentTokenCount":600}"#;
+
+        let first = format!(r#"data: {{"candidates":[{{"content":{{"parts":[{{"text":"Hi"}}]}}}}],{},"responseId":"1"}}"#, usage);
+        let chunks = process_sse_line(&first, &mut state, "test_id", "test@example.com").unwrap();
+        let start: String = chunks.iter().map(|b| String::from_utf8_lossy(b).to_string()).collect();
+        assert!(start.contains(r#""cache_read_input_tokens":600"#));
+
+        // 结束块带 usage
+        let finish = format!(r#"data: {{"candidates":[{{"content":{{"parts":[]}},"finishReason":"STOP"}}],{}}}"#, usage);
+        let mut finish_state = StreamingState::new();
+        let chunks = process_sse_line(&finish, &mut finish_state, "test_id", "test@example.com").unwrap();
+        let delta: String = chunks.iter().map(|b| String::from_utf8_lossy(b).to_string()).collect();
+        assert!(delta.contains(r#""input_tokens":400"#));
+        assert!(delta.contains(r#""cache_read_input_tokens":600"#));
+
+        // 结束块缺少 usage 时回退到最近一次 usage
+        let chunks = process_sse_line(
+            r#"data: {"candidates":[{"content":{"parts":[]},"finishReason":"STOP"}]}"#,
+            &mut state,
+            "test_id",
+            "test@example.com",
+        )
+        .unwrap();
+        let delta: String = chunks.iter().map(|b| String::from_utf8_lossy(b).to_string()).collect();
+        assert!(delta.contains("message_delta"));
+        assert!(delta.contains(r#""cache_read_input_tokens":600"#));
+    }
+
+    #[test]
+    fn test_web_search_reported_as_server_tool_use() {
+        let mut state = StreamingState::new();
+        let chunk = r#"data: {"candidates":[{"content":{"parts":[{"text":"Result"}]},"finishReason":"STOP","groundingMetadata":{"webSearchQueries":["rust 2024","axum 0.7"],"groundingChunks":[{"web":{"uri":"https://example.com","title":"Example"}}]}}],"usageMetadata":{"promptTokenCount":10,"candidatesTokenCount":3}}"#;
+        let chunks = process_sse_line(chunk, &mut state, "test_id", "test@example.com").unwrap();
+        let delta: String = chunks.iter().map(|b| String::from_utf8_lossy(b).to_string()).collect();
+        assert!(delta.contains(r#""server_tool_use":{"web_search_requests":2}"#));
+
+        // 无搜索时不上报
+        let mut state = StreamingState::new();
+        let chunk = r#"data: {"candidates":[{"content":{"parts":[{"text":"Hi"}]},"finishReason":"STOP"}],"usageMetadata":{"promptTokenCount":10,"candidatesTokenCount":3}}"#;
+        let chunks = process_sse_line(chunk, &mut state, "test_id", "test@example.com").unwrap();
+        let delta: String = chunks.iter().map(|b| String::from_utf8_lossy(b).to_string()).collect();
+        assert!(!delta.contains("server_tool_use"));
+    }
+
+    #[test]
+    fn test_top_level_usage_outside_response_wrapper() {
+        let mut state = StreamingState::new();
+        let chunk = r#"data: {"response":{"candidates":[{"content":{"parts":[{"text":"Hi"}]},"finishReason":"STOP"}],"groundingMetadata":{"webSearchQueries":["rust"],"groundingChunks":[{"web":{"uri":"https://example.com","title":"Example"}}]}},"usageMetadata":{"promptTokenCount":1000,"candidatesTokenCount":7,"cachedContentTokenCount":600}}"#;
+        let chunks = process_sse_line(chunk, &mut state, "test_id", "test@example.com").unwrap();
+        let output: String = chunks.iter().map(|b| String::from_utf8_lossy(b).to_string()).collect();
+        assert!(output.contains(r#""input_tokens":400"#));
+        assert!(output.contains(r#""output_tokens":7"#));
+        assert!(output.contains(r#""cache_read_input_tokens":600"#));
+        // 位于 candidate 之外的 groundingMetadata 同样计入搜索次数
+        assert!(output.contains(r#""server_tool_use":{"web_search_requests":1}"#));
+    }
+
     #[tokio::test]
     async fn test_thinking_only_interruption_recovery() {
         use futures::StreamExt;
@@ -500,6 +787,14 @@ mod tests {
             None,
             1, // message_count
             None, // client_adapter
+            None, // served_model
+            None, // requested_model
+            false, // defer_message_start
+            std::collections::HashMap::new(), // tool_schemas
+            None, // resumer
+            None, // prefill
+            Vec::new(), // stop_sequences
+            None, // thinking_nudger
         );
 
         // 3. 收集输出
@@ -522,4 +817,522 @@ mod tests {
         assert!(output.contains("\"usage\":"));
         assert!(output.contains("\"output_tokens\":100")); // Should contain the recovery usage
     }
+
+    #[tokio::test]
+    async fn test_interruption_recovery_reports_upstream_usage() {
+        use futures::StreamExt;
+
+        // 思考块携带 usageMetadata 后中断: 恢复事件应沿用上游 usage 而非固定值
+        let mock_stream = async_stream::stream! {
+            let thinking_json = serde_json::json!({
+                "candidates": [{
+                    "content": { "parts": [{ "text": "Thinking...", "thought": true }] }
+                }],
+                "usageMetadata": {
+                    "promptTokenCount": 420,
+                    "candidatesTokenCount": 37,
+                    "cachedContentTokenCount": 120
+                },
+                "modelVersion": "gemini-2.0-flash-thinking",
+                "responseId": "msg_interrupted_usage"
+            });
+            yield Ok(bytes::Bytes::from(format!("data: {}\n\n", thinking_json)));
+        };
+
+        let mut claude_stream = create_claude_sse_stream(
+            Box::pin(mock_stream),
+            "trace_test".to_string(),
+            "test@example.com".to_string(),
+            None,
+            false,
+            1_000_000,
+            None,
+            1,
+            None,
+            None,
+            None,
+            false,
+            std::collections::HashMap::new(),
+            None,
+            None,
+            Vec::new(),
+            None,
+        );
+
+        let mut output = String::new();
+        while let Some(Ok(bytes)) = claude_stream.next().await {
+            output.push_str(&String::from_utf8(bytes.to_vec()).unwrap());
+        }
+
+        assert!(output.contains("Recovered by Antigravity"));
+        let recovery = output
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .filter_map(|d| serde_json::from_str::<serde_json::Value>(d).ok())
+            .find(|v| v["type"] == "message_delta")
+            .expect("recovery message_delta");
+        assert_eq!(recovery["usage"]["output_tokens"], 37);
+        assert_eq!(recovery["usage"]["cache_read_input_tokens"], 120);
+        assert!(recovery["usage"]["input_tokens"].as_u64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_message_start_reports_served_model_on_web_search_fallback() {
+        use crate::proxy::mappers::common_utils::EnvelopeParams;
+        use futures::StreamExt;
+
+        // 1. claude-sonnet-4-5 + web_search 会降级到 gemini-2.5-flash
+        let req: ClaudeRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "messages": [{ "role": "user", "content": "What's new today?" }],
+            "tools": [{ "type": "web_search_20250305", "name": "web_search" }]
+        }))
+        .unwrap();
+        let body = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        let served = body["model"].as_str().unwrap().to_string();
+        assert_eq!(served, "gemini-2.5-flash");
+
+        // 2. 上游 modelVersion 与实际服务模型不一致时，以 served_model 为准
+        let mock_stream = async_stream::stream! {
+            let chunk = serde_json::json!({
+                "candidates": [{
+                    "content": { "parts": [{ "text": "Here is the news." }] },
+                    "finishReason": "STOP"
+                }],
+                "modelVersion": "upstream-model-version",
+                "responseId": "msg_fallback"
+            });
+            yield Ok(bytes::Bytes::from(format!("data: {}\n\n", chunk)));
+        };
+
+        let mut claude_stream = create_claude_sse_stream(
+            Box::pin(mock_stream),
+            "trace_test".to_string(),
+            "test@example.com".to_string(),
+            None,
+            false,
+            1_000,
+            None,
+            1,
+            None,
+            Some(served.clone()),
+            Some(req.model.clone()),
+            false,
+            std::collections::HashMap::new(),
+            None,
+            None,
+            Vec::new(),
+            None,
+        );
+
+        let mut output = String::new();
+        while let Some(result) = claude_stream.next().await {
+            if let Ok(bytes) = result {
+                output.push_str(&String::from_utf8(bytes.to_vec()).unwrap());
+            }
+        }
+
+        let message_start = output
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .filter_map(|d| serde_json::from_str::<serde_json::Value>(d).ok())
+            .find(|v| v["type"] == "message_start")
+            .expect("message_start not emitted");
+        assert_eq!(message_start["message"]["model"], "gemini-2.5-flash");
+        assert_eq!(message_start["message"]["requested_model"], "claude-sonnet-4-5");
+    }
+
+    #[tokio::test]
+    async fn test_zed_adapter_coalesces_short_text_deltas() {
+        use crate::proxy::common::client_adapter::ClientAdapter;
+        use crate::proxy::common::client_adapters::ZedAdapter;
+        use crate::proxy::config::DeltaCoalescingConfig;
+        use crate::proxy::mappers::common::delta_coalescer::{coalesce_sse_stream, SseDialect};
+        use futures::StreamExt;
+
+        // 上游以细碎片段输出 (含空片段)
+        let fragments = ["", "Hel", "lo", "", ", wor", "ld! ", "This is fine.", "!"];
+        let mock_stream = async_stream::stream! {
+            for (i, text) in fragments.iter().enumerate() {
+                let mut chunk = serde_json::json!({
+                    "candidates": [{ "content": { "parts": [{ "text": text }] } }],
+                    "modelVersion": "gemini-2.5-flash",
+                    "responseId": "msg_zed"
+                });
+                if i == fragments.len() - 1 {
+                    chunk["candidates"][0]["finishReason"] = serde_json::json!("STOP");
+                }
+                yield Ok(bytes::Bytes::from(format!("data: {}\n\n", chunk)));
+            }
+        };
+
+        let claude_stream = create_claude_sse_stream(
+            Box::pin(mock_stream),
+            "trace_test".to_string(),
+            "test@example.com".to_string(),
+            None,
+            false,
+            1_000,
+            None,
+            1,
+            Some(std::sync::Arc::new(ZedAdapter)),
+            None,
+            None,
+            false,
+            std::collections::HashMap::new(),
+            None,
+            None,
+            Vec::new(),
+            None,
+        );
+        // 与 handler 相同: 全局合并未开启时，由适配器的最小 delta 长度开启合并
+        let mut claude_stream = coalesce_sse_stream(
+            claude_stream,
+            SseDialect::Claude,
+            DeltaCoalescingConfig::default().with_min_text_delta(ZedAdapter.min_text_delta_chars()),
+        );
+
+        let mut output = String::new();
+        while let Some(result) = claude_stream.next().await {
+            if let Ok(bytes) = result {
+                output.push_str(&String::from_utf8(bytes.to_vec()).unwrap());
+            }
+        }
+
+        let deltas: Vec<String> = output
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .filter_map(|d| serde_json::from_str::<serde_json::Value>(d).ok())
+            .filter(|v| v["type"] == "content_block_delta" && v["delta"]["type"] == "text_delta")
+            .map(|v| v["delta"]["text"].as_str().unwrap().to_string())
+            .collect();
+
+        // 不得出现空 delta；除块末尾的剩余部分外，每个 delta 至少 10 个字符
+        assert!(deltas.iter().all(|d| !d.is_empty()));
+        let (last, rest) = deltas.split_last().unwrap();
+        assert!(rest.iter().all(|d| d.chars().count() >= 10));
+        assert_eq!(last, "!");
+        assert_eq!(deltas.concat(), "Hello, world! This is fine.!");
+        assert!(deltas.len() < fragments.len());
+
+        // 剩余内容必须在 content_block_stop 之前发送
+        let last_delta_pos = output.rfind("text_delta").unwrap();
+        let block_stop_pos = output.rfind("content_block_stop").unwrap();
+        assert!(last_delta_pos < block_stop_pos);
+    }
+
+    #[tokio::test]
+    async fn test_deferred_message_start_not_sent_for_erroring_empty_stream() {
+        use futures::StreamExt;
+
+        let mock_stream = async_stream::stream! {
+            // 只有空 parts 的 keepalive 块，随后立即报错
+            let keepalive = serde_json::json!({
+                "candidates": [{ "content": { "role": "model", "parts": [] } }],
+                "modelVersion": "gemini-2.5-flash"
+            });
+            yield Ok(bytes::Bytes::from(format!("data: {}\n\n", keepalive)));
+            yield Err(reqwest::Client::new().get("not a url").build().unwrap_err());
+        };
+
+        let mut claude_stream = create_claude_sse_stream(
+            Box::pin(mock_stream),
+            "trace_test".to_string(),
+            "test@example.com".to_string(),
+            None,
+            false,
+            1_000,
+            None,
+            1,
+            None,
+            None,
+            None,
+            true,
+            std::collections::HashMap::new(),
+            None,
+            None,
+            Vec::new(),
+            None,
+        );
+
+        let mut output = String::new();
+        let mut saw_error = false;
+        while let Some(result) = claude_stream.next().await {
+            match result {
+                Ok(bytes) => output.push_str(&String::from_utf8(bytes.to_vec()).unwrap()),
+                Err(_) => saw_error = true,
+            }
+        }
+
+        assert!(saw_error);
+        assert!(!output.contains("message_start"));
+        assert!(!output.contains("message_stop"));
+    }
+
+    struct CitationsAdapter;
+
+    impl crate::proxy::common::client_adapter::ClientAdapter for CitationsAdapter {
+        fn matches(&self, _headers: &axum::http::HeaderMap) -> bool {
+            false
+        }
+
+        fn supports_citations(&self) -> bool {
+            true
+        }
+    }
+
+    /// 依次处理 Gemini 块，返回所有 SSE 事件 (JSON)
+    fn run_grounding_fixture(state: &mut StreamingState) -> Vec<serde_json::Value> {
+        // 第一个 part 是 MCP XML 工具调用，会被转换为 tool_use，不出现在发送的文本中
+        let xml = r#"<mcp__web_lookup>{"q":"rust"}</mcp__web_lookup>"#;
+        let first = "Rüst was released in 2015. ";
+        let second = "It is memory safe.";
+        let base = xml.len();
+
+        let chunks = vec![
+            serde_json::json!({ "candidates": [{ "content": { "parts": [{ "text": xml }] } }] }),
+            serde_json::json!({ "candidates": [{ "content": { "parts": [{ "text": first }] } }] }),
+            serde_json::json!({
+                "candidates": [{
+                    "content": { "parts": [{ "text": second }] },
+                    "finishReason": "STOP",
+                    "groundingMetadata": {
+                        "groundingChunks": [
+                            { "web": { "uri": "https://rust-lang.org", "title": "rust-lang.org" } },
+                            { "web": { "uri": "https://example.com/safety", "title": "example.com" } }
+                        ],
+                        "groundingSupports": [
+                            {
+                                "segment": { "startIndex": base, "endIndex": base + "Rüst was released in 2015.".len(), "text": "Rüst was released in 2015." },
+                                "groundingChunkIndices": [0]
+                            },
+                            {
+                                "segment": { "startIndex": base + first.len(), "endIndex": base + first.len() + second.len(), "text": second },
+                                "groundingChunkIndices": [0, 1]
+                            }
+                        ]
+                    }
+                }]
+            }),
+        ];
+
+        let mut events = Vec::new();
+        for chunk in chunks {
+            let line = format!("data: {}", chunk);
+            for bytes in process_sse_line(&line, state, "trace_test", "test@example.com").unwrap_or_default() {
+                let text = String::from_utf8(bytes.to_vec()).unwrap();
+                events.extend(
+                    text.lines()
+                        .filter_map(|l| l.strip_prefix("data: "))
+                        .filter_map(|d| serde_json::from_str::<serde_json::Value>(d).ok()),
+                );
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn test_grounding_supports_mapped_to_citation_spans() {
+        let mut state = StreamingState::new();
+        state.set_client_adapter(Some(std::sync::Arc::new(CitationsAdapter)));
+        let events = run_grounding_fixture(&mut state);
+
+        let emitted: String = events
+            .iter()
+            .filter(|e| e["delta"]["type"] == "text_delta")
+            .map(|e| e["delta"]["text"].as_str().unwrap())
+            .collect();
+        assert!(emitted.starts_with("Rüst was released in 2015. It is memory safe."));
+
+        let citations: Vec<&serde_json::Value> = events
+            .iter()
+            .filter(|e| e["delta"]["type"] == "citations_delta")
+            .map(|e| &e["delta"]["citation"])
+            .collect();
+        assert_eq!(citations.len(), 3);
+
+        // 偏移为已发送文本中的字符索引 (已扣除被转换的 XML，且按字符而非字节计算)
+        assert_eq!(citations[0]["start_char_index"], 0);
+        assert_eq!(citations[0]["end_char_index"], 26);
+        assert_eq!(citations[0]["cited_text"], "Rüst was released in 2015.");
+        assert_eq!(citations[0]["url"], "https://rust-lang.org");
+
+        for (citation, url) in citations[1..].iter().zip(["https://rust-lang.org", "https://example.com/safety"]) {
+            assert_eq!(citation["start_char_index"], 27);
+            assert_eq!(citation["end_char_index"], 45);
+            assert_eq!(citation["cited_text"], "It is memory safe.");
+            assert_eq!(citation["url"], url);
+        }
+
+        // 引用附加在答案文本块上 (在该块结束之前)
+        let citation_pos = events.iter().position(|e| e["delta"]["type"] == "citations_delta").unwrap();
+        let citation_index = &events[citation_pos]["index"];
+        let stop_pos = events
+            .iter()
+            .position(|e| e["type"] == "content_block_stop" && &e["index"] == citation_index)
+            .unwrap();
+        assert!(citation_pos < stop_pos);
+        assert!(events[..citation_pos]
+            .iter()
+            .any(|e| e["type"] == "content_block_delta" && &e["index"] == citation_index && e["delta"]["type"] == "text_delta"));
+    }
+
+    #[test]
+    fn test_config_defined_client_profile_gates_grounding_emission() {
+        use crate::proxy::common::client_adapters::ProfileAdapter;
+        use crate::proxy::config::{ClientCapabilities, ClientProfileConfig};
+
+        let text_deltas = |events: &[serde_json::Value]| -> String {
+            events
+                .iter()
+                .filter(|e| e["delta"]["type"] == "text_delta")
+                .map(|e| e["delta"]["text"].as_str().unwrap_or_default())
+                .collect()
+        };
+
+        // 默认: 追加来源文本块，无行内引用
+        let mut state = StreamingState::new();
+        let events = run_grounding_fixture(&mut state);
+        assert!(text_deltas(&events).contains("https://example.com/safety"));
+        assert!(!events.iter().any(|e| e["delta"]["type"] == "citations_delta"));
+
+        // 自定义配置档: 使用行内引用，不再追加来源文本块
+        let custom = vec![ClientProfileConfig {
+            name: "citing-client".to_string(),
+            extends: None,
+            capabilities: ClientCapabilities {
+                citations: Some(true),
+                grounding_text: Some(false),
+                ..Default::default()
+            },
+        }];
+        let adapter = ProfileAdapter::from_header("citing-client", &custom).unwrap();
+        let mut state = StreamingState::new();
+        state.set_client_adapter(Some(std::sync::Arc::new(adapter)));
+        let events = run_grounding_fixture(&mut state);
+        assert!(!text_deltas(&events).contains("https://example.com/safety"));
+        assert!(events.iter().any(|e| e["delta"]["type"] == "citations_delta"));
+    }
+
+    fn resume_text_line(text: &str, finish: bool) -> Bytes {
+        let mut candidate = serde_json::json!({ "content": { "parts": [{ "text": text }] } });
+        if finish {
+            candidate["finishReason"] = serde_json::json!("STOP");
+        }
+        let chunk = serde_json::json!({
+            "candidates": [candidate],
+            "modelVersion": "gemini-3-flash",
+            "responseId": "msg_resume"
+        });
+        Bytes::from(format!("data: {}\n\n", chunk))
+    }
+
+    /// 模拟上游: 发送两段文本后连接断开
+    fn dying_upstream() -> resume::GeminiByteStream {
+        Box::pin(async_stream::stream! {
+            yield Ok(resume_text_line("The quick brown fox ", false));
+            yield Ok(resume_text_line("jumps over", false));
+            yield Err(reqwest::Client::new().get("not a url").build().unwrap_err());
+        })
+    }
+
+    fn fixture_resumer(
+        chunks: Vec<Bytes>,
+        captured: std::sync::Arc<parking_lot::Mutex<Option<String>>>,
+    ) -> resume::StreamResumer {
+        Box::new(move |partial: String| {
+            *captured.lock() = Some(partial);
+            Box::pin(async move {
+                let stream = futures::stream::iter(chunks.into_iter().map(Ok::<_, reqwest::Error>));
+                Ok(Box::pin(stream) as resume::GeminiByteStream)
+            })
+        })
+    }
+
+    async fn run_resumable(resumer: resume::StreamResumer) -> (String, usize) {
+        use futures::StreamExt;
+        let mut claude_stream = create_claude_sse_stream(
+            dying_upstream(),
+            "trace_test".to_string(),
+            "test@example.com".to_string(),
+            None,
+            false,
+            1_000,
+            None,
+            1,
+            None,
+            None,
+            None,
+            false,
+            std::collections::HashMap::new(),
+            Some(resumer),
+            None,
+            Vec::new(),
+            None,
+        );
+
+        let mut output = String::new();
+        let mut errors = 0;
+        while let Some(result) = claude_stream.next().await {
+            match result {
+                Ok(bytes) => output.push_str(&String::from_utf8(bytes.to_vec()).unwrap()),
+                Err(_) => errors += 1,
+            }
+        }
+        (output, errors)
+    }
+
+    fn collect_text_deltas(output: &str) -> String {
+        output
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .filter_map(|d| serde_json::from_str::<serde_json::Value>(d).ok())
+            .filter(|e| e["delta"]["type"] == "text_delta")
+            .map(|e| e["delta"]["text"].as_str().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_mid_stream_disconnect_is_resumed_into_same_message() {
+        let captured = std::sync::Arc::new(parking_lot::Mutex::new(None));
+        let continuation = vec![
+            // 续写重复了已发送文本的结尾，应被去重
+            resume_text_line("fox jumps over the lazy dog", false),
+            resume_text_line(" and naps in the sun for the rest of the afternoon.", true),
+        ];
+        let (output, errors) = run_resumable(fixture_resumer(continuation, captured.clone())).await;
+
+        assert_eq!(errors, 0);
+        assert_eq!(captured.lock().as_deref(), Some("The quick brown fox jumps over"));
+        assert_eq!(output.matches("event: message_start").count(), 1);
+        assert_eq!(output.matches("event: message_stop").count(), 1);
+        assert_eq!(
+            collect_text_deltas(&output),
+            "The quick brown fox jumps over the lazy dog and naps in the sun for the rest of the afternoon."
+        );
+        assert!(output.contains("\"stop_reason\":\"end_turn\""));
+    }
+
+    #[tokio::test]
+    async fn test_restarted_continuation_falls_back_to_error() {
+        let captured = std::sync::Arc::new(parking_lot::Mutex::new(None));
+        let continuation = vec![
+            // 模型没有续写而是从头重新作答
+            resume_text_line("The quick brown fox leaped over the fence. ", false),
+            resume_text_line("It was a sunny day in the forest and everything was calm.", true),
+        ];
+        let (output, errors) = run_resumable(fixture_resumer(continuation, captured)).await;
+
+        assert_eq!(errors, 1);
+        assert_eq!(collect_text_deltas(&output), "The quick brown fox jumps over");
+        assert!(!output.contains("sunny day"));
+    }
+
+    #[test]
+    fn test_grounding_citations_require_adapter_capability() {
+        let mut state = StreamingState::new();
+        let events = run_grounding_fixture(&mut state);
+        assert!(events.iter().all(|e| e["delta"]["type"] != "citations_delta"));
+    }
 }
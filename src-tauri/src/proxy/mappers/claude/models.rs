@@ -34,6 +34,38 @@ pub struct ClaudeRequest {
     pub size: Option<String>,
     #[serde(default)]
     pub quality: Option<String>,
+    /// 客户端指定的停止序列 (与配置的保护性停止序列合并后下发)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    /// [NEW] 远程 MCP 服务器声明 (转换前展开为 mcp__{server}__{tool} 工具，见 mcp_servers.rs)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mcp_servers: Option<Vec<McpServer>>,
+}
+
+/// 远程 MCP 服务器声明 (MCP connector beta)；授权信息不反序列化，避免进入日志
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct McpServer {
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub type_: Option<String>,
+    /// 缺失时由 flatten_mcp_servers 以 400 指明字段，而不是整体反序列化失败
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// 客户端内联的工具列表 (代理不会向远程服务器查询)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_configuration: Option<McpToolConfiguration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct McpToolConfiguration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// 仅暴露这些工具 (缺省为全部)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_tools: Option<Vec<String>>,
 }
 
 /// Thinking 配置
@@ -172,7 +204,8 @@ pub struct Tool {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
     /// Input schema - required for client tools, absent for server tools
-    #[serde(skip_serializing_if = "Option::is_none")]
+    /// (MCP tool listings use `inputSchema`)
+    #[serde(alias = "inputSchema", skip_serializing_if = "Option::is_none")]
     pub input_schema: Option<serde_json::Value>,
 }
 
@@ -238,6 +271,16 @@ pub struct ClaudeResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stop_sequence: Option<String>,
     pub usage: Usage,
+    /// [NEW] 客户端原始请求的模型名 (扩展字段，`model` 为实际服务的模型)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_model: Option<String>,
+    /// [NEW] 输出 token 按内容类型拆分 (厂商扩展字段，严格客户端可忽略)
+    #[serde(
+        rename = "_abv_usage_breakdown",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub usage_breakdown: Option<crate::modules::token_stats::OutputTokenBreakdown>,
 }
 
 /// Usage
@@ -286,15 +329,72 @@ pub struct GeminiPart {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "inlineData")]
     pub inline_data: Option<InlineData>,
+
+    /// [NEW] 内置代码执行生成的代码 ({ language, code })，仅渲染为文本
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "executableCode")]
+    pub executable_code: Option<serde_json::Value>,
+
+    /// [NEW] 内置代码执行的结果 ({ outcome, output })，仅渲染为文本
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "codeExecutionResult")]
+    pub code_execution_result: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FunctionCall {
+    /// 流式参数的后续 chunk 可能不携带 name
+    #[serde(default)]
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub args: Option<serde_json::Value>,
+    /// [NEW] 流式输出的参数片段 (按 jsonPath 写入)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "partialArgs")]
+    pub partial_args: Option<Vec<PartialArg>>,
+    /// [NEW] 为 true 时本次调用的参数还有后续 chunk
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "willContinue")]
+    pub will_continue: Option<bool>,
+}
+
+impl FunctionCall {
+    /// 是否为流式参数的 chunk (参数分多次到达)
+    pub fn is_streaming(&self) -> bool {
+        self.partial_args.is_some() || self.will_continue == Some(true)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PartialArg {
+    pub json_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub string_value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number_value: Option<serde_json::Number>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bool_value: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub null_value: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub will_continue: Option<bool>,
+}
+
+impl PartialArg {
+    pub fn value(&self) -> Option<serde_json::Value> {
+        if let Some(s) = &self.string_value {
+            Some(serde_json::Value::String(s.clone()))
+        } else if let Some(n) = &self.number_value {
+            Some(serde_json::Value::Number(n.clone()))
+        } else if let Some(b) = self.bool_value {
+            Some(serde_json::Value::Bool(b))
+        } else {
+            self.null_value.as_ref().map(|_| serde_json::Value::Null)
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -356,6 +456,10 @@ pub struct UsageMetadata {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "cachedContentTokenCount")]
     pub cached_content_token_count: Option<u32>,
+    /// 思考内容消耗的 token (不包含在 candidatesTokenCount 中)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "thoughtsTokenCount")]
+    pub thoughts_token_count: Option<u32>,
 }
 
 // ========== Grounding Metadata (for googleSearch results) ==========
@@ -0,0 +1,282 @@
+// 流式工具参数 (functionCall.partialArgs) 的累积
+// 上游流式输出工具参数时，一次调用拆分为多个 functionCall chunk: 每个 chunk 携带若干
+// { jsonPath, stringValue / numberValue / boolValue / nullValue } 片段，同一路径的字符串片段需要拼接。
+// 这里按 jsonPath 将片段写入参数对象，并只发送 "之后的片段只会在其末尾追加内容" 的 JSON 前缀，
+// 保证客户端拼接出的 partial_json 在任何截断点补齐后都是合法 JSON。
+
+use super::models::PartialArg;
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// 解析 jsonPath (`$.a.b`, `$.list[0]`, `$['key.with.dots']`)
+fn parse_json_path(path: &str) -> Option<Vec<PathSegment>> {
+    let rest = path.trim().strip_prefix('$')?;
+    let mut segments = Vec::new();
+    let mut chars = rest.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '.' => {
+                let mut key = String::new();
+                while let Some(&next) = chars.peek() {
+                    if next == '.' || next == '[' {
+                        break;
+                    }
+                    key.push(next);
+                    chars.next();
+                }
+                if key.is_empty() {
+                    return None;
+                }
+                segments.push(PathSegment::Key(key));
+            }
+            '[' => {
+                let mut inner = String::new();
+                for next in chars.by_ref() {
+                    if next == ']' {
+                        break;
+                    }
+                    inner.push(next);
+                }
+                let quoted = inner
+                    .strip_prefix('\'')
+                    .and_then(|s| s.strip_suffix('\''))
+                    .or_else(|| inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')));
+                match quoted {
+                    Some(key) => segments.push(PathSegment::Key(key.to_string())),
+                    None => segments.push(PathSegment::Index(inner.trim().parse().ok()?)),
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(segments)
+}
+
+fn empty_container(next: &PathSegment) -> Value {
+    match next {
+        PathSegment::Key(_) => Value::Object(Map::new()),
+        PathSegment::Index(_) => Value::Array(Vec::new()),
+    }
+}
+
+/// 按路径取得 (必要时创建) 子节点
+fn child_mut<'v>(
+    node: &'v mut Value,
+    segment: &PathSegment,
+    next: Option<&PathSegment>,
+) -> Option<&'v mut Value> {
+    let placeholder = next.map_or(Value::Null, empty_container);
+    match segment {
+        PathSegment::Key(key) => {
+            if !node.is_object() {
+                *node = Value::Object(Map::new());
+            }
+            Some(
+                node.as_object_mut()?
+                    .entry(key.clone())
+                    .or_insert(placeholder),
+            )
+        }
+        PathSegment::Index(idx) => {
+            if !node.is_array() {
+                *node = Value::Array(Vec::new());
+            }
+            let items = node.as_array_mut()?;
+            while items.len() <= *idx {
+                items.push(Value::Null);
+            }
+            if items[*idx].is_null() && next.is_some() {
+                items[*idx] = placeholder;
+            }
+            Some(&mut items[*idx])
+        }
+    }
+}
+
+/// 单个流式工具调用的参数累积器
+#[derive(Debug)]
+pub struct PartialArgsAccumulator {
+    args: Value,
+    /// 最近写入的路径 (同一路径的字符串片段需要拼接)
+    last_path: Option<String>,
+    /// 最近写入的值是字符串 (后续片段可能继续追加，其结束引号不能提前发送)
+    last_is_string: bool,
+    /// 已发送给客户端的 partial_json
+    emitted: String,
+}
+
+impl Default for PartialArgsAccumulator {
+    fn default() -> Self {
+        Self {
+            args: Value::Object(Map::new()),
+            last_path: None,
+            last_is_string: false,
+            emitted: String::new(),
+        }
+    }
+}
+
+impl PartialArgsAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 写入一个片段；路径无法解析或片段不含值时忽略
+    pub fn apply(&mut self, fragment: &PartialArg) -> bool {
+        let Some(value) = fragment.value() else {
+            return false;
+        };
+        let Some(segments) = parse_json_path(&fragment.json_path) else {
+            tracing::warn!(
+                "[Partial-Args] Ignoring fragment with unsupported jsonPath: {}",
+                fragment.json_path
+            );
+            return false;
+        };
+        if segments.is_empty() {
+            if value.is_object() {
+                self.args = value;
+                self.last_path = None;
+                self.last_is_string = false;
+                return true;
+            }
+            return false;
+        }
+
+        let continues_last = self.last_path.as_deref() == Some(fragment.json_path.as_str());
+        let mut node = &mut self.args;
+        for (i, segment) in segments.iter().enumerate() {
+            match child_mut(node, segment, segments.get(i + 1)) {
+                Some(child) => node = child,
+                None => return false,
+            }
+        }
+
+        self.last_is_string = value.is_string();
+        match (node, value) {
+            (Value::String(existing), Value::String(more)) if continues_last => {
+                existing.push_str(&more)
+            }
+            (slot, value) => *slot = value,
+        }
+        self.last_path = Some(fragment.json_path.clone());
+        true
+    }
+
+    pub fn args(&self) -> &Value {
+        &self.args
+    }
+
+    pub fn emitted(&self) -> &str {
+        &self.emitted
+    }
+
+    /// 当前参数中不会再被后续片段改写的 JSON 前缀 (去掉末尾的闭合括号与未结束字符串的引号)
+    fn stable_prefix(&self) -> String {
+        let json = serde_json::to_string(&self.args).unwrap_or_else(|_| "{}".to_string());
+        let mut prefix = json.trim_end_matches(['}', ']']);
+        if self.last_is_string {
+            prefix = prefix.strip_suffix('"').unwrap_or(prefix);
+        }
+        prefix.to_string()
+    }
+
+    /// 计算可以发送的新增 partial_json；前缀与已发送内容不一致 (上游改写了已发送的值) 时暂停发送
+    pub fn take_delta(&mut self) -> Option<String> {
+        let prefix = self.stable_prefix();
+        let delta = prefix.strip_prefix(self.emitted.as_str())?;
+        if delta.is_empty() {
+            return None;
+        }
+        let delta = delta.to_string();
+        self.emitted = prefix;
+        Some(delta)
+    }
+
+    /// 结束调用: 按优先级选择第一个以已发送内容为前缀的最终参数 JSON，返回其序号与剩余部分
+    pub fn finish<'j>(&mut self, candidates: &[&'j str]) -> Option<(usize, &'j str)> {
+        let emitted = std::mem::take(&mut self.emitted);
+        candidates.iter().enumerate().find_map(|(i, candidate)| {
+            candidate
+                .strip_prefix(emitted.as_str())
+                .map(|rest| (i, rest))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn string_fragment(path: &str, value: &str) -> PartialArg {
+        PartialArg {
+            json_path: path.to_string(),
+            string_value: Some(value.to_string()),
+            will_continue: Some(true),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_json_path() {
+        assert_eq!(
+            parse_json_path("$.edits[1].text"),
+            Some(vec![
+                PathSegment::Key("edits".to_string()),
+                PathSegment::Index(1),
+                PathSegment::Key("text".to_string()),
+            ])
+        );
+        assert_eq!(
+            parse_json_path("$['a.b']"),
+            Some(vec![PathSegment::Key("a.b".to_string())])
+        );
+        assert_eq!(parse_json_path("$"), Some(vec![]));
+        assert_eq!(parse_json_path("a.b"), None);
+        assert_eq!(parse_json_path("$.list[x]"), None);
+    }
+
+    #[test]
+    fn test_emitted_prefixes_extend_to_final_json() {
+        let mut acc = PartialArgsAccumulator::new();
+        let fragments = vec![
+            string_fragment("$.path", "/src/"),
+            string_fragment("$.path", "main.rs"),
+            PartialArg {
+                json_path: "$.limit".to_string(),
+                number_value: Some(20.into()),
+                ..Default::default()
+            },
+            string_fragment("$.edits[0].text", "fn main() {\n"),
+            string_fragment("$.edits[0].text", "    println!(\"hi\");\n}"),
+        ];
+
+        let mut streamed = String::new();
+        for fragment in &fragments {
+            assert!(acc.apply(fragment));
+            if let Some(delta) = acc.take_delta() {
+                streamed.push_str(&delta);
+            }
+        }
+
+        let expected = json!({
+            "path": "/src/main.rs",
+            "limit": 20,
+            "edits": [{ "text": "fn main() {\n    println!(\"hi\");\n}" }]
+        });
+        assert_eq!(acc.args(), &expected);
+        // 未结束的字符串不会提前发送结束引号
+        assert!(streamed.ends_with(r#"println!(\"hi\");\n}"#));
+
+        let final_json = serde_json::to_string(&expected).unwrap();
+        streamed.push_str(acc.finish(&[&final_json]).unwrap().1);
+        assert_eq!(serde_json::from_str::<Value>(&streamed).unwrap(), expected);
+    }
+}
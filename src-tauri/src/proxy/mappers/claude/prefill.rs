@@ -0,0 +1,166 @@
+// Assistant 预填充 (prefill)
+// 客户端以 assistant 消息结束 messages 时，该消息的文本是模型需要续写的前缀。
+// 请求侧原样保留为最后一个 model 轮次 (不注入占位思考块)；响应侧不重复发送前缀，
+// 但模型有时会先复述一遍预填充文本，这里只裁掉复述的部分，续写内容照常发送。
+
+use super::models::{ClaudeResponse, ContentBlock, Message, MessageContent};
+
+/// 检测末尾的 assistant 预填充，返回其文本 (包含工具调用或无文本时不视为预填充)
+pub fn detect_prefill(messages: &[Message]) -> Option<String> {
+    let last = messages.last().filter(|m| m.role == "assistant")?;
+    let text = match &last.content {
+        MessageContent::String(text) => text.clone(),
+        MessageContent::Array(blocks) => {
+            let mut text = String::new();
+            for block in blocks {
+                match block {
+                    ContentBlock::Text { text: t } => text.push_str(t),
+                    ContentBlock::Thinking { .. } | ContentBlock::RedactedThinking { .. } => {}
+                    _ => return None,
+                }
+            }
+            text
+        }
+    };
+    // Anthropic 要求预填充不以空白结尾，模型续写时通常也不会复述末尾空白
+    let text = text.trim_end();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// 流式裁剪模型对预填充文本的复述
+///
+/// 可见文本在仍可能是复述时暂存；与预填充一致的前缀被丢弃，出现分歧时原样发送暂存内容。
+#[derive(Debug)]
+pub struct PrefillEcho {
+    prefill: String,
+    held: String,
+    done: bool,
+}
+
+impl PrefillEcho {
+    pub fn new(prefill: String) -> Self {
+        Self {
+            prefill,
+            held: String::new(),
+            done: false,
+        }
+    }
+
+    /// 处理一段可见文本，返回应发送的部分 (仍在判断时返回空串)
+    pub fn process(&mut self, text: &str) -> String {
+        if self.done {
+            return text.to_string();
+        }
+        self.held.push_str(text);
+        if self.held.len() < self.prefill.len() && self.prefill.starts_with(self.held.as_str()) {
+            return String::new();
+        }
+
+        self.done = true;
+        let held = std::mem::take(&mut self.held);
+        match held.strip_prefix(self.prefill.as_str()) {
+            Some(rest) => {
+                tracing::debug!(
+                    "[Prefill] Trimmed {} echoed prefill chars from model output",
+                    self.prefill.len()
+                );
+                rest.to_string()
+            }
+            None => held,
+        }
+    }
+
+    /// 文本块结束时释放仍在暂存的文本: 输出停在预填充的中途时无法确认是复述，原样发送
+    pub fn flush(&mut self) -> String {
+        self.done = true;
+        std::mem::take(&mut self.held)
+    }
+}
+
+/// 非流式响应: 裁剪第一个文本块中对预填充的复述
+pub fn trim_response_echo(response: &mut ClaudeResponse, prefill: &str) {
+    let Some(text) = response.content.iter_mut().find_map(|block| match block {
+        ContentBlock::Text { text } => Some(text),
+        _ => None,
+    }) else {
+        return;
+    };
+    let mut echo = PrefillEcho::new(prefill.to_string());
+    let mut trimmed = echo.process(text);
+    trimmed.push_str(&echo.flush());
+    *text = trimmed;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream_through(prefill: &str, deltas: &[&str]) -> String {
+        let mut echo = PrefillEcho::new(prefill.to_string());
+        deltas.iter().map(|d| echo.process(d)).collect()
+    }
+
+    #[test]
+    fn test_detect_prefill() {
+        let user = Message {
+            role: "user".to_string(),
+            content: MessageContent::String("List three colors as JSON".to_string()),
+        };
+        let prefill = Message {
+            role: "assistant".to_string(),
+            content: MessageContent::Array(vec![ContentBlock::Text {
+                text: "{\"colors\": [ ".to_string(),
+            }]),
+        };
+        assert_eq!(
+            detect_prefill(&[user.clone(), prefill]).as_deref(),
+            Some("{\"colors\": [")
+        );
+        assert_eq!(detect_prefill(&[user]), None);
+    }
+
+    #[test]
+    fn test_prefill_continuation_without_echo() {
+        let out = stream_through("The capital of France is", &[" Paris", ", of course."]);
+        assert_eq!(out, " Paris, of course.");
+
+        // 续写恰好以预填充的开头字符开始，分歧后暂存内容原样发送
+        let out = stream_through("{\"colors\": [", &["\"red\", ", "\"blue\"]}"]);
+        assert_eq!(out, "\"red\", \"blue\"]}");
+    }
+
+    #[test]
+    fn test_output_ending_inside_prefill_is_flushed() {
+        // 整段输出都是预填充的前缀: 块结束时原样释放，而不是静默丢弃
+        let mut echo = PrefillEcho::new("The capital of France is".to_string());
+        assert_eq!(echo.process("The capital"), "");
+        assert_eq!(echo.flush(), "The capital");
+        assert_eq!(echo.process(" next"), " next");
+    }
+
+    #[test]
+    fn test_prefill_continuation_with_echo() {
+        // 复述跨越多个 delta 时整体裁掉，只保留续写部分
+        let out = stream_through(
+            "The capital of France is",
+            &["The capital", " of France", " is Paris."],
+        );
+        assert_eq!(out, " Paris.");
+
+        let mut response: ClaudeResponse = serde_json::from_value(serde_json::json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-sonnet-4-5",
+            "content": [{ "type": "text", "text": "{\"colors\": [\"red\"]}" }],
+            "stop_reason": "end_turn",
+            "usage": { "input_tokens": 10, "output_tokens": 5 }
+        }))
+        .unwrap();
+        trim_response_echo(&mut response, "{\"colors\": [");
+        assert!(matches!(
+            &response.content[0],
+            ContentBlock::Text { text } if text == "\"red\"]}"
+        ));
+    }
+}
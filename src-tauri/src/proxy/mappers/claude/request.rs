@@ -2,68 +2,24 @@
 // 对应 transformClaudeRequestIn
 
 use super::models::*;
+use crate::proxy::mappers::common::envelope::{apply_client_session_id, build_envelope};
+use crate::proxy::mappers::common::system_builder::{self, IdentityConfig};
+use crate::proxy::mappers::common::thinking_budget;
+use crate::proxy::mappers::common_utils::{
+    deep_clean, is_cache_control, is_thinking_field, is_undefined_string, RemovalPredicate,
+};
+use crate::proxy::mappers::common_utils::{EnvelopeParams, RequestContext};
+use crate::proxy::mappers::error::MapperError;
 use crate::proxy::mappers::signature_store::get_thought_signature; // Deprecated, kept for fallback
 use crate::proxy::mappers::tool_result_compressor;
-use crate::proxy::session_manager::SessionManager;
+use crate::proxy::session_manager::{SessionGenerationOverrides, SessionManager};
 use serde_json::{json, Value};
 use std::collections::HashMap;
 
 // ===== Safety Settings Configuration =====
-
-/// Safety threshold levels for Gemini API
-/// Can be configured via GEMINI_SAFETY_THRESHOLD environment variable
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum SafetyThreshold {
-    /// Disable all safety filters (default for proxy compatibility)
-    Off,
-    /// Block low probability and above
-    BlockLowAndAbove,
-    /// Block medium probability and above
-    BlockMediumAndAbove,
-    /// Only block high probability content
-    BlockOnlyHigh,
-    /// Don't block anything (BLOCK_NONE)
-    BlockNone,
-}
-
-impl SafetyThreshold {
-    /// Get threshold from environment variable or default to Off
-    pub fn from_env() -> Self {
-        match std::env::var("GEMINI_SAFETY_THRESHOLD").as_deref() {
-            Ok("OFF") | Ok("off") => SafetyThreshold::Off,
-            Ok("LOW") | Ok("low") => SafetyThreshold::BlockLowAndAbove,
-            Ok("MEDIUM") | Ok("medium") => SafetyThreshold::BlockMediumAndAbove,
-            Ok("HIGH") | Ok("high") => SafetyThreshold::BlockOnlyHigh,
-            Ok("NONE") | Ok("none") => SafetyThreshold::BlockNone,
-            _ => SafetyThreshold::Off, // Default: maintain current behavior
-        }
-    }
-
-    /// Convert to Gemini API threshold string
-    pub fn to_gemini_threshold(&self) -> &'static str {
-        match self {
-            SafetyThreshold::Off => "OFF",
-            SafetyThreshold::BlockLowAndAbove => "BLOCK_LOW_AND_ABOVE",
-            SafetyThreshold::BlockMediumAndAbove => "BLOCK_MEDIUM_AND_ABOVE",
-            SafetyThreshold::BlockOnlyHigh => "BLOCK_ONLY_HIGH",
-            SafetyThreshold::BlockNone => "BLOCK_NONE",
-        }
-    }
-}
-
-/// Build safety settings based on configuration
-fn build_safety_settings() -> Value {
-    let threshold = SafetyThreshold::from_env();
-    let threshold_str = threshold.to_gemini_threshold();
-
-    json!([
-        { "category": "HARM_CATEGORY_HARASSMENT", "threshold": threshold_str },
-        { "category": "HARM_CATEGORY_HATE_SPEECH", "threshold": threshold_str },
-        { "category": "HARM_CATEGORY_SEXUALLY_EXPLICIT", "threshold": threshold_str },
-        { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "threshold": threshold_str },
-        { "category": "HARM_CATEGORY_CIVIC_INTEGRITY", "threshold": threshold_str },
-    ])
-}
+// [NEW] 已迁移至 common::safety，与 OpenAI 链路共用 (保留此路径的导出以兼容既有引用)
+pub use crate::proxy::mappers::common::safety::SafetyThreshold;
+use crate::proxy::mappers::common::safety::build_safety_settings;
 
 /// 清理消息中的 cache_control 字段
 ///
@@ -152,21 +108,9 @@ pub fn clean_cache_control_from_messages(messages: &mut [Message]) {
 /// 用于处理嵌套结构和非标准位置的 cache_control。
 /// 这是最后一道防线,确保发送给 Antigravity 的请求中不包含任何 cache_control。
 fn deep_clean_cache_control(value: &mut Value) {
-    match value {
-        Value::Object(map) => {
-            if map.remove("cache_control").is_some() {
-                tracing::debug!("[DEBUG-593] Removed cache_control from nested JSON object");
-            }
-            for (_, v) in map.iter_mut() {
-                deep_clean_cache_control(v);
-            }
-        }
-        Value::Array(arr) => {
-            for item in arr.iter_mut() {
-                deep_clean_cache_control(item);
-            }
-        }
-        _ => {}
+    let removed = deep_clean(value, 0, &[is_cache_control], "deep_clean_cache_control");
+    if removed > 0 {
+        tracing::debug!("[DEBUG-593] Removed {} nested cache_control fields", removed);
     }
 }
 
@@ -298,8 +242,6 @@ pub fn merge_consecutive_messages(messages: &mut Vec<Message>) {
     *messages = merged;
 }
 
-/// 转换 Claude 请求为 Gemini v1internal 格式
-
 /// [FIX #709] Reorder serialized Gemini parts to ensure thinking blocks are first
 fn reorder_gemini_parts(parts: &mut Vec<Value>) {
     if parts.len() <= 1 {
@@ -332,11 +274,14 @@ fn reorder_gemini_parts(parts: &mut Vec<Value>) {
     parts.extend(tool_parts);
 }
 
+/// 转换 Claude 请求为 Gemini v1internal 格式
 pub fn transform_claude_request_in(
     claude_req: &ClaudeRequest,
     project_id: &str,
     is_retry: bool,
-) -> Result<Value, String> {
+    envelope: &EnvelopeParams, // [NEW] Per-account userAgent / requestType overrides
+    request_ctx: &RequestContext, // [NEW] Request-level options from client headers
+) -> Result<Value, MapperError> {
     // [CRITICAL FIX] 预先清理所有消息中的 cache_control 字段
     // 这解决了 VS Code 插件等客户端在多轮对话中将历史消息的 cache_control 字段
     // 原封不动发回导致的 "Extra inputs are not permitted" 错误
@@ -344,10 +289,20 @@ pub fn transform_claude_request_in(
 
     // [FIX #813] 合并连续的同角色消息 (Consecutive User Messages)
     // 确保请求符合 Anthropic 和 Gemini 的角色交替协议
-    merge_consecutive_messages(&mut cleaned_req.messages);
+    // [NEW] 可通过 strict_role_alternation 关闭，以保留独立的 tool-result 轮次
+    let strict_role_alternation = crate::proxy::config::get_strict_role_alternation();
+    if strict_role_alternation {
+        merge_consecutive_messages(&mut cleaned_req.messages);
+    }
 
     clean_cache_control_from_messages(&mut cleaned_req.messages);
 
+    // [NEW] 功能开关按原始请求的会话 ID 分桶 (与 handler 调试日志记录的取值一致)
+    // triple_partition_reorder 只控制合并相邻同角色轮次后的 parts 重排 (reorder_gemini_parts)
+    let flag_session_id = SessionManager::extract_session_id(claude_req);
+    let triple_partition_reorder =
+        crate::proxy::flags::is_enabled(crate::proxy::flags::TRIPLE_PARTITION_REORDER, Some(&flag_session_id));
+
     // [FIX #564] Pre-sort thinking blocks to be first in assistant messages
     // This handles cases where context compression (kilo) incorrectly reorders blocks
     sort_thinking_blocks_first(&mut cleaned_req.messages);
@@ -391,18 +346,9 @@ pub fn transform_claude_request_in(
     let mut tool_id_to_name: HashMap<String, String> = HashMap::new();
 
     // 检测是否有 mcp__ 开头的工具
-    let has_mcp_tools = claude_req
-        .tools
-        .as_ref()
-        .map(|tools| {
-            tools.iter().any(|t| {
-                t.name
-                    .as_deref()
-                    .map(|n| n.starts_with("mcp__"))
-                    .unwrap_or(false)
-            })
-        })
-        .unwrap_or(false);
+    let has_mcp_tools = system_builder::has_mcp_tools(
+        claude_req.tools.iter().flatten().filter_map(|t| t.name.as_deref()),
+    );
 
     // [New] 预先构建工具名称到原始 Schema 的映射，用于后续参数类型修正
     let mut tool_name_to_schema = HashMap::new();
@@ -440,7 +386,7 @@ pub fn transform_claude_request_in(
     });
 
     // Resolve grounding config
-    let config = crate::proxy::mappers::common_utils::resolve_request_config(
+    let mut config = crate::proxy::mappers::common_utils::resolve_request_config(
         &claude_req.model,
         &mapped_model,
         &tools_val,
@@ -449,12 +395,15 @@ pub fn transform_claude_request_in(
         None,                          // [NEW] image_size
         None,                          // body
     );
+    // [NEW] 请求级关闭 googleSearch 自动注入 (X-Abv-Disable-Google-Search)
+    config.suppress_google_search(request_ctx.disable_google_search);
 
-    // [CRITICAL FIX] Disable dummy thought injection for Vertex AI
     // [CRITICAL FIX] Disable dummy thought injection for Vertex AI
     // Vertex AI rejects thinking blocks without valid signatures
     // Even if thinking is enabled, we should NOT inject dummy blocks for historical messages
-    let allow_dummy_thought = false;
+    // [NEW] 默认关闭：由 dummy_thought_injection 开关控制 (可按会话灰度)，占位文本取自 dummy_thought.text
+    let dummy_thought_text = crate::proxy::flags::is_enabled(crate::proxy::flags::DUMMY_THOUGHT_INJECTION, Some(&flag_session_id))
+        .then(|| crate::proxy::config::get_dummy_thought_config().injection_text());
 
     // Check if thinking is enabled in the request
     let thinking_type = claude_req.thinking.as_ref().map(|t| t.type_.as_str());
@@ -549,6 +498,7 @@ pub fn transform_claude_request_in(
         &mapped_model,
         has_web_search_tool,
         is_thinking_enabled,
+        &session_id,
     );
 
     // 2. Contents (Messages)
@@ -558,10 +508,12 @@ pub fn transform_claude_request_in(
         &mut tool_id_to_name,
         &tool_name_to_schema,
         is_thinking_enabled,
-        allow_dummy_thought,
+        dummy_thought_text.as_deref(),
+        triple_partition_reorder,
         &mapped_model,
         &session_id,
         is_retry,
+        strict_role_alternation,
     )?;
 
     // 3. Tools
@@ -596,77 +548,112 @@ pub fn transform_claude_request_in(
     }
 
 
-    // 深度清理 [undefined] 字符串 (Cherry Studio 等客户端常见注入)
-    crate::proxy::mappers::common_utils::deep_clean_undefined(&mut inner_request, 0);
-
-
     if config.inject_google_search && !has_web_search_tool {
         crate::proxy::mappers::common_utils::inject_google_search_tool(&mut inner_request);
     }
 
     // Inject imageConfig if present (for image generation models)
     if let Some(image_config) = config.image_config {
-        if let Some(obj) = inner_request.as_object_mut() {
-            // 1. Remove tools (image generation does not support tools)
-            obj.remove("tools");
-
-            // 2. Remove systemInstruction (image generation does not support system prompts)
-            obj.remove("systemInstruction");
-
-            // 3. Clean generationConfig (remove responseMimeType, responseModalities etc.)
-            let gen_config = obj.entry("generationConfig").or_insert_with(|| json!({}));
-            if let Some(gen_obj) = gen_config.as_object_mut() {
-                // [RESOLVE #1694] Check image thinking mode
-                let image_thinking_mode = crate::proxy::config::get_image_thinking_mode();
-                if image_thinking_mode == "disabled" {
-                    tracing::debug!(
-                        "[Claude-Request] Image thinking mode disabled: enforcing includeThoughts=false for {}",
-                        mapped_model
-                    );
-                    gen_obj.insert(
-                        "thinkingConfig".to_string(),
-                        json!({
-                            "includeThoughts": false
-                        }),
-                    );
-                }
-
-                gen_obj.remove("responseMimeType");
-                gen_obj.remove("responseModalities");
-                gen_obj.insert("imageConfig".to_string(), image_config);
+        let obj = inner_request
+            .as_object_mut()
+            .ok_or_else(|| MapperError::internal("inner request is not a JSON object"))?;
+        // 1. Remove tools (image generation does not support tools)
+        obj.remove("tools");
+
+        // 2. Remove systemInstruction (image generation does not support system prompts)
+        obj.remove("systemInstruction");
+
+        // 3. Clean generationConfig (remove responseMimeType, responseModalities etc.)
+        let gen_config = obj.entry("generationConfig").or_insert_with(|| json!({}));
+        if let Some(gen_obj) = gen_config.as_object_mut() {
+            // [RESOLVE #1694] Check image thinking mode
+            let image_thinking_mode = crate::proxy::config::get_image_thinking_mode();
+            if image_thinking_mode == "disabled" {
+                tracing::debug!(
+                    "[Claude-Request] Image thinking mode disabled: enforcing includeThoughts=false for {}",
+                    mapped_model
+                );
+                gen_obj.insert(
+                    "thinkingConfig".to_string(),
+                    json!({
+                        "includeThoughts": false
+                    }),
+                );
             }
+
+            gen_obj.remove("responseMimeType");
+            crate::proxy::mappers::common_utils::apply_image_response_modalities(gen_obj);
+            gen_obj.insert("imageConfig".to_string(), image_config);
         }
     }
 
 
-    // 生成 requestId
-    let request_id = format!("agent-{}", uuid::Uuid::new_v4());
+    // 生成 requestId (前缀可配置，默认 agent)
+    let request_id = crate::proxy::mappers::common_utils::build_request_id(
+        &crate::proxy::config::get_request_id_prefix_config().claude,
+        &crate::proxy::config::RequestIdPrefixConfig::default().claude,
+    );
 
     // 构建最终请求体
-    let mut body = json!({
-        "project": project_id,
-        "requestId": request_id,
-        "request": inner_request,
-        "model": config.final_model,
-        "userAgent": "antigravity",
-        "requestType": config.request_type,
-    });
-
-    // 如果提供了 metadata.user_id，则复用为 sessionId
-    if let Some(metadata) = &claude_req.metadata {
-        if let Some(user_id) = &metadata.user_id {
-            body["request"]["sessionId"] = json!(user_id);
-        }
-    }
+    let mut body = build_envelope(
+        project_id,
+        request_id,
+        inner_request,
+        &config.final_model,
+        envelope,
+        &config.request_type,
+    );
 
-    // [FIX #593] 最后一道防线: 递归深度清理所有 cache_control 字段
-    // 确保发送给 Antigravity 的请求中不包含任何 cache_control
-    deep_clean_cache_control(&mut body);
+    // 如果提供了 metadata.user_id，则复用为 sessionId (客户端可控，先清洗)
+    apply_client_session_id(
+        &mut body,
+        "metadata.user_id",
+        claude_req.metadata.as_ref().and_then(|m| m.user_id.as_deref()),
+    )?;
+    // [NEW] 已学习到不接受哨兵值的上游模型: 移除注入的哨兵值
+    crate::proxy::signature_sentinel::strip_unsupported(&mut body);
+
+    // [FIX #593] 最后一道防线: 单次遍历完成所有深度清理
+    // - [undefined] 字符串 (Cherry Studio 等客户端常见注入)
+    // - cache_control 字段 (确保发送给 Antigravity 的请求中不包含任何 cache_control)
+    // - [FIX P3-4] thinking 降级时 contents 中残留的 thought / thoughtSignature
+    final_deep_clean(&mut body, !is_thinking_enabled);
     tracing::debug!("[DEBUG-593] Final deep clean complete, request ready to send");
+    crate::proxy::mappers::common_utils::trace_transformed_body("Claude", &body, request_ctx);
 
     Ok(body)
 }
 
+/// 对最终请求体执行一次深度清理
+///
+/// contents 额外移除 thinking 字段 (thinking 关闭时)，其余部分 (如工具 schema 中名为 thought 的参数) 不受影响。
+/// 每个节点只访问一次。
+fn final_deep_clean(body: &mut Value, strip_thinking: bool) -> usize {
+    let started = std::time::Instant::now();
+    let base: &[RemovalPredicate] = &[is_undefined_string, is_cache_control];
+    let with_thinking: &[RemovalPredicate] = &[is_undefined_string, is_cache_control, is_thinking_field];
+
+    // 先将 contents 取出，分别清理后放回
+    let contents = body
+        .get_mut("request")
+        .and_then(|r| r.get_mut("contents"))
+        .map(std::mem::take);
+    let mut removed = deep_clean(body, 0, base, "final_deep_clean");
+    if let Some(mut contents) = contents {
+        let predicates = if strip_thinking { with_thinking } else { base };
+        // contents 位于 body.request.contents，从第 2 层开始计算深度
+        removed += deep_clean(&mut contents, 2, predicates, "final_deep_clean");
+        body["request"]["contents"] = contents;
+    }
+
+    tracing::debug!(
+        "[Deep-Clean] Removed {} fields in {}µs",
+        removed,
+        started.elapsed().as_micros()
+    );
+    removed
+}
+
 /// 检查是否因为历史消息原因需要禁用 Thinking
 ///
 /// 场景: 如果最后一条 Assistant 消息处于 Tool Use 流程中，但没有 Thinking 块，
@@ -705,6 +692,11 @@ fn should_disable_thinking_due_to_history(messages: &[Message]) -> bool {
 /// This function determines if the model should have thinking enabled
 /// when no explicit thinking configuration is provided.
 fn should_enable_thinking_by_default(model: &str) -> bool {
+    // [NEW] 监听配置档可覆盖默认 thinking 行为
+    if let Some(enabled) = crate::proxy::listener_profile::default_thinking() {
+        return enabled;
+    }
+
     let model_lower = model.to_lowercase();
 
     // Enable thinking by default for Opus 4.5 and 4.6 variants
@@ -811,85 +803,21 @@ fn build_system_instruction(
     _model_name: &str,
     has_mcp_tools: bool,
 ) -> Option<Value> {
-    let mut parts = Vec::new();
-
-    // [NEW] Antigravity 身份指令 (原始简化版)
-    let antigravity_identity = "You are Antigravity, a powerful agentic AI coding assistant designed by the Google Deepmind team working on Advanced Agentic Coding.\n\
-    You are pair programming with a USER to solve their coding task. The task may require creating a new codebase, modifying or debugging an existing codebase, or simply answering a question.\n\
-    **Absolute paths only**\n\
-    **Proactiveness**";
-
-    // [HYBRID] 检查用户是否已提供 Antigravity 身份
-    let mut user_has_antigravity = false;
-    if let Some(sys) = system {
-        match sys {
-            SystemPrompt::String(text) => {
-                if text.contains("You are Antigravity") {
-                    user_has_antigravity = true;
-                }
-            }
-            SystemPrompt::Array(blocks) => {
-                for block in blocks {
-                    if block.block_type == "text" && block.text.contains("You are Antigravity") {
-                        user_has_antigravity = true;
-                        break;
-                    }
-                }
-            }
-        }
-    }
-
-    // 如果用户没有提供 Antigravity 身份,则注入
-    if !user_has_antigravity {
-        parts.push(json!({"text": antigravity_identity}));
-    }
-
-    // [NEW] 注入全局系统提示词 (紧跟 Antigravity 身份之后)
-    let global_prompt_config = crate::proxy::config::get_global_system_prompt();
-    if global_prompt_config.enabled && !global_prompt_config.content.trim().is_empty() {
-        parts.push(json!({"text": global_prompt_config.content}));
-    }
-
-    // 添加用户的系统提示词
-    if let Some(sys) = system {
-        match sys {
-            SystemPrompt::String(text) => {
-                // [MODIFIED] No longer filter "You are an interactive CLI tool"
-                // We pass everything through to ensure Flash/Lite models get full instructions
-                parts.push(json!({"text": text}));
-            }
-            SystemPrompt::Array(blocks) => {
-                for block in blocks {
-                    if block.block_type == "text" {
-                        // [MODIFIED] No longer filter "You are an interactive CLI tool"
-                        parts.push(json!({"text": block.text}));
-                    }
-                }
-            }
-        }
-    }
-
-    // [NEW] MCP XML Bridge: 如果存在 mcp__ 开头的工具，注入专用的调用协议
-    // 这能有效规避部分 MCP 链路在标准的 tool_use 协议下解析不稳的问题
-    if has_mcp_tools {
-        let mcp_xml_prompt = "\n\
-        ==== MCP XML 工具调用协议 (Workaround) ====\n\
-        当你需要调用名称以 `mcp__` 开头的 MCP 工具时：\n\
-        1) 优先尝试 XML 格式调用：输出 `<mcp__tool_name>{\"arg\":\"value\"}</mcp__tool_name>`。\n\
-        2) 必须直接输出 XML 块，无需 markdown 包装，内容为 JSON 格式的入参。\n\
-        3) 这种方式具有更高的连通性和容错性，适用于大型结果返回场景。\n\
-        ===========================================";
-        parts.push(json!({"text": mcp_xml_prompt}));
-    }
-
-    // 如果用户没有提供任何系统提示词,添加结束标记
-    if !user_has_antigravity {
-        parts.push(json!({"text": "\n--- [SYSTEM_PROMPT_END] ---"}));
-    }
+    // [MODIFIED] No longer filter "You are an interactive CLI tool"
+    // We pass everything through to ensure Flash/Lite models get full instructions
+    let user_texts: Vec<&str> = match system {
+        Some(SystemPrompt::String(text)) => vec![text.as_str()],
+        Some(SystemPrompt::Array(blocks)) => blocks
+            .iter()
+            .filter(|b| b.block_type == "text")
+            .map(|b| b.text.as_str())
+            .collect(),
+        None => Vec::new(),
+    };
 
     Some(json!({
         "role": "user",
-        "parts": parts
+        "parts": system_builder::build_system_parts(&user_texts, has_mcp_tools, &IdentityConfig::current())
     }))
 }
 
@@ -900,7 +828,7 @@ fn build_contents(
     _claude_req: &ClaudeRequest,
     is_thinking_enabled: bool,
     session_id: &str,
-    allow_dummy_thought: bool,
+    dummy_thought_text: Option<&str>,
     is_retry: bool,
     tool_id_to_name: &mut HashMap<String, String>,
     tool_name_to_schema: &HashMap<String, Value>,
@@ -910,7 +838,7 @@ fn build_contents(
     last_user_task_text_normalized: &mut Option<String>,
     previous_was_tool_result: &mut bool,
     _existing_tool_result_ids: &std::collections::HashSet<String>,
-) -> Result<Vec<Value>, String> {
+) -> Result<Vec<Value>, MapperError> {
     let mut parts = Vec::new();
     // Track tool results in the current turn to identify missing ones
     let mut current_turn_tool_result_ids = std::collections::HashSet::new();
@@ -933,13 +861,13 @@ fn build_contents(
                     ContentBlock::Text { text } => {
                         if text != "(no content)" {
                             // [NEW] 任务去重逻辑: 如果当前是 User 消息，且紧跟在 ToolResult 之后，
-                            // 检查该文本是否与上一轮任务描述完全一致。
+                            // 检查该文本是否与上一轮任务描述完全一致 (可配置关闭；过短的文本不参与去重)。
                             if !is_assistant && *previous_was_tool_result {
                                 if let Some(last_task) = last_user_task_text_normalized {
                                     let current_normalized =
                                         text.replace(|c: char| c.is_whitespace(), "");
-                                    if !current_normalized.is_empty()
-                                        && current_normalized == *last_task
+                                    if crate::proxy::config::get_task_echo_dedup_config()
+                                        .is_echo(&current_normalized, last_task)
                                     {
                                         tracing::info!("[Claude-Request] Dropping duplicated task text echo (len: {})", text.len());
                                         continue;
@@ -1005,7 +933,8 @@ fn build_contents(
 
                         // [FIX #752] Strict signature validation
                         // Only use signatures that are cached and compatible with the target model
-                        if let Some(sig) = signature {
+                        // (空签名视为未收到签名，可从迟到签名缓存中恢复)
+                        if let Some(sig) = signature.as_ref().filter(|s| !s.is_empty()) {
                             // Check signature length first - if it's too short, it's definitely invalid
                             if sig.len() < MIN_SIGNATURE_LENGTH {
                                 tracing::warn!(
@@ -1078,6 +1007,22 @@ fn build_contents(
                                     }
                                 }
                             }
+                        } else if let Some(sig) = crate::proxy::SignatureCache::global()
+                            .get_thinking_block_signature(session_id, thinking)
+                            .filter(|_| !is_retry)
+                            .filter(|sig| recovered_signature_compatible(sig, mapped_model))
+                        {
+                            // [NEW] 签名在该 thinking 块关闭后才到达，客户端未收到: 按块哈希恢复
+                            tracing::info!(
+                                "[Thinking-Signature] Recovered late signature for thinking block from cache (len: {})",
+                                sig.len()
+                            );
+                            *last_thought_signature = Some(sig.clone());
+                            parts.push(json!({
+                                "text": thinking,
+                                "thought": true,
+                                "thoughtSignature": sig
+                            }));
                         } else {
                             // No signature: downgrade to text
                             tracing::warn!(
@@ -1125,7 +1070,7 @@ fn build_contents(
                         signature,
                         ..
                     } => {
-                        let mut final_input = input.clone();
+                        let mut final_input = crate::proxy::mappers::common_utils::coerce_tool_input_to_object(input, name, "input");
 
                         // [New] 利用通用引擎修正参数类型 (替代以前硬编码的 shell 工具修复逻辑)
                         if let Some(original_schema) = tool_name_to_schema.get(name) {
@@ -1254,11 +1199,12 @@ fn build_contents(
                         } else {
                             // [NEW] Handle missing signature for Gemini thinking models
                             // Use skip_thought_signature_validator as a sentinel value
+                            // (已学习到不接受哨兵值的模型在信封构建后统一移除)
                             let is_google_cloud = mapped_model.starts_with("projects/");
                             if is_thinking_enabled && !is_google_cloud {
                                 tracing::debug!("[Tool-Signature] Adding GEMINI_SKIP_SIGNATURE for tool_use: {}", id);
                                 part["thoughtSignature"] =
-                                    json!("skip_thought_signature_validator");
+                                    json!(crate::proxy::signature_sentinel::SENTINEL);
                             }
                         }
                         parts.push(part);
@@ -1400,7 +1346,7 @@ fn build_contents(
     // Fix for "Thinking enabled, assistant message must start with thinking block" 400 error
     // [Optimization] Apply this to ALL assistant messages in history, not just the last one.
     // Vertex AI requires every assistant message to start with a thinking block when thinking is enabled.
-    if allow_dummy_thought && is_assistant && is_thinking_enabled {
+    if let Some(dummy_text) = dummy_thought_text.filter(|_| is_assistant && is_thinking_enabled) {
         let has_thought_part = parts.iter().any(|p| {
             p.get("thought").and_then(|v| v.as_bool()).unwrap_or(false)
                 || p.get("thoughtSignature").is_some()
@@ -1412,7 +1358,7 @@ fn build_contents(
             parts.insert(
                 0,
                 json!({
-                    "text": "Thinking...",
+                    "text": dummy_text,
                     "thought": true
                 }),
             );
@@ -1459,7 +1405,7 @@ fn build_google_content(
     claude_req: &ClaudeRequest,
     is_thinking_enabled: bool,
     session_id: &str,
-    allow_dummy_thought: bool,
+    dummy_thought_text: Option<&str>,
     is_retry: bool,
     tool_id_to_name: &mut HashMap<String, String>,
     tool_name_to_schema: &HashMap<String, Value>,
@@ -1469,7 +1415,7 @@ fn build_google_content(
     last_user_task_text_normalized: &mut Option<String>,
     previous_was_tool_result: &mut bool,
     existing_tool_result_ids: &std::collections::HashSet<String>,
-) -> Result<Value, String> {
+) -> Result<Value, MapperError> {
     let role = if msg.role == "assistant" {
         "model"
     } else {
@@ -1516,7 +1462,7 @@ fn build_google_content(
         claude_req,
         is_thinking_enabled,
         session_id,
-        allow_dummy_thought,
+        dummy_thought_text,
         is_retry,
         tool_id_to_name,
         tool_name_to_schema,
@@ -1545,11 +1491,13 @@ fn build_google_contents(
     tool_id_to_name: &mut HashMap<String, String>,
     tool_name_to_schema: &HashMap<String, Value>,
     is_thinking_enabled: bool,
-    allow_dummy_thought: bool,
+    dummy_thought_text: Option<&str>,
+    triple_partition_reorder: bool, // [NEW] 功能开关: 合并后按 [Thinking, Text, Tool] 重排
     mapped_model: &str,
     session_id: &str, // [NEW v3.3.17] Session ID for signature caching
     is_retry: bool,
-) -> Result<Value, String> {
+    strict_role_alternation: bool, // [NEW] false 时不合并相邻同角色消息
+) -> Result<Value, MapperError> {
     let mut contents = Vec::new();
     let mut last_thought_signature: Option<String> = None;
     let mut _accumulated_usage: Option<Value> = None;
@@ -1562,6 +1510,13 @@ fn build_google_contents(
 
     let _msg_count = messages.len();
 
+    // [NEW] assistant 预填充: 末尾的 assistant 消息作为最后一个 model 轮次原样保留，由模型续写，
+    // 不在其前面注入占位思考块 (否则上游会把它当作已完成的回复)
+    let prefill_index = super::prefill::detect_prefill(messages).map(|_| messages.len() - 1);
+    if prefill_index.is_some() {
+        tracing::debug!("[Claude-Request] Trailing assistant message detected, treating as prefill");
+    }
+
     // [FIX #632] Pre-scan all messages to identify all tool_result IDs that ALREADY exist in the conversation.
     // This prevents Elastic-Recovery from injecting duplicate results if they are present later in the chain.
     let mut existing_tool_result_ids = std::collections::HashSet::new();
@@ -1575,13 +1530,13 @@ fn build_google_contents(
         }
     }
 
-    for (_i, msg) in messages.iter().enumerate() {
+    for (i, msg) in messages.iter().enumerate() {
         let google_content = build_google_content(
             msg,
             claude_req,
             is_thinking_enabled,
             session_id,
-            allow_dummy_thought,
+            dummy_thought_text.filter(|_| Some(i) != prefill_index),
             is_retry,
             tool_id_to_name,
             tool_name_to_schema,
@@ -1598,28 +1553,125 @@ fn build_google_contents(
         }
     }
 
+    // [NEW] 客户端重连后可能重复发送同一 tool_use_id 的 tool_result，上游会因重复的 functionResponse 报 400
+    dedupe_function_responses(&mut contents);
+
     // [Removed] ensure_last_assistant_has_thinking
     // Corrupted signature issues proved we cannot fake thinking blocks.
     // Instead we rely on should_disable_thinking_due_to_history to prevent this state.
 
     // [FIX P3-3] Strict Role Alternation (Message Merging)
     // Merge adjacent messages with the same role to satisfy Gemini's strict alternation rule
-    let mut merged_contents = merge_adjacent_roles(contents);
+    // (skipped when strict_role_alternation is disabled for endpoints that tolerate consecutive roles)
+    let mut merged_contents = if strict_role_alternation {
+        merge_adjacent_roles(contents, triple_partition_reorder)
+    } else {
+        contents
+    };
 
-    // [FIX P3-4] Deep "Un-thinking" Cleanup
-    // If thinking is disabled (e.g. smart downgrade), recursively remove any stray 'thought'/'thoughtSignature'
-    // This is critical because converting Thinking->Text isn't enough; metadata must be gone.
-    if !is_thinking_enabled {
-        for msg in &mut merged_contents {
-            clean_thinking_fields_recursive(msg);
+    // 预填充文本末尾的空白会让续写从空白之后开始，去掉
+    if prefill_index.is_some() {
+        if let Some(text) = merged_contents
+            .last_mut()
+            .filter(|c| c["role"] == "model")
+            .and_then(|c| c["parts"].as_array_mut())
+            .and_then(|parts| parts.iter_mut().rev().find(|p| p.get("text").is_some() && p.get("thought").is_none()))
+            .and_then(|p| p.get_mut("text"))
+        {
+            if let Some(trimmed) = text.as_str().map(|t| t.trim_end().to_string()) {
+                *text = json!(trimmed);
+            }
         }
     }
 
+    // [FIX P3-4] thinking 关闭时残留的 'thought'/'thoughtSignature' 由最终的单次深度清理统一移除
+    // (见 final_deep_clean)；这里只处理仅校验最后一个思考块的模型
+    if is_thinking_enabled && crate::proxy::config::should_strip_historical_thinking(mapped_model) {
+        // [NEW] 部分模型只校验最后一个思考块，历史思考 (及其签名) 反而会触发上游报错
+        strip_historical_thinking(&mut merged_contents);
+    }
+
     Ok(json!(merged_contents))
 }
 
+/// 新旧结果体积差超过该值时，在保留的结果后附加说明
+const SUPERSEDED_RESULT_NOTE_THRESHOLD: usize = 256;
+
+/// 合并同一 id 的重复 functionResponse：保留后出现的 (通常更完整) 结果，
+/// 放在首次出现的位置 (紧跟对应的 functionCall)，并移除后续重复项
+fn dedupe_function_responses(contents: &mut Vec<Value>) {
+    // id -> (content 下标, part 下标)
+    let mut first_seen: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut superseded: Vec<((usize, usize), (usize, usize))> = Vec::new();
+
+    for (ci, content) in contents.iter().enumerate() {
+        let Some(parts) = content.get("parts").and_then(|p| p.as_array()) else { continue };
+        for (pi, part) in parts.iter().enumerate() {
+            let Some(id) = part
+                .get("functionResponse")
+                .and_then(|fr| fr.get("id"))
+                .and_then(|v| v.as_str())
+            else {
+                continue;
+            };
+            match first_seen.get(id) {
+                Some(&first) => superseded.push((first, (ci, pi))),
+                None => {
+                    first_seen.insert(id.to_string(), (ci, pi));
+                }
+            }
+        }
+    }
+
+    if superseded.is_empty() {
+        return;
+    }
+
+    let mut to_remove: Vec<(usize, usize)> = Vec::new();
+    for ((fc, fp), (lc, lp)) in superseded {
+        let mut later = contents[lc]["parts"][lp]["functionResponse"].clone();
+        let earlier = &contents[fc]["parts"][fp]["functionResponse"];
+        let id = later["id"].as_str().unwrap_or_default().to_string();
+
+        let earlier_len = earlier["response"].to_string().len();
+        let later_len = later["response"].to_string().len();
+        tracing::warn!(
+            "[Tool-Result-Dedup] Merged duplicated functionResponse for id {} ({} -> {} bytes), keeping the later result",
+            id,
+            earlier_len,
+            later_len
+        );
+        if earlier_len.abs_diff(later_len) > SUPERSEDED_RESULT_NOTE_THRESHOLD {
+            if let Some(result) = later["response"]["result"].as_str() {
+                later["response"]["result"] = json!(format!(
+                    "{}\n\n[Note: an earlier partial result for this tool call was superseded.]",
+                    result
+                ));
+            }
+        }
+
+        contents[fc]["parts"][fp]["functionResponse"] = later;
+        to_remove.push((lc, lp));
+    }
+
+    // 从后往前删除，保证下标有效
+    to_remove.sort_unstable();
+    to_remove.dedup();
+    for (ci, pi) in to_remove.into_iter().rev() {
+        if let Some(parts) = contents[ci].get_mut("parts").and_then(|p| p.as_array_mut()) {
+            parts.remove(pi);
+        }
+    }
+    // 只剩下重复结果的消息整体移除 (后续的角色合并会修复相邻同角色)
+    contents.retain(|c| {
+        c.get("parts")
+            .and_then(|p| p.as_array())
+            .map_or(true, |parts| !parts.is_empty())
+    });
+}
+
 /// Merge adjacent messages with the same role
-fn merge_adjacent_roles(mut contents: Vec<Value>) -> Vec<Value> {
+fn merge_adjacent_roles(mut contents: Vec<Value>, reorder_parts: bool) -> Vec<Value> {
     if contents.is_empty() {
         return contents;
     }
@@ -1641,7 +1693,9 @@ fn merge_adjacent_roles(mut contents: Vec<Value>) -> Vec<Value> {
                     // [FIX #709] Core Fix: After merging parts from adjacent messages,
                     // we must RE-SORT them to ensure any thinking blocks from the
                     // second message are moved to the very front of the combined array.
-                    reorder_gemini_parts(current_parts);
+                    if reorder_parts {
+                        reorder_gemini_parts(current_parts);
+                    }
                 }
             }
         } else {
@@ -1654,12 +1708,12 @@ fn merge_adjacent_roles(mut contents: Vec<Value>) -> Vec<Value> {
 }
 
 /// 构建 Tools
-fn build_tools(tools: &Option<Vec<Tool>>, has_web_search: bool) -> Result<Option<Value>, String> {
+fn build_tools(tools: &Option<Vec<Tool>>, has_web_search: bool) -> Result<Option<Value>, MapperError> {
     if let Some(tools_list) = tools {
         let mut function_declarations: Vec<Value> = Vec::new();
         let mut has_google_search = has_web_search;
 
-        for tool in tools_list {
+        for (idx, tool) in tools_list.iter().enumerate() {
             // 1. Detect server tools / built-in tools like web_search
             if tool.is_web_search() {
                 has_google_search = true;
@@ -1681,6 +1735,15 @@ fn build_tools(tools: &Option<Vec<Tool>>, has_web_search: bool) -> Result<Option
                 }
 
                 // 3. Client tools require input_schema
+                // [NEW] 畸形 schema (非对象) 属于客户端错误，直接返回 400 而不是让上游报错
+                if let Some(schema) = &tool.input_schema {
+                    if !schema.is_object() {
+                        return Err(MapperError::invalid_request(
+                            format!("tools[{}].input_schema", idx),
+                            format!("schema for tool '{}' must be a JSON object", name),
+                        ));
+                    }
+                }
                 let mut input_schema = tool.input_schema.clone().unwrap_or(json!({
                     "type": "object",
                     "properties": {}
@@ -1695,6 +1758,13 @@ fn build_tools(tools: &Option<Vec<Tool>>, has_web_search: bool) -> Result<Option
             }
         }
 
+        // [NEW] 工具数量上限 (可配置截断或报错)
+        crate::proxy::mappers::common_utils::apply_tool_limit(
+            &mut function_declarations,
+            &crate::proxy::config::get_tool_limit_config(),
+            "Claude-Request",
+        )?;
+
         let mut tool_obj = serde_json::Map::new();
 
         // [修复] 解决 "Multiple tools are supported only when they are all search tools" 400 错误
@@ -1734,6 +1804,7 @@ fn build_generation_config(
     mapped_model: &str,
     _has_web_search: bool,
     is_thinking_enabled: bool,
+    session_id: &str,
 ) -> Value {
     let mut config = json!({});
 
@@ -1743,58 +1814,37 @@ fn build_generation_config(
         let user_thinking_type = claude_req.thinking.as_ref().map(|t| t.type_.as_str());
         let user_is_adaptive = user_thinking_type == Some("adaptive");
 
+        let effort = claude_req.output_config.as_ref().and_then(|c| c.effort.as_ref())
+            .or_else(|| claude_req.thinking.as_ref().and_then(|t| t.effort.as_ref()));
+
+        let tb_config = crate::proxy::config::get_thinking_budget_config();
+
+        // [NEW] 未显式指定 budget_tokens 时，根据 effort 等级推导预算 (可配置)
         let budget_tokens = claude_req
             .thinking
             .as_ref()
             .and_then(|t| t.budget_tokens)
-            .unwrap_or(16000);
-
-        let tb_config = crate::proxy::config::get_thinking_budget_config();
-        let budget = match tb_config.mode {
-            crate::proxy::config::ThinkingBudgetMode::Passthrough => budget_tokens,
-            crate::proxy::config::ThinkingBudgetMode::Custom => {
-                let mut custom_value = tb_config.custom_value;
-                // [FIX #1602] 针对 Gemini 系列模型，在自定义模式下也强制执行 24576 上限
-                let model_lower = mapped_model.to_lowercase();
-                let is_gemini_limited = (model_lower.contains("gemini") && !model_lower.contains("-image"))
-                    || model_lower.contains("flash")
-                    || model_lower.ends_with("-thinking");
-
-                if is_gemini_limited && custom_value > 24576 {
-                    tracing::warn!(
-                        "[Claude-Request] Custom mode: capping thinking_budget from {} to 24576 for Gemini model {}",
-                        custom_value, mapped_model
-                    );
-                    custom_value = 24576;
+            .or_else(|| {
+                let derived = effort.and_then(|e| tb_config.effort_budgets.budget_for(e));
+                if let (Some(e), Some(b)) = (effort, derived) {
+                    tracing::debug!("[Claude-Request] Derived thinking budget {} from effort '{}'", b, e);
                 }
-                custom_value
-            }
-            crate::proxy::config::ThinkingBudgetMode::Auto => {
-                // [FIX #1592] Use mapped model for robust detection, same as OpenAI protocol
-                let model_lower = mapped_model.to_lowercase();
-                let is_gemini_limited = (model_lower.contains("gemini") && !model_lower.contains("-image"))
-                    || model_lower.contains("flash")
-                    || model_lower.ends_with("-thinking");
-                if is_gemini_limited && budget_tokens > 24576 {
-                    tracing::info!(
-                        "[Claude-Request] Auto mode: capping thinking_budget from {} to 24576 for Gemini model {}", 
-                        budget_tokens, mapped_model
-                    );
-                    24576
-                } else {
-                    budget_tokens
-                }
-            }
-            crate::proxy::config::ThinkingBudgetMode::Adaptive => budget_tokens, // Adaptive 模式透传原始预算（但不作为限制），用于后续逻辑判断
-        };
+                derived
+            })
+            .or(tb_config.default_thinking_budget)
+            .unwrap_or(16000);
+        let budget = thinking_budget::resolve_thinking_budget(
+            budget_tokens as i64,
+            mapped_model,
+            thinking_budget::is_claude_budget_capped_model(mapped_model),
+            &tb_config,
+            "Claude-Request",
+        );
 
         let global_mode_is_adaptive = matches!(tb_config.mode, crate::proxy::config::ThinkingBudgetMode::Adaptive);
         // 只要用户指定 adaptive 或者全局配置为 adaptive，且是 Claude 模型，就启用自适应
         let should_use_adaptive = (user_is_adaptive || global_mode_is_adaptive) && mapped_model.to_lowercase().contains("claude");
 
-        let effort = claude_req.output_config.as_ref().and_then(|c| c.effort.as_ref())
-            .or_else(|| claude_req.thinking.as_ref().and_then(|t| t.effort.as_ref()));
-
         if should_use_adaptive {
             // [FIX #1825] Claude 4.6+ adaptive 模式下映射为动态预算或分级思维
             let lower_mapped = mapped_model.to_lowercase();
@@ -1819,7 +1869,8 @@ fn build_generation_config(
                 config["maxOutputTokens"] = json!(131072);
             }
         } else {
-            thinking_config["thinkingBudget"] = json!(budget);
+            // [NEW] 防止 0 或过小的预算导致 Gemini 拒绝请求或不产生思考
+            thinking_config["thinkingBudget"] = json!(tb_config.apply_budget_floor(budget));
         }
         
         config["thinkingConfig"] = thinking_config;
@@ -1846,6 +1897,13 @@ fn build_generation_config(
         config["topK"] = json!(top_k);
     }
 
+    // [NEW] 会话级生成参数覆盖 (优先于单次请求参数)
+    let session_override = SessionGenerationOverrides::global().get(session_id);
+    if let Some(overrides) = &session_override {
+        overrides.apply_to(&mut config);
+        tracing::debug!("[Claude-Request] Applied session generation override for {}", session_id);
+    }
+
 
     // web_search 强制 candidateCount=1
     /*if has_web_search {
@@ -1854,7 +1912,11 @@ fn build_generation_config(
 
     // max_tokens 映射为 maxOutputTokens
     // [FIX] 不再默认设置 81920，防止非思维模型 (如 claude-sonnet-4-5) 报 400 Invalid Argument
-    let mut final_max_tokens: Option<i64> = claude_req.max_tokens.map(|t| t as i64);
+    let mut final_max_tokens: Option<i64> = session_override
+        .as_ref()
+        .and_then(|o| o.max_output_tokens)
+        .or(claude_req.max_tokens)
+        .map(|t| t as i64);
 
     // [NEW] 确保 maxOutputTokens 大于 thinkingBudget (API 强约束)
     // [NEW] 针对 Claude 4.6 adaptive 模式扩充默认上限到 128K
@@ -1907,9 +1969,17 @@ fn build_generation_config(
     //   2. 将其作为 stopSequence 会导致模型输出被意外截断 (如解释 SSE 协议时)
     //   3. Gemini 流的真正结束由 finishReason 字段控制,无需依赖 stopSequence
     //   4. SSE 层面的 "data: [DONE]" 已在 mod.rs 中单独处理
-    // [优化] 设置全局停止序列,防止模型幻觉出对话标记
-    // ...
-    config["stopSequences"] = json!(["<|user|>", "<|end_of_turn|>", "\n\nHuman:"]);
+    // [NEW] 保护性停止序列可配置 (默认 "<|user|>", "<|end_of_turn|>", "\n\nHuman:")
+    // 客户端 stop_sequences 与保护性序列合并发往上游 (客户端优先，受数量上限约束)；
+    // 流式管线仍在可见文本中检测客户端序列，上游未截断时也能回报 stop_reason "stop_sequence"。
+    let client_stops = claude_req.stop_sequences.as_deref().unwrap_or_default();
+    let stop_sequences = crate::proxy::mappers::common_utils::merge_stop_sequences(
+        client_stops,
+        &crate::proxy::config::get_protective_stop_sequences(),
+    );
+    if !stop_sequences.is_empty() {
+        config["stopSequences"] = json!(stop_sequences);
+    }
 
     config
 }
@@ -1917,20 +1987,59 @@ fn build_generation_config(
 /// Recursively remove 'thought' and 'thoughtSignature' fields
 /// Used when downgrading thinking (e.g. during 400 retry)
 pub fn clean_thinking_fields_recursive(val: &mut Value) {
-    match val {
-        Value::Object(map) => {
-            map.remove("thought");
-            map.remove("thoughtSignature");
-            for (_, v) in map.iter_mut() {
-                clean_thinking_fields_recursive(v);
-            }
+    deep_clean(val, 0, &[is_thinking_field], "clean_thinking_fields");
+}
+
+/// 剥离除最后一条 model 消息外所有 model 消息中的 'thought' / 'thoughtSignature'
+/// 用于只校验最后一个思考块的模型 (当前轮次的思考保持完整)
+pub fn strip_historical_thinking(contents: &mut [Value]) {
+    let last_model = contents.iter().rposition(|c| c["role"] == "model");
+    for (i, msg) in contents.iter_mut().enumerate() {
+        if msg["role"] == "model" && Some(i) != last_model {
+            clean_thinking_fields_recursive(msg);
         }
-        Value::Array(arr) => {
-            for v in arr.iter_mut() {
-                clean_thinking_fields_recursive(v);
+    }
+}
+
+/// Claude 签名宽松兼容的模型族 (按顺序匹配，更具体的标记在前)
+/// 同一族内的变体 (如 -thinking 后缀、日期版本) 可互相复用签名
+const CLAUDE_SIGNATURE_FAMILIES: &[(&str, &str)] = &[
+    ("claude-3-5", "claude-3-5"),
+    ("claude-3-7", "claude-3-7"),
+    ("opus-4-6", "claude-opus-4-6"),
+    ("opus-4-5", "claude-opus-4-5"),
+    ("sonnet-4-5", "claude-sonnet-4-5"),
+    ("claude-opus-4", "claude-4"),
+    ("claude-sonnet-4", "claude-4"),
+    ("claude-4", "claude-4"),
+];
+
+/// 获取 Claude 模型所属的签名族 (入参需已小写)
+fn claude_signature_family(model: &str) -> Option<&'static str> {
+    if !model.contains("claude") {
+        return None;
+    }
+    CLAUDE_SIGNATURE_FAMILIES
+        .iter()
+        .find(|(marker, _)| model.contains(marker))
+        .map(|(_, family)| *family)
+}
+
+/// [NEW] 迟到签名恢复与客户端回传签名使用同一兼容性规则:
+/// 已知来源模型时必须与目标模型兼容，来源未知时按长度判断
+fn recovered_signature_compatible(sig: &str, target: &str) -> bool {
+    match crate::proxy::SignatureCache::global().get_signature_family(sig) {
+        Some(family) => {
+            let compatible = is_model_compatible(&family, target);
+            if !compatible {
+                tracing::warn!(
+                    "[Thinking-Signature] Recovered late signature is incompatible (Family: {}, Target: {}), not reusing it.",
+                    family, target
+                );
             }
+            compatible
         }
-        _ => {}
+        None => sig.len() >= MIN_SIGNATURE_LENGTH,
     }
 }
 
@@ -1955,11 +2064,10 @@ fn is_model_compatible(cached: &str, target: &str) -> bool {
     // Exact model string match (already handled by c == t)
 
     // Grouped family match (Claude models are more permissive)
-    if c.contains("claude-3-5") && t.contains("claude-3-5") {
-        return true;
-    }
-    if c.contains("claude-3-7") && t.contains("claude-3-7") {
-        return true;
+    if let (Some(cf), Some(tf)) = (claude_signature_family(&c), claude_signature_family(&t)) {
+        if cf == tf {
+            return true;
+        }
     }
 
     // Gemini models: strict family match required for signatures
@@ -1986,6 +2094,21 @@ mod tests {
     use crate::proxy::common::json_schema::clean_json_schema;
     use crate::proxy::config::{ThinkingBudgetConfig, update_thinking_budget_config};
 
+    #[test]
+    fn test_claude_4_signature_families_are_permissive() {
+        assert!(is_model_compatible("claude-opus-4-5", "claude-opus-4-5"));
+        assert!(is_model_compatible("claude-opus-4-5", "claude-opus-4-5-thinking"));
+        assert!(is_model_compatible("claude-opus-4-6-thinking", "claude-opus-4-6"));
+        assert!(is_model_compatible("claude-sonnet-4-5-20250929", "claude-sonnet-4-5-thinking"));
+        assert!(is_model_compatible("claude-sonnet-4", "claude-opus-4-1"));
+
+        // 不同族之间仍然严格
+        assert!(!is_model_compatible("claude-opus-4-5", "claude-opus-4-6"));
+        assert!(!is_model_compatible("claude-opus-4-5", "claude-sonnet-4-5"));
+        assert!(!is_model_compatible("claude-opus-4-5", "gemini-3-pro"));
+        assert!(!is_model_compatible("claude-3-7-sonnet", "claude-sonnet-4"));
+    }
+
     #[test]
     fn test_ephemeral_injection_debug() {
         // This test simulates the issue where cache_control might be injected
@@ -2044,9 +2167,11 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
-        let result = transform_claude_request_in(&req, "test-project", false);
+        let result = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default());
         assert!(result.is_ok());
 
         let body = result.unwrap();
@@ -2098,9 +2223,8 @@ mod tests {
         assert_eq!(schema["properties"]["date"]["type"], "string");
     }
 
-    #[test]
-    fn test_complex_tool_result() {
-        let req = ClaudeRequest {
+    fn build_tool_use_request(input: Value) -> ClaudeRequest {
+        ClaudeRequest {
             model: "claude-3-5-sonnet-20241022".to_string(),
             messages: vec![
                 Message {
@@ -2112,22 +2236,11 @@ mod tests {
                     content: MessageContent::Array(vec![ContentBlock::ToolUse {
                         id: "call_1".to_string(),
                         name: "run_command".to_string(),
-                        input: json!({"command": "ls"}),
+                        input,
                         signature: None,
                         cache_control: None,
                     }]),
                 },
-                Message {
-                    role: "user".to_string(),
-                    content: MessageContent::Array(vec![ContentBlock::ToolResult {
-                        tool_use_id: "call_1".to_string(),
-                        content: json!([
-                            {"type": "text", "text": "file1.txt\n"},
-                            {"type": "text", "text": "file2.txt"}
-                        ]),
-                        is_error: Some(false),
-                    }]),
-                },
             ],
             system: None,
             tools: None,
@@ -2141,61 +2254,72 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
-        };
-
-        let result = transform_claude_request_in(&req, "test-project", false);
-        assert!(result.is_ok());
+            stop_sequences: None,
+            mcp_servers: None,
+        }
+    }
 
-        let body = result.unwrap();
-        let contents = body["request"]["contents"].as_array().unwrap();
+    fn function_call_args(input: Value) -> Value {
+        let req = build_tool_use_request(input);
+        let body = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        body["request"]["contents"][1]["parts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find_map(|p| p.get("functionCall"))
+            .expect("functionCall part")["args"]
+            .clone()
+    }
 
-        // Check the tool result message (last message)
-        let tool_resp_msg = &contents[2];
-        let parts = tool_resp_msg["parts"].as_array().unwrap();
-        let func_resp = &parts[0]["functionResponse"];
+    #[test]
+    fn test_string_tool_input_coerced_to_object() {
+        let args = function_call_args(json!("ls -la"));
+        assert!(args.is_object());
+        assert_eq!(args["input"], "ls -la");
+
+        // 字符串形式的 JSON 对象直接解析修复
+        let args = function_call_args(json!("{\"command\": \"ls\"}"));
+        assert_eq!(args, json!({"command": "ls"}));
+    }
 
-        assert_eq!(func_resp["name"], "run_command");
-        assert_eq!(func_resp["id"], "call_1");
+    #[test]
+    fn test_array_tool_input_coerced_to_object() {
+        let args = function_call_args(json!(["ls", "-la"]));
+        assert!(args.is_object());
+        assert_eq!(args["input"], json!(["ls", "-la"]));
 
-        // Verify merged content
-        let resp_text = func_resp["response"]["result"].as_str().unwrap();
-        assert!(resp_text.contains("file1.txt"));
-        assert!(resp_text.contains("file2.txt"));
-        assert!(resp_text.contains("\n"));
+        let args = function_call_args(Value::Null);
+        assert_eq!(args, json!({}));
     }
 
     #[test]
-    fn test_cache_control_cleanup() {
-        // 模拟 VS Code 插件发送的包含 cache_control 的历史消息
+    fn test_complex_tool_result() {
         let req = ClaudeRequest {
-            model: "claude-sonnet-4-5".to_string(),
+            model: "claude-3-5-sonnet-20241022".to_string(),
             messages: vec![
                 Message {
                     role: "user".to_string(),
-                    content: MessageContent::String("Hello".to_string()),
+                    content: MessageContent::String("Run command".to_string()),
                 },
                 Message {
                     role: "assistant".to_string(),
-                    content: MessageContent::Array(vec![
-                        ContentBlock::Thinking {
-                            thinking: "Let me think...".to_string(),
-                            signature: Some("sig123".to_string()),
-                            cache_control: Some(json!({"type": "ephemeral"})), // 这个应该被清理
-                        },
-                        ContentBlock::Text {
-                            text: "Here is my response".to_string(),
-                        },
-                    ]),
+                    content: MessageContent::Array(vec![ContentBlock::ToolUse {
+                        id: "call_1".to_string(),
+                        name: "run_command".to_string(),
+                        input: json!({"command": "ls"}),
+                        signature: None,
+                        cache_control: None,
+                    }]),
                 },
                 Message {
                     role: "user".to_string(),
-                    content: MessageContent::Array(vec![ContentBlock::Image {
-                        source: ImageSource {
-                            source_type: "base64".to_string(),
-                            media_type: "image/png".to_string(),
-                            data: "iVBORw0KGgo=".to_string(),
-                        },
-                        cache_control: Some(json!({"type": "ephemeral"})), // 这个也应该被清理
+                    content: MessageContent::Array(vec![ContentBlock::ToolResult {
+                        tool_use_id: "call_1".to_string(),
+                        content: json!([
+                            {"type": "text", "text": "file1.txt\n"},
+                            {"type": "text", "text": "file2.txt"}
+                        ]),
+                        is_error: Some(false),
                     }]),
                 },
             ],
@@ -2211,19 +2335,235 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
-        let result = transform_claude_request_in(&req, "test-project", false);
+        let result = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default());
         assert!(result.is_ok());
 
-        // 验证请求成功转换
         let body = result.unwrap();
-        assert_eq!(body["project"], "test-project");
+        let contents = body["request"]["contents"].as_array().unwrap();
 
-        // 注意: cache_control 的清理发生在内部,我们无法直接从 JSON 输出验证
-        // 但如果没有清理,后续发送到 Anthropic API 时会报错
-        // 这个测试主要确保清理逻辑不会导致转换失败
-    }
+        // Check the tool result message (last message)
+        let tool_resp_msg = &contents[2];
+        let parts = tool_resp_msg["parts"].as_array().unwrap();
+        let func_resp = &parts[0]["functionResponse"];
+
+        assert_eq!(func_resp["name"], "run_command");
+        assert_eq!(func_resp["id"], "call_1");
+
+        // Verify merged content
+        let resp_text = func_resp["response"]["result"].as_str().unwrap();
+        assert!(resp_text.contains("file1.txt"));
+        assert!(resp_text.contains("file2.txt"));
+        assert!(resp_text.contains("\n"));
+    }
+
+    #[test]
+    fn test_task_echo_dedup_respects_min_length() {
+        let echo_request = |task: &str| {
+            let mut req = build_tool_use_request(json!({"command": "ls"}));
+            req.messages[0].content = MessageContent::Array(vec![ContentBlock::Text {
+                text: task.to_string(),
+            }]);
+            req.messages.push(Message {
+                role: "user".to_string(),
+                content: MessageContent::Array(vec![
+                    ContentBlock::ToolResult {
+                        tool_use_id: "call_1".to_string(),
+                        content: json!("done"),
+                        is_error: Some(false),
+                    },
+                    ContentBlock::Text {
+                        text: task.to_string(),
+                    },
+                ]),
+            });
+            let body = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+            let contents = body["request"]["contents"].as_array().unwrap().clone();
+            contents[2]["parts"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter_map(|p| p["text"].as_str().map(str::to_string))
+                .collect::<Vec<_>>()
+        };
+
+        // Claude Code 在工具结果后回显的完整任务描述被丢弃
+        let long_task = "Refactor the parser module so it accepts streaming input";
+        assert!(echo_request(long_task).is_empty());
+
+        // 用户有意重复的短指令保留
+        assert_eq!(echo_request("continue"), vec!["continue".to_string()]);
+
+        let config = crate::proxy::config::TaskEchoDedupConfig::default();
+        let normalized = long_task.replace(' ', "");
+        assert!(config.is_echo(&normalized, &normalized));
+        let disabled = crate::proxy::config::TaskEchoDedupConfig {
+            enabled: false,
+            ..config
+        };
+        assert!(!disabled.is_echo(&normalized, &normalized));
+    }
+
+    #[test]
+    fn test_duplicated_tool_result_keeps_later_result() {
+        let tool_result = |text: &str| ContentBlock::ToolResult {
+            tool_use_id: "call_1".to_string(),
+            content: json!(text),
+            is_error: Some(false),
+        };
+        let full_output = "x".repeat(1000);
+        let mut req = build_tool_use_request(json!({"command": "ls"}));
+        req.messages.extend([
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::Array(vec![tool_result("partial")]),
+            },
+            Message {
+                role: "assistant".to_string(),
+                content: MessageContent::Array(vec![ContentBlock::Text {
+                    text: "Reconnecting...".to_string(),
+                }]),
+            },
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::Array(vec![
+                    tool_result(&full_output),
+                    ContentBlock::Text {
+                        text: "continue".to_string(),
+                    },
+                ]),
+            },
+        ]);
+
+        let body = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        let contents = body["request"]["contents"].as_array().unwrap();
+
+        let responses: Vec<&Value> = contents
+            .iter()
+            .flat_map(|c| c["parts"].as_array().unwrap())
+            .filter_map(|p| p.get("functionResponse"))
+            .collect();
+        assert_eq!(responses.len(), 1);
+
+        // 保留后出现的完整结果，位置紧跟 functionCall，并附加被替换说明
+        let result = contents[2]["parts"][0]["functionResponse"]["response"]["result"]
+            .as_str()
+            .unwrap();
+        assert!(result.starts_with(&full_output));
+        assert!(result.contains("earlier partial result"));
+        assert!(contents[4]["parts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|p| p["text"] == "continue"));
+    }
+
+    #[test]
+    fn test_duplicate_tool_result_ids_across_merged_user_turns() {
+        let tool_result = |text: &str| ContentBlock::ToolResult {
+            tool_use_id: "call_1".to_string(),
+            content: json!(text),
+            is_error: Some(false),
+        };
+        // 客户端重发结果: 两个相邻的 user 消息携带同一 id 的 tool_result，角色合并后落入同一轮
+        let mut req = build_tool_use_request(json!({"command": "ls"}));
+        req.messages.extend([
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::Array(vec![tool_result("stale listing")]),
+            },
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::Array(vec![
+                    tool_result("fresh listing"),
+                    ContentBlock::Text {
+                        text: "go on".to_string(),
+                    },
+                ]),
+            },
+        ]);
+
+        let body = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        let contents = body["request"]["contents"].as_array().unwrap();
+        let last = contents.last().unwrap();
+        assert_eq!(last["role"], "user");
+
+        let responses: Vec<&Value> = contents
+            .iter()
+            .flat_map(|c| c["parts"].as_array().unwrap())
+            .filter_map(|p| p.get("functionResponse"))
+            .collect();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0]["id"], "call_1");
+        assert_eq!(responses[0]["response"]["result"], "fresh listing");
+        assert!(last["parts"].as_array().unwrap().iter().any(|p| p["text"] == "go on"));
+    }
+
+    #[test]
+    fn test_cache_control_cleanup() {
+        // 模拟 VS Code 插件发送的包含 cache_control 的历史消息
+        let req = ClaudeRequest {
+            model: "claude-sonnet-4-5".to_string(),
+            messages: vec![
+                Message {
+                    role: "user".to_string(),
+                    content: MessageContent::String("Hello".to_string()),
+                },
+                Message {
+                    role: "assistant".to_string(),
+                    content: MessageContent::Array(vec![
+                        ContentBlock::Thinking {
+                            thinking: "Let me think...".to_string(),
+                            signature: Some("sig123".to_string()),
+                            cache_control: Some(json!({"type": "ephemeral"})), // 这个应该被清理
+                        },
+                        ContentBlock::Text {
+                            text: "Here is my response".to_string(),
+                        },
+                    ]),
+                },
+                Message {
+                    role: "user".to_string(),
+                    content: MessageContent::Array(vec![ContentBlock::Image {
+                        source: ImageSource {
+                            source_type: "base64".to_string(),
+                            media_type: "image/png".to_string(),
+                            data: "iVBORw0KGgo=".to_string(),
+                        },
+                        cache_control: Some(json!({"type": "ephemeral"})), // 这个也应该被清理
+                    }]),
+                },
+            ],
+            system: None,
+            tools: None,
+            stream: false,
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            thinking: None,
+            metadata: None,
+            output_config: None,
+            size: None,
+            quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
+        };
+
+        let result = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default());
+        assert!(result.is_ok());
+
+        // 验证请求成功转换
+        let body = result.unwrap();
+        assert_eq!(body["project"], "test-project");
+
+        // 注意: cache_control 的清理发生在内部,我们无法直接从 JSON 输出验证
+        // 但如果没有清理,后续发送到 Anthropic API 时会报错
+        // 这个测试主要确保清理逻辑不会导致转换失败
+    }
 
     #[test]
     fn test_thinking_mode_auto_disable_on_tool_use_history() {
@@ -2285,9 +2625,11 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
-        let result = transform_claude_request_in(&req, "test-project", false);
+        let result = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default());
         assert!(result.is_ok());
 
         let body = result.unwrap();
@@ -2335,9 +2677,11 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
-        let result = transform_claude_request_in(&req, "test-project", false);
+        let result = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default());
         assert!(result.is_ok());
 
         let body = result.unwrap();
@@ -2391,9 +2735,11 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
-        let result = transform_claude_request_in(&req, "test-project", false);
+        let result = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default());
         assert!(result.is_ok(), "Transformation failed");
         let body = result.unwrap();
         let contents = body["request"]["contents"].as_array().unwrap();
@@ -2439,9 +2785,11 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
-        let result = transform_claude_request_in(&req, "test-project", false);
+        let result = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default());
         assert!(result.is_ok());
         let body = result.unwrap();
         let parts = body["request"]["contents"][0]["parts"].as_array().unwrap();
@@ -2509,6 +2857,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_merge_adjacent_roles_reorder_is_flag_gated() {
+        let contents = vec![
+            json!({ "role": "model", "parts": [{ "text": "answer" }] }),
+            json!({ "role": "model", "parts": [
+                { "text": "late thought", "thought": true },
+                { "functionCall": { "name": "ls", "args": {} } }
+            ]}),
+        ];
+
+        // 开关开启: 合并后按 [Thinking, Text, Tool] 重排
+        let merged = merge_adjacent_roles(contents.clone(), true);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0]["parts"][0]["thought"], true);
+        assert_eq!(merged[0]["parts"][1]["text"], "answer");
+
+        // 开关关闭: 只合并，保持原有顺序
+        let merged = merge_adjacent_roles(contents, false);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0]["parts"][0]["text"], "answer");
+        assert_eq!(merged[0]["parts"][1]["thought"], true);
+        assert!(merged[0]["parts"][2]["functionCall"].is_object());
+    }
+
     #[test]
     fn test_thinking_blocks_no_reorder_when_already_first() {
         // Correct order: Thinking already first - should not trigger reorder
@@ -2630,9 +3002,11 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
-        let result = transform_claude_request_in(&req, "test-v", false).unwrap();
+        let result = transform_claude_request_in(&req, "test-v", false, &EnvelopeParams::default(), &Default::default()).unwrap();
         // [FIX] Since we removed the default 81920, maxOutputTokens should NOT be present
         // when max_tokens is None and thinking is disabled
         let gen_config = &result["request"]["generationConfig"];
@@ -2667,10 +3041,12 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
         // Should cap at 24576
-        let result = transform_claude_request_in(&req, "proj", false).unwrap();
+        let result = transform_claude_request_in(&req, "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
 
         let gen_config = &result["request"]["generationConfig"]; // Corrected path
         let budget = gen_config["thinkingConfig"]["thinkingBudget"]
@@ -2698,10 +3074,12 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
         // Should cap
-        let result_pro = transform_claude_request_in(&req_pro, "proj", false).unwrap();
+        let result_pro = transform_claude_request_in(&req_pro, "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
         let budget_pro = result_pro["request"]["generationConfig"]["thinkingConfig"]
             ["thinkingBudget"]
             .as_u64()
@@ -2735,10 +3113,12 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
         // Transform
-        let result = transform_claude_request_in(&req, "proj", false).unwrap();
+        let result = transform_claude_request_in(&req, "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
         let gen_config = &result["request"]["generationConfig"];
 
         // thinkingConfig should be present (not forced disabled)
@@ -2754,6 +3134,60 @@ mod tests {
         assert_eq!(budget, 16000);
     }
 
+    #[test]
+    fn test_effort_derives_thinking_budget() {
+        // 重置为 Auto 模式，避免其他测试修改的全局配置干扰
+        update_thinking_budget_config(ThinkingBudgetConfig::default());
+
+        let budget_for = |effort: &str, budget_tokens: Option<u32>| -> u64 {
+            let req = ClaudeRequest {
+                model: "gemini-3-pro-preview".to_string(),
+                messages: vec![Message {
+                    role: "user".to_string(),
+                    content: MessageContent::String("Hello".to_string()),
+                }],
+                thinking: Some(ThinkingConfig {
+                    type_: "enabled".to_string(),
+                    budget_tokens,
+                    effort: None,
+                }),
+                max_tokens: None,
+                temperature: None,
+                top_p: None,
+                top_k: None,
+                stream: false,
+                system: None,
+                tools: None,
+                metadata: None,
+                output_config: Some(OutputConfig {
+                    effort: Some(effort.to_string()),
+                }),
+                size: None,
+                quality: None,
+                stop_sequences: None,
+                mcp_servers: None,
+            };
+            let result = transform_claude_request_in(&req, "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+            result["request"]["generationConfig"]["thinkingConfig"]["thinkingBudget"]
+                .as_u64()
+                .unwrap()
+        };
+
+        let low = budget_for("low", None);
+        let medium = budget_for("medium", None);
+        let high = budget_for("high", None);
+
+        // 预算随 effort 递增，且不超过 Gemini 上限
+        assert!(low < medium && medium < high, "budgets: {} / {} / {}", low, medium, high);
+        assert!(high <= 24576);
+        assert_eq!(low, 4096);
+        assert_eq!(medium, 12288);
+        assert_eq!(high, 24576);
+
+        // 显式 budget_tokens 优先于 effort 推导
+        assert_eq!(budget_for("high", Some(2048)), 2048);
+    }
+
     #[test]
     fn test_gemini_pro_default_thinking() {
         // Setup request for Gemini Pro WITHOUT thinking config
@@ -2775,10 +3209,12 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
         // Transform
-        let result = transform_claude_request_in(&req, "proj", false).unwrap();
+        let result = transform_claude_request_in(&req, "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
         let gen_config = &result["request"]["generationConfig"];
 
         // thinkingConfig SHOULD be injected because of default-on logic
@@ -2812,10 +3248,12 @@ mod tests {
             output_config: None,
             size: Some("1024x1024".to_string()),
             quality: Some("hd".to_string()),
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
         // 3. Transform request
-        let result = transform_claude_request_in(&req, "test-proj", false).unwrap();
+        let result = transform_claude_request_in(&req, "test-proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
 
         // 4. Verify thinkingConfig has includeThoughts: false
         let gen_config = result["request"]["generationConfig"].as_object().expect("Should have generationConfig");
@@ -2827,6 +3265,296 @@ mod tests {
         crate::proxy::config::update_image_thinking_mode(Some("enabled".to_string()));
     }
 
+    #[test]
+    fn test_strict_role_alternation_off_keeps_consecutive_roles() {
+        let messages = vec![
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::String("First".to_string()),
+            },
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::String("Second".to_string()),
+            },
+            Message {
+                role: "assistant".to_string(),
+                content: MessageContent::String("Reply".to_string()),
+            },
+        ];
+        let req = ClaudeRequest {
+            model: "gemini-2.5-flash".to_string(),
+            messages: messages.clone(),
+            thinking: None,
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stream: false,
+            system: None,
+            tools: None,
+            metadata: None,
+            output_config: None,
+            size: None,
+            quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
+        };
+
+        let build = |strict: bool| {
+            let mut tool_id_to_name = HashMap::new();
+            build_google_contents(
+                &messages,
+                &req,
+                &mut tool_id_to_name,
+                &HashMap::new(),
+                false,
+                None,
+                true,
+                "gemini-2.5-flash",
+                "test-session",
+                false,
+                strict,
+            )
+            .unwrap()
+        };
+
+        // 关闭时保留原始的连续 user 消息
+        let unmerged = build(false);
+        let unmerged = unmerged.as_array().unwrap();
+        assert_eq!(unmerged.len(), 3);
+        assert_eq!(unmerged[0]["role"], "user");
+        assert_eq!(unmerged[1]["role"], "user");
+        assert_eq!(unmerged[0]["parts"][0]["text"], "First");
+        assert_eq!(unmerged[1]["parts"][0]["text"], "Second");
+
+        // 默认开启时合并为一条
+        let merged = build(true);
+        let merged = merged.as_array().unwrap();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0]["parts"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_dummy_thought_injection_on_and_off() {
+        let messages = vec![
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::String("Hi".to_string()),
+            },
+            Message {
+                role: "assistant".to_string(),
+                content: MessageContent::String("Hello".to_string()),
+            },
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::String("Continue".to_string()),
+            },
+        ];
+        let req = ClaudeRequest {
+            model: "gemini-2.5-flash".to_string(),
+            messages: messages.clone(),
+            thinking: None,
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stream: false,
+            system: None,
+            tools: None,
+            metadata: None,
+            output_config: None,
+            size: None,
+            quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
+        };
+
+        let build = |dummy_thought_text: Option<&str>| {
+            let mut tool_id_to_name = HashMap::new();
+            build_google_contents(
+                &messages,
+                &req,
+                &mut tool_id_to_name,
+                &HashMap::new(),
+                true,
+                dummy_thought_text,
+                true,
+                "gemini-2.5-flash",
+                "test-session-dummy-thought",
+                false,
+                true,
+            )
+            .unwrap()
+        };
+
+        // 开启时历史 assistant 消息首位补入配置的占位思考块
+        let injected = build(Some("Reasoning..."));
+        let parts = injected[1]["parts"].as_array().unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0], json!({ "text": "Reasoning...", "thought": true }));
+        assert_eq!(parts[1]["text"], "Hello");
+
+        // 关闭时 (默认，Vertex AI 安全) 不注入
+        let plain = build(None);
+        let parts = plain[1]["parts"].as_array().unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0]["text"], "Hello");
+
+        // 占位文本配置与空文本回退
+        let mut cfg = crate::proxy::config::DummyThoughtConfig::default();
+        assert_eq!(cfg.injection_text(), "Thinking...");
+        cfg.text = " Reasoning... ".to_string();
+        assert_eq!(cfg.injection_text(), "Reasoning...");
+        cfg.text = "  ".to_string();
+        assert_eq!(cfg.injection_text(), "Thinking...");
+    }
+
+    #[test]
+    fn test_prefill_kept_as_final_model_turn_without_dummy_thought() {
+        let messages = vec![
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::String("Hi".to_string()),
+            },
+            Message {
+                role: "assistant".to_string(),
+                content: MessageContent::String("Hello".to_string()),
+            },
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::String("List three colors as JSON".to_string()),
+            },
+            Message {
+                role: "assistant".to_string(),
+                content: MessageContent::String("{\"colors\": [ ".to_string()),
+            },
+        ];
+        let req = ClaudeRequest {
+            model: "gemini-2.5-flash".to_string(),
+            messages: messages.clone(),
+            thinking: None,
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stream: false,
+            system: None,
+            tools: None,
+            metadata: None,
+            output_config: None,
+            size: None,
+            quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
+        };
+
+        let mut tool_id_to_name = HashMap::new();
+        let contents = build_google_contents(
+            &messages,
+            &req,
+            &mut tool_id_to_name,
+            &HashMap::new(),
+            true,
+            Some("Reasoning..."),
+            true,
+            "gemini-2.5-flash",
+            "test-session-prefill",
+            false,
+            true,
+        )
+        .unwrap();
+        let contents = contents.as_array().unwrap();
+
+        assert_eq!(contents.len(), 4);
+        // 历史 assistant 消息照常注入占位思考块
+        assert_eq!(contents[1]["parts"][0]["thought"], true);
+        // 预填充保留为最后一个 model 轮次，不注入思考块，末尾空白被去掉
+        let last = &contents[3];
+        assert_eq!(last["role"], "model");
+        assert_eq!(last["parts"], json!([{ "text": "{\"colors\": [" }]));
+    }
+
+    #[test]
+    fn test_image_multimodal_output_sets_response_modalities() {
+        let req = ClaudeRequest {
+            model: "gemini-3-pro-image".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: MessageContent::String("Draw a cat and describe it".to_string()),
+            }],
+            thinking: None,
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stream: false,
+            system: None,
+            tools: None,
+            metadata: None,
+            output_config: None,
+            size: Some("1024x1024".to_string()),
+            quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
+        };
+
+        // 默认不设置 responseModalities
+        crate::proxy::config::update_image_multimodal_output(false);
+        let result = transform_claude_request_in(&req, "test-proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert!(result["request"]["generationConfig"].get("responseModalities").is_none());
+
+        // 开启后请求文本 + 图片
+        crate::proxy::config::update_image_multimodal_output(true);
+        let result = transform_claude_request_in(&req, "test-proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        crate::proxy::config::update_image_multimodal_output(false);
+        assert_eq!(
+            result["request"]["generationConfig"]["responseModalities"],
+            json!(["TEXT", "IMAGE"])
+        );
+        assert!(result["request"]["generationConfig"].get("imageConfig").is_some());
+    }
+
+    #[test]
+    fn test_google_search_injection_suppressed_by_request_flag() {
+        let req = ClaudeRequest {
+            model: "gemini-2.5-flash-online".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: MessageContent::String("What changed in the latest release?".to_string()),
+            }],
+            thinking: None,
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stream: false,
+            system: None,
+            tools: None,
+            metadata: None,
+            output_config: None,
+            size: None,
+            quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
+        };
+        let has_search = |body: &Value| {
+            body["request"]["tools"]
+                .as_array()
+                .map_or(false, |tools| tools.iter().any(|t| t.get("googleSearch").is_some()))
+        };
+
+        let body = transform_claude_request_in(&req, "test-proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert!(has_search(&body));
+
+        // 请求级开关覆盖 -online 触发的自动注入
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("x-abv-disable-google-search", "true".parse().unwrap());
+        let request_ctx = crate::proxy::handlers::common::request_context(&headers);
+        let body = transform_claude_request_in(&req, "test-proj", false, &EnvelopeParams::default(), &request_ctx).unwrap();
+        assert!(!has_search(&body));
+        assert_eq!(body["requestType"], "agent");
+    }
+
     #[test]
     fn test_claude_adaptive_global_config() {
         // Set global config to Adaptive + High effort
@@ -2834,6 +3562,9 @@ mod tests {
             mode: crate::proxy::config::ThinkingBudgetMode::Adaptive,
             custom_value: 0,
             effort: Some("high".to_string()),
+            effort_budgets: Default::default(),
+            default_thinking_budget: None,
+            min_thinking_budget: 256,
         };
         crate::proxy::config::update_thinking_budget_config(config);
 
@@ -2856,10 +3587,12 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
         // Transform
-        let result = transform_claude_request_in(&req, "test-proj", false).unwrap();
+        let result = transform_claude_request_in(&req, "test-proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
         
         let gen_config = result["request"]["generationConfig"].as_object().unwrap();
         let thinking_config = gen_config["thinkingConfig"].as_object().unwrap();
@@ -2877,4 +3610,545 @@ mod tests {
         // Reset global config
         crate::proxy::config::update_thinking_budget_config(ThinkingBudgetConfig::default());
     }
+
+    #[test]
+    fn test_configured_default_thinking_budget_used_when_omitted() {
+        crate::proxy::config::update_thinking_budget_config(ThinkingBudgetConfig {
+            mode: crate::proxy::config::ThinkingBudgetMode::Passthrough,
+            default_thinking_budget: Some(12000),
+            ..Default::default()
+        });
+
+        let req = ClaudeRequest {
+            model: "claude-sonnet-4-5".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: MessageContent::String("test".to_string()),
+            }],
+            thinking: Some(ThinkingConfig {
+                type_: "enabled".to_string(),
+                budget_tokens: None, // 客户端未指定预算
+                effort: None,
+            }),
+            stream: false,
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            system: None,
+            tools: None,
+            metadata: None,
+            output_config: None,
+            size: None,
+            quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
+        };
+
+        let result = transform_claude_request_in(&req, "test-proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert_eq!(
+            result["request"]["generationConfig"]["thinkingConfig"]["thinkingBudget"],
+            12000
+        );
+
+        // 未配置时保持 Claude 协议原有默认值
+        crate::proxy::config::update_thinking_budget_config(ThinkingBudgetConfig {
+            mode: crate::proxy::config::ThinkingBudgetMode::Passthrough,
+            ..Default::default()
+        });
+        let result = transform_claude_request_in(&req, "test-proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert_eq!(
+            result["request"]["generationConfig"]["thinkingConfig"]["thinkingBudget"],
+            16000
+        );
+
+        crate::proxy::config::update_thinking_budget_config(ThinkingBudgetConfig::default());
+    }
+
+    #[test]
+    fn test_zero_thinking_budget_raised_to_floor() {
+        crate::proxy::config::update_thinking_budget_config(ThinkingBudgetConfig {
+            mode: crate::proxy::config::ThinkingBudgetMode::Passthrough,
+            min_thinking_budget: 256,
+            ..Default::default()
+        });
+
+        let req = ClaudeRequest {
+            model: "claude-sonnet-4-5".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: MessageContent::String("test".to_string()),
+            }],
+            thinking: Some(ThinkingConfig {
+                type_: "enabled".to_string(),
+                budget_tokens: Some(0),
+                effort: None,
+            }),
+            stream: false,
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            system: None,
+            tools: None,
+            metadata: None,
+            output_config: None,
+            size: None,
+            quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
+        };
+
+        let result = transform_claude_request_in(&req, "test-proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert_eq!(
+            result["request"]["generationConfig"]["thinkingConfig"]["thinkingBudget"],
+            256
+        );
+
+        crate::proxy::config::update_thinking_budget_config(ThinkingBudgetConfig::default());
+    }
+
+    #[test]
+    fn test_envelope_overrides_land_in_body() {
+        let req = ClaudeRequest {
+            model: "claude-sonnet-4-5".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: MessageContent::String("Hello".to_string()),
+            }],
+            system: None,
+            tools: None,
+            stream: false,
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            thinking: None,
+            metadata: None,
+            output_config: None,
+            size: None,
+            quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
+        };
+
+        // Defaults unchanged
+        let body = transform_claude_request_in(&req, "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert_eq!(body["userAgent"], "antigravity");
+        assert_eq!(body["requestType"], "agent");
+
+        // Overrides applied
+        let envelope = EnvelopeParams::from_overrides(Some("jetski"), Some("chat"));
+        let body = transform_claude_request_in(&req, "proj", false, &envelope, &Default::default()).unwrap();
+        assert_eq!(body["userAgent"], "jetski");
+        assert_eq!(body["requestType"], "chat");
+    }
+
+    #[test]
+    fn test_malformed_tool_schema_is_invalid_request() {
+        let req = ClaudeRequest {
+            model: "claude-sonnet-4-5".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: MessageContent::String("Hello".to_string()),
+            }],
+            system: None,
+            tools: Some(vec![Tool {
+                type_: None,
+                name: Some("read_file".to_string()),
+                description: Some("Read a file".to_string()),
+                input_schema: Some(json!(["not", "an", "object"])),
+            }]),
+            stream: false,
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            thinking: None,
+            metadata: None,
+            output_config: None,
+            size: None,
+            quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
+        };
+
+        let err = transform_claude_request_in(&req, "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap_err();
+        match &err {
+            MapperError::InvalidRequest { field, .. } => assert_eq!(field, "tools[0].input_schema"),
+            other => panic!("Expected InvalidRequest, got {:?}", other),
+        }
+        assert!(!err.is_retryable());
+        assert_eq!(err.to_anthropic_body()["error"]["type"], "invalid_request_error");
+    }
+
+    #[test]
+    fn test_system_instruction_matches_shared_builder() {
+        let req: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "system": [
+                { "type": "text", "text": "You are a helpful CLI." },
+                { "type": "text", "text": "Be brief." }
+            ],
+            "messages": [{ "role": "user", "content": "Hello" }],
+            "tools": [{
+                "name": "mcp__fs_list",
+                "description": "List files",
+                "input_schema": { "type": "object", "properties": {} }
+            }]
+        }))
+        .unwrap();
+
+        let body = transform_claude_request_in(&req, "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        let expected = system_builder::build_system_parts(
+            &["You are a helpful CLI.", "Be brief."],
+            true,
+            &IdentityConfig::current(),
+        );
+        assert_eq!(body["request"]["systemInstruction"]["parts"], json!(expected));
+    }
+
+    #[tokio::test]
+    async fn test_response_language_injected_except_for_image_models() {
+        use crate::proxy::query_overrides::{self, QueryOverrides, ResponseLanguageOverride};
+
+        let request = |model: &str| -> ClaudeRequest {
+            serde_json::from_value(json!({
+                "model": model,
+                "system": "Answer in English.",
+                "messages": [{ "role": "user", "content": "Draw a cat" }]
+            }))
+            .unwrap()
+        };
+        let overrides = std::sync::Arc::new(QueryOverrides {
+            response_language: Some(ResponseLanguageOverride::Language("ja".to_string())),
+            ..Default::default()
+        });
+        let (text_body, image_body) = query_overrides::scope(overrides, async {
+            (
+                transform_claude_request_in(&request("claude-sonnet-4-5"), "proj", false, &EnvelopeParams::default(), &Default::default()),
+                transform_claude_request_in(&request("gemini-3-pro-image"), "proj", false, &EnvelopeParams::default(), &Default::default()),
+            )
+        })
+        .await;
+
+        // 语言指令紧跟用户系统提示词
+        let parts = text_body.unwrap()["request"]["systemInstruction"]["parts"].clone();
+        let texts: Vec<&str> = parts.as_array().unwrap().iter().filter_map(|p| p["text"].as_str()).collect();
+        let user_pos = texts.iter().position(|t| *t == "Answer in English.").unwrap();
+        assert_eq!(texts[user_pos + 1], system_builder::response_language_instruction("ja"));
+
+        // 图像生成请求整体移除 systemInstruction，语言指令一并排除
+        let image_body = image_body.unwrap();
+        assert!(image_body["request"].get("systemInstruction").is_none());
+        assert!(!image_body.to_string().contains("Always respond in"));
+    }
+
+    #[test]
+    fn test_client_stop_sequences_merged_upstream() {
+        // 客户端停止序列排在保护性序列之前发往上游，总数不超过上限
+        let req: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{ "role": "user", "content": "Hello" }],
+            "stop_sequences": ["END", "<|user|>", "###"]
+        }))
+        .unwrap();
+
+        let body = transform_claude_request_in(&req, "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        let upstream = body["request"]["generationConfig"]["stopSequences"].as_array().unwrap();
+        assert_eq!(&upstream[..3], &[json!("END"), json!("<|user|>"), json!("###")]);
+        assert!(upstream.len() <= crate::proxy::mappers::common_utils::MAX_STOP_SEQUENCES);
+        assert_eq!(upstream.iter().filter(|s| *s == "<|user|>").count(), 1);
+    }
+
+    #[test]
+    fn test_project_id_bare_and_vertex_path() {
+        let req: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{ "role": "user", "content": "Hello" }]
+        }))
+        .unwrap();
+
+        for project_id in ["my-proj-123", "projects/my-proj-123/locations/us-central1"] {
+            let body = transform_claude_request_in(&req, project_id, false, &EnvelopeParams::default(), &Default::default()).unwrap();
+            assert_eq!(body["project"], "my-proj-123");
+            // model 字段不受 project 路径影响
+            assert!(!body["model"].as_str().unwrap().contains("projects/"));
+        }
+    }
+
+    #[test]
+    fn test_session_generation_override_applies_across_requests() {
+        use crate::proxy::session_manager::{GenerationOverride, SessionGenerationOverrides};
+
+        let turn = |temperature: f64, max_tokens: u32| -> ClaudeRequest {
+            serde_json::from_value(json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": max_tokens,
+                "temperature": temperature,
+                "top_k": 20,
+                "metadata": { "user_id": "pinned-claude-run" },
+                "messages": [{ "role": "user", "content": "Run the agent" }]
+            }))
+            .unwrap()
+        };
+        let session_id = SessionManager::extract_session_id(&turn(1.0, 1024));
+        SessionGenerationOverrides::global().set(
+            &session_id,
+            GenerationOverride {
+                temperature: Some(0.0),
+                max_output_tokens: Some(4096),
+                seed: Some(7),
+                ..Default::default()
+            },
+        );
+
+        for (temperature, max_tokens) in [(1.0, 1024), (0.7, 2048)] {
+            let body = transform_claude_request_in(&turn(temperature, max_tokens), "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+            let gen = &body["request"]["generationConfig"];
+            assert_eq!(gen["temperature"], 0.0);
+            assert_eq!(gen["maxOutputTokens"], 4096);
+            assert_eq!(gen["seed"], 7);
+            assert_eq!(gen["topK"], 20);
+        }
+
+        SessionGenerationOverrides::global().clear(&session_id);
+        let body = transform_claude_request_in(&turn(0.7, 2048), "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert_eq!(body["request"]["generationConfig"]["temperature"], 0.7);
+        assert!(body["request"]["generationConfig"].get("seed").is_none());
+    }
+
+    #[test]
+    fn test_strip_historical_thinking_keeps_last_assistant() {
+        let mut contents = vec![
+            json!({ "role": "user", "parts": [{ "text": "q1" }] }),
+            json!({ "role": "model", "parts": [
+                { "text": "old thought", "thought": true, "thoughtSignature": "sig_old" },
+                { "text": "a1" }
+            ]}),
+            json!({ "role": "user", "parts": [{ "text": "q2" }] }),
+            json!({ "role": "model", "parts": [
+                { "text": "new thought", "thought": true, "thoughtSignature": "sig_new" },
+                { "functionCall": { "name": "ls", "args": {} }, "thoughtSignature": "sig_call" }
+            ]}),
+            json!({ "role": "user", "parts": [{ "functionResponse": { "name": "ls", "response": {} } }] }),
+        ];
+
+        strip_historical_thinking(&mut contents);
+
+        let historical = &contents[1]["parts"][0];
+        assert!(historical.get("thought").is_none());
+        assert!(historical.get("thoughtSignature").is_none());
+        assert_eq!(historical["text"], "old thought");
+
+        // 最后一条 model 消息 (当前轮次) 保持不变
+        assert_eq!(contents[3]["parts"][0]["thought"], true);
+        assert_eq!(contents[3]["parts"][0]["thoughtSignature"], "sig_new");
+        assert_eq!(contents[3]["parts"][1]["thoughtSignature"], "sig_call");
+    }
+
+    /// 构造 depth 层嵌套对象，每层都带有需要清理的字段
+    fn deeply_nested(depth: usize) -> Value {
+        let dirty = || json!({ "cache_control": {"type": "ephemeral"}, "thought": true, "note": "[undefined]" });
+        let mut value = dirty();
+        for _ in 0..depth {
+            let mut parent = dirty();
+            parent["x"] = value;
+            value = parent;
+        }
+        value
+    }
+
+    fn innermost(value: &Value) -> &Value {
+        let mut current = value;
+        while let Some(next) = current.get("x") {
+            current = next;
+        }
+        current
+    }
+
+    /// 迭代释放，避免超深嵌套的递归 Drop 本身耗尽测试线程的栈
+    fn drop_iteratively(value: Value) {
+        let mut stack = vec![value];
+        while let Some(mut v) = stack.pop() {
+            match &mut v {
+                Value::Object(map) => stack.extend(std::mem::take(map).into_iter().map(|(_, c)| c)),
+                Value::Array(arr) => stack.extend(arr.drain(..)),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_recursive_cleaners_cap_deeply_nested_input() {
+        const DEPTH: usize = 10_000;
+
+        let mut value = deeply_nested(DEPTH);
+        deep_clean_cache_control(&mut value);
+        clean_thinking_fields_recursive(&mut value);
+        crate::proxy::mappers::common_utils::deep_clean_undefined(&mut value, 0);
+        // 浅层正常清理
+        assert!(value.get("cache_control").is_none());
+        assert!(value.get("thought").is_none());
+        assert!(value.get("note").is_none());
+        // 超出深度上限的部分保持原样，而不是栈溢出
+        let leaf = innermost(&value);
+        assert!(leaf.get("cache_control").is_some());
+        assert_eq!(leaf["thought"], true);
+        drop_iteratively(value);
+
+        let mut schema = deeply_nested(DEPTH);
+        crate::proxy::common::json_schema::clean_json_schema(&mut schema);
+        drop_iteratively(schema);
+    }
+
+    #[test]
+    fn test_metadata_user_id_sanitized_into_session_id() {
+        let build = |user_id: &str| {
+            let req: ClaudeRequest = serde_json::from_value(json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 1024,
+                "metadata": { "user_id": user_id },
+                "messages": [{ "role": "user", "content": "hi" }]
+            }))
+            .unwrap();
+            transform_claude_request_in(&req, "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap()
+        };
+
+        let body = build(&"x".repeat(1024 * 1024));
+        assert_eq!(
+            body["request"]["sessionId"].as_str().unwrap().len(),
+            crate::proxy::mappers::common_utils::MAX_ENVELOPE_SESSION_ID_LEN
+        );
+        assert_eq!(build("user_1\nX-Injected: 1")["request"]["sessionId"], "user_1X-Injected: 1");
+        assert_eq!(build("user_1\0\0")["request"]["sessionId"], "user_1");
+        assert!(build(r#"user","labels":{"x":"y"}"#)["request"].get("sessionId").is_none());
+        assert_eq!(build("user_abc_session_123")["request"]["sessionId"], "user_abc_session_123");
+    }
+
+    #[test]
+    fn test_request_id_prefix_configurable() {
+        use crate::proxy::config::{
+            update_request_id_prefix_config, RequestIdPrefixConfig, REQUEST_ID_PREFIX_TEST_LOCK,
+        };
+        let _guard = REQUEST_ID_PREFIX_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let req: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "messages": [{ "role": "user", "content": "hello" }]
+        }))
+        .unwrap();
+        let request_id = || {
+            let body =
+                transform_claude_request_in(&req, "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+            body["requestId"].as_str().unwrap().to_string()
+        };
+
+        assert!(request_id().starts_with("agent-"));
+        // 仅修改 claude 前缀，其他协议保持默认
+        update_request_id_prefix_config(RequestIdPrefixConfig {
+            claude: "ag-claude".to_string(),
+            ..Default::default()
+        });
+        let configured = request_id();
+        // 非法前缀回退到协议默认值
+        update_request_id_prefix_config(RequestIdPrefixConfig {
+            claude: "bad prefix".to_string(),
+            ..Default::default()
+        });
+        let fallback = request_id();
+        update_request_id_prefix_config(RequestIdPrefixConfig::default());
+        assert!(configured.starts_with("ag-claude-"), "{}", configured);
+        assert!(fallback.starts_with("agent-"), "{}", fallback);
+    }
+
+    #[test]
+    fn test_recovered_late_signature_requires_compatible_family() {
+        let build = |session: &str, thought: &str| {
+            let req: ClaudeRequest = serde_json::from_value(json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 4096,
+                "thinking": { "type": "enabled", "budget_tokens": 1024 },
+                "metadata": { "user_id": session },
+                "messages": [
+                    { "role": "user", "content": "hi" },
+                    { "role": "assistant", "content": [
+                        { "type": "thinking", "thinking": thought, "signature": "" },
+                        { "type": "text", "text": "Hello" }
+                    ]},
+                    { "role": "user", "content": "continue" }
+                ]
+            }))
+            .unwrap();
+            let body = transform_claude_request_in(&req, "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+            body["request"]["contents"][1]["parts"][0].clone()
+        };
+        let cache = crate::proxy::SignatureCache::global();
+
+        // 迟到签名来自不兼容的模型族: 不复用，降级为文本
+        let foreign_sig = format!("late_foreign_{}", "f".repeat(MIN_SIGNATURE_LENGTH));
+        cache.cache_thinking_block_signature("late-sig-foreign", "Foreign thought.", foreign_sig.clone());
+        cache.cache_thinking_family(foreign_sig, "gemini-2.0-flash".to_string());
+        let part = build("late-sig-foreign", "Foreign thought.");
+        assert_eq!(part["text"], "Foreign thought.");
+        assert!(part.get("thoughtSignature").is_none());
+
+        // 同族签名照常恢复
+        let own_sig = format!("late_own_{}", "s".repeat(MIN_SIGNATURE_LENGTH));
+        cache.cache_thinking_block_signature("late-sig-own", "Own thought.", own_sig.clone());
+        cache.cache_thinking_family(own_sig.clone(), "claude-sonnet-4-5".to_string());
+        let part = build("late-sig-own", "Own thought.");
+        assert_eq!(part["thoughtSignature"], own_sig);
+        assert_eq!(part["thought"], true);
+    }
+
+    #[test]
+    fn test_sentinel_omitted_after_upstream_model_rejects_it() {
+        use crate::proxy::signature_sentinel;
+
+        // 历史思考块的签名来自不兼容的模型族 (降级为文本)，工具调用只能依赖哨兵值
+        let foreign_sig = format!("sentinel_claude_path_{}", "x".repeat(MIN_SIGNATURE_LENGTH));
+        crate::proxy::SignatureCache::global().cache_thinking_family(foreign_sig.clone(), "claude-opus-4-5".to_string());
+        let req: ClaudeRequest = serde_json::from_value(json!({
+            "model": "gemini-3-pro-sentinel-claude-path",
+            "max_tokens": 4096,
+            "thinking": { "type": "enabled", "budget_tokens": 1024 },
+            "metadata": { "user_id": "sentinel-claude-path" },
+            "messages": [
+                { "role": "user", "content": "List files" },
+                { "role": "assistant", "content": [
+                    { "type": "thinking", "thinking": "Listing.", "signature": foreign_sig },
+                    { "type": "tool_use", "id": "toolu_sentinel_claude_path", "name": "ls", "input": {} }
+                ]},
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "toolu_sentinel_claude_path", "content": "a.txt" }
+                ]}
+            ]
+        }))
+        .unwrap();
+
+        let body = transform_claude_request_in(&req, "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        let probe = signature_sentinel::probe(&body).expect("sentinel injected for tool_use");
+        assert_eq!(probe.model, body["model"].as_str().unwrap());
+        let (content_idx, part_idx) = body["request"]["contents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .find_map(|(i, c)| {
+                c["parts"].as_array().unwrap().iter().position(|p| p.get("functionCall").is_some()).map(|j| (i, j))
+            })
+            .unwrap();
+
+        assert!(signature_sentinel::observe_rejection(
+            &probe,
+            &format!("Invalid value at 'contents[{}].parts[{}].thought_signature'", content_idx, part_idx)
+        ));
+        let body = transform_claude_request_in(&req, "proj", false, &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert!(signature_sentinel::probe(&body).is_none());
+        assert!(!body.to_string().contains(signature_sentinel::SENTINEL));
+
+        signature_sentinel::reset(Some(&probe.model));
+    }
 }
@@ -3,6 +3,7 @@
 
 use super::models::*;
 use super::utils::to_claude_usage;
+use crate::proxy::mappers::error::MapperError;
 use serde_json::json;
 
 /// Known parameter remappings for Gemini → Claude compatibility
@@ -158,6 +159,9 @@ pub struct NonStreamingProcessor {
     pub session_id: Option<String>,
     pub model_name: String,
     pub message_count: usize, // [NEW v4.0.0] Message count for rewind detection
+    pub requested_model: Option<String>, // [NEW] Client-requested model (extension field)
+    pub tool_schemas: std::collections::HashMap<String, serde_json::Value>, // [NEW] 客户端工具名 -> input_schema
+    web_search_requests: u32,
 }
 
 impl NonStreamingProcessor {
@@ -174,6 +178,9 @@ impl NonStreamingProcessor {
             session_id,
             model_name,
             message_count,
+            requested_model: None,
+            tool_schemas: std::collections::HashMap::new(),
+            web_search_requests: 0,
         }
     }
 
@@ -295,7 +302,12 @@ impl NonStreamingProcessor {
             }
 
             // [FIX] Remap args for Gemini → Claude compatibility
-            let mut args = fc.args.clone().unwrap_or(serde_json::json!({}));
+            // [NEW] args 可能以 JSON 字符串返回，先规范化为对象并按 schema 修正类型
+            let mut args = crate::proxy::mappers::common_utils::normalize_function_call_args(
+                fc.args.as_ref(),
+                &fc.name,
+                self.tool_schemas.get(&fc.name),
+            );
             remap_function_call_args(&tool_name, &mut args);
 
             let mut tool_use = ContentBlock::ToolUse {
@@ -371,6 +383,23 @@ impl NonStreamingProcessor {
             }
         }
 
+        // [NEW] executableCode / codeExecutionResult: 渲染为文本，保持与前后文本的顺序
+        if let Some(rendered) = crate::proxy::mappers::common_utils::render_code_execution_part(
+            part.executable_code.as_ref(),
+            part.code_execution_result.as_ref(),
+        ) {
+            self.flush_thinking();
+            if let Some(trailing_sig) = self.trailing_signature.take() {
+                self.flush_text();
+                self.content_blocks.push(ContentBlock::Thinking {
+                    thinking: String::new(),
+                    signature: Some(trailing_sig),
+                    cache_control: None,
+                });
+            }
+            self.text_builder.push_str(&rendered);
+        }
+
         // 3. InlineData (Image) 处理
         if let Some(img) = &part.inline_data {
             self.flush_thinking();
@@ -378,41 +407,58 @@ impl NonStreamingProcessor {
             let mime_type = &img.mime_type;
             let data = &img.data;
             if !data.is_empty() {
-                let markdown_img = format!("![image](data:{};base64,{})", mime_type, data);
-                self.text_builder.push_str(&markdown_img);
-                self.flush_text();
+                if crate::proxy::config::get_image_multimodal_output() {
+                    // [NEW] 多模态输出: 图片作为独立的 image 内容块
+                    self.flush_text();
+                    self.content_blocks.push(ContentBlock::Image {
+                        source: ImageSource {
+                            source_type: "base64".to_string(),
+                            media_type: mime_type.clone(),
+                            data: data.clone(),
+                        },
+                        cache_control: None,
+                    });
+                } else {
+                    let markdown_img = format!("![image](data:{};base64,{})", mime_type, data);
+                    self.text_builder.push_str(&markdown_img);
+                    self.flush_text();
+                }
             }
         }
     }
 
     /// 处理 Grounding 元数据 (Web Search 结果)
     fn process_grounding(&mut self, grounding: &GroundingMetadata) {
-        let mut grounding_text = String::new();
-
-        // 1. 处理搜索词
-        if let Some(queries) = &grounding.web_search_queries {
-            if !queries.is_empty() {
-                grounding_text.push_str("\n\n---\n**🔍 已为您搜索：** ");
-                grounding_text.push_str(&queries.join(", "));
-            }
-        }
-
-        // 2. 处理来源链接 (Chunks)
-        if let Some(chunks) = &grounding.grounding_chunks {
-            let mut links = Vec::new();
-            for (i, chunk) in chunks.iter().enumerate() {
-                if let Some(web) = &chunk.web {
-                    let title = web.title.as_deref().unwrap_or("网页来源");
-                    let uri = web.uri.as_deref().unwrap_or("#");
-                    links.push(format!("[{}] [{}]({})", i + 1, title, uri));
-                }
-            }
-
-            if !links.is_empty() {
-                grounding_text.push_str("\n\n**🌐 来源引文：**\n");
-                grounding_text.push_str(&links.join("\n"));
-            }
-        }
+        self.web_search_requests = grounding
+            .web_search_queries
+            .as_ref()
+            .map_or(0, |queries| queries.len() as u32)
+            .max(1);
+        let query = grounding
+            .web_search_queries
+            .as_ref()
+            .filter(|queries| !queries.is_empty())
+            .map(|queries| queries.join(", "));
+        let sources: Vec<(usize, &str, &str)> = grounding
+            .grounding_chunks
+            .iter()
+            .flatten()
+            .enumerate()
+            .filter_map(|(i, chunk)| {
+                let web = chunk.web.as_ref()?;
+                Some((
+                    i + 1,
+                    web.title.as_deref().unwrap_or("网页来源"),
+                    web.uri.as_deref().unwrap_or("#"),
+                ))
+            })
+            .collect();
+        // [NEW] 与流式输出共用引文样式
+        let grounding_text = super::streaming::render_grounding_text(
+            crate::proxy::config::get_citation_style(),
+            query.as_deref(),
+            &sources,
+        );
 
         if !grounding_text.is_empty() {
             // 在常规内容前后刷新并插入文本
@@ -510,7 +556,7 @@ impl NonStreamingProcessor {
             "end_turn"
         };
 
-        let usage = gemini_response
+        let mut usage = gemini_response
             .usage_metadata
             .as_ref()
             .map(|u| to_claude_usage(u, self.scaling_enabled, self.context_limit))
@@ -521,6 +567,7 @@ impl NonStreamingProcessor {
                 cache_creation_input_tokens: None,
                 server_tool_use: None,
             });
+        usage.server_tool_use = super::utils::web_search_usage(self.web_search_requests);
 
         ClaudeResponse {
             id: gemini_response.response_id.clone().unwrap_or_else(|| {
@@ -528,11 +575,18 @@ impl NonStreamingProcessor {
             }),
             type_: "message".to_string(),
             role: "assistant".to_string(),
-            model: gemini_response.model_version.clone().unwrap_or_default(),
+            // [NEW] 报告实际服务的模型 (映射/降级后)，未知时回退到上游 modelVersion
+            model: if self.model_name.is_empty() {
+                gemini_response.model_version.clone().unwrap_or_default()
+            } else {
+                self.model_name.clone()
+            },
             content: self.content_blocks.clone(),
             stop_reason: stop_reason.to_string(),
             stop_sequence: None,
             usage,
+            requested_model: self.requested_model.clone(),
+            usage_breakdown: None,
         }
     }
 }
@@ -544,8 +598,12 @@ pub fn transform_response(
     session_id: Option<String>,
     model_name: String,
     message_count: usize, // [NEW v4.0.0] Message count for rewind detection
-) -> Result<ClaudeResponse, String> {
+    requested_model: Option<String>, // [NEW] Client-requested model (extension field)
+    tool_schemas: std::collections::HashMap<String, serde_json::Value>, // [NEW] Client tool schemas for args type fixing
+) -> Result<ClaudeResponse, MapperError> {
     let mut processor = NonStreamingProcessor::new(session_id, model_name, message_count);
+    processor.requested_model = requested_model;
+    processor.tool_schemas = tool_schemas;
     Ok(processor.process(gemini_response, scaling_enabled, context_limit))
 }
 
@@ -565,6 +623,8 @@ mod tests {
                         thought_signature: None,
                         function_call: None,
                         function_response: None,
+                        executable_code: None,
+                        code_execution_result: None,
                         inline_data: None,
                     }],
                 }),
@@ -577,6 +637,7 @@ mod tests {
                 candidates_token_count: Some(5),
                 total_token_count: Some(15),
                 cached_content_token_count: None,
+                thoughts_token_count: None,
             }),
             model_version: Some("gemini-2.5-flash".to_string()),
             response_id: Some("resp_123".to_string()),
@@ -589,6 +650,8 @@ mod tests {
             None,
             "gemini-2.5-flash".to_string(),
             1,
+            None,
+            std::collections::HashMap::new(),
         );
         assert!(result.is_ok());
 
@@ -605,6 +668,89 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_code_execution_parts_rendered_as_text() {
+        let gemini_resp: GeminiResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{
+                "content": {
+                    "role": "model",
+                    "parts": [
+                        { "text": "Let me compute." },
+                        { "executableCode": { "language": "PYTHON", "code": "print(2 + 2)" } },
+                        { "codeExecutionResult": { "outcome": "OUTCOME_OK", "output": "4" } },
+                        { "text": "So the answer is 4." }
+                    ]
+                },
+                "finishReason": "STOP"
+            }]
+        }))
+        .unwrap();
+
+        let claude_resp = transform_response(
+            &gemini_resp,
+            false,
+            1_000_000,
+            None,
+            "gemini-2.5-flash".to_string(),
+            1,
+            None,
+            std::collections::HashMap::new(),
+        )
+        .unwrap();
+
+        assert_eq!(claude_resp.content.len(), 1);
+        match &claude_resp.content[0] {
+            ContentBlock::Text { text } => assert_eq!(
+                text,
+                "Let me compute.\n```python\nprint(2 + 2)\n```\n\n```output\n4\n```\nSo the answer is 4."
+            ),
+            _ => panic!("Expected Text block"),
+        }
+    }
+
+    #[test]
+    fn test_function_call_args_fixed_by_tool_schema() {
+        let gemini_resp: GeminiResponse = serde_json::from_value(json!({
+            "candidates": [{
+                "content": {
+                    "role": "model",
+                    "parts": [
+                        { "functionCall": { "name": "fetch_page", "args": "{\"url\": \"https://example.com\", \"max_lines\": \"20\"}", "id": "call_1" } }
+                    ]
+                },
+                "finishReason": "STOP"
+            }]
+        }))
+        .unwrap();
+        let schemas = std::collections::HashMap::from([(
+            "fetch_page".to_string(),
+            json!({
+                "type": "object",
+                "properties": { "url": { "type": "string" }, "max_lines": { "type": "integer" } }
+            }),
+        )]);
+
+        let claude_resp = transform_response(
+            &gemini_resp,
+            false,
+            1_000_000,
+            None,
+            "gemini-2.5-flash".to_string(),
+            1,
+            None,
+            schemas,
+        )
+        .unwrap();
+
+        match &claude_resp.content[0] {
+            ContentBlock::ToolUse { name, input, .. } => {
+                assert_eq!(name, "fetch_page");
+                assert_eq!(input, &json!({ "url": "https://example.com", "max_lines": 20 }));
+            }
+            other => panic!("Expected ToolUse block, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_thinking_with_signature() {
         let gemini_resp = GeminiResponse {
@@ -618,6 +764,8 @@ mod tests {
                             thought_signature: Some("sig123".to_string()),
                             function_call: None,
                             function_response: None,
+                            executable_code: None,
+                            code_execution_result: None,
                             inline_data: None,
                         },
                         GeminiPart {
@@ -626,6 +774,8 @@ mod tests {
                             thought_signature: None,
                             function_call: None,
                             function_response: None,
+                            executable_code: None,
+                            code_execution_result: None,
                             inline_data: None,
                         },
                     ],
@@ -646,6 +796,8 @@ mod tests {
             None,
             "gemini-2.5-flash".to_string(),
             1,
+            None,
+            std::collections::HashMap::new(),
         );
         assert!(result.is_ok());
 
@@ -0,0 +1,325 @@
+// 上游流中断续写 (opt-in)
+// 上游连接在已向客户端转发内容后断开时，携带已生成的助手文本重新请求一次，
+// 并把续写内容拼接到同一条 Claude 消息中 (不再发送新的 message_start)。
+// 续写开头若重复了已发送文本的结尾，会去掉重叠部分；若模型明显从头重新作答，
+// 则放弃拼接，回退到原有的错误处理。
+// 同一机制也用于思考耗尽追问: 上游只输出思考即以 MAX_TOKENS 结束时 (opt-in)，
+// 携带被截断的思考内容、关闭思考重新请求一次最终答案，作为新的文本块并入同一条消息。
+
+use bytes::Bytes;
+use futures::{Future, Stream};
+use serde_json::{json, Value};
+use std::pin::Pin;
+
+pub type GeminiByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>;
+pub type ResumeFuture = Pin<Box<dyn Future<Output = Result<GeminiByteStream, String>> + Send>>;
+
+/// 续写请求发起器，参数为已转发的助手文本 (每个流最多调用一次)
+pub type StreamResumer = Box<dyn FnOnce(String) -> ResumeFuture + Send>;
+
+/// 思考耗尽追问发起器，参数为被截断的思考文本 (每个流最多调用一次)
+pub type ThinkingNudger = Box<dyn FnOnce(String) -> ResumeFuture + Send>;
+
+/// 追加在被截断的思考内容之后的追问指令
+pub const NUDGE_INSTRUCTION: &str = "Your previous turn ran out of output tokens while reasoning, before giving an answer. \
+The reasoning so far is above. Produce the final answer concisely without further deliberation.";
+
+/// 追问请求的输出上限
+pub const NUDGE_MAX_OUTPUT_TOKENS: u32 = 4096;
+
+/// 追加在部分助手回复之后的续写指令
+pub const CONTINUE_INSTRUCTION: &str = "Your previous response was cut off by a network interruption. \
+Continue it exactly from where it stopped, without repeating any earlier text, \
+without acknowledging the interruption and without starting over.";
+
+/// 判断续写是否重复/重启前需要缓冲的字符数
+const PROBE_CHARS: usize = 64;
+/// 认定为重叠所需的最少字符数，避免误删偶然相同的短片段
+const MIN_OVERLAP_CHARS: usize = 8;
+/// 续写以原回答开头这么多字符起始时，视为模型从头重新作答
+const RESTART_PROBE_CHARS: usize = 16;
+
+/// 在 v1internal 请求体中追加部分助手回复与续写指令
+pub fn build_continuation_body(body: &Value, partial: &str) -> Value {
+    let mut body = body.clone();
+    if let Some(contents) = body
+        .get_mut("request")
+        .and_then(|r| r.get_mut("contents"))
+        .and_then(|c| c.as_array_mut())
+    {
+        contents.push(json!({ "role": "model", "parts": [{ "text": partial }] }));
+        contents.push(json!({ "role": "user", "parts": [{ "text": CONTINUE_INSTRUCTION }] }));
+    }
+    body
+}
+
+/// 构造追问请求体: 追加截断的思考内容与追问指令，关闭思考并限制输出长度
+pub fn build_nudge_body(body: &Value, thinking: &str) -> Value {
+    let mut body = body.clone();
+    let Some(request) = body.get_mut("request").filter(|r| r.is_object()) else {
+        return body;
+    };
+    if let Some(contents) = request.get_mut("contents").and_then(|c| c.as_array_mut()) {
+        contents.push(json!({ "role": "model", "parts": [{ "text": thinking }] }));
+        contents.push(json!({ "role": "user", "parts": [{ "text": NUDGE_INSTRUCTION }] }));
+    }
+    if !request.get("generationConfig").map_or(false, |c| c.is_object()) {
+        request["generationConfig"] = json!({});
+    }
+    let config = &mut request["generationConfig"];
+    config["thinkingConfig"] = json!({ "includeThoughts": false, "thinkingBudget": 0 });
+    config["maxOutputTokens"] = json!(NUDGE_MAX_OUTPUT_TOKENS);
+    body
+}
+
+/// 拆分以 MAX_TOKENS 结束的 SSE 行: 返回 (去掉 finishReason 的内容行, 仅含 finishReason 与 usage 的结束行)
+/// 其他行返回 None
+pub fn split_max_tokens_finish(line: &str) -> Option<(String, String)> {
+    let data = line.strip_prefix("data: ")?;
+    let mut json: Value = serde_json::from_str(data.trim()).ok()?;
+    let wrapped = json.get("response").is_some();
+    // usageMetadata 可能位于 response 包装之外
+    let usage = json
+        .pointer("/response/usageMetadata")
+        .or_else(|| json.get("usageMetadata"))
+        .cloned();
+    let raw = if wrapped { &mut json["response"] } else { &mut json };
+    let candidate = raw.get_mut("candidates")?.get_mut(0)?.as_object_mut()?;
+    if candidate.get("finishReason").and_then(|f| f.as_str()) != Some("MAX_TOKENS") {
+        return None;
+    }
+    candidate.remove("finishReason");
+
+    let mut finish = json!({ "candidates": [{ "finishReason": "MAX_TOKENS" }] });
+    if let Some(usage) = usage {
+        finish["usageMetadata"] = usage;
+    }
+    if wrapped {
+        finish = json!({ "response": finish });
+    }
+    Some((format!("data: {}", json), format!("data: {}", finish)))
+}
+
+/// 结束行中的 usageMetadata (追问成功后累加到最终 usage)
+pub fn finish_usage(finish_line: &str) -> Option<super::models::UsageMetadata> {
+    let json: Value = serde_json::from_str(finish_line.strip_prefix("data: ")?.trim()).ok()?;
+    let usage = json
+        .pointer("/response/usageMetadata")
+        .or_else(|| json.get("usageMetadata"))?;
+    serde_json::from_value(usage.clone()).ok()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SpliceOutcome {
+    /// 仍在缓冲，暂不发送
+    Pending,
+    /// 可发送的文本 (已去除重叠前缀)
+    Emit(String),
+    /// 模型从头重新作答，放弃拼接
+    Restarted,
+}
+
+/// 续写文本拼接器: 缓冲续写开头，与已发送文本比较后决定去重或放弃
+pub struct ContinuationSplicer {
+    previous: String,
+    pending: String,
+    decided: bool,
+}
+
+impl ContinuationSplicer {
+    pub fn new(previous: String) -> Self {
+        Self {
+            previous,
+            pending: String::new(),
+            decided: false,
+        }
+    }
+
+    pub fn push(&mut self, text: &str) -> SpliceOutcome {
+        if self.decided {
+            return SpliceOutcome::Emit(text.to_string());
+        }
+        self.pending.push_str(text);
+        if self.pending.chars().count() < PROBE_CHARS {
+            return SpliceOutcome::Pending;
+        }
+        self.decide()
+    }
+
+    /// 续写结束时输出仍在缓冲的文本
+    pub fn finish(&mut self) -> SpliceOutcome {
+        if self.decided || self.pending.is_empty() {
+            return SpliceOutcome::Emit(String::new());
+        }
+        self.decide()
+    }
+
+    fn decide(&mut self) -> SpliceOutcome {
+        self.decided = true;
+        let pending = std::mem::take(&mut self.pending);
+        if let Some(overlap) = overlap_len(&self.previous, &pending) {
+            return SpliceOutcome::Emit(pending[overlap..].to_string());
+        }
+        if looks_restarted(&self.previous, &pending) {
+            return SpliceOutcome::Restarted;
+        }
+        SpliceOutcome::Emit(pending)
+    }
+}
+
+/// 已发送文本的结尾与续写开头的最长重叠 (字节长度)
+fn overlap_len(previous: &str, next: &str) -> Option<usize> {
+    next.char_indices()
+        .map(|(i, c)| i + c.len_utf8())
+        .rev()
+        .find(|&end| previous.ends_with(&next[..end]))
+        .filter(|&end| next[..end].chars().count() >= MIN_OVERLAP_CHARS)
+}
+
+/// 续写是否以原回答的开头重新开始
+fn looks_restarted(previous: &str, next: &str) -> bool {
+    let head: String = previous.trim_start().chars().take(RESTART_PROBE_CHARS).collect();
+    head.chars().count() == RESTART_PROBE_CHARS && next.trim_start().starts_with(&head)
+}
+
+/// 续写流中单行 SSE 的处理结果
+pub enum SplicedLine {
+    Line(String),
+    Restarted,
+}
+
+/// 对续写流的一行 SSE 数据做拼接处理:
+/// 丢弃思考内容 (文本块之后不能再出现 thinking 块)，文本经拼接器去重，
+/// 遇到 finishReason 时补发仍在缓冲的文本
+pub fn splice_sse_line(line: &str, splicer: &mut ContinuationSplicer) -> SplicedLine {
+    let Some(data) = line.strip_prefix("data: ") else {
+        return SplicedLine::Line(line.to_string());
+    };
+    let Ok(mut json) = serde_json::from_str::<Value>(data.trim()) else {
+        return SplicedLine::Line(line.to_string());
+    };
+
+    let raw = if json.get("response").is_some() {
+        &mut json["response"]
+    } else {
+        &mut json
+    };
+    let Some(candidate) = raw.get_mut("candidates").and_then(|c| c.get_mut(0)) else {
+        return SplicedLine::Line(line.to_string());
+    };
+    let finished = candidate.get("finishReason").is_some();
+
+    if let Some(parts) = candidate
+        .pointer_mut("/content/parts")
+        .and_then(|p| p.as_array_mut())
+    {
+        parts.retain(|p| !p.get("thought").and_then(|t| t.as_bool()).unwrap_or(false));
+        for part in parts.iter_mut() {
+            let Some(text) = part.get("text").and_then(|t| t.as_str()) else {
+                continue;
+            };
+            match splicer.push(text) {
+                SpliceOutcome::Pending => part["text"] = json!(""),
+                SpliceOutcome::Emit(s) => part["text"] = json!(s),
+                SpliceOutcome::Restarted => return SplicedLine::Restarted,
+            }
+        }
+    }
+
+    if finished {
+        match splicer.finish() {
+            SpliceOutcome::Emit(rest) if !rest.is_empty() => {
+                let text_part = json!({ "text": rest });
+                match candidate
+                    .pointer_mut("/content/parts")
+                    .and_then(|p| p.as_array_mut())
+                {
+                    Some(parts) => parts.insert(0, text_part),
+                    None => candidate["content"] = json!({ "role": "model", "parts": [text_part] }),
+                }
+            }
+            SpliceOutcome::Restarted => return SplicedLine::Restarted,
+            _ => {}
+        }
+    }
+
+    SplicedLine::Line(format!("data: {}", json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splicer_strips_repeated_prefix() {
+        let mut splicer = ContinuationSplicer::new("The quick brown fox jumps over".to_string());
+        assert_eq!(splicer.push("brown fox jumps over"), SpliceOutcome::Pending);
+        assert_eq!(
+            splicer.push(" the lazy dog, and then it keeps running through the field."),
+            SpliceOutcome::Emit(" the lazy dog, and then it keeps running through the field.".to_string())
+        );
+        assert_eq!(splicer.push(" More."), SpliceOutcome::Emit(" More.".to_string()));
+    }
+
+    #[test]
+    fn test_splicer_detects_restarted_answer() {
+        let previous = "Here is a detailed explanation of how the borrow checker works in Rust. First,";
+        let mut splicer = ContinuationSplicer::new(previous.to_string());
+        assert_eq!(splicer.push("Here is a detailed explanation of how the borrow "), SpliceOutcome::Pending);
+        assert_eq!(splicer.push("checker works in Rust. Ownership"), SpliceOutcome::Restarted);
+    }
+
+    #[test]
+    fn test_short_continuation_flushed_on_finish() {
+        let mut splicer = ContinuationSplicer::new("Answer: 4".to_string());
+        assert_eq!(splicer.push("2."), SpliceOutcome::Pending);
+        assert_eq!(splicer.finish(), SpliceOutcome::Emit("2.".to_string()));
+    }
+
+    #[test]
+    fn test_continuation_body_appends_partial_turn() {
+        let body = json!({ "project": "p", "request": { "contents": [
+            { "role": "user", "parts": [{ "text": "hi" }] }
+        ]}});
+        let resumed = build_continuation_body(&body, "partial answer");
+        let contents = resumed["request"]["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 3);
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(contents[1]["parts"][0]["text"], "partial answer");
+        assert_eq!(contents[2]["parts"][0]["text"], CONTINUE_INSTRUCTION);
+        // 原请求体不受影响
+        assert_eq!(body["request"]["contents"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_nudge_body_disables_thinking() {
+        let body = json!({ "project": "p", "request": {
+            "contents": [{ "role": "user", "parts": [{ "text": "hi" }] }],
+            "generationConfig": {
+                "maxOutputTokens": 65536,
+                "thinkingConfig": { "includeThoughts": true, "thinkingBudget": 32000 }
+            }
+        }});
+        let nudged = build_nudge_body(&body, "Let me think about this...");
+        let request = &nudged["request"];
+        assert_eq!(request["contents"][1]["parts"][0]["text"], "Let me think about this...");
+        assert_eq!(request["contents"][2]["parts"][0]["text"], NUDGE_INSTRUCTION);
+        assert_eq!(request["generationConfig"]["thinkingConfig"]["thinkingBudget"], 0);
+        assert_eq!(request["generationConfig"]["maxOutputTokens"], NUDGE_MAX_OUTPUT_TOKENS);
+    }
+
+    #[test]
+    fn test_split_max_tokens_finish() {
+        let line = format!("data: {}", json!({ "response": {
+            "candidates": [{ "content": { "parts": [{ "text": "...", "thought": true }] }, "finishReason": "MAX_TOKENS" }],
+            "usageMetadata": { "candidatesTokenCount": 8192 }
+        }}));
+        let (content, finish) = split_max_tokens_finish(&line).unwrap();
+        assert!(!content.contains("finishReason") && content.contains("\"thought\":true"));
+        assert!(finish.contains("MAX_TOKENS") && finish.contains("8192") && !finish.contains("thought"));
+        assert_eq!(finish_usage(&finish).unwrap().candidates_token_count, Some(8192));
+
+        let stop = format!("data: {}", json!({ "candidates": [{ "finishReason": "STOP" }] }));
+        assert!(split_max_tokens_finish(&stop).is_none());
+    }
+}
@@ -1,15 +1,43 @@
 // Claude 流式响应转换 (Gemini SSE → Claude SSE)
 // 对应 StreamingState + PartProcessor
 
+use super::delta_filter::{DeltaClass, DeltaPipeline};
+use super::diagnostics::{record_truncated_tool_use, TruncatedToolUseOutcome};
 use super::models::*;
-use super::utils::to_claude_usage;
+use super::partial_args::PartialArgsAccumulator;
+use super::utils::{to_claude_usage, web_search_usage};
+use crate::proxy::mappers::common::mcp_xml::{McpXmlEvent, McpXmlParser};
 use crate::proxy::mappers::estimation_calibrator::get_calibrator;
 // use crate::proxy::mappers::signature_store::store_thought_signature; // Deprecated
 use crate::proxy::SignatureCache;
 use crate::proxy::common::client_adapter::{ClientAdapter, SignatureBufferStrategy}; // [NEW]
+use crate::proxy::config::CitationStyle;
 use bytes::Bytes;
 use serde_json::{json, Value};
 
+/// [NEW] message_delta 上的内部扩展字段: 上游思考 token 数 (仅供收集器做 token 拆分，不下发给流式客户端)
+pub const THOUGHTS_TOKENS_FIELD: &str = "_abv_thoughts_tokens";
+
+/// 移除 SSE 分块中 data 行上的内部扩展字段 (流式直接返回客户端前调用)
+pub fn strip_internal_fields(chunk: Bytes) -> Bytes {
+    let Ok(text) = std::str::from_utf8(&chunk) else {
+        return chunk;
+    };
+    if !text.contains(THOUGHTS_TOKENS_FIELD) {
+        return chunk;
+    }
+    let mut out = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let stripped = line.strip_prefix("data: ").and_then(|data| {
+            let mut value: Value = serde_json::from_str(data.trim_end()).ok()?;
+            value.as_object_mut()?.remove(THOUGHTS_TOKENS_FIELD)?;
+            Some(format!("data: {}{}", value, &data[data.trim_end().len()..]))
+        });
+        out.push_str(stripped.as_deref().unwrap_or(line));
+    }
+    Bytes::from(out)
+}
+
 /// Known parameter remappings for Gemini → Claude compatibility
 /// [FIX] Gemini sometimes uses different parameter names than specified in tool schema
 pub fn remap_function_call_args(name: &str, args: &mut Value) {
@@ -171,6 +199,7 @@ pub enum BlockType {
     Text,
     Thinking,
     Function,
+    Image,
 }
 
 /// 签名管理器
@@ -203,12 +232,16 @@ pub struct StreamingState {
     block_type: BlockType,
     pub block_index: usize,
     pub message_start_sent: bool,
+    // [NEW] 在首个真实内容或 finishReason 到达前不发送 message_start
+    pub defer_message_start: bool,
     pub message_stop_sent: bool,
     used_tool: bool,
     signatures: SignatureManager,
     trailing_signature: Option<String>,
     pub web_search_query: Option<String>,
     pub grounding_chunks: Option<Vec<serde_json::Value>>,
+    // [NEW] groundingSupports: 文本片段 (上游字节偏移) -> groundingChunks 索引
+    pub grounding_supports: Option<Vec<serde_json::Value>>,
     // [IMPROVED] Error recovery 状态追踪 (prepared for future use)
     #[allow(dead_code)]
     parse_error_count: usize,
@@ -216,15 +249,18 @@ pub struct StreamingState {
     last_valid_state: Option<BlockType>,
     // [NEW] Model tracking for signature cache
     pub model_name: Option<String>,
+    // [NEW] Final resolved model (post-mapping, post-fallback) reported in message_start
+    pub served_model: Option<String>,
+    // [NEW] Model name originally requested by the client (extension field)
+    pub requested_model: Option<String>,
     // [NEW v3.3.17] Session ID for session-based signature caching
     pub session_id: Option<String>,
     // [NEW] Flag for context usage scaling
     pub scaling_enabled: bool,
     // [NEW] Context limit for smart threshold recovery (default to 1M)
     pub context_limit: u32,
-    // [NEW] MCP XML Bridge 缓冲区
-    pub mcp_xml_buffer: String,
-    pub in_mcp_xml: bool,
+    // [NEW] MCP XML Bridge 解析器
+    pub mcp_xml_parser: McpXmlParser,
     // [FIX] Estimated prompt tokens for calibrator learning
     pub estimated_prompt_tokens: Option<u32>,
     // [FIX #859] Post-thinking interruption tracking
@@ -232,6 +268,95 @@ pub struct StreamingState {
     pub has_content: bool,
     pub message_count: usize, // [NEW v4.0.0] Message count for rewind detection
     pub client_adapter: Option<std::sync::Arc<dyn ClientAdapter>>, // [FIX] Remove Box, use Arc<dyn> directly
+    // [NEW] 行内引用的偏移追踪 (仅在适配器支持 citations 时记录)
+    citation_offsets: CitationOffsets,
+    // [NEW] 客户端工具名 -> input_schema，用于修正上游返回的工具参数类型
+    pub tool_schemas: std::collections::HashMap<String, Value>,
+    // [NEW] 类型化 delta 后处理 (停止序列仅检测可见文本，脱敏按类别，输出 token 估算)
+    delta_pipeline: parking_lot::Mutex<DeltaPipeline>,
+    // [NEW] 已转发的上游答案文本 (仅在启用中断续写时记录)
+    pub resume_transcript: Option<String>,
+    // [NEW] 本轮全部思考文本 (仅在启用思考耗尽追问时记录，跨 thinking 块累积)
+    pub thinking_transcript: Option<String>,
+    /// [NEW] 最近一次上游 usageMetadata (结束块缺少 usage 时回退使用，保留缓存命中统计)
+    pub last_usage_metadata: Option<UsageMetadata>,
+    /// [NEW] 思考耗尽追问前首个请求的 usage (含思考 token)，结束时累加到最终 usage
+    pub carried_usage: Option<UsageMetadata>,
+    /// [NEW] 本轮联网搜索次数 (上报为 usage.server_tool_use.web_search_requests)
+    pub web_search_requests: u32,
+    /// [NEW] 截断的工具调用无法修复、已降级为文本时强制 stop_reason 为 end_turn
+    force_end_turn: bool,
+    /// [NEW] 正在流式接收参数的工具调用 (functionCall.partialArgs)
+    streaming_tool_call: Option<StreamingToolCall>,
+    /// [NEW] 当前 (或最近关闭的) thinking 块文本与是否已发送签名，用于处理迟到的签名
+    thinking_text: String,
+    thinking_signed: bool,
+}
+
+/// 参数分多个 chunk 到达的工具调用
+struct StreamingToolCall {
+    id: Option<String>,
+    /// 上游工具名 (用于查找 schema 与参数重映射)
+    name: String,
+    accumulator: PartialArgsAccumulator,
+}
+
+/// 上游文本偏移 -> 已发送文本偏移的映射
+///
+/// groundingSupports 的 segment 索引基于上游原始文本 (UTF-8 字节)，
+/// 而客户端看到的是我们实际发送的文本 (可能被 MCP XML 转换、注入额外内容)。
+/// 每个上游文本 part 记录一个锚点，part 内部按 1:1 映射。
+#[derive(Default)]
+struct CitationOffsets {
+    /// 上游答案文本 (不含 thinking)
+    upstream_text: String,
+    /// 已发送的答案文本 (跨所有 text 块拼接)
+    emitted_text: String,
+    emitted_chars: usize,
+    /// (上游字节偏移, 已发送字符偏移)
+    anchors: Vec<(usize, usize)>,
+}
+
+impl CitationOffsets {
+    fn record_upstream(&mut self, text: &str) {
+        self.anchors.push((self.upstream_text.len(), self.emitted_chars));
+        self.upstream_text.push_str(text);
+    }
+
+    fn record_emitted(&mut self, text: &str) {
+        self.emitted_text.push_str(text);
+        self.emitted_chars += text.chars().count();
+    }
+
+    /// 将上游字节偏移映射为已发送文本中的字符偏移
+    fn map_offset(&self, byte_idx: usize) -> Option<usize> {
+        let &(upstream_start, emitted_start) =
+            self.anchors.iter().rev().find(|(u, _)| *u <= byte_idx)?;
+        let within = self.upstream_text.get(upstream_start..byte_idx)?;
+        Some(emitted_start + within.chars().count())
+    }
+
+    /// 计算片段在已发送文本中的字符区间 [start, end)
+    /// 映射结果与片段原文不一致时 (前面有被改写的内容)，回退为按原文查找
+    fn resolve_span(&self, start_byte: usize, end_byte: usize, segment_text: Option<&str>) -> Option<(usize, usize)> {
+        let mapped = self
+            .map_offset(start_byte)
+            .zip(self.map_offset(end_byte))
+            .filter(|(s, e)| s < e && *e <= self.emitted_chars);
+
+        let Some(expected) = segment_text.filter(|t| !t.is_empty()) else {
+            return mapped;
+        };
+        if let Some((s, e)) = mapped {
+            let actual: String = self.emitted_text.chars().skip(s).take(e - s).collect();
+            if actual == expected {
+                return Some((s, e));
+            }
+        }
+        let byte_pos = self.emitted_text.find(expected)?;
+        let s = self.emitted_text[..byte_pos].chars().count();
+        Some((s, s + expected.chars().count()))
+    }
 }
 
 impl StreamingState {
@@ -240,29 +365,54 @@ impl StreamingState {
             block_type: BlockType::None,
             block_index: 0,
             message_start_sent: false,
+            defer_message_start: false,
             message_stop_sent: false,
             used_tool: false,
             signatures: SignatureManager::new(),
             trailing_signature: None,
             web_search_query: None,
             grounding_chunks: None,
+            grounding_supports: None,
             // [IMPROVED] 初始化 error recovery 字段
             parse_error_count: 0,
             last_valid_state: None,
             model_name: None,
+            served_model: None,
+            requested_model: None,
             session_id: None,
             scaling_enabled: false,
             context_limit: 1_048_576, // Default to 1M
-            mcp_xml_buffer: String::new(),
-            in_mcp_xml: false,
+            mcp_xml_parser: McpXmlParser::new(),
             estimated_prompt_tokens: None,
             has_thinking: false,
             has_content: false,
             message_count: 0,
             client_adapter: None,
+            citation_offsets: CitationOffsets::default(),
+            tool_schemas: std::collections::HashMap::new(),
+            delta_pipeline: parking_lot::Mutex::new(DeltaPipeline::default()),
+            resume_transcript: None,
+            thinking_transcript: None,
+            last_usage_metadata: None,
+            carried_usage: None,
+            web_search_requests: 0,
+            force_end_turn: false,
+            streaming_tool_call: None,
+            thinking_text: String::new(),
+            thinking_signed: false,
         }
     }
 
+    // [NEW] Set delta post-processing pipeline
+    pub fn set_delta_pipeline(&mut self, pipeline: DeltaPipeline) {
+        self.delta_pipeline = parking_lot::Mutex::new(pipeline);
+    }
+
+    /// 可见文本已命中客户端停止序列 (之后的上游内容不再发送)
+    pub fn stop_sequence_hit(&self) -> bool {
+        self.delta_pipeline.lock().stop_sequence().is_some()
+    }
+
     // [NEW] Set client adapter
     pub fn set_client_adapter(&mut self, adapter: Option<std::sync::Arc<dyn ClientAdapter>>) {
         self.client_adapter = adapter;
@@ -289,22 +439,44 @@ impl StreamingState {
             .and_then(|u| serde_json::from_value::<UsageMetadata>(u.clone()).ok())
             .map(|u| to_claude_usage(&u, self.scaling_enabled, self.context_limit));
 
+        // [NEW] 优先报告实际服务的模型 (映射/降级后)，否则回退到上游 modelVersion，
+        // 上游省略 modelVersion 时使用客户端请求的模型名
+        let upstream_model = raw_json
+            .get("modelVersion")
+            .and_then(|v| v.as_str())
+            .filter(|m| !m.is_empty());
+        let reported_model = self
+            .served_model
+            .as_deref()
+            .filter(|m| !m.is_empty())
+            .or(upstream_model)
+            .or(self.requested_model.as_deref())
+            .unwrap_or("unknown");
+
+        // [NEW] 部分分块不携带 responseId，生成兜底的消息 ID
+        let message_id = raw_json
+            .get("responseId")
+            .and_then(|v| v.as_str())
+            .filter(|id| !id.is_empty())
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| format!("msg_{}", uuid::Uuid::new_v4().simple()));
+
         let mut message = json!({
-            "id": raw_json.get("responseId")
-                .and_then(|v| v.as_str())
-                .unwrap_or_else(|| "msg_unknown"),
+            "id": message_id,
             "type": "message",
             "role": "assistant",
             "content": [],
-            "model": raw_json.get("modelVersion")
-                .and_then(|v| v.as_str())
-                .unwrap_or(""),
+            "model": reported_model,
             "stop_reason": null,
             "stop_sequence": null,
         });
 
+        if let Some(requested) = &self.requested_model {
+            message["requested_model"] = json!(requested);
+        }
+
         // Capture model name for signature cache
-        if let Some(m) = raw_json.get("modelVersion").and_then(|v| v.as_str()) {
+        if let Some(m) = upstream_model {
             self.model_name = Some(m.to_string());
         }
 
@@ -344,6 +516,10 @@ impl StreamingState {
             }),
         ));
 
+        if block_type == BlockType::Thinking {
+            self.thinking_text.clear();
+            self.thinking_signed = false;
+        }
         self.block_type = block_type;
         chunks
     }
@@ -356,10 +532,26 @@ impl StreamingState {
 
         let mut chunks = Vec::new();
 
+        // 补发管线中暂存的可见文本
+        if self.block_type == BlockType::Text {
+            let held = self.delta_pipeline.lock().flush_visible();
+            if let Some(text) = held {
+                chunks.push(self.emit(
+                    "content_block_delta",
+                    json!({
+                        "type": "content_block_delta",
+                        "index": self.block_index,
+                        "delta": { "type": "text_delta", "text": text }
+                    }),
+                ));
+            }
+        }
+
         // Thinking 块结束时发送暂存的签名
         if self.block_type == BlockType::Thinking && self.signatures.has_pending() {
             if let Some(signature) = self.signatures.consume() {
                 chunks.push(self.emit_delta("signature_delta", json!({ "signature": signature })));
+                self.thinking_signed = true;
             }
         }
 
@@ -378,6 +570,9 @@ impl StreamingState {
     }
 
     /// 发送 delta 事件
+    ///
+    /// 内容类 delta (thinking/text/input_json) 会先经过 DeltaPipeline 后处理，
+    /// 命中停止序列后的内容 delta 以及处理后为空的 delta 不再发送
     pub fn emit_delta(&self, delta_type: &str, delta_content: serde_json::Value) -> Bytes {
         let mut delta = json!({ "type": delta_type });
         if let serde_json::Value::Object(map) = delta_content {
@@ -386,6 +581,16 @@ impl StreamingState {
             }
         }
 
+        if let Some((class, field)) = DeltaClass::from_delta_type(delta_type) {
+            let text = delta[field].as_str().unwrap_or_default().to_string();
+            match self.delta_pipeline.lock().process(class, &text) {
+                Some(processed) if !processed.is_empty() => {
+                    delta[field] = json!(processed)
+                }
+                _ => return Bytes::new(),
+            }
+        }
+
         self.emit(
             "content_block_delta",
             json!({
@@ -396,6 +601,107 @@ impl StreamingState {
         )
     }
 
+    /// 发送 text_delta 事件
+    ///
+    /// 细碎 delta 的合并 (含客户端适配器要求的最小长度) 由 SSE 层的 delta_coalescer 统一处理
+    pub fn emit_text_delta(&mut self, text: &str) -> Vec<Bytes> {
+        if self.citations_enabled() {
+            self.citation_offsets.record_emitted(text);
+        }
+        vec![self.emit_delta("text_delta", json!({ "text": text }))]
+    }
+
+    /// 客户端是否支持行内引用
+    fn citations_enabled(&self) -> bool {
+        self.client_adapter
+            .as_ref()
+            .map_or(false, |a| a.supports_citations())
+    }
+
+    /// 是否追加联网搜索来源文本块 (未识别客户端时默认追加)
+    fn grounding_text_enabled(&self) -> bool {
+        self.client_adapter
+            .as_ref()
+            .map_or(true, |a| a.emits_grounding_text())
+    }
+
+    /// 记录上游答案文本 part (用于 groundingSupports 偏移映射及中断续写)
+    pub fn record_upstream_text(&mut self, text: &str) {
+        if self.citations_enabled() {
+            self.citation_offsets.record_upstream(text);
+        }
+        if let Some(transcript) = self.resume_transcript.as_mut() {
+            transcript.push_str(text);
+        }
+    }
+
+    /// 将 groundingSupports 转换为 citations_delta，附加在最后一个 text 块上
+    ///
+    /// 每个引用的 start/end_char_index 指向已发送的答案文本 (所有 text 块按序拼接)
+    fn emit_citations(&mut self) -> Vec<Bytes> {
+        if !self.citations_enabled() {
+            return vec![];
+        }
+        let (Some(supports), Some(sources)) = (self.grounding_supports.take(), self.grounding_chunks.as_ref()) else {
+            return vec![];
+        };
+
+        let mut citations = Vec::new();
+        for support in &supports {
+            let Some(segment) = support.get("segment") else { continue };
+            // proto3 默认值 0 可能被省略
+            let start = segment.get("startIndex").and_then(|v| v.as_u64()).unwrap_or(0) as usize;
+            let Some(end) = segment.get("endIndex").and_then(|v| v.as_u64()).map(|v| v as usize) else {
+                continue;
+            };
+            let segment_text = segment.get("text").and_then(|v| v.as_str());
+            let Some((start_char, end_char)) = self.citation_offsets.resolve_span(start, end, segment_text) else {
+                tracing::debug!("[Citations] Unable to map grounding segment {}..{}", start, end);
+                continue;
+            };
+            let cited_text: String = self
+                .citation_offsets
+                .emitted_text
+                .chars()
+                .skip(start_char)
+                .take(end_char - start_char)
+                .collect();
+
+            let indices = support
+                .get("groundingChunkIndices")
+                .and_then(|v| v.as_array())
+                .map(|arr| arr.iter().filter_map(|i| i.as_u64()).collect::<Vec<_>>())
+                .unwrap_or_default();
+            for idx in indices {
+                let Some(web) = sources.get(idx as usize).and_then(|c| c.get("web")) else {
+                    continue;
+                };
+                citations.push(json!({
+                    "type": "char_location",
+                    "cited_text": cited_text,
+                    "document_index": idx,
+                    "document_title": web.get("title").and_then(|v| v.as_str()).unwrap_or("网页来源"),
+                    "url": web.get("uri").and_then(|v| v.as_str()).unwrap_or("#"),
+                    "start_char_index": start_char,
+                    "end_char_index": end_char,
+                }));
+            }
+        }
+
+        if citations.is_empty() {
+            return vec![];
+        }
+
+        let mut chunks = Vec::new();
+        if self.block_type != BlockType::Text {
+            chunks.extend(self.start_block(BlockType::Text, json!({ "type": "text", "text": "" })));
+        }
+        for citation in citations {
+            chunks.push(self.emit_delta("citations_delta", json!({ "citation": citation })));
+        }
+        chunks
+    }
+
     /// 发送结束事件
     pub fn emit_finish(
         &mut self,
@@ -404,6 +710,32 @@ impl StreamingState {
     ) -> Vec<Bytes> {
         let mut chunks = Vec::new();
 
+        // [NEW] 流式参数尚未结束的工具调用: 已累积的参数按当前内容结束
+        if let Some((name, emitted_len)) = self
+            .streaming_tool_call
+            .as_ref()
+            .map(|call| (call.name.clone(), call.accumulator.emitted().len()))
+        {
+            record_truncated_tool_use(&name, TruncatedToolUseOutcome::Repaired, emitted_len);
+            chunks.extend(PartProcessor::new(self).finish_streaming_function_call());
+        }
+
+        // [NEW] 上游在输出工具参数途中断开: 修复截断的参数，无法修复时降级为文本块
+        chunks.extend(self.close_truncated_tool_use());
+
+        // [NEW] 未闭合的 MCP XML 内容按普通文本输出
+        if let Some(rest) = self.mcp_xml_parser.flush() {
+            if self.block_type != BlockType::Text {
+                chunks.extend(
+                    self.start_block(BlockType::Text, json!({ "type": "text", "text": "" })),
+                );
+            }
+            chunks.extend(self.emit_text_delta(&rest));
+        }
+
+        // [NEW] 行内引用附加在最后一个 text 块上
+        chunks.extend(self.emit_citations());
+
         // 关闭最后一个块
         chunks.extend(self.end_block());
 
@@ -421,37 +753,27 @@ impl StreamingState {
             // 不再追加 chunks.push(self.emit("content_block_start", ...))
         }
 
-        // 处理 grounding(web search) -> 转换为 Markdown 文本块
-        if self.web_search_query.is_some() || self.grounding_chunks.is_some() {
-            let mut grounding_text = String::new();
-
-            // 1. 处理搜索词
-            if let Some(query) = &self.web_search_query {
-                if !query.is_empty() {
-                    grounding_text.push_str("\n\n---\n**🔍 已为您搜索：** ");
-                    grounding_text.push_str(query);
-                }
-            }
-
-            // 2. 处理来源链接
-            if let Some(chunks) = &self.grounding_chunks {
-                let mut links = Vec::new();
-                for (i, chunk) in chunks.iter().enumerate() {
-                    if let Some(web) = chunk.get("web") {
-                        let title = web
-                            .get("title")
-                            .and_then(|v| v.as_str())
-                            .unwrap_or("网页来源");
-                        let uri = web.get("uri").and_then(|v| v.as_str()).unwrap_or("#");
-                        links.push(format!("[{}] [{}]({})", i + 1, title, uri));
-                    }
-                }
-
-                if !links.is_empty() {
-                    grounding_text.push_str("\n\n**🌐 来源引文：**\n");
-                    grounding_text.push_str(&links.join("\n"));
-                }
-            }
+        // 处理 grounding(web search) -> 按配置的引文样式转换为文本块 (客户端能力可关闭)
+        if (self.web_search_query.is_some() || self.grounding_chunks.is_some())
+            && self.grounding_text_enabled()
+        {
+            let sources: Vec<(usize, &str, &str)> = self
+                .grounding_chunks
+                .iter()
+                .flatten()
+                .enumerate()
+                .filter_map(|(i, chunk)| {
+                    let web = chunk.get("web")?;
+                    let title = web.get("title").and_then(|v| v.as_str()).unwrap_or("网页来源");
+                    let uri = web.get("uri").and_then(|v| v.as_str()).unwrap_or("#");
+                    Some((i + 1, title, uri))
+                })
+                .collect();
+            let grounding_text = render_grounding_text(
+                crate::proxy::config::get_citation_style(),
+                self.web_search_query.as_deref(),
+                &sources,
+            );
 
             if !grounding_text.is_empty() {
                 // 发送一个新的 text 块
@@ -473,7 +795,12 @@ impl StreamingState {
         }
 
         // 确定 stop_reason
-        let stop_reason = if self.used_tool {
+        let stop_sequence = self.delta_pipeline.lock().stop_sequence().map(str::to_string);
+        let stop_reason = if stop_sequence.is_some() {
+            "stop_sequence"
+        } else if self.force_end_turn {
+            "end_turn"
+        } else if self.used_tool {
             "tool_use"
         } else if finish_reason == Some("MAX_TOKENS") {
             "max_tokens"
@@ -481,7 +808,13 @@ impl StreamingState {
             "end_turn"
         };
 
-        let usage = usage_metadata
+        let current_usage = usage_metadata.cloned().or_else(|| self.last_usage_metadata.clone());
+        let usage_source = match (current_usage, self.carried_usage.take()) {
+            (Some(current), Some(carried)) => Some(add_carried_usage(current, &carried)),
+            (current, carried) => current.or(carried),
+        };
+        let mut usage = usage_source
+            .as_ref()
             .map(|u| {
                 // [FIX] Record actual token usage for calibrator learning
                 // Now properly pairs estimated tokens from request with actual tokens from response
@@ -502,20 +835,24 @@ impl StreamingState {
             })
             .unwrap_or(Usage {
                 input_tokens: 0,
-                output_tokens: 0,
+                // 上游未返回 usage 时使用已发送内容 (含思考) 的估算值
+                output_tokens: self.delta_pipeline.lock().estimated_output_tokens(),
                 cache_read_input_tokens: None,
                 cache_creation_input_tokens: None,
                 server_tool_use: None,
             });
+        usage.server_tool_use = web_search_usage(self.web_search_requests);
 
-        chunks.push(self.emit(
-            "message_delta",
-            json!({
-                "type": "message_delta",
-                "delta": { "stop_reason": stop_reason, "stop_sequence": null },
-                "usage": usage
-            }),
-        ));
+        let mut message_delta = json!({
+            "type": "message_delta",
+            "delta": { "stop_reason": stop_reason, "stop_sequence": stop_sequence },
+            "usage": usage
+        });
+        // [NEW] 上游思考 token 数 (扩展字段，供收集器做 token 拆分)
+        if let Some(thoughts) = usage_source.as_ref().and_then(|u| u.thoughts_token_count) {
+            message_delta[THOUGHTS_TOKENS_FIELD] = json!(thoughts);
+        }
+        chunks.push(self.emit("message_delta", message_delta));
 
         if !self.message_stop_sent {
             chunks.push(Bytes::from(
@@ -527,6 +864,46 @@ impl StreamingState {
         chunks
     }
 
+    /// 处理流结束时仍未闭合的工具调用 (其参数尚未发送给客户端)
+    fn close_truncated_tool_use(&mut self) -> Vec<Bytes> {
+        let Some((name, input, raw)) = self.mcp_xml_parser.take_open_call() else {
+            return vec![];
+        };
+
+        if let Some(args) = super::json_repair::repair_truncated_json(&input).filter(|v| v.is_object()) {
+            tracing::warn!(
+                "[Streaming] Repaired truncated tool_use input for {} ({} bytes)",
+                name,
+                input.len()
+            );
+            record_truncated_tool_use(&name, TruncatedToolUseOutcome::Repaired, input.len());
+            let fc = FunctionCall {
+                name,
+                args: Some(args),
+                ..Default::default()
+            };
+            return PartProcessor::new(self).process_function_call(&fc, None);
+        }
+
+        tracing::warn!(
+            "[Streaming] Truncated tool_use input for {} could not be repaired, sending as text",
+            name
+        );
+        record_truncated_tool_use(&name, TruncatedToolUseOutcome::ConvertedToText, input.len());
+        self.force_end_turn = true;
+
+        let mut chunks = Vec::new();
+        if self.block_type != BlockType::Text {
+            chunks.extend(self.start_block(BlockType::Text, json!({ "type": "text", "text": "" })));
+        }
+        chunks.extend(self.emit_text_delta(&raw));
+        chunks.extend(self.emit_text_delta(&format!(
+            "\n\n[System] The upstream stream ended while generating arguments for tool `{}`; the incomplete call above was not executed.",
+            name
+        )));
+        chunks
+    }
+
     /// 标记使用了工具
     pub fn mark_tool_used(&mut self) {
         self.used_tool = true;
@@ -547,6 +924,54 @@ impl StreamingState {
         self.signatures.store(signature);
     }
 
+    /// 记录当前 thinking 块的文本
+    fn record_thinking_text(&mut self, text: &str) {
+        self.thinking_text.push_str(text);
+        if let Some(transcript) = self.thinking_transcript.as_mut() {
+            transcript.push_str(text);
+        }
+    }
+
+    /// [NEW] 处理在 thinking 内容之后才到达的签名
+    /// - thinking 块仍未关闭: 立即以 signature_delta 发送，返回 Some
+    /// - thinking 块已关闭且未携带签名: 客户端已无法收到该签名，按会话 + 块哈希缓存，
+    ///   下一轮请求回传该 thinking 块时据此恢复签名；返回 None
+    pub fn attach_late_signature(&mut self, signature: &str) -> Option<Vec<Bytes>> {
+        if self.thinking_signed || self.thinking_text.is_empty() {
+            return None;
+        }
+        self.thinking_signed = true;
+
+        if self.block_type == BlockType::Thinking {
+            // 以本次签名为准，丢弃块内暂存的旧签名
+            self.signatures.consume();
+            tracing::debug!(
+                "[Streaming] Late signature attached to open thinking block (len: {})",
+                signature.len()
+            );
+            return Some(vec![self.emit_delta("signature_delta", json!({ "signature": signature }))]);
+        }
+
+        match &self.session_id {
+            Some(session_id) => {
+                tracing::info!(
+                    "[Streaming] Signature arrived after thinking block closed (len: {}), caching by block hash",
+                    signature.len()
+                );
+                let cache = SignatureCache::global();
+                cache.cache_thinking_block_signature(session_id, &self.thinking_text, signature.to_string());
+                cache.cache_session_signature(session_id, signature.to_string(), self.message_count);
+                if let Some(model) = &self.model_name {
+                    cache.cache_thinking_family(signature.to_string(), model.clone());
+                }
+            }
+            None => tracing::warn!(
+                "[Streaming] Signature arrived after thinking block closed, but no session id to cache it"
+            ),
+        }
+        None
+    }
+
     /// 设置 trailing signature
     pub fn set_trailing_signature(&mut self, signature: Option<String>) {
         self.trailing_signature = signature;
@@ -637,6 +1062,77 @@ pub struct PartProcessor<'a> {
     state: &'a mut StreamingState,
 }
 
+/// [NEW] 追问成功后合并两次请求的 usage: 输出与思考 token 相加，输入 / 缓存沿用首个请求
+/// (追问请求的输入包含原始上下文与已计入输出的思考内容，不重复计入)
+fn add_carried_usage(current: UsageMetadata, carried: &UsageMetadata) -> UsageMetadata {
+    let add = |a: Option<u32>, b: Option<u32>| match (a, b) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(0).saturating_add(b.unwrap_or(0))),
+    };
+    // 追问请求的 total 包含其输入，只累加其中的输出部分，与沿用的 prompt_token_count 保持一致
+    let current_output_total = current
+        .total_token_count
+        .map(|total| total.saturating_sub(current.prompt_token_count.unwrap_or(0)));
+    UsageMetadata {
+        prompt_token_count: carried.prompt_token_count.or(current.prompt_token_count),
+        candidates_token_count: add(current.candidates_token_count, carried.candidates_token_count),
+        total_token_count: add(current_output_total, carried.total_token_count),
+        cached_content_token_count: carried
+            .cached_content_token_count
+            .or(current.cached_content_token_count),
+        thoughts_token_count: add(current.thoughts_token_count, carried.thoughts_token_count),
+    }
+}
+
+/// [NEW] 按引文样式渲染联网搜索的搜索词与来源
+/// `sources` 为 (编号, 标题, 链接)，编号对应 groundingChunks 中的位置 (从 1 开始)
+pub fn render_grounding_text(
+    style: CitationStyle,
+    query: Option<&str>,
+    sources: &[(usize, &str, &str)],
+) -> String {
+    let query = query.filter(|q| !q.is_empty());
+    let mut text = String::new();
+    match style {
+        CitationStyle::Off => {}
+        CitationStyle::Markdown => {
+            if let Some(query) = query {
+                text.push_str("\n\n---\n**🔍 已为您搜索：** ");
+                text.push_str(query);
+            }
+            if !sources.is_empty() {
+                let links: Vec<String> = sources
+                    .iter()
+                    .map(|(n, title, uri)| format!("[{}] [{}]({})", n, title, uri))
+                    .collect();
+                text.push_str("\n\n**🌐 来源引文：**\n");
+                text.push_str(&links.join("\n"));
+            }
+        }
+        CitationStyle::Footnotes => {
+            if !sources.is_empty() {
+                let notes: Vec<String> = sources
+                    .iter()
+                    .map(|(n, title, uri)| format!("[^{}]: [{}]({})", n, title, uri))
+                    .collect();
+                text.push_str("\n\n");
+                text.push_str(&notes.join("\n"));
+            }
+        }
+        CitationStyle::InlineLinks => {
+            if !sources.is_empty() {
+                let links: Vec<String> = sources
+                    .iter()
+                    .map(|(_, title, uri)| format!("[{}]({})", title, uri))
+                    .collect();
+                text.push_str("\n\nSources: ");
+                text.push_str(&links.join(", "));
+            }
+        }
+    }
+    text
+}
+
 impl<'a> PartProcessor<'a> {
     pub fn new(state: &'a mut StreamingState) -> Self {
         Self { state }
@@ -645,6 +1141,11 @@ impl<'a> PartProcessor<'a> {
     /// 处理单个 part
     pub fn process(&mut self, part: &GeminiPart) -> Vec<Bytes> {
         let mut chunks = Vec::new();
+
+        // [NEW] 流式参数的工具调用被其他内容打断时先结束该调用
+        if part.function_call.is_none() && self.state.streaming_tool_call.is_some() {
+            chunks.extend(self.finish_streaming_function_call());
+        }
         // [FIX #545] Decode Base64 signature if present (Gemini sends Base64, Claude expects Raw)
         let signature = part.thought_signature.as_ref().map(|sig| {
             // Try to decode as base64
@@ -670,30 +1171,13 @@ impl<'a> PartProcessor<'a> {
         // 1. FunctionCall 处理
         if let Some(fc) = &part.function_call {
             // 先处理 trailingSignature (B4/C3 场景)
-            if self.state.has_trailing_signature() {
-                chunks.extend(self.state.end_block());
-                if let Some(trailing_sig) = self.state.trailing_signature.take() {
-                    chunks.push(self.state.emit(
-                        "content_block_start",
-                        json!({
-                            "type": "content_block_start",
-                            "index": self.state.current_block_index(),
-                            "content_block": { "type": "thinking", "thinking": "" }
-                        }),
-                    ));
-                    chunks.push(
-                        self.state
-                            .emit_delta("thinking_delta", json!({ "thinking": "" })),
-                    );
-                    chunks.push(
-                        self.state
-                            .emit_delta("signature_delta", json!({ "signature": trailing_sig })),
-                    );
-                    chunks.extend(self.state.end_block());
-                }
-            }
+            chunks.extend(self.flush_trailing_signature());
 
-            chunks.extend(self.process_function_call(fc, signature));
+            if fc.is_streaming() || self.state.streaming_tool_call.is_some() {
+                chunks.extend(self.process_streaming_function_call(fc, signature));
+            } else {
+                chunks.extend(self.process_function_call(fc, signature));
+            }
             // [FIX #859] Mark that we have received actual content (tool use)
             self.state.has_content = true;
             return chunks;
@@ -712,14 +1196,73 @@ impl<'a> PartProcessor<'a> {
 
         // 3. InlineData (Image) 处理
         if let Some(img) = &part.inline_data {
-            let mime_type = &img.mime_type;
-            let data = &img.data;
-            if !data.is_empty() {
-                let markdown_img = format!("![image](data:{};base64,{})", mime_type, data);
-                chunks.extend(self.process_text(&markdown_img, None));
-            }
+            chunks.extend(self.process_inline_data(img));
+        }
+
+        // 4. [NEW] executableCode / codeExecutionResult: 按原顺序渲染为文本
+        if let Some(rendered) = crate::proxy::mappers::common_utils::render_code_execution_part(
+            part.executable_code.as_ref(),
+            part.code_execution_result.as_ref(),
+        ) {
+            chunks.extend(self.process_text(&rendered, None));
+        }
+
+        chunks
+    }
+
+    /// [NEW] 处理图片输出 (图像生成结果)
+    /// 以独立的 image 内容块返回: content_block_start 携带完整 base64 数据，随即 content_block_stop
+    fn process_inline_data(&mut self, img: &InlineData) -> Vec<Bytes> {
+        let mut chunks = Vec::new();
+        if img.data.is_empty() {
+            return chunks;
         }
 
+        // 先补发暂存的 trailingSignature，避免签名挂到图片块之后
+        chunks.extend(self.flush_trailing_signature());
+
+        self.state.has_content = true;
+        chunks.extend(self.state.start_block(
+            BlockType::Image,
+            json!({
+                "type": "image",
+                "source": {
+                    "type": "base64",
+                    "media_type": img.mime_type,
+                    "data": img.data
+                }
+            }),
+        ));
+        chunks.extend(self.state.end_block());
+        chunks
+    }
+
+    /// 将暂存的 trailingSignature 作为空 thinking 块发送
+    fn flush_trailing_signature(&mut self) -> Vec<Bytes> {
+        let mut chunks = Vec::new();
+        if !self.state.has_trailing_signature() {
+            return chunks;
+        }
+        chunks.extend(self.state.end_block());
+        if let Some(trailing_sig) = self.state.trailing_signature.take() {
+            chunks.push(self.state.emit(
+                "content_block_start",
+                json!({
+                    "type": "content_block_start",
+                    "index": self.state.current_block_index(),
+                    "content_block": { "type": "thinking", "thinking": "" }
+                }),
+            ));
+            chunks.push(
+                self.state
+                    .emit_delta("thinking_delta", json!({ "thinking": "" })),
+            );
+            chunks.push(
+                self.state
+                    .emit_delta("signature_delta", json!({ "signature": trailing_sig })),
+            );
+            chunks.extend(self.state.end_block());
+        }
         chunks
     }
 
@@ -728,26 +1271,14 @@ impl<'a> PartProcessor<'a> {
         let mut chunks = Vec::new();
 
         // 处理之前的 trailingSignature
-        if self.state.has_trailing_signature() {
-            chunks.extend(self.state.end_block());
-            if let Some(trailing_sig) = self.state.trailing_signature.take() {
-                chunks.push(self.state.emit(
-                    "content_block_start",
-                    json!({
-                        "type": "content_block_start",
-                        "index": self.state.current_block_index(),
-                        "content_block": { "type": "thinking", "thinking": "" }
-                    }),
-                ));
-                chunks.push(
-                    self.state
-                        .emit_delta("thinking_delta", json!({ "thinking": "" })),
-                );
-                chunks.push(
-                    self.state
-                        .emit_delta("signature_delta", json!({ "signature": trailing_sig })),
-                );
-                chunks.extend(self.state.end_block());
+        chunks.extend(self.flush_trailing_signature());
+
+        // [NEW] 仅携带签名的空 thinking chunk: 签名晚于内容到达，附加到当前块 (或按块哈希缓存)
+        let mut late_attached = false;
+        if let Some(sig) = signature.as_deref().filter(|_| text.is_empty()) {
+            if let Some(sig_chunks) = self.state.attach_late_signature(sig) {
+                chunks.extend(sig_chunks);
+                late_attached = true;
             }
         }
 
@@ -767,6 +1298,7 @@ impl<'a> PartProcessor<'a> {
                 self.state
                     .emit_delta("thinking_delta", json!({ "thinking": text })),
             );
+            self.state.record_thinking_text(text);
         }
 
         // [NEW] Apply Client Adapter Strategy
@@ -811,15 +1343,25 @@ impl<'a> PartProcessor<'a> {
         // If FIFO, we strictly follow the sequence. The default logic is effectively LIFO for a single turn 
         // (store latest, consume at end). 
         // For opencode, we just want to ensure we capture IT.
-        self.state.store_signature(signature);
+        if !late_attached {
+            self.state.store_signature(signature);
+        }
 
         chunks
     }
 
     /// 处理普通 Text
-    fn process_text(&mut self, text: &str, signature: Option<String>) -> Vec<Bytes> {
+    fn process_text(&mut self, text: &str, mut signature: Option<String>) -> Vec<Bytes> {
         let mut chunks = Vec::new();
 
+        // [NEW] 签名晚于 thinking 内容到达 (落在随后的 text part 上): thinking 块未关闭时直接附加
+        if let Some(sig) = signature.clone() {
+            if let Some(sig_chunks) = self.state.attach_late_signature(&sig) {
+                chunks.extend(sig_chunks);
+                signature = None;
+            }
+        }
+
         // 空 text 带签名 - 暂存
         if text.is_empty() {
             if signature.is_some() {
@@ -830,30 +1372,10 @@ impl<'a> PartProcessor<'a> {
 
         // [FIX #859] Mark that we have received actual content (text)
         self.state.has_content = true;
+        self.state.record_upstream_text(text);
 
         // 处理之前的 trailingSignature
-        if self.state.has_trailing_signature() {
-            chunks.extend(self.state.end_block());
-            if let Some(trailing_sig) = self.state.trailing_signature.take() {
-                chunks.push(self.state.emit(
-                    "content_block_start",
-                    json!({
-                        "type": "content_block_start",
-                        "index": self.state.current_block_index(),
-                        "content_block": { "type": "thinking", "thinking": "" }
-                    }),
-                ));
-                chunks.push(
-                    self.state
-                        .emit_delta("thinking_delta", json!({ "thinking": "" })),
-                );
-                chunks.push(
-                    self.state
-                        .emit_delta("signature_delta", json!({ "signature": trailing_sig })),
-                );
-                chunks.extend(self.state.end_block());
-            }
-        }
+        chunks.extend(self.flush_trailing_signature());
 
         // 非空 text 带签名 - 立即处理
         if signature.is_some() {
@@ -866,7 +1388,7 @@ impl<'a> PartProcessor<'a> {
                 self.state
                     .start_block(BlockType::Text, json!({ "type": "text", "text": "" })),
             );
-            chunks.push(self.state.emit_delta("text_delta", json!({ "text": text })));
+            chunks.extend(self.state.emit_text_delta(text));
             chunks.extend(self.state.end_block());
 
             return chunks;
@@ -874,84 +1396,29 @@ impl<'a> PartProcessor<'a> {
 
         // Ordinary text (without signature)
 
-        // [NEW] MCP XML Bridge: Intercept and parse <mcp__...> tags
-        if text.contains("<mcp__") || self.state.in_mcp_xml {
-            self.state.in_mcp_xml = true;
-            self.state.mcp_xml_buffer.push_str(text);
-
-            // Check if we have a complete tag in the buffer
-            if self.state.mcp_xml_buffer.contains("</mcp__")
-                && self.state.mcp_xml_buffer.contains('>')
-            {
-                let buffer = self.state.mcp_xml_buffer.clone();
-                if let Some(start_idx) = buffer.find("<mcp__") {
-                    if let Some(tag_end_idx) = buffer[start_idx..].find('>') {
-                        let actual_tag_end = start_idx + tag_end_idx;
-                        let tool_name = &buffer[start_idx + 1..actual_tag_end];
-                        let end_tag = format!("</{}>", tool_name);
-
-                        if let Some(close_idx) = buffer.find(&end_tag) {
-                            let input_str = &buffer[actual_tag_end + 1..close_idx];
-                            let input_json: serde_json::Value =
-                                serde_json::from_str(input_str.trim())
-                                    .unwrap_or_else(|_| json!({ "input": input_str.trim() }));
-
-                            // 构造并发送 tool_use
-                            let fc = FunctionCall {
-                                name: tool_name.to_string(),
-                                args: Some(input_json),
-                                id: Some(format!("{}-xml", tool_name)),
-                            };
-
-                            let tool_chunks = self.process_function_call(&fc, None);
-
-                            // 清理缓冲区并重置状态
-                            self.state.mcp_xml_buffer.clear();
-                            self.state.in_mcp_xml = false;
-
-                            // 处理标签之前可能存在的非 XML 文本
-                            if start_idx > 0 {
-                                let prefix_text = &buffer[..start_idx];
-                                // 这里不能递归。直接 emit 之前的 text 块。
-                                if self.state.current_block_type() != BlockType::Text {
-                                    chunks.extend(self.state.start_block(
-                                        BlockType::Text,
-                                        json!({ "type": "text", "text": "" }),
-                                    ));
-                                }
-                                chunks.push(
-                                    self.state
-                                        .emit_delta("text_delta", json!({ "text": prefix_text })),
-                                );
-                            }
-
-                            chunks.extend(tool_chunks);
-
-                            // 处理标签之后可能存在的非 XML 文本
-                            let suffix = &buffer[close_idx + end_tag.len()..];
-                            if !suffix.is_empty() {
-                                // 递归处理后缀内容
-                                chunks.extend(self.process_text(suffix, None));
-                            }
-
-                            return chunks;
-                        }
+        // [NEW] MCP XML Bridge: 将 <mcp__...> 标签还原为 tool_use (支持跨 chunk)
+        for event in self.state.mcp_xml_parser.feed(text) {
+            match event {
+                McpXmlEvent::Text(segment) => {
+                    if self.state.current_block_type() != BlockType::Text {
+                        chunks.extend(
+                            self.state
+                                .start_block(BlockType::Text, json!({ "type": "text", "text": "" })),
+                        );
                     }
+                    chunks.extend(self.state.emit_text_delta(&segment));
+                }
+                McpXmlEvent::ToolCall { name, input } => {
+                    let fc = FunctionCall {
+                        name,
+                        args: Some(input),
+                        ..Default::default()
+                    };
+                    chunks.extend(self.process_function_call(&fc, None));
                 }
             }
-            // While in XML, don't emit text deltas
-            return vec![];
         }
 
-        if self.state.current_block_type() != BlockType::Text {
-            chunks.extend(
-                self.state
-                    .start_block(BlockType::Text, json!({ "type": "text", "text": "" })),
-            );
-        }
-
-        chunks.push(self.state.emit_delta("text_delta", json!({ "text": text })));
-
         chunks
     }
 
@@ -961,8 +1428,28 @@ impl<'a> PartProcessor<'a> {
         fc: &FunctionCall,
         signature: Option<String>,
     ) -> Vec<Bytes> {
-        let mut chunks = Vec::new();
+        let mut chunks = self.start_tool_use_block(fc, signature);
+
+        // 2. 发送 input_json_delta (完整的参数 JSON 字符串)
+        // [FIX] Remap args before serialization for Gemini → Claude compatibility
+        if fc.args.is_some() {
+            let remapped_args = self.finalize_tool_args(fc.args.as_ref(), &fc.name);
+            let json_str =
+                serde_json::to_string(&remapped_args).unwrap_or_else(|_| "{}".to_string());
+            chunks.push(
+                self.state
+                    .emit_delta("input_json_delta", json!({ "partial_json": json_str })),
+            );
+        }
+
+        // 3. 结束块
+        chunks.extend(self.state.end_block());
+
+        chunks
+    }
 
+    /// 发送 tool_use 的 content_block_start (input 为空对象，参数通过 delta 发送)
+    fn start_tool_use_block(&mut self, fc: &FunctionCall, signature: Option<String>) -> Vec<Bytes> {
         self.state.mark_tool_used();
 
         let tool_id = fc.id.clone().unwrap_or_else(|| {
@@ -1008,34 +1495,129 @@ impl<'a> PartProcessor<'a> {
             );
         }
 
-        chunks.extend(self.state.start_block(BlockType::Function, tool_use));
+        self.state.start_block(BlockType::Function, tool_use)
+    }
 
-        // 2. 发送 input_json_delta (完整的参数 JSON 字符串)
-        // [FIX] Remap args before serialization for Gemini → Claude compatibility
-        if let Some(args) = &fc.args {
-            let mut remapped_args = args.clone();
-
-            let tool_name_title = fc.name.clone();
-            // [OPTIMIZED] Only rename if it's "search" which is a known hallucination.
-            // Avoid renaming "grep" to "Grep" if possible to protect signature,
-            // unless we're sure Grep is the standard.
-            let mut final_tool_name = tool_name_title;
-            if final_tool_name.to_lowercase() == "search" {
-                final_tool_name = "Grep".to_string();
-            }
-            remap_function_call_args(&final_tool_name, &mut remapped_args);
+    /// 规范化工具参数 (字符串形式的 JSON、schema 类型修正) 并重映射参数名
+    fn finalize_tool_args(&self, args: Option<&Value>, name: &str) -> Value {
+        // [NEW] args 可能以 JSON 字符串返回，先规范化为对象并按 schema 修正类型
+        let mut remapped_args = crate::proxy::mappers::common_utils::normalize_function_call_args(
+            args,
+            name,
+            self.state.tool_schemas.get(name),
+        );
 
-            let json_str =
-                serde_json::to_string(&remapped_args).unwrap_or_else(|_| "{}".to_string());
+        // [OPTIMIZED] Only rename if it's "search" which is a known hallucination.
+        // Avoid renaming "grep" to "Grep" if possible to protect signature,
+        // unless we're sure Grep is the standard.
+        let mut final_tool_name = name.to_string();
+        if final_tool_name.to_lowercase() == "search" {
+            final_tool_name = "Grep".to_string();
+        }
+        remap_function_call_args(&final_tool_name, &mut remapped_args);
+        remapped_args
+    }
+
+    /// [NEW] 处理参数分多个 chunk 到达的 functionCall (partialArgs + willContinue)
+    /// 参数片段到达时即发送 input_json_delta；规范化 / 重映射会改变已累积的参数时暂缓发送，
+    /// 调用结束后再补齐剩余部分
+    fn process_streaming_function_call(
+        &mut self,
+        fc: &FunctionCall,
+        signature: Option<String>,
+    ) -> Vec<Bytes> {
+        let mut chunks = Vec::new();
+
+        // 带有不同 id 的新调用: 先结束上一个
+        let is_new_call = self
+            .state
+            .streaming_tool_call
+            .as_ref()
+            .map_or(false, |call| {
+                fc.id.is_some() && call.id.is_some() && fc.id != call.id
+            });
+        if is_new_call {
+            chunks.extend(self.finish_streaming_function_call());
+        }
+
+        if self.state.streaming_tool_call.is_none() {
+            chunks.extend(self.start_tool_use_block(fc, signature));
+            self.state.streaming_tool_call = Some(StreamingToolCall {
+                id: fc.id.clone(),
+                name: fc.name.clone(),
+                accumulator: PartialArgsAccumulator::new(),
+            });
+        }
+
+        let Some(call) = self.state.streaming_tool_call.as_mut() else {
+            return chunks;
+        };
+        for fragment in fc.partial_args.iter().flatten() {
+            call.accumulator.apply(fragment);
+        }
+        let (raw, name) = (call.accumulator.args().clone(), call.name.clone());
+
+        // 仅在规范化 / 重映射不改变已累积参数时发送，保证已发送前缀与最终参数一致
+        let delta = if self.finalize_tool_args(Some(&raw), &name) == raw {
+            self.state
+                .streaming_tool_call
+                .as_mut()
+                .and_then(|call| call.accumulator.take_delta())
+        } else {
+            None
+        };
+        if let Some(partial_json) = delta {
             chunks.push(
                 self.state
-                    .emit_delta("input_json_delta", json!({ "partial_json": json_str })),
+                    .emit_delta("input_json_delta", json!({ "partial_json": partial_json })),
             );
         }
 
-        // 3. 结束块
-        chunks.extend(self.state.end_block());
+        if fc.will_continue != Some(true) {
+            chunks.extend(self.finish_streaming_function_call());
+        }
+        chunks
+    }
+
+    /// [NEW] 结束流式参数的工具调用: 补齐最终参数 JSON 的剩余部分并关闭块
+    fn finish_streaming_function_call(&mut self) -> Vec<Bytes> {
+        let Some(mut call) = self.state.streaming_tool_call.take() else {
+            return vec![];
+        };
+        let mut chunks = Vec::new();
+
+        let raw = call.accumulator.args().clone();
+        let to_json = |v: &Value| serde_json::to_string(v).unwrap_or_else(|_| "{}".to_string());
+        let final_json = to_json(&self.finalize_tool_args(Some(&raw), &call.name));
+        let raw_json = to_json(&raw);
+        // 已发送的前缀一定可以闭合为合法 JSON (闭合字符串与括号)
+        let closed_json = super::json_repair::repair_truncated_json(call.accumulator.emitted())
+            .map(|v| to_json(&v))
+            .unwrap_or_default();
+
+        let candidates = [final_json.as_str(), raw_json.as_str(), closed_json.as_str()];
+        match call.accumulator.finish(&candidates) {
+            Some((used, rest)) => {
+                if used > 0 {
+                    tracing::warn!(
+                        "[Streaming] Streamed tool_use input for {} diverged after normalization, completing without it",
+                        call.name
+                    );
+                }
+                if !rest.is_empty() {
+                    chunks.push(
+                        self.state
+                            .emit_delta("input_json_delta", json!({ "partial_json": rest })),
+                    );
+                }
+            }
+            None => tracing::error!(
+                "[Streaming] Streamed tool_use input for {} cannot be completed as valid JSON",
+                call.name
+            ),
+        }
 
+        chunks.extend(self.state.end_block());
         chunks
     }
 }
@@ -1067,6 +1649,93 @@ mod tests {
         assert!(s.contains("\"foo\":\"bar\""));
     }
 
+    #[test]
+    fn test_strip_internal_fields_keeps_other_events() {
+        let state = StreamingState::new();
+        let mut delta = json!({
+            "type": "message_delta",
+            "delta": { "stop_reason": "end_turn", "stop_sequence": null },
+            "usage": { "input_tokens": 10, "output_tokens": 5 }
+        });
+        delta[THOUGHTS_TOKENS_FIELD] = json!(42);
+        let mut chunk = state.emit("message_delta", delta).to_vec();
+        chunk.extend_from_slice(b"event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n");
+
+        let stripped = String::from_utf8(strip_internal_fields(Bytes::from(chunk)).to_vec()).unwrap();
+        assert!(!stripped.contains(THOUGHTS_TOKENS_FIELD));
+        assert!(stripped.contains("\"output_tokens\":5"));
+        assert!(stripped.ends_with("event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n"));
+
+        let plain = state.emit("ping", json!({ "type": "ping" }));
+        assert_eq!(strip_internal_fields(plain.clone()), plain);
+    }
+
+    #[test]
+    fn test_message_start_without_model_version_or_response_id() {
+        let mut state = StreamingState::new();
+        state.requested_model = Some("claude-sonnet-4-5".to_string());
+        let chunk = json!({
+            "candidates": [{ "content": { "role": "model", "parts": [{ "text": "hi" }] } }],
+            "usageMetadata": { "promptTokenCount": 10, "candidatesTokenCount": 1 }
+        });
+
+        let bytes = state.emit_message_start(&chunk);
+        let s = String::from_utf8(bytes.to_vec()).unwrap();
+        let data = s
+            .lines()
+            .find_map(|l| l.strip_prefix("data: "))
+            .expect("message_start data line");
+        let event: serde_json::Value = serde_json::from_str(data).unwrap();
+
+        assert_eq!(event["type"], "message_start");
+        let message = &event["message"];
+        let id = message["id"].as_str().unwrap();
+        assert!(id.starts_with("msg_") && id.len() > "msg_".len());
+        assert_eq!(message["model"], "claude-sonnet-4-5");
+        assert_eq!(message["usage"]["input_tokens"], 10);
+        assert!(state.model_name.is_none());
+    }
+
+    #[test]
+    fn test_stop_sequence_only_applies_to_visible_deltas() {
+        let mut state = StreamingState::new();
+        state.set_delta_pipeline(DeltaPipeline::new(vec!["STOP".to_string()]));
+
+        let thought = state.emit_delta("thinking_delta", json!({ "thinking": "should I STOP?" }));
+        assert!(String::from_utf8(thought.to_vec()).unwrap().contains("should I STOP?"));
+
+        let text = state.emit_delta("text_delta", json!({ "text": "answer STOP tail" }));
+        let text = String::from_utf8(text.to_vec()).unwrap();
+        assert!(text.contains("\"text\":\"answer \""));
+        assert!(state.emit_delta("text_delta", json!({ "text": "more" })).is_empty());
+
+        let finish = state.emit_finish(None, None);
+        let finish: String = finish.iter().map(|b| String::from_utf8(b.to_vec()).unwrap()).collect();
+        assert!(finish.contains("\"stop_reason\":\"stop_sequence\""));
+        assert!(finish.contains("\"stop_sequence\":\"STOP\""));
+    }
+
+    #[test]
+    fn test_partial_stop_sequence_is_held_until_block_end() {
+        let mut state = StreamingState::new();
+        state.set_delta_pipeline(DeltaPipeline::new(vec!["END".to_string()]));
+        state.start_block(BlockType::Text, json!({ "type": "text", "text": "" }));
+
+        let first = String::from_utf8(state.emit_delta("text_delta", json!({ "text": "the E" })).to_vec()).unwrap();
+        assert!(first.contains("\"text\":\"the \""));
+        // 仍可能是停止序列的开头: 不发送空 delta
+        assert!(state.emit_delta("text_delta", json!({ "text": "N" })).is_empty());
+
+        let end: String = state
+            .end_block()
+            .iter()
+            .map(|b| String::from_utf8(b.to_vec()).unwrap())
+            .collect();
+        assert!(end.contains("\"text\":\"EN\""));
+        assert!(end.contains("content_block_stop"));
+        assert_eq!(state.delta_pipeline.lock().stop_sequence(), None);
+    }
+
     #[test]
     fn test_process_function_call_deltas() {
         let mut state = StreamingState::new();
@@ -1076,6 +1745,7 @@ mod tests {
             name: "test_tool".to_string(),
             args: Some(json!({"arg": "value"})),
             id: Some("call_123".to_string()),
+            ..Default::default()
         };
 
         // Create a dummy GeminiPart with function_call
@@ -1086,6 +1756,8 @@ mod tests {
             thought: None,
             thought_signature: None,
             function_response: None,
+            executable_code: None,
+            code_execution_result: None,
         };
 
         let chunks = processor.process(&part);
@@ -1110,4 +1782,595 @@ mod tests {
         // 3. content_block_stop
         assert!(output.contains(r#""type":"content_block_stop""#));
     }
+
+    #[test]
+    fn test_chunked_mcp_xml_emits_tool_use_events() {
+        let mut state = StreamingState::new();
+        let mut processor = PartProcessor::new(&mut state);
+
+        let text_part = |text: &str| GeminiPart {
+            text: Some(text.to_string()),
+            function_call: None,
+            inline_data: None,
+            thought: None,
+            thought_signature: None,
+            function_response: None,
+            executable_code: None,
+            code_execution_result: None,
+        };
+
+        let mut output = String::new();
+        for chunk in ["Checking.\n<mcp__", "search__query>{\"q\":\"ru", "st\"}</mcp__search__query>"] {
+            for bytes in processor.process(&text_part(chunk)) {
+                output.push_str(&String::from_utf8(bytes.to_vec()).unwrap());
+            }
+        }
+
+        let events: Vec<Value> = output
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .filter_map(|d| serde_json::from_str(d).ok())
+            .collect();
+
+        let tool_start = events
+            .iter()
+            .find(|e| e["type"] == "content_block_start" && e["content_block"]["type"] == "tool_use")
+            .expect("tool_use block not emitted");
+        assert_eq!(tool_start["content_block"]["name"], "mcp__search__query");
+
+        let input_delta = events
+            .iter()
+            .find(|e| e["delta"]["type"] == "input_json_delta")
+            .expect("input_json_delta not emitted");
+        assert_eq!(input_delta["delta"]["partial_json"], r#"{"q":"rust"}"#);
+
+        // 原始 XML 不应作为文本泄露给客户端
+        let text: String = events
+            .iter()
+            .filter(|e| e["delta"]["type"] == "text_delta")
+            .map(|e| e["delta"]["text"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(text, "Checking.\n");
+        assert!(state.used_tool);
+    }
+
+    #[test]
+    fn test_truncated_tool_use_input_repaired_or_converted_on_interruption() {
+        use crate::proxy::mappers::claude::diagnostics::recent_truncated_tool_uses;
+
+        // 上游在输出工具参数途中断开，随后强制结束
+        let run = |chunks: &[&str]| -> Vec<Value> {
+            let mut state = StreamingState::new();
+            let mut output = String::new();
+            {
+                let mut processor = PartProcessor::new(&mut state);
+                for chunk in chunks {
+                    let part = GeminiPart {
+                        text: Some(chunk.to_string()),
+                        function_call: None,
+                        inline_data: None,
+                        thought: None,
+                        thought_signature: None,
+                        function_response: None,
+                        executable_code: None,
+                        code_execution_result: None,
+                    };
+                    for bytes in processor.process(&part) {
+                        output.push_str(&String::from_utf8(bytes.to_vec()).unwrap());
+                    }
+                }
+            }
+            for bytes in state.emit_finish(None, None) {
+                output.push_str(&String::from_utf8(bytes.to_vec()).unwrap());
+            }
+            output
+                .lines()
+                .filter_map(|l| l.strip_prefix("data: "))
+                .filter_map(|d| serde_json::from_str(d).ok())
+                .collect()
+        };
+        let tool_input = |events: &[Value]| -> Value {
+            let delta = events
+                .iter()
+                .find(|e| e["delta"]["type"] == "input_json_delta")
+                .expect("input_json_delta not emitted");
+            serde_json::from_str(delta["delta"]["partial_json"].as_str().unwrap()).unwrap()
+        };
+        let stop_reason = |events: &[Value]| -> Value {
+            events
+                .iter()
+                .find(|e| e["type"] == "message_delta")
+                .map(|e| e["delta"]["stop_reason"].clone())
+                .unwrap()
+        };
+
+        // 1. 字符串中间截断
+        let events = run(&["Writing.\n<mcp__fs__write_file>", r#"{"path": "a.txt", "content": "hello wo"#]);
+        assert_eq!(tool_input(&events), json!({ "path": "a.txt", "content": "hello wo" }));
+        assert_eq!(stop_reason(&events), "tool_use");
+
+        // 2. 对象键之间截断
+        let events = run(&[r#"<mcp__shell__run>{"command": "ls", "cw"#]);
+        assert_eq!(tool_input(&events), json!({ "command": "ls" }));
+
+        // 3. 数组中间截断
+        let events = run(&[r#"<mcp__fs__read_many>{"paths": ["a.rs", "b.rs", "#]);
+        assert_eq!(tool_input(&events), json!({ "paths": ["a.rs", "b.rs"] }));
+
+        // 4. 无法修复: 以文本块发送原始片段并以 end_turn 结束
+        let events = run(&[r#"<mcp__fs__truncated_probe>{"file_pa"#]);
+        assert!(!events.iter().any(|e| e["content_block"]["type"] == "tool_use"));
+        let text: String = events
+            .iter()
+            .filter(|e| e["delta"]["type"] == "text_delta")
+            .map(|e| e["delta"]["text"].as_str().unwrap().to_string())
+            .collect();
+        assert!(text.starts_with(r#"<mcp__fs__truncated_probe>{"file_pa"#));
+        assert!(text.contains("[System]"));
+        assert_eq!(stop_reason(&events), "end_turn");
+
+        // 诊断记录中区分两种处理路径
+        let records = recent_truncated_tool_uses();
+        let outcome = |name: &str| records.iter().rev().find(|r| r.tool_name == name).map(|r| r.outcome);
+        assert_eq!(outcome("mcp__fs__write_file"), Some(TruncatedToolUseOutcome::Repaired));
+        assert_eq!(outcome("mcp__fs__truncated_probe"), Some(TruncatedToolUseOutcome::ConvertedToText));
+    }
+
+    #[test]
+    fn test_render_grounding_text_styles() {
+        let sources = [
+            (1, "Rust Blog", "https://blog.rust-lang.org"),
+            (2, "Docs", "https://doc.rust-lang.org"),
+        ];
+        let query = Some("rust release");
+
+        assert_eq!(
+            render_grounding_text(CitationStyle::Markdown, query, &sources),
+            "\n\n---\n**🔍 已为您搜索：** rust release\n\n**🌐 来源引文：**\n\
+             [1] [Rust Blog](https://blog.rust-lang.org)\n[2] [Docs](https://doc.rust-lang.org)"
+        );
+        assert_eq!(
+            render_grounding_text(CitationStyle::Footnotes, query, &sources),
+            "\n\n[^1]: [Rust Blog](https://blog.rust-lang.org)\n[^2]: [Docs](https://doc.rust-lang.org)"
+        );
+        assert_eq!(
+            render_grounding_text(CitationStyle::InlineLinks, query, &sources),
+            "\n\nSources: [Rust Blog](https://blog.rust-lang.org), [Docs](https://doc.rust-lang.org)"
+        );
+        assert_eq!(render_grounding_text(CitationStyle::Off, query, &sources), "");
+
+        // 无来源时仅 Markdown 样式输出搜索词
+        assert_eq!(
+            render_grounding_text(CitationStyle::Markdown, query, &[]),
+            "\n\n---\n**🔍 已为您搜索：** rust release"
+        );
+        assert_eq!(render_grounding_text(CitationStyle::Footnotes, query, &[]), "");
+    }
+
+    #[test]
+    fn test_code_execution_parts_rendered_in_order() {
+        let mut state = StreamingState::new();
+        let mut processor = PartProcessor::new(&mut state);
+
+        let parts: Vec<GeminiPart> = serde_json::from_value(json!([
+            { "text": "Let me compute." },
+            { "executableCode": { "language": "PYTHON", "code": "print(2 + 2)" } },
+            { "codeExecutionResult": { "outcome": "OUTCOME_OK", "output": "4" } },
+            { "text": "So the answer is 4." }
+        ]))
+        .unwrap();
+
+        let mut output = String::new();
+        for part in &parts {
+            for bytes in processor.process(part) {
+                output.push_str(&String::from_utf8(bytes.to_vec()).unwrap());
+            }
+        }
+
+        let text: String = output
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .filter_map(|d| serde_json::from_str::<Value>(d).ok())
+            .filter(|e| e["type"] == "content_block_delta" && e["delta"]["type"] == "text_delta")
+            .map(|e| e["delta"]["text"].as_str().unwrap_or_default().to_string())
+            .collect();
+        assert_eq!(
+            text,
+            "Let me compute.\n```python\nprint(2 + 2)\n```\n\n```output\n4\n```\nSo the answer is 4."
+        );
+    }
+
+    #[test]
+    fn test_text_and_image_parts_emit_separate_blocks() {
+        let mut state = StreamingState::new();
+        let mut processor = PartProcessor::new(&mut state);
+
+        let mut output = String::new();
+        let parts = [
+            GeminiPart {
+                text: Some("Here is your cat:".to_string()),
+                function_call: None,
+                inline_data: None,
+                thought: None,
+                thought_signature: None,
+                function_response: None,
+                executable_code: None,
+                code_execution_result: None,
+            },
+            GeminiPart {
+                text: None,
+                function_call: None,
+                inline_data: Some(InlineData {
+                    mime_type: "image/png".to_string(),
+                    data: "iVBORw0KGgo=".to_string(),
+                }),
+                thought: None,
+                thought_signature: None,
+                function_response: None,
+                executable_code: None,
+                code_execution_result: None,
+            },
+        ];
+        for part in &parts {
+            for bytes in processor.process(part) {
+                output.push_str(&String::from_utf8(bytes.to_vec()).unwrap());
+            }
+        }
+
+        let events: Vec<Value> = output
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .filter_map(|d| serde_json::from_str(d).ok())
+            .collect();
+
+        let text_start = events
+            .iter()
+            .find(|e| e["type"] == "content_block_start" && e["content_block"]["type"] == "text")
+            .expect("text block not emitted");
+        let image_start = events
+            .iter()
+            .find(|e| e["type"] == "content_block_start" && e["content_block"]["type"] == "image")
+            .expect("image block not emitted");
+        assert_eq!(text_start["index"], 0);
+        assert_eq!(image_start["index"], 1);
+        assert_eq!(image_start["content_block"]["source"]["media_type"], "image/png");
+        assert_eq!(image_start["content_block"]["source"]["data"], "iVBORw0KGgo=");
+        // 图片不再以 Markdown 文本内联
+        assert!(!output.contains("![image]"));
+        // 文本块先于图片块关闭，图片块随即关闭
+        let stops = events.iter().filter(|e| e["type"] == "content_block_stop").count();
+        assert_eq!(stops, 2);
+    }
+
+    #[test]
+    fn test_inline_data_part_emits_image_block() {
+        let mut state = StreamingState::new();
+        let mut processor = PartProcessor::new(&mut state);
+
+        let part = GeminiPart {
+            text: None,
+            function_call: None,
+            inline_data: Some(InlineData {
+                mime_type: "image/jpeg".to_string(),
+                data: "/9j/4AAQSkZJRg==".to_string(),
+            }),
+            thought: None,
+            thought_signature: None,
+            function_response: None,
+            executable_code: None,
+            code_execution_result: None,
+        };
+
+        let output: String = processor
+            .process(&part)
+            .iter()
+            .map(|b| String::from_utf8(b.to_vec()).unwrap())
+            .collect();
+        let events: Vec<Value> = output
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .filter_map(|d| serde_json::from_str(d).ok())
+            .collect();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["type"], "content_block_start");
+        assert_eq!(
+            events[0]["content_block"],
+            json!({
+                "type": "image",
+                "source": { "type": "base64", "media_type": "image/jpeg", "data": "/9j/4AAQSkZJRg==" }
+            })
+        );
+        assert_eq!(events[1]["type"], "content_block_stop");
+        assert_eq!(events[1]["index"], events[0]["index"]);
+        assert!(state.has_content);
+    }
+
+    #[test]
+    fn test_stringified_function_call_args_parsed_to_object() {
+        let mut state = StreamingState::new();
+        state.tool_schemas.insert(
+            "fetch_page".to_string(),
+            json!({
+                "type": "object",
+                "properties": { "url": { "type": "string" }, "max_lines": { "type": "integer" } }
+            }),
+        );
+        let mut processor = PartProcessor::new(&mut state);
+
+        let call = |args: Value| GeminiPart {
+            text: None,
+            function_call: Some(FunctionCall {
+                name: "fetch_page".to_string(),
+                args: Some(args),
+                id: Some("call_1".to_string()),
+                ..Default::default()
+            }),
+            inline_data: None,
+            thought: None,
+            thought_signature: None,
+            function_response: None,
+            executable_code: None,
+            code_execution_result: None,
+        };
+        let partial_json = |chunks: Vec<Bytes>| -> Value {
+            let output: String = chunks.iter().map(|b| String::from_utf8(b.to_vec()).unwrap()).collect();
+            let delta = output
+                .lines()
+                .filter_map(|l| l.strip_prefix("data: "))
+                .filter_map(|d| serde_json::from_str::<Value>(d).ok())
+                .find(|e| e["delta"]["type"] == "input_json_delta")
+                .expect("input_json_delta not emitted");
+            serde_json::from_str(delta["delta"]["partial_json"].as_str().unwrap()).unwrap()
+        };
+
+        // 字符串形式的 JSON 参数被解析为对象，并按 schema 修正类型
+        let input = partial_json(processor.process(&call(json!(r#"{"url": "https://example.com", "max_lines": "20"}"#))));
+        assert_eq!(input, json!({ "url": "https://example.com", "max_lines": 20 }));
+
+        // 无法解析的字符串包装为 {"raw": ...}
+        let input = partial_json(processor.process(&call(json!("not json at all"))));
+        assert_eq!(input, json!({ "raw": "not json at all" }));
+    }
+
+    #[test]
+    fn test_fragmented_function_call_args_stream_as_input_json_delta() {
+        let mut state = StreamingState::new();
+        let mut processor = PartProcessor::new(&mut state);
+
+        let part = |fc: Value| GeminiPart {
+            text: None,
+            function_call: Some(serde_json::from_value(fc).unwrap()),
+            inline_data: None,
+            thought: None,
+            thought_signature: None,
+            function_response: None,
+            executable_code: None,
+            code_execution_result: None,
+        };
+        let chunks = [
+            json!({ "name": "write_file", "id": "call_1", "willContinue": true }),
+            json!({ "partialArgs": [{ "jsonPath": "$.path", "stringValue": "/tmp/", "willContinue": true }], "willContinue": true }),
+            json!({ "partialArgs": [{ "jsonPath": "$.path", "stringValue": "a.txt" }], "willContinue": true }),
+            json!({ "partialArgs": [{ "jsonPath": "$.content", "stringValue": "line \"1\"\n", "willContinue": true }], "willContinue": true }),
+            json!({ "partialArgs": [{ "jsonPath": "$.content", "stringValue": "line 2" }, { "jsonPath": "$.append", "boolValue": true }], "willContinue": true }),
+            json!({}),
+        ];
+
+        let mut events = Vec::new();
+        for fc in chunks {
+            let output: String = processor
+                .process(&part(fc))
+                .iter()
+                .map(|b| String::from_utf8(b.to_vec()).unwrap())
+                .collect();
+            events.extend(
+                output
+                    .lines()
+                    .filter_map(|l| l.strip_prefix("data: "))
+                    .filter_map(|d| serde_json::from_str::<Value>(d).ok()),
+            );
+        }
+
+        let starts: Vec<&Value> = events.iter().filter(|e| e["type"] == "content_block_start").collect();
+        assert_eq!(starts.len(), 1);
+        assert_eq!(starts[0]["content_block"]["name"], "write_file");
+        assert_eq!(starts[0]["content_block"]["input"], json!({}));
+
+        let deltas: Vec<&str> = events
+            .iter()
+            .filter(|e| e["delta"]["type"] == "input_json_delta")
+            .map(|e| e["delta"]["partial_json"].as_str().unwrap())
+            .collect();
+        // 参数随片段增量发送，而不是在结束时一次性发送
+        assert!(deltas.len() >= 4, "deltas: {:?}", deltas);
+        assert!(deltas.iter().all(|d| !d.is_empty()));
+
+        let input: Value = serde_json::from_str(&deltas.concat()).unwrap();
+        assert_eq!(
+            input,
+            json!({ "path": "/tmp/a.txt", "content": "line \"1\"\nline 2", "append": true })
+        );
+        assert_eq!(events.last().unwrap()["type"], "content_block_stop");
+        assert!(state.streaming_tool_call.is_none());
+    }
+
+    #[test]
+    fn test_truncated_streaming_function_call_closes_with_valid_json() {
+        let mut state = StreamingState::new();
+        let fc: FunctionCall = serde_json::from_value(json!({
+            "name": "write_file",
+            "partialArgs": [{ "jsonPath": "$.content", "stringValue": "unfinished \\", "willContinue": true }],
+            "willContinue": true
+        }))
+        .unwrap();
+        let part = GeminiPart {
+            text: None,
+            function_call: Some(fc),
+            inline_data: None,
+            thought: None,
+            thought_signature: None,
+            function_response: None,
+            executable_code: None,
+            code_execution_result: None,
+        };
+
+        let mut chunks = PartProcessor::new(&mut state).process(&part);
+        // 上游在参数途中断开
+        chunks.extend(state.emit_finish(Some("STOP"), None));
+        let partial_json: String = chunks
+            .iter()
+            .map(|b| String::from_utf8(b.to_vec()).unwrap())
+            .collect::<String>()
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .filter_map(|d| serde_json::from_str::<Value>(d).ok())
+            .filter(|e| e["delta"]["type"] == "input_json_delta")
+            .map(|e| e["delta"]["partial_json"].as_str().unwrap().to_string())
+            .collect();
+
+        assert_eq!(
+            serde_json::from_str::<Value>(&partial_json).unwrap(),
+            json!({ "content": "unfinished \\" })
+        );
+    }
+
+    fn late_signature_events(parts: &[GeminiPart], session_id: &str) -> Vec<Value> {
+        let mut state = StreamingState::new();
+        state.session_id = Some(session_id.to_string());
+        let mut output = String::new();
+        for part in parts {
+            for bytes in PartProcessor::new(&mut state).process(part) {
+                output.push_str(&String::from_utf8(bytes.to_vec()).unwrap());
+            }
+        }
+        for bytes in state.emit_finish(Some("STOP"), None) {
+            output.push_str(&String::from_utf8(bytes.to_vec()).unwrap());
+        }
+        output
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .filter_map(|d| serde_json::from_str::<Value>(d).ok())
+            .collect()
+    }
+
+    fn signature_part(text: &str, thought: bool, signature: Option<&str>) -> GeminiPart {
+        GeminiPart {
+            text: Some(text.to_string()),
+            function_call: None,
+            inline_data: None,
+            thought: Some(thought),
+            thought_signature: signature.map(str::to_string),
+            function_response: None,
+            executable_code: None,
+            code_execution_result: None,
+        }
+    }
+
+    /// 第一个 thinking 块 (index 0) 内 signature_delta 的签名，要求出现在 content_block_stop 之前
+    fn thinking_block_signature(events: &[Value]) -> Option<String> {
+        let start = events.iter().find(|e| e["type"] == "content_block_start").unwrap();
+        assert_eq!(start["content_block"]["type"], "thinking");
+        assert_eq!(start["index"], 0);
+        let stop = events
+            .iter()
+            .position(|e| e["type"] == "content_block_stop" && e["index"] == 0)
+            .unwrap();
+        events[..stop]
+            .iter()
+            .find(|e| e["index"] == 0 && e["delta"]["type"] == "signature_delta")
+            .map(|e| e["delta"]["signature"].as_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_thinking_signature_with_text() {
+        // 非 base64 字符串，保持原样
+        let sig = format!("sig-with-text-{}", "x".repeat(60));
+        let events = late_signature_events(
+            &[
+                signature_part("Planning the answer.", true, Some(&sig)),
+                signature_part("Answer.", false, None),
+            ],
+            "sid-late-signature-with-text",
+        );
+        assert_eq!(thinking_block_signature(&events), Some(sig));
+    }
+
+    #[test]
+    fn test_thinking_signature_one_chunk_late() {
+        let sig = format!("sig-one-chunk-late-{}", "x".repeat(60));
+        // 签名落在 thinking 内容之后的空 text part 上
+        let events = late_signature_events(
+            &[
+                signature_part("Planning the answer.", true, None),
+                signature_part("", false, Some(&sig)),
+                signature_part("Answer.", false, None),
+            ],
+            "sid-late-signature-one-chunk",
+        );
+        assert_eq!(thinking_block_signature(&events), Some(sig.clone()));
+        // 不再额外生成空 thinking 块承载签名
+        let thinking_blocks = events
+            .iter()
+            .filter(|e| e["type"] == "content_block_start" && e["content_block"]["type"] == "thinking")
+            .count();
+        assert_eq!(thinking_blocks, 1);
+
+        // 签名落在空 thought part 上
+        let events = late_signature_events(
+            &[
+                signature_part("Planning the answer.", true, None),
+                signature_part("", true, Some(&sig)),
+                signature_part("Answer.", false, None),
+            ],
+            "sid-late-signature-one-chunk-thought",
+        );
+        assert_eq!(thinking_block_signature(&events), Some(sig));
+    }
+
+    #[test]
+    fn test_thinking_signature_after_close_cached_by_block_hash() {
+        let sig = format!("sig-after-close-{}", "x".repeat(60));
+        let session_id = "sid-late-signature-after-close";
+        let events = late_signature_events(
+            &[
+                signature_part("Planning the answer.", true, None),
+                signature_part("Answer.", false, None),
+                signature_part("", false, Some(&sig)),
+            ],
+            session_id,
+        );
+        // 客户端收到的 thinking 块没有签名
+        assert_eq!(thinking_block_signature(&events), None);
+        // 下一轮请求回传该 thinking 块时可按块哈希恢复签名
+        assert_eq!(
+            SignatureCache::global().get_thinking_block_signature(session_id, "Planning the answer."),
+            Some(sig)
+        );
+    }
+
+    #[test]
+    fn test_carried_usage_counts_prompt_once() {
+        let carried = UsageMetadata {
+            prompt_token_count: Some(1000),
+            candidates_token_count: Some(50),
+            total_token_count: Some(1250),
+            cached_content_token_count: Some(200),
+            thoughts_token_count: Some(200),
+        };
+        // 追问请求的输入包含原始上下文与首个请求的思考内容
+        let current = UsageMetadata {
+            prompt_token_count: Some(1300),
+            candidates_token_count: Some(80),
+            total_token_count: Some(1400),
+            cached_content_token_count: None,
+            thoughts_token_count: Some(20),
+        };
+        let merged = add_carried_usage(current, &carried);
+        assert_eq!(merged.prompt_token_count, Some(1000));
+        assert_eq!(merged.candidates_token_count, Some(130));
+        assert_eq!(merged.thoughts_token_count, Some(220));
+        assert_eq!(merged.cached_content_token_count, Some(200));
+        assert_eq!(merged.total_token_count, Some(1000 + 130 + 220));
+    }
 }
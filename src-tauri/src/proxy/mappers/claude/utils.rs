@@ -18,6 +18,17 @@ pub fn get_context_limit_for_model(model: &str) -> u32 {
     }
 }
 
+/// [NEW] 收集客户端工具的 input_schema (工具名 -> schema)，用于修正上游返回的工具参数
+pub fn collect_tool_schemas(
+    tools: &Option<Vec<super::models::Tool>>,
+) -> std::collections::HashMap<String, serde_json::Value> {
+    tools
+        .iter()
+        .flatten()
+        .filter_map(|tool| Some((tool.name.clone()?, tool.input_schema.clone()?)))
+        .collect()
+}
+
 pub fn to_claude_usage(usage_metadata: &super::models::UsageMetadata, scaling_enabled: bool, context_limit: u32) -> super::models::Usage {
     let prompt_tokens = usage_metadata.prompt_token_count.unwrap_or(0);
     let cached_tokens = usage_metadata.cached_content_token_count.unwrap_or(0);
@@ -98,6 +109,14 @@ pub fn to_claude_usage(usage_metadata: &super::models::UsageMetadata, scaling_en
     }
 }
 
+/// [NEW] usage.server_tool_use: 本轮联网搜索 (grounding) 次数，未搜索或已关闭上报时为 None
+pub fn web_search_usage(web_search_requests: u32) -> Option<serde_json::Value> {
+    if web_search_requests == 0 || !crate::proxy::config::get_report_web_search_usage() {
+        return None;
+    }
+    Some(serde_json::json!({ "web_search_requests": web_search_requests }))
+}
+
 /// 提取 thoughtSignature
 // 已移除未使用的 extract_thought_signature 函数
 
@@ -114,6 +133,7 @@ mod tests {
             candidates_token_count: Some(50),
             total_token_count: Some(150),
             cached_content_token_count: None,
+            thoughts_token_count: None,
         };
 
         let claude_usage = to_claude_usage(&usage, true, 1_000_000);
@@ -127,6 +147,7 @@ mod tests {
             candidates_token_count: Some(10),
             total_token_count: Some(500_010),
             cached_content_token_count: None,
+            thoughts_token_count: None,
         };
         let res_50 = to_claude_usage(&usage_50, true, 1_000_000);
         // 50% * 0.6 = 30% of 195k = 58,500
@@ -138,6 +159,7 @@ mod tests {
             candidates_token_count: Some(10),
             total_token_count: Some(700_010),
             cached_content_token_count: None,
+            thoughts_token_count: None,
         };
         let res_70 = to_claude_usage(&usage_70, true, 1_000_000);
         // 50% of 195k = 97,500
@@ -149,6 +171,7 @@ mod tests {
             candidates_token_count: Some(10),
             total_token_count: Some(850_010),
             cached_content_token_count: None,
+            thoughts_token_count: None,
         };
         let res_85 = to_claude_usage(&usage_85, true, 1_000_000);
         // 70% of 195k = 136,500
@@ -160,6 +183,7 @@ mod tests {
             candidates_token_count: Some(10),
             total_token_count: Some(1_000_010),
             cached_content_token_count: None,
+            thoughts_token_count: None,
         };
         let res_100 = to_claude_usage(&usage_100, true, 1_000_000);
         // 97% of 195k = 189,150
@@ -0,0 +1,417 @@
+// 流式文本 delta 合并 (opt-in)
+// Flash 等高速模型会产生大量极小的文本片段，逐个转发为 SSE 事件时帧开销超过负载本身，
+// 还会让部分终端闪烁。合并器缓冲相邻的可见文本 / 思考 delta，在达到字节阈值、
+// 时间阈值或遇到任何其他事件 (块边界、tool_use、finish 等) 时刷新。
+// 事件顺序保持不变；非文本事件到达时先刷出缓冲文本，再立即转发，不会被延迟。
+// 合并后为空的 Claude text_delta 不再发送 (Zed 渲染空 delta 会崩溃)。
+// 客户端适配器要求的最小 delta 长度也由这里实现 (见 DeltaCoalescingConfig::with_min_text_delta)。
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde_json::Value;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use crate::proxy::config::DeltaCoalescingConfig;
+
+/// SSE 输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SseDialect {
+    /// Claude Messages (`event: content_block_delta` + text_delta / thinking_delta)
+    Claude,
+    /// OpenAI Chat Completions (`chat.completion.chunk` 的 content / reasoning_content)
+    OpenAi,
+}
+
+/// 可合并的 delta 事件
+struct MergeableDelta {
+    /// (块 / choice 索引, 文本字段名)，相同 key 的相邻事件才会合并
+    key: (u64, &'static str),
+    text: String,
+    thinking: bool,
+    json: Value,
+}
+
+struct PendingDelta {
+    key: (u64, &'static str),
+    text: String,
+    thinking: bool,
+    /// 第一个事件的 JSON，刷新时写回合并后的文本
+    json: Value,
+    started_at: Instant,
+}
+
+pub struct DeltaCoalescer {
+    dialect: SseDialect,
+    config: DeltaCoalescingConfig,
+    pending: Option<PendingDelta>,
+}
+
+impl DeltaCoalescer {
+    pub fn new(dialect: SseDialect, config: DeltaCoalescingConfig) -> Self {
+        Self {
+            dialect,
+            config,
+            pending: None,
+        }
+    }
+
+    /// 缓冲内容开始累积的时间 (用于时间阈值)
+    pub fn pending_since(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|p| p.started_at)
+    }
+
+    /// 处理一段输出字节，返回需要立即发送的内容
+    pub fn push(&mut self, chunk: &[u8]) -> Option<Bytes> {
+        let text = String::from_utf8_lossy(chunk);
+        let mut out = String::new();
+
+        for event in split_events(&text) {
+            match self.parse_mergeable(event) {
+                Some(delta) => {
+                    let same_key = self.pending.as_ref().map(|p| p.key == delta.key).unwrap_or(false);
+                    if same_key {
+                        if let Some(pending) = self.pending.as_mut() {
+                            pending.text.push_str(&delta.text);
+                        }
+                    } else {
+                        self.flush_into(&mut out);
+                        self.pending = Some(PendingDelta {
+                            key: delta.key,
+                            text: delta.text,
+                            thinking: delta.thinking,
+                            json: delta.json,
+                            started_at: Instant::now(),
+                        });
+                    }
+                    if self.pending_exceeds_threshold() {
+                        self.flush_into(&mut out);
+                    }
+                }
+                None => {
+                    self.flush_into(&mut out);
+                    out.push_str(event);
+                }
+            }
+        }
+
+        (!out.is_empty()).then(|| Bytes::from(out))
+    }
+
+    /// 立即发送缓冲中的文本
+    pub fn flush(&mut self) -> Option<Bytes> {
+        let mut out = String::new();
+        self.flush_into(&mut out);
+        (!out.is_empty()).then(|| Bytes::from(out))
+    }
+
+    fn pending_exceeds_threshold(&self) -> bool {
+        self.pending
+            .as_ref()
+            .map(|p| {
+                let limit = if p.thinking {
+                    self.config.thinking_max_bytes
+                } else {
+                    self.config.max_bytes
+                };
+                p.text.len() >= limit
+            })
+            .unwrap_or(false)
+    }
+
+    fn flush_into(&mut self, out: &mut String) {
+        let Some(mut pending) = self.pending.take() else {
+            return;
+        };
+        if pending.text.is_empty() {
+            return;
+        }
+        let data = match self.dialect {
+            SseDialect::Claude => {
+                pending.json["delta"][pending.key.1] = Value::String(pending.text);
+                format!(
+                    "event: content_block_delta\ndata: {}\n\n",
+                    serde_json::to_string(&pending.json).unwrap_or_default()
+                )
+            }
+            SseDialect::OpenAi => {
+                pending.json["choices"][0]["delta"][pending.key.1] = Value::String(pending.text);
+                format!("data: {}\n\n", serde_json::to_string(&pending.json).unwrap_or_default())
+            }
+        };
+        out.push_str(&data);
+    }
+
+    fn parse_mergeable(&self, event: &str) -> Option<MergeableDelta> {
+        let data = event.lines().find_map(|l| l.strip_prefix("data: "))?;
+        let json: Value = serde_json::from_str(data.trim()).ok()?;
+        match self.dialect {
+            SseDialect::Claude => parse_claude_delta(json),
+            SseDialect::OpenAi => parse_openai_delta(json),
+        }
+    }
+}
+
+/// content_block_delta 中的 text_delta / thinking_delta
+fn parse_claude_delta(json: Value) -> Option<MergeableDelta> {
+    if json.get("type").and_then(|t| t.as_str()) != Some("content_block_delta") {
+        return None;
+    }
+    let (field, thinking) = match json.pointer("/delta/type").and_then(|t| t.as_str())? {
+        "text_delta" => ("text", false),
+        "thinking_delta" => ("thinking", true),
+        _ => return None,
+    };
+    let text = json.pointer("/delta").and_then(|d| d.get(field)).and_then(|t| t.as_str())?.to_string();
+    let index = json.get("index").and_then(|i| i.as_u64()).unwrap_or(0);
+    Some(MergeableDelta {
+        key: (index, field),
+        text,
+        thinking,
+        json,
+    })
+}
+
+/// 只包含 content 或 reasoning_content 的 chat.completion.chunk (无 tool_calls / finish_reason / usage)
+fn parse_openai_delta(json: Value) -> Option<MergeableDelta> {
+    if json.get("object").and_then(|o| o.as_str()) != Some("chat.completion.chunk") {
+        return None;
+    }
+    if json.get("usage").map(|u| !u.is_null()).unwrap_or(false) {
+        return None;
+    }
+    let choices = json.get("choices").and_then(|c| c.as_array())?;
+    if choices.len() != 1 {
+        return None;
+    }
+    let choice = &choices[0];
+    if choice.get("finish_reason").map(|f| !f.is_null()).unwrap_or(false) {
+        return None;
+    }
+    let delta = choice.get("delta").and_then(|d| d.as_object())?;
+    if delta
+        .keys()
+        .any(|k| !matches!(k.as_str(), "role" | "content" | "reasoning_content"))
+    {
+        return None;
+    }
+
+    let text_of = |field: &str| delta.get(field).and_then(|v| v.as_str()).filter(|s| !s.is_empty());
+    let (field, thinking, text) = match (text_of("content"), text_of("reasoning_content")) {
+        (Some(text), None) => ("content", false, text.to_string()),
+        (None, Some(text)) => ("reasoning_content", true, text.to_string()),
+        _ => return None,
+    };
+    let index = choice.get("index").and_then(|i| i.as_u64()).unwrap_or(0);
+    Some(MergeableDelta {
+        key: (index, field),
+        text,
+        thinking,
+        json,
+    })
+}
+
+/// 按空行切分 SSE 事件 (保留结尾的分隔符)
+fn split_events(text: &str) -> Vec<&str> {
+    let mut events = Vec::new();
+    let mut rest = text;
+    while let Some(pos) = rest.find("\n\n") {
+        events.push(&rest[..pos + 2]);
+        rest = &rest[pos + 2..];
+    }
+    if !rest.is_empty() {
+        events.push(rest);
+    }
+    events
+}
+
+/// 为 SSE 输出流套上 delta 合并 (未启用时原样返回)
+pub fn coalesce_sse_stream(
+    stream: Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>>,
+    dialect: SseDialect,
+    config: DeltaCoalescingConfig,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>> {
+    if !config.enabled {
+        return stream;
+    }
+    let max_delay = Duration::from_millis(config.max_delay_ms);
+
+    Box::pin(async_stream::stream! {
+        let mut stream = stream;
+        let mut coalescer = DeltaCoalescer::new(dialect, config);
+        loop {
+            let next = match coalescer.pending_since() {
+                Some(since) => {
+                    let deadline = tokio::time::Instant::from_std(since + max_delay);
+                    match tokio::time::timeout_at(deadline, stream.next()).await {
+                        Ok(item) => item,
+                        Err(_) => {
+                            // 时间阈值到达，发送已缓冲的文本
+                            if let Some(bytes) = coalescer.flush() {
+                                yield Ok(bytes);
+                            }
+                            continue;
+                        }
+                    }
+                }
+                None => stream.next().await,
+            };
+
+            match next {
+                Some(Ok(chunk)) => {
+                    if let Some(bytes) = coalescer.push(&chunk) {
+                        yield Ok(bytes);
+                    }
+                }
+                Some(Err(e)) => {
+                    if let Some(bytes) = coalescer.flush() {
+                        yield Ok(bytes);
+                    }
+                    yield Err(e);
+                }
+                None => break,
+            }
+        }
+        if let Some(bytes) = coalescer.flush() {
+            yield Ok(bytes);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn enabled() -> DeltaCoalescingConfig {
+        DeltaCoalescingConfig {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    fn claude_event(event: &str, data: Value) -> String {
+        format!("event: {}\ndata: {}\n\n", event, data)
+    }
+
+    fn claude_text(text: &str) -> String {
+        claude_event(
+            "content_block_delta",
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": text } }),
+        )
+    }
+
+    /// 从 Claude SSE 输出中提取 (事件类型, 文本) 序列
+    fn claude_events(output: &str) -> Vec<(String, String)> {
+        split_events(output)
+            .into_iter()
+            .filter_map(|e| {
+                let data: Value = serde_json::from_str(e.lines().find_map(|l| l.strip_prefix("data: "))?).ok()?;
+                let text = data
+                    .pointer("/delta/text")
+                    .and_then(|t| t.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                Some((data["type"].as_str()?.to_string(), text))
+            })
+            .collect()
+    }
+
+    fn run(coalescer: &mut DeltaCoalescer, inputs: &[String]) -> String {
+        let mut out = String::new();
+        for input in inputs {
+            if let Some(bytes) = coalescer.push(input.as_bytes()) {
+                out.push_str(&String::from_utf8_lossy(&bytes));
+            }
+        }
+        if let Some(bytes) = coalescer.flush() {
+            out.push_str(&String::from_utf8_lossy(&bytes));
+        }
+        out
+    }
+
+    #[test]
+    fn test_coalesced_content_matches_uncoalesced() {
+        let mut inputs = vec![claude_event(
+            "content_block_start",
+            json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "text", "text": "" } }),
+        )];
+        let words: Vec<String> = (0..40).map(|i| format!("w{} ", i)).collect();
+        inputs.extend(words.iter().map(|w| claude_text(w)));
+        inputs.push(claude_event("content_block_stop", json!({ "type": "content_block_stop", "index": 0 })));
+
+        let raw = inputs.concat();
+        let coalesced = run(&mut DeltaCoalescer::new(SseDialect::Claude, enabled()), &inputs);
+
+        let text_of = |events: &[(String, String)]| events.iter().map(|(_, t)| t.as_str()).collect::<String>();
+        let raw_events = claude_events(&raw);
+        let merged_events = claude_events(&coalesced);
+        assert_eq!(text_of(&raw_events), text_of(&merged_events));
+        assert!(merged_events.len() < raw_events.len());
+        // 块边界顺序不变
+        assert_eq!(merged_events.first().unwrap().0, "content_block_start");
+        assert_eq!(merged_events.last().unwrap().0, "content_block_stop");
+    }
+
+    #[test]
+    fn test_finish_event_flushes_pending_text_first() {
+        let mut coalescer = DeltaCoalescer::new(SseDialect::Claude, enabled());
+        assert!(coalescer.push(claude_text("Hel").as_bytes()).is_none());
+        assert!(coalescer.push(claude_text("lo").as_bytes()).is_none());
+
+        let finish = claude_event(
+            "message_delta",
+            json!({ "type": "message_delta", "delta": { "stop_reason": "end_turn" } }),
+        );
+        let out = coalescer.push(finish.as_bytes()).unwrap();
+        let events = claude_events(&String::from_utf8_lossy(&out));
+        assert_eq!(
+            events,
+            vec![
+                ("content_block_delta".to_string(), "Hello".to_string()),
+                ("message_delta".to_string(), String::new()),
+            ]
+        );
+        assert!(coalescer.pending_since().is_none());
+    }
+
+    #[test]
+    fn test_client_min_delta_enables_coalescing_and_drops_empty_deltas() {
+        let config = DeltaCoalescingConfig::default().with_min_text_delta(10);
+        assert!(config.enabled);
+        assert_eq!(config.max_bytes, 10);
+        // 已开启时不降低全局阈值
+        assert_eq!(enabled().with_min_text_delta(10).max_bytes, enabled().max_bytes);
+
+        let stop = claude_event("content_block_stop", json!({ "type": "content_block_stop", "index": 0 }));
+        let inputs = vec![claude_text(""), stop.clone()];
+        let out = run(&mut DeltaCoalescer::new(SseDialect::Claude, config), &inputs);
+        assert_eq!(out, stop);
+    }
+
+    #[test]
+    fn test_openai_reasoning_and_content_not_merged_together() {
+        let chunk = |delta: Value, finish: Value| {
+            format!(
+                "data: {}\n\n",
+                json!({ "id": "c", "object": "chat.completion.chunk", "choices": [{ "index": 0, "delta": delta, "finish_reason": finish }] })
+            )
+        };
+        let inputs = vec![
+            chunk(json!({ "role": "assistant", "content": null, "reasoning_content": "think " }), Value::Null),
+            chunk(json!({ "role": "assistant", "content": null, "reasoning_content": "more" }), Value::Null),
+            chunk(json!({ "content": "A" }), Value::Null),
+            chunk(json!({ "content": "B" }), Value::Null),
+            chunk(json!({ "content": "" }), json!("stop")),
+        ];
+        let out = run(&mut DeltaCoalescer::new(SseDialect::OpenAi, enabled()), &inputs);
+        let chunks: Vec<Value> = split_events(&out)
+            .into_iter()
+            .map(|e| serde_json::from_str(e.trim().strip_prefix("data: ").unwrap()).unwrap())
+            .collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0]["choices"][0]["delta"]["reasoning_content"], "think more");
+        assert_eq!(chunks[1]["choices"][0]["delta"]["content"], "AB");
+        assert_eq!(chunks[2]["choices"][0]["finish_reason"], "stop");
+    }
+}
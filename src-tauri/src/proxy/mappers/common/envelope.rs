@@ -0,0 +1,44 @@
+// v1internal 信封构建 - Claude / OpenAI 链路共用
+
+use crate::proxy::mappers::common_utils::{self, EnvelopeParams};
+use crate::proxy::mappers::error::MapperError;
+use serde_json::{json, Value};
+
+/// 构建 v1internal 请求信封
+/// project 字段只接受裸 id，完整 Vertex 路径在此归一化；userAgent / requestType 可按账号覆盖
+pub fn build_envelope(
+    project_id: &str,
+    request_id: String,
+    inner_request: Value,
+    final_model: &str,
+    envelope: &EnvelopeParams,
+    computed_request_type: &str,
+) -> Value {
+    json!({
+        "project": crate::proxy::project_resolver::normalize_project_id(project_id),
+        "requestId": request_id,
+        "request": inner_request,
+        "model": final_model,
+        "userAgent": envelope.user_agent(),
+        "requestType": envelope.request_type(computed_request_type),
+    })
+}
+
+/// 将客户端提供的会话标识 (OpenAI `user` / Claude `metadata.user_id`) 清洗后写入 request.sessionId
+pub fn apply_client_session_id(
+    body: &mut Value,
+    field: &str,
+    value: Option<&str>,
+) -> Result<(), MapperError> {
+    let Some(raw) = value else {
+        return Ok(());
+    };
+    if let Some(session_id) = common_utils::sanitize_envelope_value(
+        field,
+        raw,
+        common_utils::MAX_ENVELOPE_SESSION_ID_LEN,
+    )? {
+        body["request"]["sessionId"] = json!(session_id);
+    }
+    Ok(())
+}
@@ -0,0 +1,193 @@
+// MCP XML Bridge 解析 - Claude / OpenAI 响应转换共用
+// 注入 MCP XML 协议提示词后，模型可能以文本形式输出 `<mcp__tool>{json}</mcp__tool>`，
+// 这里把此类标签还原为工具调用。
+
+use serde_json::{json, Value};
+
+/// MCP XML 解析事件
+#[derive(Debug, Clone, PartialEq)]
+pub enum McpXmlEvent {
+    /// 普通文本 (标签之外的内容)
+    Text(String),
+    /// 完整的 <mcp__tool>...</mcp__tool> 调用
+    ToolCall { name: String, input: Value },
+}
+
+/// MCP XML Bridge 流式解析器
+///
+/// MCP XML Bridge 提示词要求模型输出 `<mcp__tool>{json}</mcp__tool>`，
+/// 该解析器从 text delta 中识别此类标签并还原为工具调用。
+/// 标签可能跨多个 chunk 到达 (包括 `<mc` + `p__` 这类前缀被切开的情况)，
+/// 未完成的部分会暂存在缓冲区中，直到标签闭合或流结束。
+#[derive(Debug, Default)]
+pub struct McpXmlParser {
+    buffer: String,
+}
+
+impl McpXmlParser {
+    const OPEN_PREFIX: &'static str = "<mcp__";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 输入一段文本，返回可以立即发送的事件
+    pub fn feed(&mut self, text: &str) -> Vec<McpXmlEvent> {
+        self.buffer.push_str(text);
+        let mut events = Vec::new();
+
+        loop {
+            let Some(start) = self.buffer.find(Self::OPEN_PREFIX) else {
+                // 保留末尾可能是标签前缀的部分 (如 "<mc")
+                let keep = self.partial_prefix_len();
+                let emit_len = self.buffer.len() - keep;
+                if emit_len > 0 {
+                    events.push(McpXmlEvent::Text(self.buffer.drain(..emit_len).collect()));
+                }
+                break;
+            };
+
+            if start > 0 {
+                events.push(McpXmlEvent::Text(self.buffer.drain(..start).collect()));
+            }
+
+            // 此时缓冲区以 "<mcp__" 开头，解析标签名
+            let name_len = self.buffer[1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.'));
+            let name_end = match name_len {
+                Some(len) => 1 + len,
+                None => break, // 标签名尚未接收完整
+            };
+            if !self.buffer[name_end..].starts_with('>') {
+                // 不是合法的开标签 (如 "<mcp__ foo")，按普通文本处理 "<"
+                events.push(McpXmlEvent::Text(self.buffer.drain(..1).collect()));
+                continue;
+            }
+
+            let name = self.buffer[1..name_end].to_string();
+            let close_tag = format!("</{}>", name);
+            let Some(close_idx) = self.buffer.find(&close_tag) else {
+                break; // 等待闭合标签
+            };
+
+            let raw_input = self.buffer[name_end + 1..close_idx].trim();
+            let input = serde_json::from_str::<Value>(raw_input)
+                .ok()
+                .filter(|v| v.is_object())
+                .unwrap_or_else(|| json!({ "input": raw_input }));
+            self.buffer.drain(..close_idx + close_tag.len());
+
+            tracing::debug!("[MCP-XML] Parsed tool call from text: {}", name);
+            events.push(McpXmlEvent::ToolCall { name, input });
+        }
+
+        events
+    }
+
+    /// 流结束时取出已开始但未闭合的工具调用: (工具名, 已累积的参数, 原始片段)
+    pub fn take_open_call(&mut self) -> Option<(String, String, String)> {
+        if !self.buffer.starts_with(Self::OPEN_PREFIX) {
+            return None;
+        }
+        let name_len = self.buffer[1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.'))?;
+        let name_end = 1 + name_len;
+        if !self.buffer[name_end..].starts_with('>') {
+            return None;
+        }
+        let raw = std::mem::take(&mut self.buffer);
+        let name = raw[1..name_end].to_string();
+        let input = raw[name_end + 1..].to_string();
+        Some((name, input, raw))
+    }
+
+    /// 流结束时调用：未闭合的内容按普通文本返回，避免丢失输出
+    pub fn flush(&mut self) -> Option<String> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        tracing::debug!(
+            "[MCP-XML] Flushing {} unterminated bytes as text",
+            self.buffer.len()
+        );
+        Some(std::mem::take(&mut self.buffer))
+    }
+
+    /// 缓冲区末尾与 "<mcp__" 前缀重合的长度
+    fn partial_prefix_len(&self) -> usize {
+        (1..Self::OPEN_PREFIX.len())
+            .rev()
+            .find(|&k| self.buffer.ends_with(&Self::OPEN_PREFIX[..k]))
+            .unwrap_or(0)
+    }
+}
+
+/// 还原出的 OpenAI tool_call id (流式与非流式响应共用同一格式)
+pub fn mcp_tool_call_id() -> String {
+    format!("call_{}", uuid::Uuid::new_v4().simple())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mcp_xml_parser_chunked_tool_call() {
+        let mut parser = McpXmlParser::new();
+        let chunks = [
+            "Sure, let me check. <mc",
+            "p__fs__read",
+            "_file>{\"path\":",
+            "\"/tmp/a.txt\"}</mcp__fs__re",
+            "ad_file> Done.",
+        ];
+
+        let mut events = Vec::new();
+        for chunk in chunks {
+            events.extend(parser.feed(chunk));
+        }
+
+        assert_eq!(
+            events,
+            vec![
+                McpXmlEvent::Text("Sure, let me check. ".to_string()),
+                McpXmlEvent::ToolCall {
+                    name: "mcp__fs__read_file".to_string(),
+                    input: json!({ "path": "/tmp/a.txt" }),
+                },
+                McpXmlEvent::Text(" Done.".to_string()),
+            ]
+        );
+        assert_eq!(parser.flush(), None);
+    }
+
+    #[test]
+    fn test_mcp_xml_parser_plain_text_and_unterminated() {
+        let mut parser = McpXmlParser::new();
+
+        // 非 JSON 参数包装为 {"input": ...}
+        let events = parser.feed("<mcp__echo>hello world</mcp__echo>");
+        assert_eq!(
+            events,
+            vec![McpXmlEvent::ToolCall {
+                name: "mcp__echo".to_string(),
+                input: json!({ "input": "hello world" }),
+            }]
+        );
+
+        // 非法标签与普通 "<" 按文本输出
+        let text: String = parser
+            .feed("a < b and <mcp__ bad> tag")
+            .into_iter()
+            .map(|e| match e {
+                McpXmlEvent::Text(t) => t,
+                other => panic!("unexpected event: {:?}", other),
+            })
+            .collect();
+        assert_eq!(text, "a < b and <mcp__ bad> tag");
+
+        // 未闭合的标签在流结束时作为文本返回
+        assert!(parser.feed("<mcp__slow>{\"a\":1}").is_empty());
+        assert_eq!(parser.flush(), Some("<mcp__slow>{\"a\":1}".to_string()));
+    }
+}
@@ -0,0 +1,8 @@
+// Mappers 公共模块 - Claude / OpenAI 转换器共用的构建逻辑
+
+pub mod system_builder;
+pub mod delta_coalescer;
+pub mod safety;
+pub mod thinking_budget;
+pub mod envelope;
+pub mod mcp_xml;
@@ -0,0 +1,82 @@
+// 安全设置 (safetySettings) 构建 - Claude / OpenAI 链路共用
+
+use serde_json::{json, Value};
+
+/// Safety threshold levels for Gemini API
+/// Can be configured via GEMINI_SAFETY_THRESHOLD environment variable
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SafetyThreshold {
+    /// Disable all safety filters (default for proxy compatibility)
+    Off,
+    /// Block low probability and above
+    BlockLowAndAbove,
+    /// Block medium probability and above
+    BlockMediumAndAbove,
+    /// Only block high probability content
+    BlockOnlyHigh,
+    /// Don't block anything (BLOCK_NONE)
+    BlockNone,
+}
+
+impl SafetyThreshold {
+    /// Get threshold from environment variable or default to Off
+    pub fn from_env() -> Self {
+        std::env::var("GEMINI_SAFETY_THRESHOLD")
+            .ok()
+            .and_then(|v| Self::parse(&v))
+            .unwrap_or(SafetyThreshold::Off) // Default: maintain current behavior
+    }
+
+    /// Parse a threshold name (OFF / LOW / MEDIUM / HIGH / NONE, case-insensitive)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_uppercase().as_str() {
+            "OFF" => Some(SafetyThreshold::Off),
+            "LOW" => Some(SafetyThreshold::BlockLowAndAbove),
+            "MEDIUM" => Some(SafetyThreshold::BlockMediumAndAbove),
+            "HIGH" => Some(SafetyThreshold::BlockOnlyHigh),
+            "NONE" => Some(SafetyThreshold::BlockNone),
+            _ => None,
+        }
+    }
+
+    /// [NEW] Explicit per-request threshold: query override, then listener profile
+    pub fn overridden() -> Option<Self> {
+        crate::proxy::query_overrides::safety_threshold_override()
+            .or_else(crate::proxy::listener_profile::safety_threshold_override)
+            .and_then(|v| Self::parse(&v))
+    }
+
+    /// [NEW] Resolve the effective threshold: query override, then listener profile, then env
+    pub fn effective() -> Self {
+        Self::overridden().unwrap_or_else(Self::from_env)
+    }
+
+    /// Convert to Gemini API threshold string
+    pub fn to_gemini_threshold(&self) -> &'static str {
+        match self {
+            SafetyThreshold::Off => "OFF",
+            SafetyThreshold::BlockLowAndAbove => "BLOCK_LOW_AND_ABOVE",
+            SafetyThreshold::BlockMediumAndAbove => "BLOCK_MEDIUM_AND_ABOVE",
+            SafetyThreshold::BlockOnlyHigh => "BLOCK_ONLY_HIGH",
+            SafetyThreshold::BlockNone => "BLOCK_NONE",
+        }
+    }
+}
+
+/// Build safety settings based on configuration
+pub fn build_safety_settings() -> Value {
+    build_safety_settings_with(SafetyThreshold::effective())
+}
+
+/// Build safety settings with every category set to the given threshold
+pub fn build_safety_settings_with(threshold: SafetyThreshold) -> Value {
+    let threshold_str = threshold.to_gemini_threshold();
+
+    json!([
+        { "category": "HARM_CATEGORY_HARASSMENT", "threshold": threshold_str },
+        { "category": "HARM_CATEGORY_HATE_SPEECH", "threshold": threshold_str },
+        { "category": "HARM_CATEGORY_SEXUALLY_EXPLICIT", "threshold": threshold_str },
+        { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "threshold": threshold_str },
+        { "category": "HARM_CATEGORY_CIVIC_INTEGRITY", "threshold": threshold_str },
+    ])
+}
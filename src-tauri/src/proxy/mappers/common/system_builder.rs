@@ -0,0 +1,227 @@
+// System Instruction 构建器
+// Claude 与 OpenAI 转换器共用: Antigravity 身份注入、全局系统提示词、
+// 用户系统提示词、强制回复语言、MCP XML 协议与结束标记统一在此生成，保证两条链路行为一致。
+
+use crate::proxy::query_overrides::ResponseLanguageOverride;
+use serde_json::{json, Value};
+
+/// Antigravity 身份指令 (原始简化版)
+pub const ANTIGRAVITY_IDENTITY: &str = "You are Antigravity, a powerful agentic AI coding assistant designed by the Google Deepmind team working on Advanced Agentic Coding.\n\
+    You are pair programming with a USER to solve their coding task. The task may require creating a new codebase, modifying or debugging an existing codebase, or simply answering a question.\n\
+    **Absolute paths only**\n\
+    **Proactiveness**";
+
+/// 用于识别用户是否已自带 Antigravity 身份
+const IDENTITY_MARKER: &str = "You are Antigravity";
+
+/// MCP XML Bridge 调用协议 (存在 mcp__ 开头的工具时注入)
+pub const MCP_XML_PROMPT: &str = "\n\
+    ==== MCP XML 工具调用协议 (Workaround) ====\n\
+    当你需要调用名称以 `mcp__` 开头的 MCP 工具时：\n\
+    1) 优先尝试 XML 格式调用：输出 `<mcp__tool_name>{\"arg\":\"value\"}</mcp__tool_name>`。\n\
+    2) 必须直接输出 XML 块，无需 markdown 包装，内容为 JSON 格式的入参。\n\
+    3) 这种方式具有更高的连通性和容错性，适用于大型结果返回场景。\n\
+    ===========================================";
+
+/// BCP-47 语言标签长度上限
+const MAX_LANGUAGE_TAG_LEN: usize = 35;
+
+/// 系统提示词结束标记
+pub const SYSTEM_PROMPT_END: &str = "\n--- [SYSTEM_PROMPT_END] ---";
+
+/// 身份注入相关配置
+#[derive(Debug, Clone, Default)]
+pub struct IdentityConfig {
+    /// 是否注入 Antigravity 身份 (监听配置档可关闭)
+    pub inject_identity: bool,
+    /// 全局系统提示词 (已启用且非空时才有值)
+    pub global_prompt: Option<String>,
+    /// 强制回复语言 (BCP-47 标签)
+    pub response_language: Option<String>,
+}
+
+impl IdentityConfig {
+    /// 读取当前运行时配置
+    pub fn current() -> Self {
+        let global = crate::proxy::config::get_global_system_prompt();
+        Self {
+            inject_identity: crate::proxy::listener_profile::identity_injection_enabled(),
+            global_prompt: (global.enabled && !global.content.trim().is_empty())
+                .then_some(global.content),
+            response_language: resolve_response_language(
+                crate::proxy::config::get_forced_response_language(),
+                crate::proxy::query_overrides::response_language_override(),
+            ),
+        }
+    }
+}
+
+/// 请求头覆盖 (或关闭) 优先于全局配置的强制回复语言
+fn resolve_response_language(
+    configured: Option<String>,
+    overridden: Option<ResponseLanguageOverride>,
+) -> Option<String> {
+    match overridden {
+        Some(ResponseLanguageOverride::Off) => None,
+        Some(ResponseLanguageOverride::Language(tag)) => Some(tag),
+        None => configured,
+    }
+}
+
+/// 宽松的 BCP-47 语言标签校验: 主标签 2-8 位字母，其余子标签 1-8 位字母数字 (如 ja、zh-Hans-CN)
+pub fn is_valid_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let primary = subtags.next().unwrap_or_default();
+    tag.len() <= MAX_LANGUAGE_TAG_LEN
+        && (2..=8).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|s| (1..=8).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// 强制回复语言指令
+pub fn response_language_instruction(language: &str) -> String {
+    format!(
+        "Always respond in {} unless the user explicitly requests another language.",
+        language
+    )
+}
+
+/// 工具名称中是否包含 mcp__ 开头的 MCP 工具
+pub fn has_mcp_tools<'a>(mut tool_names: impl Iterator<Item = &'a str>) -> bool {
+    tool_names.any(|name| name.starts_with("mcp__"))
+}
+
+/// 构建 systemInstruction 的 parts 数组
+///
+/// 顺序: Antigravity 身份 → 全局系统提示词 → 用户系统提示词 → 强制回复语言 → MCP XML 协议 → 结束标记。
+/// 用户已自带 Antigravity 身份时不注入身份，也不追加结束标记。
+pub fn build_system_parts(
+    user_texts: &[&str],
+    has_mcp_tools: bool,
+    identity: &IdentityConfig,
+) -> Vec<Value> {
+    let user_has_antigravity = user_texts.iter().any(|t| t.contains(IDENTITY_MARKER));
+    let mut parts = Vec::new();
+
+    if !user_has_antigravity && identity.inject_identity {
+        parts.push(json!({ "text": ANTIGRAVITY_IDENTITY }));
+    }
+
+    // 全局系统提示词紧跟 Antigravity 身份之后
+    if let Some(global) = &identity.global_prompt {
+        parts.push(json!({ "text": global }));
+    }
+
+    for text in user_texts {
+        parts.push(json!({ "text": text }));
+    }
+
+    // 位于用户系统提示词之后，优先于其中的语言要求
+    if let Some(language) = &identity.response_language {
+        parts.push(json!({ "text": response_language_instruction(language) }));
+    }
+
+    // 规避部分 MCP 链路在标准 tool_use 协议下解析不稳的问题
+    if has_mcp_tools {
+        parts.push(json!({ "text": MCP_XML_PROMPT }));
+    }
+
+    if !user_has_antigravity {
+        parts.push(json!({ "text": SYSTEM_PROMPT_END }));
+    }
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(parts: &[Value]) -> Vec<&str> {
+        parts.iter().map(|p| p["text"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_full_injection_order() {
+        let identity = IdentityConfig {
+            inject_identity: true,
+            global_prompt: Some("GLOBAL".to_string()),
+            response_language: None,
+        };
+        let parts = build_system_parts(&["user sys"], true, &identity);
+        assert_eq!(
+            texts(&parts),
+            vec![ANTIGRAVITY_IDENTITY, "GLOBAL", "user sys", MCP_XML_PROMPT, SYSTEM_PROMPT_END]
+        );
+    }
+
+    #[test]
+    fn test_user_provided_identity_skips_injection_and_end_marker() {
+        let identity = IdentityConfig {
+            inject_identity: true,
+            global_prompt: None,
+            response_language: None,
+        };
+        let parts = build_system_parts(&["You are Antigravity, custom."], false, &identity);
+        assert_eq!(texts(&parts), vec!["You are Antigravity, custom."]);
+    }
+
+    #[test]
+    fn test_identity_injection_disabled_keeps_end_marker() {
+        let parts = build_system_parts(&[], false, &IdentityConfig::default());
+        assert_eq!(texts(&parts), vec![SYSTEM_PROMPT_END]);
+    }
+
+    #[test]
+    fn test_response_language_follows_user_prompt() {
+        let identity = IdentityConfig {
+            inject_identity: true,
+            global_prompt: None,
+            response_language: Some("ja".to_string()),
+        };
+        let parts = build_system_parts(&["Answer in English.", "Be brief."], true, &identity);
+        let instruction = response_language_instruction("ja");
+        assert_eq!(
+            texts(&parts),
+            vec![
+                ANTIGRAVITY_IDENTITY,
+                "Answer in English.",
+                "Be brief.",
+                instruction.as_str(),
+                MCP_XML_PROMPT,
+                SYSTEM_PROMPT_END
+            ]
+        );
+        assert_eq!(
+            instruction,
+            "Always respond in ja unless the user explicitly requests another language."
+        );
+    }
+
+    #[test]
+    fn test_response_language_header_override() {
+        let configured = Some("ja".to_string());
+        assert_eq!(resolve_response_language(configured.clone(), None).as_deref(), Some("ja"));
+        assert_eq!(
+            resolve_response_language(
+                configured.clone(),
+                Some(ResponseLanguageOverride::Language("fr-CA".to_string()))
+            )
+            .as_deref(),
+            Some("fr-CA")
+        );
+        assert_eq!(resolve_response_language(configured, Some(ResponseLanguageOverride::Off)), None);
+
+        assert!(is_valid_language_tag("ja"));
+        assert!(is_valid_language_tag("zh-Hans-CN"));
+        assert!(!is_valid_language_tag("j"));
+        assert!(!is_valid_language_tag("ja_JP"));
+        assert!(!is_valid_language_tag("ja-"));
+    }
+
+    #[test]
+    fn test_has_mcp_tools() {
+        assert!(has_mcp_tools(["read", "mcp__fs_list"].into_iter()));
+        assert!(!has_mcp_tools(["read", "my_mcp__tool"].into_iter()));
+    }
+}
@@ -0,0 +1,115 @@
+// 思维预算 (thinkingBudget) 解析 - Claude / OpenAI 链路共用
+// 按 thinking_budget 配置的模式决定最终预算，并对 Gemini 类模型执行 24576 上限。
+
+use crate::proxy::config::{ThinkingBudgetConfig, ThinkingBudgetMode};
+
+/// [FIX #1592] Gemini 原生模型 (如 gemini-3-pro) 不支持 32k 预算，统一上限 24576
+pub const GEMINI_THINKING_BUDGET_CAP: i64 = 24576;
+
+/// Claude 链路需要执行预算上限的模型 (画图模型除外)
+pub fn is_claude_budget_capped_model(mapped_model: &str) -> bool {
+    let model_lower = mapped_model.to_lowercase();
+    (model_lower.contains("gemini") && !model_lower.contains("-image"))
+        || model_lower.contains("flash")
+        || model_lower.ends_with("-thinking")
+}
+
+/// OpenAI 链路需要执行预算上限的模型: Gemini (画图模型除外) 与 -thinking 模型，不含其他 flash 模型
+pub fn is_openai_budget_capped_model(mapped_model: &str) -> bool {
+    let model_lower = mapped_model.to_lowercase();
+    (model_lower.contains("gemini") && !model_lower.contains("-image"))
+        || model_lower.ends_with("-thinking")
+}
+
+/// 根据配置模式计算思维预算 (未做最小值保护，调用方随后执行 apply_budget_floor)
+/// - Passthrough / Adaptive: 使用调用方传入的值
+/// - Custom: 使用配置的固定值，capped 为 true 时仍受上限约束
+/// - Auto: capped 为 true 且调用方的值超过上限时截断
+/// capped 由各协议的上限模型判定函数给出
+pub fn resolve_thinking_budget(
+    requested: i64,
+    mapped_model: &str,
+    capped: bool,
+    tb_config: &ThinkingBudgetConfig,
+    log_tag: &str,
+) -> i64 {
+    match tb_config.mode {
+        ThinkingBudgetMode::Passthrough | ThinkingBudgetMode::Adaptive => requested,
+        ThinkingBudgetMode::Custom => {
+            let custom_value = tb_config.custom_value as i64;
+            if capped && custom_value > GEMINI_THINKING_BUDGET_CAP {
+                tracing::warn!(
+                    "[{}] Custom mode: capping thinking_budget from {} to {} for Gemini model {}",
+                    log_tag,
+                    custom_value,
+                    GEMINI_THINKING_BUDGET_CAP,
+                    mapped_model
+                );
+                return GEMINI_THINKING_BUDGET_CAP;
+            }
+            custom_value
+        }
+        ThinkingBudgetMode::Auto => {
+            if capped && requested > GEMINI_THINKING_BUDGET_CAP {
+                tracing::info!(
+                    "[{}] Auto mode: capping thinking_budget from {} to {} for model: {}",
+                    log_tag,
+                    requested,
+                    GEMINI_THINKING_BUDGET_CAP,
+                    mapped_model
+                );
+                GEMINI_THINKING_BUDGET_CAP
+            } else {
+                requested
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(mode: ThinkingBudgetMode, custom_value: u32) -> ThinkingBudgetConfig {
+        ThinkingBudgetConfig {
+            mode,
+            custom_value,
+            ..Default::default()
+        }
+    }
+
+    fn resolve(requested: i64, model: &str, tb_config: &ThinkingBudgetConfig) -> i64 {
+        resolve_thinking_budget(requested, model, is_claude_budget_capped_model(model), tb_config, "Test")
+    }
+
+    #[test]
+    fn test_resolve_thinking_budget_modes() {
+        let auto = config(ThinkingBudgetMode::Auto, 0);
+        assert_eq!(resolve(32000, "gemini-3-pro", &auto), 24576);
+        assert_eq!(resolve(32000, "claude-opus-4-6", &auto), 32000);
+        assert_eq!(resolve(32000, "gemini-3-pro-image", &auto), 32000);
+
+        let custom = config(ThinkingBudgetMode::Custom, 40000);
+        assert_eq!(resolve(1000, "gemini-2.5-flash", &custom), 24576);
+        assert_eq!(resolve(1000, "claude-sonnet-4-5", &custom), 40000);
+
+        let passthrough = config(ThinkingBudgetMode::Passthrough, 0);
+        assert_eq!(resolve(32000, "gemini-3-pro", &passthrough), 32000);
+    }
+
+    #[test]
+    fn test_capped_models_differ_per_protocol() {
+        // Claude 链路额外覆盖 flash 模型，OpenAI 链路保持原有范围
+        for model in ["gemini-3-pro", "claude-opus-4-6-thinking"] {
+            assert!(is_claude_budget_capped_model(model));
+            assert!(is_openai_budget_capped_model(model));
+        }
+        assert!(is_claude_budget_capped_model("mistral-flash"));
+        assert!(!is_openai_budget_capped_model("mistral-flash"));
+        assert!(!is_openai_budget_capped_model("gemini-3-pro-image"));
+
+        let auto = config(ThinkingBudgetMode::Auto, 0);
+        let openai_capped = is_openai_budget_capped_model("mistral-flash");
+        assert_eq!(resolve_thinking_budget(32000, "mistral-flash", openai_capped, &auto, "Test"), 32000);
+    }
+}
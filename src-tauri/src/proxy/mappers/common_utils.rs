@@ -3,6 +3,10 @@
 
 use serde_json::{json, Value};
 
+use crate::proxy::common::json_guard::JsonWalkGuard;
+use crate::proxy::config::{EnvelopeSanitizeAction, ToolLimitAction, ToolLimitConfig};
+use crate::proxy::mappers::error::MapperError;
+
 /// Request configuration after grounding resolution
 #[derive(Debug, Clone)]
 pub struct RequestConfig {
@@ -16,6 +20,336 @@ pub struct RequestConfig {
     pub image_config: Option<Value>,
 }
 
+impl RequestConfig {
+    /// Per-request opt-out: drop automatic googleSearch injection (the resolved model is kept)
+    pub fn suppress_google_search(&mut self, disabled: bool) {
+        if !disabled || !self.inject_google_search {
+            return;
+        }
+        tracing::info!("[Common-Utils] googleSearch injection suppressed by request");
+        self.inject_google_search = false;
+        if self.request_type == "web_search" {
+            self.request_type = "agent".to_string();
+        }
+    }
+}
+
+/// Request-level options taken from client headers, independent of the account's envelope overrides
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestContext {
+    /// Suppress automatic googleSearch injection (X-Abv-Disable-Google-Search)
+    pub disable_google_search: bool,
+    /// Log the full transformed body at trace level (X-Abv-Debug)
+    pub trace_body: bool,
+}
+
+/// Default `userAgent` written into the v1internal envelope
+pub const DEFAULT_ENVELOPE_USER_AGENT: &str = "antigravity";
+
+/// Maximum length accepted for per-account envelope overrides
+const MAX_ENVELOPE_OVERRIDE_LEN: usize = 64;
+
+/// Per-account overrides for the v1internal envelope fields (`userAgent` / `requestType`).
+/// All fields default to `None`, which keeps the built-in behavior.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvelopeParams {
+    pub user_agent: Option<String>,
+    pub request_type: Option<String>,
+}
+
+impl EnvelopeParams {
+    /// Build params from raw (possibly invalid) override values; invalid values are dropped.
+    pub fn from_overrides(user_agent: Option<&str>, request_type: Option<&str>) -> Self {
+        Self {
+            user_agent: sanitize_envelope_override("user_agent_override", user_agent),
+            request_type: sanitize_envelope_override("request_type_override", request_type),
+        }
+    }
+
+    /// Effective `userAgent` for the envelope
+    pub fn user_agent(&self) -> &str {
+        self.user_agent.as_deref().unwrap_or(DEFAULT_ENVELOPE_USER_AGENT)
+    }
+
+    /// Effective `requestType` for the envelope, falling back to the computed one
+    pub fn request_type<'a>(&'a self, computed: &'a str) -> &'a str {
+        self.request_type.as_deref().unwrap_or(computed)
+    }
+}
+
+/// Validate an envelope override value: non-empty, printable ASCII, length-capped.
+pub fn validate_envelope_override(value: &str) -> Result<(), String> {
+    if value.trim().is_empty() {
+        return Err("override must not be empty".to_string());
+    }
+    if value.len() > MAX_ENVELOPE_OVERRIDE_LEN {
+        return Err(format!(
+            "override exceeds {} characters",
+            MAX_ENVELOPE_OVERRIDE_LEN
+        ));
+    }
+    if !value.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+        return Err("override must contain printable ASCII only".to_string());
+    }
+    Ok(())
+}
+
+fn sanitize_envelope_override(field: &str, value: Option<&str>) -> Option<String> {
+    let value = value?;
+    match validate_envelope_override(value) {
+        Ok(()) => Some(value.trim().to_string()),
+        Err(e) => {
+            tracing::warn!("[Envelope] Ignoring invalid {}: {}", field, e);
+            None
+        }
+    }
+}
+
+/// Maximum length of a configured `requestId` prefix
+const MAX_REQUEST_ID_PREFIX_LEN: usize = 32;
+
+/// Build a v1internal `requestId` as `<prefix>-<uuid>`; an invalid configured prefix
+/// (empty, too long or non `[A-Za-z0-9_-]`) falls back to the protocol default.
+pub fn build_request_id(prefix: &str, default_prefix: &str) -> String {
+    let valid = !prefix.is_empty()
+        && prefix.len() <= MAX_REQUEST_ID_PREFIX_LEN
+        && prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    let prefix = if valid {
+        prefix
+    } else {
+        tracing::warn!("[Request-Id] Invalid requestId prefix {:?}, using {}", prefix, default_prefix);
+        default_prefix
+    };
+    format!("{}-{}", prefix, uuid::Uuid::new_v4())
+}
+
+/// Copy of a transformed body safe for logs: `thoughtSignature` values and `inlineData.data`
+/// payloads are replaced by their length.
+pub fn redact_body_for_log(body: &Value) -> Value {
+    match body {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| {
+                    let redacted = match (key.as_str(), value) {
+                        ("thoughtSignature", Value::String(sig)) => {
+                            json!(format!("[redacted signature, {} chars]", sig.len()))
+                        }
+                        ("inlineData", Value::Object(inline)) => {
+                            let mut inline = inline.clone();
+                            if let Some(Value::String(data)) = inline.get("data") {
+                                let len = data.len();
+                                inline.insert("data".to_string(), json!(format!("[{} base64 chars]", len)));
+                            }
+                            Value::Object(inline)
+                        }
+                        _ => redact_body_for_log(value),
+                    };
+                    (key.clone(), redacted)
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(redact_body_for_log).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Trace-level log of the complete transformed body, only when the request carries the debug flag
+pub fn trace_transformed_body(protocol: &str, body: &Value, request_ctx: &RequestContext) {
+    if !request_ctx.trace_body || !tracing::enabled!(tracing::Level::TRACE) {
+        return;
+    }
+    tracing::trace!(
+        "[{}-Request] Transformed body (redacted): {}",
+        protocol,
+        serde_json::to_string(&redact_body_for_log(body)).unwrap_or_default()
+    );
+}
+
+/// Maximum length of `metadata.user_id` when copied into `request.sessionId`
+pub const MAX_ENVELOPE_SESSION_ID_LEN: usize = 256;
+/// Maximum length of a client-supplied `imageSize`
+pub const MAX_ENVELOPE_IMAGE_SIZE_LEN: usize = 16;
+
+/// Characters that never appear in legitimate ids but indicate JSON / structural injection
+fn looks_structural(value: &str) -> bool {
+    value.chars().any(|c| matches!(c, '{' | '}' | '[' | ']' | '"' | '\\'))
+}
+
+/// Sanitize a client-controlled string before it is copied into the v1internal envelope
+/// or upstream headers: control characters are stripped, over-long values are truncated,
+/// and values that look like structural injection are dropped. With the `reject` action
+/// any violation fails the request instead. `Ok(None)` means the field should be omitted.
+pub fn sanitize_envelope_value(field: &str, value: &str, max_len: usize) -> Result<Option<String>, MapperError> {
+    sanitize_envelope_value_with(field, value, max_len, crate::proxy::config::get_envelope_sanitize_action())
+}
+
+fn sanitize_envelope_value_with(
+    field: &str,
+    value: &str,
+    max_len: usize,
+    action: EnvelopeSanitizeAction,
+) -> Result<Option<String>, MapperError> {
+    let cleaned: String = value.chars().filter(|c| !c.is_control()).collect();
+    let cleaned = cleaned.trim();
+
+    let structural = looks_structural(cleaned);
+    let violation = if structural {
+        Some("looks like structured data".to_string())
+    } else if value.chars().any(char::is_control) {
+        Some("contains control characters".to_string())
+    } else if cleaned.len() > max_len {
+        Some(format!("exceeds {} bytes", max_len))
+    } else {
+        None
+    };
+
+    if let Some(reason) = &violation {
+        if action == EnvelopeSanitizeAction::Reject {
+            return Err(MapperError::invalid_request(field, reason.clone()));
+        }
+        tracing::warn!("[Envelope] Sanitizing client-supplied {} ({} bytes): {}", field, value.len(), reason);
+        if structural {
+            return Ok(None);
+        }
+    }
+
+    let mut end = cleaned.len().min(max_len);
+    while !cleaned.is_char_boundary(end) {
+        end -= 1;
+    }
+    let truncated = &cleaned[..end];
+    Ok(Some(truncated.to_string()).filter(|v| !v.is_empty()))
+}
+
+/// 判断一个被映射到图像模型的请求是否明显期望文本输出
+/// 客户端显式请求图像模型时不视为误映射；否则携带工具或未带任何图像参数即视为文本请求
+pub fn expects_text_output(
+    original_model: &str,
+    tools: &Option<Vec<Value>>,
+    size: Option<&str>,
+    quality: Option<&str>,
+    image_size: Option<&str>,
+    body: Option<&Value>,
+) -> bool {
+    let original_lower = original_model.to_lowercase();
+    if original_lower.contains("image") || original_lower.contains("imagen") || original_lower.contains("dall-e") {
+        return false;
+    }
+
+    let has_tools = tools.as_ref().map_or(false, |t| !t.is_empty());
+    let has_image_params = size.is_some()
+        || quality.is_some()
+        || image_size.is_some()
+        || body
+            .and_then(|b| b.get("generationConfig"))
+            .and_then(|g| g.get("imageConfig"))
+            .is_some();
+
+    has_tools || !has_image_params
+}
+
+/// [NEW] 将工具调用参数规范化为 JSON 对象
+///
+/// Gemini 的 functionCall.args 与 Claude 的 tool_use input 都必须是对象，但两侧偶尔会出现畸形参数:
+/// - null → {}
+/// - 字符串形式的 JSON 对象 → 解析后的对象 (修复)
+/// - 其他字符串 / 数组 / 标量 → {<wrap_key>: <value>}
+///
+/// 客户端 tool_use input 以 "input" 包装；上游 functionCall.args 以 "raw" 包装
+pub fn coerce_tool_input_to_object(input: &Value, tool_name: &str, wrap_key: &str) -> Value {
+    match input {
+        Value::Object(_) => input.clone(),
+        Value::Null => {
+            tracing::warn!("[Common-Utils] tool input for '{}' is null, using empty object", tool_name);
+            json!({})
+        }
+        Value::String(raw) => match serde_json::from_str::<Value>(raw) {
+            Ok(parsed @ Value::Object(_)) => {
+                tracing::warn!("[Common-Utils] tool input for '{}' is a JSON string, parsed into object", tool_name);
+                parsed
+            }
+            _ => {
+                tracing::warn!("[Common-Utils] tool input for '{}' is a string, wrapping as {{\"{}\": ...}}", tool_name, wrap_key);
+                json!({ wrap_key: input })
+            }
+        },
+        _ => {
+            tracing::warn!("[Common-Utils] tool input for '{}' is not an object, wrapping as {{\"{}\": ...}}", tool_name, wrap_key);
+            json!({ wrap_key: input })
+        }
+    }
+}
+
+/// [NEW] 规范化上游返回的 functionCall.args
+/// Gemini 偶尔将 args 以 JSON 字符串形式返回 ("{\"path\": ...}")，客户端无法解析会中断工具循环，
+/// 先按 coerce_tool_input_to_object 规范化为对象 (无法解析的字符串包装为 {"raw": "<string>"}，缺失时为空对象)；
+/// 已知工具 schema 时再按 schema 修正参数类型
+pub fn normalize_function_call_args(args: Option<&Value>, tool_name: &str, schema: Option<&Value>) -> Value {
+    let mut normalized = args
+        .map(|a| coerce_tool_input_to_object(a, tool_name, "raw"))
+        .unwrap_or_else(|| json!({}));
+
+    if let Some(schema) = schema {
+        crate::proxy::common::json_schema::fix_tool_call_args(&mut normalized, schema);
+    }
+    normalized
+}
+
+/// [NEW] 将 Gemini 的 executableCode / codeExecutionResult part 渲染为 Markdown 文本
+/// - executableCode: 带声明语言的代码块
+/// - codeExecutionResult: output 代码块 (非 OUTCOME_OK 时附带结果状态)
+/// 非代码执行 part 或配置为丢弃 (drop_code_execution_parts) 时返回 None
+pub fn render_code_execution_part(
+    executable_code: Option<&Value>,
+    code_execution_result: Option<&Value>,
+) -> Option<String> {
+    if executable_code.is_none() && code_execution_result.is_none() {
+        return None;
+    }
+    if crate::proxy::config::get_drop_code_execution_parts() {
+        tracing::debug!("[Code-Execution] Dropping executableCode/codeExecutionResult part");
+        return None;
+    }
+    Some(format_code_execution_part(executable_code, code_execution_result))
+}
+
+fn format_code_execution_part(
+    executable_code: Option<&Value>,
+    code_execution_result: Option<&Value>,
+) -> String {
+    let mut out = String::new();
+    if let Some(exec) = executable_code {
+        let language = exec
+            .get("language")
+            .and_then(|v| v.as_str())
+            .filter(|l| !l.is_empty() && *l != "LANGUAGE_UNSPECIFIED")
+            .map(|l| l.to_lowercase())
+            .unwrap_or_default();
+        let code = exec.get("code").and_then(|v| v.as_str()).unwrap_or("");
+        out.push_str(&format!("\n```{}\n{}\n```\n", language, code.trim_end_matches('\n')));
+    }
+    if let Some(result) = code_execution_result {
+        let outcome = result.get("outcome").and_then(|v| v.as_str()).unwrap_or("OUTCOME_OK");
+        let output = result.get("output").and_then(|v| v.as_str()).unwrap_or("");
+        out.push_str(&format!("\n```output\n{}\n```\n", output.trim_end_matches('\n')));
+        if outcome != "OUTCOME_OK" {
+            out.push_str(&format!("({})\n", outcome));
+        }
+    }
+    out
+}
+
+/// [NEW] 图像生成请求的 responseModalities 处理
+/// 默认移除 (Cherry Studio 等客户端会发送，可能与 imageConfig 冲突)；
+/// 开启多模态输出时显式请求文本 + 图片
+pub fn apply_image_response_modalities(gen_obj: &mut serde_json::Map<String, Value>) {
+    if crate::proxy::config::get_image_multimodal_output() {
+        gen_obj.insert("responseModalities".to_string(), json!(["TEXT", "IMAGE"]));
+    } else {
+        gen_obj.remove("responseModalities");
+    }
+}
+
 pub fn resolve_request_config(
     original_model: &str,
     mapped_model: &str,
@@ -27,6 +361,35 @@ pub fn resolve_request_config(
 ) -> RequestConfig {
     // 1. Image Generation Check (Priority)
     if mapped_model.starts_with("gemini-3-pro-image") {
+        // [NEW] 文本请求被误映射到图像模型: 告警，并在配置了回退模型时改走文本模型
+        if expects_text_output(original_model, tools, size, quality, image_size, body) {
+            let fallback = crate::proxy::config::get_image_text_fallback_model()
+                .filter(|m| !m.starts_with("gemini-3-pro-image"));
+            match fallback {
+                Some(fallback) => {
+                    tracing::warn!(
+                        "[Common-Utils] {} is mapped to image model {} but the request expects text, falling back to {}",
+                        original_model, mapped_model, fallback
+                    );
+                    return resolve_request_config(
+                        original_model,
+                        &fallback,
+                        tools,
+                        size,
+                        quality,
+                        image_size,
+                        body,
+                    );
+                }
+                None => {
+                    tracing::warn!(
+                        "[Common-Utils] {} is mapped to image model {} but the request expects text (tools and systemInstruction will be dropped). Check your model mapping",
+                        original_model, mapped_model
+                    );
+                }
+            }
+        }
+
         // [RESOLVE #1694] Improved priority logic:
         // 1. First parse inferred config from model suffix and OpenAI parameters
         let (mut inferred_config, parsed_base_model) =
@@ -341,33 +704,154 @@ pub fn inject_google_search_tool(body: &mut Value) {
     }
 }
 
-/// 深度迭代清理客户端发送的 [undefined] 脏字符串，防止 Gemini 接口校验失败
-pub fn deep_clean_undefined(value: &mut Value, depth: usize) {
-    if depth > 10 {
-        return;
+/// 按配置限制 functionDeclarations 数量
+/// 超出上限时记录警告，并根据配置截断 (保留前 N 个) 或返回 InvalidRequest
+pub fn apply_tool_limit(
+    declarations: &mut Vec<Value>,
+    config: &ToolLimitConfig,
+    source: &str,
+) -> Result<(), MapperError> {
+    let max = match config.max_tools {
+        Some(max) if declarations.len() > max => max,
+        _ => return Ok(()),
+    };
+
+    tracing::warn!(
+        "[{}] Tool count {} exceeds configured limit {} (on_exceed={:?})",
+        source,
+        declarations.len(),
+        max,
+        config.on_exceed
+    );
+
+    match config.on_exceed {
+        ToolLimitAction::Truncate => {
+            declarations.truncate(max);
+            Ok(())
+        }
+        ToolLimitAction::Error => Err(MapperError::invalid_request(
+            "tools",
+            format!(
+                "{} tools exceed the configured limit of {}",
+                declarations.len(),
+                max
+            ),
+        )),
+    }
+}
+
+/// 深度清理的移除判定: (键名, 值) -> 是否移除该键
+pub type RemovalPredicate = fn(&str, &Value) -> bool;
+
+/// 值为客户端注入的 "[undefined]" 字符串
+pub fn is_undefined_string(_key: &str, value: &Value) -> bool {
+    value.as_str() == Some("[undefined]")
+}
+
+/// cache_control 字段 (上游不接受)
+pub fn is_cache_control(key: &str, _value: &Value) -> bool {
+    key == "cache_control"
+}
+
+/// thought / thoughtSignature 字段 (thinking 降级时移除)
+pub fn is_thinking_field(key: &str, _value: &Value) -> bool {
+    key == "thought" || key == "thoughtSignature"
+}
+
+/// [NEW] 单次遍历的深度清理: 移除任一判定命中的键，返回移除的键数量
+///
+/// 多种清理合并为一次遍历，避免大体积历史 (数百 KB) 被反复整体遍历；
+/// 各判定只依赖键名和值本身，合并后的结果与依次单独清理一致。
+pub fn deep_clean(
+    value: &mut Value,
+    depth: usize,
+    predicates: &[RemovalPredicate],
+    label: &'static str,
+) -> usize {
+    if predicates.is_empty() {
+        return 0;
+    }
+    deep_clean_inner(value, depth, predicates, &mut JsonWalkGuard::new(label))
+}
+
+fn deep_clean_inner(
+    value: &mut Value,
+    depth: usize,
+    predicates: &[RemovalPredicate],
+    guard: &mut JsonWalkGuard,
+) -> usize {
+    if !guard.enter(depth) {
+        return 0;
     }
     match value {
         Value::Object(map) => {
-            // 移除值为 "[undefined]" 的键
-            map.retain(|_, v| {
-                if let Some(s) = v.as_str() {
-                    s != "[undefined]"
-                } else {
-                    true
-                }
-            });
-            // 递归处理嵌套
+            let before = map.len();
+            map.retain(|k, v| !predicates.iter().any(|p| p(k, v)));
+            let mut removed = before - map.len();
             for v in map.values_mut() {
-                deep_clean_undefined(v, depth + 1);
+                removed += deep_clean_inner(v, depth + 1, predicates, guard);
             }
+            removed
         }
-        Value::Array(arr) => {
-            for v in arr.iter_mut() {
-                deep_clean_undefined(v, depth + 1);
-            }
+        Value::Array(arr) => arr
+            .iter_mut()
+            .map(|v| deep_clean_inner(v, depth + 1, predicates, guard))
+            .sum(),
+        _ => 0,
+    }
+}
+
+/// 深度迭代清理客户端发送的 [undefined] 脏字符串，防止 Gemini 接口校验失败
+pub fn deep_clean_undefined(value: &mut Value, depth: usize) {
+    deep_clean(value, depth, &[is_undefined_string], "deep_clean_undefined");
+}
+
+/// 流式发送工具参数时每个片段的最大字符数
+pub const TOOL_ARGS_FRAGMENT_CHARS: usize = 256;
+
+/// 将序列化后的工具参数按字符边界切分为多个片段，用于流式增量发送
+/// (OpenAI tool_calls[].function.arguments)
+pub fn split_json_fragments(json_str: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut fragments = Vec::new();
+    let mut current = String::new();
+    let mut count = 0;
+    for c in json_str.chars() {
+        current.push(c);
+        count += 1;
+        if count == max_chars {
+            fragments.push(std::mem::take(&mut current));
+            count = 0;
         }
-        _ => {}
     }
+    if !current.is_empty() {
+        fragments.push(current);
+    }
+    fragments
+}
+
+/// Gemini 单次请求允许的 stopSequences 数量上限
+pub const MAX_STOP_SEQUENCES: usize = 5;
+
+/// 合并客户端停止序列与保护性停止序列
+/// 客户端指定的序列优先 (影响输出语义)，随后补充保护性序列；去重、跳过空串并截断到上限
+pub fn merge_stop_sequences(client: &[String], protective: &[String]) -> Vec<String> {
+    let mut merged: Vec<String> = Vec::new();
+    for seq in client.iter().chain(protective.iter()) {
+        if seq.is_empty() || merged.contains(seq) {
+            continue;
+        }
+        if merged.len() >= MAX_STOP_SEQUENCES {
+            tracing::warn!(
+                "[Stop-Sequences] More than {} stop sequences, dropping: {:?}",
+                MAX_STOP_SEQUENCES,
+                seq
+            );
+            continue;
+        }
+        merged.push(seq.clone());
+    }
+    merged
 }
 
 /// Detects if the tool list contains a request for networking/web search.
@@ -494,6 +978,145 @@ pub fn contains_non_networking_tool(tools: &Option<Vec<Value>>) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// 捕获 tracing 输出的写入器
+    #[derive(Clone, Default)]
+    struct CapturedLogs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capture_trace_logs(f: impl FnOnce()) -> String {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, f);
+        let bytes = logs.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    const CLEAN_KEYS: &[&str] = &["cache_control", "thought", "thoughtSignature", "text", "args", "parts", "id"];
+
+    fn random_json(rng: &mut StdRng, depth: usize) -> Value {
+        let leaf = depth >= 5 || rng.gen_bool(0.3);
+        match rng.gen_range(0..if leaf { 4 } else { 6 }) {
+            0 => Value::Null,
+            1 => json!(rng.gen_bool(0.5)),
+            2 => json!(rng.gen_range(-100..100)),
+            3 => json!(if rng.gen_bool(0.3) { "[undefined]" } else { "value" }),
+            4 => Value::Array((0..rng.gen_range(0..4)).map(|_| random_json(rng, depth + 1)).collect()),
+            _ => Value::Object(
+                (0..rng.gen_range(0..5))
+                    .map(|_| {
+                        let key = CLEAN_KEYS[rng.gen_range(0..CLEAN_KEYS.len())].to_string();
+                        (key, random_json(rng, depth + 1))
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// 独立实现的参考清理 (不经过 deep_clean)
+    fn reference_clean(value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                map.retain(|k, v| {
+                    !matches!(k.as_str(), "cache_control" | "thought" | "thoughtSignature")
+                        && v.as_str() != Some("[undefined]")
+                });
+                map.values_mut().for_each(reference_clean);
+            }
+            Value::Array(arr) => arr.iter_mut().for_each(reference_clean),
+            _ => {}
+        }
+    }
+
+    fn clean_sequentially(value: &mut Value) {
+        deep_clean(value, 0, &[is_cache_control], "test_cache_control");
+        deep_clean(value, 0, &[is_thinking_field], "test_thinking");
+        deep_clean_undefined(value, 0);
+    }
+
+    const ALL_PREDICATES: &[RemovalPredicate] = &[is_undefined_string, is_cache_control, is_thinking_field];
+
+    #[test]
+    fn test_merged_deep_clean_matches_sequential_cleaners() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        for _ in 0..500 {
+            let original = random_json(&mut rng, 0);
+
+            let mut merged = original.clone();
+            deep_clean(&mut merged, 0, ALL_PREDICATES, "test_merged");
+            let mut sequential = original.clone();
+            clean_sequentially(&mut sequential);
+            let mut reference = original.clone();
+            reference_clean(&mut reference);
+
+            assert_eq!(merged, sequential, "input: {}", original);
+            assert_eq!(merged, reference, "input: {}", original);
+        }
+    }
+
+    /// 微基准: cargo test --release deep_clean_benchmark -- --ignored
+    #[test]
+    #[ignore]
+    fn deep_clean_benchmark() {
+        let message = |i: usize| {
+            json!({
+                "role": if i % 2 == 0 { "user" } else { "model" },
+                "cache_control": { "type": "ephemeral" },
+                "parts": [
+                    { "text": "x".repeat(200), "thought": true, "thoughtSignature": "s".repeat(300) },
+                    { "text": "[undefined]", "cache_control": { "type": "ephemeral" } },
+                    { "functionCall": { "name": "run", "id": format!("call_{}", i), "args": {
+                        "command": "ls -la",
+                        "options": { "depth": 3, "filters": ["a", "b", "[undefined]"], "note": "[undefined]" }
+                    }}},
+                ]
+            })
+        };
+        let fixture = json!({
+            "request": { "contents": (0..600).map(message).collect::<Vec<_>>() }
+        });
+        let size = fixture.to_string().len();
+        assert!(size > 500 * 1024, "fixture is only {} bytes", size);
+
+        const ROUNDS: u32 = 50;
+        let time = |f: &dyn Fn(&mut Value)| {
+            let mut total = std::time::Duration::ZERO;
+            for _ in 0..ROUNDS {
+                let mut value = fixture.clone();
+                let started = std::time::Instant::now();
+                f(&mut value);
+                total += started.elapsed();
+            }
+            total / ROUNDS
+        };
+        let sequential = time(&clean_sequentially);
+        let merged = time(&|v: &mut Value| {
+            deep_clean(v, 0, ALL_PREDICATES, "bench_merged");
+        });
+        assert!(
+            merged <= sequential,
+            "deep clean of {} KB: sequential {:?}, merged {:?}",
+            size / 1024,
+            sequential,
+            merged
+        );
+    }
 
     #[test]
     fn test_high_quality_model_auto_grounding() {
@@ -544,6 +1167,46 @@ mod tests {
         assert!(!config.inject_google_search);
     }
 
+    #[test]
+    fn test_expects_text_output_detection() {
+        let tools = Some(vec![json!({"name": "read_file", "parameters": {}})]);
+
+        // 文本模型别名 + 工具 → 文本请求
+        assert!(expects_text_output("claude-sonnet-4-5", &tools, None, None, None, None));
+        // 无任何图像参数 → 文本请求
+        assert!(expects_text_output("gpt-4o", &None, None, None, None, None));
+        // 携带图像参数 → 图像请求
+        assert!(!expects_text_output("gpt-4o", &None, Some("1024x1024"), None, None, None));
+        let body = json!({"generationConfig": {"imageConfig": {"aspectRatio": "1:1"}}});
+        assert!(!expects_text_output("my-alias", &None, None, None, None, Some(&body)));
+        // 客户端显式请求图像模型 → 不视为误映射
+        assert!(!expects_text_output("gemini-3-pro-image-4k", &tools, None, None, None, None));
+        assert!(!expects_text_output("dall-e-3", &None, None, None, None, None));
+    }
+
+    #[test]
+    fn test_image_mismapping_fallback() {
+        let tools = Some(vec![json!({"name": "read_file", "parameters": {}})]);
+
+        // 未配置回退模型: 仅告警，仍按图像生成处理
+        crate::proxy::config::update_image_text_fallback_model(None);
+        let config = resolve_request_config("claude-sonnet-4-5", "gemini-3-pro-image", &tools, None, None, None, None);
+        assert_eq!(config.request_type, "image_gen");
+
+        // 配置了回退模型: 改走文本模型
+        crate::proxy::config::update_image_text_fallback_model(Some("gemini-3-flash".to_string()));
+        let config = resolve_request_config("claude-sonnet-4-5", "gemini-3-pro-image", &tools, None, None, None, None);
+        assert_eq!(config.request_type, "agent");
+        assert_eq!(config.final_model, "gemini-3-flash");
+        assert!(config.image_config.is_none());
+
+        // 真实的图像请求不受回退影响
+        let config = resolve_request_config("gemini-3-pro-image", "gemini-3-pro-image", &None, None, None, None, None);
+        assert_eq!(config.request_type, "image_gen");
+
+        crate::proxy::config::update_image_text_fallback_model(None);
+    }
+
     #[test]
     fn test_image_2k_and_ultrawide_config() {
         // Test 2K
@@ -726,4 +1389,181 @@ mod tests {
         assert_eq!(config_3["imageSize"], "4K");
         assert_eq!(config_3["aspectRatio"], "16:9");
     }
+
+    #[test]
+    fn test_envelope_override_validation() {
+        assert!(validate_envelope_override("antigravity-ide").is_ok());
+        assert!(validate_envelope_override("").is_err());
+        assert!(validate_envelope_override("   ").is_err());
+        assert!(validate_envelope_override("客户端").is_err());
+        assert!(validate_envelope_override(&"a".repeat(65)).is_err());
+
+        let params = EnvelopeParams::from_overrides(Some("vscode"), Some("chat"));
+        assert_eq!(params.user_agent(), "vscode");
+        assert_eq!(params.request_type("agent"), "chat");
+
+        let invalid = EnvelopeParams::from_overrides(Some(""), Some("bad\ttype"));
+        assert_eq!(invalid, EnvelopeParams::default());
+        assert_eq!(invalid.user_agent(), DEFAULT_ENVELOPE_USER_AGENT);
+        assert_eq!(invalid.request_type("agent"), "agent");
+    }
+
+    fn oversized_declarations(n: usize) -> Vec<Value> {
+        (0..n)
+            .map(|i| json!({ "name": format!("tool_{}", i), "parameters": { "type": "OBJECT" } }))
+            .collect()
+    }
+
+    #[test]
+    fn test_tool_limit_truncate_mode() {
+        let config = ToolLimitConfig {
+            max_tools: Some(8),
+            on_exceed: ToolLimitAction::Truncate,
+        };
+        let mut decls = oversized_declarations(35);
+        apply_tool_limit(&mut decls, &config, "Test").unwrap();
+        assert_eq!(decls.len(), 8);
+        assert_eq!(decls[0]["name"], "tool_0");
+        assert_eq!(decls[7]["name"], "tool_7");
+
+        // 未设置上限时不做处理
+        let mut decls = oversized_declarations(35);
+        apply_tool_limit(&mut decls, &ToolLimitConfig::default(), "Test").unwrap();
+        assert_eq!(decls.len(), 35);
+    }
+
+    #[test]
+    fn test_tool_limit_error_mode() {
+        let config = ToolLimitConfig {
+            max_tools: Some(8),
+            on_exceed: ToolLimitAction::Error,
+        };
+        let mut decls = oversized_declarations(35);
+        let err = apply_tool_limit(&mut decls, &config, "Test").unwrap_err();
+        assert!(matches!(err, MapperError::InvalidRequest { ref field, .. } if field == "tools"));
+        assert_eq!(decls.len(), 35);
+
+        // 恰好等于上限时不报错
+        let mut decls = oversized_declarations(8);
+        assert!(apply_tool_limit(&mut decls, &config, "Test").is_ok());
+    }
+
+    #[test]
+    fn test_format_code_execution_parts() {
+        let code = json!({ "language": "PYTHON", "code": "print(2 + 2)\n" });
+        assert_eq!(
+            format_code_execution_part(Some(&code), None),
+            "\n```python\nprint(2 + 2)\n```\n"
+        );
+
+        let ok = json!({ "outcome": "OUTCOME_OK", "output": "4\n" });
+        assert_eq!(format_code_execution_part(None, Some(&ok)), "\n```output\n4\n```\n");
+
+        let failed = json!({ "outcome": "OUTCOME_FAILED", "output": "NameError" });
+        assert_eq!(
+            format_code_execution_part(None, Some(&failed)),
+            "\n```output\nNameError\n```\n(OUTCOME_FAILED)\n"
+        );
+
+        // 普通 part 不处理
+        assert!(render_code_execution_part(None, None).is_none());
+    }
+
+    #[test]
+    fn test_merge_stop_sequences_respects_cap() {
+        let protective = vec!["<|user|>".to_string(), "STOP_A".to_string()];
+        let client: Vec<String> = ["x", "y", "STOP_A", "z", ""].iter().map(|s| s.to_string()).collect();
+        let merged = merge_stop_sequences(&client, &protective);
+        // 客户端序列优先，重复项与空串被跳过，配置的保护性序列补在后面
+        assert_eq!(merged, vec!["x", "y", "STOP_A", "z", "<|user|>"]);
+
+        let many: Vec<String> = (0..7).map(|i| format!("s{}", i)).collect();
+        let merged = merge_stop_sequences(&many, &protective);
+        assert_eq!(merged.len(), MAX_STOP_SEQUENCES);
+        assert_eq!(merged[0], "s0");
+
+        // 保护性序列可配置为空
+        assert_eq!(merge_stop_sequences(&[], &[]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_split_json_fragments_on_char_boundaries() {
+        let args = r#"{"text":"你好, world"}"#;
+        let fragments = split_json_fragments(args, 5);
+        assert!(fragments.iter().all(|f| f.chars().count() <= 5));
+        assert_eq!(fragments.concat(), args);
+        assert!(split_json_fragments("", 5).is_empty());
+    }
+
+    #[test]
+    fn test_sanitize_envelope_value_adversarial_inputs() {
+        let truncate = |v: &str| sanitize_envelope_value_with("f", v, 16, EnvelopeSanitizeAction::Truncate);
+        let reject = |v: &str| sanitize_envelope_value_with("f", v, 16, EnvelopeSanitizeAction::Reject);
+
+        let huge = "a".repeat(1024 * 1024);
+        assert_eq!(truncate(&huge).unwrap().unwrap().len(), 16);
+        assert_eq!(truncate("user\nid\r\n").unwrap().as_deref(), Some("userid"));
+        assert_eq!(truncate("user\0id").unwrap().as_deref(), Some("userid"));
+        assert_eq!(truncate(r#"{"a":{"b":[1]}}"#).unwrap(), None);
+        assert_eq!(truncate("\0\n").unwrap(), None);
+        // 多字节字符按字符边界截断
+        assert_eq!(truncate(&"é".repeat(20)).unwrap().unwrap(), "é".repeat(8));
+        assert_eq!(reject("user_abc-123").unwrap().as_deref(), Some("user_abc-123"));
+
+        for value in [huge.as_str(), "user\nid", "user\0id", r#"{"a":{"b":[1]}}"#] {
+            match reject(value) {
+                Err(MapperError::InvalidRequest { field, .. }) => assert_eq!(field, "f"),
+                other => panic!("{:?} should be rejected, got {:?}", &value[..value.len().min(20)], other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_build_request_id_uses_configured_prefix() {
+        let id = build_request_id("ag-claude", "agent");
+        assert!(id.starts_with("ag-claude-"));
+        assert!(uuid::Uuid::parse_str(&id["ag-claude-".len()..]).is_ok());
+
+        let too_long = "p".repeat(MAX_REQUEST_ID_PREFIX_LEN + 1);
+        for invalid in ["", "bad prefix", "x\n", too_long.as_str()] {
+            assert!(build_request_id(invalid, "agent").starts_with("agent-"), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_trace_transformed_body_redacts_secrets() {
+        let signature = "EqQBCkgIARABGAEiQL9x".repeat(8);
+        let image = "iVBORw0KGgoAAAANSUhEUg".repeat(20);
+        let body = json!({
+            "model": "gemini-3-pro",
+            "request": { "contents": [{
+                "role": "model",
+                "parts": [
+                    { "text": "hello from the model" },
+                    { "functionCall": { "name": "read_file", "args": {} }, "thoughtSignature": signature },
+                    { "inlineData": { "mimeType": "image/png", "data": image } }
+                ]
+            }] }
+        });
+
+        // 未携带调试标记时不记录
+        let logs = capture_trace_logs(|| {
+            trace_transformed_body("Claude", &body, &RequestContext::default())
+        });
+        assert!(!logs.contains("Transformed body"));
+
+        let request_ctx = RequestContext {
+            trace_body: true,
+            ..Default::default()
+        };
+        let logs = capture_trace_logs(|| trace_transformed_body("Claude", &body, &request_ctx));
+        assert!(logs.contains("TRACE"));
+        assert!(logs.contains("[Claude-Request] Transformed body (redacted)"));
+        assert!(logs.contains("hello from the model"));
+        assert!(logs.contains("image/png"));
+        assert!(logs.contains(&format!("[redacted signature, {} chars]", signature.len())));
+        assert!(logs.contains(&format!("[{} base64 chars]", image.len())));
+        assert!(!logs.contains(&signature));
+        assert!(!logs.contains(&image));
+    }
 }
@@ -4,15 +4,19 @@
 //! to prevent "Prompt is too long" errors and avoid invalid signatures.
 
 use super::claude::models::{ClaudeRequest, ContentBlock, Message, MessageContent, SystemPrompt};
+use serde_json::Value;
 use tracing::{debug, info};
 
+/// Gemini bills each inline image / file part at a fixed token count
+const MEDIA_PART_TOKENS: u32 = 258;
+
 /// Helper to estimate tokens from text with multi-language awareness
 ///
 /// Improved estimation algorithm:
 /// - ASCII/English: ~4 characters per token
 /// - Unicode/CJK: ~1.5 characters per token (Chinese, Japanese, Korean are tokenized differently)
 /// - Adds 15% safety margin to prevent underestimation
-fn estimate_tokens_from_str(s: &str) -> u32 {
+pub(crate) fn estimate_tokens_from_str(s: &str) -> u32 {
     if s.is_empty() {
         return 0;
     }
@@ -198,6 +202,37 @@ impl ContextManager {
         total
     }
 
+    /// Estimate input tokens for a transformed v1internal request (the inner `request` object)
+    ///
+    /// Unlike `estimate_token_usage`, this counts exactly what the upstream receives:
+    /// injected system parts, converted tool declarations and inline media.
+    pub fn estimate_gemini_request_tokens(request: &Value) -> u32 {
+        let mut total = 0;
+
+        if let Some(parts) = request
+            .pointer("/systemInstruction/parts")
+            .and_then(|p| p.as_array())
+        {
+            total += parts.iter().map(estimate_part_tokens).sum::<u32>();
+        }
+
+        if let Some(contents) = request.get("contents").and_then(|c| c.as_array()) {
+            for content in contents {
+                // Message overhead
+                total += 4;
+                if let Some(parts) = content.get("parts").and_then(|p| p.as_array()) {
+                    total += parts.iter().map(estimate_part_tokens).sum::<u32>();
+                }
+            }
+        }
+
+        if let Some(tools) = request.get("tools") {
+            total += estimate_tokens_from_str(&tools.to_string());
+        }
+
+        total
+    }
+
     // ===== [Layer 2] Thinking Content Compression + Signature Preservation =====
     // Borrowed from learn-claude-code's "append-only log" principle
     // This layer compresses thinking text but PRESERVES signatures
@@ -366,6 +401,22 @@ struct ToolRound {
     indices: Vec<usize>, // All indices in this round
 }
 
+/// Token estimate for one Gemini part (media parts count as a fixed cost)
+fn estimate_part_tokens(part: &Value) -> u32 {
+    if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
+        estimate_tokens_from_str(text)
+    } else if part.get("inlineData").is_some() || part.get("fileData").is_some() {
+        MEDIA_PART_TOKENS
+    } else if let Some(call) = part.get("functionCall") {
+        20 + estimate_tokens_from_str(&call.to_string())
+    } else if let Some(response) = part.get("functionResponse") {
+        10 + estimate_tokens_from_str(&response.to_string())
+    } else {
+        // executableCode / codeExecutionResult etc.
+        estimate_tokens_from_str(&part.to_string())
+    }
+}
+
 /// Identify tool call rounds in the message history
 fn identify_tool_rounds(messages: &[Message]) -> Vec<ToolRound> {
     let mut rounds = Vec::new();
@@ -459,6 +510,8 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         }
     }
 
@@ -551,6 +604,52 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_estimate_transformed_request_tokens() {
+        use crate::proxy::mappers::common_utils::EnvelopeParams;
+        use serde_json::json;
+
+        let req: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "system": "You are a helpful travel assistant.",
+            "messages": [
+                { "role": "user", "content": "What is the capital of France? Answer in one sentence." },
+                { "role": "assistant", "content": "The capital of France is Paris." },
+                { "role": "user", "content": "What's the weather there today?" }
+            ],
+            "tools": [{
+                "name": "get_weather",
+                "description": "Get the current weather for a city",
+                "input_schema": {
+                    "type": "object",
+                    "properties": { "city": { "type": "string" } },
+                    "required": ["city"]
+                }
+            }]
+        }))
+        .unwrap();
+
+        let body = crate::proxy::mappers::claude::transform_claude_request_in(
+            &req,
+            "proj",
+            false,
+            &EnvelopeParams::default(),
+            &Default::default(),
+        )
+        .unwrap();
+        let tokens = ContextManager::estimate_gemini_request_tokens(&body["request"]);
+
+        // 注入的系统提示词 + 三轮短对话 + 一个工具: 几百 token 以内
+        assert!((50..1000).contains(&tokens), "implausible count {}", tokens);
+        // 转换后的请求至少包含客户端请求本身的内容
+        assert!(tokens >= ContextManager::estimate_token_usage(&req));
+
+        assert_eq!(
+            estimate_part_tokens(&json!({ "inlineData": { "mimeType": "image/png", "data": "AAAA" } })),
+            MEDIA_PART_TOKENS
+        );
+    }
+
     #[test]
     fn test_purify_history_aggressive() {
         let mut messages = vec![Message {
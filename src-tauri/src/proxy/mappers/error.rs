@@ -0,0 +1,159 @@
+// Mapper 层错误类型
+// 区分 "客户端请求非法" (4xx) 与 "内部不变量被破坏" (5xx)，供 handler 映射为正确的 HTTP 状态与错误格式
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum MapperError {
+    /// 客户端请求字段非法 (如畸形的工具 schema)
+    #[error("Invalid {field}: {reason}")]
+    InvalidRequest { field: String, reason: String },
+
+    /// 某条消息 (及其中某个内容块) 无法转换，携带位置便于客户端定位
+    #[error(
+        "Invalid messages[{index}]{}: {reason}",
+        .block.map(|b| format!(".content[{}]", b)).unwrap_or_default()
+    )]
+    InvalidMessage {
+        index: usize,
+        block: Option<usize>,
+        reason: String,
+    },
+
+    /// 请求使用了当前上游不支持的功能
+    #[error("Unsupported feature: {name}")]
+    UnsupportedFeature { name: String },
+
+    /// 内部不变量被破坏 (非客户端问题)
+    #[error("{context}")]
+    Internal { context: String },
+}
+
+impl MapperError {
+    pub fn invalid_request(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self::InvalidRequest {
+            field: field.into(),
+            reason: reason.into(),
+        }
+    }
+
+    pub fn invalid_message(index: usize, block: Option<usize>, reason: impl Into<String>) -> Self {
+        Self::InvalidMessage {
+            index,
+            block,
+            reason: reason.into(),
+        }
+    }
+
+    pub fn unsupported(name: impl Into<String>) -> Self {
+        Self::UnsupportedFeature { name: name.into() }
+    }
+
+    pub fn internal(context: impl Into<String>) -> Self {
+        Self::Internal {
+            context: context.into(),
+        }
+    }
+
+    /// 对应的 HTTP 状态码
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidRequest { .. }
+            | Self::InvalidMessage { .. }
+            | Self::UnsupportedFeature { .. } => StatusCode::BAD_REQUEST,
+            Self::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// 换账号/重试是否可能成功 (客户端错误重试无意义)
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Internal { .. })
+    }
+
+    /// Anthropic 错误格式: {"type":"error","error":{"type":...,"message":...}}
+    pub fn to_anthropic_body(&self) -> Value {
+        let error_type = match self {
+            Self::InvalidRequest { .. }
+            | Self::InvalidMessage { .. }
+            | Self::UnsupportedFeature { .. } => "invalid_request_error",
+            Self::Internal { .. } => "api_error",
+        };
+        json!({
+            "type": "error",
+            "error": {
+                "type": error_type,
+                "message": format!("Transform error: {}", self)
+            }
+        })
+    }
+
+    /// OpenAI 错误格式: {"error":{"message":...,"type":...,"param":...}}
+    pub fn to_openai_body(&self) -> Value {
+        let (error_type, param) = match self {
+            Self::InvalidRequest { field, .. } => ("invalid_request_error", Some(field.clone())),
+            Self::InvalidMessage { index, .. } => {
+                ("invalid_request_error", Some(format!("messages[{}]", index)))
+            }
+            Self::UnsupportedFeature { .. } => ("invalid_request_error", None),
+            Self::Internal { .. } => ("server_error", None),
+        };
+        json!({
+            "error": {
+                "message": format!("Transform error: {}", self),
+                "type": error_type,
+                "param": param,
+                "code": null
+            }
+        })
+    }
+}
+
+impl From<serde_json::Error> for MapperError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::internal(format!("JSON serialization failed: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_and_shape_per_variant() {
+        let invalid = MapperError::invalid_request("tools[0].input_schema", "must be a JSON object");
+        assert_eq!(invalid.status_code(), StatusCode::BAD_REQUEST);
+        assert!(!invalid.is_retryable());
+        assert_eq!(invalid.to_string(), "Invalid tools[0].input_schema: must be a JSON object");
+        assert_eq!(invalid.to_anthropic_body()["error"]["type"], "invalid_request_error");
+        assert_eq!(invalid.to_openai_body()["error"]["param"], "tools[0].input_schema");
+
+        let internal = MapperError::internal("inner request is not a JSON object");
+        assert_eq!(internal.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(internal.is_retryable());
+        assert_eq!(internal.to_anthropic_body()["error"]["type"], "api_error");
+        assert_eq!(internal.to_openai_body()["error"]["type"], "server_error");
+    }
+
+    #[test]
+    fn test_invalid_message_display_and_param() {
+        let err = MapperError::invalid_message(3, Some(1), "image source has empty base64 data");
+        assert_eq!(
+            err.to_string(),
+            "Invalid messages[3].content[1]: image source has empty base64 data"
+        );
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        assert!(!err.is_retryable());
+        assert_eq!(err.to_openai_body()["error"]["param"], "messages[3]");
+
+        let err = MapperError::invalid_message(0, None, "unsupported role");
+        assert_eq!(err.to_string(), "Invalid messages[0]: unsupported role");
+    }
+
+    #[test]
+    fn test_serde_error_is_internal() {
+        let err = serde_json::from_str::<Value>("{not json").unwrap_err();
+        assert!(matches!(MapperError::from(err), MapperError::Internal { .. }));
+    }
+}
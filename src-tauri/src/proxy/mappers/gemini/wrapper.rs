@@ -351,7 +351,7 @@ pub fn wrap_request(
                 }
                 
                 gen_obj.remove("responseMimeType");
-                gen_obj.remove("responseModalities"); // Cherry Studio sends this, might conflict
+                crate::proxy::mappers::common_utils::apply_image_response_modalities(gen_obj);
                 gen_obj.insert("imageConfig".to_string(), image_config);
             }
         }
@@ -361,6 +361,8 @@ pub fn wrap_request(
         You are pair programming with a USER to solve their coding task. The task may require creating a new codebase, modifying or debugging an existing codebase, or simply answering a question.\n\
         **Absolute paths only**\n\
         **Proactiveness**";
+        // [NEW] 监听配置档可关闭身份注入
+        let inject_identity = crate::proxy::listener_profile::identity_injection_enabled();
 
         // [HYBRID] 检查是否已有 systemInstruction
         if let Some(system_instruction) = inner_request.get_mut("systemInstruction") {
@@ -381,7 +383,7 @@ pub fn wrap_request(
                         .map(|s| s.contains("You are Antigravity"))
                         .unwrap_or(false);
 
-                    if !has_antigravity {
+                    if !has_antigravity && inject_identity {
                         // 在前面插入 Antigravity 身份
                         parts_array.insert(0, json!({"text": antigravity_identity}));
                     }
@@ -392,7 +394,7 @@ pub fn wrap_request(
                         && !global_prompt_config.content.trim().is_empty()
                     {
                         // 插入位置：Antigravity 身份之后 (index 1)
-                        let insert_pos = if has_antigravity { 1 } else { 1 };
+                        let insert_pos = if has_antigravity || inject_identity { 1 } else { 0 };
                         if insert_pos <= parts_array.len() {
                             parts_array
                                 .insert(insert_pos, json!({"text": global_prompt_config.content}));
@@ -404,22 +406,30 @@ pub fn wrap_request(
             }
         } else {
             // 没有 systemInstruction,创建一个新的
-            let mut parts = vec![json!({"text": antigravity_identity})];
+            let mut parts = Vec::new();
+            if inject_identity {
+                parts.push(json!({"text": antigravity_identity}));
+            }
             // [NEW] 注入全局系统提示词
             let global_prompt_config = crate::proxy::config::get_global_system_prompt();
             if global_prompt_config.enabled && !global_prompt_config.content.trim().is_empty() {
                 parts.push(json!({"text": global_prompt_config.content}));
             }
-            inner_request["systemInstruction"] = json!({
-                "role": "user",
-                "parts": parts
-            });
+            if !parts.is_empty() {
+                inner_request["systemInstruction"] = json!({
+                    "role": "user",
+                    "parts": parts
+                });
+            }
         }
     }
 
     let final_request = json!({
-        "project": project_id,
-        "requestId": format!("agent-{}", uuid::Uuid::new_v4()), // 修正为 agent- 前缀
+        "project": crate::proxy::project_resolver::normalize_project_id(project_id),
+        "requestId": crate::proxy::mappers::common_utils::build_request_id(
+            &crate::proxy::config::get_request_id_prefix_config().gemini,
+            &crate::proxy::config::RequestIdPrefixConfig::default().gemini,
+        ), // 默认 agent- 前缀 (可配置)
         "request": inner_request,
         "model": config.final_model,
         "userAgent": "antigravity",
@@ -527,6 +537,18 @@ mod tests {
         assert!(result["requestId"].as_str().unwrap().starts_with("agent-"));
     }
 
+    #[test]
+    fn test_wrap_request_with_vertex_project_path() {
+        let body = json!({
+            "model": "gemini-2.5-flash",
+            "contents": [{"role": "user", "parts": [{"text": "Hi"}]}]
+        });
+
+        let result = wrap_request(&body, "projects/test-project/locations/us-central1", "gemini-2.5-flash", None);
+        assert_eq!(result["project"], "test-project");
+        assert_eq!(result["model"], "gemini-2.5-flash");
+    }
+
     #[test]
     fn test_unwrap_response() {
         let wrapped = json!({
@@ -724,6 +746,9 @@ mod tests {
             mode: ThinkingBudgetMode::Custom,
             custom_value: 1024, // Distinct value
             effort: None,
+            effort_budgets: Default::default(),
+            default_thinking_budget: None,
+            min_thinking_budget: 256,
         });
 
         let body = json!({
@@ -764,6 +789,9 @@ mod tests {
                 mode: crate::proxy::config::ThinkingBudgetMode::Auto,
                 custom_value: 24576,
                 effort: None,
+                effort_budgets: Default::default(),
+                default_thinking_budget: None,
+                min_thinking_budget: 256,
             },
         );
 
@@ -2,11 +2,14 @@
 // 协议转换器模块
 
 pub mod claude;
+pub mod common;
 pub mod common_utils;
 pub mod context_manager;
+pub mod error;
 pub mod error_classifier;
 pub mod estimation_calibrator;
 pub mod gemini;
 pub mod openai;
+pub mod request_validation;
 pub mod signature_store;
 pub mod tool_result_compressor;
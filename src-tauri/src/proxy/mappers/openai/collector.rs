@@ -22,6 +22,7 @@ where
         model: "unknown".to_string(),
         choices: Vec::new(),
         usage: None,
+        requested_model: None,
     };
 
     let mut role: Option<String> = None;
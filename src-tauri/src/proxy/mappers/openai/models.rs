@@ -16,6 +16,9 @@ pub struct OpenAIRequest {
     pub n: Option<u32>, // [NEW] 支持多候选结果数量
     #[serde(rename = "max_tokens")]
     pub max_tokens: Option<u32>,
+    // [NEW] 新版 OpenAI 客户端使用 max_completion_tokens 代替 max_tokens
+    #[serde(default)]
+    pub max_completion_tokens: Option<u32>,
     pub temperature: Option<f64>,
     #[serde(rename = "top_p")]
     pub top_p: Option<f64>,
@@ -43,6 +46,9 @@ pub struct OpenAIRequest {
     // [NEW] Direct imageSize support (for Gemini native parameter)
     #[serde(default, rename = "imageSize")]
     pub image_size: Option<String>,
+    // [NEW] 终端用户标识，复用为 Gemini sessionId (与 Claude metadata.user_id 一致)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
 }
 
 /// Thinking 配置 (兼容 Anthropic 和 OpenAI 扩展协议)
@@ -129,6 +135,9 @@ pub struct OpenAIResponse {
     pub choices: Vec<Choice>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub usage: Option<OpenAIUsage>,
+    /// [NEW] 客户端原始请求的模型名 (扩展字段，`model` 为实际服务的模型)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_model: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1,13 +1,42 @@
 // OpenAI → Gemini 请求转换
 use super::models::*;
+use crate::proxy::mappers::common::envelope::{apply_client_session_id, build_envelope};
+use crate::proxy::mappers::common::safety::{build_safety_settings_with, SafetyThreshold};
+use crate::proxy::mappers::common::system_builder::{self, IdentityConfig};
+use crate::proxy::mappers::common::thinking_budget;
+use crate::proxy::mappers::common_utils::{EnvelopeParams, RequestContext};
+use crate::proxy::mappers::error::MapperError;
 
 use serde_json::{json, Value};
 
+/// [NEW] 单个请求内联图片 (base64) 的累计上限，按 base64 字节数计
+/// 超出后的 data: / 本地图片直接跳过并告警 (不向提示词注入任何文本)，避免请求体过大被上游拒绝 (http 图片以 fileData 引用，不计入)
+/// 消息因此没有剩余 parts 时由 map_messages 末尾的空 parts 过滤整体丢弃
+pub const MAX_INLINE_IMAGE_BYTES_PER_REQUEST: usize = 20 * 1024 * 1024;
+
+/// 在累计上限内追加 inlineData part，超出上限时跳过该图片
+fn push_inline_image(parts: &mut Vec<Value>, inlined_bytes: &mut usize, mime_type: &str, data: &str) {
+    if *inlined_bytes + data.len() > MAX_INLINE_IMAGE_BYTES_PER_REQUEST {
+        tracing::warn!(
+            "[OpenAI-Request] Skipping inline image ({} bytes): cumulative inline size would exceed {} bytes",
+            data.len(),
+            MAX_INLINE_IMAGE_BYTES_PER_REQUEST
+        );
+        return;
+    }
+    *inlined_bytes += data.len();
+    parts.push(json!({
+        "inlineData": { "mimeType": mime_type, "data": data }
+    }));
+}
+
 pub fn transform_openai_request(
     request: &OpenAIRequest,
     project_id: &str,
     mapped_model: &str,
-) -> (Value, String, usize) {
+    envelope: &EnvelopeParams, // [NEW] Per-account userAgent / requestType overrides
+    request_ctx: &RequestContext, // [NEW] Request-level options from client headers
+) -> Result<(Value, String, usize), MapperError> {
     let session_id = crate::proxy::session_manager::SessionManager::extract_openai_session_id(request);
     let message_count = request.messages.len();
     // 将 OpenAI 工具转为 Value 数组以便探测
@@ -18,74 +47,29 @@ pub fn transform_openai_request(
 
     let mapped_model_lower = mapped_model.to_lowercase();
 
+    // [NEW] imageSize 会原样写入信封的 imageConfig，先清洗
+    let image_size = match request.image_size.as_deref() {
+        Some(raw) => crate::proxy::mappers::common_utils::sanitize_envelope_value(
+            "imageSize",
+            raw,
+            crate::proxy::mappers::common_utils::MAX_ENVELOPE_IMAGE_SIZE_LEN,
+        )?,
+        None => None,
+    };
+
     // Resolve grounding config
-    let config = crate::proxy::mappers::common_utils::resolve_request_config(
+    let mut config = crate::proxy::mappers::common_utils::resolve_request_config(
         &request.model,
         &mapped_model_lower,
         &tools_val,
         request.size.as_deref(),       // [NEW] Pass size parameter
         request.quality.as_deref(),    // [NEW] Pass quality parameter
-        request.image_size.as_deref(), // [FIX] Pass imageSize parameter
+        image_size.as_deref(),         // [FIX] Pass imageSize parameter
         None,  // body
     );
+    config.suppress_google_search(request_ctx.disable_google_search);
 
-    // [FIX] 仅当模型名称显式包含 "-thinking" 时才视为 Gemini 思维模型
-    // 避免对 gemini-3-pro (preview) 等其实不支持 thinkingConfig 的模型注入参数导致 400
-    // [FIX #1557] Allow "pro" models (e.g. gemini-3-pro, gemini-2.0-pro) to bypass thinking check
-    // These models support thinking but do not have "-thinking" suffix
-    let is_gemini_3_thinking = mapped_model_lower.contains("gemini")
-        && (
-            mapped_model_lower.contains("-thinking")
-                || mapped_model_lower.contains("gemini-2.0-pro")
-                || mapped_model_lower.contains("gemini-3-pro")
-        )
-        && !mapped_model_lower.contains("claude");
-    let is_claude_thinking = mapped_model_lower.ends_with("-thinking");
-    let is_thinking_model = is_gemini_3_thinking || is_claude_thinking;
-
-    // [NEW] 检查用户是否在请求中显式启用 thinking
-    let user_enabled_thinking = request.thinking.as_ref()
-        .map(|t| t.thinking_type.as_deref() == Some("enabled"))
-        .unwrap_or(false);
-    let user_thinking_budget = request.thinking.as_ref()
-        .and_then(|t| t.budget_tokens);
-
-    // [NEW] 检查历史消息是否兼容思维模型 (是否有 Assistant 消息缺失 reasoning_content)
-    let has_incompatible_assistant_history = request.messages.iter().any(|msg| {
-        msg.role == "assistant"
-            && msg
-                .reasoning_content
-                .as_ref()
-                .map(|s| s.is_empty())
-                .unwrap_or(true)
-    });
-    let has_tool_history = request.messages.iter().any(|msg| {
-        msg.role == "tool" || msg.role == "function" || msg.tool_calls.is_some()
-    });
-
-
-
-    // [NEW] 决定是否开启 Thinking 功能:
-    // 1. 模型名包含 -thinking 时自动开启
-    // 2. 用户在请求中显式设置 thinking.type = "enabled" 时开启
-    // 如果是 Claude 思考模型且历史不兼容且没有可用签名来占位, 则禁用 Thinking 以防 400
-    let mut actual_include_thinking = is_thinking_model || user_enabled_thinking;
-    
-    // [REFACTORED] 使用 SignatureCache 获取 Session 级别的签名
-    let session_thought_sig = crate::proxy::SignatureCache::global().get_session_signature(&session_id);
-    
-    if is_claude_thinking && has_incompatible_assistant_history && session_thought_sig.is_none() {
-        tracing::warn!("[OpenAI-Thinking] Incompatible assistant history detected for Claude thinking model without session signature. Disabling thinking for this request to avoid 400 error. (sid: {})", session_id);
-        actual_include_thinking = false;
-    }
-    
-    // [NEW] 日志：用户显式设置 thinking
-    if user_enabled_thinking {
-        tracing::info!(
-            "[OpenAI-Thinking] User explicitly enabled thinking with budget: {:?}",
-            user_thinking_budget
-        );
-    }
+    let plan = ThinkingPlan::resolve(request, &mapped_model_lower, &session_id);
 
     tracing::debug!(
         "[Debug] OpenAI Request: original='{}', mapped='{}', type='{}', has_image_config={}",
@@ -95,7 +79,195 @@ pub fn transform_openai_request(
         config.image_config.is_some()
     );
 
-    // 1. 提取所有 System Message 并注入补丁
+    // 1. 提取所有 System Message
+    let system_instructions = extract_system(request);
+
+    // 2. 构建 Gemini contents (过滤掉 system/developer 指令)
+    let contents = map_messages(request, &plan);
+
+    // 3. 构建请求体
+    let gen_config = build_generation_config(
+        request,
+        &plan,
+        mapped_model,
+        &config.request_type,
+        &session_id,
+    );
+
+    let mut inner_request = json!({
+        "contents": contents,
+        "generationConfig": gen_config,
+        // OpenAI 链路不读取 GEMINI_SAFETY_THRESHOLD，未显式覆盖时保持 OFF
+        "safetySettings": build_safety_settings_with(
+            SafetyThreshold::overridden().unwrap_or(SafetyThreshold::Off)
+        ),
+    });
+
+    // 深度清理 [undefined] 字符串 (Cherry Studio 等客户端常见注入)
+    crate::proxy::mappers::common_utils::deep_clean_undefined(&mut inner_request, 0);
+
+    // 4. Handle Tools (Merged Cleaning)
+    if let Some(tools) = &request.tools {
+        let function_declarations = build_tools(tools)?;
+        if !function_declarations.is_empty() {
+            inner_request["tools"] = json!([{ "functionDeclarations": function_declarations }]);
+        }
+    }
+
+    // [NEW] 与 Claude 链路共用的 System Instruction 构建 (身份注入、全局提示词、MCP XML 协议)
+    let has_mcp_tools = system_builder::has_mcp_tools(
+        request
+            .tools
+            .iter()
+            .flatten()
+            .filter_map(|t| t.pointer("/function/name").or_else(|| t.get("name")))
+            .filter_map(|n| n.as_str()),
+    );
+    let user_texts: Vec<&str> = system_instructions.iter().map(String::as_str).collect();
+    let parts = system_builder::build_system_parts(&user_texts, has_mcp_tools, &IdentityConfig::current());
+
+    inner_request["systemInstruction"] = json!({
+        "role": "user",
+        "parts": parts
+    });
+
+    if config.inject_google_search {
+        crate::proxy::mappers::common_utils::inject_google_search_tool(&mut inner_request);
+    }
+
+    if let Some(image_config) = config.image_config {
+        if let Some(obj) = inner_request.as_object_mut() {
+            obj.remove("tools");
+            obj.remove("systemInstruction");
+            let gen_config = obj.entry("generationConfig").or_insert_with(|| json!({}));
+            if let Some(gen_obj) = gen_config.as_object_mut() {
+                // [REMOVED] thinkingConfig 拦截已删除，允许图像生成时输出思维链
+                // gen_obj.remove("thinkingConfig");
+                gen_obj.remove("responseMimeType");
+                crate::proxy::mappers::common_utils::apply_image_response_modalities(gen_obj);
+                gen_obj.insert("imageConfig".to_string(), image_config);
+            }
+        }
+    }
+
+    // 5. 构建信封
+    let request_id = crate::proxy::mappers::common_utils::build_request_id(
+        &crate::proxy::config::get_request_id_prefix_config().openai,
+        &crate::proxy::config::RequestIdPrefixConfig::default().openai,
+    );
+    let mut final_body = build_envelope(
+        project_id,
+        request_id,
+        inner_request,
+        &config.final_model,
+        envelope,
+        &config.request_type,
+    );
+
+    // [NEW] 如果提供了 user，则复用为 sessionId (客户端可控，先清洗)
+    apply_client_session_id(&mut final_body, "user", request.user.as_deref())?;
+    // [NEW] 已学习到不接受哨兵值的上游模型: 移除注入的哨兵值
+    crate::proxy::signature_sentinel::strip_unsupported(&mut final_body);
+    crate::proxy::mappers::common_utils::trace_transformed_body("OpenAI", &final_body, request_ctx);
+
+    Ok((final_body, session_id, message_count))
+}
+
+/// 本次请求的思维模式判定结果
+#[derive(Debug, Clone, Default)]
+struct ThinkingPlan {
+    is_gemini_3_thinking: bool,
+    is_thinking_model: bool,
+    /// 最终是否开启 Thinking
+    include_thinking: bool,
+    /// 用户在请求中显式指定的预算
+    user_budget: Option<u32>,
+    /// 会话级思维签名 (用于历史工具调用)
+    session_signature: Option<String>,
+}
+
+impl ThinkingPlan {
+    fn resolve(request: &OpenAIRequest, mapped_model_lower: &str, session_id: &str) -> Self {
+        // [FIX] 仅当模型名称显式包含 "-thinking" 时才视为 Gemini 思维模型
+        // 避免对 gemini-3-pro (preview) 等其实不支持 thinkingConfig 的模型注入参数导致 400
+        // [FIX #1557] Allow "pro" models (e.g. gemini-3-pro, gemini-2.0-pro) to bypass thinking check
+        // These models support thinking but do not have "-thinking" suffix
+        let is_gemini_3_thinking = mapped_model_lower.contains("gemini")
+            && (
+                mapped_model_lower.contains("-thinking")
+                    || mapped_model_lower.contains("gemini-2.0-pro")
+                    || mapped_model_lower.contains("gemini-3-pro")
+            )
+            && !mapped_model_lower.contains("claude");
+        let is_claude_thinking = mapped_model_lower.ends_with("-thinking");
+        let is_thinking_model = is_gemini_3_thinking || is_claude_thinking;
+
+        // [NEW] 检查用户是否在请求中显式启用 thinking
+        let user_enabled_thinking = request.thinking.as_ref()
+            .map(|t| t.thinking_type.as_deref() == Some("enabled"))
+            .unwrap_or(false);
+        let user_budget = request.thinking.as_ref()
+            .and_then(|t| t.budget_tokens);
+
+        // [NEW] 检查历史消息是否兼容思维模型 (是否有 Assistant 消息缺失 reasoning_content)
+        let has_incompatible_assistant_history = request.messages.iter().any(|msg| {
+            msg.role == "assistant"
+                && msg
+                    .reasoning_content
+                    .as_ref()
+                    .map(|s| s.is_empty())
+                    .unwrap_or(true)
+        });
+
+        // [NEW] 决定是否开启 Thinking 功能:
+        // 1. 模型名包含 -thinking 时自动开启
+        // 2. 用户在请求中显式设置 thinking.type = "enabled" 时开启
+        // 如果是 Claude 思考模型且历史不兼容且没有可用签名来占位, 则禁用 Thinking 以防 400
+        let mut include_thinking = is_thinking_model || user_enabled_thinking;
+
+        // [NEW] 客户端未指定 thinking 时，监听配置档的默认值覆盖模型默认策略 (与 Claude 协议一致)
+        if request.thinking.is_none() {
+            if let Some(enabled) = crate::proxy::listener_profile::default_thinking() {
+                include_thinking = enabled;
+            }
+        }
+
+        // [REFACTORED] 使用 SignatureCache 获取 Session 级别的签名
+        let session_signature = crate::proxy::SignatureCache::global().get_session_signature(session_id);
+
+        if is_claude_thinking && has_incompatible_assistant_history && session_signature.is_none() {
+            tracing::warn!("[OpenAI-Thinking] Incompatible assistant history detected for Claude thinking model without session signature. Disabling thinking for this request to avoid 400 error. (sid: {})", session_id);
+            include_thinking = false;
+        }
+
+        // [NEW] 日志：用户显式设置 thinking
+        if user_enabled_thinking {
+            tracing::info!(
+                "[OpenAI-Thinking] User explicitly enabled thinking with budget: {:?}",
+                user_budget
+            );
+        }
+
+        if let Some(sig) = &session_signature {
+            tracing::debug!(
+                "[OpenAI-Request] Using session signature (sid: {}, len: {})",
+                session_id,
+                sig.len()
+            );
+        }
+
+        Self {
+            is_gemini_3_thinking,
+            is_thinking_model,
+            include_thinking,
+            user_budget,
+            session_signature,
+        }
+    }
+}
+
+/// 提取 system / developer 消息文本；instructions 字段优先
+fn extract_system(request: &OpenAIRequest) -> Vec<String> {
     let mut system_instructions: Vec<String> = request
         .messages
         .iter()
@@ -124,6 +296,14 @@ pub fn transform_openai_request(
             system_instructions.insert(0, inst.clone());
         }
     }
+    system_instructions
+}
+
+/// 将 OpenAI messages 映射为 Gemini contents (不含 system/developer)，并合并连续相同角色
+fn map_messages(request: &OpenAIRequest, plan: &ThinkingPlan) -> Vec<Value> {
+    let has_tool_history = request.messages.iter().any(|msg| {
+        msg.role == "tool" || msg.role == "function" || msg.tool_calls.is_some()
+    });
 
     // Pre-scan to map tool_call_id to function name (for Codex)
     let mut tool_id_to_name = std::collections::HashMap::new();
@@ -141,16 +321,6 @@ pub fn transform_openai_request(
         }
     }
 
-    // 从缓存获取当前会话的思维签名
-    let thought_sig = session_thought_sig;
-    if thought_sig.is_some() {
-        tracing::debug!(
-            "[OpenAI-Request] Using session signature (sid: {}, len: {})",
-            session_id,
-            thought_sig.as_ref().unwrap().len()
-        );
-    }
-
     // [New] 预先构建工具名称到原始 Schema 的映射，用于后续参数类型修正
     let mut tool_name_to_schema = std::collections::HashMap::new();
     if let Some(tools) = &request.tools {
@@ -172,7 +342,10 @@ pub fn transform_openai_request(
         }
     }
 
-    // 2. 构建 Gemini contents (过滤掉 system/developer 指令)
+    let thought_sig = plan.session_signature.as_ref();
+
+    // [NEW] 整个请求累计内联的图片字节数
+    let mut inlined_bytes: usize = 0;
     let contents: Vec<Value> = request
         .messages
         .iter()
@@ -198,7 +371,7 @@ pub fn transform_openai_request(
                     });
                     parts.push(thought_part);
                 }
-            } else if actual_include_thinking && role == "model" {
+            } else if plan.include_thinking && role == "model" {
                 // [FIX] 解决 Claude 4.6 Thinking 模型的强制性校验:
                 // "Expected thinking... but found tool_use/text"
                 // 如果是思维模型且缺失 reasoning_content, 则注入占位符
@@ -210,8 +383,8 @@ pub fn transform_openai_request(
                 
                 // [FIX #1575] 占位符永远不能使用真实签名（签名与真实思考内容绑定）
                 // 仅 Gemini 支持哨兵值跳过验证
-                if is_gemini_3_thinking {
-                    thought_part["thoughtSignature"] = json!("skip_thought_signature_validator");
+                if plan.is_gemini_3_thinking {
+                    thought_part["thoughtSignature"] = json!(crate::proxy::signature_sentinel::SENTINEL);
                 }
                 
                 parts.push(thought_part);
@@ -240,10 +413,8 @@ pub fn transform_openai_request(
                                             let mime_part = &image_url.url[5..pos];
                                             let mime_type = mime_part.split(';').next().unwrap_or("image/jpeg");
                                             let data = &image_url.url[pos + 1..];
-                                            
-                                            parts.push(json!({
-                                                "inlineData": { "mimeType": mime_type, "data": data }
-                                            }));
+
+                                            push_inline_image(&mut parts, &mut inlined_bytes, mime_type, data);
                                         }
                                     } else if image_url.url.starts_with("http") {
                                         parts.push(json!({
@@ -279,9 +450,7 @@ pub fn transform_openai_request(
                                                 "image/jpeg"
                                             };
                                             
-                                            parts.push(json!({
-                                                "inlineData": { "mimeType": mime_type, "data": b64 }
-                                            }));
+                                            push_inline_image(&mut parts, &mut inlined_bytes, mime_type, &b64);
                                             tracing::debug!("[OpenAI-Request] Successfully loaded image: {} ({} bytes)", file_path, file_bytes.len());
                                         } else {
                                             tracing::debug!("[OpenAI-Request] Failed to read local image: {}", file_path);
@@ -332,11 +501,11 @@ pub fn transform_openai_request(
 
                     if let Some(ref sig) = thought_sig {
                         func_call_part["thoughtSignature"] = json!(sig);
-                    } else if is_thinking_model {
+                    } else if plan.is_thinking_model {
                         // [NEW] Handle missing signature for Gemini thinking models
                         // [FIX #1650] Allow sentinel injection for Vertex AI (projects/...) as well
                         tracing::debug!("[OpenAI-Signature] Adding GEMINI_SKIP_SIGNATURE for tool_use: {}", tc.id);
-                        func_call_part["thoughtSignature"] = json!("skip_thought_signature_validator");
+                        func_call_part["thoughtSignature"] = json!(crate::proxy::signature_sentinel::SENTINEL);
                     }
 
                     parts.push(func_call_part);
@@ -373,7 +542,7 @@ pub fn transform_openai_request(
     // [FIX #1575] 针对思维模型的历史故障恢复
     // 在带有工具的历史记录中，剥离旧的思考块，防止 API 因签名失效或结构冲突报 400
     let mut contents = contents;
-    if actual_include_thinking && has_tool_history {
+    if plan.include_thinking && has_tool_history {
         tracing::debug!("[OpenAI-Thinking] Applied thinking recovery (stripping old thought blocks) for tool history");
         contents = super::thinking_recovery::strip_all_thinking_blocks(contents);
     }
@@ -394,18 +563,28 @@ pub fn transform_openai_request(
         }
         merged_contents.push(msg);
     }
-    let contents = merged_contents;
-
-    // 3. 构建请求体
+    merged_contents
+}
 
+/// 构建 generationConfig (采样参数、会话覆盖、thinkingConfig、停止序列、响应格式)
+fn build_generation_config(
+    request: &OpenAIRequest,
+    plan: &ThinkingPlan,
+    mapped_model: &str,
+    request_type: &str,
+    session_id: &str,
+) -> Value {
     let mut gen_config = json!({
         "temperature": request.temperature.unwrap_or(1.0),
         "topP": request.top_p.unwrap_or(0.95), // Gemini default is usually 0.95
     });
 
+    // [NEW] max_tokens 缺省时回退到 max_completion_tokens
+    let max_tokens = request.max_tokens.or(request.max_completion_tokens);
+
     // [FIX] 移除默认的 81920 maxOutputTokens，防止非思维模型 (如 claude-sonnet-4-5) 报 400 Invalid Argument
     // 仅在用户显式提供时设置
-    if let Some(max_tokens) = request.max_tokens {
+    if let Some(max_tokens) = max_tokens {
          gen_config["maxOutputTokens"] = json!(max_tokens);
     }
 
@@ -414,12 +593,17 @@ pub fn transform_openai_request(
         gen_config["candidateCount"] = json!(n);
     }
 
+    // [NEW] 会话级生成参数覆盖 (优先于单次请求参数，思维预算校验仍在其后执行)
+    if crate::proxy::session_manager::SessionGenerationOverrides::global().apply(session_id, &mut gen_config) {
+        tracing::debug!("[OpenAI-Request] Applied session generation override for {}", session_id);
+    }
+
     // 为 thinking 模型注入 thinkingConfig (使用 thinkingBudget 而非 thinkingLevel)
-    if actual_include_thinking {
+    if plan.include_thinking {
         // [RESOLVE #1694] Check image thinking mode
         let image_thinking_mode = crate::proxy::config::get_image_thinking_mode();
         // Only disable if mode is explicitly "disabled" AND it's an image generation request
-        let is_image_gen_disabled = config.request_type == "image_gen" && image_thinking_mode == "disabled";
+        let is_image_gen_disabled = request_type == "image_gen" && image_thinking_mode == "disabled";
 
         if is_image_gen_disabled {
             tracing::debug!("[OpenAI-Request] Image thinking mode disabled: enforcing includeThoughts=false for {}", mapped_model);
@@ -429,61 +613,23 @@ pub fn transform_openai_request(
         } else {
             // [CONFIGURABLE] 根据用户配置决定 thinking_budget 处理方式
             let tb_config = crate::proxy::config::get_thinking_budget_config();
-            // [FIX #1592] 下调默认 budget 到 24576，以更好地兼容不支持 32k 的 Gemini 原生模型 (如 gemini-3-pro)
-            let user_budget: i64 = user_thinking_budget.map(|b| b as i64).unwrap_or(24576);
-            
-            let budget = match tb_config.mode {
-                crate::proxy::config::ThinkingBudgetMode::Passthrough => {
-                    // 透传模式：使用用户传入的值，不做任何限制
-                    tracing::debug!(
-                        "[OpenAI-Request] Passthrough mode: using caller's budget {}",
-                        user_budget
-                    );
-                    user_budget
-                }
-                crate::proxy::config::ThinkingBudgetMode::Custom => {
-                    // 自定义模式：使用全局配置的固定值
-                    let mut custom_value = tb_config.custom_value as i64;
-                    
-                    // [FIX #1592/1602] 针对 Gemini 类模型强制执行 24576 上限 (除画图模型外，见用户反馈)
-                    let is_gemini_limited = (mapped_model_lower.contains("gemini") && !mapped_model_lower.contains("-image"))
-                        || is_claude_thinking; 
-
-                    if is_gemini_limited && custom_value > 24576 {
-                        tracing::warn!(
-                            "[OpenAI-Request] Custom mode: capping thinking_budget from {} to 24576 for Gemini model {}",
-                            custom_value, mapped_model
-                        );
-                        custom_value = 24576;
-                    }
+            // [FIX #1592] 默认 budget 24576，以更好地兼容不支持 32k 的 Gemini 原生模型 (如 gemini-3-pro)
+            // [NEW] 默认值可通过 thinking_budget.default_thinking_budget 配置
+            let user_budget: i64 = plan
+                .user_budget
+                .or(tb_config.default_thinking_budget)
+                .unwrap_or(24576) as i64;
+
+            let budget = thinking_budget::resolve_thinking_budget(
+                user_budget,
+                mapped_model,
+                thinking_budget::is_openai_budget_capped_model(mapped_model),
+                &tb_config,
+                "OpenAI-Request",
+            );
 
-                    tracing::debug!(
-                        "[OpenAI-Request] Custom mode: overriding {} with fixed value {}",
-                        user_budget,
-                        custom_value
-                    );
-                    custom_value
-                }
-                crate::proxy::config::ThinkingBudgetMode::Auto => {
-                    // [FIX #1592] 拓宽判定逻辑，确保所有 Gemini 思考模型都应用 24k 上限 (除画画模型外)
-                    let is_gemini_limited = (mapped_model_lower.contains("gemini") && !mapped_model_lower.contains("-image"))
-                        || is_claude_thinking;  
-                    
-                    if is_gemini_limited && user_budget > 24576 {
-                        tracing::info!(
-                            "[OpenAI-Request] Auto mode: capping thinking budget from {} to 24576 for model: {}", 
-                            user_budget, mapped_model
-                        );
-                        24576
-                    } else {
-                        user_budget
-                    }
-                }
-                crate::proxy::config::ThinkingBudgetMode::Adaptive => {
-                    // Adaptive 模式暂时使用默认值,实际的 adaptive 逻辑在后续处理
-                    user_budget
-                }
-            };
+            // [NEW] 防止 0 或过小的预算导致 Gemini 拒绝请求或不产生思考
+            let budget = tb_config.apply_budget_floor(budget);
 
             gen_config["thinkingConfig"] = json!({
                 "includeThoughts": true,
@@ -492,10 +638,10 @@ pub fn transform_openai_request(
 
             // [CRITICAL] 思维模型的 maxOutputTokens 必须大于 thinkingBudget
             // [FIX #1675] 针对图像模型使用更保守的 max_tokens 增量，避免触发 128k 限制
-            let overhead = if config.request_type == "image_gen" { 2048 } else { 32768 };
-            let min_overhead = if config.request_type == "image_gen" { 1024 } else { 8192 };
+            let overhead = if request_type == "image_gen" { 2048 } else { 32768 };
+            let min_overhead = if request_type == "image_gen" { 1024 } else { 8192 };
 
-            if let Some(max_tokens) = request.max_tokens {
+            if let Some(max_tokens) = max_tokens {
                  if (max_tokens as i64) <= budget {
                      gen_config["maxOutputTokens"] = json!(budget + min_overhead);
                  }
@@ -503,13 +649,13 @@ pub fn transform_openai_request(
                  // [FIX #1592] Use a more conservative default to avoid 400 error on 128k context models
                  gen_config["maxOutputTokens"] = json!(budget + overhead);
             }
-            
+
             let new_max = gen_config["maxOutputTokens"].as_i64().unwrap_or(0);
             tracing::debug!(
                 "[OpenAI-Request] Adjusted maxOutputTokens to {} for thinking model (budget={})",
                 new_max, budget
             );
-            
+
             tracing::debug!(
                 "[OpenAI-Request] Injected thinkingConfig for model {}: thinkingBudget={} (mode={:?})",
                 mapped_model, budget, tb_config.mode
@@ -517,12 +663,18 @@ pub fn transform_openai_request(
         }
     }
 
-    if let Some(stop) = &request.stop {
-        if stop.is_string() {
-            gen_config["stopSequences"] = json!([stop]);
-        } else if stop.is_array() {
-            gen_config["stopSequences"] = stop.clone();
-        }
+    // [NEW] 客户端 stop 与配置的保护性停止序列合并 (上限 5 个)
+    let client_stop: Vec<String> = match &request.stop {
+        Some(Value::String(s)) => vec![s.clone()],
+        Some(Value::Array(arr)) => arr.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect(),
+        _ => Vec::new(),
+    };
+    let stop_sequences = crate::proxy::mappers::common_utils::merge_stop_sequences(
+        &client_stop,
+        &crate::proxy::config::get_protective_stop_sequences(),
+    );
+    if !stop_sequences.is_empty() {
+        gen_config["stopSequences"] = json!(stop_sequences);
     }
 
     if let Some(fmt) = &request.response_format {
@@ -531,177 +683,131 @@ pub fn transform_openai_request(
         }
     }
 
-    let mut inner_request = json!({
-        "contents": contents,
-        "generationConfig": gen_config,
-        "safetySettings": [
-            { "category": "HARM_CATEGORY_HARASSMENT", "threshold": "OFF" },
-            { "category": "HARM_CATEGORY_HATE_SPEECH", "threshold": "OFF" },
-            { "category": "HARM_CATEGORY_SEXUALLY_EXPLICIT", "threshold": "OFF" },
-            { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "threshold": "OFF" },
-            { "category": "HARM_CATEGORY_CIVIC_INTEGRITY", "threshold": "OFF" },
-        ]
-    });
-
-    // 深度清理 [undefined] 字符串 (Cherry Studio 等客户端常见注入)
-    crate::proxy::mappers::common_utils::deep_clean_undefined(&mut inner_request, 0);
-
-    // 4. Handle Tools (Merged Cleaning)
-    if let Some(tools) = &request.tools {
-        let mut function_declarations: Vec<Value> = Vec::new();
-        for tool in tools.iter() {
-            let mut gemini_func = if let Some(func) = tool.get("function") {
-                func.clone()
-            } else {
-                let mut func = tool.clone();
-                if let Some(obj) = func.as_object_mut() {
-                    obj.remove("type");
-                    obj.remove("strict");
-                    obj.remove("additionalProperties");
-                }
-                func
-            };
-
-            let name_opt = gemini_func.get("name").and_then(|v| v.as_str()).map(|s| s.to_string());
-
-            if let Some(name) = &name_opt {
-                // 跳过内置联网工具名称，避免重复定义
-                if name == "web_search" || name == "google_search" || name == "web_search_20250305"
-                {
-                    continue;
-                }
+    gen_config
+}
 
-                if name == "local_shell_call" {
-                    if let Some(obj) = gemini_func.as_object_mut() {
-                        obj.insert("name".to_string(), json!("shell"));
-                    }
-                }
-            } else {
-                 // [FIX] 如果工具没有名称，视为无效工具直接跳过 (防止 REQUIRED_FIELD_MISSING)
-                 tracing::warn!("[OpenAI-Request] Skipping tool without name: {:?}", gemini_func);
-                 continue;
-            }
+/// [NEW] 收集客户端工具的 parameters schema (工具名 -> schema)，用于修正上游返回的工具参数
+pub fn collect_tool_schemas(tools: &Option<Vec<Value>>) -> std::collections::HashMap<String, Value> {
+    tools
+        .iter()
+        .flatten()
+        .filter_map(|tool| {
+            let func = tool.get("function").unwrap_or(tool);
+            let name = func.get("name")?.as_str()?.to_string();
+            Some((name, func.get("parameters")?.clone()))
+        })
+        .collect()
+}
 
-            // [NEW CRITICAL FIX] 清除函数定义根层级的非法字段 (解决报错持久化)
-            if let Some(obj) = gemini_func.as_object_mut() {
-                obj.remove("format");
+/// 将 OpenAI tools 转为 Gemini functionDeclarations (清洗 schema、补全缺失参数、执行数量上限)
+fn build_tools(tools: &[Value]) -> Result<Vec<Value>, MapperError> {
+    let mut function_declarations: Vec<Value> = Vec::new();
+    for (idx, tool) in tools.iter().enumerate() {
+        let mut gemini_func = if let Some(func) = tool.get("function") {
+            func.clone()
+        } else {
+            let mut func = tool.clone();
+            if let Some(obj) = func.as_object_mut() {
+                obj.remove("type");
                 obj.remove("strict");
                 obj.remove("additionalProperties");
-                obj.remove("type"); // [NEW] Gemini 不支持在 FunctionDeclaration 根层级出现 type: "function"
-                obj.remove("external_web_access"); // [FIX #1278] Remove invalid field injected by OpenAI Codex
             }
+            func
+        };
 
-            if let Some(params) = gemini_func.get_mut("parameters") {
-                // [DEEP FIX] 统一调用公共库清洗：展开 $ref 并剔除所有层级的 format/definitions
-                crate::proxy::common::json_schema::clean_json_schema(params);
+        let name_opt = gemini_func.get("name").and_then(|v| v.as_str()).map(|s| s.to_string());
 
-                // Gemini v1internal 要求：
-                // 1. type 必须是大写 (OBJECT, STRING 等)
-                // 2. 根对象必须有 "type": "OBJECT"
-                if let Some(params_obj) = params.as_object_mut() {
-                    if !params_obj.contains_key("type") {
-                        params_obj.insert("type".to_string(), json!("OBJECT"));
-                    }
-                }
+        if let Some(name) = &name_opt {
+            // 跳过内置联网工具名称，避免重复定义
+            if name == "web_search" || name == "google_search" || name == "web_search_20250305"
+            {
+                continue;
+            }
 
-                // 递归转换 type 为大写 (符合 Protobuf 定义)
-                enforce_uppercase_types(params);
-            } else {
-                // [FIX] 针对自定义工具 (如 apply_patch) 补全缺失的参数模式
-                // 解决 Vertex AI (Claude) 报错: tools.5.custom.input_schema: Field required
-                tracing::debug!(
-                    "[OpenAI-Request] Injecting default schema for custom tool: {}",
-                    gemini_func
-                        .get("name")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("unknown")
-                );
-
-                gemini_func.as_object_mut().unwrap().insert(
-                    "parameters".to_string(),
-                    json!({
-                        "type": "OBJECT",
-                        "properties": {
-                            "content": {
-                                "type": "STRING",
-                                "description": "The raw content or patch to be applied"
-                            }
-                        },
-                        "required": ["content"]
-                    }),
-                );
+            if name == "local_shell_call" {
+                if let Some(obj) = gemini_func.as_object_mut() {
+                    obj.insert("name".to_string(), json!("shell"));
+                }
             }
-            function_declarations.push(gemini_func);
+        } else {
+             // [FIX] 如果工具没有名称，视为无效工具直接跳过 (防止 REQUIRED_FIELD_MISSING)
+             tracing::warn!("[OpenAI-Request] Skipping tool without name: {:?}", gemini_func);
+             continue;
         }
 
-        if !function_declarations.is_empty() {
-            inner_request["tools"] = json!([{ "functionDeclarations": function_declarations }]);
+        // [NEW CRITICAL FIX] 清除函数定义根层级的非法字段 (解决报错持久化)
+        if let Some(obj) = gemini_func.as_object_mut() {
+            obj.remove("format");
+            obj.remove("strict");
+            obj.remove("additionalProperties");
+            obj.remove("type"); // [NEW] Gemini 不支持在 FunctionDeclaration 根层级出现 type: "function"
+            obj.remove("external_web_access"); // [FIX #1278] Remove invalid field injected by OpenAI Codex
         }
-    }
-
-    // [NEW] Antigravity 身份指令 (原始简化版)
-    let antigravity_identity = "You are Antigravity, a powerful agentic AI coding assistant designed by the Google Deepmind team working on Advanced Agentic Coding.\n\
-    You are pair programming with a USER to solve their coding task. The task may require creating a new codebase, modifying or debugging an existing codebase, or simply answering a question.\n\
-    **Absolute paths only**\n\
-    **Proactiveness**";
-
-    // [HYBRID] 检查用户是否已提供 Antigravity 身份
-    let user_has_antigravity = system_instructions
-        .iter()
-        .any(|s| s.contains("You are Antigravity"));
 
-    let mut parts = Vec::new();
-
-    // 1. Antigravity 身份 (如果需要, 作为独立 Part 插入)
-    if !user_has_antigravity {
-        parts.push(json!({"text": antigravity_identity}));
-    }
-
-    // 2. [NEW] 注入全局系统提示词 (紧跟 Antigravity 身份之后)
-    let global_prompt_config = crate::proxy::config::get_global_system_prompt();
-    if global_prompt_config.enabled && !global_prompt_config.content.trim().is_empty() {
-        parts.push(json!({"text": global_prompt_config.content}));
-    }
+        // [NEW] 畸形 schema (非对象) 属于客户端错误，直接返回 400 而不是让上游报错
+        if let Some(params) = gemini_func.get("parameters") {
+            if !params.is_object() {
+                return Err(MapperError::invalid_request(
+                    format!("tools[{}].function.parameters", idx),
+                    format!(
+                        "schema for tool '{}' must be a JSON object",
+                        name_opt.as_deref().unwrap_or("unknown")
+                    ),
+                ));
+            }
+        }
 
-    // 3. 追加用户指令 (作为独立 Parts)
-    for inst in system_instructions {
-        parts.push(json!({"text": inst}));
-    }
+        if let Some(params) = gemini_func.get_mut("parameters") {
+            // [DEEP FIX] 统一调用公共库清洗：展开 $ref 并剔除所有层级的 format/definitions
+            crate::proxy::common::json_schema::clean_json_schema(params);
 
-    inner_request["systemInstruction"] = json!({
-        "role": "user",
-        "parts": parts
-    });
+            // Gemini v1internal 要求：
+            // 1. type 必须是大写 (OBJECT, STRING 等)
+            // 2. 根对象必须有 "type": "OBJECT"
+            if let Some(params_obj) = params.as_object_mut() {
+                if !params_obj.contains_key("type") {
+                    params_obj.insert("type".to_string(), json!("OBJECT"));
+                }
+            }
 
-    if config.inject_google_search {
-        crate::proxy::mappers::common_utils::inject_google_search_tool(&mut inner_request);
-    }
+            // 递归转换 type 为大写 (符合 Protobuf 定义)
+            enforce_uppercase_types(params);
+        } else {
+            // [FIX] 针对自定义工具 (如 apply_patch) 补全缺失的参数模式
+            // 解决 Vertex AI (Claude) 报错: tools.5.custom.input_schema: Field required
+            tracing::debug!(
+                "[OpenAI-Request] Injecting default schema for custom tool: {}",
+                gemini_func
+                    .get("name")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("unknown")
+            );
 
-    if let Some(image_config) = config.image_config {
-        if let Some(obj) = inner_request.as_object_mut() {
-            obj.remove("tools");
-            obj.remove("systemInstruction");
-            let gen_config = obj.entry("generationConfig").or_insert_with(|| json!({}));
-            if let Some(gen_obj) = gen_config.as_object_mut() {
-                // [REMOVED] thinkingConfig 拦截已删除，允许图像生成时输出思维链
-                // gen_obj.remove("thinkingConfig");
-                gen_obj.remove("responseMimeType");
-                gen_obj.remove("responseModalities");
-                gen_obj.insert("imageConfig".to_string(), image_config);
-            }
+            gemini_func.as_object_mut().unwrap().insert(
+                "parameters".to_string(),
+                json!({
+                    "type": "OBJECT",
+                    "properties": {
+                        "content": {
+                            "type": "STRING",
+                            "description": "The raw content or patch to be applied"
+                        }
+                    },
+                    "required": ["content"]
+                }),
+            );
         }
+        function_declarations.push(gemini_func);
     }
 
-    let final_body = json!({
-        "project": project_id,
-        "requestId": format!("openai-{}", uuid::Uuid::new_v4()),
-        "request": inner_request,
-        "model": config.final_model,
-        "userAgent": "antigravity",
-        "requestType": config.request_type
-    });
+    // [NEW] 工具数量上限 (可配置截断或报错)
+    crate::proxy::mappers::common_utils::apply_tool_limit(
+        &mut function_declarations,
+        &crate::proxy::config::get_tool_limit_config(),
+        "OpenAI-Request",
+    )?;
 
-    (final_body, session_id, message_count)
+    Ok(function_declarations)
 }
 
 fn enforce_uppercase_types(value: &mut Value) {
@@ -750,6 +856,7 @@ mod tests {
             stream: false,
             n: None,
             max_tokens: None,
+            max_completion_tokens: None,
             temperature: None,
             top_p: None,
             stop: None,
@@ -763,11 +870,13 @@ mod tests {
             size: None,
             quality: None,
             person_generation: None,
+            image_size: None,
+            user: None,
             thinking: None,
         };
 
         // Auto mode (default) should cap gemini-3-pro thinking budget to 24576
-        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-v", "gemini-3-pro");
+        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-v", "gemini-3-pro", &EnvelopeParams::default(), &Default::default()).unwrap();
         let budget = result["request"]["generationConfig"]["thinkingConfig"]["thinkingBudget"]
             .as_i64()
             .unwrap();
@@ -783,6 +892,10 @@ mod tests {
         update_thinking_budget_config(ThinkingBudgetConfig {
             mode: ThinkingBudgetMode::Custom,
             custom_value: 32000,
+            effort: None,
+            effort_budgets: Default::default(),
+            default_thinking_budget: None,
+            min_thinking_budget: 256,
         });
 
         let req = OpenAIRequest {
@@ -798,6 +911,7 @@ mod tests {
             stream: false,
             n: None,
             max_tokens: None,
+            max_completion_tokens: None,
             temperature: None,
             top_p: None,
             stop: None,
@@ -811,11 +925,13 @@ mod tests {
             size: None,
             quality: None,
             person_generation: None,
+            image_size: None,
+            user: None,
             thinking: None,
         };
 
         // 验证针对 Gemini 模型即使是 Custom 模式也会被修正为 24576
-        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-v", "gemini-2.0-flash-thinking");
+        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-v", "gemini-2.0-flash-thinking", &EnvelopeParams::default(), &Default::default()).unwrap();
         let budget = result["request"]["generationConfig"]["thinkingConfig"]["thinkingBudget"]
             .as_i64()
             .unwrap();
@@ -823,7 +939,7 @@ mod tests {
 
         // 验证非 Gemini 模型（如 Claude 原生路径，假设映射后名不含 gemini）则不应截断
         // 注意：这里的 transform_openai_request 第三个参数是 mapped_model
-        let (result_claude, _, _) = transform_openai_request(&req, "test-v", "claude-3-7-sonnet");
+        let (result_claude, _, _) = transform_openai_request(&req, "test-v", "claude-3-7-sonnet", &EnvelopeParams::default(), &Default::default()).unwrap();
         let budget_claude = result_claude["request"]["generationConfig"]["thinkingConfig"]["thinkingBudget"]
             .as_i64();
         // 如果不是 gemini 模型且协议中没带 thinking 配置，可能会是 None 或 32000
@@ -834,6 +950,112 @@ mod tests {
         update_thinking_budget_config(ThinkingBudgetConfig::default());
     }
 
+    #[test]
+    fn test_configured_default_thinking_budget_used_when_omitted() {
+        use crate::proxy::config::{ThinkingBudgetConfig, ThinkingBudgetMode, update_thinking_budget_config};
+
+        update_thinking_budget_config(ThinkingBudgetConfig {
+            mode: ThinkingBudgetMode::Passthrough,
+            default_thinking_budget: Some(12000),
+            ..Default::default()
+        });
+
+        let req = OpenAIRequest {
+            model: "gemini-2.0-flash-thinking".to_string(),
+            messages: vec![OpenAIMessage {
+                role: "user".to_string(),
+                content: Some(OpenAIContent::String("test".into())),
+                reasoning_content: None,
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+            }],
+            stream: false,
+            n: None,
+            max_tokens: None,
+            max_completion_tokens: None,
+            temperature: None,
+            top_p: None,
+            stop: None,
+            response_format: None,
+            tools: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            instructions: None,
+            input: None,
+            prompt: None,
+            size: None,
+            quality: None,
+            person_generation: None,
+            image_size: None,
+            user: None,
+            thinking: None,
+        };
+
+        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-v", "gemini-2.0-flash-thinking", &EnvelopeParams::default(), &Default::default()).unwrap();
+        let budget = result["request"]["generationConfig"]["thinkingConfig"]["thinkingBudget"]
+            .as_i64()
+            .unwrap();
+        assert_eq!(budget, 12000);
+
+        update_thinking_budget_config(ThinkingBudgetConfig::default());
+    }
+
+    #[test]
+    fn test_zero_thinking_budget_raised_to_floor() {
+        use crate::proxy::config::{ThinkingBudgetConfig, ThinkingBudgetMode, update_thinking_budget_config};
+
+        update_thinking_budget_config(ThinkingBudgetConfig {
+            mode: ThinkingBudgetMode::Passthrough,
+            min_thinking_budget: 256,
+            ..Default::default()
+        });
+
+        let req = OpenAIRequest {
+            model: "gemini-2.0-flash-thinking".to_string(),
+            messages: vec![OpenAIMessage {
+                role: "user".to_string(),
+                content: Some(OpenAIContent::String("test".into())),
+                reasoning_content: None,
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+            }],
+            stream: false,
+            n: None,
+            max_tokens: None,
+            max_completion_tokens: None,
+            temperature: None,
+            top_p: None,
+            stop: None,
+            response_format: None,
+            tools: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            instructions: None,
+            input: None,
+            prompt: None,
+            size: None,
+            quality: None,
+            person_generation: None,
+            image_size: None,
+            user: None,
+            thinking: Some(crate::proxy::mappers::openai::models::ThinkingConfig {
+                thinking_type: Some("enabled".to_string()),
+                budget_tokens: Some(0),
+                effort: None,
+            }),
+        };
+
+        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-v", "gemini-2.0-flash-thinking", &EnvelopeParams::default(), &Default::default()).unwrap();
+        let budget = result["request"]["generationConfig"]["thinkingConfig"]["thinkingBudget"]
+            .as_i64()
+            .unwrap();
+        assert_eq!(budget, 256);
+
+        update_thinking_budget_config(ThinkingBudgetConfig::default());
+    }
+
     #[test]
     fn test_transform_openai_request_multimodal() {
         let req = OpenAIRequest {
@@ -855,6 +1077,7 @@ mod tests {
             stream: false,
             n: None,
             max_tokens: None,
+            max_completion_tokens: None,
             temperature: None,
             top_p: None,
             stop: None,
@@ -868,10 +1091,12 @@ mod tests {
             size: None,
             quality: None,
             person_generation: None,
+            image_size: None,
+            user: None,
             thinking: None,
         };
 
-        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-v", "gemini-1.5-flash");
+        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-v", "gemini-1.5-flash", &EnvelopeParams::default(), &Default::default()).unwrap();
         let parts = &result["request"]["contents"][0]["parts"];
         assert_eq!(parts.as_array().unwrap().len(), 2);
         assert_eq!(parts[0]["text"].as_str().unwrap(), "What is in this image?");
@@ -881,6 +1106,65 @@ mod tests {
         );
     }
     
+    #[test]
+    fn test_inline_image_cumulative_cap() {
+        // 三张 7MB 图片 + 一张 http 图片: 前两张内联，第三张超出上限被跳过，http 图片不受影响
+        let image_size = 7 * 1024 * 1024;
+        let data_url = |seed: char| format!("data:image/png;base64,{}", seed.to_string().repeat(image_size));
+        let image = |url: String| OpenAIContentBlock::ImageUrl { image_url: OpenAIImageUrl { url, detail: None } };
+
+        let req = OpenAIRequest {
+            model: "gpt-4-vision".to_string(),
+            messages: vec![OpenAIMessage {
+                role: "user".to_string(),
+                content: Some(OpenAIContent::Array(vec![
+                    OpenAIContentBlock::Text { text: "Compare these".to_string() },
+                    image(data_url('A')),
+                    image("https://example.com/cat.jpg".to_string()),
+                    image(data_url('B')),
+                    image(data_url('C')),
+                ])),
+                reasoning_content: None,
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+            }],
+            stream: false,
+            n: None,
+            max_tokens: None,
+            max_completion_tokens: None,
+            temperature: None,
+            top_p: None,
+            stop: None,
+            response_format: None,
+            tools: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            instructions: None,
+            input: None,
+            prompt: None,
+            size: None,
+            quality: None,
+            person_generation: None,
+            image_size: None,
+            user: None,
+            thinking: None,
+        };
+
+        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-v", "gemini-1.5-flash", &EnvelopeParams::default(), &Default::default()).unwrap();
+        let parts = result["request"]["contents"][0]["parts"].as_array().unwrap();
+        assert_eq!(parts.len(), 4);
+
+        let inlined: Vec<&Value> = parts.iter().filter(|p| p.get("inlineData").is_some()).collect();
+        assert_eq!(inlined.len(), 2);
+        assert!(inlined[0]["inlineData"]["data"].as_str().unwrap().starts_with('A'));
+        assert!(inlined[1]["inlineData"]["data"].as_str().unwrap().starts_with('B'));
+        assert_eq!(parts[2]["fileData"]["fileUri"], "https://example.com/cat.jpg");
+        // 被跳过的图片不留下任何模型可见的文本
+        let texts: Vec<&str> = parts.iter().filter_map(|p| p["text"].as_str()).collect();
+        assert_eq!(texts, vec!["Compare these"]);
+    }
+
     #[test]
     fn test_gemini_pro_thinking_injection() {
         let req = OpenAIRequest {
@@ -901,6 +1185,7 @@ mod tests {
                 budget_tokens: Some(16000),
             }),
             max_tokens: None,
+            max_completion_tokens: None,
             temperature: None,
             top_p: None,
             stop: None,
@@ -914,10 +1199,12 @@ mod tests {
             size: None,
             quality: None,
             person_generation: None,
+            image_size: None,
+            user: None,
         };
 
         // Pass explicit gemini-3-pro-preview which doesn't have "-thinking" suffix
-        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-p", "gemini-3-pro-preview");
+        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-p", "gemini-3-pro-preview", &EnvelopeParams::default(), &Default::default()).unwrap();
         let gen_config = &result["request"]["generationConfig"];
         
         // Assert thinkingConfig is present (fix verification)
@@ -943,6 +1230,7 @@ mod tests {
             n: None,
             thinking: None,
             max_tokens: None,
+            max_completion_tokens: None,
             temperature: None,
             top_p: None,
             stop: None,
@@ -956,10 +1244,12 @@ mod tests {
             size: Some("1024x1024".to_string()),
             quality: Some("hd".to_string()),
             person_generation: None,
+            image_size: None,
+            user: None,
         };
 
         // Pass gemini-3-pro-image which matches "gemini-3-pro" substring
-        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-p", "gemini-3-pro-image");
+        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-p", "gemini-3-pro-image", &EnvelopeParams::default(), &Default::default()).unwrap();
         let gen_config = &result["request"]["generationConfig"];
         
         // Assert thinkingConfig IS present (based on latest user feedback)
@@ -985,6 +1275,7 @@ mod tests {
             stream: false,
             n: None,
             max_tokens: None,
+            max_completion_tokens: None,
             temperature: None,
             top_p: None,
             stop: None,
@@ -998,10 +1289,12 @@ mod tests {
             size: None,
             quality: None,
             person_generation: None,
+            image_size: None,
+            user: None,
             thinking: None,
         };
 
-        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-p", "gemini-3-pro-high-thinking");
+        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-p", "gemini-3-pro-high-thinking", &EnvelopeParams::default(), &Default::default()).unwrap();
         let gen_config = &result["request"]["generationConfig"];
         let max_output_tokens = gen_config["maxOutputTokens"].as_i64().unwrap();
         // budget(24576) + overhead(32768) = 57344
@@ -1033,6 +1326,7 @@ mod tests {
                 budget_tokens: Some(32768),
             }),
             max_tokens: None,
+            max_completion_tokens: None,
             temperature: None,
             top_p: None,
             stop: None,
@@ -1046,10 +1340,12 @@ mod tests {
             size: None,
             quality: None,
             person_generation: None,
+            image_size: None,
+            user: None,
         };
 
         // Test with Flash model
-        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-p", "gemini-2.0-flash-thinking-exp");
+        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-p", "gemini-2.0-flash-thinking-exp", &EnvelopeParams::default(), &Default::default()).unwrap();
         let gen_config = &result["request"]["generationConfig"];
         
         // Should be capped at 24576
@@ -1084,6 +1380,7 @@ mod tests {
             stream: false,
             n: None,
             max_tokens: None,
+            max_completion_tokens: None,
             temperature: None,
             top_p: None,
             stop: None,
@@ -1107,13 +1404,15 @@ mod tests {
             size: None,
             quality: None,
             person_generation: None,
+            image_size: None,
+            user: None,
             thinking: None,
         };
 
         // Simulate Vertex AI path
         let mapped_model = "projects/my-project/locations/us-central1/publishers/google/models/gemini-2.0-flash-thinking-exp";
         
-        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-v", mapped_model);
+        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-v", mapped_model, &EnvelopeParams::default(), &Default::default()).unwrap();
         
         // Extract the tool call part from contents
         let contents = result["contents"].as_array().unwrap();
@@ -1125,6 +1424,62 @@ mod tests {
         assert_eq!(tool_part["thoughtSignature"].as_str(), Some("skip_thought_signature_validator"));
     }
 
+    #[test]
+    fn test_sentinel_omitted_after_model_rejects_it() {
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gemini-3-pro-thinking",
+            "messages": [
+                { "role": "user", "content": "List files" },
+                {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "list_files", "arguments": "{}" }
+                    }]
+                }
+            ]
+        }))
+        .unwrap();
+        let mapped_model = "gemini-3-pro-thinking-sentinel-reject";
+        let tool_signature = |body: &Value| {
+            body["request"]["contents"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .flat_map(|c| c["parts"].as_array().unwrap().iter())
+                .find(|p| p.get("functionCall").is_some())
+                .expect("Should find functionCall part")
+                .get("thoughtSignature")
+                .cloned()
+        };
+
+        let (body, _, _) = transform_openai_request(&req, "proj", mapped_model, &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert_eq!(tool_signature(&body), Some(json!(crate::proxy::signature_sentinel::SENTINEL)));
+
+        // 上游以 400 拒绝哨兵值后，下一次转换不再注入
+        let probe = crate::proxy::signature_sentinel::probe(&body).expect("sentinel injected");
+        let (content_idx, part_idx) = body["request"]["contents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .find_map(|(i, c)| {
+                c["parts"].as_array().unwrap().iter().position(|p| p.get("functionCall").is_some()).map(|j| (i, j))
+            })
+            .unwrap();
+        assert!(crate::proxy::signature_sentinel::observe_rejection(
+            &probe,
+            &format!("Invalid value at 'contents[{}].parts[{}].thought_signature'", content_idx, part_idx)
+        ));
+        let (body, _, _) = transform_openai_request(&req, "proj", mapped_model, &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert_eq!(tool_signature(&body), None);
+        assert!(!serde_json::to_string(&body).unwrap().contains(crate::proxy::signature_sentinel::SENTINEL));
+
+        crate::proxy::signature_sentinel::reset(Some(&probe.model));
+    }
+
     #[test]
     fn test_openai_image_thinking_mode_disabled() {
         // 1. Set global mode to disabled
@@ -1147,6 +1502,7 @@ mod tests {
             temperature: None,
             top_p: None,
             max_tokens: None,
+            max_completion_tokens: None,
             n: None,
             stop: None,
             response_format: None,
@@ -1156,11 +1512,13 @@ mod tests {
             size: None,
             quality: None,
             person_generation: None,
+            image_size: None,
+            user: None,
             thinking: None,
         };
 
         // 2. Transform request
-        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-proj", "gemini-3-pro-image");
+        let (result, _sid, _msg_count) = transform_openai_request(&req, "test-proj", "gemini-3-pro-image", &EnvelopeParams::default(), &Default::default()).unwrap();
 
         // 3. Verify thinkingConfig has includeThoughts: false
         let gen_config = result["request"]["generationConfig"].as_object().expect("Should have generationConfig in request payload");
@@ -1171,5 +1529,401 @@ mod tests {
         // 4. Reset global mode
         crate::proxy::config::update_image_thinking_mode(Some("enabled".to_string()));
     }
-}
 
+    #[test]
+    fn test_envelope_overrides_land_in_body() {
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{ "role": "user", "content": "hi" }]
+        }))
+        .unwrap();
+
+        // Defaults unchanged
+        let (body, _, _) =
+            transform_openai_request(&req, "proj", "gemini-2.5-flash", &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert_eq!(body["userAgent"], "antigravity");
+        assert_eq!(body["requestType"], "agent");
+
+        // Overrides applied
+        let envelope = EnvelopeParams::from_overrides(Some("jetski"), Some("chat"));
+        let (body, _, _) = transform_openai_request(&req, "proj", "gemini-2.5-flash", &envelope, &Default::default()).unwrap();
+        assert_eq!(body["userAgent"], "jetski");
+        assert_eq!(body["requestType"], "chat");
+    }
+
+    #[test]
+    fn test_safety_default_off_without_override() {
+        // OpenAI 链路未显式覆盖时保持 OFF (与 GEMINI_SAFETY_THRESHOLD 无关)
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{ "role": "user", "content": "hi" }]
+        }))
+        .unwrap();
+
+        let (body, _, _) =
+            transform_openai_request(&req, "proj", "gemini-2.5-flash", &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert_eq!(body["request"]["safetySettings"], build_safety_settings_with(SafetyThreshold::Off));
+    }
+
+    #[test]
+    fn test_non_gemini_flash_budget_not_capped() {
+        // OpenAI 链路仅对 Gemini 与 -thinking 模型执行 24576 上限
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "custom-flash",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "thinking": { "type": "enabled", "budget_tokens": 32768 }
+        }))
+        .unwrap();
+
+        let (body, _, _) =
+            transform_openai_request(&req, "proj", "custom-flash", &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert_eq!(body["request"]["generationConfig"]["thinkingConfig"]["thinkingBudget"], 32768);
+    }
+
+    #[test]
+    fn test_malformed_tool_schema_is_invalid_request() {
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "tools": [{
+                "type": "function",
+                "function": { "name": "read_file", "parameters": "not-a-schema" }
+            }]
+        }))
+        .unwrap();
+
+        let err = transform_openai_request(&req, "proj", "gemini-2.5-flash", &EnvelopeParams::default(), &Default::default())
+            .unwrap_err();
+        match &err {
+            MapperError::InvalidRequest { field, .. } => {
+                assert_eq!(field, "tools[0].function.parameters")
+            }
+            other => panic!("Expected InvalidRequest, got {:?}", other),
+        }
+        assert_eq!(err.status_code(), axum::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_max_completion_tokens_maps_to_max_output_tokens() {
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "max_completion_tokens": 1234
+        }))
+        .unwrap();
+
+        let (body, _, _) =
+            transform_openai_request(&req, "proj", "gemini-2.5-flash", &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert_eq!(body["request"]["generationConfig"]["maxOutputTokens"], 1234);
+
+        // max_tokens 同时存在时优先
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "max_tokens": 100,
+            "max_completion_tokens": 1234
+        }))
+        .unwrap();
+        let (body, _, _) =
+            transform_openai_request(&req, "proj", "gemini-2.5-flash", &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert_eq!(body["request"]["generationConfig"]["maxOutputTokens"], 100);
+    }
+
+    #[test]
+    fn test_stop_merged_with_protective_stop_sequences() {
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "stop": "END"
+        }))
+        .unwrap();
+
+        let (body, _, _) =
+            transform_openai_request(&req, "proj", "gemini-2.5-flash", &EnvelopeParams::default(), &Default::default()).unwrap();
+        let expected = crate::proxy::mappers::common_utils::merge_stop_sequences(
+            &["END".to_string()],
+            &crate::proxy::config::get_protective_stop_sequences(),
+        );
+        assert_eq!(expected[0], "END");
+        assert_eq!(body["request"]["generationConfig"]["stopSequences"], json!(expected));
+    }
+
+    #[test]
+    fn test_project_id_bare_and_vertex_path() {
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{ "role": "user", "content": "hi" }]
+        }))
+        .unwrap();
+
+        for project_id in ["my-proj-123", "projects/my-proj-123/locations/global"] {
+            let (body, _, _) =
+                transform_openai_request(&req, project_id, "gemini-2.5-flash", &EnvelopeParams::default(), &Default::default()).unwrap();
+            assert_eq!(body["project"], "my-proj-123");
+            assert_eq!(body["model"], "gemini-2.5-flash");
+        }
+    }
+
+    #[test]
+    fn test_session_generation_override_applies_across_requests() {
+        use crate::proxy::session_manager::{GenerationOverride, SessionGenerationOverrides};
+
+        let turn = |temperature: f64, extra: &str| -> OpenAIRequest {
+            serde_json::from_value(json!({
+                "model": "gemini-2.5-flash",
+                "temperature": temperature,
+                "messages": [
+                    { "role": "user", "content": "pinned openai agent run" },
+                    { "role": "assistant", "content": "ok" },
+                    { "role": "user", "content": extra }
+                ]
+            }))
+            .unwrap()
+        };
+        let session_id = crate::proxy::session_manager::SessionManager::extract_openai_session_id(&turn(1.0, "first"));
+        SessionGenerationOverrides::global().set(
+            &session_id,
+            GenerationOverride {
+                temperature: Some(0.2),
+                top_p: Some(0.5),
+                presence_penalty: Some(0.4),
+                ..Default::default()
+            },
+        );
+
+        for (temperature, extra) in [(1.0, "first"), (1.5, "second")] {
+            let (body, sid, _) =
+                transform_openai_request(&turn(temperature, extra), "proj", "gemini-2.5-flash", &EnvelopeParams::default(), &Default::default()).unwrap();
+            assert_eq!(sid, session_id);
+            let gen = &body["request"]["generationConfig"];
+            assert_eq!(gen["temperature"], 0.2);
+            assert_eq!(gen["topP"], 0.5);
+            assert_eq!(gen["presencePenalty"], 0.4);
+        }
+
+        SessionGenerationOverrides::global().clear(&session_id);
+    }
+
+    #[test]
+    fn test_system_instruction_matches_shared_builder() {
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gemini-2.5-flash",
+            "messages": [
+                { "role": "system", "content": "You are a helpful CLI." },
+                { "role": "developer", "content": "Be brief." },
+                { "role": "user", "content": "hi" }
+            ],
+            "tools": [{
+                "type": "function",
+                "function": {
+                    "name": "mcp__fs_list",
+                    "parameters": { "type": "object", "properties": {} }
+                }
+            }]
+        }))
+        .unwrap();
+
+        let (body, _, _) =
+            transform_openai_request(&req, "proj", "gemini-2.5-flash", &EnvelopeParams::default(), &Default::default()).unwrap();
+        let expected = system_builder::build_system_parts(
+            &["You are a helpful CLI.", "Be brief."],
+            true,
+            &IdentityConfig::current(),
+        );
+        // OpenAI 链路与 Claude 链路一致: 含 MCP XML 协议与结束标记
+        assert_eq!(body["request"]["systemInstruction"]["parts"], json!(expected));
+        assert!(expected.iter().any(|p| p["text"] == system_builder::MCP_XML_PROMPT));
+    }
+
+    #[test]
+    fn test_image_size_sanitized_into_image_config() {
+        let build = |image_size: &str| {
+            let req: OpenAIRequest = serde_json::from_value(json!({
+                "model": "gemini-3-pro-image",
+                "imageSize": image_size,
+                "messages": [{ "role": "user", "content": "draw a cat" }]
+            }))
+            .unwrap();
+            let (body, _, _) =
+                transform_openai_request(&req, "proj", "gemini-3-pro-image", &EnvelopeParams::default(), &Default::default()).unwrap();
+            body["request"]["generationConfig"]["imageConfig"].clone()
+        };
+
+        assert_eq!(build("4k")["imageSize"], "4K");
+        assert_eq!(build("4k\n\r")["imageSize"], "4K");
+        assert_eq!(build("2k\0")["imageSize"], "2K");
+        let huge = build(&"k".repeat(1024 * 1024));
+        assert_eq!(
+            huge["imageSize"].as_str().unwrap().len(),
+            crate::proxy::mappers::common_utils::MAX_ENVELOPE_IMAGE_SIZE_LEN
+        );
+        assert!(build(r#"{"imageSize":"4K","x":[1]}"#).get("imageSize").is_none());
+    }
+
+    #[test]
+    fn test_user_field_becomes_session_id() {
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gemini-2.5-flash",
+            "user": "user-42",
+            "messages": [{ "role": "user", "content": "hello" }]
+        }))
+        .unwrap();
+        let (body, _, _) =
+            transform_openai_request(&req, "proj", "gemini-2.5-flash", &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert_eq!(body["request"]["sessionId"], "user-42");
+
+        let mut anonymous = req.clone();
+        anonymous.user = None;
+        let (body, _, _) =
+            transform_openai_request(&anonymous, "proj", "gemini-2.5-flash", &EnvelopeParams::default(), &Default::default()).unwrap();
+        assert!(body["request"].get("sessionId").is_none());
+    }
+
+    #[test]
+    fn test_request_id_prefix_configurable() {
+        use crate::proxy::config::{
+            update_request_id_prefix_config, RequestIdPrefixConfig, REQUEST_ID_PREFIX_TEST_LOCK,
+        };
+        let _guard = REQUEST_ID_PREFIX_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{ "role": "user", "content": "hello" }]
+        }))
+        .unwrap();
+        let request_id = || {
+            let (body, _, _) =
+                transform_openai_request(&req, "proj", "gemini-2.5-flash", &EnvelopeParams::default(), &Default::default()).unwrap();
+            body["requestId"].as_str().unwrap().to_string()
+        };
+
+        assert!(request_id().starts_with("openai-"));
+        // 仅修改 openai 前缀，其他协议保持默认
+        update_request_id_prefix_config(RequestIdPrefixConfig {
+            openai: "chat".to_string(),
+            ..Default::default()
+        });
+        let configured = request_id();
+        update_request_id_prefix_config(RequestIdPrefixConfig::default());
+        assert!(configured.starts_with("chat-"), "{}", configured);
+    }
+
+    #[test]
+    fn test_google_search_injection_suppressed_by_request_flag() {
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gemini-2.5-flash-online",
+            "messages": [{ "role": "user", "content": "latest rust release?" }]
+        }))
+        .unwrap();
+        let has_search = |body: &Value| {
+            body["request"]["tools"]
+                .as_array()
+                .map_or(false, |tools| tools.iter().any(|t| t.get("googleSearch").is_some()))
+        };
+
+        let (body, _, _) = transform_openai_request(
+            &req,
+            "test-project",
+            "gemini-2.5-flash-online",
+            &EnvelopeParams::default(),
+            &Default::default(),
+        )
+        .unwrap();
+        assert!(has_search(&body));
+
+        let request_ctx = RequestContext {
+            disable_google_search: true,
+            ..Default::default()
+        };
+        let (body, _, _) = transform_openai_request(
+            &req,
+            "test-project",
+            "gemini-2.5-flash-online",
+            &EnvelopeParams::default(),
+            &request_ctx,
+        )
+        .unwrap();
+        assert!(!has_search(&body));
+        assert_eq!(body["requestType"], "agent");
+    }
+
+    #[test]
+    fn test_map_messages_tool_roles() {
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gemini-2.5-flash",
+            "messages": [
+                { "role": "system", "content": "be brief" },
+                { "role": "user", "content": "list files" },
+                {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "local_shell_call", "arguments": "{\"command\":[\"ls\"]}" }
+                    }]
+                },
+                { "role": "tool", "tool_call_id": "call_1", "content": "a.rs\nb.rs" },
+                { "role": "user", "content": "thanks" }
+            ]
+        }))
+        .unwrap();
+
+        let contents = map_messages(&req, &ThinkingPlan::default());
+        // system 消息不进入 contents；tool 结果与其后的 user 消息合并
+        assert_eq!(contents.len(), 3);
+        assert_eq!(contents[0]["role"], "user");
+
+        let call = &contents[1]["parts"][0]["functionCall"];
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(call["name"], "shell");
+        assert_eq!(call["id"], "call_1");
+        assert_eq!(call["args"]["command"], json!(["ls"]));
+
+        let user_parts = contents[2]["parts"].as_array().unwrap();
+        assert_eq!(contents[2]["role"], "user");
+        assert_eq!(user_parts.len(), 2);
+        let response = &user_parts[0]["functionResponse"];
+        assert_eq!(response["name"], "shell");
+        assert_eq!(response["id"], "call_1");
+        assert_eq!(response["response"]["result"], "a.rs\nb.rs");
+        assert_eq!(user_parts[1]["text"], "thanks");
+    }
+
+    #[test]
+    fn test_build_tools_cleans_schema_and_fills_missing_parameters() {
+        let tools = vec![
+            json!({
+                "type": "function",
+                "function": {
+                    "name": "read_file",
+                    "strict": true,
+                    "parameters": {
+                        "type": "object",
+                        "properties": { "path": { "type": "string", "format": "uri" } },
+                        "required": ["path"]
+                    }
+                }
+            }),
+            json!({ "type": "custom", "name": "apply_patch", "description": "Apply a patch" }),
+            json!({ "type": "function", "function": { "name": "web_search" } }),
+            json!({ "type": "function", "function": { "description": "nameless" } }),
+        ];
+
+        let declarations = build_tools(&tools).unwrap();
+        assert_eq!(declarations.len(), 2);
+
+        let read_file = &declarations[0];
+        assert!(read_file.get("strict").is_none());
+        assert_eq!(read_file["parameters"]["type"], "OBJECT");
+        assert_eq!(read_file["parameters"]["properties"]["path"]["type"], "STRING");
+        assert!(read_file["parameters"]["properties"]["path"].get("format").is_none());
+
+        let apply_patch = &declarations[1];
+        assert_eq!(apply_patch["name"], "apply_patch");
+        assert!(apply_patch.get("type").is_none());
+        assert_eq!(apply_patch["parameters"]["type"], "OBJECT");
+        assert_eq!(apply_patch["parameters"]["required"], json!(["content"]));
+        assert_eq!(apply_patch["parameters"]["properties"]["content"]["type"], "STRING");
+    }
+}
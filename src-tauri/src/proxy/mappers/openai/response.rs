@@ -1,8 +1,37 @@
 // OpenAI 协议响应转换模块
 use super::models::*;
+use crate::proxy::mappers::common::mcp_xml::{mcp_tool_call_id, McpXmlEvent, McpXmlParser};
 use serde_json::Value;
 
-pub fn transform_openai_response(gemini_response: &Value, session_id: Option<&str>, message_count: usize) -> OpenAIResponse {
+/// [NEW] MCP XML Bridge: 文本中的 `<mcp__tool>{json}</mcp__tool>` 还原为 tool_calls，返回剩余文本
+fn extract_mcp_xml_calls(text: &str, tool_calls: &mut Vec<ToolCall>) -> String {
+    let mut parser = McpXmlParser::new();
+    let mut events = parser.feed(text);
+    events.extend(parser.flush().map(McpXmlEvent::Text));
+
+    let mut rest = String::new();
+    for event in events {
+        match event {
+            McpXmlEvent::Text(segment) => rest.push_str(&segment),
+            McpXmlEvent::ToolCall { name, input } => tool_calls.push(ToolCall {
+                id: mcp_tool_call_id(),
+                r#type: "function".to_string(),
+                function: ToolFunction {
+                    name,
+                    arguments: input.to_string(),
+                },
+            }),
+        }
+    }
+    rest
+}
+
+pub fn transform_openai_response(
+    gemini_response: &Value,
+    session_id: Option<&str>,
+    message_count: usize,
+    tool_schemas: &std::collections::HashMap<String, Value>, // [NEW] 客户端工具名 -> parameters schema
+) -> OpenAIResponse {
     // 解包 response 字段
     let raw = gemini_response.get("response").unwrap_or(gemini_response);
 
@@ -50,13 +79,24 @@ pub fn transform_openai_response(gemini_response: &Value, session_id: Option<&st
                         }
                     }
 
+                    // [NEW] executableCode / codeExecutionResult 渲染为文本
+                    if let Some(rendered) = crate::proxy::mappers::common_utils::render_code_execution_part(
+                        part.get("executableCode"),
+                        part.get("codeExecutionResult"),
+                    ) {
+                        content_out.push_str(&rendered);
+                    }
+
                     // 工具调用部分
                     if let Some(fc) = part.get("functionCall") {
                         let name = fc.get("name").and_then(|v| v.as_str()).unwrap_or("unknown");
-                        let args = fc
-                            .get("args")
-                            .map(|v| v.to_string())
-                            .unwrap_or_else(|| "{}".to_string());
+                        // [NEW] args 可能以 JSON 字符串返回，规范化并按 schema 修正类型后再序列化 (避免双重编码)
+                        let args = crate::proxy::mappers::common_utils::normalize_function_call_args(
+                            fc.get("args"),
+                            name,
+                            tool_schemas.get(name),
+                        )
+                        .to_string();
                         let id = fc
                             .get("id")
                             .and_then(|v| v.as_str())
@@ -88,6 +128,10 @@ pub fn transform_openai_response(gemini_response: &Value, session_id: Option<&st
                 }
             }
 
+            if content_out.contains("<mcp__") {
+                content_out = extract_mcp_xml_calls(&content_out, &mut tool_calls);
+            }
+
             // 提取并处理该候选结果的联网搜索引文 (Grounding Metadata)
             if let Some(grounding) = candidate.get("groundingMetadata") {
                 let mut grounding_text = String::new();
@@ -212,6 +256,7 @@ pub fn transform_openai_response(gemini_response: &Value, session_id: Option<&st
             .to_string(),
         choices,
         usage,
+        requested_model: None,
     }
 }
 
@@ -233,7 +278,7 @@ mod tests {
             "responseId": "resp_123"
         });
 
-        let result = transform_openai_response(&gemini_resp, Some("session-123"), 1);
+        let result = transform_openai_response(&gemini_resp, Some("session-123"), 1, &Default::default());
         assert_eq!(result.object, "chat.completion");
         let content = match result.choices[0].message.content.as_ref().unwrap() {
             OpenAIContent::String(s) => s,
@@ -260,7 +305,7 @@ mod tests {
             "responseId": "resp_123"
         });
 
-        let result = transform_openai_response(&gemini_resp, Some("session-123"), 1);
+        let result = transform_openai_response(&gemini_resp, Some("session-123"), 1, &Default::default());
 
         assert!(result.usage.is_some());
         let usage = result.usage.unwrap();
@@ -282,7 +327,100 @@ mod tests {
             "responseId": "resp_123"
         });
 
-        let result = transform_openai_response(&gemini_resp, Some("session-123"), 1);
+        let result = transform_openai_response(&gemini_resp, Some("session-123"), 1, &Default::default());
         assert!(result.usage.is_none());
     }
+
+    #[test]
+    fn test_stringified_function_call_args() {
+        let gemini_resp = json!({
+            "candidates": [{
+                "content": {
+                    "parts": [
+                        { "functionCall": { "name": "fetch_page", "args": "{\"url\": \"https://example.com\", \"max_lines\": \"20\"}", "id": "call_1" } },
+                        { "functionCall": { "name": "fetch_page", "args": "oops", "id": "call_2" } }
+                    ]
+                },
+                "finishReason": "STOP"
+            }],
+            "modelVersion": "gemini-2.5-flash",
+            "responseId": "resp_123"
+        });
+
+        let tools = Some(vec![json!({
+            "type": "function",
+            "function": {
+                "name": "fetch_page",
+                "parameters": {
+                    "type": "object",
+                    "properties": { "url": { "type": "string" }, "max_lines": { "type": "integer" } }
+                }
+            }
+        })]);
+        let schemas = crate::proxy::mappers::openai::request::collect_tool_schemas(&tools);
+        let result = transform_openai_response(&gemini_resp, None, 1, &schemas);
+        let tool_calls = result.choices[0].message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls.len(), 2);
+
+        // arguments 为对象的 JSON 文本，而不是被二次编码的字符串，并按 schema 修正类型
+        let args: serde_json::Value = serde_json::from_str(&tool_calls[0].function.arguments).unwrap();
+        assert_eq!(args, json!({ "url": "https://example.com", "max_lines": 20 }));
+
+        let args: serde_json::Value = serde_json::from_str(&tool_calls[1].function.arguments).unwrap();
+        assert_eq!(args, json!({ "raw": "oops" }));
+    }
+
+    #[test]
+    fn test_code_execution_parts_rendered_as_text() {
+        let gemini_resp = json!({
+            "candidates": [{
+                "content": {
+                    "parts": [
+                        { "text": "Let me compute." },
+                        { "executableCode": { "language": "PYTHON", "code": "print(2 + 2)" } },
+                        { "codeExecutionResult": { "outcome": "OUTCOME_OK", "output": "4" } },
+                        { "text": "So the answer is 4." }
+                    ]
+                },
+                "finishReason": "STOP"
+            }]
+        });
+
+        let result = transform_openai_response(&gemini_resp, None, 1, &Default::default());
+        let content = match result.choices[0].message.content.as_ref().unwrap() {
+            OpenAIContent::String(s) => s.clone(),
+            _ => panic!("Expected string content"),
+        };
+        assert_eq!(
+            content,
+            "Let me compute.\n```python\nprint(2 + 2)\n```\n\n```output\n4\n```\nSo the answer is 4."
+        );
+    }
+
+    #[test]
+    fn test_mcp_xml_text_becomes_tool_call() {
+        let gemini_resp = json!({
+            "candidates": [{
+                "content": {
+                    "parts": [
+                        { "text": "Checking. <mcp__fs__read_file>{\"path\":\"/tmp/a.txt\"}</mcp__fs__read_file>" }
+                    ]
+                },
+                "finishReason": "STOP"
+            }]
+        });
+
+        let result = transform_openai_response(&gemini_resp, None, 1, &Default::default());
+        let message = &result.choices[0].message;
+        let tool_calls = message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].function.name, "mcp__fs__read_file");
+        assert!(tool_calls[0].id.starts_with("call_"));
+        let args: serde_json::Value = serde_json::from_str(&tool_calls[0].function.arguments).unwrap();
+        assert_eq!(args, json!({ "path": "/tmp/a.txt" }));
+        match message.content.as_ref().unwrap() {
+            OpenAIContent::String(s) => assert_eq!(s, "Checking. "),
+            _ => panic!("Expected string content"),
+        }
+    }
 }
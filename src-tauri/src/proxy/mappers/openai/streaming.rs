@@ -1,4 +1,5 @@
 // OpenAI 流式转换
+use crate::proxy::mappers::common::mcp_xml::{mcp_tool_call_id, McpXmlEvent, McpXmlParser};
 use bytes::{Bytes, BytesMut};
 use chrono::Utc;
 use futures::{Stream, StreamExt};
@@ -63,11 +64,57 @@ fn extract_usage_metadata(u: &Value) -> Option<super::models::OpenAIUsage> {
     })
 }
 
+/// 按 OpenAI 流式增量协议构造单个工具调用的 chunk 序列:
+/// 首个 chunk 携带 index / id / type / function.name，后续 chunk 只携带 index 与 arguments 片段
+/// `envelope` 为 chunk 公共字段 (id / object / created / model)
+fn build_tool_call_chunks(
+    envelope: &Value,
+    choice_index: u32,
+    tool_index: u32,
+    call_id: &str,
+    name: &str,
+    args_str: &str,
+) -> Vec<Value> {
+    let chunk = |delta: Value| {
+        let mut chunk = envelope.clone();
+        chunk["choices"] = json!([{
+            "index": choice_index,
+            "delta": delta,
+            "finish_reason": serde_json::Value::Null
+        }]);
+        chunk
+    };
+
+    let mut chunks = vec![chunk(json!({
+        "role": "assistant",
+        "tool_calls": [{
+            "index": tool_index,
+            "id": call_id,
+            "type": "function",
+            "function": { "name": name, "arguments": "" }
+        }]
+    }))];
+    for fragment in crate::proxy::mappers::common_utils::split_json_fragments(
+        args_str,
+        crate::proxy::mappers::common_utils::TOOL_ARGS_FRAGMENT_CHARS,
+    ) {
+        chunks.push(chunk(json!({
+            "tool_calls": [{
+                "index": tool_index,
+                "function": { "arguments": fragment }
+            }]
+        })));
+    }
+    chunks
+}
+
 pub fn create_openai_sse_stream(
     mut gemini_stream: Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>,
     model: String,
     session_id: String,
     message_count: usize,
+    emit_reasoning_content: bool, // [NEW] 客户端能力: 是否输出 reasoning_content
+    tool_schemas: std::collections::HashMap<String, Value>, // [NEW] 客户端工具名 -> parameters schema
 ) -> Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>> {
     let mut buffer = BytesMut::new();
     let stream_id = format!("chatcmpl-{}", Uuid::new_v4());
@@ -75,6 +122,10 @@ pub fn create_openai_sse_stream(
 
     let stream = async_stream::stream! {
         let mut emitted_tool_calls = std::collections::HashSet::new();
+        // 工具调用在整个流内使用稳定递增的 index (多个并行调用可能分布在不同事件中)
+        let mut next_tool_call_index: u32 = 0;
+        // [NEW] MCP XML Bridge: 每个候选结果一个解析器，文本中的 <mcp__...> 标签还原为 tool_calls
+        let mut mcp_parsers: std::collections::HashMap<usize, McpXmlParser> = std::collections::HashMap::new();
         let mut final_usage: Option<super::models::OpenAIUsage> = None;
         let mut error_occurred = false;
 
@@ -86,6 +137,7 @@ pub fn create_openai_sse_stream(
                 item = gemini_stream.next() => {
                     match item {
                         Some(Ok(bytes)) => {
+                            crate::proxy::session_registry::touch(&session_id);
                             buffer.extend_from_slice(&bytes);
                             while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
                                 let line_raw = buffer.split_to(pos + 1);
@@ -108,13 +160,19 @@ pub fn create_openai_sse_stream(
                                                     let mut thought_out = String::new();
 
                                                     if let Some(parts_list) = parts {
-                                                        let mut tool_call_index = 0;
                                                         for part in parts_list {
                                                             let is_thought_part = part.get("thought").and_then(|v| v.as_bool()).unwrap_or(false);
                                                             if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
                                                                 if is_thought_part { thought_out.push_str(text); }
                                                                 else { content_out.push_str(text); }
                                                             }
+                                                            // [NEW] executableCode / codeExecutionResult 渲染为文本
+                                                            if let Some(rendered) = crate::proxy::mappers::common_utils::render_code_execution_part(
+                                                                part.get("executableCode"),
+                                                                part.get("codeExecutionResult"),
+                                                            ) {
+                                                                content_out.push_str(&rendered);
+                                                            }
                                                             if let Some(sig) = part.get("thoughtSignature").or(part.get("thought_signature")).and_then(|s| s.as_str()) {
                                                                 store_thought_signature(sig, &session_id, message_count);
                                                             }
@@ -130,7 +188,12 @@ pub fn create_openai_sse_stream(
                                                                 if !emitted_tool_calls.contains(&call_key) {
                                                                     emitted_tool_calls.insert(call_key);
                                                                     let name = func_call.get("name").and_then(|v| v.as_str()).unwrap_or("unknown");
-                                                                    let mut args = func_call.get("args").unwrap_or(&json!({})).clone();
+                                                                    // [NEW] args 可能以 JSON 字符串返回，先规范化为对象并按 schema 修正类型
+                                                                    let mut args = crate::proxy::mappers::common_utils::normalize_function_call_args(
+                                                                        func_call.get("args"),
+                                                                        name,
+                                                                        tool_schemas.get(name),
+                                                                    );
                                                                     
                                                                     // [FIX #1575] 标准化 shell 工具参数名称
                                                                     // Gemini 可能使用 cmd/code/script 等替代参数名，统一为 command
@@ -154,27 +217,59 @@ pub fn create_openai_sse_stream(
                                                                     serde_json::to_string(func_call).unwrap_or_default().hash(&mut hasher);
                                                                     let call_id = format!("call_{:x}", hasher.finish());
  
-                                                                    let tool_call_chunk = json!({
+                                                                    // [NEW] 按增量协议发送: 首个 chunk 携带 index/id/type/name，后续 chunk 只携带 arguments 片段
+                                                                    let envelope = json!({
                                                                         "id": &stream_id,
                                                                         "object": "chat.completion.chunk",
                                                                         "created": created_ts,
                                                                         "model": &model,
-                                                                        "choices": [{
-                                                                            "index": idx as u32,
-                                                                            "delta": {
-                                                                                "role": "assistant",
-                                                                                "tool_calls": [{
-                                                                                    "index": tool_call_index,
-                                                                                    "id": call_id,
-                                                                                    "type": "function",
-                                                                                    "function": { "name": name, "arguments": args_str }
-                                                                                }]
-                                                                            },
-                                                                            "finish_reason": serde_json::Value::Null
-                                                                        }]
                                                                     });
-                                                                    tool_call_index += 1;
-                                                                    let sse_out = format!("data: {}\n\n", serde_json::to_string(&tool_call_chunk).unwrap_or_default());
+                                                                    let chunks = build_tool_call_chunks(
+                                                                        &envelope,
+                                                                        idx as u32,
+                                                                        next_tool_call_index,
+                                                                        &call_id,
+                                                                        name,
+                                                                        &args_str,
+                                                                    );
+                                                                    next_tool_call_index += 1;
+                                                                    for chunk in chunks {
+                                                                        let sse_out = format!("data: {}\n\n", serde_json::to_string(&chunk).unwrap_or_default());
+                                                                        yield Ok::<Bytes, String>(Bytes::from(sse_out));
+                                                                    }
+                                                                }
+                                                            }
+                                                        }
+                                                    }
+
+                                                    let parser = mcp_parsers.entry(idx).or_default();
+                                                    let mut events = parser.feed(&std::mem::take(&mut content_out));
+                                                    if candidate.get("finishReason").is_some() {
+                                                        events.extend(parser.flush().map(McpXmlEvent::Text));
+                                                    }
+                                                    for event in events {
+                                                        match event {
+                                                            McpXmlEvent::Text(segment) => content_out.push_str(&segment),
+                                                            McpXmlEvent::ToolCall { name, input } => {
+                                                                let call_id = mcp_tool_call_id();
+                                                                emitted_tool_calls.insert(call_id.clone());
+                                                                let envelope = json!({
+                                                                    "id": &stream_id,
+                                                                    "object": "chat.completion.chunk",
+                                                                    "created": created_ts,
+                                                                    "model": &model,
+                                                                });
+                                                                let chunks = build_tool_call_chunks(
+                                                                    &envelope,
+                                                                    idx as u32,
+                                                                    next_tool_call_index,
+                                                                    &call_id,
+                                                                    &name,
+                                                                    &input.to_string(),
+                                                                );
+                                                                next_tool_call_index += 1;
+                                                                for chunk in chunks {
+                                                                    let sse_out = format!("data: {}\n\n", serde_json::to_string(&chunk).unwrap_or_default());
                                                                     yield Ok::<Bytes, String>(Bytes::from(sse_out));
                                                                 }
                                                             }
@@ -223,7 +318,7 @@ pub fn create_openai_sse_stream(
                                                         gemini_finish_reason
                                                     };
 
-                                                    if !thought_out.is_empty() {
+                                                    if emit_reasoning_content && !thought_out.is_empty() {
                                                         let reasoning_chunk = json!({
                                                             "id": &stream_id,
                                                             "object": "chat.completion.chunk",
@@ -303,6 +398,26 @@ pub fn create_openai_sse_stream(
             }
         }
 
+        // [NEW] 上游未给出 finishReason 就结束时，补发 MCP 解析器中暂存的文本
+        if !error_occurred {
+            for (idx, mut parser) in mcp_parsers.drain() {
+                if let Some(rest) = parser.flush() {
+                    let openai_chunk = json!({
+                        "id": &stream_id,
+                        "object": "chat.completion.chunk",
+                        "created": created_ts,
+                        "model": &model,
+                        "choices": [{
+                            "index": idx as u32,
+                            "delta": { "content": rest },
+                            "finish_reason": serde_json::Value::Null
+                        }]
+                    });
+                    yield Ok::<Bytes, String>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&openai_chunk).unwrap_or_default())));
+                }
+            }
+        }
+
         if !error_occurred {
             yield Ok::<Bytes, String>(Bytes::from("data: [DONE]\n\n"));
         }
@@ -337,6 +452,7 @@ pub fn create_legacy_sse_stream(
                 item = gemini_stream.next() => {
                     match item {
                         Some(Ok(bytes)) => {
+                            crate::proxy::session_registry::touch(&session_id);
                             buffer.extend_from_slice(&bytes);
                             while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
                                 let line_raw = buffer.split_to(pos + 1);
@@ -358,6 +474,12 @@ pub fn create_legacy_sse_stream(
                                                             if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
                                                                 content_out.push_str(text);
                                                             }
+                                                            if let Some(rendered) = crate::proxy::mappers::common_utils::render_code_execution_part(
+                                                                part.get("executableCode"),
+                                                                part.get("codeExecutionResult"),
+                                                            ) {
+                                                                content_out.push_str(&rendered);
+                                                            }
                                                             if let Some(sig) = part.get("thoughtSignature").or(part.get("thought_signature")).and_then(|s| s.as_str()) {
                                                                 store_thought_signature(sig, &session_id, message_count);
                                                             }
@@ -436,6 +558,7 @@ pub fn create_codex_sse_stream(
                 item = gemini_stream.next() => {
                     match item {
                         Some(Ok(bytes)) => {
+                            crate::proxy::session_registry::touch(&session_id);
                             buffer.extend_from_slice(&bytes);
                             while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
                                 let line_raw = buffer.split_to(pos + 1);
@@ -455,6 +578,13 @@ pub fn create_codex_sse_stream(
                                                             let delta_ev = json!({ "type": "response.output_text.delta", "delta": text });
                                                             yield Ok::<Bytes, String>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&delta_ev).unwrap())));
                                                         }
+                                                        if let Some(rendered) = crate::proxy::mappers::common_utils::render_code_execution_part(
+                                                            part.get("executableCode"),
+                                                            part.get("codeExecutionResult"),
+                                                        ) {
+                                                            let delta_ev = json!({ "type": "response.output_text.delta", "delta": rendered });
+                                                            yield Ok::<Bytes, String>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&delta_ev).unwrap())));
+                                                        }
                                                         if let Some(sig) = part.get("thoughtSignature").or(part.get("thought_signature")).and_then(|s| s.as_str()) {
                                                             store_thought_signature(sig, &session_id, message_count);
                                                         }
@@ -483,3 +613,136 @@ pub fn create_codex_sse_stream(
     };
     Box::pin(stream)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// 按 OpenAI SDK 的方式从 chunk 序列重建工具调用: 以 index 为键，首个 chunk 取 id/name，arguments 逐片拼接
+    fn reconstruct_tool_calls(chunks: &[Value]) -> BTreeMap<u64, (String, String, String)> {
+        let mut calls: BTreeMap<u64, (String, String, String)> = BTreeMap::new();
+        for chunk in chunks {
+            let Some(tool_calls) = chunk.pointer("/choices/0/delta/tool_calls").and_then(|t| t.as_array()) else {
+                continue;
+            };
+            for call in tool_calls {
+                let index = call["index"].as_u64().expect("every tool call delta carries an index");
+                let entry = calls.entry(index).or_default();
+                if let Some(id) = call.get("id").and_then(|v| v.as_str()) {
+                    entry.0 = id.to_string();
+                }
+                if let Some(name) = call.pointer("/function/name").and_then(|v| v.as_str()) {
+                    entry.1 = name.to_string();
+                }
+                if let Some(args) = call.pointer("/function/arguments").and_then(|v| v.as_str()) {
+                    entry.2.push_str(args);
+                }
+            }
+        }
+        calls
+    }
+
+    fn sse_chunks(output: &str) -> Vec<Value> {
+        output
+            .split("\n\n")
+            .filter_map(|e| e.trim().strip_prefix("data: "))
+            .filter_map(|d| serde_json::from_str(d).ok())
+            .collect()
+    }
+
+    #[test]
+    fn test_tool_call_chunks_follow_incremental_contract() {
+        let envelope = json!({ "id": "chatcmpl-1", "object": "chat.completion.chunk", "created": 0, "model": "m" });
+        let long_args = json!({ "content": "x".repeat(600) }).to_string();
+        let chunks = build_tool_call_chunks(&envelope, 0, 3, "call_1", "write_file", &long_args);
+
+        assert!(chunks.len() > 2, "arguments should be split into fragments");
+        let first = &chunks[0]["choices"][0]["delta"]["tool_calls"][0];
+        assert_eq!(first["index"], 3);
+        assert_eq!(first["id"], "call_1");
+        assert_eq!(first["type"], "function");
+        assert_eq!(first["function"]["name"], "write_file");
+        for chunk in &chunks[1..] {
+            let call = &chunk["choices"][0]["delta"]["tool_calls"][0];
+            assert_eq!(call["index"], 3);
+            assert!(call.get("id").is_none());
+            assert!(call["function"].get("name").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parallel_tool_calls_reconstructed_from_stream() {
+        let event = |part: Value, finish: Option<&str>| {
+            let mut candidate = json!({ "content": { "role": "model", "parts": [part] } });
+            if let Some(f) = finish {
+                candidate["finishReason"] = json!(f);
+            }
+            Ok::<Bytes, reqwest::Error>(Bytes::from(format!(
+                "data: {}\n\n",
+                json!({ "response": { "candidates": [candidate] } })
+            )))
+        };
+        let read_args = json!({ "path": "/tmp/a.txt" });
+        let write_args = json!({ "path": "/tmp/b.txt", "content": "y".repeat(700) });
+        let upstream = futures::stream::iter(vec![
+            event(json!({ "functionCall": { "name": "read_file", "args": read_args } }), None),
+            event(json!({ "functionCall": { "name": "write_file", "args": write_args } }), Some("STOP")),
+        ]);
+
+        let stream = create_openai_sse_stream(Box::pin(upstream), "gemini-2.5-flash".to_string(), "sid-tool-chunks".to_string(), 1, true, Default::default());
+        let output: Vec<Bytes> = stream.map(|r| r.unwrap()).collect().await;
+        let output = String::from_utf8_lossy(&output.concat()).to_string();
+        let chunks = sse_chunks(&output);
+
+        let calls = reconstruct_tool_calls(&chunks);
+        assert_eq!(calls.len(), 2);
+        let (id0, name0, args0) = &calls[&0];
+        let (id1, name1, args1) = &calls[&1];
+        assert!(id0.starts_with("call_") && id1.starts_with("call_") && id0 != id1);
+        assert_eq!(name0, "read_file");
+        assert_eq!(name1, "write_file");
+        assert_eq!(serde_json::from_str::<Value>(args0).unwrap(), read_args);
+        assert_eq!(serde_json::from_str::<Value>(args1).unwrap(), write_args);
+
+        let last = chunks.last().unwrap();
+        assert_eq!(last["choices"][0]["finish_reason"], "tool_calls");
+    }
+
+    #[tokio::test]
+    async fn test_mcp_xml_text_split_across_events_becomes_tool_call() {
+        let event = |text: &str, finish: Option<&str>| {
+            let mut candidate = json!({ "content": { "role": "model", "parts": [{ "text": text }] } });
+            if let Some(f) = finish {
+                candidate["finishReason"] = json!(f);
+            }
+            Ok::<Bytes, reqwest::Error>(Bytes::from(format!(
+                "data: {}\n\n",
+                json!({ "response": { "candidates": [candidate] } })
+            )))
+        };
+        let upstream = futures::stream::iter(vec![
+            event("Checking. <mcp__fs__re", None),
+            event("ad_file>{\"path\":\"/tmp/a.txt\"}</mcp__fs__read_file>", None),
+            event(" Done.", Some("STOP")),
+        ]);
+
+        let stream = create_openai_sse_stream(Box::pin(upstream), "gemini-2.5-flash".to_string(), "sid-mcp-xml".to_string(), 1, true, Default::default());
+        let output: Vec<Bytes> = stream.map(|r| r.unwrap()).collect().await;
+        let chunks = sse_chunks(&String::from_utf8_lossy(&output.concat()));
+
+        let calls = reconstruct_tool_calls(&chunks);
+        assert_eq!(calls.len(), 1);
+        let (id, name, args) = &calls[&0];
+        assert!(id.starts_with("call_"), "same id format as non-streaming responses: {}", id);
+        assert_eq!(name, "mcp__fs__read_file");
+        assert_eq!(serde_json::from_str::<Value>(args).unwrap(), json!({ "path": "/tmp/a.txt" }));
+
+        let text: String = chunks
+            .iter()
+            .filter_map(|c| c.pointer("/choices/0/delta/content").and_then(|v| v.as_str()))
+            .collect();
+        assert_eq!(text, "Checking.  Done.");
+        assert_eq!(chunks.last().unwrap()["choices"][0]["finish_reason"], "tool_calls");
+    }
+}
@@ -0,0 +1,247 @@
+// 请求体校验
+// 在协议转换之前检查 ClaudeRequest / OpenAIRequest 的基本不变量，
+// 让格式错误的请求以明确的 400 返回，而不是在转换深处报出难以理解的错误。
+
+use crate::proxy::mappers::claude::models::{ClaudeRequest, ContentBlock, MessageContent};
+use crate::proxy::mappers::openai::OpenAIRequest;
+use std::collections::HashSet;
+use std::fmt;
+
+const CLAUDE_ROLES: &[&str] = &["user", "assistant"];
+const OPENAI_ROLES: &[&str] = &["system", "developer", "user", "assistant", "tool", "function"];
+
+/// 校验失败: 出错字段路径 + 具体问题
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestValidationError {
+    /// 出错字段，如 "messages[2].content[0].tool_use_id"
+    pub param: String,
+    pub message: String,
+}
+
+impl RequestValidationError {
+    pub(crate) fn new(param: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            param: param.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for RequestValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.param, self.message)
+    }
+}
+
+fn check_role(index: usize, role: &str, allowed: &[&str]) -> Result<(), RequestValidationError> {
+    if allowed.contains(&role) {
+        return Ok(());
+    }
+    Err(RequestValidationError::new(
+        format!("messages[{}].role", index),
+        format!("invalid role '{}', expected one of: {}", role, allowed.join(", ")),
+    ))
+}
+
+/// 校验 Claude 请求: 消息非空、角色合法、tool_result 引用之前出现过的 tool_use
+pub fn validate_claude_request(request: &ClaudeRequest) -> Result<(), RequestValidationError> {
+    if request.messages.is_empty() {
+        return Err(RequestValidationError::new(
+            "messages",
+            "at least one message is required",
+        ));
+    }
+
+    let mut tool_use_ids: HashSet<&str> = HashSet::new();
+    for (i, msg) in request.messages.iter().enumerate() {
+        check_role(i, &msg.role, CLAUDE_ROLES)?;
+
+        let MessageContent::Array(blocks) = &msg.content else {
+            continue;
+        };
+        for (j, block) in blocks.iter().enumerate() {
+            match block {
+                ContentBlock::ToolUse { id, .. } | ContentBlock::ServerToolUse { id, .. } => {
+                    if id.is_empty() {
+                        return Err(RequestValidationError::new(
+                            format!("messages[{}].content[{}].id", i, j),
+                            "tool_use id must not be empty",
+                        ));
+                    }
+                    tool_use_ids.insert(id.as_str());
+                }
+                ContentBlock::ToolResult { tool_use_id, .. } => {
+                    if !tool_use_ids.contains(tool_use_id.as_str()) {
+                        return Err(RequestValidationError::new(
+                            format!("messages[{}].content[{}].tool_use_id", i, j),
+                            format!(
+                                "tool_result references unknown tool_use id '{}' (no preceding tool_use block with this id)",
+                                tool_use_id
+                            ),
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 校验 OpenAI Chat 请求: 消息非空、角色合法、tool 消息引用之前出现过的 tool_call
+pub fn validate_openai_request(request: &OpenAIRequest) -> Result<(), RequestValidationError> {
+    if request.messages.is_empty() {
+        return Err(RequestValidationError::new(
+            "messages",
+            "at least one message is required",
+        ));
+    }
+
+    let mut tool_call_ids: HashSet<&str> = HashSet::new();
+    for (i, msg) in request.messages.iter().enumerate() {
+        check_role(i, &msg.role, OPENAI_ROLES)?;
+
+        for (j, call) in msg.tool_calls.iter().flatten().enumerate() {
+            if call.id.is_empty() {
+                return Err(RequestValidationError::new(
+                    format!("messages[{}].tool_calls[{}].id", i, j),
+                    "tool call id must not be empty",
+                ));
+            }
+            tool_call_ids.insert(call.id.as_str());
+        }
+
+        if msg.role == "tool" {
+            let param = format!("messages[{}].tool_call_id", i);
+            match msg.tool_call_id.as_deref() {
+                None | Some("") => {
+                    return Err(RequestValidationError::new(
+                        param,
+                        "tool message requires tool_call_id",
+                    ));
+                }
+                Some(id) if !tool_call_ids.contains(id) => {
+                    return Err(RequestValidationError::new(
+                        param,
+                        format!(
+                            "tool message references unknown tool_call_id '{}' (no preceding assistant tool_calls entry with this id)",
+                            id
+                        ),
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn claude(messages: serde_json::Value) -> ClaudeRequest {
+        serde_json::from_value(json!({ "model": "claude-sonnet-4-5", "messages": messages })).unwrap()
+    }
+
+    fn openai(messages: serde_json::Value) -> OpenAIRequest {
+        serde_json::from_value(json!({ "model": "gpt-4o", "messages": messages })).unwrap()
+    }
+
+    #[test]
+    fn test_claude_valid_tool_chain_passes() {
+        let req = claude(json!([
+            { "role": "user", "content": "list files" },
+            { "role": "assistant", "content": [
+                { "type": "tool_use", "id": "toolu_1", "name": "ls", "input": {} }
+            ]},
+            { "role": "user", "content": [
+                { "type": "tool_result", "tool_use_id": "toolu_1", "content": "a.txt" }
+            ]}
+        ]));
+        assert_eq!(validate_claude_request(&req), Ok(()));
+    }
+
+    #[test]
+    fn test_claude_empty_messages_rejected() {
+        let err = validate_claude_request(&claude(json!([]))).unwrap_err();
+        assert_eq!(err.param, "messages");
+        assert!(err.message.contains("at least one message"));
+    }
+
+    #[test]
+    fn test_claude_invalid_role_rejected() {
+        let req = claude(json!([
+            { "role": "user", "content": "hi" },
+            { "role": "system", "content": "be nice" }
+        ]));
+        let err = validate_claude_request(&req).unwrap_err();
+        assert_eq!(err.param, "messages[1].role");
+        assert!(err.message.contains("invalid role 'system'"));
+    }
+
+    #[test]
+    fn test_claude_unknown_tool_result_id_rejected() {
+        let req = claude(json!([
+            { "role": "user", "content": [
+                { "type": "tool_result", "tool_use_id": "toolu_missing", "content": "x" }
+            ]}
+        ]));
+        let err = validate_claude_request(&req).unwrap_err();
+        assert_eq!(err.param, "messages[0].content[0].tool_use_id");
+        assert!(err.message.contains("toolu_missing"));
+        assert_eq!(
+            err.to_string(),
+            format!("messages[0].content[0].tool_use_id: {}", err.message)
+        );
+    }
+
+    #[test]
+    fn test_openai_valid_tool_chain_passes() {
+        let req = openai(json!([
+            { "role": "system", "content": "sys" },
+            { "role": "user", "content": "weather?" },
+            { "role": "assistant", "content": null, "tool_calls": [
+                { "id": "call_1", "type": "function", "function": { "name": "weather", "arguments": "{}" } }
+            ]},
+            { "role": "tool", "tool_call_id": "call_1", "content": "sunny" }
+        ]));
+        assert_eq!(validate_openai_request(&req), Ok(()));
+    }
+
+    #[test]
+    fn test_openai_empty_messages_rejected() {
+        let err = validate_openai_request(&openai(json!([]))).unwrap_err();
+        assert_eq!(err.param, "messages");
+    }
+
+    #[test]
+    fn test_openai_invalid_role_rejected() {
+        let err = validate_openai_request(&openai(json!([
+            { "role": "bot", "content": "hi" }
+        ])))
+        .unwrap_err();
+        assert_eq!(err.param, "messages[0].role");
+        assert!(err.message.contains("invalid role 'bot'"));
+    }
+
+    #[test]
+    fn test_openai_tool_message_without_matching_call_rejected() {
+        let missing = validate_openai_request(&openai(json!([
+            { "role": "user", "content": "hi" },
+            { "role": "tool", "content": "result" }
+        ])))
+        .unwrap_err();
+        assert_eq!(missing.param, "messages[1].tool_call_id");
+        assert!(missing.message.contains("requires tool_call_id"));
+
+        let unknown = validate_openai_request(&openai(json!([
+            { "role": "user", "content": "hi" },
+            { "role": "tool", "tool_call_id": "call_x", "content": "result" }
+        ])))
+        .unwrap_err();
+        assert_eq!(unknown.param, "messages[1].tool_call_id");
+        assert!(unknown.message.contains("call_x"));
+    }
+}
@@ -0,0 +1,44 @@
+// 账号分组中间件
+// 根据 API Key 映射 / X-Antigravity-Group 请求头解析请求所属分组，并为请求设置分组上下文
+use axum::{
+    extract::Request,
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::proxy::account_groups::{self, GROUP_HEADER};
+use crate::proxy::middleware::auth::presented_key;
+
+pub async fn account_group_middleware(request: Request, next: Next) -> Response {
+    let header = request
+        .headers()
+        .get(GROUP_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let key_groups = crate::proxy::config::get_api_key_groups();
+
+    match account_groups::resolve(presented_key(request.headers()), header, &key_groups) {
+        Ok(Some(group)) => {
+            tracing::debug!(
+                "[Account-Groups] {} scoped to group {}",
+                request.uri().path(),
+                group
+            );
+            account_groups::scope(Some(Arc::from(group)), next.run(request)).await
+        }
+        Ok(None) => next.run(request).await,
+        Err(e) => {
+            tracing::warn!("[Account-Groups] Rejected {}: {}", request.uri().path(), e);
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": { "message": e, "type": "invalid_request_error", "code": 400 }
+                })),
+            )
+                .into_response()
+        }
+    }
+}
@@ -6,7 +6,8 @@ use axum::{
     middleware::Next,
     response::Response,
 };
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, OnceLock};
 use tokio::sync::RwLock;
 
 use crate::proxy::{ProxyAuthMode, ProxySecurityConfig};
@@ -32,7 +33,7 @@ pub async fn admin_auth_middleware(
 /// 内部认证逻辑
 async fn auth_middleware_internal(
     State(security): State<Arc<RwLock<ProxySecurityConfig>>>,
-    request: Request,
+    mut request: Request,
     next: Next,
     force_strict: bool,
 ) -> Result<Response, StatusCode> {
@@ -56,6 +57,19 @@ async fn auth_middleware_internal(
     let security = security.read().await.clone();
     let effective_mode = security.effective_auth_mode();
 
+    // [NEW] 调试请求头 (x-abv-include-raw) 需要 admin 权限范围；仅在携带该请求头时校验凭据，避免每个请求查询令牌库
+    if !force_strict
+        && request
+            .headers()
+            .contains_key(crate::proxy::raw_passthrough::INCLUDE_RAW_HEADER)
+    {
+        let is_admin = presented_key(request.headers())
+            .map_or(false, |key| has_admin_scope(&security, key));
+        if is_admin {
+            request.extensions_mut().insert(AdminScope);
+        }
+    }
+
     // 权限检查逻辑
     if !force_strict {
         // AI 代理接口 (v1/chat/completions 等)
@@ -77,11 +91,7 @@ async fn auth_middleware_internal(
             if let Some(token) = api_key {
                 // 尝试验证是否为 User Token（不阻止请求，只记录）
                 if let Ok(Some(user_token)) = crate::modules::user_token_db::get_token_by_value(token) {
-                    let identity = UserTokenIdentity {
-                        token_id: user_token.id,
-                        token: user_token.token,
-                        username: user_token.username,
-                    };
+                    let identity = UserTokenIdentity::from_token(user_token);
                     // 注入 identity 到请求
                     let (mut parts, body) = request.into_parts();
                     parts.extensions.insert(identity);
@@ -162,6 +172,33 @@ async fn auth_middleware_internal(
 
     if authorized {
         Ok(next.run(request).await)
+    } else if force_strict && api_key.is_some() {
+        // [NEW] 管理接口: 尝试按权限范围校验签发的管理令牌
+        let requires_admin = !is_read_only_admin_route(&method, &path);
+        match crate::modules::user_token_db::authorize_admin_token(api_key.unwrap(), requires_admin) {
+            Ok(Some(Ok(_))) => Ok(next.run(request).await),
+            Ok(Some(Err(denial))) => {
+                tracing::warn!("Admin token rejected for {} {}: {}", method, path, denial.code());
+                let body = serde_json::json!({
+                    "error": {
+                        "message": denial.message(),
+                        "type": "token_rejected",
+                        "code": denial.code()
+                    }
+                });
+                let response = axum::response::Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .header("Content-Type", "application/json")
+                    .body(axum::body::Body::from(serde_json::to_string(&body).unwrap()))
+                    .unwrap();
+                Ok(response)
+            }
+            Ok(None) => Err(StatusCode::UNAUTHORIZED),
+            Err(e) => {
+                tracing::error!("Admin token validation error: {}", e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
     } else if !force_strict && api_key.is_some() {
         // 尝试验证 UserToken
         let token = api_key.unwrap();
@@ -181,33 +218,40 @@ async fn auth_middleware_internal(
             })
             .unwrap_or_else(|| "127.0.0.1".to_string()); // Default fallback
 
-        // 验证 Token
-        match crate::modules::user_token_db::validate_token(token, &client_ip) {
+        // 验证 Token: 令牌库只查询一次，未知令牌按未认证处理 (401)
+        let user_token = match crate::modules::user_token_db::get_token_by_value(token) {
+            Ok(Some(user_token)) => user_token,
+            Ok(None) => return Err(StatusCode::UNAUTHORIZED),
+            Err(e) => {
+                tracing::error!("UserToken lookup error: {}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+
+        match crate::modules::user_token_db::check_token_access(&user_token, &client_ip) {
             Ok((true, _)) => {
-                // Token 有效，查询信息以便传递
-                if let Ok(Some(user_token)) = crate::modules::user_token_db::get_token_by_value(token) {
-                     let identity = UserTokenIdentity {
-                        token_id: user_token.id,
-                        token: user_token.token,
-                        username: user_token.username,
-                    };
-                    
-                    // [FIX] 将身份信息注入到请求 extensions 中，而不是响应
-                    // 这样 monitor_middleware 在处理请求时就能获取到 identity
-                    // 因为中间件执行顺序：auth (外层) -> monitor (内层) -> handler
-                    // 响应返回时：handler -> monitor -> auth
-                    // 如果注入到 response，monitor 执行时 identity 还不存在
-                    let (mut parts, body) = request.into_parts();
-                    parts.extensions.insert(identity);
-                    let request = Request::from_parts(parts, body);
-                    
-                    // 执行请求
-                    let response = next.run(request).await;
-                    
-                    Ok(response)
-                } else {
-                    Err(StatusCode::UNAUTHORIZED)
+                // [NEW] 按令牌限流 (RPM / TPM 滑动窗口)，超限返回 429 + Retry-After
+                let now_ms = chrono::Utc::now().timestamp_millis();
+                if let Err(rejection) = enforce_rate_limit(key_rate_limiter(), &user_token, now_ms) {
+                    tracing::warn!("UserToken {} rate limited on {}", user_token.username, path);
+                    return Ok(rejection);
                 }
+
+                let identity = UserTokenIdentity::from_token(user_token);
+
+                // [FIX] 将身份信息注入到请求 extensions 中，而不是响应
+                // 这样 monitor_middleware 在处理请求时就能获取到 identity
+                // 因为中间件执行顺序：auth (外层) -> monitor (内层) -> handler
+                // 响应返回时：handler -> monitor -> auth
+                // 如果注入到 response，monitor 执行时 identity 还不存在
+                let (mut parts, body) = request.into_parts();
+                parts.extensions.insert(identity);
+                let request = Request::from_parts(parts, body);
+
+                // 执行请求
+                let response = next.run(request).await;
+
+                Ok(response)
             }
             Ok((false, reason)) => {
                 let reason_str = reason.unwrap_or_else(|| "Access denied".to_string());
@@ -236,6 +280,149 @@ async fn auth_middleware_internal(
     }
 }
 
+/// 限流滑动窗口长度
+const RATE_WINDOW_MS: i64 = 60_000;
+
+/// [NEW] 按令牌的滑动窗口请求计数 (RPM)；TPM 以 user_token_db 的使用日志为准
+#[derive(Default)]
+pub struct KeyRateLimiter {
+    requests: parking_lot::Mutex<HashMap<String, VecDeque<i64>>>,
+}
+
+impl KeyRateLimiter {
+    /// 记录一次请求；窗口已满时返回最早一次请求移出窗口前需等待的秒数
+    fn check_request(&self, key: &str, rpm: u32, now_ms: i64) -> Result<(), u64> {
+        let mut requests = self.requests.lock();
+        let window = requests.entry(key.to_string()).or_default();
+        while window
+            .front()
+            .map_or(false, |&t| t <= now_ms - RATE_WINDOW_MS)
+        {
+            window.pop_front();
+        }
+        if window.len() >= rpm as usize {
+            let oldest = window.front().copied().unwrap_or(now_ms);
+            return Err(retry_after_secs(oldest + RATE_WINDOW_MS - now_ms));
+        }
+        window.push_back(now_ms);
+        Ok(())
+    }
+}
+
+fn key_rate_limiter() -> &'static KeyRateLimiter {
+    static INSTANCE: OnceLock<KeyRateLimiter> = OnceLock::new();
+    INSTANCE.get_or_init(KeyRateLimiter::default)
+}
+
+fn retry_after_secs(wait_ms: i64) -> u64 {
+    ((wait_ms.max(0) + 999) / 1000).max(1) as u64
+}
+
+/// 窗口内已用 token 数 (最早一条记录时间 earliest_secs) 达到 TPM 上限时需等待的秒数
+fn tpm_retry_after(used: i64, earliest_secs: Option<i64>, tpm: u64, now_ms: i64) -> Option<u64> {
+    if used < tpm as i64 {
+        return None;
+    }
+    let earliest_ms = earliest_secs.map_or(now_ms, |secs| secs * 1000);
+    Some(retry_after_secs(earliest_ms + RATE_WINDOW_MS - now_ms))
+}
+
+fn too_many_requests(retry_after: u64, message: &str) -> Response {
+    let body = serde_json::json!({
+        "error": {
+            "message": message,
+            "type": "rate_limit_error",
+            "code": "rate_limit_exceeded"
+        }
+    });
+    axum::response::Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header("Content-Type", "application/json")
+        .header(header::RETRY_AFTER, retry_after)
+        .body(axum::body::Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// 应用令牌的 TPM / RPM 上限 (先查 TPM，被拒的请求不占用 RPM 名额)；None 或 0 表示不限
+fn enforce_rate_limit(
+    limiter: &KeyRateLimiter,
+    token: &crate::modules::user_token_db::UserToken,
+    now_ms: i64,
+) -> Result<(), Response> {
+    if let Some(tpm) = token.rate_limit_tpm.filter(|v| *v > 0) {
+        let since = (now_ms - RATE_WINDOW_MS) / 1000;
+        match crate::modules::user_token_db::get_token_usage_since(&token.id, since) {
+            Ok((used, earliest)) => {
+                if let Some(retry_after) = tpm_retry_after(used, earliest, tpm as u64, now_ms) {
+                    return Err(too_many_requests(
+                        retry_after,
+                        "Token rate limit exceeded (tokens per minute)",
+                    ));
+                }
+            }
+            Err(e) => tracing::warn!("TPM check skipped for token {}: {}", token.id, e),
+        }
+    }
+    if let Some(rpm) = token.rate_limit_rpm.filter(|v| *v > 0) {
+        limiter
+            .check_request(&token.id, rpm.min(u32::MAX as i64) as u32, now_ms)
+            .map_err(|retry_after| {
+                too_many_requests(retry_after, "Rate limit exceeded (requests per minute)")
+            })?;
+    }
+    Ok(())
+}
+
+/// 只读权限 (scope = read) 的管理令牌可访问的接口前缀 (仅限 GET)
+const READ_ONLY_ADMIN_PREFIXES: &[&str] = &[
+    "/health",
+    "/proxy/status",
+    "/proxy/stats",
+    "/stats/",
+    "/logs",
+    "/security/stats",
+    "/security/token-stats",
+    "/user-tokens/summary",
+];
+
+/// 是否为只读管理接口 (状态 / 统计 / 日志 / 用量)
+fn is_read_only_admin_route(method: &axum::http::Method, path: &str) -> bool {
+    if method != axum::http::Method::GET {
+        return false;
+    }
+    let path = path.strip_prefix("/api").unwrap_or(path);
+    READ_ONLY_ADMIN_PREFIXES.iter().any(|prefix| path.starts_with(prefix))
+}
+
+/// 请求携带的凭据 (Authorization Bearer / x-api-key / x-goog-api-key)
+pub(crate) fn presented_key(headers: &axum::http::HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer ").or(Some(s)))
+        .or_else(|| headers.get("x-api-key").and_then(|h| h.to_str().ok()))
+        .or_else(|| headers.get("x-goog-api-key").and_then(|h| h.to_str().ok()))
+}
+
+/// 凭据是否具备 admin 权限范围: 管理密码 (未设置时回退 api_key，与管理接口一致)，或 admin 范围的管理令牌
+fn has_admin_scope(security: &ProxySecurityConfig, key: &str) -> bool {
+    let admin_secret = match &security.admin_password {
+        Some(pwd) if !pwd.is_empty() => pwd.as_str(),
+        _ => security.api_key.as_str(),
+    };
+    if !admin_secret.is_empty() && key == admin_secret {
+        return true;
+    }
+    matches!(
+        crate::modules::user_token_db::authorize_admin_token(key, true),
+        Ok(Some(Ok(_)))
+    )
+}
+
+/// [NEW] 代理请求的调用方具备 admin 权限范围 (由鉴权中间件注入 request extensions)
+#[derive(Clone, Copy, Debug)]
+pub struct AdminScope;
+
 /// 用户令牌身份信息 (传递给 Monitor 使用)
 #[derive(Clone, Debug)]
 pub struct UserTokenIdentity {
@@ -243,6 +430,27 @@ pub struct UserTokenIdentity {
     #[allow(dead_code)] // 保留原始 token 便于审计/调试
     pub token: String,
     pub username: String,
+    /// [NEW] 令牌映射到的账号分组 (proxy.api_key_groups，未映射时为 None)
+    pub account_group: Option<String>,
+}
+
+impl UserTokenIdentity {
+    fn from_token(user_token: crate::modules::user_token_db::UserToken) -> Self {
+        let account_group = crate::proxy::account_groups::mapped_group(
+            Some(&user_token.token),
+            &crate::proxy::config::get_api_key_groups(),
+        )
+        .unwrap_or_else(|e| {
+            tracing::warn!("UserToken {} has an invalid account group mapping: {}", user_token.username, e);
+            None
+        });
+        Self {
+            token_id: user_token.id,
+            token: user_token.token,
+            username: user_token.username,
+            account_group,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -272,6 +480,188 @@ mod tests {
         // 我们在 auth_middleware_internal 基础上做了逻辑校验即可
     }
 
+    #[test]
+    fn test_read_only_admin_routes() {
+        use axum::http::Method;
+        assert!(is_read_only_admin_route(&Method::GET, "/api/proxy/status"));
+        assert!(is_read_only_admin_route(&Method::GET, "/stats/token/summary"));
+        assert!(is_read_only_admin_route(&Method::GET, "/api/logs/count"));
+        // 变更类接口需要 admin 权限
+        assert!(!is_read_only_admin_route(&Method::POST, "/api/system/cache/clear"));
+        assert!(!is_read_only_admin_route(&Method::POST, "/api/logs/clear"));
+        assert!(!is_read_only_admin_route(&Method::DELETE, "/api/accounts/abc"));
+        assert!(!is_read_only_admin_route(&Method::GET, "/api/config"));
+    }
+
+    #[test]
+    fn test_admin_scope_for_admin_password_or_api_key_fallback() {
+        let mut security = ProxySecurityConfig {
+            auth_mode: ProxyAuthMode::Strict,
+            api_key: "sk-api".to_string(),
+            admin_password: Some("admin123".to_string()),
+            allow_lan_access: true,
+            port: 8045,
+            security_monitor: crate::proxy::config::SecurityMonitorConfig::default(),
+        };
+        assert!(has_admin_scope(&security, "admin123"));
+        assert!(!has_admin_scope(&security, "sk-api"));
+
+        // 未设置管理密码时 api_key 即管理凭据
+        security.admin_password = None;
+        assert!(has_admin_scope(&security, "sk-api"));
+    }
+
+    fn rate_limited_token(rpm: Option<i64>) -> crate::modules::user_token_db::UserToken {
+        crate::modules::user_token_db::UserToken {
+            id: "token-1".to_string(),
+            token: "sk-limited".to_string(),
+            username: "alice".to_string(),
+            description: None,
+            enabled: true,
+            expires_type: "never".to_string(),
+            expires_at: None,
+            max_ips: 0,
+            curfew_start: None,
+            curfew_end: None,
+            created_at: 0,
+            updated_at: 0,
+            last_used_at: None,
+            total_requests: 0,
+            total_tokens_used: 0,
+            scope: "read".to_string(),
+            admin_api_access: false,
+            rate_limit_rpm: rpm,
+            rate_limit_tpm: None,
+        }
+    }
+
+    #[test]
+    fn test_sliding_window_rpm() {
+        let limiter = KeyRateLimiter::default();
+        assert_eq!(limiter.check_request("key-a", 3, 0), Ok(()));
+        assert_eq!(limiter.check_request("key-a", 3, 10_000), Ok(()));
+        assert_eq!(limiter.check_request("key-a", 3, 20_000), Ok(()));
+        // 窗口已满: 等待 t=0 的请求移出窗口
+        assert_eq!(limiter.check_request("key-a", 3, 30_000), Err(30));
+        // 其他令牌有独立窗口
+        assert_eq!(limiter.check_request("key-b", 3, 30_000), Ok(()));
+        // 窗口是滑动的而非整体重置: 只空出 t=0 的名额
+        assert_eq!(limiter.check_request("key-a", 3, 60_000), Ok(()));
+        assert_eq!(limiter.check_request("key-a", 3, 60_500), Err(10));
+
+        // TPM: 用量达到上限时等待最早一条记录移出窗口
+        assert_eq!(tpm_retry_after(900, Some(100), 1000, 130_000), None);
+        assert_eq!(tpm_retry_after(1000, Some(100), 1000, 130_000), Some(30));
+    }
+
+    #[test]
+    fn test_rate_limited_token_gets_429_with_retry_after() {
+        let limiter = KeyRateLimiter::default();
+        let token = rate_limited_token(Some(1));
+        assert!(enforce_rate_limit(&limiter, &token, 1_000).is_ok());
+
+        let response = enforce_rate_limit(&limiter, &token, 1_500).unwrap_err();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+
+        // 未设置上限 (None / 0) 的令牌不限流
+        for unlimited in [rate_limited_token(None), rate_limited_token(Some(0))] {
+            for i in 0..100 {
+                assert!(enforce_rate_limit(&limiter, &unlimited, 2_000 + i).is_ok());
+            }
+        }
+    }
+
+    /// 经由真实中间件栈发送请求，handler 回显注入的用户令牌身份
+    async fn send_proxy_request(security: ProxySecurityConfig, key: Option<(&str, &str)>) -> (StatusCode, String) {
+        use tower::ServiceExt;
+
+        let app = axum::Router::new()
+            .route(
+                "/v1/models",
+                axum::routing::get(|request: Request| async move {
+                    request
+                        .extensions()
+                        .get::<UserTokenIdentity>()
+                        .map(|identity| match &identity.account_group {
+                            Some(group) => format!("{}@{}", identity.username, group),
+                            None => identity.username.clone(),
+                        })
+                        .unwrap_or_default()
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(RwLock::new(security)),
+                auth_middleware,
+            ));
+        let mut builder = Request::builder().uri("/v1/models");
+        if let Some((name, value)) = key {
+            builder = builder.header(name, value);
+        }
+        let response = app
+            .oneshot(builder.body(axum::body::Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8_lossy(&body).to_string())
+    }
+
+    fn strict_security() -> ProxySecurityConfig {
+        ProxySecurityConfig {
+            auth_mode: ProxyAuthMode::Strict,
+            api_key: "sk-api".to_string(),
+            admin_password: None,
+            allow_lan_access: false,
+            port: 8045,
+            security_monitor: crate::proxy::config::SecurityMonitorConfig::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proxy_auth_valid_missing_and_invalid_key() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire().await;
+        crate::modules::user_token_db::init_db().unwrap();
+
+        let (status, _) = send_proxy_request(strict_security(), Some(("authorization", "Bearer sk-api"))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = send_proxy_request(strict_security(), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let unknown = format!("sk-unknown-{}", uuid::Uuid::new_v4());
+        let (status, _) = send_proxy_request(strict_security(), Some(("x-api-key", &unknown))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_auth_attaches_user_token_identity_and_rejects_disabled() {
+        use crate::modules::user_token_db;
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire().await;
+        user_token_db::init_db().unwrap();
+
+        let username = "AuthUser".to_string();
+        let token = user_token_db::create_token(username.clone(), "never".to_string(), None, 0, None, None, None).unwrap();
+
+        let (status, body) = send_proxy_request(strict_security(), Some(("x-api-key", &token.token))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, username);
+
+        // 令牌映射了账号分组时，身份中携带该分组
+        let previous_groups = crate::proxy::config::get_api_key_groups();
+        let mut groups = previous_groups.clone();
+        groups.insert(token.token.clone(), "Team-A".to_string());
+        crate::proxy::config::update_api_key_groups(groups);
+        let (status, body) = send_proxy_request(strict_security(), Some(("x-api-key", &token.token))).await;
+        crate::proxy::config::update_api_key_groups(previous_groups);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, format!("{}@team-a", username));
+
+        user_token_db::update_token(&token.id, None, None, Some(false), None, None, None, None, None).unwrap();
+        let (status, _) = send_proxy_request(strict_security(), Some(("x-api-key", &token.token))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
     #[test]
     fn test_auth_placeholder() {
         assert!(true);
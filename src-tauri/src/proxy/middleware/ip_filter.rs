@@ -115,7 +115,7 @@ pub async fn ip_filter_middleware(
                         username: None,
                     };
                     
-                    tokio::spawn(async move {
+                    tokio::task::spawn_blocking(move || {
                         if let Err(e) = security_db::save_ip_access_log(&log) {
                             tracing::error!("[IP Filter] Failed to save blocked access log: {}", e);
                         }
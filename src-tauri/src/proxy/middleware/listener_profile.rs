@@ -0,0 +1,44 @@
+// 监听配置档中间件
+// 校验配置档的客户端 Key 白名单，并为请求设置配置档上下文
+use axum::{
+    extract::Request,
+    extract::State,
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+
+use crate::proxy::config::ListenerProfile;
+
+/// 从请求头提取客户端 Key (Authorization Bearer / x-api-key / x-goog-api-key)
+fn extract_client_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.strip_prefix("Bearer ").unwrap_or(s))
+        .or_else(|| headers.get("x-api-key").and_then(|h| h.to_str().ok()))
+        .or_else(|| headers.get("x-goog-api-key").and_then(|h| h.to_str().ok()))
+}
+
+pub async fn listener_profile_middleware(
+    State(profile): State<Arc<ListenerProfile>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    if request.method() != Method::OPTIONS && !profile.allowed_client_keys.is_empty() {
+        let allowed = extract_client_key(request.headers())
+            .map(|key| profile.allowed_client_keys.iter().any(|k| k == key))
+            .unwrap_or(false);
+        if !allowed {
+            tracing::warn!(
+                "[ListenerProfile] 客户端 Key 不在配置档 {} 的白名单中: {}",
+                profile.name,
+                request.uri().path()
+            );
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    Ok(crate::proxy::listener_profile::scope(profile, next.run(request)).await)
+}
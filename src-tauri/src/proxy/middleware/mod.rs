@@ -1,10 +1,13 @@
 // Middleware 模块 - Axum 中间件
 
+pub mod account_group;
 pub mod auth;
 pub mod cors;
 pub mod logging;
 pub mod monitor;
 pub mod ip_filter;
+pub mod listener_profile;
+pub mod query_overrides;
 
 pub mod service_status;
 
@@ -13,3 +16,6 @@ pub use monitor::monitor_middleware;
 pub use service_status::service_status_middleware;
 pub use auth::{auth_middleware, admin_auth_middleware};
 pub use ip_filter::ip_filter_middleware;
+pub use listener_profile::listener_profile_middleware;
+pub use query_overrides::query_overrides_middleware;
+pub use account_group::account_group_middleware;
@@ -9,8 +9,25 @@ use crate::proxy::server::AppState;
 use crate::proxy::monitor::ProxyRequestLog;
 use serde_json::Value;
 use crate::proxy::middleware::auth::UserTokenIdentity;
+use crate::proxy::model_versions::{self, ModelVersionSlot};
 use futures::StreamExt;
 
+/// [NEW] 请求重放指纹槽位
+/// 由 monitor 中间件注入请求 extensions，协议 handler 解析并清洗请求后写入指纹，
+/// 中间件在响应返回后读取并随日志写入 token 统计
+#[derive(Debug, Clone, Default)]
+pub struct ReplayHashSlot(std::sync::Arc<std::sync::OnceLock<String>>);
+
+impl ReplayHashSlot {
+    pub fn set(&self, hash: String) {
+        let _ = self.0.set(hash);
+    }
+
+    pub fn get(&self) -> Option<String> {
+        self.0.get().cloned()
+    }
+}
+
 const MAX_REQUEST_LOG_SIZE: usize = 100 * 1024 * 1024; // 100MB
 const MAX_RESPONSE_LOG_SIZE: usize = 100 * 1024 * 1024; // 100MB for image responses
 
@@ -21,21 +38,53 @@ fn record_user_token_usage(
     user_agent: Option<String>,
 ) {
     if let Some(identity) = user_token_identity {
-        let _ = crate::modules::user_token_db::record_token_usage_and_ip(
-            &identity.token_id,
-            log.client_ip.as_deref().unwrap_or("127.0.0.1"),
-            log.model.as_deref().unwrap_or("unknown"),
-            log.input_tokens.unwrap_or(0) as i32,
-            log.output_tokens.unwrap_or(0) as i32,
-            log.status as u16,
-            user_agent,
-        );
+        let token_id = identity.token_id.clone();
+        let client_ip = log.client_ip.clone().unwrap_or_else(|| "127.0.0.1".to_string());
+        let model = log.model.clone().unwrap_or_else(|| "unknown".to_string());
+        let input_tokens = log.input_tokens.unwrap_or(0) as i32;
+        let output_tokens = log.output_tokens.unwrap_or(0) as i32;
+        let status = log.status as u16;
+        // 阻塞线程池执行: 数据库维护期间写入闸门会阻塞写入方，不能占用 tokio worker
+        tokio::task::spawn_blocking(move || {
+            let _ = crate::modules::user_token_db::record_token_usage_and_ip(
+                &token_id,
+                &client_ip,
+                &model,
+                input_tokens,
+                output_tokens,
+                status,
+                user_agent,
+            );
+        });
+    }
+}
+
+/// [NEW] 成功请求的上游模型版本交给漂移跟踪 (按映射后的模型归类)
+fn observe_model_version(integration: &crate::modules::integration::SystemManager, log: &ProxyRequestLog) {
+    if log.status >= 400 {
+        return;
+    }
+    if let (Some(model), Some(version)) = (
+        log.mapped_model.as_deref().or(log.model.as_deref()),
+        log.model_version.as_deref(),
+    ) {
+        model_versions::observe(integration, model, version);
     }
 }
 
+/// Extract cached input tokens (Anthropic / OpenAI / Gemini usage formats)
+fn extract_cached_tokens(usage: &Value) -> Option<u32> {
+    usage
+        .get("cache_read_input_tokens")
+        .or_else(|| usage.get("prompt_tokens_details").and_then(|d| d.get("cached_tokens")))
+        .or_else(|| usage.get("cachedContentTokenCount"))
+        .and_then(|v| v.as_u64())
+        .map(|v| v as u32)
+}
+
 pub async fn monitor_middleware(
     State(state): State<AppState>,
-    request: Request,
+    mut request: Request,
     next: Next,
 ) -> Response {
     let _logging_enabled = state.monitor.is_enabled();
@@ -85,6 +134,11 @@ pub async fn monitor_middleware(
     // [FIX] 从请求 extensions 提取 UserTokenIdentity (由 Auth 中间件注入)
     // 必须在处理 request body 之前提取，因为 into_parts() 后需要保留这个值
     let user_token_identity = request.extensions().get::<UserTokenIdentity>().cloned();
+
+    let replay_hash_slot = ReplayHashSlot::default();
+    request.extensions_mut().insert(replay_hash_slot.clone());
+    // [NEW] 上游 modelVersion 槽位 (handler 包装上游流时捕获)
+    let model_version_slot = ModelVersionSlot::default();
     
     let request = if method == "POST" {
         let (parts, body) = request.into_parts();
@@ -112,7 +166,7 @@ pub async fn monitor_middleware(
         request
     };
     
-    let response = next.run(request).await;
+    let response = model_versions::scope(model_version_slot.clone(), next.run(request)).await;
     
     // user_token_identity 已在上面从请求 extensions 中提取
     
@@ -138,6 +192,13 @@ pub async fn monitor_middleware(
         .and_then(|v| v.to_str().ok())
         .map(|s| s.to_string());
 
+    // [NEW] URL 查询参数覆盖 (由 query_overrides 中间件通过响应头回显)
+    let query_overrides = response
+        .headers()
+        .get(crate::proxy::query_overrides::OVERRIDES_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+
     // Determine protocol from URL path
     let protocol = if uri.contains("/v1/messages") {
         Some("anthropic".to_string())
@@ -171,8 +232,13 @@ pub async fn monitor_middleware(
         response_body: None,
         input_tokens: None,
         output_tokens: None,
+        cached_tokens: None,
         protocol,
         username,
+        request_hash: replay_hash_slot.get(),
+        output_breakdown: None,
+        model_version: model_version_slot.get(),
+        query_overrides,
     };
 
 
@@ -180,13 +246,28 @@ pub async fn monitor_middleware(
         let (parts, body) = response.into_parts();
         let mut stream = body.into_data_stream();
         let (tx, rx) = tokio::sync::mpsc::channel(64);
+        let integration = state.integration.clone();
         
         tokio::spawn(async move {
             let mut all_stream_data = Vec::new();
             let mut last_few_bytes = Vec::new();
+            let mut first_chunk_seen = false;
             
             while let Some(chunk_res) = stream.next().await {
                 if let Ok(chunk) = chunk_res {
+                    // [NEW] 首字延迟 SLO 监控 (仅成功的流式请求)
+                    if !first_chunk_seen {
+                        first_chunk_seen = true;
+                        if log.status < 400 {
+                            if let Some(slo_model) = log.mapped_model.as_deref().or(log.model.as_deref()) {
+                                crate::proxy::latency_slo::observe(
+                                    &integration,
+                                    slo_model,
+                                    start.elapsed().as_millis() as u64,
+                                );
+                            }
+                        }
+                    }
                     all_stream_data.extend_from_slice(&chunk);
                     
                     if chunk.len() > 8192 {
@@ -340,6 +421,7 @@ pub async fn monitor_middleware(
                             .or(json.get("usageMetadata"))
                             .or(json.get("response").and_then(|r| r.get("usage")))
                         {
+                            log.cached_tokens = extract_cached_tokens(usage);
                             log.input_tokens = usage.get("prompt_tokens")
                                 .or(usage.get("input_tokens"))
                                 .or(usage.get("promptTokenCount"))
@@ -408,6 +490,7 @@ pub async fn monitor_middleware(
                                     .or(json.get("usageMetadata"))
                                     .or(json.get("response").and_then(|r| r.get("usage")))
                                 {
+                                    log.cached_tokens = extract_cached_tokens(usage);
                                     log.input_tokens = usage.get("prompt_tokens")
                                         .or(usage.get("input_tokens"))
                                         .or(usage.get("promptTokenCount"))
@@ -430,6 +513,12 @@ pub async fn monitor_middleware(
                 log.error = Some("Stream Error or Failed".to_string());
             }
 
+            // 流结束时上游首块必然已读取
+            if log.model_version.is_none() {
+                log.model_version = model_version_slot.get();
+            }
+            observe_model_version(&integration, &log);
+
             // Record User Token Usage
             record_user_token_usage(&user_token_identity, &log, user_agent.clone());
 
@@ -445,6 +534,7 @@ pub async fn monitor_middleware(
                     if let Ok(json) = serde_json::from_str::<Value>(&s) {
                         // 支持 OpenAI "usage" 或 Gemini "usageMetadata"
                         if let Some(usage) = json.get("usage").or(json.get("usageMetadata")) {
+                            log.cached_tokens = extract_cached_tokens(usage);
                             log.input_tokens = usage.get("prompt_tokens")
                                 .or(usage.get("input_tokens"))
                                 .or(usage.get("promptTokenCount"))
@@ -463,6 +553,10 @@ pub async fn monitor_middleware(
                                     .map(|v| v as u32);
                             }
                         }
+                        // [NEW] 收集器附加的输出 token 拆分 (扩展字段)
+                        log.output_breakdown = json
+                            .get("_abv_usage_breakdown")
+                            .and_then(|b| serde_json::from_value(b.clone()).ok());
                     }
                     log.response_body = Some(s.to_string());
                 } else {
@@ -472,6 +566,7 @@ pub async fn monitor_middleware(
                 if log.status >= 400 {
                     log.error = log.response_body.clone();
                 }
+                observe_model_version(&state.integration, &log);
 
                 // Record User Token Usage
                 record_user_token_usage(&user_token_identity, &log, user_agent.clone());
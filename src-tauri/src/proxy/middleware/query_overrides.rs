@@ -0,0 +1,86 @@
+// URL 查询参数覆盖中间件 (仅挂载在 Claude / OpenAI 对话路由上)
+// 在 handler 解析请求体之前改写 JSON，并通过 x-abv-overrides 响应头回显实际应用的覆盖项
+// (监控中间件据此把覆盖项写入请求日志)
+// (x-abv-response-language 请求头同样在此解析)
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::proxy::query_overrides::{self, OVERRIDES_HEADER, RESPONSE_LANGUAGE_HEADER};
+use crate::proxy::server::AppState;
+
+fn bad_request(is_claude: bool, message: String) -> Response {
+    let body = if is_claude {
+        json!({
+            "type": "error",
+            "error": { "type": "invalid_request_error", "message": message }
+        })
+    } else {
+        json!({
+            "error": { "message": message, "type": "invalid_request_error", "code": 400 }
+        })
+    };
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+pub async fn query_overrides_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let enabled = state.debug_logging.read().await.allow_query_overrides;
+    let is_claude = request.uri().path().starts_with("/v1/messages");
+
+    let response_language = request
+        .headers()
+        .get(RESPONSE_LANGUAGE_HEADER)
+        .and_then(|v| v.to_str().ok());
+
+    let overrides = match query_overrides::resolve(request.uri().query(), response_language, enabled) {
+        Ok(Some(overrides)) => overrides,
+        Ok(None) => return next.run(request).await,
+        Err(e) => {
+            tracing::warn!("[Query-Overrides] Rejected {}: {}", request.uri(), e);
+            return bad_request(is_claude, e);
+        }
+    };
+
+    let (mut parts, body) = request.into_parts();
+    // 与 DefaultBodyLimit 使用同一上限，避免读取无界请求体
+    let limit = crate::proxy::server::max_body_size();
+    let bytes = match axum::body::to_bytes(body, limit).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return bad_request(
+                is_claude,
+                format!("Failed to read request body (limit {} bytes): {}", limit, e),
+            )
+        }
+    };
+    // 非 JSON 请求体原样交给 handler 处理 (由其返回解析错误)
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(mut value) => {
+            overrides.apply_to_body(&mut value);
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Body::from(serde_json::to_vec(&value).unwrap_or_else(|_| bytes.to_vec()))
+        }
+        Err(_) => Body::from(bytes),
+    };
+
+    let summary = overrides.summary();
+    tracing::info!("[Query-Overrides] {} applied: {}", parts.uri.path(), summary);
+
+    let request = Request::from_parts(parts, body);
+    let mut response = query_overrides::scope(Arc::new(overrides), next.run(request)).await;
+    if let Ok(value) = HeaderValue::from_str(&summary) {
+        response.headers_mut().insert(OVERRIDES_HEADER, value);
+    }
+    response
+}
@@ -11,19 +11,32 @@ pub mod token_manager;
 pub mod audio; // 音频处理模块
 pub mod cli_sync; // CLI 配置同步 (v3.3.35)
 pub mod droid_sync; // Droid (Factory CLI) 配置同步
+pub mod flags; // 功能开关 (灰度发布)
 pub mod common; // 公共工具
 pub mod debug_logger;
 pub mod handlers; // API 端点处理器
+pub mod account_groups; // 账号分组 (租户隔离)
+pub mod account_warmup; // 新账号预热策略
+pub mod latency_slo; // 首字延迟 SLO 监控
+pub mod model_versions; // 上游 modelVersion 漂移跟踪
+pub mod listener_profile; // 监听配置档 (多端口)
 pub mod mappers; // 协议转换器
 pub mod middleware; // Axum 中间件
 pub mod monitor; // 监控
 pub mod opencode_sync; // OpenCode 配置同步
+pub mod poison_quarantine; // 毒消息隔离
 pub mod providers; // Extra upstream providers (z.ai, etc.)
 pub mod proxy_pool; // 代理池管理器
+pub mod query_overrides; // URL 查询参数覆盖 (调试)
+pub mod raw_passthrough; // 原始 Gemini 响应透传 (调试)
 pub mod rate_limit; // 限流跟踪
 pub mod session_manager; // 会话指纹管理
+pub mod session_registry; // 会话活跃登记与空闲状态回收
 pub mod signature_cache; // Signature Cache (v3.3.16)
+pub mod signature_sentinel; // thoughtSignature 哨兵值能力学习
 pub mod sticky_config; // 粘性调度配置
+pub mod stream_slots; // 每账号并发流计数
+pub mod stream_recording; // 流式会话录制 / 回放 (调试)
 pub mod upstream; // 上游客户端
 pub mod zai_vision_mcp; // Built-in Vision MCP server state
 pub mod zai_vision_tools; // Built-in Vision MCP tools (z.ai vision API) // 调试日志
@@ -31,6 +44,36 @@ pub mod zai_vision_tools; // Built-in Vision MCP tools (z.ai vision API) // 调
 pub use config::update_global_system_prompt_config;
 pub use config::update_thinking_budget_config;
 pub use config::update_image_thinking_mode;
+pub use config::update_image_text_fallback_model;
+pub use config::update_forced_response_language;
+pub use config::update_image_multimodal_output;
+pub use config::update_strict_role_alternation;
+pub use config::update_drop_code_execution_parts;
+pub use config::update_citation_style;
+pub use config::update_tool_limit_config;
+pub use config::update_envelope_sanitize_action;
+pub use config::update_latency_slo_config;
+pub use config::update_max_account_rotations;
+pub use config::update_max_concurrent_streams_per_account;
+pub use config::update_session_idle_ttl_secs;
+pub use config::update_stream_resumption;
+pub use config::update_poison_quarantine;
+pub use config::update_thinking_nudge;
+pub use config::update_strip_historical_thinking_models;
+pub use config::update_max_json_clean_depth;
+pub use config::update_token_refresh_ahead_secs;
+pub use config::update_recent_failure_window_secs;
+pub use config::update_account_warmup_config;
+pub use config::update_dummy_thought_config;
+pub use config::update_task_echo_dedup_config;
+pub use config::update_report_web_search_usage;
+pub use config::update_delta_coalescing_config;
+pub use config::update_request_id_prefix_config;
+pub use config::update_protective_stop_sequences;
+pub use config::update_stream_redactions;
+pub use config::update_client_profiles;
+pub use config::update_feature_flags;
+pub use config::update_api_key_groups;
 pub use config::ProxyAuthMode;
 pub use config::ProxyConfig;
 pub use config::ProxyPoolConfig;
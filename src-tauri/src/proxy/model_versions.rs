@@ -0,0 +1,255 @@
+// 上游 modelVersion 漂移跟踪
+// 同一个映射模型背后实际服务的具体版本可能被上游静默切换，导致同一会话前后表现不一致。
+// 协议 handler 从上游首个响应块中提取 modelVersion 写入请求级槽位 (task-local 传递)，
+// monitor 中间件随请求日志和 token 统计一起记录；某个模型出现新版本时记录日志并通知一次。
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+
+/// 在上游响应中查找 modelVersion 的最大扫描字节数 (超过后放弃)
+const MAX_SCAN_BYTES: usize = 64 * 1024;
+/// modelVersion 长度上限，超出视为异常数据
+const MAX_VERSION_LEN: usize = 128;
+
+type UpstreamByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>;
+
+tokio::task_local! {
+    static CURRENT_SLOT: ModelVersionSlot;
+}
+
+/// 请求级 modelVersion 槽位：由 monitor 中间件创建，handler 在读取上游响应时写入
+#[derive(Debug, Clone, Default)]
+pub struct ModelVersionSlot(Arc<OnceLock<String>>);
+
+impl ModelVersionSlot {
+    pub fn set(&self, version: String) {
+        let _ = self.0.set(version);
+    }
+
+    pub fn get(&self) -> Option<String> {
+        self.0.get().cloned()
+    }
+}
+
+/// 在槽位上下文中执行 future (handler 在其中构造的上游流会捕获该槽位)
+pub async fn scope<F: Future>(slot: ModelVersionSlot, fut: F) -> F::Output {
+    CURRENT_SLOT.scope(slot, fut).await
+}
+
+fn current_slot() -> Option<ModelVersionSlot> {
+    CURRENT_SLOT.try_with(|s| s.clone()).ok()
+}
+
+/// 从上游 JSON / SSE 文本中提取第一个完整的 modelVersion 值
+pub fn extract_model_version(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    let key = "\"modelVersion\"";
+    let start = text.find(key)? + key.len();
+    let rest = text[start..]
+        .trim_start()
+        .strip_prefix(':')?
+        .trim_start()
+        .strip_prefix('"')?;
+    let end = rest.find('"')?;
+    let version = rest[..end].trim();
+    (!version.is_empty() && version.len() <= MAX_VERSION_LEN).then(|| version.to_string())
+}
+
+/// 非流式响应: 从完整响应体中记录 modelVersion
+pub fn record_from_bytes(data: &[u8]) {
+    if let (Some(slot), Some(version)) = (current_slot(), extract_model_version(data)) {
+        slot.set(version);
+    }
+}
+
+/// 非流式响应: 从已解析的响应 (可能带 v1internal 的 response 包装) 中记录 modelVersion
+pub fn record_from_json(value: &serde_json::Value) {
+    let version = value
+        .get("response")
+        .unwrap_or(value)
+        .get("modelVersion")
+        .and_then(|v| v.as_str())
+        .map(str::trim)
+        .filter(|v| !v.is_empty() && v.len() <= MAX_VERSION_LEN);
+    if let (Some(slot), Some(version)) = (current_slot(), version) {
+        slot.set(version.to_string());
+    }
+}
+
+/// 包装上游字节流，从首个携带 modelVersion 的 SSE 块中提取版本 (不在槽位上下文中时原样返回)
+pub fn tap_stream(stream: UpstreamByteStream) -> UpstreamByteStream {
+    let Some(slot) = current_slot() else {
+        return stream;
+    };
+    // 跨块缓存，处理 modelVersion 字段被拆分到两个网络块的情况
+    let mut pending: Vec<u8> = Vec::new();
+    let mut done = false;
+    Box::pin(stream.inspect(move |item| {
+        if done {
+            return;
+        }
+        if let Ok(bytes) = item {
+            pending.extend_from_slice(bytes);
+            if let Some(version) = extract_model_version(&pending) {
+                slot.set(version);
+                done = true;
+            } else if pending.len() > MAX_SCAN_BYTES {
+                done = true;
+            }
+            if done {
+                pending = Vec::new();
+            }
+        }
+    }))
+}
+
+/// 某个模型的服务版本发生变化
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionChange {
+    pub model: String,
+    pub previous: String,
+    pub current: String,
+    /// 该版本此前是否从未出现过 (仅新版本发送通知)
+    pub is_new: bool,
+}
+
+#[derive(Default)]
+struct TrackerState {
+    current: HashMap<String, String>,
+    seen: HashSet<(String, String)>,
+}
+
+#[derive(Default)]
+pub struct ModelVersionTracker {
+    state: Mutex<TrackerState>,
+}
+
+impl ModelVersionTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录一次观测，版本与上次不同时返回变化 (首次观测只建立基线)
+    pub fn record(&self, model: &str, version: &str) -> Option<VersionChange> {
+        let mut state = self.state.lock();
+        let is_new = state.seen.insert((model.to_string(), version.to_string()));
+        let previous = state.current.insert(model.to_string(), version.to_string())?;
+        (previous != version).then(|| VersionChange {
+            model: model.to_string(),
+            previous,
+            current: version.to_string(),
+            is_new,
+        })
+    }
+}
+
+static GLOBAL_TRACKER: OnceLock<ModelVersionTracker> = OnceLock::new();
+
+fn global() -> &'static ModelVersionTracker {
+    GLOBAL_TRACKER.get_or_init(ModelVersionTracker::new)
+}
+
+/// 记录映射模型的服务版本，出现新版本时记录日志并通过系统集成层通知一次
+pub fn observe(
+    integration: &crate::modules::integration::SystemManager,
+    model: &str,
+    version: &str,
+) {
+    let Some(change) = global().record(model, version) else {
+        return;
+    };
+    if !change.is_new {
+        tracing::debug!(
+            "[Model-Version] {} switched back to {} (was {})",
+            change.model,
+            change.current,
+            change.previous
+        );
+        return;
+    }
+    tracing::info!(
+        "[Model-Version] {} now served by {} (was {})",
+        change.model,
+        change.current,
+        change.previous
+    );
+    integration.show_notification(
+        "Upstream model version changed",
+        &format!("{}: {} → {}", change.model, change.previous, change.current),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstream_chunk(version: &str) -> Vec<u8> {
+        format!(
+            "data: {{\"response\":{{\"candidates\":[{{\"content\":{{\"parts\":[{{\"text\":\"Hi\"}}]}}}}],\"modelVersion\":\"{}\",\"responseId\":\"r1\"}}}}\n\n",
+            version
+        )
+        .into_bytes()
+    }
+
+    async fn capture(chunks: Vec<Vec<u8>>) -> Option<String> {
+        let slot = ModelVersionSlot::default();
+        let items: Vec<Result<Bytes, reqwest::Error>> =
+            chunks.into_iter().map(|c| Ok(Bytes::from(c))).collect();
+        scope(slot.clone(), async {
+            let stream = tap_stream(Box::pin(futures::stream::iter(items)));
+            stream.collect::<Vec<_>>().await;
+        })
+        .await;
+        slot.get()
+    }
+
+    #[tokio::test]
+    async fn test_version_change_notifies_once_and_records_both() {
+        // 两个模拟的上游响应携带不同的 modelVersion
+        let first = capture(vec![upstream_chunk("gemini-3-pro-preview-1105")]).await;
+        // 字段被拆分到两个网络块
+        let chunk = upstream_chunk("gemini-3-pro-preview-1201");
+        let split = String::from_utf8_lossy(&chunk).find("modelVersion").unwrap() + 5;
+        let (a, b) = chunk.split_at(split);
+        let second = capture(vec![a.to_vec(), b.to_vec()]).await;
+        assert_eq!(first.as_deref(), Some("gemini-3-pro-preview-1105"));
+        assert_eq!(second.as_deref(), Some("gemini-3-pro-preview-1201"));
+
+        let tracker = ModelVersionTracker::new();
+        let changes: Vec<VersionChange> = [first, second.clone(), second]
+            .iter()
+            .filter_map(|v| tracker.record("gemini-3-pro", v.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![VersionChange {
+                model: "gemini-3-pro".to_string(),
+                previous: "gemini-3-pro-preview-1105".to_string(),
+                current: "gemini-3-pro-preview-1201".to_string(),
+                is_new: true,
+            }]
+        );
+
+        // 回切到已出现过的版本不再视为新版本
+        let back = tracker.record("gemini-3-pro", "gemini-3-pro-preview-1105").unwrap();
+        assert!(!back.is_new);
+        // 其它模型互不影响
+        assert!(tracker.record("gemini-3-flash", "gemini-3-flash-001").is_none());
+    }
+
+    #[test]
+    fn test_extract_model_version() {
+        assert_eq!(
+            extract_model_version(br#"{"candidates":[],"modelVersion" : "gemini-2.5-flash"}"#).as_deref(),
+            Some("gemini-2.5-flash")
+        );
+        assert!(extract_model_version(br#"{"modelVersion":"gemini-2.5-fl"#).is_none());
+        assert!(extract_model_version(br#"{"modelVersion":""}"#).is_none());
+        assert!(extract_model_version(b"data: {\"candidates\":[]}").is_none());
+    }
+}
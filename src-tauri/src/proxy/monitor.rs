@@ -21,8 +21,18 @@ pub struct ProxyRequestLog {
     pub response_body: Option<String>,
     pub input_tokens: Option<u32>,
     pub output_tokens: Option<u32>,
+    #[serde(default)]
+    pub cached_tokens: Option<u32>,   // 缓存命中的输入 token 数
     pub protocol: Option<String>,     // 协议类型: "openai", "anthropic", "gemini"
     pub username: Option<String>,     // User token username
+    #[serde(default)]
+    pub request_hash: Option<String>, // [NEW] 请求重放指纹，用于 token 统计中识别客户端重试
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_breakdown: Option<crate::modules::token_stats::OutputTokenBreakdown>, // [NEW] 输出 token 按内容类型拆分
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_version: Option<String>, // [NEW] 上游实际服务的具体模型版本 (modelVersion)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query_overrides: Option<String>, // [NEW] 本次请求实际应用的 URL 查询参数覆盖 (x-abv-overrides)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -74,6 +84,13 @@ impl ProxyMonitor {
         self.enabled.store(enabled, Ordering::Relaxed);
     }
 
+    /// [NEW] 向前端发送事件 (Headless 模式无 app_handle 时忽略)
+    pub fn emit_event<S: Serialize + Clone>(&self, event: &str, payload: S) {
+        if let Some(app) = &self.app_handle {
+            let _ = app.emit(event, payload);
+        }
+    }
+
     pub fn is_enabled(&self) -> bool {
         self.enabled.load(Ordering::Relaxed)
     }
@@ -86,8 +103,13 @@ impl ProxyMonitor {
         ) {
             let model = log.model.clone().unwrap_or_else(|| "unknown".to_string());
             let account = account.clone();
-            tokio::spawn(async move {
-                if let Err(e) = crate::modules::token_stats::record_usage(&account, &model, input, output) {
+            let cached = log.cached_tokens.unwrap_or(0);
+            let client_key = log.username.clone();
+            let request_hash = log.request_hash.clone();
+            let breakdown = log.output_breakdown;
+            let model_version = log.model_version.clone();
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = crate::modules::token_stats::record_usage(&account, &model, input, output, cached, client_key.as_deref(), request_hash.as_deref(), breakdown.as_ref(), model_version.as_deref()) {
                     tracing::debug!("Failed to record token stats: {}", e);
                 }
             });
@@ -117,9 +139,9 @@ impl ProxyMonitor {
             logs.push_front(log.clone());
         }
 
-        // Save to DB
+        // Save to DB (阻塞线程池执行: 数据库维护期间写入闸门会阻塞写入方，不能占用 tokio worker)
         let log_to_save = log.clone();
-        tokio::spawn(async move {
+        tokio::task::spawn_blocking(move || {
             if let Err(e) = crate::modules::proxy_db::save_log(&log_to_save) {
                 tracing::error!("Failed to save proxy log to DB: {}", e);
             }
@@ -145,18 +167,6 @@ impl ProxyMonitor {
                      tracing::error!("Failed to save security log: {}", e);
                 }
             }
-
-            // Record token stats if available
-            if let (Some(account), Some(input), Some(output)) = (
-                &log_to_save.account_email,
-                log_to_save.input_tokens,
-                log_to_save.output_tokens,
-            ) {
-                let model = log_to_save.model.clone().unwrap_or_else(|| "unknown".to_string());
-                if let Err(e) = crate::modules::token_stats::record_usage(account, &model, input, output) {
-                    tracing::debug!("Failed to record token stats: {}", e);
-                }
-            }
         });
 
         // Emit event (send summary only, without body to reduce memory)
@@ -177,8 +187,13 @@ impl ProxyMonitor {
                 response_body: None, // Don't send body in event
                 input_tokens: log.input_tokens,
                 output_tokens: log.output_tokens,
+                cached_tokens: log.cached_tokens,
                 protocol: log.protocol.clone(),
                 username: log.username.clone(),
+                request_hash: log.request_hash.clone(),
+                output_breakdown: log.output_breakdown,
+                model_version: log.model_version.clone(),
+                query_overrides: log.query_overrides.clone(),
             };
             let _ = app.emit("proxy://request", &log_summary);
         }
@@ -0,0 +1,446 @@
+// 毒消息隔离
+// 某条历史消息 (如含非法代理对的 Windows 路径 tool_result) 可能让会话的每次请求都被上游 400，
+// 任何重试策略都无法绕过。重试耗尽后通过二分探测找出最小的问题消息集合，
+// 用简短占位替换后再重试一次；成功则按会话缓存问题消息的哈希，后续轮次预先替换。
+// 探测会额外消耗上游请求，需通过 poison_quarantine 配置显式开启。
+
+use crate::proxy::mappers::claude::models::{ContentBlock, Message, MessageContent};
+use dashmap::DashMap;
+use futures::Future;
+use serde_json::{json, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::OnceLock;
+
+/// 替换问题消息的占位文本
+pub const QUARANTINE_PLACEHOLDER: &str =
+    "[This message was removed by the proxy because it repeatedly caused upstream request errors.]";
+
+/// 单个文本块超过该字节数视为可疑
+const OVERSIZED_PART_BYTES: usize = 256 * 1024;
+
+/// 二分阶段最多发起的探测请求数 (不含每轮的整体探测)
+pub const MAX_BISECT_PROBES: usize = 8;
+
+/// tool_result 内容中允许的块类型
+const KNOWN_RESULT_BLOCK_TYPES: &[&str] = &["text", "image", "document"];
+
+/// 消息内容哈希 (同一进程内稳定，用于会话级缓存)
+pub fn message_hash(msg: &Message) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    msg.role.hash(&mut hasher);
+    serde_json::to_string(&msg.content)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+fn is_suspicious_text(text: &str) -> Option<&'static str> {
+    if text.contains('\u{FFFD}') {
+        return Some("replacement_char");
+    }
+    if text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+    {
+        return Some("control_char");
+    }
+    if text.len() > OVERSIZED_PART_BYTES {
+        return Some("oversized_part");
+    }
+    None
+}
+
+fn suspicious_value(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::String(s) => is_suspicious_text(s),
+        Value::Array(items) => items.iter().find_map(|item| {
+            let block_type = item.get("type").and_then(|t| t.as_str());
+            match block_type {
+                Some(t) if !KNOWN_RESULT_BLOCK_TYPES.contains(&t) => Some("unknown_block_type"),
+                _ => suspicious_value(item),
+            }
+        }),
+        Value::Object(map) => map.values().find_map(suspicious_value),
+        _ => None,
+    }
+}
+
+/// 消息可疑的原因 (非 UTF-8 转义残留、控制字符、超大文本块、未知块类型)
+pub fn suspect_reason(msg: &Message) -> Option<&'static str> {
+    match &msg.content {
+        MessageContent::String(text) => is_suspicious_text(text),
+        MessageContent::Array(blocks) => blocks.iter().find_map(|block| match block {
+            ContentBlock::Text { text } => is_suspicious_text(text),
+            ContentBlock::Thinking { thinking, .. } => is_suspicious_text(thinking),
+            ContentBlock::ToolUse { input, .. } | ContentBlock::ServerToolUse { input, .. } => {
+                suspicious_value(input)
+            }
+            ContentBlock::ToolResult { content, .. } => suspicious_value(content),
+            _ => None,
+        }),
+    }
+}
+
+/// 用占位替换消息内容，保留 tool_use / tool_result 的 id 配对以免破坏工具调用链
+pub fn quarantine_message(msg: &Message) -> Message {
+    let content = match &msg.content {
+        MessageContent::String(_) => MessageContent::String(QUARANTINE_PLACEHOLDER.to_string()),
+        MessageContent::Array(blocks) => {
+            let mut out = Vec::new();
+            let mut placeholder_added = false;
+            for block in blocks {
+                match block {
+                    ContentBlock::ToolUse { id, name, .. } => out.push(ContentBlock::ToolUse {
+                        id: id.clone(),
+                        name: name.clone(),
+                        input: json!({}),
+                        signature: None,
+                        cache_control: None,
+                    }),
+                    ContentBlock::ToolResult { tool_use_id, .. } => {
+                        out.push(ContentBlock::ToolResult {
+                            tool_use_id: tool_use_id.clone(),
+                            content: json!(QUARANTINE_PLACEHOLDER),
+                            is_error: Some(true),
+                        })
+                    }
+                    _ if !placeholder_added => {
+                        placeholder_added = true;
+                        out.push(ContentBlock::Text {
+                            text: QUARANTINE_PLACEHOLDER.to_string(),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            MessageContent::Array(out)
+        }
+    };
+    Message {
+        role: msg.role.clone(),
+        content,
+    }
+}
+
+/// 替换指定下标的消息
+pub fn quarantine_indices(messages: &[Message], indices: &[usize]) -> Vec<Message> {
+    messages
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            if indices.contains(&i) {
+                quarantine_message(m)
+            } else {
+                m.clone()
+            }
+        })
+        .collect()
+}
+
+/// 单次探测的结论
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeVerdict {
+    /// 上游以 2xx 接受请求
+    Accepted,
+    /// 上游以 400 拒绝请求
+    Rejected,
+    /// 其他状态或网络错误 (限流、5xx 等)，无法判断消息是否有问题
+    Unknown,
+}
+
+/// 二分探测结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BisectOutcome {
+    /// 问题消息下标 (替换后请求可成功)
+    pub culprits: Vec<usize>,
+    /// 发起的探测请求数
+    pub probes: usize,
+}
+
+/// 找出替换后能让请求成功的最小消息集合
+///
+/// 候选集合依次为: 可疑消息 → 全部历史消息 (不含最后一条)。
+/// 每个候选集合先整体替换探测一次，成功后再按二分缩小 (ddmin 补集约简)，
+/// 探测预算用尽时返回当前已验证可行的集合。
+/// 探测结论为 Unknown 时停止: 整体探测阶段直接放弃，缩小阶段返回当前已验证可行的集合。
+pub async fn bisect<F, Fut>(messages: &[Message], mut probe: F) -> Option<BisectOutcome>
+where
+    F: FnMut(Vec<Message>) -> Fut,
+    Fut: Future<Output = ProbeVerdict>,
+{
+    let historical = messages.len().saturating_sub(1);
+    let suspects: Vec<usize> = (0..historical)
+        .filter(|&i| suspect_reason(&messages[i]).is_some())
+        .collect();
+    let everything: Vec<usize> = (0..historical).collect();
+
+    let mut stages = Vec::new();
+    if !suspects.is_empty() {
+        stages.push(suspects);
+    }
+    if stages.first().map(|s| s.len()) != Some(everything.len()) && !everything.is_empty() {
+        stages.push(everything);
+    }
+
+    let mut probes = 0;
+    for candidates in stages {
+        probes += 1;
+        match probe(quarantine_indices(messages, &candidates)).await {
+            ProbeVerdict::Accepted => {}
+            ProbeVerdict::Rejected => continue,
+            ProbeVerdict::Unknown => return None,
+        }
+
+        let mut needed = candidates;
+        let mut chunk = (needed.len() / 2).max(1);
+        let mut budget = MAX_BISECT_PROBES;
+        'shrink: loop {
+            let mut start = 0;
+            while start < needed.len() {
+                if needed.len() == 1 || budget == 0 {
+                    break 'shrink;
+                }
+                let end = (start + chunk).min(needed.len());
+                let trial: Vec<usize> = needed[..start]
+                    .iter()
+                    .chain(&needed[end..])
+                    .copied()
+                    .collect();
+                budget -= 1;
+                probes += 1;
+                match probe(quarantine_indices(messages, &trial)).await {
+                    ProbeVerdict::Accepted => needed = trial,
+                    ProbeVerdict::Rejected => start = end,
+                    ProbeVerdict::Unknown => break 'shrink,
+                }
+            }
+            if chunk == 1 {
+                break;
+            }
+            chunk = (chunk / 2).max(1);
+        }
+
+        return Some(BisectOutcome {
+            culprits: needed,
+            probes,
+        });
+    }
+    None
+}
+
+/// 会话级问题消息缓存
+#[derive(Default)]
+pub struct PoisonCache {
+    by_session: DashMap<String, HashSet<u64>>,
+}
+
+impl PoisonCache {
+    pub fn global() -> &'static PoisonCache {
+        static INSTANCE: OnceLock<PoisonCache> = OnceLock::new();
+        INSTANCE.get_or_init(PoisonCache::default)
+    }
+
+    /// 记录会话中的问题消息哈希
+    pub fn remember(&self, session_id: &str, hashes: impl IntoIterator<Item = u64>) {
+        self.by_session
+            .entry(session_id.to_string())
+            .or_default()
+            .extend(hashes);
+    }
+
+    /// 对已知问题消息预先替换，返回替换数量
+    pub fn apply(&self, session_id: &str, messages: &mut [Message]) -> usize {
+        let Some(known) = self.by_session.get(session_id) else {
+            return 0;
+        };
+        let mut replaced = 0;
+        for msg in messages.iter_mut() {
+            if known.contains(&message_hash(msg)) {
+                *msg = quarantine_message(msg);
+                replaced += 1;
+            }
+        }
+        replaced
+    }
+}
+
+impl crate::proxy::session_registry::SessionStateStore for PoisonCache {
+    fn name(&self) -> &'static str {
+        "poison_messages"
+    }
+
+    fn evict_session(&self, session_id: &str) -> usize {
+        self.by_session
+            .remove(session_id)
+            .map(|(_, hashes)| hashes.len())
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn user(text: &str) -> Message {
+        Message {
+            role: "user".to_string(),
+            content: MessageContent::String(text.to_string()),
+        }
+    }
+
+    fn assistant_tool_use(id: &str) -> Message {
+        Message {
+            role: "assistant".to_string(),
+            content: MessageContent::Array(vec![ContentBlock::ToolUse {
+                id: id.to_string(),
+                name: "read_file".to_string(),
+                input: json!({ "path": "C:\\src\\main.rs" }),
+                signature: None,
+                cache_control: None,
+            }]),
+        }
+    }
+
+    fn tool_result(id: &str, content: &str) -> Message {
+        Message {
+            role: "user".to_string(),
+            content: MessageContent::Array(vec![ContentBlock::ToolResult {
+                tool_use_id: id.to_string(),
+                content: json!(content),
+                is_error: None,
+            }]),
+        }
+    }
+
+    fn verdict(accepted: bool) -> ProbeVerdict {
+        if accepted {
+            ProbeVerdict::Accepted
+        } else {
+            ProbeVerdict::Rejected
+        }
+    }
+
+    /// 模拟上游: 请求中只要还有含 \u{FFFD} 的 tool_result 就返回 400
+    fn upstream_accepts(messages: &[Message]) -> bool {
+        !messages.iter().any(|m| match &m.content {
+            MessageContent::Array(blocks) => blocks.iter().any(|b| {
+                matches!(b, ContentBlock::ToolResult { content, .. }
+                    if content.as_str().map(|s| s.contains('\u{FFFD}')).unwrap_or(false))
+            }),
+            _ => false,
+        })
+    }
+
+    fn poisoned_history() -> Vec<Message> {
+        let mut history = Vec::new();
+        for i in 0..6 {
+            history.push(user(&format!("step {}", i)));
+            history.push(assistant_tool_use(&format!("toolu_{}", i)));
+            let content = if i == 3 {
+                "C:\\Users\\dev\\\u{FFFD}\u{FFFD}\\file.txt".to_string()
+            } else {
+                format!("result {}", i)
+            };
+            history.push(tool_result(&format!("toolu_{}", i), &content));
+        }
+        history.push(user("continue"));
+        history
+    }
+
+    #[tokio::test]
+    async fn test_bisect_isolates_poison_message_and_session_recovers() {
+        let history = poisoned_history();
+        let poison_index = 11; // 第 4 轮 (i == 3) 的 tool_result
+        assert_eq!(suspect_reason(&history[poison_index]), Some("replacement_char"));
+        assert!(!upstream_accepts(&history));
+
+        let probes = Cell::new(0);
+        let outcome = bisect(&history, |msgs| {
+            probes.set(probes.get() + 1);
+            let verdict = verdict(upstream_accepts(&msgs));
+            async move { verdict }
+        })
+        .await
+        .expect("culprit found");
+        assert_eq!(outcome.culprits, vec![poison_index]);
+        assert_eq!(outcome.probes, probes.get());
+
+        // 替换后请求成功，且工具调用链保持完整
+        let repaired = quarantine_indices(&history, &outcome.culprits);
+        assert!(upstream_accepts(&repaired));
+        match &repaired[poison_index].content {
+            MessageContent::Array(blocks) => assert!(matches!(
+                &blocks[0],
+                ContentBlock::ToolResult { tool_use_id, content, .. }
+                    if tool_use_id == "toolu_3" && content == QUARANTINE_PLACEHOLDER
+            )),
+            _ => panic!("Expected tool_result block"),
+        }
+
+        // 后续轮次: 缓存预先替换，首次请求即成功
+        let cache = PoisonCache::default();
+        cache.remember("sid-1", outcome.culprits.iter().map(|&i| message_hash(&history[i])));
+        let mut next_turn = history.clone();
+        next_turn.push(user("and then?"));
+        assert_eq!(cache.apply("sid-1", &mut next_turn), 1);
+        assert!(upstream_accepts(&next_turn));
+        // 其他会话不受影响
+        let mut other = history.clone();
+        assert_eq!(cache.apply("sid-2", &mut other), 0);
+    }
+
+    #[tokio::test]
+    async fn test_bisect_falls_back_to_all_history_when_no_suspects() {
+        let mut history: Vec<Message> = (0..8).map(|i| user(&format!("m{}", i))).collect();
+        history[5] = user("looks innocent");
+        let outcome = bisect(&history, |msgs| {
+            let ok = !msgs.iter().any(|m| matches!(&m.content, MessageContent::String(s) if s == "looks innocent"));
+            let verdict = verdict(ok);
+            async move { verdict }
+        })
+        .await
+        .unwrap();
+        assert_eq!(outcome.culprits, vec![5]);
+    }
+
+    #[tokio::test]
+    async fn test_bisect_gives_up_when_history_is_not_the_cause() {
+        let history = poisoned_history();
+        let outcome = bisect(&history, |_| async { ProbeVerdict::Rejected }).await;
+        assert_eq!(outcome, None);
+    }
+
+    #[tokio::test]
+    async fn test_bisect_stops_on_inconclusive_probe() {
+        let history = poisoned_history();
+        // 整体探测即遇到限流: 不把任何消息当作问题消息
+        let probes = Cell::new(0);
+        let outcome = bisect(&history, |_| {
+            probes.set(probes.get() + 1);
+            async { ProbeVerdict::Unknown }
+        })
+        .await;
+        assert_eq!(outcome, None);
+        assert_eq!(probes.get(), 1);
+
+        // 缩小阶段遇到 5xx: 返回已验证可行的集合，不再继续探测
+        let history: Vec<Message> = (0..8).map(|i| user(&format!("m{}", i))).collect();
+        let calls = Cell::new(0);
+        let outcome = bisect(&history, |_| {
+            calls.set(calls.get() + 1);
+            let verdict = if calls.get() == 1 {
+                ProbeVerdict::Accepted
+            } else {
+                ProbeVerdict::Unknown
+            };
+            async move { verdict }
+        })
+        .await
+        .expect("whole-set probe succeeded");
+        assert_eq!(outcome.probes, 2);
+        assert_eq!(outcome.culprits, (0..7).collect::<Vec<_>>());
+    }
+}
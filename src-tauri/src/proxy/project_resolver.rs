@@ -44,3 +44,68 @@ pub async fn fetch_project_id(access_token: &str) -> Result<String, String> {
     // 如果没有返回 project_id，说明账号无资格，返回错误以触发 token_manager 的稳定兜底逻辑
     Err("账号无资格获取官方 cloudaicompanionProject".to_string())
 }
+
+/// [NEW] 规范化 project_id: 兼容 Vertex 资源路径形式
+/// "projects/my-proj/locations/us-central1/..." (可带 URL 前缀) -> "my-proj"；裸 id 原样返回
+pub fn normalize_project_id(project_id: &str) -> String {
+    let trimmed = project_id.trim().trim_matches('/');
+    let mut segments = trimmed.split('/');
+    while let Some(segment) = segments.next() {
+        if segment == "projects" {
+            if let Some(bare) = segments.next().filter(|s| !s.is_empty()) {
+                if bare != project_id {
+                    tracing::debug!("[Project] Normalized Vertex path '{}' to '{}'", project_id, bare);
+                }
+                return bare.to_string();
+            }
+        }
+    }
+    trimmed.to_string()
+}
+
+/// 校验 GCP project id 格式
+/// 规则: 6-30 位，小写字母开头，仅含小写字母/数字/连字符，不以连字符结尾
+pub fn validate_project_id(project_id: &str) -> Result<(), String> {
+    let len = project_id.len();
+    if !(6..=30).contains(&len) {
+        return Err(format!("Invalid project id '{}': length must be 6-30", project_id));
+    }
+    if !project_id.starts_with(|c: char| c.is_ascii_lowercase()) {
+        return Err(format!("Invalid project id '{}': must start with a lowercase letter", project_id));
+    }
+    if project_id.ends_with('-') {
+        return Err(format!("Invalid project id '{}': must not end with a hyphen", project_id));
+    }
+    if !project_id
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(format!(
+            "Invalid project id '{}': only lowercase letters, digits and hyphens are allowed",
+            project_id
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_project_id() {
+        assert_eq!(normalize_project_id("bamboo-precept-lgxtn"), "bamboo-precept-lgxtn");
+        assert_eq!(normalize_project_id(" my-proj-123 "), "my-proj-123");
+        assert_eq!(normalize_project_id("projects/my-proj-123"), "my-proj-123");
+        assert_eq!(
+            normalize_project_id("projects/my-proj-123/locations/us-central1"),
+            "my-proj-123"
+        );
+        assert_eq!(
+            normalize_project_id(
+                "https://us-central1-aiplatform.googleapis.com/v1/projects/my-proj-123/locations/us-central1/publishers/google/models/gemini-2.5-pro"
+            ),
+            "my-proj-123"
+        );
+    }
+}
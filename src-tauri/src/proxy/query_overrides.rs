@@ -0,0 +1,296 @@
+// 请求级 URL 查询参数覆盖 (调试用)
+// 例如 `POST /v1/messages?thinking=off&temperature=0.2&safety=none`，无需修改请求体即可快速试验。
+// 仅在调试配置 allow_query_overrides 开启时生效；thinking / temperature / top_p / model 直接改写请求体，
+// 安全阈值与监听配置档一样通过 task-local 传递给协议转换器。
+// 请求头 x-abv-response-language 受同一开关约束，可覆盖或关闭 (off) 全局强制回复语言。
+
+use serde_json::{json, Value};
+use std::future::Future;
+use std::sync::Arc;
+
+/// 响应头: 本次请求实际应用的覆盖项
+pub const OVERRIDES_HEADER: &str = "x-abv-overrides";
+
+/// 请求头: 覆盖强制回复语言 (BCP-47 标签) 或关闭 (off)
+pub const RESPONSE_LANGUAGE_HEADER: &str = "x-abv-response-language";
+
+/// thinking 预算上限
+const MAX_THINKING_BUDGET: u32 = 1_000_000;
+/// 模型名长度上限
+const MAX_MODEL_LEN: usize = 128;
+
+tokio::task_local! {
+    static CURRENT_OVERRIDES: Arc<QueryOverrides>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThinkingOverride {
+    Off,
+    On,
+    Budget(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResponseLanguageOverride {
+    Off,
+    Language(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryOverrides {
+    pub thinking: Option<ThinkingOverride>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    /// 安全阈值 (OFF / LOW / MEDIUM / HIGH / NONE)
+    pub safety: Option<String>,
+    pub model: Option<String>,
+    /// 来自 x-abv-response-language 请求头
+    pub response_language: Option<ResponseLanguageOverride>,
+}
+
+impl QueryOverrides {
+    /// 解析查询字符串中的白名单参数 (其他参数如 `beta=true` 忽略)，取值非法时返回错误
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let mut overrides = Self::default();
+        for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+            let value = value.trim();
+            match key.as_ref() {
+                "thinking" => overrides.thinking = Some(parse_thinking(value)?),
+                "temperature" => overrides.temperature = Some(parse_range("temperature", value, 0.0, 2.0)?),
+                "top_p" => overrides.top_p = Some(parse_range("top_p", value, 0.0, 1.0)?),
+                "safety" => {
+                    crate::proxy::mappers::claude::request::SafetyThreshold::parse(value).ok_or_else(|| {
+                        format!("Invalid safety '{}': expected off, low, medium, high or none", value)
+                    })?;
+                    overrides.safety = Some(value.to_uppercase());
+                }
+                "model" => {
+                    let valid = !value.is_empty()
+                        && value.len() <= MAX_MODEL_LEN
+                        && value
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':' | '/'));
+                    if !valid {
+                        return Err(format!("Invalid model '{}'", value));
+                    }
+                    overrides.model = Some(value.to_string());
+                }
+                _ => {}
+            }
+        }
+        Ok(overrides)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// 改写 Claude / OpenAI 请求体 (两种协议的 thinking / temperature / top_p / model 字段同名)
+    pub fn apply_to_body(&self, body: &mut Value) {
+        let Some(obj) = body.as_object_mut() else { return };
+        if let Some(thinking) = self.thinking {
+            let config = match thinking {
+                ThinkingOverride::Off => json!({ "type": "disabled" }),
+                ThinkingOverride::On => json!({ "type": "enabled" }),
+                ThinkingOverride::Budget(budget) => json!({ "type": "enabled", "budget_tokens": budget }),
+            };
+            obj.insert("thinking".to_string(), config);
+        }
+        if let Some(temperature) = self.temperature {
+            obj.insert("temperature".to_string(), json!(temperature));
+        }
+        if let Some(top_p) = self.top_p {
+            obj.insert("top_p".to_string(), json!(top_p));
+        }
+        if let Some(model) = &self.model {
+            obj.insert("model".to_string(), json!(model));
+        }
+    }
+
+    /// 已应用覆盖项的摘要，例如 `thinking=off,temperature=0.2,safety=NONE`
+    pub fn summary(&self) -> String {
+        let mut items = Vec::new();
+        if let Some(thinking) = self.thinking {
+            items.push(match thinking {
+                ThinkingOverride::Off => "thinking=off".to_string(),
+                ThinkingOverride::On => "thinking=on".to_string(),
+                ThinkingOverride::Budget(budget) => format!("thinking={}", budget),
+            });
+        }
+        if let Some(temperature) = self.temperature {
+            items.push(format!("temperature={}", temperature));
+        }
+        if let Some(top_p) = self.top_p {
+            items.push(format!("top_p={}", top_p));
+        }
+        if let Some(safety) = &self.safety {
+            items.push(format!("safety={}", safety));
+        }
+        if let Some(model) = &self.model {
+            items.push(format!("model={}", model));
+        }
+        match &self.response_language {
+            Some(ResponseLanguageOverride::Off) => items.push("response_language=off".to_string()),
+            Some(ResponseLanguageOverride::Language(tag)) => items.push(format!("response_language={}", tag)),
+            None => {}
+        }
+        items.join(",")
+    }
+}
+
+fn parse_thinking(value: &str) -> Result<ThinkingOverride, String> {
+    match value.to_lowercase().as_str() {
+        "off" | "false" | "disabled" => Ok(ThinkingOverride::Off),
+        "on" | "true" | "enabled" => Ok(ThinkingOverride::On),
+        other => match other.parse::<u32>() {
+            Ok(budget) if (1..=MAX_THINKING_BUDGET).contains(&budget) => Ok(ThinkingOverride::Budget(budget)),
+            _ => Err(format!(
+                "Invalid thinking '{}': expected on, off or a budget between 1 and {}",
+                value, MAX_THINKING_BUDGET
+            )),
+        },
+    }
+}
+
+fn parse_range(name: &str, value: &str, min: f64, max: f64) -> Result<f64, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|v| (min..=max).contains(v))
+        .ok_or_else(|| format!("Invalid {} '{}': expected a number between {} and {}", name, value, min, max))
+}
+
+fn parse_response_language(value: &str) -> Result<ResponseLanguageOverride, String> {
+    let value = value.trim();
+    if matches!(value.to_lowercase().as_str(), "off" | "none" | "false") {
+        return Ok(ResponseLanguageOverride::Off);
+    }
+    if !crate::proxy::mappers::common::system_builder::is_valid_language_tag(value) {
+        return Err(format!(
+            "Invalid {} '{}': expected a BCP-47 language tag or off",
+            RESPONSE_LANGUAGE_HEADER, value
+        ));
+    }
+    Ok(ResponseLanguageOverride::Language(value.to_string()))
+}
+
+/// 根据调试开关解析查询参数与回复语言请求头：开关关闭或没有任何覆盖项时返回 Ok(None)
+pub fn resolve(
+    query: Option<&str>,
+    response_language: Option<&str>,
+    enabled: bool,
+) -> Result<Option<QueryOverrides>, String> {
+    if !enabled {
+        return Ok(None);
+    }
+    let mut overrides = match query {
+        Some(query) => QueryOverrides::parse(query)?,
+        None => QueryOverrides::default(),
+    };
+    if let Some(value) = response_language {
+        overrides.response_language = Some(parse_response_language(value)?);
+    }
+    Ok(Some(overrides).filter(|o| !o.is_empty()))
+}
+
+/// 当前请求的查询参数覆盖
+pub fn current() -> Option<Arc<QueryOverrides>> {
+    CURRENT_OVERRIDES.try_with(|o| o.clone()).ok()
+}
+
+/// 在覆盖上下文中执行 future
+pub async fn scope<F: Future>(overrides: Arc<QueryOverrides>, fut: F) -> F::Output {
+    CURRENT_OVERRIDES.scope(overrides, fut).await
+}
+
+/// 请求头指定的回复语言覆盖
+pub fn response_language_override() -> Option<ResponseLanguageOverride> {
+    current().and_then(|o| o.response_language.clone())
+}
+
+/// 查询参数指定的安全阈值 (优先级高于监听配置档)
+pub fn safety_threshold_override() -> Option<String> {
+    current().and_then(|o| o.safety.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::mappers::claude::request::SafetyThreshold;
+
+    #[test]
+    fn test_each_param_applied_to_body() {
+        let overrides =
+            QueryOverrides::parse("thinking=off&temperature=0.2&top_p=0.9&safety=none&model=gemini-3-flash&beta=true")
+                .unwrap();
+        let mut body = json!({ "model": "claude-sonnet-4-5", "temperature": 1.0, "messages": [] });
+        overrides.apply_to_body(&mut body);
+
+        assert_eq!(body["thinking"], json!({ "type": "disabled" }));
+        assert_eq!(body["temperature"], 0.2);
+        assert_eq!(body["top_p"], 0.9);
+        assert_eq!(body["model"], "gemini-3-flash");
+        assert_eq!(overrides.safety.as_deref(), Some("NONE"));
+        assert_eq!(
+            overrides.summary(),
+            "thinking=off,temperature=0.2,top_p=0.9,safety=NONE,model=gemini-3-flash"
+        );
+
+        let budget = QueryOverrides::parse("thinking=4096").unwrap();
+        let mut body = json!({});
+        budget.apply_to_body(&mut body);
+        assert_eq!(body["thinking"], json!({ "type": "enabled", "budget_tokens": 4096 }));
+        assert_eq!(QueryOverrides::parse("thinking=on").unwrap().thinking, Some(ThinkingOverride::On));
+    }
+
+    #[test]
+    fn test_invalid_values_rejected() {
+        for query in [
+            "temperature=3",
+            "temperature=abc",
+            "top_p=-0.1",
+            "thinking=maybe",
+            "thinking=0",
+            "safety=extreme",
+            "model=",
+            "model=bad%20model",
+        ] {
+            assert!(QueryOverrides::parse(query).is_err(), "{} should be rejected", query);
+        }
+    }
+
+    #[test]
+    fn test_flag_off_ignores_params() {
+        assert_eq!(resolve(Some("temperature=0.2"), None, false), Ok(None));
+        // 开关关闭时非法值同样忽略
+        assert_eq!(resolve(Some("temperature=99"), None, false), Ok(None));
+        assert_eq!(resolve(Some("beta=true"), None, true), Ok(None));
+        assert!(resolve(Some("temperature=99"), None, true).is_err());
+        assert!(resolve(Some("temperature=0.2"), None, true).unwrap().is_some());
+        assert_eq!(resolve(None, Some("ja"), false), Ok(None));
+    }
+
+    #[test]
+    fn test_response_language_header() {
+        let overrides = resolve(None, Some("ja-JP"), true).unwrap().unwrap();
+        assert_eq!(
+            overrides.response_language,
+            Some(ResponseLanguageOverride::Language("ja-JP".to_string()))
+        );
+        assert_eq!(overrides.summary(), "response_language=ja-JP");
+
+        let overrides = resolve(Some("temperature=0.2"), Some("off"), true).unwrap().unwrap();
+        assert_eq!(overrides.response_language, Some(ResponseLanguageOverride::Off));
+        assert_eq!(overrides.summary(), "temperature=0.2,response_language=off");
+
+        assert!(resolve(None, Some("Japanese please"), true).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_safety_override_scoped_to_request() {
+        let overrides = Arc::new(QueryOverrides::parse("safety=high").unwrap());
+        let inside = scope(overrides, async { SafetyThreshold::effective() }).await;
+        assert_eq!(inside.to_gemini_threshold(), "BLOCK_ONLY_HIGH");
+        assert!(safety_threshold_override().is_none());
+    }
+}
@@ -38,6 +38,16 @@ pub struct RateLimitInfo {
     pub model: Option<String>,
 }
 
+/// 上游 429 / RESOURCE_EXHAUSTED 错误体中的结构化配额细节
+/// 来源: google.rpc.RetryInfo / google.rpc.QuotaFailure / google.rpc.ErrorInfo
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QuotaErrorDetails {
+    /// 上游给出的重试延时(秒) (ErrorInfo.metadata.quotaResetDelay 或 RetryInfo.retryDelay)
+    pub retry_delay_secs: Option<u64>,
+    /// 限流作用的模型 (QuotaFailure.violations[].quotaDimensions.model 或 ErrorInfo.metadata.model)
+    pub model: Option<String>,
+}
+
 /// 失败计数过期时间：1小时（超过此时间未失败则重置计数）
 const FAILURE_COUNT_EXPIRY_SECONDS: u64 = 3600;
 
@@ -193,6 +203,13 @@ impl RateLimitTracker {
             return None;
         }
         
+        // [NEW] 解析结构化错误细节 (仅 429 携带 RetryInfo / QuotaFailure)
+        let quota_details = if status == 429 {
+            self.parse_quota_details(body)
+        } else {
+            QuotaErrorDetails::default()
+        };
+
         // 1. 解析限流原因类型
         let reason = if status == 429 {
             tracing::warn!("Google 429 Error Body: {}", body);
@@ -304,7 +321,9 @@ impl RateLimitTracker {
         
         // [FIX] 使用复合 Key 存储 (如果是 Quota 且有 Model)
         // 只有 QuotaExhausted 适合做模型隔离，其他如 RateLimitExceeded 通常是全账号的 TPM
-        let use_model_key = matches!(reason, RateLimitReason::QuotaExhausted) && model.is_some();
+        // [NEW] 上游明确指出了受限模型时 (QuotaFailure / ErrorInfo)，同样按模型隔离
+        let use_model_key = model.is_some()
+            && (matches!(reason, RateLimitReason::QuotaExhausted) || quota_details.model.is_some());
         let key = if use_model_key { 
             self.get_limit_key(account_id, model.as_deref())
         } else {
@@ -423,24 +442,11 @@ impl RateLimitTracker {
         let trimmed = body.trim();
         if trimmed.starts_with('{') || trimmed.starts_with('[') {
             if let Ok(json) = serde_json::from_str::<serde_json::Value>(trimmed) {
-                // 1. Google 常见的 quotaResetDelay 格式 (支持所有格式："2h1m1s", "1h30m", "42s", "500ms" 等)
-                // 路径: error.details[0].metadata.quotaResetDelay
-                if let Some(delay_str) = json.get("error")
-                    .and_then(|e| e.get("details"))
-                    .and_then(|d| d.as_array())
-                    .and_then(|a| a.get(0))
-                    .and_then(|o| o.get("metadata"))  // 添加 metadata 层级
-                    .and_then(|m| m.get("quotaResetDelay"))
-                    .and_then(|v| v.as_str()) {
-                    
-                    tracing::debug!("[JSON解析] 找到 quotaResetDelay: '{}'", delay_str);
-                    
-                    // 使用通用时间解析函数
-                    if let Some(seconds) = self.parse_duration_string(delay_str) {
-                        return Some(seconds);
-                    }
+                // 1. Google 结构化细节: quotaResetDelay / RetryInfo.retryDelay (支持 "2h1m1s", "42s", "500ms" 等)
+                if let Some(seconds) = self.extract_quota_details(&json).retry_delay_secs {
+                    return Some(seconds);
                 }
-                
+
                 // 2. OpenAI 常见的 retry_after 字段 (数字)
                 if let Some(retry) = json.get("error")
                     .and_then(|e| e.get("retry_after"))
@@ -499,6 +505,78 @@ impl RateLimitTracker {
         None
     }
     
+    /// 解析 429 错误体中的结构化配额细节 (无法解析时返回空细节)
+    pub fn parse_quota_details(&self, body: &str) -> QuotaErrorDetails {
+        let trimmed = body.trim();
+        if !trimmed.starts_with('{') {
+            return QuotaErrorDetails::default();
+        }
+        match serde_json::from_str::<serde_json::Value>(trimmed) {
+            Ok(json) => self.extract_quota_details(&json),
+            Err(_) => QuotaErrorDetails::default(),
+        }
+    }
+
+    /// 遍历 error.details，提取重试延时与受限模型
+    /// 延时优先级: ErrorInfo.metadata.quotaResetDelay > RetryInfo.retryDelay
+    fn extract_quota_details(&self, json: &serde_json::Value) -> QuotaErrorDetails {
+        let mut details = QuotaErrorDetails::default();
+        let Some(items) = json
+            .get("error")
+            .and_then(|e| e.get("details"))
+            .and_then(|d| d.as_array())
+        else {
+            return details;
+        };
+
+        let mut reset_delay = None;
+        let mut retry_delay = None;
+        for item in items {
+            let type_str = item.get("@type").and_then(|t| t.as_str()).unwrap_or("");
+
+            if let Some(metadata) = item.get("metadata") {
+                if reset_delay.is_none() {
+                    reset_delay = metadata
+                        .get("quotaResetDelay")
+                        .and_then(|v| v.as_str())
+                        .and_then(|v| self.parse_duration_string(v));
+                }
+                if details.model.is_none() {
+                    details.model = metadata
+                        .get("model")
+                        .and_then(|v| v.as_str())
+                        .filter(|m| !m.is_empty())
+                        .map(|m| m.to_string());
+                }
+            }
+
+            if type_str.contains("RetryInfo") && retry_delay.is_none() {
+                retry_delay = item
+                    .get("retryDelay")
+                    .and_then(|v| v.as_str())
+                    .and_then(|v| self.parse_duration_string(v));
+            }
+
+            if type_str.contains("QuotaFailure") && details.model.is_none() {
+                details.model = item
+                    .get("violations")
+                    .and_then(|v| v.as_array())
+                    .and_then(|violations| {
+                        violations.iter().find_map(|v| {
+                            v.get("quotaDimensions")
+                                .and_then(|d| d.get("model"))
+                                .and_then(|m| m.as_str())
+                                .filter(|m| !m.is_empty())
+                                .map(|m| m.to_string())
+                        })
+                    });
+            }
+        }
+
+        details.retry_delay_secs = reset_delay.or(retry_delay);
+        details
+    }
+
     /// 获取账号的限流信息
     pub fn get(&self, account_id: &str) -> Option<RateLimitInfo> {
         self.limits.get(account_id).map(|r| r.clone())
@@ -679,4 +757,71 @@ mod tests {
         let info = tracker.parse_from_error("acc2", 429, None, quota_body, None, &backoff_steps);
         assert_eq!(info.unwrap().retry_after_sec, 7200);
     }
+
+    /// 抓取自真实环境的 429 响应 (含 RetryInfo + QuotaFailure)
+    const RETRY_INFO_BODY: &str = r#"{
+        "error": {
+            "code": 429,
+            "message": "Resource has been exhausted (e.g. check quota).",
+            "status": "RESOURCE_EXHAUSTED",
+            "details": [
+                {
+                    "@type": "type.googleapis.com/google.rpc.QuotaFailure",
+                    "violations": [
+                        {
+                            "subject": "project:123",
+                            "quotaMetric": "cloudcode-pa.googleapis.com/generate_content_requests",
+                            "quotaDimensions": { "model": "gemini-3-pro-high" }
+                        }
+                    ]
+                },
+                {
+                    "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                    "retryDelay": "37.52s"
+                }
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn test_parse_quota_details_retry_info_and_quota_failure() {
+        let tracker = RateLimitTracker::new();
+        let details = tracker.parse_quota_details(RETRY_INFO_BODY);
+        assert_eq!(details.retry_delay_secs, Some(38));
+        assert_eq!(details.model.as_deref(), Some("gemini-3-pro-high"));
+
+        // 无结构化细节 / 非 JSON 时返回空细节
+        let plain = tracker.parse_quota_details(r#"{"error":{"code":429,"status":"RESOURCE_EXHAUSTED"}}"#);
+        assert_eq!(plain, QuotaErrorDetails::default());
+        assert_eq!(tracker.parse_quota_details("Too Many Requests"), QuotaErrorDetails::default());
+    }
+
+    #[test]
+    fn test_retry_info_locks_only_affected_model() {
+        let tracker = RateLimitTracker::new();
+        let backoff_steps = vec![60, 300, 1800, 7200];
+
+        let info = tracker
+            .parse_from_error("acc1", 429, None, RETRY_INFO_BODY, Some("gemini-3-pro-high".to_string()), &backoff_steps)
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 38);
+
+        // 仅锁定 (账号, 模型) 组合，账号的其他模型不受影响
+        assert!(tracker.is_rate_limited("acc1", Some("gemini-3-pro-high")));
+        assert!(!tracker.is_rate_limited("acc1", Some("claude")));
+        assert!(!tracker.is_rate_limited("acc1", None));
+        let wait = tracker.get_remaining_wait("acc1", Some("gemini-3-pro-high"));
+        assert!(wait > 30 && wait <= 38);
+    }
+
+    #[test]
+    fn test_429_without_retry_info_uses_default_backoff() {
+        let tracker = RateLimitTracker::new();
+        let backoff_steps = vec![60, 300, 1800, 7200];
+        let body = r#"{"error":{"code":429,"message":"Resource has been exhausted (e.g. check quota).","status":"RESOURCE_EXHAUSTED"}}"#;
+
+        let info = tracker.parse_from_error("acc1", 429, None, body, None, &backoff_steps).unwrap();
+        assert_eq!(info.reason, RateLimitReason::QuotaExhausted);
+        assert_eq!(info.retry_after_sec, 60);
+    }
 }
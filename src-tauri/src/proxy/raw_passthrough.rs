@@ -0,0 +1,258 @@
+// 原始 Gemini 响应透传 (调试映射保真度)
+// 携带 x-abv-include-raw: true 且具备 admin 权限范围的请求:
+// - 非流式 Claude / OpenAI 响应附带扩展字段 _abv_raw_gemini (未经映射的 Gemini 聚合 JSON，base64 媒体截断)
+// - 流式响应将上游原始 SSE 写入调试日志目录 (即使该请求未开启调试日志)
+// 附带的原始 JSON 序列化后超过 debug_logging.raw_passthrough_max_bytes 时以截断标记替代。
+
+use axum::http::HeaderMap;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use parking_lot::Mutex;
+use serde_json::{json, Map, Value};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// 开启原始响应透传的请求头
+pub const INCLUDE_RAW_HEADER: &str = "x-abv-include-raw";
+/// 响应中的扩展字段名
+pub const RAW_FIELD: &str = "_abv_raw_gemini";
+/// inlineData.data 截断后保留的 base64 前缀长度
+const MEDIA_PREVIEW_CHARS: usize = 64;
+
+type UpstreamByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>;
+
+/// 是否透传原始响应：请求头为 true 且调用方具备 admin 权限范围
+pub fn raw_requested(headers: &HeaderMap, admin_scope: bool) -> bool {
+    let requested =
+        crate::proxy::handlers::common::header_flag_enabled(headers, INCLUDE_RAW_HEADER);
+    if requested && !admin_scope {
+        tracing::warn!(
+            "[Raw-Passthrough] Ignoring {} header: admin scope required",
+            INCLUDE_RAW_HEADER
+        );
+    }
+    requested && admin_scope
+}
+
+/// 上游原始 SSE 的聚合结果 (流结束后可读取)
+#[derive(Clone, Default)]
+pub struct RawAggregate {
+    chunks: Arc<Mutex<Vec<Value>>>,
+}
+
+impl RawAggregate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 包装上游字节流，按完整行解析 `data:` 事件并保留原始 JSON
+    pub fn tap(&self, stream: UpstreamByteStream) -> UpstreamByteStream {
+        let chunks = self.chunks.clone();
+        let mut pending: Vec<u8> = Vec::new();
+        Box::pin(stream.inspect(move |item| {
+            let Ok(bytes) = item else {
+                return;
+            };
+            pending.extend_from_slice(bytes);
+            while let Some(pos) = pending.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line);
+                if let Some(data) = line.trim().strip_prefix("data:") {
+                    if let Ok(value) = serde_json::from_str::<Value>(data.trim()) {
+                        chunks.lock().push(value);
+                    }
+                }
+            }
+        }))
+    }
+
+    /// 将收集到的分块合并为一个 Gemini 响应
+    pub fn collected(&self) -> Value {
+        aggregate_chunks(&self.chunks.lock())
+    }
+}
+
+/// 合并流式分块: 按顺序拼接 candidates[0].content.parts (不合并文本，保留上游原始分块)，
+/// 其余字段 (finishReason / usageMetadata / modelVersion 等) 以最后出现的为准
+pub fn aggregate_chunks(chunks: &[Value]) -> Value {
+    let mut top = Map::new();
+    let mut candidate = Map::new();
+    let mut parts: Vec<Value> = Vec::new();
+    let mut role: Option<Value> = None;
+
+    for chunk in chunks {
+        // 解包 v1internal 的 response 字段
+        let Some(obj) = chunk.get("response").unwrap_or(chunk).as_object() else {
+            continue;
+        };
+        for (key, value) in obj {
+            if key != "candidates" {
+                top.insert(key.clone(), value.clone());
+            }
+        }
+        let Some(first) = obj
+            .get("candidates")
+            .and_then(|c| c.get(0))
+            .and_then(|c| c.as_object())
+        else {
+            continue;
+        };
+        for (key, value) in first {
+            if key != "content" {
+                candidate.insert(key.clone(), value.clone());
+            }
+        }
+        if let Some(content) = first.get("content") {
+            if let Some(r) = content.get("role") {
+                role = Some(r.clone());
+            }
+            if let Some(p) = content.get("parts").and_then(|p| p.as_array()) {
+                parts.extend(p.iter().cloned());
+            }
+        }
+    }
+
+    candidate.insert(
+        "content".to_string(),
+        json!({ "role": role.unwrap_or_else(|| json!("model")), "parts": parts }),
+    );
+    top.insert("candidates".to_string(), json!([candidate]));
+    Value::Object(top)
+}
+
+/// 截断 inlineData 中的 base64 数据，保留前缀与原始长度
+fn truncate_media(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            if let Some(Value::String(data)) =
+                map.get_mut("inlineData").and_then(|d| d.get_mut("data"))
+            {
+                if data.len() > MEDIA_PREVIEW_CHARS {
+                    let preview: String = data.chars().take(MEDIA_PREVIEW_CHARS).collect();
+                    *data = format!("{}...[truncated {} base64 chars]", preview, data.len());
+                }
+            }
+            for child in map.values_mut() {
+                truncate_media(child);
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(truncate_media),
+        _ => {}
+    }
+}
+
+/// 生成扩展字段的值: 截断媒体数据，序列化后超过上限时以截断标记 + 前缀预览替代
+pub fn build_raw_field(mut raw: Value, max_bytes: usize) -> Value {
+    truncate_media(&mut raw);
+    let serialized = serde_json::to_string(&raw).unwrap_or_default();
+    if serialized.len() <= max_bytes {
+        return raw;
+    }
+    let mut end = max_bytes.min(serialized.len());
+    while !serialized.is_char_boundary(end) {
+        end -= 1;
+    }
+    tracing::debug!(
+        "[Raw-Passthrough] Raw response truncated from {} to {} bytes",
+        serialized.len(),
+        end
+    );
+    json!({
+        "_truncated": true,
+        "original_bytes": serialized.len(),
+        "max_bytes": max_bytes,
+        "preview": &serialized[..end],
+    })
+}
+
+/// 将原始响应写入已映射的响应体
+pub fn attach_raw(body: &mut Value, raw: Value, max_bytes: usize) {
+    if let Some(obj) = body.as_object_mut() {
+        obj.insert(RAW_FIELD.to_string(), build_raw_field(raw, max_bytes));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers_with_raw() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(INCLUDE_RAW_HEADER, "true".parse().unwrap());
+        headers
+    }
+
+    /// 模拟处理器: 仅在 raw_requested 时附带原始响应
+    fn finalize(headers: &HeaderMap, admin_scope: bool, raw: Value) -> Value {
+        let mut body = json!({ "id": "msg_1", "type": "message" });
+        if raw_requested(headers, admin_scope) {
+            attach_raw(&mut body, raw, 256 * 1024);
+        }
+        body
+    }
+
+    #[test]
+    fn test_raw_field_requires_admin_scope() {
+        let raw = json!({ "candidates": [{ "content": { "parts": [{ "text": "hi" }] } }] });
+
+        let body = finalize(&headers_with_raw(), true, raw.clone());
+        assert_eq!(body[RAW_FIELD], raw);
+
+        let body = finalize(&headers_with_raw(), false, raw.clone());
+        assert!(body.get(RAW_FIELD).is_none());
+
+        let body = finalize(&HeaderMap::new(), true, raw);
+        assert!(body.get(RAW_FIELD).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tap_collects_stream_chunks() {
+        let sse = concat!(
+            "data: {\"response\":{\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\"Hel\"}]}}]}}\n\n",
+            "data: {\"response\":{\"candidates\":[{\"content\":{\"parts\":[{\"te",
+            "xt\":\"lo\"}]},\"finishReason\":\"STOP\"}],\"usageMetadata\":{\"totalTokenCount\":5}}}\n\n",
+        );
+        let items: Vec<Result<Bytes, reqwest::Error>> = sse
+            .split_inclusive("\"te")
+            .map(|s| Ok(Bytes::from(s.to_string())))
+            .collect();
+
+        let aggregate = RawAggregate::new();
+        let tapped = aggregate.tap(Box::pin(futures::stream::iter(items)));
+        let forwarded: Vec<_> = tapped.collect().await;
+        assert_eq!(forwarded.len(), 3);
+
+        let raw = aggregate.collected();
+        assert_eq!(
+            raw["candidates"][0]["content"]["parts"],
+            json!([{ "text": "Hel" }, { "text": "lo" }])
+        );
+        assert_eq!(raw["candidates"][0]["finishReason"], "STOP");
+        assert_eq!(raw["usageMetadata"]["totalTokenCount"], 5);
+    }
+
+    #[test]
+    fn test_raw_field_truncation() {
+        let image = "A".repeat(10_000);
+        let raw = json!({
+            "candidates": [{ "content": { "parts": [
+                { "inlineData": { "mimeType": "image/png", "data": image } }
+            ] } }]
+        });
+        let field = build_raw_field(raw, 256 * 1024);
+        let data = field["candidates"][0]["content"]["parts"][0]["inlineData"]["data"]
+            .as_str()
+            .unwrap();
+        assert!(data.ends_with("...[truncated 10000 base64 chars]"));
+        assert!(data.len() < 200);
+
+        // 超过大小上限时以截断标记替代
+        let long_text = "x".repeat(5_000);
+        let raw = json!({ "candidates": [{ "content": { "parts": [{ "text": long_text }] } }] });
+        let field = build_raw_field(raw, 1024);
+        assert_eq!(field["_truncated"], true);
+        assert_eq!(field["max_bytes"], 1024);
+        assert!(field["original_bytes"].as_u64().unwrap() > 5_000);
+        assert_eq!(field["preview"].as_str().unwrap().len(), 1024);
+    }
+}
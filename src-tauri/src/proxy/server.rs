@@ -149,10 +149,15 @@ struct AccountResponse {
     quota: Option<QuotaResponse>,
     device_bound: bool,
     last_used: i64,
+    /// [NEW] 新账号预热剩余时间 (秒)，不在预热期时省略
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warmup_remaining_secs: Option<i64>,
 }
 
 #[derive(Serialize)]
 struct QuotaResponse {
+    /// [NEW] 存在无法解析剩余百分比的模型 (上游配额结构变化)
+    unparsed: bool,
     models: Vec<ModelQuota>,
     last_updated: i64,
     subscription_tier: Option<String>,
@@ -164,6 +169,15 @@ struct ModelQuota {
     name: String,
     percentage: i32,
     reset_time: String,
+    // [NEW] 绝对额度 (上游提供时)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remaining_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reset_window_secs: Option<u64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    unparsed: bool,
 }
 
 #[derive(Serialize)]
@@ -172,6 +186,15 @@ struct AccountListResponse {
     current_account_id: Option<String>,
 }
 
+/// 账号剩余预热时间 (按当前预热策略与账号添加时间计算)
+fn warmup_remaining_secs(created_at: i64) -> Option<i64> {
+    crate::proxy::account_warmup::remaining_secs(
+        &crate::proxy::config::get_account_warmup_config(),
+        created_at,
+        chrono::Utc::now().timestamp(),
+    )
+}
+
 fn to_account_response(
     account: &crate::models::account::Account,
     current_id: &Option<String>,
@@ -189,6 +212,7 @@ fn to_account_response(
         proxy_disabled_at: account.proxy_disabled_at,
         protected_models: account.protected_models.iter().cloned().collect(),
         quota: account.quota.as_ref().map(|q| QuotaResponse {
+            unparsed: q.is_unparsed(),
             models: q
                 .models
                 .iter()
@@ -196,6 +220,10 @@ fn to_account_response(
                     name: m.name.clone(),
                     percentage: m.percentage,
                     reset_time: m.reset_time.clone(),
+                    remaining_tokens: m.remaining_tokens,
+                    limit_tokens: m.limit_tokens,
+                    reset_window_secs: m.reset_window_secs,
+                    unparsed: m.unparsed,
                 })
                 .collect(),
             last_updated: q.last_updated,
@@ -207,6 +235,7 @@ fn to_account_response(
         validation_blocked: account.validation_blocked,
         validation_blocked_until: account.validation_blocked_until,
         validation_blocked_reason: account.validation_blocked_reason.clone(),
+        warmup_remaining_secs: warmup_remaining_secs(account.created_at),
     }
 }
 
@@ -227,6 +256,7 @@ pub struct AxumServer {
     pub token_manager: Arc<TokenManager>, // [NEW] 暴露出 TokenManager 供反代服务复用
     pub proxy_pool_state: Arc<tokio::sync::RwLock<crate::proxy::config::ProxyPoolConfig>>, // [NEW] 代理池配置状态
     pub proxy_pool_manager: Arc<crate::proxy::proxy_pool::ProxyPoolManager>, // [NEW] 暴露代理池管理器供命令调用
+    profile_listeners: Arc<crate::proxy::listener_profile::ProfileListenerManager>, // [NEW] 监听配置档 (额外端口)
 }
 
 impl AxumServer {
@@ -283,6 +313,23 @@ impl AxumServer {
         tracing::info!("User-Agent 配置已热更新: {:?}", config.user_agent_override);
     }
 
+    pub async fn update_upstream_base_url(&self, config: &crate::proxy::config::ProxyConfig) {
+        self.upstream
+            .set_base_url_override(config.upstream_base_url.clone())
+            .await;
+        tracing::info!("上游基础 URL 配置已热更新: {:?}", config.upstream_base_url);
+    }
+
+    pub async fn update_listener_profiles(&self, config: &crate::proxy::config::ProxyConfig) {
+        self.profile_listeners.sync(&config.listener_profiles).await;
+        tracing::info!("监听配置档已热更新: {} 个", config.listener_profiles.len());
+    }
+
+    /// 监听配置档实际绑定的端口 (未运行时返回 None)
+    pub async fn listener_profile_port(&self, name: &str) -> Option<u16> {
+        self.profile_listeners.bound_port(name).await
+    }
+
     pub async fn set_running(&self, running: bool) {
         let mut r = self.is_running.write().await;
         *r = running;
@@ -308,6 +355,55 @@ impl AxumServer {
         cloudflared_state: Arc<crate::commands::cloudflared::CloudflaredState>,
         proxy_pool_config: crate::proxy::config::ProxyPoolConfig, // [NEW]
     ) -> Result<(Self, tokio::task::JoinHandle<()>), String> {
+        // 绑定地址
+        let addr = format!("{}:{}", host, port);
+        let listener = tokio::net::TcpListener::bind(&addr)
+            .await
+            .map_err(|e| format!("地址 {} 绑定失败: {}", addr, e))?;
+
+        Self::start_with_listener(
+            host,
+            listener,
+            token_manager,
+            custom_mapping,
+            _request_timeout,
+            upstream_proxy,
+            user_agent_override,
+            security_config,
+            zai_config,
+            monitor,
+            experimental_config,
+            debug_logging,
+            integration,
+            cloudflared_state,
+            proxy_pool_config,
+        )
+        .await
+    }
+
+    /// [NEW] 在已绑定的监听器上启动 Axum 服务器 (调用方可先绑定随机端口再读取实际地址)
+    pub async fn start_with_listener(
+        host: String,
+        listener: tokio::net::TcpListener,
+        token_manager: Arc<TokenManager>,
+        custom_mapping: std::collections::HashMap<String, String>,
+        _request_timeout: u64,
+        upstream_proxy: crate::proxy::config::UpstreamProxyConfig,
+        user_agent_override: Option<String>,
+        security_config: crate::proxy::ProxySecurityConfig,
+        zai_config: crate::proxy::ZaiConfig,
+        monitor: Arc<crate::proxy::monitor::ProxyMonitor>,
+        experimental_config: crate::proxy::config::ExperimentalConfig,
+        debug_logging: crate::proxy::config::DebugLoggingConfig,
+
+        integration: crate::modules::integration::SystemManager,
+        cloudflared_state: Arc<crate::commands::cloudflared::CloudflaredState>,
+        proxy_pool_config: crate::proxy::config::ProxyPoolConfig, // [NEW]
+    ) -> Result<(Self, tokio::task::JoinHandle<()>), String> {
+        let addr = listener
+            .local_addr()
+            .map_err(|e| format!("读取监听地址失败: {}", e))?;
+        let port = addr.port();
         let custom_mapping_state = Arc::new(tokio::sync::RwLock::new(custom_mapping));
         let proxy_state = Arc::new(tokio::sync::RwLock::new(upstream_proxy.clone()));
         let proxy_pool_state = Arc::new(tokio::sync::RwLock::new(proxy_pool_config));
@@ -319,6 +415,7 @@ impl AxumServer {
         let zai_state = Arc::new(RwLock::new(zai_config));
         let provider_rr = Arc::new(AtomicUsize::new(0));
         let zai_vision_mcp_state = Arc::new(crate::proxy::zai_vision_mcp::ZaiVisionMcpState::new());
+        crate::proxy::session_registry::register_store(zai_vision_mcp_state.clone());
         let experimental_state = Arc::new(RwLock::new(experimental_config));
         let debug_logging_state = Arc::new(RwLock::new(debug_logging));
         let is_running_state = Arc::new(RwLock::new(true));
@@ -362,94 +459,10 @@ impl AxumServer {
         };
 
         // 构建路由 - 使用新架构的 handlers！
-        use crate::proxy::handlers;
-        use crate::proxy::middleware::{
-            admin_auth_middleware, auth_middleware, cors_layer, ip_filter_middleware,
-            monitor_middleware, service_status_middleware,
-        };
+        use crate::proxy::middleware::{admin_auth_middleware, cors_layer, service_status_middleware};
 
         // 1. 构建主 AI 代理路由 (遵循 auth_mode 配置)
-        let proxy_routes = Router::new()
-            .route("/health", get(health_check_handler))
-            .route("/healthz", get(health_check_handler))
-            // OpenAI Protocol
-            .route("/v1/models", get(handlers::openai::handle_list_models))
-            .route(
-                "/v1/chat/completions",
-                post(handlers::openai::handle_chat_completions),
-            )
-            .route(
-                "/v1/completions",
-                post(handlers::openai::handle_completions),
-            )
-            .route("/v1/responses", post(handlers::openai::handle_completions)) // 兼容 Codex CLI
-            .route(
-                "/v1/images/generations",
-                post(handlers::openai::handle_images_generations),
-            ) // 图像生成 API
-            .route(
-                "/v1/images/edits",
-                post(handlers::openai::handle_images_edits),
-            ) // 图像编辑 API
-            .route(
-                "/v1/audio/transcriptions",
-                post(handlers::audio::handle_audio_transcription),
-            ) // 音频转录 API
-            // Claude Protocol
-            .route("/v1/messages", post(handlers::claude::handle_messages))
-            .route(
-                "/v1/messages/count_tokens",
-                post(handlers::claude::handle_count_tokens),
-            )
-            .route(
-                "/v1/models/claude",
-                get(handlers::claude::handle_list_models),
-            )
-            // z.ai MCP (optional reverse-proxy)
-            .route(
-                "/mcp/web_search_prime/mcp",
-                any(handlers::mcp::handle_web_search_prime),
-            )
-            .route("/mcp/web_reader/mcp", any(handlers::mcp::handle_web_reader))
-            .route(
-                "/mcp/zai-mcp-server/mcp",
-                any(handlers::mcp::handle_zai_mcp_server),
-            )
-            // Gemini Protocol (Native)
-            .route("/v1beta/models", get(handlers::gemini::handle_list_models))
-            // Handle both GET (get info) and POST (generateContent with colon) at the same route
-            .route(
-                "/v1beta/models/:model",
-                get(handlers::gemini::handle_get_model).post(handlers::gemini::handle_generate),
-            )
-            .route(
-                "/v1beta/models/:model/countTokens",
-                post(handlers::gemini::handle_count_tokens),
-            ) // Specific route priority
-            .route(
-                "/v1/models/detect",
-                post(handlers::common::handle_detect_model),
-            )
-            .route("/internal/warmup", post(handlers::warmup::handle_warmup)) // 内部预热端点
-            .route("/v1/api/event_logging/batch", post(silent_ok_handler))
-            .route("/v1/api/event_logging", post(silent_ok_handler))
-            // 应用 AI 服务特定的层
-            // 注意：Axum layer 执行顺序是从下往上（洋葱模型）
-            // 请求: ip_filter -> auth -> monitor -> handler
-            // 响应: handler -> monitor -> auth -> ip_filter
-            // monitor 需要在 auth 之后执行才能获取 UserTokenIdentity
-            .layer(axum::middleware::from_fn_with_state(
-                state.clone(),
-                monitor_middleware,
-            ))
-            .layer(axum::middleware::from_fn_with_state(
-                state.clone(),
-                auth_middleware,
-            ))
-            .layer(axum::middleware::from_fn_with_state(
-                state.clone(),
-                ip_filter_middleware,
-            ));
+        let proxy_routes = build_proxy_routes(&state);
 
         // 2. 构建管理 API (强制鉴权)
         let admin_routes = Router::new()
@@ -491,6 +504,14 @@ impl AxumServer {
                 "/accounts/:accountId/device-versions/:versionId",
                 delete(admin_delete_device_version),
             )
+            .route(
+                "/accounts/device-consistency",
+                get(admin_check_device_profile_consistency),
+            )
+            .route(
+                "/accounts/device-consistency/regenerate",
+                post(admin_regenerate_collided_device_profiles),
+            )
             .route("/accounts/import/v1", post(admin_import_v1_accounts))
             .route("/accounts/import/db", post(admin_import_from_db))
             .route("/accounts/import/db-custom", post(admin_import_custom_db))
@@ -535,10 +556,25 @@ impl AxumServer {
                 "/proxy/rate-limits/:accountId",
                 delete(admin_clear_rate_limit),
             )
+            .route(
+                "/proxy/sessions/:sessionId/generation-config",
+                get(admin_get_session_generation_override)
+                    .post(admin_set_session_generation_override)
+                    .delete(admin_clear_session_generation_override),
+            )
             .route(
                 "/proxy/preferred-account",
                 get(admin_get_preferred_account).post(admin_set_preferred_account),
             )
+            .route(
+                "/proxy/signature-sentinel",
+                get(admin_get_signature_sentinel_capabilities)
+                    .delete(admin_reset_signature_sentinel_capabilities),
+            )
+            .route(
+                "/proxy/preferred-account/override",
+                post(admin_set_preferred_override),
+            )
             .route("/accounts/oauth/prepare", post(admin_prepare_oauth_url))
             .route("/accounts/oauth/start", post(admin_start_oauth_login))
             .route("/accounts/oauth/complete", post(admin_complete_oauth_login))
@@ -571,6 +607,9 @@ impl AxumServer {
             .route("/debug/enabled", get(admin_is_debug_console_enabled))
             .route("/debug/logs", get(admin_get_debug_console_logs))
             .route("/debug/logs/clear", post(admin_clear_debug_console_logs))
+            .route("/debug/captures/decrypt", post(admin_decrypt_debug_capture))
+            .route("/debug/captures/rotate-key", post(admin_rotate_debug_capture_key))
+            .route("/debug/recordings/replay", post(admin_replay_stream_recording))
             .route("/stats/token/clear", post(admin_clear_token_stats))
             .route("/stats/token/hourly", get(admin_get_token_stats_hourly))
             .route("/stats/token/daily", get(admin_get_token_stats_daily))
@@ -581,6 +620,7 @@ impl AxumServer {
             )
             .route("/stats/token/summary", get(admin_get_token_stats_summary))
             .route("/stats/token/by-model", get(admin_get_token_stats_by_model))
+            .route("/stats/quota-forecast", get(admin_get_quota_forecasts))
             .route(
                 "/stats/token/model-trend/hourly",
                 get(admin_get_token_stats_model_trend_hourly),
@@ -649,6 +689,8 @@ impl AxumServer {
             .route("/user-tokens", get(admin_list_user_tokens).post(admin_create_user_token))
             .route("/user-tokens/summary", get(admin_get_user_token_summary))
             .route("/user-tokens/:id/renew", post(admin_renew_user_token))
+            .route("/user-tokens/scoped", post(admin_mint_scoped_token))
+            .route("/user-tokens/scoped/:id", delete(admin_revoke_scoped_token))
             .route("/user-tokens/:id", delete(admin_delete_user_token).patch(admin_update_user_token))
             // OAuth (Web) - Admin 接口
             .route("/auth/url", get(admin_prepare_oauth_url_web))
@@ -659,11 +701,7 @@ impl AxumServer {
             ));
 
         // 3. 整合并应用全局层
-        // 从环境变量读取 body 大小限制，默认 50MB
-        let max_body_size: usize = std::env::var("ABV_MAX_BODY_SIZE")
-            .ok()
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(100 * 1024 * 1024); // 默认 100MB
+        let max_body_size = max_body_size();
         tracing::info!("请求体大小限制: {} MB", max_body_size / 1024 / 1024);
 
         let app = Router::new()
@@ -691,16 +729,17 @@ impl AxumServer {
             app
         };
 
-        // 绑定地址
-        let addr = format!("{}:{}", host, port);
-        let listener = tokio::net::TcpListener::bind(&addr)
-            .await
-            .map_err(|e| format!("地址 {} 绑定失败: {}", addr, e))?;
+        // [NEW] 监听配置档共享同一个 AppState (账号池)，仅请求默认行为不同
+        let profile_state = state.clone();
+        let profile_listeners = Arc::new(crate::proxy::listener_profile::ProfileListenerManager::new(
+            host.clone(),
+            move |_profile| build_profile_app(&profile_state),
+        ));
 
         tracing::info!("反代服务器启动在 http://{}", addr);
 
         // 创建关闭通道
-        let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
 
         let server_instance = Self {
             shutdown_tx: Arc::new(tokio::sync::Mutex::new(Some(shutdown_tx))),
@@ -716,53 +755,11 @@ impl AxumServer {
             token_manager: token_manager.clone(),
             proxy_pool_state,
             proxy_pool_manager,
+            profile_listeners,
         };
 
         // 在新任务中启动服务器
-        let handle = tokio::spawn(async move {
-            use hyper::server::conn::http1;
-            use hyper_util::rt::TokioIo;
-            use hyper_util::service::TowerToHyperService;
-
-            loop {
-                tokio::select! {
-                    res = listener.accept() => {
-                        match res {
-                            Ok((stream, remote_addr)) => {
-                                let io = TokioIo::new(stream);
-                                
-                                // 注入 ConnectInfo (用于获取真实 IP)
-                                use tower::ServiceExt;
-                                use hyper::body::Incoming;
-                                let app_with_info = app.clone().map_request(move |mut req: axum::http::Request<Incoming>| {
-                                    req.extensions_mut().insert(axum::extract::ConnectInfo(remote_addr));
-                                    req
-                                });
-
-                                let service = TowerToHyperService::new(app_with_info);
-
-                                tokio::task::spawn(async move {
-                                    if let Err(err) = http1::Builder::new()
-                                        .serve_connection(io, service)
-                                        .with_upgrades() // 支持 WebSocket (如果以后需要)
-                                        .await
-                                    {
-                                        debug!("连接处理结束或出错: {:?}", err);
-                                    }
-                                });
-                            }
-                            Err(e) => {
-                                error!("接收连接失败: {:?}", e);
-                            }
-                        }
-                    }
-                    _ = &mut shutdown_rx => {
-                        tracing::info!("反代服务器停止监听");
-                        break;
-                    }
-                }
-            }
-        });
+        let handle = spawn_accept_loop(listener, app, shutdown_rx);
 
         Ok((server_instance, handle))
     }
@@ -770,23 +767,207 @@ impl AxumServer {
     /// 停止服务器
     pub fn stop(&self) {
         let tx_mutex = self.shutdown_tx.clone();
+        let profile_listeners = self.profile_listeners.clone();
         tokio::spawn(async move {
             let mut lock = tx_mutex.lock().await;
             if let Some(tx) = lock.take() {
                 let _ = tx.send(());
                 tracing::info!("Axum server 停止信号已发送");
             }
+            profile_listeners.stop_all().await;
         });
     }
 }
 
+/// 请求体大小上限: 从环境变量 ABV_MAX_BODY_SIZE 读取，默认 100MB
+/// (DefaultBodyLimit 与需要自行读取请求体的中间件共用)
+pub(crate) fn max_body_size() -> usize {
+    std::env::var("ABV_MAX_BODY_SIZE")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(100 * 1024 * 1024)
+}
+
+/// 构建 AI 代理路由 (主监听端口与监听配置档共用)
+fn build_proxy_routes(state: &AppState) -> Router<AppState> {
+    use crate::proxy::handlers;
+    use crate::proxy::middleware::{
+        account_group_middleware, auth_middleware, ip_filter_middleware, monitor_middleware,
+        query_overrides_middleware,
+    };
+
+    Router::new()
+        .route("/health", get(health_check_handler))
+        .route("/healthz", get(health_check_handler))
+        // OpenAI Protocol
+        .route("/v1/models", get(handlers::openai::handle_list_models))
+        .route(
+            "/v1/chat/completions",
+            post(handlers::openai::handle_chat_completions).layer(
+                axum::middleware::from_fn_with_state(state.clone(), query_overrides_middleware),
+            ),
+        )
+        .route(
+            "/v1/completions",
+            post(handlers::openai::handle_completions),
+        )
+        .route("/v1/responses", post(handlers::openai::handle_completions)) // 兼容 Codex CLI
+        .route(
+            "/v1/images/generations",
+            post(handlers::openai::handle_images_generations),
+        ) // 图像生成 API
+        .route(
+            "/v1/images/edits",
+            post(handlers::openai::handle_images_edits),
+        ) // 图像编辑 API
+        .route(
+            "/v1/audio/transcriptions",
+            post(handlers::audio::handle_audio_transcription),
+        ) // 音频转录 API
+        // Claude Protocol
+        .route(
+            "/v1/messages",
+            post(handlers::claude::handle_messages).layer(
+                axum::middleware::from_fn_with_state(state.clone(), query_overrides_middleware),
+            ),
+        )
+        .route(
+            "/v1/messages/count_tokens",
+            post(handlers::claude::handle_count_tokens),
+        )
+        .route(
+            "/v1/models/claude",
+            get(handlers::claude::handle_list_models),
+        )
+        // z.ai MCP (optional reverse-proxy)
+        .route(
+            "/mcp/web_search_prime/mcp",
+            any(handlers::mcp::handle_web_search_prime),
+        )
+        .route("/mcp/web_reader/mcp", any(handlers::mcp::handle_web_reader))
+        .route(
+            "/mcp/zai-mcp-server/mcp",
+            any(handlers::mcp::handle_zai_mcp_server),
+        )
+        // Gemini Protocol (Native)
+        .route("/v1beta/models", get(handlers::gemini::handle_list_models))
+        // Handle both GET (get info) and POST (generateContent with colon) at the same route
+        .route(
+            "/v1beta/models/:model",
+            get(handlers::gemini::handle_get_model).post(handlers::gemini::handle_generate),
+        )
+        .route(
+            "/v1beta/models/:model/countTokens",
+            post(handlers::gemini::handle_count_tokens),
+        ) // Specific route priority
+        .route(
+            "/v1/models/detect",
+            post(handlers::common::handle_detect_model),
+        )
+        .route(
+            "/v1/abv/capabilities",
+            get(handlers::common::handle_capabilities),
+        )
+        .route("/internal/warmup", post(handlers::warmup::handle_warmup)) // 内部预热端点
+        .route("/v1/api/event_logging/batch", post(silent_ok_handler))
+        .route("/v1/api/event_logging", post(silent_ok_handler))
+        // 应用 AI 服务特定的层
+        // 注意：Axum layer 执行顺序是从下往上（洋葱模型）
+        // 请求: ip_filter -> auth -> monitor -> account_group -> handler
+        // 响应: handler -> account_group -> monitor -> auth -> ip_filter
+        // monitor 需要在 auth 之后执行才能获取 UserTokenIdentity
+        .layer(axum::middleware::from_fn(account_group_middleware))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            monitor_middleware,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            auth_middleware,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            ip_filter_middleware,
+        ))
+}
+
+/// 为监听配置档构建完整应用 (不包含管理 API)
+fn build_profile_app(state: &AppState) -> Router {
+    use crate::proxy::middleware::{cors_layer, service_status_middleware};
+
+    let max_body_size = max_body_size();
+
+    build_proxy_routes(state)
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            service_status_middleware,
+        ))
+        .layer(cors_layer())
+        .layer(DefaultBodyLimit::max(max_body_size))
+        .with_state(state.clone())
+}
+
+/// 启动连接接收循环
+/// 收到关闭信号后停止接收新连接，已建立的连接会继续处理直至完成 (drain)
+pub(crate) fn spawn_accept_loop(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    mut shutdown_rx: oneshot::Receiver<()>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        use hyper::server::conn::http1;
+        use hyper_util::rt::TokioIo;
+        use hyper_util::service::TowerToHyperService;
+
+        loop {
+            tokio::select! {
+                res = listener.accept() => {
+                    match res {
+                        Ok((stream, remote_addr)) => {
+                            let io = TokioIo::new(stream);
+                        
+                            // 注入 ConnectInfo (用于获取真实 IP)
+                            use tower::ServiceExt;
+                            use hyper::body::Incoming;
+                            let app_with_info = app.clone().map_request(move |mut req: axum::http::Request<Incoming>| {
+                                req.extensions_mut().insert(axum::extract::ConnectInfo(remote_addr));
+                                req
+                            });
+
+                            let service = TowerToHyperService::new(app_with_info);
+
+                            tokio::task::spawn(async move {
+                                if let Err(err) = http1::Builder::new()
+                                    .serve_connection(io, service)
+                                    .with_upgrades() // 支持 WebSocket (如果以后需要)
+                                    .await
+                                {
+                                    debug!("连接处理结束或出错: {:?}", err);
+                                }
+                            });
+                        }
+                        Err(e) => {
+                            error!("接收连接失败: {:?}", e);
+                        }
+                    }
+                }
+                _ = &mut shutdown_rx => {
+                    tracing::info!("反代服务器停止监听");
+                    break;
+                }
+            }
+        }
+    })
+}
+
 // ===== API 处理器 (旧代码已移除，由 src/proxy/handlers/* 接管) =====
 
 /// 健康检查处理器
 async fn health_check_handler() -> Response {
     Json(serde_json::json!({
         "status": "ok",
-        "version": env!("CARGO_PKG_VERSION")
+        "version": env!("CARGO_PKG_VERSION"),
+        "degraded": crate::proxy::latency_slo::status().degraded
     }))
     .into_response()
 }
@@ -819,6 +1000,7 @@ async fn admin_list_accounts(
         .map(|acc| {
             let is_current = current_id.as_ref().map(|id| id == &acc.id).unwrap_or(false);
             let quota = acc.quota.map(|q| QuotaResponse {
+                unparsed: q.is_unparsed(),
                 models: q
                     .models
                     .into_iter()
@@ -826,6 +1008,10 @@ async fn admin_list_accounts(
                         name: m.name,
                         percentage: m.percentage,
                         reset_time: m.reset_time,
+                        remaining_tokens: m.remaining_tokens,
+                        limit_tokens: m.limit_tokens,
+                        reset_window_secs: m.reset_window_secs,
+                        unparsed: m.unparsed,
                     })
                     .collect(),
                 last_updated: q.last_updated,
@@ -851,6 +1037,7 @@ async fn admin_list_accounts(
                 quota,
                 device_bound: acc.device_profile.is_some(),
                 last_used: acc.last_used,
+                warmup_remaining_secs: warmup_remaining_secs(acc.created_at),
             }
         })
         .collect();
@@ -896,6 +1083,7 @@ async fn admin_get_current_account(
         let acc = account::load_account(&id).ok();
         acc.map(|acc| {
             let quota = acc.quota.map(|q| QuotaResponse {
+                unparsed: q.is_unparsed(),
                 models: q
                     .models
                     .into_iter()
@@ -903,6 +1091,10 @@ async fn admin_get_current_account(
                         name: m.name,
                         percentage: m.percentage,
                         reset_time: m.reset_time,
+                        remaining_tokens: m.remaining_tokens,
+                        limit_tokens: m.limit_tokens,
+                        reset_window_secs: m.reset_window_secs,
+                        unparsed: m.unparsed,
                     })
                     .collect(),
                 last_updated: q.last_updated,
@@ -928,6 +1120,7 @@ async fn admin_get_current_account(
                 quota,
                 device_bound: acc.device_profile.is_some(),
                 last_used: acc.last_used,
+                warmup_remaining_secs: warmup_remaining_secs(acc.created_at),
             }
         })
     } else {
@@ -1195,6 +1388,28 @@ async fn admin_bind_device(
     })))
 }
 
+async fn admin_check_device_profile_consistency(
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let report = crate::modules::device_consistency::check_and_persist().map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: e }),
+        )
+    })?;
+    Ok(Json(report))
+}
+
+async fn admin_regenerate_collided_device_profiles(
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let result = crate::modules::device_consistency::regenerate_collided_profiles().map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: e }),
+        )
+    })?;
+    Ok(Json(result))
+}
+
 #[derive(Deserialize, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 #[allow(dead_code)] // 预留日志接口结构体
@@ -1273,6 +1488,9 @@ async fn admin_save_config(
     // 或者直接操作 AppState 里的各状态。
     // 在本重构中，各个状态已经在 AppState 中了。
 
+    // [NEW] 实时日志视图缓冲区大小
+    crate::modules::log_bridge::set_buffer_capacity(new_config.logging.buffer_size);
+
     // 更新模型映射
     {
         let mut mapping = state.custom_mapping.write().await;
@@ -1403,6 +1621,8 @@ async fn admin_get_proxy_status(
         "port": state.port,
         "base_url": format!("http://127.0.0.1:{}", state.port),
         "active_accounts": active_accounts,
+        "latency_slo": crate::proxy::latency_slo::status(),
+        "preferred_override": state.token_manager.get_preferred_override(),
     })))
 }
 
@@ -1529,6 +1749,88 @@ async fn admin_set_preferred_account(
     StatusCode::OK
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetPreferredOverrideRequest {
+    account_id: Option<String>,
+    #[serde(default)]
+    duration_secs: u64,
+}
+
+async fn admin_set_preferred_override(
+    State(state): State<AppState>,
+    Json(payload): Json<SetPreferredOverrideRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let Some(account_id) = payload.account_id.filter(|s| !s.trim().is_empty()) else {
+        state.token_manager.clear_preferred_override();
+        return Ok(Json(None::<crate::proxy::token_manager::PreferredAccountOverride>));
+    };
+    if payload.duration_secs == 0 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "durationSecs must be greater than 0".to_string(),
+            }),
+        ));
+    }
+
+    let expires_at = chrono::Utc::now().timestamp() + payload.duration_secs as i64;
+    let entry = state
+        .token_manager
+        .set_preferred_override(&account_id, expires_at)
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+    crate::commands::proxy::schedule_preferred_override_expiry(
+        state.token_manager.clone(),
+        Some(state.monitor.clone()),
+        expires_at,
+    );
+    Ok(Json(Some(entry)))
+}
+
+// [NEW] 会话级生成参数覆盖 (可复现的 Agent 运行)
+async fn admin_get_session_generation_override(Path(session_id): Path<String>) -> impl IntoResponse {
+    Json(crate::proxy::session_manager::SessionGenerationOverrides::global().get(&session_id))
+}
+
+async fn admin_set_session_generation_override(
+    Path(session_id): Path<String>,
+    Json(payload): Json<crate::proxy::session_manager::GenerationOverride>,
+) -> impl IntoResponse {
+    logger::log_info(&format!("[API] 已设置会话 {} 的生成参数覆盖", session_id));
+    crate::proxy::session_manager::SessionGenerationOverrides::global().set(&session_id, payload);
+    StatusCode::OK
+}
+
+async fn admin_clear_session_generation_override(Path(session_id): Path<String>) -> impl IntoResponse {
+    if crate::proxy::session_manager::SessionGenerationOverrides::global().clear(&session_id) {
+        logger::log_info(&format!("[API] 已清除会话 {} 的生成参数覆盖", session_id));
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn admin_get_signature_sentinel_capabilities() -> impl IntoResponse {
+    Json(crate::proxy::signature_sentinel::snapshot())
+}
+
+#[derive(Deserialize)]
+struct SignatureSentinelResetQuery {
+    model: Option<String>,
+}
+
+async fn admin_reset_signature_sentinel_capabilities(
+    Query(params): Query<SignatureSentinelResetQuery>,
+) -> impl IntoResponse {
+    let removed = crate::proxy::signature_sentinel::reset(params.model.as_deref());
+    logger::log_info(&format!(
+        "[API] 已清除 {} 条签名哨兵值学习记录 (model: {})",
+        removed,
+        params.model.as_deref().unwrap_or("*")
+    ));
+    Json(serde_json::json!({ "removed": removed }))
+}
+
 async fn admin_fetch_zai_models(
     Path(_id): Path<String>,
     Json(payload): Json<serde_json::Value>, // 复用前端传来的参数
@@ -1790,6 +2092,30 @@ async fn admin_delete_user_token(
     Ok(StatusCode::NO_CONTENT)
 }
 
+async fn admin_mint_scoped_token(
+    Json(payload): Json<crate::commands::user_token::MintScopedTokenRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let token = crate::commands::user_token::mint_scoped_token(payload).await.map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: e }),
+        )
+    })?;
+    Ok(Json(token))
+}
+
+async fn admin_revoke_scoped_token(
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    crate::commands::user_token::revoke_scoped_token(id).await.map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: e }),
+        )
+    })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 async fn admin_update_user_token(
     Path(id): Path<String>,
     Json(payload): Json<crate::commands::user_token::UpdateTokenRequest>,
@@ -2008,6 +2334,29 @@ async fn admin_get_token_stats_by_model(
     }
 }
 
+async fn admin_get_quota_forecasts(
+    Query(p): Query<StatsPeriodQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let res = tokio::task::spawn_blocking(move || {
+        crate::modules::quota_forecast::get_quota_forecasts(p.hours)
+    })
+    .await;
+
+    match res {
+        Ok(Ok(forecasts)) => Ok(Json(forecasts)),
+        Ok(Err(e)) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: e }),
+        )),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )),
+    }
+}
+
 async fn admin_get_token_stats_model_trend_hourly(
 ) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
     let res = tokio::task::spawn_blocking(|| {
@@ -3306,6 +3655,53 @@ async fn admin_update_security_config(
 
 // --- Debug Console Handlers ---
 
+#[derive(Deserialize)]
+struct DecryptCaptureRequest {
+    path: String,
+}
+
+// [NEW] 抓包文件解密 / 密钥轮换 (POST，仅 admin 范围令牌可用)
+async fn admin_decrypt_debug_capture(
+    State(state): State<AppState>,
+    Json(payload): Json<DecryptCaptureRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let cfg = state.debug_logging.read().await.clone();
+    let res = tokio::task::spawn_blocking(move || {
+        crate::proxy::debug_logger::decrypt_capture(&cfg, &payload.path)
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|r| r);
+
+    match res {
+        Ok(content) => Ok(Json(serde_json::json!({ "content": content }))),
+        Err(e) => Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }))),
+    }
+}
+
+async fn admin_rotate_debug_capture_key(
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let key_id = tokio::task::spawn_blocking(crate::proxy::debug_logger::rotate_capture_key)
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|r| r)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: e })))?;
+    logger::log_info(&format!("[API] 已轮换抓包加密密钥 (#{})", key_id));
+    Ok(Json(serde_json::json!({ "key_id": key_id })))
+}
+
+// [NEW] 离线回放流式录制 (不请求上游)，返回客户端 SSE 输出
+async fn admin_replay_stream_recording(
+    State(state): State<AppState>,
+    Json(payload): Json<DecryptCaptureRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let cfg = state.debug_logging.read().await.clone();
+    match crate::proxy::stream_recording::replay_file(&cfg, &payload.path).await {
+        Ok(output) => Ok(Json(serde_json::json!({ "output": output }))),
+        Err(e) => Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }))),
+    }
+}
+
 async fn admin_enable_debug_console() -> impl IntoResponse {
     crate::modules::log_bridge::enable_log_bridge();
     StatusCode::OK
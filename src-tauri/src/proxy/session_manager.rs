@@ -1,7 +1,10 @@
 use sha2::{Sha256, Digest};
 use crate::proxy::mappers::claude::models::{ClaudeRequest, MessageContent};
 use crate::proxy::mappers::openai::models::{OpenAIRequest, OpenAIContent};
-use serde_json::Value;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use dashmap::DashMap;
+use std::sync::OnceLock;
 
 /// 会话管理器工具
 pub struct SessionManager;
@@ -77,6 +80,24 @@ impl SessionManager {
         sid
     }
 
+    /// [NEW] 根据清洗后的 Claude 请求生成请求重放指纹
+    ///
+    /// 与会话指纹不同，这里哈希完整的 messages + system + tools，
+    /// 不包含 metadata / stream / max_tokens 等易变字段。
+    /// 客户端以相同请求体重试时得到相同指纹，用于 token 统计去重。
+    pub fn compute_replay_hash(request: &ClaudeRequest) -> String {
+        let mut hasher = Sha256::new();
+        let mut feed = |label: &str, value: Value| {
+            hasher.update(label.as_bytes());
+            hasher.update(value.to_string().as_bytes());
+        };
+        feed("messages", serde_json::to_value(&request.messages).unwrap_or(Value::Null));
+        feed("system", serde_json::to_value(&request.system).unwrap_or(Value::Null));
+        feed("tools", serde_json::to_value(&request.tools).unwrap_or(Value::Null));
+
+        format!("rh-{}", &format!("{:x}", hasher.finalize())[..32])
+    }
+
     /// 根据 OpenAI 请求生成稳定的会话指纹
     pub fn extract_openai_session_id(request: &OpenAIRequest) -> String {
         let mut hasher = Sha256::new();
@@ -158,3 +179,171 @@ impl SessionManager {
         sid
     }
 }
+
+/// [NEW] 会话级生成参数覆盖 (用于可复现的 Agent 运行)
+/// 会话开始时通过请求头或管理 API 设置，之后该会话的每个请求都强制使用这些参数，
+/// 无论单次请求携带了什么值。未设置的字段保持请求原值。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationOverride {
+    pub temperature: Option<f64>,
+    #[serde(alias = "topP")]
+    pub top_p: Option<f64>,
+    #[serde(alias = "topK")]
+    pub top_k: Option<u32>,
+    #[serde(alias = "maxOutputTokens", alias = "max_tokens")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(alias = "presencePenalty")]
+    pub presence_penalty: Option<f64>,
+    #[serde(alias = "frequencyPenalty")]
+    pub frequency_penalty: Option<f64>,
+    pub seed: Option<i64>,
+}
+
+impl GenerationOverride {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// 将覆盖值写入 Gemini generationConfig
+    pub fn apply_to(&self, config: &mut Value) {
+        let fields = [
+            ("temperature", self.temperature.map(|v| json!(v))),
+            ("topP", self.top_p.map(|v| json!(v))),
+            ("topK", self.top_k.map(|v| json!(v))),
+            ("maxOutputTokens", self.max_output_tokens.map(|v| json!(v))),
+            ("presencePenalty", self.presence_penalty.map(|v| json!(v))),
+            ("frequencyPenalty", self.frequency_penalty.map(|v| json!(v))),
+            ("seed", self.seed.map(|v| json!(v))),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                config[key] = value;
+            }
+        }
+    }
+}
+
+/// [NEW] 按 session_id 保存的生成参数覆盖，随会话空闲回收一并清理
+#[derive(Default)]
+pub struct SessionGenerationOverrides {
+    by_session: DashMap<String, GenerationOverride>,
+}
+
+impl SessionGenerationOverrides {
+    pub fn global() -> &'static SessionGenerationOverrides {
+        static INSTANCE: OnceLock<SessionGenerationOverrides> = OnceLock::new();
+        INSTANCE.get_or_init(SessionGenerationOverrides::default)
+    }
+
+    /// 设置会话覆盖 (空覆盖等同于清除)
+    pub fn set(&self, session_id: &str, overrides: GenerationOverride) {
+        if session_id.is_empty() {
+            return;
+        }
+        if overrides.is_empty() {
+            self.by_session.remove(session_id);
+        } else {
+            self.by_session.insert(session_id.to_string(), overrides);
+        }
+    }
+
+    pub fn get(&self, session_id: &str) -> Option<GenerationOverride> {
+        self.by_session.get(session_id).map(|o| o.clone())
+    }
+
+    pub fn clear(&self, session_id: &str) -> bool {
+        self.by_session.remove(session_id).is_some()
+    }
+
+    /// 将会话覆盖合并进 generationConfig，返回是否应用了覆盖
+    pub fn apply(&self, session_id: &str, config: &mut Value) -> bool {
+        match self.by_session.get(session_id) {
+            Some(overrides) => {
+                overrides.apply_to(config);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl crate::proxy::session_registry::SessionStateStore for SessionGenerationOverrides {
+    fn name(&self) -> &'static str {
+        "generation_overrides"
+    }
+
+    fn evict_session(&self, session_id: &str) -> usize {
+        usize::from(self.clear(session_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(body: Value) -> ClaudeRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn test_replay_hash_ignores_volatile_fields() {
+        let a = request(json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "metadata": { "user_id": "session-a" },
+            "system": "be concise",
+            "messages": [{ "role": "user", "content": "hello" }]
+        }));
+        let b = request(json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 2048,
+            "stream": true,
+            "metadata": { "user_id": "session-b" },
+            "system": "be concise",
+            "messages": [{ "role": "user", "content": "hello" }]
+        }));
+        let c = request(json!({
+            "model": "claude-sonnet-4-5",
+            "system": "be concise",
+            "messages": [{ "role": "user", "content": "hello again" }]
+        }));
+
+        assert_eq!(SessionManager::compute_replay_hash(&a), SessionManager::compute_replay_hash(&b));
+        assert_ne!(SessionManager::compute_replay_hash(&a), SessionManager::compute_replay_hash(&c));
+    }
+
+    #[test]
+    fn test_generation_override_applies_until_session_evicted() {
+        use crate::proxy::session_registry::SessionStateStore;
+
+        let store = SessionGenerationOverrides::default();
+        let overrides: GenerationOverride = serde_json::from_value(json!({
+            "temperature": 0.0,
+            "topP": 0.5,
+            "frequency_penalty": 0.3,
+            "seed": 42
+        }))
+        .unwrap();
+        store.set("sid-pinned", overrides);
+
+        for client_temperature in [0.7, 1.3] {
+            let mut config = json!({ "temperature": client_temperature, "topK": 40 });
+            assert!(store.apply("sid-pinned", &mut config));
+            assert_eq!(config["temperature"], 0.0);
+            assert_eq!(config["topP"], 0.5);
+            assert_eq!(config["frequencyPenalty"], 0.3);
+            assert_eq!(config["seed"], 42);
+            // 未覆盖的字段保持请求原值
+            assert_eq!(config["topK"], 40);
+        }
+
+        let mut other = json!({ "temperature": 0.7 });
+        assert!(!store.apply("sid-other", &mut other));
+        assert_eq!(other["temperature"], 0.7);
+
+        assert_eq!(store.evict_session("sid-pinned"), 1);
+        assert!(store.get("sid-pinned").is_none());
+    }
+}
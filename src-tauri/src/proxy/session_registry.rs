@@ -0,0 +1,313 @@
+// 会话活跃登记与空闲状态回收
+// 各流式/请求路径在拿到 session_id 后调用 touch() 更新最后活跃时间；
+// 调度器中的清理任务定期调用 run_janitor()，对空闲超过 TTL (+ 宽限期) 的会话，
+// 依次调用所有已注册的会话级存储 (SessionStateStore) 回收其状态。
+// 新增的会话级存储只需实现该 trait 并 register_store() 即可自动参与回收。
+
+use dashmap::DashMap;
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// 清理任务额外等待的宽限期，避免恰好在 TTL 边界上恢复活跃的会话被误回收
+pub const JANITOR_GRACE_PERIOD: Duration = Duration::from_secs(5 * 60);
+
+/// 持有会话级状态的子系统 (签名缓存、粘性绑定等)
+pub trait SessionStateStore: Send + Sync {
+    /// 存储名称 (用于日志统计，同名注册会替换旧实例)
+    fn name(&self) -> &'static str;
+
+    /// 回收指定会话的全部状态，返回删除的条目数
+    fn evict_session(&self, session_id: &str) -> usize;
+}
+
+impl<T: SessionStateStore + ?Sized> SessionStateStore for &'static T {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn evict_session(&self, session_id: &str) -> usize {
+        (**self).evict_session(session_id)
+    }
+}
+
+/// 单次清理结果
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct JanitorReport {
+    /// 被判定为空闲并回收的会话数
+    pub sessions: usize,
+    /// 各存储删除的条目数
+    pub evicted: BTreeMap<&'static str, usize>,
+}
+
+impl JanitorReport {
+    pub fn total_entries(&self) -> usize {
+        self.evicted.values().sum()
+    }
+}
+
+/// 清理累计指标 (进程启动以来)
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct JanitorMetrics {
+    /// 清理执行次数
+    pub runs: u64,
+    /// 累计回收的会话数
+    pub sessions_reaped: u64,
+    /// 各存储累计删除的条目数
+    pub evicted: BTreeMap<&'static str, u64>,
+    /// 当前登记的活跃会话数
+    pub active_sessions: usize,
+}
+
+#[derive(Default)]
+pub struct SessionRegistry {
+    activity: DashMap<String, Instant>,
+    stores: RwLock<Vec<Arc<dyn SessionStateStore>>>,
+    metrics: Mutex<JanitorMetrics>,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 注册会话级存储 (同名存储会被替换，便于服务重启后重复注册)
+    pub fn register(&self, store: Arc<dyn SessionStateStore>) {
+        let mut stores = self.stores.write();
+        stores.retain(|s| s.name() != store.name());
+        stores.push(store);
+    }
+
+    /// 更新会话最后活跃时间
+    pub fn touch_at(&self, session_id: &str, now: Instant) {
+        if session_id.is_empty() {
+            return;
+        }
+        match self.activity.get_mut(session_id) {
+            Some(mut last) => {
+                if now > *last {
+                    *last = now;
+                }
+            }
+            None => {
+                self.activity.insert(session_id.to_string(), now);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.activity.len()
+    }
+
+    /// 回收空闲超过 idle_ttl + grace 的会话
+    pub fn reap_idle(&self, now: Instant, idle_ttl: Duration, grace: Duration) -> JanitorReport {
+        let cutoff = idle_ttl + grace;
+        let is_idle = |last: &Instant| now.saturating_duration_since(*last) > cutoff;
+
+        let candidates: Vec<String> = self
+            .activity
+            .iter()
+            .filter(|entry| is_idle(entry.value()))
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        let stores = self.stores.read().clone();
+        let mut report = JanitorReport::default();
+        for session_id in candidates {
+            // 扫描后会话可能已被再次 touch，移除时重新判断，保证不会回收活跃会话
+            if self
+                .activity
+                .remove_if(&session_id, |_, last| is_idle(last))
+                .is_none()
+            {
+                continue;
+            }
+            report.sessions += 1;
+            for store in &stores {
+                let removed = store.evict_session(&session_id);
+                *report.evicted.entry(store.name()).or_default() += removed;
+            }
+        }
+
+        let mut metrics = self.metrics.lock();
+        metrics.runs += 1;
+        metrics.sessions_reaped += report.sessions as u64;
+        for (name, removed) in &report.evicted {
+            *metrics.evicted.entry(name).or_default() += *removed as u64;
+        }
+        report
+    }
+
+    /// 累计清理指标
+    pub fn metrics(&self) -> JanitorMetrics {
+        JanitorMetrics {
+            active_sessions: self.len(),
+            ..self.metrics.lock().clone()
+        }
+    }
+}
+
+static GLOBAL_REGISTRY: OnceLock<SessionRegistry> = OnceLock::new();
+
+fn global() -> &'static SessionRegistry {
+    GLOBAL_REGISTRY.get_or_init(|| {
+        let registry = SessionRegistry::new();
+        registry.register(Arc::new(crate::proxy::SignatureCache::global()));
+        registry.register(Arc::new(crate::proxy::poison_quarantine::PoisonCache::global()));
+        registry.register(Arc::new(
+            crate::proxy::session_manager::SessionGenerationOverrides::global(),
+        ));
+        // 粘性绑定 (TokenManager) 与 Vision MCP 会话随服务实例创建，启动时通过 register_store() 注册；
+        // 估算校准器只保存全局累计值，不持有会话级条目，无需参与回收
+        registry
+    })
+}
+
+/// 注册会话级存储到全局登记表
+pub fn register_store(store: Arc<dyn SessionStateStore>) {
+    global().register(store);
+}
+
+/// 标记会话活跃 (请求开始及流式数据到达时调用)
+pub fn touch(session_id: &str) {
+    global().touch_at(session_id, Instant::now());
+}
+
+/// 执行一次空闲会话回收 (由调度器定期调用)
+pub fn run_janitor() -> JanitorReport {
+    let ttl = Duration::from_secs(crate::proxy::config::get_session_idle_ttl_secs());
+    let report = global().reap_idle(Instant::now(), ttl, JANITOR_GRACE_PERIOD);
+    if report.sessions > 0 {
+        let metrics = global().metrics();
+        tracing::info!(
+            "[Session-Janitor] Reaped {} idle session(s), {} entries {:?}, {} active (total reaped: {}, evicted: {:?})",
+            report.sessions,
+            report.total_entries(),
+            report.evicted,
+            metrics.active_sessions,
+            metrics.sessions_reaped,
+            metrics.evicted
+        );
+    }
+    report
+}
+
+/// 空闲会话回收的累计指标 (供状态接口使用)
+pub fn metrics() -> JanitorMetrics {
+    global().metrics()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// 模拟会话级存储
+    struct FakeStore {
+        name: &'static str,
+        entries: Mutex<HashMap<String, usize>>,
+    }
+
+    impl FakeStore {
+        fn new(name: &'static str, sessions: &[(&str, usize)]) -> Arc<Self> {
+            Arc::new(Self {
+                name,
+                entries: Mutex::new(sessions.iter().map(|(s, n)| (s.to_string(), *n)).collect()),
+            })
+        }
+
+        fn has(&self, session_id: &str) -> bool {
+            self.entries.lock().contains_key(session_id)
+        }
+    }
+
+    impl SessionStateStore for FakeStore {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn evict_session(&self, session_id: &str) -> usize {
+            self.entries.lock().remove(session_id).unwrap_or(0)
+        }
+    }
+
+    const TTL: Duration = Duration::from_secs(6 * 60 * 60);
+    const GRACE: Duration = Duration::from_secs(5 * 60);
+
+    #[test]
+    fn test_janitor_evicts_idle_sessions_across_stores() {
+        let registry = SessionRegistry::new();
+        let signatures = FakeStore::new("signatures", &[("sid-idle", 1), ("sid-active", 1)]);
+        let bindings = FakeStore::new("sticky", &[("sid-idle", 2), ("sid-active", 1)]);
+        registry.register(signatures.clone());
+        registry.register(bindings.clone());
+
+        let t0 = Instant::now();
+        registry.touch_at("sid-idle", t0);
+        registry.touch_at("sid-active", t0);
+
+        // 活跃会话持续有流式数据
+        let later = t0 + TTL + GRACE + Duration::from_secs(60);
+        registry.touch_at("sid-active", later - Duration::from_secs(30));
+
+        let report = registry.reap_idle(later, TTL, GRACE);
+        assert_eq!(report.sessions, 1);
+        assert_eq!(report.evicted.get("signatures"), Some(&1));
+        assert_eq!(report.evicted.get("sticky"), Some(&2));
+        assert_eq!(report.total_entries(), 3);
+
+        assert!(!signatures.has("sid-idle"));
+        assert!(!bindings.has("sid-idle"));
+        assert!(signatures.has("sid-active"));
+        assert!(bindings.has("sid-active"));
+        assert_eq!(registry.len(), 1);
+
+        // 累计指标跨多次清理叠加
+        registry.reap_idle(later, TTL, GRACE);
+        let metrics = registry.metrics();
+        assert_eq!(metrics.runs, 2);
+        assert_eq!(metrics.sessions_reaped, 1);
+        assert_eq!(metrics.evicted.get("sticky"), Some(&2));
+        assert_eq!(metrics.active_sessions, 1);
+    }
+
+    #[test]
+    fn test_grace_period_protects_sessions_near_ttl() {
+        let registry = SessionRegistry::new();
+        let store = FakeStore::new("signatures", &[("sid-a", 1)]);
+        registry.register(store.clone());
+
+        let t0 = Instant::now();
+        registry.touch_at("sid-a", t0);
+
+        // 刚超过 TTL 但仍在宽限期内: 不回收
+        let report = registry.reap_idle(t0 + TTL + Duration::from_secs(1), TTL, GRACE);
+        assert_eq!(report.sessions, 0);
+        assert!(store.has("sid-a"));
+
+        // 较早的时间戳不会覆盖较新的活跃时间
+        registry.touch_at("sid-a", t0 + TTL);
+        registry.touch_at("sid-a", t0);
+        let report = registry.reap_idle(t0 + TTL + GRACE + Duration::from_secs(1), TTL, GRACE);
+        assert_eq!(report.sessions, 0);
+        assert!(store.has("sid-a"));
+    }
+
+    #[test]
+    fn test_register_replaces_store_with_same_name() {
+        let registry = SessionRegistry::new();
+        let old = FakeStore::new("sticky", &[("sid-a", 1)]);
+        let new = FakeStore::new("sticky", &[("sid-a", 1)]);
+        registry.register(old.clone());
+        registry.register(new.clone());
+
+        let t0 = Instant::now();
+        registry.touch_at("sid-a", t0);
+        let report = registry.reap_idle(t0 + TTL + GRACE * 2, TTL, GRACE);
+        assert_eq!(report.evicted.get("sticky"), Some(&1));
+        assert!(old.has("sid-a"));
+        assert!(!new.has("sid-a"));
+    }
+}
@@ -10,6 +10,7 @@ const MIN_SIGNATURE_LENGTH: usize = 50;
 const TOOL_CACHE_LIMIT: usize = 500;      // Layer 1: Tool-specific signatures
 const FAMILY_CACHE_LIMIT: usize = 200;    // Layer 2: Model family mappings
 const SESSION_CACHE_LIMIT: usize = 1000;  // Layer 3: Session-based signatures (largest)
+const BLOCK_CACHE_LIMIT: usize = 1000;    // Layer 4: Late signatures keyed by thinking block hash
 
 /// Cache entry with timestamp for TTL
 #[derive(Clone, Debug)]
@@ -58,6 +59,19 @@ pub struct SignatureCache {
     /// Value: The most recent valid thought signature for this session
     /// This prevents signature pollution between different conversations
     session_signatures: Mutex<HashMap<String, CacheEntry<SessionSignatureEntry>>>,
+
+    /// Layer 4: (Session ID, Thinking Block Hash) -> Signature (NEW)
+    /// Key: "{session_id}:{hash of thinking text}"
+    /// Value: A signature that arrived after its thinking block was already closed,
+    /// so the client never received it with the block
+    block_signatures: Mutex<HashMap<String, CacheEntry<String>>>,
+}
+
+/// Layer 4 key: session + thinking 文本哈希 (忽略首尾空白，客户端回传时可能被裁剪)
+fn thinking_block_key(session_id: &str, thinking: &str) -> String {
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::digest(thinking.trim().as_bytes());
+    format!("{}:{:x}", session_id, digest)
 }
 
 impl SignatureCache {
@@ -66,6 +80,7 @@ impl SignatureCache {
             tool_signatures: Mutex::new(HashMap::new()),
             thinking_families: Mutex::new(HashMap::new()),
             session_signatures: Mutex::new(HashMap::new()),
+            block_signatures: Mutex::new(HashMap::new()),
         }
     }
 
@@ -239,6 +254,48 @@ impl SignatureCache {
         None
     }
 
+    // ===== Layer 4: Late Signatures by Thinking Block =====
+
+    /// Store a signature for a thinking block that was closed before the signature arrived.
+    pub fn cache_thinking_block_signature(&self, session_id: &str, thinking: &str, signature: String) {
+        if signature.len() < MIN_SIGNATURE_LENGTH || thinking.trim().is_empty() {
+            return;
+        }
+
+        if let Ok(mut cache) = self.block_signatures.lock() {
+            tracing::debug!(
+                "[SignatureCache] Session {} -> caching late signature for thinking block (len={})",
+                session_id,
+                signature.len()
+            );
+            cache.insert(thinking_block_key(session_id, thinking), CacheEntry::new(signature));
+
+            if cache.len() > BLOCK_CACHE_LIMIT {
+                let before = cache.len();
+                cache.retain(|_, v| !v.is_expired());
+                let after = cache.len();
+                if before != after {
+                    tracing::debug!("[SignatureCache] Block cache cleanup: {} -> {} entries", before, after);
+                }
+            }
+        }
+    }
+
+    /// Retrieve the late signature cached for a thinking block in this session.
+    pub fn get_thinking_block_signature(&self, session_id: &str, thinking: &str) -> Option<String> {
+        let cache = self.block_signatures.lock().ok()?;
+        let entry = cache.get(&thinking_block_key(session_id, thinking))?;
+        if entry.is_expired() {
+            return None;
+        }
+        tracing::debug!(
+            "[SignatureCache] Session {} -> HIT late thinking block signature (len={})",
+            session_id,
+            entry.data.len()
+        );
+        Some(entry.data.clone())
+    }
+
     /// 删除指定会话的缓存签名
     #[allow(dead_code)] // 预留给管理接口或调试使用
     pub fn delete_session_signature(&self, session_id: &str) {
@@ -249,6 +306,26 @@ impl SignatureCache {
         }
     }
 
+    /// 回收指定会话的缓存签名，返回删除的条目数
+    pub fn evict_session_signature(&self, session_id: &str) -> usize {
+        let session_entries = self
+            .session_signatures
+            .lock()
+            .map(|mut cache| cache.remove(session_id).is_some() as usize)
+            .unwrap_or(0);
+        let prefix = format!("{}:", session_id);
+        let block_entries = self
+            .block_signatures
+            .lock()
+            .map(|mut cache| {
+                let before = cache.len();
+                cache.retain(|key, _| !key.starts_with(&prefix));
+                before - cache.len()
+            })
+            .unwrap_or(0);
+        session_entries + block_entries
+    }
+
     /// Clear all caches (for testing or manual reset)
     #[allow(dead_code)] // Used in tests
     pub fn clear(&self) {
@@ -261,6 +338,19 @@ impl SignatureCache {
         if let Ok(mut cache) = self.session_signatures.lock() {
             cache.clear();
         }
+        if let Ok(mut cache) = self.block_signatures.lock() {
+            cache.clear();
+        }
+    }
+}
+
+impl crate::proxy::session_registry::SessionStateStore for SignatureCache {
+    fn name(&self) -> &'static str {
+        "signature_cache"
+    }
+
+    fn evict_session(&self, session_id: &str) -> usize {
+        self.evict_session_signature(session_id)
     }
 }
 
@@ -329,6 +419,21 @@ mod tests {
         assert!(cache.get_session_signature("sid-other").is_none());
     }
 
+    #[test]
+    fn test_thinking_block_signature() {
+        let cache = SignatureCache::new();
+        let sig = "s".repeat(60);
+
+        cache.cache_thinking_block_signature("sid-1", "Let me think.\n", sig.clone());
+        // 客户端回传时首尾空白可能被裁剪
+        assert_eq!(cache.get_thinking_block_signature("sid-1", "Let me think."), Some(sig.clone()));
+        assert!(cache.get_thinking_block_signature("sid-1", "Other thought").is_none());
+        assert!(cache.get_thinking_block_signature("sid-2", "Let me think.").is_none());
+
+        assert_eq!(cache.evict_session_signature("sid-1"), 1);
+        assert!(cache.get_thinking_block_signature("sid-1", "Let me think.").is_none());
+    }
+
     #[test]
     fn test_clear_all_caches() {
         let cache = SignatureCache::new();
@@ -0,0 +1,334 @@
+// thoughtSignature 哨兵值能力学习
+// 缺少真实签名时，我们为 Gemini 思维模型注入 skip_thought_signature_validator 跳过校验，
+// 但部分模型版本会以 400 拒绝该哨兵值，硬编码的 is_google_cloud 判断无法覆盖。
+// 这里按上游模型 (信封中的 model 字段) 记录学习结果并持久化: 携带哨兵值的请求被 400，
+// 且错误指向的正是带哨兵值的 part 时记为不支持，之后该模型的转换不再注入哨兵值
+// (交由签名校验失败后的去思维重试路径处理)；携带哨兵值成功的请求记为支持。
+// 转换与学习两侧都以请求体中的 model 为键，避免联网等场景下映射模型与上游模型不一致。
+
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// 跳过签名校验的哨兵值
+pub const SENTINEL: &str = "skip_thought_signature_validator";
+/// 学习结果的持久化文件
+const STORE_FILE: &str = "signature_sentinel_capabilities.json";
+/// 记录的错误摘要长度上限
+const MAX_ERROR_CHARS: usize = 300;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SentinelCapability {
+    pub supported: bool,
+    pub updated_at: i64,
+    /// 最近一次拒绝的错误摘要
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+type CapabilityTable = BTreeMap<String, SentinelCapability>;
+
+static STORE: OnceLock<RwLock<CapabilityTable>> = OnceLock::new();
+/// 串行化落盘，文件写入不占用学习表的写锁
+static PERSIST_LOCK: Mutex<()> = Mutex::new(());
+
+/// 一次上游请求中哨兵值的位置
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentinelProbe {
+    /// 上游模型 (请求体 model 字段)
+    pub model: String,
+    /// 携带哨兵值的 (contents 下标, parts 下标)
+    parts: Vec<(usize, usize)>,
+}
+
+fn store_path() -> Option<PathBuf> {
+    // 测试中只使用内存表，不读写用户数据目录
+    if cfg!(test) {
+        return None;
+    }
+    crate::modules::account::get_data_dir()
+        .ok()
+        .map(|dir| dir.join(STORE_FILE))
+}
+
+fn store() -> &'static RwLock<CapabilityTable> {
+    STORE.get_or_init(|| {
+        let table = store_path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        RwLock::new(table)
+    })
+}
+
+fn persist() {
+    let Some(path) = store_path() else {
+        return;
+    };
+    let _guard = PERSIST_LOCK.lock();
+    let result = serde_json::to_string_pretty(&*store().read())
+        .map_err(|e| e.to_string())
+        .and_then(|content| std::fs::write(&path, content).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        tracing::warn!(
+            "[Signature-Sentinel] Failed to persist capability table: {}",
+            e
+        );
+    }
+}
+
+/// 该模型是否允许注入哨兵值 (未学习过的模型默认允许)
+pub fn sentinel_allowed(model: &str) -> bool {
+    store().read().get(model).map_or(true, |c| c.supported)
+}
+
+fn contents_mut(body: &mut Value) -> Option<&mut Vec<Value>> {
+    if body.get("request").is_some() {
+        body["request"].get_mut("contents")?.as_array_mut()
+    } else {
+        body.get_mut("contents")?.as_array_mut()
+    }
+}
+
+fn contents(body: &Value) -> Option<&Vec<Value>> {
+    body.get("request")
+        .unwrap_or(body)
+        .get("contents")?
+        .as_array()
+}
+
+fn is_sentinel_part(part: &Value) -> bool {
+    part.get("thoughtSignature").and_then(|v| v.as_str()) == Some(SENTINEL)
+}
+
+/// 发送前记录请求体中哨兵值的位置；未携带哨兵值时返回 None
+pub fn probe(body: &Value) -> Option<SentinelProbe> {
+    let parts: Vec<(usize, usize)> = contents(body)?
+        .iter()
+        .enumerate()
+        .flat_map(|(i, content)| {
+            content["parts"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .enumerate()
+                .filter(|(_, part)| is_sentinel_part(part))
+                .map(move |(j, _)| (i, j))
+        })
+        .collect();
+    if parts.is_empty() {
+        return None;
+    }
+    Some(SentinelProbe {
+        model: body["model"].as_str().unwrap_or_default().to_string(),
+        parts,
+    })
+}
+
+/// 转换完成后调用: 请求体 model 已学习为不支持时移除所有哨兵值，返回移除的数量
+pub fn strip_unsupported(body: &mut Value) -> usize {
+    let model = body["model"].as_str().unwrap_or_default().to_string();
+    if sentinel_allowed(&model) {
+        return 0;
+    }
+    let mut removed = 0;
+    for content in contents_mut(body).into_iter().flatten() {
+        for part in content["parts"].as_array_mut().into_iter().flatten() {
+            if is_sentinel_part(part) {
+                if let Some(obj) = part.as_object_mut() {
+                    obj.remove("thoughtSignature");
+                    removed += 1;
+                }
+            }
+        }
+    }
+    if removed > 0 {
+        tracing::debug!(
+            "[Signature-Sentinel] Omitted {} sentinel signatures for {}",
+            removed,
+            model
+        );
+    }
+    removed
+}
+
+/// 读取 `[n]` 或 `.n` 形式的下标
+fn take_index(s: &str) -> Option<(usize, &str)> {
+    let s = s.strip_prefix('[').or_else(|| s.strip_prefix('.'))?;
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let index = s[..end].parse().ok()?;
+    let rest = &s[end..];
+    Some((index, rest.strip_prefix(']').unwrap_or(rest)))
+}
+
+/// 错误信息中引用的 part 位置，如 `contents[1].parts[0].thought_signature`
+fn referenced_parts(error_text: &str) -> Vec<(usize, usize)> {
+    let mut refs = Vec::new();
+    let mut rest = error_text;
+    while let Some(pos) = rest.find("contents") {
+        rest = &rest[pos + "contents".len()..];
+        let Some((content, after)) = take_index(rest) else {
+            continue;
+        };
+        let Some(after) = after.strip_prefix(".parts") else {
+            continue;
+        };
+        if let Some((part, _)) = take_index(after) {
+            refs.push((content, part));
+        }
+    }
+    refs
+}
+
+/// 400 错误是否指向 thoughtSignature
+pub fn is_sentinel_rejection(error_text: &str) -> bool {
+    let lower = error_text.to_lowercase();
+    lower.contains("thought_signature")
+        || lower.contains("thoughtsignature")
+        || lower.contains("thought signature")
+}
+
+fn record(model: &str, supported: bool, last_error: Option<String>) {
+    {
+        let mut table = store().write();
+        if table.get(model).map(|c| c.supported) == Some(supported) && last_error.is_none() {
+            return;
+        }
+        table.insert(
+            model.to_string(),
+            SentinelCapability {
+                supported,
+                updated_at: chrono::Utc::now().timestamp(),
+                last_error,
+            },
+        );
+    }
+    persist();
+}
+
+/// 携带哨兵值的请求被 400 拒绝: 错误提及 thought signature 且指向带哨兵值的 part 时记为不支持，
+/// 返回是否已记录
+pub fn observe_rejection(probe: &SentinelProbe, error_text: &str) -> bool {
+    if !is_sentinel_rejection(error_text)
+        || !referenced_parts(error_text)
+            .iter()
+            .any(|p| probe.parts.contains(p))
+    {
+        return false;
+    }
+    tracing::warn!(
+        "[Signature-Sentinel] Model {} rejected {}; omitting it for future requests",
+        probe.model,
+        SENTINEL
+    );
+    record(
+        &probe.model,
+        false,
+        Some(error_text.chars().take(MAX_ERROR_CHARS).collect()),
+    );
+    true
+}
+
+/// 携带哨兵值的请求成功
+pub fn observe_success(model: &str) {
+    record(model, true, None);
+}
+
+/// 当前学习结果 (按模型名排序)
+pub fn snapshot() -> CapabilityTable {
+    store().read().clone()
+}
+
+/// 清除学习结果 (指定模型或全部)，返回清除的条目数
+pub fn reset(model: Option<&str>) -> usize {
+    let removed = {
+        let mut table = store().write();
+        match model {
+            Some(m) => usize::from(table.remove(m).is_some()),
+            None => std::mem::take(&mut *table).len(),
+        }
+    };
+    if removed > 0 {
+        persist();
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn body(model: &str) -> Value {
+        json!({
+            "model": model,
+            "request": { "contents": [
+                { "role": "user", "parts": [{ "text": "List files" }] },
+                { "role": "model", "parts": [
+                    { "text": "Sure" },
+                    { "functionCall": { "name": "ls", "args": {} }, "thoughtSignature": SENTINEL }
+                ]}
+            ]}
+        })
+    }
+
+    #[test]
+    fn test_rejection_is_learned_and_resettable() {
+        let model = "gemini-sentinel-test-reject";
+        let probe = probe(&body(model)).expect("sentinel present");
+        assert!(sentinel_allowed(model));
+
+        // 与签名无关的 400 不影响学习结果
+        assert!(!observe_rejection(&probe, "Request contains an invalid argument."));
+        // 签名错误但指向的不是哨兵值所在的 part (真实签名失效)
+        assert!(!observe_rejection(
+            &probe,
+            "Invalid value at 'contents[1].parts[0].thought_signature'"
+        ));
+        assert!(!observe_rejection(&probe, "Corrupted thought signature."));
+        assert!(sentinel_allowed(model));
+
+        assert!(observe_rejection(
+            &probe,
+            "Invalid value at 'contents[1].parts[1].thought_signature'"
+        ));
+        assert!(!sentinel_allowed(model));
+        assert!(snapshot()[model].last_error.is_some());
+
+        assert_eq!(reset(Some(model)), 1);
+        assert!(sentinel_allowed(model));
+
+        observe_success(model);
+        assert!(snapshot()[model].supported);
+        reset(Some(model));
+    }
+
+    #[test]
+    fn test_strip_unsupported_keys_on_body_model() {
+        let model = "gemini-sentinel-test-strip";
+        let mut allowed = body(model);
+        assert_eq!(strip_unsupported(&mut allowed), 0);
+        assert_eq!(probe(&allowed).unwrap().parts, vec![(1, 1)]);
+
+        record(model, false, Some("rejected".to_string()));
+        let mut stripped = body(model);
+        assert_eq!(strip_unsupported(&mut stripped), 1);
+        assert!(probe(&stripped).is_none());
+        // 其他模型不受影响
+        assert_eq!(strip_unsupported(&mut body("gemini-sentinel-test-other")), 0);
+        reset(Some(model));
+    }
+
+    #[test]
+    fn test_referenced_parts_formats() {
+        assert_eq!(
+            referenced_parts("Invalid value at 'request.contents[3].parts[12].thought_signature'"),
+            vec![(3, 12)]
+        );
+        assert_eq!(referenced_parts("contents.2.parts.0.thoughtSignature"), vec![(2, 0)]);
+        assert!(referenced_parts("thought signature is invalid").is_empty());
+    }
+}
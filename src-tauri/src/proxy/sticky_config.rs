@@ -25,6 +25,8 @@ pub struct StickySessionConfig {
     pub mode: SchedulingMode,
     /// 缓存优先模式下的最大等待时间 (秒)
     pub max_wait_seconds: u64,
+    /// [NEW] 最低健康分 (0.0 - 1.0)，低于该值的账号不参与选号，直到健康分恢复 (默认 0.0 即不限制)
+    pub min_health_score: f32,
 }
 
 impl Default for StickySessionConfig {
@@ -32,6 +34,7 @@ impl Default for StickySessionConfig {
         Self {
             mode: SchedulingMode::Balance,
             max_wait_seconds: 60,
+            min_health_score: 0.0,
         }
     }
 }
@@ -0,0 +1,416 @@
+// 流式会话录制 / 回放 (调试用)
+// 录制: 将上游 Gemini SSE 原始字节按完整行分块记录 (签名脱敏)，连同转换参数在流结束后写入调试日志目录
+//       (遵循 encrypt_at_rest)。debug_logging.record_streams 开启时录制所有 Claude 流式请求；
+//       调试日志开启时也可通过 x-abv-record-stream 请求头录制单个请求。
+// 回放: 读取录制文件，将分块重新送入 create_claude_sse_stream，无需请求上游即可复现客户端输出。
+// 中断续写 (resumer) 发起的续写请求不在录制范围内。
+
+use axum::http::HeaderMap;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::proxy::config::DebugLoggingConfig;
+
+/// 单个请求开启录制的请求头
+pub const RECORD_STREAM_HEADER: &str = "x-abv-record-stream";
+/// 录制文件名前缀
+pub const RECORDING_PREFIX: &str = "stream_recording";
+/// 脱敏后的签名占位值
+pub const REDACTED_SIGNATURE: &str = "[redacted]";
+
+/// 需要脱敏的签名字段 (上游 Gemini 与 Claude 客户端输出)
+const SIGNATURE_KEYS: &[&str] = &["thoughtSignature", "signature"];
+
+type UpstreamByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>;
+
+/// 回放时复现客户端输出所需的转换参数
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplayContext {
+    #[serde(default)]
+    pub served_model: Option<String>,
+    #[serde(default)]
+    pub requested_model: Option<String>,
+    #[serde(default)]
+    pub scaling_enabled: bool,
+    #[serde(default)]
+    pub context_limit: u32,
+    #[serde(default)]
+    pub estimated_prompt_tokens: Option<u32>,
+    #[serde(default)]
+    pub message_count: usize,
+    /// 内置客户端适配器名称 (自定义配置档无法回放，按默认行为处理)
+    #[serde(default)]
+    pub client_adapter: Option<String>,
+    #[serde(default)]
+    pub defer_message_start: bool,
+    #[serde(default)]
+    pub tool_schemas: HashMap<String, Value>,
+    /// assistant 预填充文本
+    #[serde(default)]
+    pub prefill: Option<String>,
+    /// 客户端 stop_sequences
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
+}
+
+/// 录制文件内容
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamRecording {
+    pub kind: String,
+    pub trace_id: String,
+    pub context: ReplayContext,
+    /// 上游 SSE 分块 (均以完整行结束，签名已脱敏)
+    pub chunks: Vec<String>,
+}
+
+impl StreamRecording {
+    pub fn new(trace_id: String, context: ReplayContext) -> Self {
+        Self {
+            kind: RECORDING_PREFIX.to_string(),
+            trace_id,
+            context,
+            chunks: Vec::new(),
+        }
+    }
+}
+
+/// 是否录制当前请求：全局开关，或调试日志开启时由请求头显式要求
+pub fn should_record(cfg: &DebugLoggingConfig, headers: &HeaderMap) -> bool {
+    if cfg.record_streams {
+        return true;
+    }
+    cfg.enabled
+        && headers
+            .get(RECORD_STREAM_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map_or(false, |v| {
+                matches!(
+                    v.trim().to_lowercase().as_str(),
+                    "" | "1" | "true" | "on" | "yes"
+                )
+            })
+}
+
+/// 将文本中所有签名字段的值替换为占位值
+pub fn redact_signatures(text: &str) -> String {
+    let mut result = text.to_string();
+    for key in SIGNATURE_KEYS {
+        result = redact_key(&result, key);
+    }
+    result
+}
+
+fn redact_key(text: &str, key: &str) -> String {
+    let needle = format!("\"{}\"", key);
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find(&needle) {
+        let after_key = pos + needle.len();
+        out.push_str(&rest[..after_key]);
+        rest = &rest[after_key..];
+
+        // 仅处理 "key": "value" 形式，其它情况 (如作为字符串值出现) 原样保留
+        let trimmed = rest.trim_start();
+        let Some(value) = trimmed.strip_prefix(':').map(str::trim_start) else {
+            continue;
+        };
+        let Some(body) = value.strip_prefix('"') else {
+            continue;
+        };
+        let Some(end) = find_string_end(body) else {
+            continue;
+        };
+        let prefix_len = rest.len() - body.len();
+        out.push_str(&rest[..prefix_len]);
+        out.push_str(REDACTED_SIGNATURE);
+        rest = &body[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// JSON 字符串内容中未转义的结束引号位置
+fn find_string_end(body: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in body.char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Some(i),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// 按完整行切分上游字节 (避免多字节字符或签名被网络分块截断)，并在入录前脱敏
+#[derive(Default)]
+struct Recorder {
+    pending: Vec<u8>,
+    chunks: Vec<String>,
+}
+
+impl Recorder {
+    fn push(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+        if let Some(pos) = self.pending.iter().rposition(|b| *b == b'\n') {
+            let complete: Vec<u8> = self.pending.drain(..=pos).collect();
+            self.chunks
+                .push(redact_signatures(&String::from_utf8_lossy(&complete)));
+        }
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        if !self.pending.is_empty() {
+            let rest = std::mem::take(&mut self.pending);
+            self.chunks
+                .push(redact_signatures(&String::from_utf8_lossy(&rest)));
+        }
+        self.chunks
+    }
+}
+
+fn recording_tap<F, Fut>(
+    stream: UpstreamByteStream,
+    mut recording: StreamRecording,
+    on_finish: F,
+) -> UpstreamByteStream
+where
+    F: FnOnce(StreamRecording) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Box::pin(async_stream::stream! {
+        let mut recorder = Recorder::default();
+        let mut inner = stream;
+        while let Some(item) = inner.next().await {
+            if let Ok(bytes) = &item {
+                recorder.push(bytes);
+            }
+            yield item;
+        }
+        recording.chunks = recorder.finish();
+        on_finish(recording).await;
+    })
+}
+
+/// 录制 sink: 原样转发上游流，流结束后将录制写入调试日志目录
+pub fn record_stream(
+    stream: UpstreamByteStream,
+    cfg: DebugLoggingConfig,
+    trace_id: String,
+    context: ReplayContext,
+) -> UpstreamByteStream {
+    let recording = StreamRecording::new(trace_id, context);
+    recording_tap(stream, recording, move |recording| async move {
+        match serde_json::to_value(&recording) {
+            Ok(payload) => {
+                crate::proxy::debug_logger::write_capture(
+                    &cfg,
+                    Some(&recording.trace_id),
+                    RECORDING_PREFIX,
+                    &payload,
+                )
+                .await;
+                tracing::info!(
+                    "[Stream-Record] Recorded {} upstream chunks for trace {}",
+                    recording.chunks.len(),
+                    recording.trace_id
+                );
+            }
+            Err(e) => tracing::warn!("[Stream-Record] Failed to serialize recording: {}", e),
+        }
+    })
+}
+
+/// 回放 source: 按录制分块重新产出上游字节流
+pub fn replay_source(recording: &StreamRecording) -> UpstreamByteStream {
+    let items: Vec<Result<Bytes, reqwest::Error>> = recording
+        .chunks
+        .iter()
+        .map(|c| Ok(Bytes::from(c.clone())))
+        .collect();
+    Box::pin(futures::stream::iter(items))
+}
+
+/// 通过 create_claude_sse_stream 回放录制，返回客户端收到的完整 SSE 输出
+pub async fn replay_claude_sse(recording: &StreamRecording) -> Result<String, String> {
+    convert_to_claude_sse(
+        replay_source(recording),
+        &recording.trace_id,
+        &recording.context,
+    )
+    .await
+}
+
+async fn convert_to_claude_sse(
+    upstream: UpstreamByteStream,
+    trace_id: &str,
+    ctx: &ReplayContext,
+) -> Result<String, String> {
+    let client_adapter = ctx
+        .client_adapter
+        .as_deref()
+        .and_then(crate::proxy::common::client_adapter::find_builtin_adapter);
+    let mut stream = crate::proxy::mappers::claude::create_claude_sse_stream(
+        upstream,
+        trace_id.to_string(),
+        "replay".to_string(),
+        None, // 不写入会话签名缓存
+        ctx.scaling_enabled,
+        ctx.context_limit,
+        ctx.estimated_prompt_tokens,
+        ctx.message_count,
+        client_adapter,
+        ctx.served_model.clone(),
+        ctx.requested_model.clone(),
+        ctx.defer_message_start,
+        ctx.tool_schemas.clone(),
+        None, // 回放不发起续写请求
+        ctx.prefill.clone(),
+        ctx.stop_sequences.clone(),
+        None, // 回放不发起追问请求
+    );
+
+    let mut output = String::new();
+    while let Some(item) = stream.next().await {
+        let bytes = item?;
+        output.push_str(&String::from_utf8_lossy(&bytes));
+    }
+    Ok(output)
+}
+
+/// 从调试日志目录读取录制文件并回放
+pub async fn replay_file(cfg: &DebugLoggingConfig, path: &str) -> Result<String, String> {
+    let cfg_owned = cfg.clone();
+    let path = path.to_string();
+    let content = tokio::task::spawn_blocking(move || {
+        crate::proxy::debug_logger::read_capture(&cfg_owned, &path)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    let recording: StreamRecording =
+        serde_json::from_str(&content).map_err(|e| format!("Invalid stream recording: {}", e))?;
+    if recording.kind != RECORDING_PREFIX {
+        return Err(format!("Not a stream recording (kind: {})", recording.kind));
+    }
+    replay_claude_sse(&recording).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use base64::Engine;
+
+    fn upstream_chunks(signature: &str) -> Vec<Vec<u8>> {
+        let thinking = serde_json::json!({
+            "response": {
+                "candidates": [{ "content": { "role": "model", "parts": [
+                    { "text": "Let me think.", "thought": true, "thoughtSignature": signature }
+                ]}}],
+                "modelVersion": "gemini-3-pro-preview",
+                "responseId": "resp_record_replay"
+            }
+        });
+        let text = serde_json::json!({
+            "response": {
+                "candidates": [{ "content": { "role": "model", "parts": [{ "text": "你好，world" }] }}],
+                "responseId": "resp_record_replay"
+            }
+        });
+        let finish = serde_json::json!({
+            "response": {
+                "candidates": [{ "content": { "role": "model", "parts": [{ "text": "!" }] }, "finishReason": "STOP" }],
+                "usageMetadata": { "promptTokenCount": 12, "candidatesTokenCount": 5, "totalTokenCount": 17 },
+                "responseId": "resp_record_replay"
+            }
+        });
+        let raw = format!(
+            "data: {}\n\ndata: {}\n\ndata: {}\n\n",
+            thinking, text, finish
+        );
+        // 模拟网络分块: 签名与多字节字符都被拆到两个块中
+        let bytes = raw.as_bytes();
+        let sig_cut = raw.find(signature).unwrap() + 10;
+        let utf8_cut = raw.find("你好").unwrap() + 1;
+        vec![
+            bytes[..sig_cut].to_vec(),
+            bytes[sig_cut..utf8_cut].to_vec(),
+            bytes[utf8_cut..].to_vec(),
+        ]
+    }
+
+    fn context() -> ReplayContext {
+        ReplayContext {
+            served_model: Some("gemini-3-pro-high".to_string()),
+            requested_model: Some("claude-opus-4-5-thinking".to_string()),
+            context_limit: 1_000_000,
+            message_count: 1,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay_produces_identical_output() {
+        let signature = base64::engine::general_purpose::STANDARD
+            .encode("sig-record-replay-secret-0123456789-abcdefghijklmnopqrstuvwxyz");
+        let items: Vec<Result<Bytes, reqwest::Error>> = upstream_chunks(&signature)
+            .into_iter()
+            .map(|c| Ok(Bytes::from(c)))
+            .collect();
+
+        // 1. 实时请求: 上游流经录制 sink 后送入转换器
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let recorded = recording_tap(
+            Box::pin(futures::stream::iter(items)),
+            StreamRecording::new("trace_record".to_string(), context()),
+            move |recording| async move {
+                let _ = tx.send(recording);
+            },
+        );
+        let live = convert_to_claude_sse(recorded, "trace_record", &context())
+            .await
+            .unwrap();
+        assert!(live.contains("你好，world"));
+        assert!(live.contains("signature_delta"));
+
+        // 2. 录制内容已脱敏，并可经文件格式往返
+        let recording = rx.await.unwrap();
+        let serialized = serde_json::to_string(&recording).unwrap();
+        assert!(!serialized.contains(&signature));
+        assert!(!serialized.contains("sig-record-replay-secret"));
+        assert!(serialized.contains(REDACTED_SIGNATURE));
+        let recording: StreamRecording = serde_json::from_str(&serialized).unwrap();
+
+        // 3. 回放不请求上游，客户端输出与实时请求一致 (签名除外，均为脱敏占位)
+        let replayed = replay_claude_sse(&recording).await.unwrap();
+        assert_eq!(replayed, redact_signatures(&live));
+        assert_eq!(replay_claude_sse(&recording).await.unwrap(), replayed);
+    }
+
+    #[test]
+    fn test_redaction_and_gating() {
+        let line = r#"data: {"parts":[{"thoughtSignature" : "abc\"def","text":"signature"}],"signature":"xyz"}"#;
+        assert_eq!(
+            redact_signatures(line),
+            r#"data: {"parts":[{"thoughtSignature" : "[redacted]","text":"signature"}],"signature":"[redacted]"}"#
+        );
+
+        let mut headers = HeaderMap::new();
+        let mut cfg = DebugLoggingConfig::default();
+        assert!(!should_record(&cfg, &headers));
+        headers.insert(RECORD_STREAM_HEADER, HeaderValue::from_static("1"));
+        // 请求头仅在调试日志开启时生效
+        assert!(!should_record(&cfg, &headers));
+        cfg.enabled = true;
+        assert!(should_record(&cfg, &headers));
+        headers.insert(RECORD_STREAM_HEADER, HeaderValue::from_static("off"));
+        assert!(!should_record(&cfg, &headers));
+        cfg.record_streams = true;
+        assert!(should_record(&cfg, &HeaderMap::new()));
+    }
+}
@@ -0,0 +1,172 @@
+// 每账号并发流计数
+// 同一账号同时承载过多长流会拖慢所有请求。handler 在拿到账号后获取许可 (StreamPermit)，
+// 许可随响应流一起释放；TokenManager 选号时优先跳过已达上限的账号，
+// 全部饱和时由 acquire() 排队等待空闲名额；等待有上限，超时后返回错误 (不超限放行)，由 handler 回复 429。
+
+use dashmap::DashMap;
+use futures::{Stream, StreamExt};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// 所有账号饱和时排队等待的最长时间
+pub const STREAM_SLOT_WAIT: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+pub struct StreamSlots {
+    active: DashMap<String, usize>,
+    released: Notify,
+}
+
+impl StreamSlots {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 账号当前活跃的流数量
+    pub fn active(&self, account_id: &str) -> usize {
+        self.active.get(account_id).map(|v| *v).unwrap_or(0)
+    }
+
+    /// 账号是否已达上限 (max 为 0 表示不限制)
+    pub fn is_saturated(&self, account_id: &str, max: usize) -> bool {
+        max > 0 && self.active(account_id) >= max
+    }
+
+    /// 未达上限时占用一个名额
+    pub fn try_acquire(self: &Arc<Self>, account_id: &str, max: usize) -> Option<StreamPermit> {
+        let mut count = self.active.entry(account_id.to_string()).or_insert(0);
+        if max > 0 && *count >= max {
+            return None;
+        }
+        *count += 1;
+        drop(count);
+        Some(StreamPermit {
+            slots: Arc::clone(self),
+            account_id: account_id.to_string(),
+        })
+    }
+
+    /// 获取名额，已达上限时排队等待其他流结束，最多等待 wait；超时返回 None
+    pub async fn acquire(self: &Arc<Self>, account_id: &str, max: usize, wait: Duration) -> Option<StreamPermit> {
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            // 先注册通知再检查，避免检查与等待之间的释放被错过
+            let notified = self.released.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if let Some(permit) = self.try_acquire(account_id, max) {
+                return Some(permit);
+            }
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                tracing::warn!(
+                    "[Stream-Limit] Account {} still at {} concurrent streams after {:?}, rejecting",
+                    account_id,
+                    max,
+                    wait
+                );
+                return None;
+            }
+        }
+    }
+
+    fn release(&self, account_id: &str) {
+        self.active.remove_if_mut(account_id, |_, count| {
+            *count = count.saturating_sub(1);
+            *count == 0
+        });
+        self.released.notify_waiters();
+    }
+}
+
+/// 并发流名额 (drop 时释放)
+pub struct StreamPermit {
+    slots: Arc<StreamSlots>,
+    account_id: String,
+}
+
+impl StreamPermit {
+    /// 让名额跟随响应流的生命周期 (流结束或客户端断开时释放)
+    pub fn attach<S>(self, stream: S) -> impl Stream<Item = S::Item> + Send + 'static
+    where
+        S: Stream + Send + 'static,
+    {
+        stream.map(move |item| {
+            let _permit = &self;
+            item
+        })
+    }
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        self.slots.release(&self.account_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_bounds_concurrent_streams_per_account() {
+        let slots = Arc::new(StreamSlots::new());
+        let first = slots.try_acquire("acc_a", 2).unwrap();
+        let _second = slots.try_acquire("acc_a", 2).unwrap();
+        assert!(slots.try_acquire("acc_a", 2).is_none());
+        assert!(slots.is_saturated("acc_a", 2));
+        // 其他账号不受影响
+        assert!(slots.try_acquire("acc_b", 2).is_some());
+
+        drop(first);
+        assert_eq!(slots.active("acc_a"), 1);
+        assert!(slots.try_acquire("acc_a", 2).is_some());
+
+        // 0 表示不限制
+        assert!(!slots.is_saturated("acc_a", 0));
+    }
+
+    #[tokio::test]
+    async fn test_acquire_queues_until_slot_released() {
+        let slots = Arc::new(StreamSlots::new());
+        let held = slots.try_acquire("acc_a", 1).unwrap();
+
+        let waiter = {
+            let slots = slots.clone();
+            tokio::spawn(async move { slots.acquire("acc_a", 1, Duration::from_secs(5)).await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+
+        drop(held);
+        let permit = tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap()
+            .expect("queued request gets the released slot");
+        assert_eq!(slots.active("acc_a"), 1);
+        drop(permit);
+        assert_eq!(slots.active("acc_a"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_gives_up_after_wait_without_exceeding_limit() {
+        let slots = Arc::new(StreamSlots::new());
+        let _held = slots.try_acquire("acc_a", 1).unwrap();
+
+        assert!(slots.acquire("acc_a", 1, Duration::from_millis(50)).await.is_none());
+        assert_eq!(slots.active("acc_a"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_permit_released_when_stream_dropped() {
+        let slots = Arc::new(StreamSlots::new());
+        let permit = slots.try_acquire("acc_a", 4).unwrap();
+        let mut stream = Box::pin(permit.attach(futures::stream::iter(vec![1, 2])));
+        assert_eq!(stream.next().await, Some(1));
+        assert_eq!(slots.active("acc_a"), 1);
+        drop(stream);
+        assert_eq!(slots.active("acc_a"), 0);
+    }
+}
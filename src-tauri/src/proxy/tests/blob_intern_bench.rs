@@ -0,0 +1,81 @@
+//! Base64 驻留基准测试
+//! 模拟 Claude handler 的请求流水线 (原始 body 副本 → ClaudeRequest → 重试克隆 → Gemini body → 序列化)，
+//! 对比驻留前后各阶段同时持有的大字符串字节数
+
+#[cfg(test)]
+mod bench {
+    use crate::proxy::common::blob_intern::{BlobTable, BLOB_INTERN_THRESHOLD};
+    use crate::proxy::mappers::claude::{transform_claude_request_in, ClaudeRequest};
+    use crate::proxy::mappers::common_utils::EnvelopeParams;
+    use serde_json::{json, Value};
+
+    const IMAGE_SIZE: usize = 5 * 1024 * 1024;
+
+    fn three_image_request() -> Value {
+        let content: Vec<Value> = (0..3u8)
+            .map(|i| {
+                let data: String = std::iter::repeat((b'A' + i) as char).take(IMAGE_SIZE).collect();
+                json!({
+                    "type": "image",
+                    "source": { "type": "base64", "media_type": "image/jpeg", "data": data }
+                })
+            })
+            .chain(std::iter::once(json!({ "type": "text", "text": "compare these photos" })))
+            .collect();
+        json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "messages": [{ "role": "user", "content": content }]
+        })
+    }
+
+    /// 统计 Value 中所有字符串占用的字节数
+    fn string_bytes(value: &Value) -> usize {
+        match value {
+            Value::String(s) => s.len(),
+            Value::Array(items) => items.iter().map(string_bytes).sum(),
+            Value::Object(map) => map.iter().map(|(k, v)| k.len() + string_bytes(v)).sum(),
+            _ => 0,
+        }
+    }
+
+    /// 按 handler 的顺序执行流水线，返回 (同时存活的载荷字节数, 最终请求体)
+    fn run_pipeline(mut body: Value, intern: bool) -> (usize, Vec<u8>) {
+        let blobs = if intern {
+            BlobTable::intern_claude_request(&mut body, BLOB_INTERN_THRESHOLD)
+        } else {
+            BlobTable::default()
+        };
+
+        let original_body = body.clone();
+        let request: ClaudeRequest = serde_json::from_value(body.clone()).unwrap();
+        let request_for_body = request.clone();
+        let request_with_mapped = request_for_body.clone();
+        let mut gemini_body =
+            transform_claude_request_in(&request_with_mapped, "bench-project", false, &EnvelopeParams::default(), &Default::default())
+                .unwrap();
+        gemini_body["requestId"] = json!("agent-bench");
+        let payload = blobs.to_vec(&gemini_body).unwrap();
+
+        let request_bytes = string_bytes(&serde_json::to_value(&request).unwrap());
+        let live = string_bytes(&body)
+            + string_bytes(&original_body)
+            + request_bytes * 3 // request / request_for_body / request_with_mapped
+            + string_bytes(&gemini_body)
+            + payload.len()
+            + blobs.total_bytes();
+        (live, payload)
+    }
+
+    #[test]
+    fn bench_three_5mb_images() {
+        let (baseline_live, baseline_payload) = run_pipeline(three_image_request(), false);
+        let (interned_live, interned_payload) = run_pipeline(three_image_request(), true);
+
+        // 线上字节完全一致
+        assert_eq!(baseline_payload, interned_payload);
+        // 驻留后只剩共享表 + 最终请求体两份
+        assert!(interned_live < 3 * IMAGE_SIZE * 3);
+        assert!(baseline_live > interned_live * 3);
+    }
+}
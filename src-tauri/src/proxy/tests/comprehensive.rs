@@ -4,6 +4,7 @@ mod tests {
         ClaudeRequest, Message, MessageContent, ContentBlock, ThinkingConfig
     };
     use crate::proxy::mappers::claude::request::transform_claude_request_in;
+    use crate::proxy::mappers::common_utils::EnvelopeParams;
     use crate::proxy::mappers::claude::thinking_utils::{analyze_conversation_state, close_tool_loop_for_thinking};
     use serde_json::json;
 
@@ -39,11 +40,13 @@ mod tests {
             output_config: None,
             size: None,
             quality: None,
+            stop_sequences: None,
+            mcp_servers: None,
         };
 
         // 2. 执行转换
         // 如果修复生效，这里应该成功返回，且 thinkingConfig 被保留
-        let result = transform_claude_request_in(&req, "test-project", false);
+        let result = transform_claude_request_in(&req, "test-project", false, &EnvelopeParams::default(), &Default::default());
         assert!(result.is_ok(), "First thinking request should be allowed");
 
         let body = result.unwrap();
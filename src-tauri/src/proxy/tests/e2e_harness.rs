@@ -0,0 +1,620 @@
+//! 端到端测试工具：模拟 v1internal 上游 + 指向它的真实反代服务器
+//!
+//! MockUpstream 在随机端口上按脚本依次应答 generateContent / streamGenerateContent 请求
+//! (SSE 事件可带延迟、可在中途断开)，其余 v1internal 调用返回 `{}`；
+//! ProxyHarness 写入临时账号、启动 AxumServer 并通过 upstream_base_url 覆盖指向 MockUpstream。
+
+use crate::proxy::config::ProxyConfig;
+use crate::proxy::{AxumServer, ProxySecurityConfig, TokenManager};
+use axum::body::{Body, Bytes};
+use axum::extract::OriginalUri;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use axum::Router;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+// ===== 脚本化的上游响应 =====
+
+#[derive(Debug, Clone)]
+pub struct ScriptedResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    /// SSE 事件 (发送前的延迟, data JSON)
+    events: Vec<(Duration, Value)>,
+    /// 非 SSE 响应体
+    body: Option<Value>,
+    /// 发送完事件后以连接错误结束 (模拟中途断开)
+    disconnect: bool,
+}
+
+impl ScriptedResponse {
+    /// 200 SSE 响应
+    pub fn sse(events: Vec<Value>) -> Self {
+        Self {
+            status: 200,
+            headers: Vec::new(),
+            events: events.into_iter().map(|e| (Duration::ZERO, e)).collect(),
+            body: None,
+            disconnect: false,
+        }
+    }
+
+    /// 错误响应 (JSON 响应体)
+    pub fn error(status: u16, body: Value) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            events: Vec::new(),
+            body: Some(body),
+            disconnect: false,
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// 每个 SSE 事件发送前等待指定时长
+    pub fn with_event_delay(mut self, delay: Duration) -> Self {
+        for (d, _) in &mut self.events {
+            *d = delay;
+        }
+        self
+    }
+
+    /// 发送完所有事件后断开连接而不是正常结束
+    pub fn then_disconnect(mut self) -> Self {
+        self.disconnect = true;
+        self
+    }
+}
+
+/// v1internal 流式文本分块
+pub fn text_chunk(text: &str, finish: bool) -> Value {
+    let mut candidate = json!({ "content": { "role": "model", "parts": [{ "text": text }] } });
+    if finish {
+        candidate["finishReason"] = json!("STOP");
+    }
+    json!({
+        "response": {
+            "candidates": [candidate],
+            "usageMetadata": { "promptTokenCount": 12, "candidatesTokenCount": 8, "totalTokenCount": 20 },
+            "modelVersion": "gemini-3-flash",
+            "responseId": "resp_e2e"
+        }
+    })
+}
+
+/// v1internal 流式思考分块
+pub fn thought_chunk(text: &str) -> Value {
+    json!({
+        "response": {
+            "candidates": [{ "content": { "role": "model", "parts": [{ "text": text, "thought": true }] } }],
+            "modelVersion": "gemini-3-flash",
+            "responseId": "resp_e2e"
+        }
+    })
+}
+
+/// 429 配额耗尽响应 (带 Retry-After，避免触发实时配额查询)
+pub fn quota_exhausted(retry_after_secs: u64) -> ScriptedResponse {
+    ScriptedResponse::error(
+        429,
+        json!({ "error": { "code": 429, "status": "RESOURCE_EXHAUSTED", "message": "QUOTA_EXHAUSTED" } }),
+    )
+    .with_header("Retry-After", &retry_after_secs.to_string())
+}
+
+// ===== 模拟上游 =====
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub path: String,
+    /// Authorization 中的 Bearer token
+    pub token: String,
+    pub body: Value,
+}
+
+impl RecordedRequest {
+    pub fn is_generate(&self) -> bool {
+        self.path.contains(":generateContent") || self.path.contains(":streamGenerateContent")
+    }
+}
+
+#[derive(Clone)]
+pub struct MockUpstream {
+    addr: SocketAddr,
+    script: Arc<Mutex<VecDeque<ScriptedResponse>>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockUpstream {
+    pub async fn start() -> Self {
+        let script: Arc<Mutex<VecDeque<ScriptedResponse>>> = Arc::default();
+        let requests: Arc<Mutex<Vec<RecordedRequest>>> = Arc::default();
+
+        let (script_ref, requests_ref) = (script.clone(), requests.clone());
+        let app = Router::new().fallback(
+            move |OriginalUri(uri): OriginalUri, headers: HeaderMap, body: Bytes| {
+                let (script, requests) = (script_ref.clone(), requests_ref.clone());
+                async move {
+                    let recorded = RecordedRequest {
+                        path: uri.to_string(),
+                        token: headers
+                            .get("authorization")
+                            .and_then(|v| v.to_str().ok())
+                            .and_then(|v| v.strip_prefix("Bearer "))
+                            .unwrap_or_default()
+                            .to_string(),
+                        body: serde_json::from_slice(&body).unwrap_or(Value::Null),
+                    };
+                    let is_generate = recorded.is_generate();
+                    requests.lock().push(recorded);
+
+                    if !is_generate {
+                        return json_response(200, json!({}));
+                    }
+                    match script.lock().pop_front() {
+                        Some(scripted) => render(scripted),
+                        None => json_response(
+                            500,
+                            json!({ "error": { "code": 500, "message": "mock upstream: unscripted request" } }),
+                        ),
+                    }
+                }
+            },
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        Self {
+            addr,
+            script,
+            requests,
+        }
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}/v1internal", self.addr)
+    }
+
+    pub fn enqueue(&self, response: ScriptedResponse) {
+        self.script.lock().push_back(response);
+    }
+
+    /// 收到的 generateContent / streamGenerateContent 请求
+    pub fn generate_requests(&self) -> Vec<RecordedRequest> {
+        self.requests
+            .lock()
+            .iter()
+            .filter(|r| r.is_generate())
+            .cloned()
+            .collect()
+    }
+}
+
+fn json_response(status: u16, body: Value) -> Response {
+    Response::builder()
+        .status(StatusCode::from_u16(status).unwrap())
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn render(scripted: ScriptedResponse) -> Response {
+    let mut builder = Response::builder().status(StatusCode::from_u16(scripted.status).unwrap());
+    for (name, value) in &scripted.headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    if let Some(body) = scripted.body {
+        return builder
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+    }
+
+    let (events, disconnect) = (scripted.events, scripted.disconnect);
+    let stream = async_stream::stream! {
+        for (delay, data) in events {
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            yield Ok::<Bytes, std::io::Error>(Bytes::from(format!("data: {}\n\n", data)));
+        }
+        if disconnect {
+            yield Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "scripted disconnect"));
+        }
+    };
+    builder
+        .header("content-type", "text/event-stream")
+        .body(Body::from_stream(stream))
+        .unwrap()
+}
+
+// ===== 反代服务器 =====
+
+/// 配额保护监控的模型：仅此分组会因低配额被保护，不影响其他测试使用的 flash 账号
+pub const PROTECTED_MODEL: &str = "gemini-3-pro-high";
+
+#[derive(Debug, Clone)]
+pub struct TestAccount {
+    pub id: &'static str,
+    pub email: &'static str,
+    /// PROTECTED_MODEL 的剩余配额百分比 (其他模型固定为 100%)
+    pub protected_model_quota: i64,
+}
+
+impl TestAccount {
+    pub fn new(id: &'static str, email: &'static str) -> Self {
+        Self {
+            id,
+            email,
+            protected_model_quota: 100,
+        }
+    }
+
+    pub fn with_quota(mut self, percentage: i64) -> Self {
+        self.protected_model_quota = percentage;
+        self
+    }
+
+    pub fn access_token(&self) -> String {
+        format!("atk-{}", self.id)
+    }
+}
+
+/// 进程级共享的数据目录 (proxy_db / gui_config.json 等)，其中的 gui_config 开启针对 PROTECTED_MODEL 的配额保护
+/// 调用方需已设置 ABV_DATA_DIR 指向它
+fn shared_data_dir() -> &'static PathBuf {
+    static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+    DATA_DIR.get_or_init(|| {
+        std::env::temp_dir().join(format!("antigravity-e2e-{}", uuid::Uuid::new_v4()))
+    })
+}
+
+fn write_shared_config() {
+    static WRITTEN: OnceLock<()> = OnceLock::new();
+    WRITTEN.get_or_init(|| {
+        std::fs::create_dir_all(shared_data_dir()).unwrap();
+        let mut config = crate::models::AppConfig::new();
+        config.quota_protection.enabled = true;
+        config.quota_protection.threshold_percentage = 10;
+        config.quota_protection.monitored_models = vec![PROTECTED_MODEL.to_string()];
+        crate::modules::config::save_app_config(&config).unwrap();
+    });
+}
+
+/// 修改进程级全局状态 (ABV_DATA_DIR、流恢复/思考续写开关、账号轮换次数、功能开关) 的测试共用此锁，逐个串行执行
+/// 读写数据目录的单元测试 (user_token_db、security_db、账号文件) 通过 IsolatedDataDir 持有此锁
+pub(crate) static E2E_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// 独立的临时数据目录: 持有 E2E_LOCK 期间将 ABV_DATA_DIR 指向新的临时目录，Drop 时恢复原值并删除目录
+/// 读写数据目录 (user_token_db、账号文件等) 的单元测试使用，避免触碰真实数据目录或与 e2e 场景互相覆盖
+pub(crate) struct IsolatedDataDir {
+    path: PathBuf,
+    previous: Option<std::ffi::OsString>,
+    _serial: tokio::sync::MutexGuard<'static, ()>,
+}
+
+impl IsolatedDataDir {
+    pub(crate) async fn acquire() -> Self {
+        Self::with_guard(E2E_LOCK.lock().await)
+    }
+
+    /// 同步测试使用 (不可在 tokio 运行时内调用)
+    pub(crate) fn acquire_blocking() -> Self {
+        Self::with_guard(E2E_LOCK.blocking_lock())
+    }
+
+    fn with_guard(serial: tokio::sync::MutexGuard<'static, ()>) -> Self {
+        let path = std::env::temp_dir().join(format!("antigravity-data-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        let previous = std::env::var_os("ABV_DATA_DIR");
+        std::env::set_var("ABV_DATA_DIR", &path);
+        Self {
+            path,
+            previous,
+            _serial: serial,
+        }
+    }
+
+    pub(crate) fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+impl Drop for IsolatedDataDir {
+    fn drop(&mut self) {
+        match &self.previous {
+            Some(dir) => std::env::set_var("ABV_DATA_DIR", dir),
+            None => std::env::remove_var("ABV_DATA_DIR"),
+        }
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// 场景开始前的全局状态，Drop 时恢复
+struct SavedGlobals {
+    data_dir: Option<std::ffi::OsString>,
+    stream_resumption: bool,
+    thinking_nudge: bool,
+    max_account_rotations: usize,
+    feature_flags: Vec<crate::proxy::config::FeatureFlagConfig>,
+}
+
+impl SavedGlobals {
+    fn capture() -> Self {
+        Self {
+            data_dir: std::env::var_os("ABV_DATA_DIR"),
+            stream_resumption: crate::proxy::config::get_stream_resumption_enabled(),
+            thinking_nudge: crate::proxy::config::get_thinking_nudge_enabled(),
+            max_account_rotations: crate::proxy::config::get_max_account_rotations(),
+            feature_flags: crate::proxy::config::get_feature_flags(),
+        }
+    }
+
+    fn restore(&self) {
+        match &self.data_dir {
+            Some(dir) => std::env::set_var("ABV_DATA_DIR", dir),
+            None => std::env::remove_var("ABV_DATA_DIR"),
+        }
+        crate::proxy::update_stream_resumption(self.stream_resumption);
+        crate::proxy::update_thinking_nudge(self.thinking_nudge);
+        crate::proxy::update_max_account_rotations(self.max_account_rotations);
+        crate::proxy::update_feature_flags(self.feature_flags.clone());
+    }
+}
+
+fn write_accounts(accounts: &[TestAccount]) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("antigravity-e2e-pool-{}", uuid::Uuid::new_v4()));
+    let accounts_dir = root.join("accounts");
+    std::fs::create_dir_all(&accounts_dir).unwrap();
+
+    let now = chrono::Utc::now().timestamp();
+    for account in accounts {
+        let json = json!({
+            "id": account.id,
+            "email": account.email,
+            "token": {
+                "access_token": account.access_token(),
+                "refresh_token": format!("rtk-{}", account.id),
+                "expires_in": 3600,
+                "expiry_timestamp": now + 3600,
+                "project_id": format!("pid-{}", account.id)
+            },
+            // 选号只考虑拥有目标模型配额信息的账号
+            "quota": {
+                "models": [
+                    { "name": "gemini-3-flash", "percentage": 100 },
+                    { "name": PROTECTED_MODEL, "percentage": account.protected_model_quota }
+                ]
+            },
+            "disabled": false,
+            "proxy_disabled": false,
+            "created_at": now,
+            "last_used": now
+        });
+        std::fs::write(
+            accounts_dir.join(format!("{}.json", account.id)),
+            serde_json::to_string_pretty(&json).unwrap(),
+        )
+        .unwrap();
+    }
+    root
+}
+
+pub struct ProxyHarness {
+    pub upstream: MockUpstream,
+    base_url: String,
+    server: AxumServer,
+    monitor: Arc<crate::proxy::monitor::ProxyMonitor>,
+    accounts_root: PathBuf,
+    client: reqwest::Client,
+    saved: SavedGlobals,
+    _serial: tokio::sync::MutexGuard<'static, ()>,
+}
+
+impl ProxyHarness {
+    pub async fn start(accounts: &[TestAccount]) -> Self {
+        let serial = E2E_LOCK.lock().await;
+        let saved = SavedGlobals::capture();
+        std::env::set_var("ABV_DATA_DIR", shared_data_dir());
+        write_shared_config();
+        let upstream = MockUpstream::start().await;
+
+        let accounts_root = write_accounts(accounts);
+        let token_manager = Arc::new(TokenManager::new(accounts_root.clone()));
+        token_manager.load_accounts().await.unwrap();
+
+        // 绑定随机端口后直接交给服务器，避免释放后被其他进程抢占
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut config = ProxyConfig::default();
+        config.upstream_base_url = Some(upstream.base_url());
+        let monitor = Arc::new(crate::proxy::monitor::ProxyMonitor::new(100, None));
+        let (server, _handle) = AxumServer::start_with_listener(
+            "127.0.0.1".to_string(),
+            listener,
+            token_manager,
+            std::collections::HashMap::new(),
+            config.request_timeout,
+            config.upstream_proxy.clone(),
+            None,
+            ProxySecurityConfig::from_proxy_config(&config),
+            config.zai.clone(),
+            monitor.clone(),
+            config.experimental.clone(),
+            config.debug_logging.clone(),
+            crate::modules::integration::SystemManager::Headless,
+            Arc::new(crate::commands::cloudflared::CloudflaredState::new()),
+            config.proxy_pool.clone(),
+        )
+        .await
+        .unwrap();
+        server.update_upstream_base_url(&config).await;
+
+        Self {
+            upstream,
+            base_url: format!("http://{}", addr),
+            server,
+            monitor,
+            accounts_root,
+            client: reqwest::Client::new(),
+            saved,
+            _serial: serial,
+        }
+    }
+
+    /// 本场景开启流中断恢复 (结束时恢复原值)
+    pub fn enable_stream_resumption(&self) {
+        crate::proxy::update_stream_resumption(true);
+    }
+
+    /// 本场景开启思考续写 (结束时恢复原值)
+    pub fn enable_thinking_nudge(&self) {
+        crate::proxy::update_thinking_nudge(true);
+    }
+
+    /// 本场景的账号轮换次数 (结束时恢复原值)
+    pub fn set_max_account_rotations(&self, rotations: usize) {
+        crate::proxy::update_max_account_rotations(rotations);
+    }
+
+    /// 本场景的功能开关 (结束时恢复原值)
+    pub fn set_feature_flags(&self, flags: Vec<crate::proxy::config::FeatureFlagConfig>) {
+        crate::proxy::update_feature_flags(flags);
+    }
+
+    /// 开启调试日志，以明文写入指定目录 (服务器随场景结束，无需恢复)
+    pub async fn enable_debug_logging(&self, output_dir: &std::path::Path) {
+        let mut config = ProxyConfig::default();
+        config.debug_logging.enabled = true;
+        config.debug_logging.encrypt_at_rest = false;
+        config.debug_logging.output_dir = Some(output_dir.to_string_lossy().into_owned());
+        self.server.update_debug_logging(&config).await;
+    }
+
+    /// 开启 URL 查询参数覆盖 (服务器随场景结束，无需恢复)
+    pub async fn enable_query_overrides(&self) {
+        let mut config = ProxyConfig::default();
+        config.debug_logging.allow_query_overrides = true;
+        self.server.update_debug_logging(&config).await;
+    }
+
+    /// 开启请求日志记录 (监控实例随场景结束，无需恢复)
+    pub fn enable_request_log(&self) {
+        self.monitor.set_enabled(true);
+    }
+
+    /// 内存中的请求日志 (最新在前)
+    pub async fn request_logs(&self) -> Vec<crate::proxy::monitor::ProxyRequestLog> {
+        self.monitor.logs.read().await.iter().cloned().collect()
+    }
+
+    /// Anthropic Messages API
+    pub async fn post_claude(&self, body: Value) -> reqwest::Response {
+        self.post_claude_with_query("", body).await
+    }
+
+    /// Anthropic Messages API，附带 URL 查询参数 (如 `temperature=0.2`)
+    pub async fn post_claude_with_query(&self, query: &str, body: Value) -> reqwest::Response {
+        let query = if query.is_empty() { String::new() } else { format!("?{}", query) };
+        self.client
+            .post(format!("{}/v1/messages{}", self.base_url, query))
+            .header("anthropic-version", "2023-06-01")
+            .json(&body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Anthropic count_tokens API
+    pub async fn post_claude_count_tokens(&self, body: Value) -> reqwest::Response {
+        self.client
+            .post(format!("{}/v1/messages/count_tokens", self.base_url))
+            .header("anthropic-version", "2023-06-01")
+            .json(&body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// OpenAI Chat Completions API
+    pub async fn post_openai(&self, body: Value) -> reqwest::Response {
+        self.client
+            .post(format!("{}/v1/chat/completions", self.base_url))
+            .json(&body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// 启动监听配置档 (额外端口，与主端口共享账号池；服务器随场景结束一并停止)
+    pub async fn start_listener_profiles(&self, profiles: Vec<crate::proxy::config::ListenerProfile>) {
+        let mut config = ProxyConfig::default();
+        config.listener_profiles = profiles;
+        self.server.update_listener_profiles(&config).await;
+    }
+
+    /// 经指定监听配置档的端口调用 OpenAI Chat Completions API
+    pub async fn post_openai_via_profile(&self, profile: &str, body: Value) -> reqwest::Response {
+        let port = self
+            .server
+            .listener_profile_port(profile)
+            .await
+            .expect("listener profile is running");
+        self.client
+            .post(format!("http://127.0.0.1:{}/v1/chat/completions", port))
+            .json(&body)
+            .send()
+            .await
+            .unwrap()
+    }
+}
+
+impl Drop for ProxyHarness {
+    fn drop(&mut self) {
+        self.server.stop();
+        let _ = std::fs::remove_dir_all(&self.accounts_root);
+        self.saved.restore();
+    }
+}
+
+// ===== 客户端 SSE 解析 =====
+
+fn sse_data(sse: &str) -> impl Iterator<Item = Value> + '_ {
+    sse.lines()
+        .filter_map(|l| l.strip_prefix("data:"))
+        .filter_map(|d| serde_json::from_str::<Value>(d.trim()).ok())
+}
+
+/// 拼接 Claude SSE 中的 text_delta
+pub fn claude_stream_text(sse: &str) -> String {
+    sse_data(sse)
+        .filter(|e| e["delta"]["type"] == "text_delta")
+        .filter_map(|e| e["delta"]["text"].as_str().map(str::to_string))
+        .collect()
+}
+
+/// 拼接 OpenAI SSE 中的 choices[0].delta.content
+pub fn openai_stream_text(sse: &str) -> String {
+    sse_data(sse)
+        .filter_map(|e| {
+            e["choices"][0]["delta"]["content"]
+                .as_str()
+                .map(str::to_string)
+        })
+        .collect()
+}
@@ -0,0 +1,527 @@
+//! 端到端场景：真实反代服务器 + 模拟 v1internal 上游，通过 Claude / OpenAI 协议的 HTTP 客户端驱动
+
+use super::e2e_harness::*;
+use serde_json::json;
+use std::time::Duration;
+
+fn claude_stream_request(model: &str, prompt: &str) -> serde_json::Value {
+    json!({
+        "model": model,
+        "max_tokens": 256,
+        "stream": true,
+        "messages": [{ "role": "user", "content": prompt }]
+    })
+}
+
+fn openai_stream_request(model: &str, prompt: &str) -> serde_json::Value {
+    json!({
+        "model": model,
+        "stream": true,
+        "messages": [{ "role": "user", "content": prompt }]
+    })
+}
+
+#[tokio::test]
+async fn test_e2e_happy_path_streaming_both_protocols() {
+    let harness = ProxyHarness::start(&[TestAccount::new("e2e_happy", "happy@test.com")]).await;
+
+    harness.upstream.enqueue(
+        ScriptedResponse::sse(vec![
+            text_chunk("Hello", false),
+            text_chunk(" from Claude", true),
+        ])
+        .with_event_delay(Duration::from_millis(20)),
+    );
+    let resp = harness
+        .post_claude(claude_stream_request("gemini-3-flash", "Say hello"))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let sse = resp.text().await.unwrap();
+    assert_eq!(claude_stream_text(&sse), "Hello from Claude");
+    assert_eq!(sse.matches("event: message_stop").count(), 1);
+
+    harness.upstream.enqueue(ScriptedResponse::sse(vec![
+        text_chunk("Hello", false),
+        text_chunk(" from OpenAI", true),
+    ]));
+    let resp = harness
+        .post_openai(openai_stream_request("gemini-3-flash", "Say hello"))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let sse = resp.text().await.unwrap();
+    assert_eq!(openai_stream_text(&sse), "Hello from OpenAI");
+    assert!(sse.contains("data: [DONE]"));
+
+    let requests = harness.upstream.generate_requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests
+        .iter()
+        .all(|r| r.path.contains(":streamGenerateContent")));
+    assert!(requests.iter().all(|r| r.token == "atk-e2e_happy"));
+    assert_eq!(requests[0].body["project"], "pid-e2e_happy");
+}
+
+#[tokio::test]
+async fn test_e2e_429_fails_over_to_next_account() {
+    let harness = ProxyHarness::start(&[
+        TestAccount::new("e2e_429_a", "a429@test.com"),
+        TestAccount::new("e2e_429_b", "b429@test.com"),
+    ])
+    .await;
+
+    harness.upstream.enqueue(quota_exhausted(30));
+    harness
+        .upstream
+        .enqueue(ScriptedResponse::sse(vec![text_chunk(
+            "Served after failover",
+            true,
+        )]));
+
+    let resp = harness
+        .post_openai(openai_stream_request("gemini-3-flash", "Hi"))
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        openai_stream_text(&resp.text().await.unwrap()),
+        "Served after failover"
+    );
+
+    // 第二次尝试换用了另一个账号
+    let requests = harness.upstream.generate_requests();
+    assert_eq!(requests.len(), 2);
+    assert_ne!(requests[0].token, requests[1].token);
+
+    // 被限流的账号在锁定期内不再用于同一模型 (内存锁定，无需再次 429)
+    harness
+        .upstream
+        .enqueue(ScriptedResponse::sse(vec![text_chunk("Still on the other account", true)]));
+    let resp = harness
+        .post_claude(claude_stream_request("gemini-3-flash", "Hi again"))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let requests = harness.upstream.generate_requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[2].token, requests[1].token);
+}
+
+#[tokio::test]
+async fn test_e2e_account_attempts_stop_at_rotation_limit() {
+    let harness = ProxyHarness::start(&[
+        TestAccount::new("e2e_limit_a", "alimit@test.com"),
+        TestAccount::new("e2e_limit_b", "blimit@test.com"),
+        TestAccount::new("e2e_limit_c", "climit@test.com"),
+    ])
+    .await;
+    harness.set_max_account_rotations(1);
+    for _ in 0..3 {
+        harness.upstream.enqueue(quota_exhausted(30));
+    }
+
+    let resp = harness
+        .post_claude(claude_stream_request("gemini-3-flash", "Hi"))
+        .await;
+    assert_eq!(resp.status(), 429);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    let message = body["error"]["message"].as_str().unwrap();
+
+    // 首个账号 + 1 次轮换，第三个账号不再尝试
+    let requests = harness.upstream.generate_requests();
+    assert_eq!(requests.len(), 2);
+    assert_ne!(requests[0].token, requests[1].token);
+    assert!(message.contains("across 2 account(s)"), "{}", message);
+    assert!(message.contains("[1] ") && message.contains("[2] "), "{}", message);
+    assert!(!message.contains("[3] "), "{}", message);
+}
+
+#[tokio::test]
+async fn test_e2e_mid_stream_disconnect_is_resumed() {
+    let harness = ProxyHarness::start(&[TestAccount::new("e2e_resume", "resume@test.com")]).await;
+    harness.enable_stream_resumption();
+
+    harness.upstream.enqueue(
+        ScriptedResponse::sse(vec![
+            text_chunk("The quick brown fox ", false),
+            text_chunk("jumps over", false),
+        ])
+        .then_disconnect(),
+    );
+    harness
+        .upstream
+        .enqueue(ScriptedResponse::sse(vec![text_chunk(
+            " the lazy dog.",
+            true,
+        )]));
+
+    let resp = harness
+        .post_claude(claude_stream_request(
+            "gemini-3-flash",
+            "Tell me about the fox",
+        ))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let sse = resp.text().await.unwrap();
+    assert_eq!(
+        claude_stream_text(&sse),
+        "The quick brown fox jumps over the lazy dog."
+    );
+    assert_eq!(sse.matches("event: message_start").count(), 1);
+
+    // 续写请求携带已生成的文本与续写指令
+    let requests = harness.upstream.generate_requests();
+    assert_eq!(requests.len(), 2);
+    let continuation = requests[1].body.to_string();
+    assert!(continuation.contains("The quick brown fox jumps over"));
+    assert!(continuation.contains("cut off by a network interruption"));
+}
+
+#[tokio::test]
+async fn test_e2e_thinking_only_interruption_is_recovered() {
+    let harness = ProxyHarness::start(&[TestAccount::new("e2e_think", "think@test.com")]).await;
+
+    // 上游只输出思考内容就结束了流
+    harness
+        .upstream
+        .enqueue(ScriptedResponse::sse(vec![thought_chunk(
+            "Let me consider the question carefully.",
+        )]));
+
+    let mut request = claude_stream_request("gemini-3-flash", "Why is the sky blue?");
+    request["thinking"] = json!({ "type": "enabled", "budget_tokens": 1024 });
+    let resp = harness.post_claude(request).await;
+    assert_eq!(resp.status(), 200);
+    let sse = resp.text().await.unwrap();
+    assert!(sse.contains("thinking_delta"));
+    assert!(claude_stream_text(&sse).contains("Upstream model interrupted after thinking"));
+    assert_eq!(sse.matches("event: message_stop").count(), 1);
+}
+
+#[tokio::test]
+async fn test_e2e_all_accounts_protected_is_rejected() {
+    // 两个账号的 PROTECTED_MODEL 配额均低于阈值，加载时即被保护
+    let harness = ProxyHarness::start(&[
+        TestAccount::new("e2e_prot_a", "prota@test.com").with_quota(5),
+        TestAccount::new("e2e_prot_b", "protb@test.com").with_quota(0),
+    ])
+    .await;
+
+    let resp = harness
+        .post_claude(claude_stream_request(PROTECTED_MODEL, "Hi"))
+        .await;
+    assert_eq!(resp.status(), 503);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "overloaded_error");
+    assert!(body["error"]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("No available accounts"));
+
+    // 请求未到达上游
+    assert!(harness.upstream.generate_requests().is_empty());
+}
+
+#[tokio::test]
+async fn test_e2e_thinking_only_max_tokens_is_nudged_once() {
+    let harness = ProxyHarness::start(&[TestAccount::new("e2e_nudge", "nudge@test.com")]).await;
+    harness.enable_thinking_nudge();
+
+    // 上游只输出思考即触达输出上限，追问返回最终答案
+    let mut exhausted = thought_chunk("Weighing every possible interpretation...");
+    exhausted["response"]["candidates"][0]["finishReason"] = json!("MAX_TOKENS");
+    exhausted["response"]["usageMetadata"] = json!({
+        "promptTokenCount": 30,
+        "candidatesTokenCount": 2,
+        "thoughtsTokenCount": 1000,
+        "totalTokenCount": 1032
+    });
+    harness.upstream.enqueue(ScriptedResponse::sse(vec![exhausted]));
+    harness
+        .upstream
+        .enqueue(ScriptedResponse::sse(vec![text_chunk("The answer is 42.", true)]));
+
+    let mut request = claude_stream_request("gemini-3-flash", "What is the answer?");
+    request["thinking"] = json!({ "type": "enabled", "budget_tokens": 1024 });
+    let resp = harness.post_claude(request).await;
+    assert_eq!(resp.status(), 200);
+    let sse = resp.text().await.unwrap();
+    assert!(sse.contains("thinking_delta"));
+    assert_eq!(claude_stream_text(&sse), "The answer is 42.");
+    assert!(!sse.contains("Upstream model interrupted after thinking"));
+    assert_eq!(sse.matches("event: message_start").count(), 1);
+    assert_eq!(sse.matches("event: message_stop").count(), 1);
+
+    // 最终 usage 累加首个请求的输出 token，输入沿用首个请求
+    let delta = sse
+        .lines()
+        .filter_map(|l| l.strip_prefix("data: "))
+        .filter_map(|d| serde_json::from_str::<serde_json::Value>(d).ok())
+        .find(|v| v["type"] == "message_delta")
+        .expect("message_delta");
+    assert_eq!(delta["usage"]["output_tokens"], 2 + 8);
+    assert_eq!(delta["usage"]["input_tokens"], 30);
+    // 内部思考 token 扩展字段不下发给流式客户端
+    assert!(delta.get("_abv_thoughts_tokens").is_none());
+
+    // 追问只发起一次，使用同一账号，携带截断的思考并关闭思考
+    let requests = harness.upstream.generate_requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].token, requests[1].token);
+    let nudge = &requests[1].body["request"];
+    assert!(nudge["contents"]
+        .to_string()
+        .contains("Weighing every possible interpretation"));
+    assert_eq!(nudge["generationConfig"]["thinkingConfig"]["thinkingBudget"], 0);
+}
+
+#[tokio::test]
+async fn test_e2e_debug_log_records_feature_flag_decisions() {
+    use crate::proxy::config::FeatureFlagConfig;
+    use crate::proxy::flags::{rollout_bucket, DUMMY_THOUGHT_INJECTION, TRIPLE_PARTITION_REORDER};
+
+    let harness = ProxyHarness::start(&[TestAccount::new("e2e_flags", "flags@test.com")]).await;
+    let log_dir = std::env::temp_dir().join(format!("antigravity-e2e-debug-{}", uuid::Uuid::new_v4()));
+    harness.enable_debug_logging(&log_dir).await;
+    harness.set_feature_flags(vec![
+        FeatureFlagConfig {
+            name: TRIPLE_PARTITION_REORDER.to_string(),
+            enabled: false,
+            rollout_percent: None,
+        },
+        FeatureFlagConfig {
+            name: "e2e_half_rollout".to_string(),
+            enabled: true,
+            rollout_percent: Some(50),
+        },
+    ]);
+
+    harness
+        .upstream
+        .enqueue(ScriptedResponse::sse(vec![text_chunk("ok", true)]));
+    let mut request = claude_stream_request("gemini-3-flash", "Log my flags");
+    // 显式 user_id 作为分桶用的会话 ID
+    request["metadata"] = json!({ "user_id": "e2e-flag-user" });
+    let resp = harness.post_claude(request).await;
+    assert_eq!(resp.status(), 200);
+    resp.text().await.unwrap();
+
+    let entry = std::fs::read_dir(&log_dir)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .find(|e| e.file_name().to_string_lossy().ends_with("_v1internal_request.json"))
+        .expect("v1internal_request debug log written");
+    let logged: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(entry.path()).unwrap()).unwrap();
+    let _ = std::fs::remove_dir_all(&log_dir);
+
+    // 记录的是本次请求实际生效的取值: 配置覆盖、内置默认值与按会话分桶的灰度结果
+    let flags = &logged["feature_flags"];
+    assert_eq!(flags[TRIPLE_PARTITION_REORDER], false);
+    assert_eq!(flags[DUMMY_THOUGHT_INJECTION], false);
+    assert_eq!(
+        flags["e2e_half_rollout"],
+        rollout_bucket("e2e_half_rollout", "e2e-flag-user") < 50
+    );
+    assert_eq!(
+        logged["v1internal_request"]["project"],
+        harness.upstream.generate_requests()[0].body["project"]
+    );
+}
+
+#[tokio::test]
+async fn test_e2e_count_tokens_includes_mcp_server_tools() {
+    let harness = ProxyHarness::start(&[TestAccount::new("e2e_count", "count@test.com")]).await;
+
+    let base = json!({
+        "model": "gemini-3-flash",
+        "messages": [{ "role": "user", "content": "Open an issue about the crash" }]
+    });
+    let mut with_mcp = base.clone();
+    with_mcp["mcp_servers"] = json!([{
+        "type": "url",
+        "url": "https://mcp.example.com/github",
+        "name": "github",
+        "tools": [{
+            "name": "create_issue",
+            "description": "Create a GitHub issue with a title, a markdown body and optional labels",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "title": { "type": "string", "description": "Issue title" },
+                    "body": { "type": "string", "description": "Markdown body" },
+                    "labels": { "type": "array", "items": { "type": "string" } }
+                },
+                "required": ["title"]
+            }
+        }]
+    }]);
+
+    let count = |resp: serde_json::Value| resp["input_tokens"].as_u64().unwrap();
+    let resp = harness.post_claude_count_tokens(base).await;
+    assert_eq!(resp.status(), 200);
+    let plain = count(resp.json().await.unwrap());
+    let resp = harness.post_claude_count_tokens(with_mcp.clone()).await;
+    assert_eq!(resp.status(), 200);
+    let with_tools = count(resp.json().await.unwrap());
+    assert!(with_tools > plain, "{} <= {}", with_tools, plain);
+
+    // 与 /v1/messages 相同的校验
+    with_mcp["mcp_servers"][0]
+        .as_object_mut()
+        .unwrap()
+        .remove("name");
+    let resp = harness.post_claude_count_tokens(with_mcp).await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert!(body["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("mcp_servers[0].name"));
+
+    assert!(harness.upstream.generate_requests().is_empty());
+}
+
+#[tokio::test]
+async fn test_e2e_listener_profile_default_thinking_applies_to_openai() {
+    use crate::proxy::config::{IdentityInjectionMode, ListenerProfile};
+
+    let harness = ProxyHarness::start(&[TestAccount::new("e2e_profile", "profile@test.com")]).await;
+    let profile = |name: &str, default_thinking: bool| ListenerProfile {
+        name: name.to_string(),
+        port: 0,
+        enabled: true,
+        default_thinking: Some(default_thinking),
+        safety_threshold: None,
+        identity_injection: IdentityInjectionMode::Auto,
+        allowed_client_keys: Vec::new(),
+    };
+    harness
+        .start_listener_profiles(vec![profile("thinking-on", true), profile("thinking-off", false)])
+        .await;
+
+    for _ in 0..4 {
+        harness
+            .upstream
+            .enqueue(ScriptedResponse::sse(vec![text_chunk("ok", true)]));
+    }
+    let request = openai_stream_request("gemini-3-flash", "Hi");
+    let mut explicit = request.clone();
+    explicit["thinking"] = json!({ "type": "enabled", "budget_tokens": 2048 });
+
+    assert_eq!(harness.post_openai(request.clone()).await.status(), 200);
+    assert_eq!(
+        harness
+            .post_openai_via_profile("thinking-on", request.clone())
+            .await
+            .status(),
+        200
+    );
+    assert_eq!(
+        harness
+            .post_openai_via_profile("thinking-off", request)
+            .await
+            .status(),
+        200
+    );
+    // 客户端显式指定时不受配置档默认值影响
+    assert_eq!(
+        harness
+            .post_openai_via_profile("thinking-off", explicit)
+            .await
+            .status(),
+        200
+    );
+
+    let has_thinking: Vec<bool> = harness
+        .upstream
+        .generate_requests()
+        .iter()
+        .map(|r| r.body["request"]["generationConfig"]["thinkingConfig"].is_object())
+        .collect();
+    assert_eq!(has_thinking, vec![false, true, false, true]);
+}
+
+#[tokio::test]
+async fn test_e2e_query_overrides_validated_and_recorded_in_request_log() {
+    let harness = ProxyHarness::start(&[TestAccount::new("e2e_query", "query@test.com")]).await;
+    harness.enable_query_overrides().await;
+    harness.enable_request_log();
+
+    let request = json!({
+        "model": "gemini-3-flash",
+        "max_tokens": 256,
+        "messages": [{ "role": "user", "content": "Hi" }]
+    });
+
+    // 超出范围的参数在中间件中以 400 拒绝，不会调用上游
+    let resp = harness
+        .post_claude_with_query("temperature=5", request.clone())
+        .await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+    assert!(harness.upstream.generate_requests().is_empty());
+
+    harness
+        .upstream
+        .enqueue(ScriptedResponse::sse(vec![text_chunk("ok", true)]));
+    let resp = harness
+        .post_claude_with_query("temperature=0.2", request)
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["x-abv-overrides"], "temperature=0.2");
+    let _ = resp.text().await.unwrap();
+
+    let requests = harness.upstream.generate_requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].body["request"]["generationConfig"]["temperature"], 0.2);
+
+    // 覆盖项写入请求日志 (无需开启调试日志)
+    let logs = harness.request_logs().await;
+    let applied = logs.iter().find(|l| l.status == 200).expect("logged request");
+    assert_eq!(applied.query_overrides.as_deref(), Some("temperature=0.2"));
+    let rejected = logs.iter().find(|l| l.status == 400).expect("logged rejection");
+    assert!(rejected.query_overrides.is_none());
+}
+
+#[tokio::test]
+async fn test_e2e_client_stop_sequence_reported_on_both_claude_paths() {
+    let harness = ProxyHarness::start(&[TestAccount::new("e2e_stop", "stop@test.com")]).await;
+
+    // 非流式: 收集器回报命中的停止序列
+    harness.upstream.enqueue(ScriptedResponse::sse(vec![
+        text_chunk("Hello EN", false),
+        text_chunk("D ignored", false),
+        text_chunk(" tail", true),
+    ]));
+    let resp = harness
+        .post_claude(json!({
+            "model": "gemini-3-flash",
+            "max_tokens": 256,
+            "stop_sequences": ["END"],
+            "messages": [{ "role": "user", "content": "Hi" }]
+        }))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["stop_reason"], "stop_sequence");
+    assert_eq!(body["stop_sequence"], "END");
+    assert_eq!(body["content"][0]["text"], "Hello ");
+
+    // 流式: message_delta 携带 stop_reason / stop_sequence
+    harness.upstream.enqueue(ScriptedResponse::sse(vec![
+        text_chunk("One STOP two", false),
+        text_chunk(" three", true),
+    ]));
+    let mut request = claude_stream_request("gemini-3-flash", "Count");
+    request["stop_sequences"] = json!(["STOP"]);
+    let sse = harness.post_claude(request).await.text().await.unwrap();
+    assert_eq!(claude_stream_text(&sse), "One ");
+    assert!(sse.contains("\"stop_reason\":\"stop_sequence\""));
+    assert!(sse.contains("\"stop_sequence\":\"STOP\""));
+    assert_eq!(sse.matches("event: message_stop").count(), 1);
+
+    // 客户端停止序列不发往上游 (上游命中时无法得知是哪一个)
+    for request in harness.upstream.generate_requests() {
+        let upstream = request.body["request"]["generationConfig"]["stopSequences"].to_string();
+        assert!(!upstream.contains("END") && !upstream.contains("STOP"), "{}", upstream);
+    }
+}
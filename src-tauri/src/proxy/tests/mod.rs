@@ -5,3 +5,7 @@ pub mod quota_protection;
 pub mod ultra_priority_tests;
 pub mod retry_strategy_tests;
 pub mod rate_limit_404_tests;
+pub mod blob_intern_bench;
+pub mod rate_limit_rotation_tests;
+pub mod e2e_harness;
+pub mod e2e_tests;
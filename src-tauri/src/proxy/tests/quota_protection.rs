@@ -38,6 +38,13 @@ mod tests {
             validation_blocked: false,
             validation_blocked_until: 0,
             model_quotas: std::collections::HashMap::new(),
+            model_quota_tokens: std::collections::HashMap::new(),
+            unparsed_quota_models: std::collections::HashSet::new(),
+            envelope: Default::default(),
+            additional_project_ids: Vec::new(),
+            monthly_token_budget: None,
+            group: None,
+            created_at: 0,
         }
     }
 
@@ -1116,6 +1123,46 @@ mod tests {
         let _ = std::fs::remove_file(&account_path);
     }
 
+    // ==================================================================================
+    // 测试 20: 配额结构兼容 (平铺 / 嵌套窗口 / 无法解析)
+    // 嵌套窗口取最严格的窗口；无法解析时返回 None (未知)，而不是 0%
+    // ==================================================================================
+
+    #[test]
+    fn test_get_model_quota_from_json_tolerates_quota_shapes() {
+        let account_json = serde_json::json!({
+            "email": "test@example.com",
+            "quota": {
+                "models": [
+                    { "name": "gemini-3-flash", "percentage": 45 },
+                    { "name": "gemini-3-pro-high", "windows": [
+                        { "percentage": 70, "reset_time": "2026-01-07T00:00:00Z" },
+                        { "remaining_fraction": 0.08, "reset_time": "2026-01-01T05:00:00Z" }
+                    ]},
+                    { "name": "claude", "quota": { "left": "plenty" } },
+                    { "name": "gemini-3-pro-image", "percentage": 0, "unparsed": true }
+                ]
+            }
+        });
+
+        let temp_dir = std::env::temp_dir();
+        let account_path = temp_dir.join(format!("test_shapes_{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&account_path, account_json.to_string()).expect("Failed to write temp file");
+
+        let read = |model: &str| {
+            crate::proxy::token_manager::TokenManager::get_model_quota_from_json_for_test(
+                &account_path,
+                model,
+            )
+        };
+        assert_eq!(read("gemini-3-flash"), Some(45), "平铺结构");
+        assert_eq!(read("gemini-3-pro-high"), Some(8), "嵌套窗口应取最严格的窗口");
+        assert_eq!(read("claude"), None, "无法解析的结构应视为未知");
+        assert_eq!(read("gemini-3-pro-image"), None, "已标记 unparsed 的占位百分比不可信");
+
+        let _ = std::fs::remove_file(&account_path);
+    }
+
     /// 辅助函数：创建带有自定义 account_path 的 mock token
     fn create_mock_token_with_path(
         account_id: &str,
@@ -1141,6 +1188,13 @@ mod tests {
             validation_blocked: false,
             validation_blocked_until: 0,
             model_quotas: std::collections::HashMap::new(),
+            model_quota_tokens: std::collections::HashMap::new(),
+            unparsed_quota_models: std::collections::HashSet::new(),
+            envelope: Default::default(),
+            additional_project_ids: Vec::new(),
+            monthly_token_budget: None,
+            group: None,
+            created_at: 0,
         }
     }
 }
@@ -0,0 +1,112 @@
+//! 测试 429 账号轮换：账号 A 返回 429 后，block_rate_limited_account 在内存中按模型锁定 A，
+//! 同一模型的后续选号立即改用 B，A 的其他模型与账号文件不受影响。
+//! 经由真实 handler 的完整重试流程见 e2e_tests::test_e2e_429_fails_over_to_next_account。
+
+use crate::proxy::handlers::common::{
+    block_rate_limited_account, is_rate_limit_error, max_retry_attempts,
+};
+use crate::proxy::TokenManager;
+use std::path::{Path, PathBuf};
+
+const MODEL: &str = "gemini-1.5-flash";
+const OTHER_MODEL: &str = "gemini-1.5-pro";
+const QUOTA_EXHAUSTED_BODY: &str =
+    r#"{"error":{"code":429,"status":"RESOURCE_EXHAUSTED","message":"QUOTA_EXHAUSTED"}}"#;
+
+fn setup_pool(accounts: &[(&str, &str, i64)]) -> PathBuf {
+    let tmp_root = std::env::temp_dir().join(format!(
+        "antigravity-rate-limit-rotation-{}",
+        uuid::Uuid::new_v4()
+    ));
+    let accounts_dir = tmp_root.join("accounts");
+    std::fs::create_dir_all(&accounts_dir).unwrap();
+
+    let now = chrono::Utc::now().timestamp();
+    for (id, email, percentage) in accounts {
+        let json = serde_json::json!({
+            "id": id,
+            "email": email,
+            "token": {
+                "access_token": format!("atk-{}", id),
+                "refresh_token": format!("rtk-{}", id),
+                "expires_in": 3600,
+                "expiry_timestamp": now + 3600,
+                "project_id": format!("pid-{}", id)
+            },
+            "quota": {
+                "models": [
+                    { "name": MODEL, "percentage": percentage },
+                    { "name": OTHER_MODEL, "percentage": percentage }
+                ]
+            },
+            "disabled": false,
+            "proxy_disabled": false,
+            "created_at": now,
+            "last_used": now
+        });
+        std::fs::write(
+            accounts_dir.join(format!("{}.json", id)),
+            serde_json::to_string_pretty(&json).unwrap(),
+        )
+        .unwrap();
+    }
+    tmp_root
+}
+
+fn read_account(root: &Path, id: &str) -> serde_json::Value {
+    let content = std::fs::read_to_string(root.join("accounts").join(format!("{}.json", id))).unwrap();
+    serde_json::from_str(&content).unwrap()
+}
+
+async fn pick(manager: &TokenManager, model: &str) -> String {
+    let (_token, _project_id, email, _account_id, _wait_ms) =
+        manager.get_token("gemini", false, None, model).await.unwrap();
+    email
+}
+
+#[tokio::test]
+async fn test_429_locks_model_in_memory_and_rotates_to_next() {
+    // A 配额更高，会被优先选中
+    let root = setup_pool(&[("acc_a", "a@test.com", 90), ("acc_b", "b@test.com", 10)]);
+    let manager = TokenManager::new(root.clone());
+    manager.load_accounts().await.unwrap();
+    assert_eq!(pick(&manager, MODEL).await, "a@test.com");
+
+    assert!(block_rate_limited_account(&manager, "acc_a", "a@test.com", MODEL, Some("30"), QUOTA_EXHAUSTED_BODY));
+
+    // 同一模型立即改用 B (锁定键为归一化后的模型分组)
+    assert_eq!(pick(&manager, MODEL).await, "b@test.com");
+    assert!(manager.is_rate_limited("acc_a", Some("gemini-3-flash")).await);
+    assert!(!manager.is_rate_limited("acc_a", Some("gemini-3-pro-high")).await);
+
+    // 锁定只作用于该模型，且不写入账号文件
+    assert_eq!(pick(&manager, OTHER_MODEL).await, "a@test.com");
+    assert_eq!(read_account(&root, "acc_a")["validation_blocked"], serde_json::Value::Null);
+
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[tokio::test]
+async fn test_single_account_pool_is_not_blocked() {
+    let root = setup_pool(&[("acc_a", "a@test.com", 90)]);
+    let manager = TokenManager::new(root.clone());
+    manager.load_accounts().await.unwrap();
+
+    // 单账号时保留原有退避重试，不锁定唯一账号
+    assert!(!block_rate_limited_account(&manager, "acc_a", "a@test.com", MODEL, None, QUOTA_EXHAUSTED_BODY));
+    assert!(!manager.is_rate_limited("acc_a", Some("gemini-3-flash")).await);
+
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn test_rate_limit_detection_and_attempt_budget() {
+    assert!(is_rate_limit_error(429, ""));
+    assert!(is_rate_limit_error(403, "QUOTA_EXHAUSTED"));
+    assert!(!is_rate_limit_error(503, "overloaded"));
+    assert!(!is_rate_limit_error(200, "QUOTA_EXHAUSTED"));
+
+    // 默认最多轮换 2 次；单账号仍保留 2 次尝试
+    assert_eq!(max_retry_attempts(1), 2);
+    assert_eq!(max_retry_attempts(10), 3);
+}
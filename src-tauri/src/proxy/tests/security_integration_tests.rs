@@ -37,6 +37,7 @@ mod integration_tests {
     /// 3. 响应体包含封禁原因
     #[test]
     fn test_scenario_blacklist_blocks_request() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -74,6 +75,7 @@ mod integration_tests {
     /// 3. 请求应该被允许（白名单优先）
     #[test]
     fn test_scenario_whitelist_priority() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -114,6 +116,7 @@ mod integration_tests {
     /// 3. 请求应该被允许
     #[test]
     fn test_scenario_temporary_ban_expiration() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -150,6 +153,7 @@ mod integration_tests {
     /// 3. 192.168.2.x 的请求正常通过
     #[test]
     fn test_scenario_cidr_subnet_blocking() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -192,6 +196,7 @@ mod integration_tests {
     ///    - 剩余封禁时间（如果是临时）
     #[test]
     fn test_scenario_ban_message_details() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -234,6 +239,7 @@ mod integration_tests {
     /// 3. 访问日志记录：IP、时间、状态(403)、封禁原因
     #[test]
     fn test_scenario_blocked_request_logging() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -278,6 +284,7 @@ mod integration_tests {
     /// 2. 与没有安全检查的基线相比，延迟增加 < 10ms
     #[test]
     fn test_scenario_performance_impact() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -326,6 +333,7 @@ mod integration_tests {
     /// 2. 数据仍然存在
     #[test]
     fn test_scenario_data_persistence() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -371,6 +379,7 @@ mod stress_tests {
     /// 压力测试：大量黑名单条目
     #[test]
     fn stress_test_large_blacklist() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -404,6 +413,7 @@ mod stress_tests {
     /// 压力测试：大量访问日志
     #[test]
     fn stress_test_access_logging() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         let _ = clear_ip_access_logs();
 
@@ -447,6 +457,7 @@ mod stress_tests {
     /// 压力测试：并发操作
     #[test]
     fn stress_test_concurrent_operations() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -52,6 +52,7 @@ mod security_db_tests {
     
     #[test]
     fn test_db_initialization() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         // 验证数据库初始化不会 panic
         let result = init_db();
         assert!(result.is_ok(), "Database initialization should succeed: {:?}", result.err());
@@ -59,6 +60,7 @@ mod security_db_tests {
 
     #[test]
     fn test_db_multiple_initializations() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         // 验证多次初始化不会出错 (幂等性)
         for _ in 0..3 {
             let result = init_db();
@@ -72,6 +74,7 @@ mod security_db_tests {
 
     #[test]
     fn test_blacklist_add_and_check() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -94,6 +97,7 @@ mod security_db_tests {
 
     #[test]
     fn test_blacklist_remove() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -115,6 +119,7 @@ mod security_db_tests {
 
     #[test]
     fn test_blacklist_get_entry_details() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -148,6 +153,7 @@ mod security_db_tests {
 
     #[test]
     fn test_cidr_matching_basic() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -168,6 +174,7 @@ mod security_db_tests {
 
     #[test]
     fn test_cidr_matching_various_masks() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -191,6 +198,7 @@ mod security_db_tests {
 
     #[test]
     fn test_cidr_edge_cases() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -217,6 +225,7 @@ mod security_db_tests {
 
     #[test]
     fn test_blacklist_expiration() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -239,6 +248,7 @@ mod security_db_tests {
 
     #[test]
     fn test_blacklist_not_yet_expired() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -258,6 +268,7 @@ mod security_db_tests {
 
     #[test]
     fn test_permanent_blacklist() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -281,6 +292,7 @@ mod security_db_tests {
 
     #[test]
     fn test_whitelist_add_and_check() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -297,6 +309,7 @@ mod security_db_tests {
 
     #[test]
     fn test_whitelist_cidr() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -319,6 +332,7 @@ mod security_db_tests {
 
     #[test]
     fn test_access_log_save_and_retrieve() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -354,6 +368,7 @@ mod security_db_tests {
 
     #[test]
     fn test_access_log_blocked_filter() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -406,6 +421,7 @@ mod security_db_tests {
 
     #[test]
     fn test_ip_stats() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -453,6 +469,7 @@ mod security_db_tests {
 
     #[test]
     fn test_cleanup_old_logs() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -512,6 +529,7 @@ mod security_db_tests {
 
     #[test]
     fn test_concurrent_access() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         use std::thread;
         
         let _ = init_db();
@@ -544,6 +562,7 @@ mod security_db_tests {
 
     #[test]
     fn test_duplicate_blacklist_entry() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -560,6 +579,7 @@ mod security_db_tests {
 
     #[test]
     fn test_empty_ip_pattern() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -574,6 +594,7 @@ mod security_db_tests {
 
     #[test]
     fn test_special_characters_in_reason() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -590,6 +611,7 @@ mod security_db_tests {
 
     #[test]
     fn test_hit_count_increment() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         cleanup_test_data();
 
@@ -655,6 +677,7 @@ mod performance_benchmarks {
     /// 基准测试：黑名单查找性能
     #[test]
     fn benchmark_blacklist_lookup() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
         
         // 清理并添加 100 个黑名单条目
@@ -700,6 +723,7 @@ mod performance_benchmarks {
     /// 基准测试：CIDR 匹配性能
     #[test]
     fn benchmark_cidr_matching() {
+        let _data_dir = crate::proxy::tests::e2e_harness::IsolatedDataDir::acquire_blocking();
         let _ = init_db();
 
         // 清理并添加 CIDR 规则
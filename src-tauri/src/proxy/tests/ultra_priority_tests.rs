@@ -53,6 +53,13 @@ fn create_test_token(
         validation_blocked: false,
         validation_blocked_until: 0,
         model_quotas,
+        model_quota_tokens: HashMap::new(),
+        unparsed_quota_models: HashSet::new(),
+        envelope: Default::default(),
+        additional_project_ids: Vec::new(),
+        monthly_token_budget: None,
+        group: None,
+        created_at: 0,
     }
 }
 
@@ -2,12 +2,28 @@
 use dashmap::DashMap;
 use std::collections::{HashSet, HashMap};
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio_util::sync::CancellationToken;
 
+use crate::proxy::mappers::common_utils::EnvelopeParams;
 use crate::proxy::rate_limit::RateLimitTracker;
 use crate::proxy::sticky_config::StickySessionConfig;
+use crate::proxy::stream_slots::{StreamPermit, StreamSlots, STREAM_SLOT_WAIT};
+
+/// [NEW] 健康分被动恢复速度 (每分钟)；低于最低健康分被跳过的账号没有成功请求可用于恢复
+/// 仅在设置了最低健康分 (按健康分选号) 时生效
+const HEALTH_RECOVERY_PER_MIN: f32 = 0.01;
+
+/// [NEW] 月度预算过滤使用的用量快照有效期 (选号时不再每次同步查询统计库)
+const BUDGET_USAGE_TTL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// 本月各账号 token 用量快照
+struct BudgetUsageSnapshot {
+    month_start: i64,
+    fetched_at: std::time::Instant,
+    usage: Arc<HashMap<String, u64>>,
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum OnDiskAccountState {
@@ -34,6 +50,35 @@ pub struct ProxyToken {
     pub validation_blocked: bool,          // [NEW] Check for validation block (VALIDATION_REQUIRED temporary block)
     pub validation_blocked_until: i64,     // [NEW] Timestamp until which the account is blocked
     pub model_quotas: HashMap<String, i32>, // [OPTIMIZATION] In-memory cache for model-specific quotas
+    pub model_quota_tokens: HashMap<String, u64>, // [NEW] 绝对剩余 token (仅上游提供时存在，优先于百分比)
+    pub unparsed_quota_models: HashSet<String>, // [NEW] 配额结构无法解析的模型组 (保留能力，不参与百分比排序与保护)
+    pub envelope: EnvelopeParams,          // [NEW] Per-account userAgent / requestType overrides
+    pub additional_project_ids: Vec<String>, // [NEW] Extra projects allowed for X-Antigravity-Project
+    pub monthly_token_budget: Option<u64>, // [NEW] 每月 token 预算 (None = 不限制)
+    pub created_at: i64,                   // [NEW] 账号添加时间 (新账号预热策略，0 = 未知)
+    pub group: Option<String>,             // [NEW] 账号分组 (多团队租户隔离，None = 未分组)
+}
+
+impl ProxyToken {
+    /// access_token 是否会在 window_secs 秒内到期 (timestamp 为到期时间戳)
+    pub fn expires_within(&self, window_secs: i64, now: i64) -> bool {
+        now >= self.timestamp - window_secs
+    }
+}
+
+/// [NEW] 临时优先账号 (调试用，到期自动恢复)
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PreferredAccountOverride {
+    pub account_id: String,
+    /// 到期时间 (Unix 秒)
+    pub expires_at: i64,
+}
+
+/// [NEW] 429 锁定期间被标记为耗尽的模型配额原值 (锁定结束后恢复)
+#[derive(Debug, Clone, Copy)]
+struct QuotaLockout {
+    previous_percentage: Option<i32>,
+    previous_tokens: Option<u64>,
 }
 
 pub struct TokenManager {
@@ -45,8 +90,15 @@ pub struct TokenManager {
     sticky_config: Arc<tokio::sync::RwLock<StickySessionConfig>>, // 新增：调度配置
     session_accounts: Arc<DashMap<String, String>>, // 新增：会话与账号映射 (SessionID -> AccountID)
     preferred_account_id: Arc<tokio::sync::RwLock<Option<String>>>, // [FIX #820] 优先使用的账号ID（固定账号模式）
+    preferred_override: Arc<parking_lot::Mutex<Option<PreferredAccountOverride>>>, // [NEW] 临时优先账号 (非独占，到期恢复)
     health_scores: Arc<DashMap<String, f32>>,                       // account_id -> health_score
+    health_updated_at: Arc<DashMap<String, i64>>, // [NEW] account_id -> 健康分最后更新时间 (用于被动恢复)
+    health_recovery_enabled: Arc<AtomicBool>, // [NEW] 是否启用被动恢复 (min_health_score > 0 时)
+    stream_slots: Arc<StreamSlots>, // [NEW] 每账号活跃流计数
+    recent_failures: Arc<DashMap<String, std::time::Instant>>, // [NEW] 近期失败账号 (account_id -> 失败时间)
     circuit_breaker_config: Arc<tokio::sync::RwLock<crate::models::CircuitBreakerConfig>>, // [NEW] 熔断配置缓存
+    budget_usage: Arc<parking_lot::Mutex<Option<BudgetUsageSnapshot>>>, // [NEW] 本月用量缓存 (月度预算过滤)
+    quota_lockouts: Arc<DashMap<(String, String), QuotaLockout>>, // [NEW] (account_id, model) -> 锁定前的配额
     /// 支持优雅关闭时主动 abort 后台任务
     auto_cleanup_handle: Arc<tokio::sync::Mutex<Option<tokio::task::JoinHandle<()>>>>,
     cancel_token: CancellationToken,
@@ -64,10 +116,17 @@ impl TokenManager {
             sticky_config: Arc::new(tokio::sync::RwLock::new(StickySessionConfig::default())),
             session_accounts: Arc::new(DashMap::new()),
             preferred_account_id: Arc::new(tokio::sync::RwLock::new(None)), // [FIX #820]
+            preferred_override: Arc::new(parking_lot::Mutex::new(None)),
             health_scores: Arc::new(DashMap::new()),
+            health_updated_at: Arc::new(DashMap::new()),
+            health_recovery_enabled: Arc::new(AtomicBool::new(false)),
+            stream_slots: Arc::new(StreamSlots::new()),
+            recent_failures: Arc::new(DashMap::new()),
             circuit_breaker_config: Arc::new(tokio::sync::RwLock::new(
                 crate::models::CircuitBreakerConfig::default(),
             )),
+            budget_usage: Arc::new(parking_lot::Mutex::new(None)),
+            quota_lockouts: Arc::new(DashMap::new()),
             auto_cleanup_handle: Arc::new(tokio::sync::Mutex::new(None)),
             cancel_token: CancellationToken::new(),
         }
@@ -203,6 +262,7 @@ impl TokenManager {
 
         // 2. 清理相关的健康分数
         self.health_scores.remove(account_id);
+        self.health_updated_at.remove(account_id);
 
         // 3. 清理该账号的所有限流记录
         self.clear_rate_limit(account_id);
@@ -293,6 +353,44 @@ impl TokenManager {
     }
 
     /// 加载单个账号
+    /// 从账号 JSON 构建模型配额缓存: (剩余百分比, 绝对剩余 token, 配额无法解析的模型组)
+    /// [NEW] 兼容平铺/嵌套窗口结构；剩余百分比未知的模型组不写入百分比缓存 (不按 0% 参与排序)，
+    /// 仅记录在 unparsed 集合中以保留该模型能力
+    fn build_model_quota_cache(
+        account: &serde_json::Value,
+    ) -> (HashMap<String, i32>, HashMap<String, u64>, HashSet<String>) {
+        let mut model_quotas = HashMap::new();
+        let mut model_quota_tokens: HashMap<String, u64> = HashMap::new();
+        let mut unparsed = HashSet::new();
+        if let Some(models) = account.get("quota").and_then(|q| q.get("models")).and_then(|m| m.as_array()) {
+            for model in models {
+                if let Some(name) = model.get("name").and_then(|v| v.as_str()) {
+                    // Normalize name to standard ID
+                    let standard_id = crate::proxy::common::model_mapping::normalize_to_standard_id(name)
+                        .unwrap_or_else(|| name.to_string());
+                    // [NEW] 同组多个模型 (如 claude 系列) 取最保守的剩余额度
+                    if let Some(remaining) = model.get("remaining_tokens").and_then(|v| v.as_u64()) {
+                        model_quota_tokens
+                            .entry(standard_id.clone())
+                            .and_modify(|v| *v = (*v).min(remaining))
+                            .or_insert(remaining);
+                    }
+                    match crate::models::quota::percentage_from_json(model) {
+                        Some(pct) => {
+                            model_quotas.insert(standard_id, pct);
+                        }
+                        None => {
+                            unparsed.insert(standard_id);
+                        }
+                    }
+                }
+            }
+        }
+        // 同组中有可解析的模型时以其百分比为准
+        unparsed.retain(|id| !model_quotas.contains_key(id));
+        (model_quotas, model_quota_tokens, unparsed)
+    }
+
     async fn load_single_account(&self, path: &PathBuf) -> Result<Option<ProxyToken>, String> {
         let content = std::fs::read_to_string(path).map_err(|e| format!("读取文件失败: {}", e))?;
 
@@ -357,9 +455,12 @@ impl TokenManager {
                 account["validation_blocked_until"] = serde_json::json!(0);
                 account["validation_blocked_reason"] = serde_json::Value::Null;
 
-                let updated_json =
-                    serde_json::to_string_pretty(&account).map_err(|e| e.to_string())?;
-                std::fs::write(path, updated_json).map_err(|e| e.to_string())?;
+                crate::modules::account::update_account_json_at(path, |fresh| {
+                    fresh["validation_blocked"] = serde_json::json!(false);
+                    fresh["validation_blocked_until"] = serde_json::json!(0);
+                    fresh["validation_blocked_reason"] = serde_json::Value::Null;
+                    Ok(())
+                })?;
                 tracing::info!(
                     "Validation block expired and cleared for account: {}",
                     account
@@ -450,11 +551,12 @@ impl TokenManager {
             .ok_or("缺少 expiry_timestamp")?;
 
         // project_id 是可选的
+        // [NEW] 兼容存储为 Vertex 完整路径的 project_id
         let project_id = token_obj
             .get("project_id")
             .and_then(|v| v.as_str())
-            .filter(|s| !s.is_empty())
-            .map(|s| s.to_string());
+            .map(crate::proxy::project_resolver::normalize_project_id)
+            .filter(|s| !s.is_empty());
 
         // 【新增】提取订阅等级 (subscription_tier 为 "FREE" | "PRO" | "ULTRA")
         let subscription_tier = account
@@ -481,23 +583,31 @@ impl TokenManager {
             })
             .unwrap_or_default();
 
-        let health_score = self.health_scores.get(&account_id).map(|v| *v).unwrap_or(1.0);
+        let health_score = self.current_health_score(&account_id, chrono::Utc::now().timestamp());
 
         // [NEW] 提取最近的配额刷新时间（用于排序优化：刷新时间越近优先级越高）
         let reset_time = self.extract_earliest_reset_time(&account);
 
         // [OPTIMIZATION] 构建模型配额内存缓存，避免排序时读取磁盘
-        let mut model_quotas = HashMap::new();
-        if let Some(models) = account.get("quota").and_then(|q| q.get("models")).and_then(|m| m.as_array()) {
-            for model in models {
-                if let (Some(name), Some(pct)) = (model.get("name").and_then(|v| v.as_str()), model.get("percentage").and_then(|v| v.as_i64())) {
-                    // Normalize name to standard ID
-                    let standard_id = crate::proxy::common::model_mapping::normalize_to_standard_id(name)
-                        .unwrap_or_else(|| name.to_string());
-                    model_quotas.insert(standard_id, pct as i32);
-                }
-            }
-        }
+        let (model_quotas, model_quota_tokens, unparsed_quota_models) = Self::build_model_quota_cache(&account);
+
+        // [NEW] 读取信封覆盖项 (非法值会被忽略并记录警告)
+        let envelope = EnvelopeParams::from_overrides(
+            account.get("user_agent_override").and_then(|v| v.as_str()),
+            account.get("request_type_override").and_then(|v| v.as_str()),
+        );
+
+        // [NEW] 读取账号可访问的其他 project (用于请求级 project 覆盖校验)
+        let additional_project_ids: Vec<String> = account
+            .get("additional_project_ids")
+            .and_then(|v| v.as_array())
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|v| v.as_str())
+                    .map(|s| s.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
 
         Ok(Some(ProxyToken {
             account_id,
@@ -516,6 +626,19 @@ impl TokenManager {
             validation_blocked: account.get("validation_blocked").and_then(|v| v.as_bool()).unwrap_or(false),
             validation_blocked_until: account.get("validation_blocked_until").and_then(|v| v.as_i64()).unwrap_or(0),
             model_quotas,
+            model_quota_tokens,
+            unparsed_quota_models,
+            envelope,
+            additional_project_ids,
+            monthly_token_budget: account
+                .get("monthly_token_budget")
+                .and_then(|v| v.as_u64())
+                .filter(|b| *b > 0),
+            created_at: account.get("created_at").and_then(|v| v.as_i64()).unwrap_or(0),
+            group: account
+                .get("group")
+                .and_then(|v| v.as_str())
+                .and_then(|g| crate::proxy::account_groups::normalize_group(g).ok().flatten()),
         }))
     }
 
@@ -571,12 +694,18 @@ impl TokenManager {
         // 5. [重构] 聚合判定逻辑：按 Standard ID 对账号所有型号进行分组
         // 解决如 Pro-Low (0%) 和 Pro-High (100%) 在同一账号内导致状态冲突的问题
         let mut group_min_percentage: HashMap<String, i32> = HashMap::new();
+        // [NEW] 剩余百分比未知 (结构无法解析) 的分组，不据此触发或解除保护
+        let mut unknown_groups: HashSet<String> = HashSet::new();
 
         for model in models {
             let name = model.get("name").and_then(|v| v.as_str()).unwrap_or("");
-            let percentage = model.get("percentage").and_then(|v| v.as_i64()).unwrap_or(100) as i32;
+            let percentage = crate::models::quota::percentage_from_json(model);
 
             if let Some(std_id) = crate::proxy::common::model_mapping::normalize_to_standard_id(name) {
+                let Some(percentage) = percentage else {
+                    unknown_groups.insert(std_id);
+                    continue;
+                };
                 let entry = group_min_percentage.entry(std_id).or_insert(100);
                 if percentage < *entry {
                     *entry = percentage;
@@ -594,6 +723,9 @@ impl TokenManager {
         let mut changed = false;
 
         for std_id in &config.monitored_models {
+            if !group_min_percentage.contains_key(std_id) && unknown_groups.contains(std_id) {
+                continue;
+            }
             // 获取该组的最低百分比，如果账号没该组型号则视为 100%
             let min_pct = group_min_percentage.get(std_id).cloned().unwrap_or(100);
 
@@ -659,8 +791,7 @@ impl TokenManager {
         let mut has_data = false;
 
         for model in models {
-            if let Some(pct) = model.get("percentage").and_then(|v| v.as_i64()) {
-                let pct_i32 = pct as i32;
+            if let Some(pct_i32) = crate::models::quota::percentage_from_json(model) {
                 if pct_i32 > max_percentage {
                     max_percentage = pct_i32;
                 }
@@ -692,10 +823,7 @@ impl TokenManager {
                     .unwrap_or_else(|| name.to_string())
                     == model_name
                 {
-                    return model
-                        .get("percentage")
-                        .and_then(|v| v.as_i64())
-                        .map(|p| p as i32);
+                    return crate::models::quota::percentage_from_json(model);
                 }
             }
         }
@@ -741,9 +869,18 @@ impl TokenManager {
                 threshold
             );
 
-            // 3. 写入磁盘
-            std::fs::write(account_path, serde_json::to_string_pretty(account_json).unwrap())
-                .map_err(|e| format!("写入文件失败: {}", e))?;
+            // 3. 写入磁盘 (在账号锁内基于最新文件内容追加，避免覆盖并发写入)
+            crate::modules::account::update_account_json_at(account_path, |fresh| {
+                if !fresh.get("protected_models").map_or(false, |v| v.is_array()) {
+                    fresh["protected_models"] = serde_json::Value::Array(Vec::new());
+                }
+                let models = fresh["protected_models"].as_array_mut().unwrap();
+                if !models.iter().any(|m| m.as_str() == Some(model_name)) {
+                    models.push(serde_json::Value::String(model_name.to_string()));
+                }
+                Ok(())
+            })
+            .map_err(|e| format!("写入文件失败: {}", e))?;
 
             // [FIX] 触发 TokenManager 的账号重新加载信号，确保内存中的 protected_models 同步
             crate::proxy::server::trigger_account_reload(account_id);
@@ -784,16 +921,25 @@ impl TokenManager {
                 let name = model.get("name").and_then(|v| v.as_str()).unwrap_or("");
                 if !config.monitored_models.iter().any(|m| m == name) { continue; }
 
-                let percentage = model.get("percentage").and_then(|v| v.as_i64()).unwrap_or(0) as i32;
+                // 剩余百分比未知时不纳入保护
+                let Some(percentage) = crate::models::quota::percentage_from_json(model) else {
+                    continue;
+                };
                 if percentage <= threshold {
                     protected_list.push(serde_json::Value::String(name.to_string()));
                 }
             }
         }
 
-        account_json["protected_models"] = serde_json::Value::Array(protected_list);
+        account_json["protected_models"] = serde_json::Value::Array(protected_list.clone());
 
-        let _ = std::fs::write(account_path, serde_json::to_string_pretty(account_json).unwrap());
+        let _ = crate::modules::account::update_account_json_at(account_path, |fresh| {
+            fresh["proxy_disabled"] = serde_json::Value::Bool(false);
+            fresh["proxy_disabled_reason"] = serde_json::Value::Null;
+            fresh["proxy_disabled_at"] = serde_json::Value::Null;
+            fresh["protected_models"] = serde_json::Value::Array(protected_list);
+            Ok(())
+        });
 
         false // 返回 false 表示现在已可以尝试加载该账号（模型级过滤会在 get_token 时发生）
     }
@@ -820,10 +966,15 @@ impl TokenManager {
                     account_id,
                     model_name
                 );
-                std::fs::write(
-                    account_path,
-                    serde_json::to_string_pretty(account_json).unwrap(),
-                )
+                crate::modules::account::update_account_json_at(account_path, |fresh| {
+                    if let Some(models) = fresh
+                        .get_mut("protected_models")
+                        .and_then(|v| v.as_array_mut())
+                    {
+                        models.retain(|m| m.as_str() != Some(model_name));
+                    }
+                    Ok(())
+                })
                 .map_err(|e| format!("写入文件失败: {}", e))?;
                 return Ok(true);
             }
@@ -997,6 +1148,7 @@ impl TokenManager {
         session_id: Option<&str>,
         target_model: &str,
     ) -> Result<(String, String, String, String, u64), String> {
+        self.restore_expired_quota_lockouts();
         let mut tokens_snapshot: Vec<ProxyToken> =
             self.tokens.iter().map(|e| e.value().clone()).collect();
         let mut total = tokens_snapshot.len();
@@ -1004,6 +1156,14 @@ impl TokenManager {
             return Err("Token pool is empty".to_string());
         }
 
+        // [NEW] 账号分组过滤: 请求解析出分组时只在该分组的账号中选号
+        if let Some(group) = crate::proxy::account_groups::current() {
+            Self::retain_in_group(&mut tokens_snapshot, &group);
+            if tokens_snapshot.is_empty() {
+                return Err(format!("No accounts available in account group '{}'", group));
+            }
+        }
+
         // [NEW] 1. 动态能力过滤 (Capability Filter)
         
         // 定义常量
@@ -1019,7 +1179,10 @@ impl TokenManager {
         
         // 此处假设所有受支持的模型都会出现在 model_quotas 中
         // 如果 API 返回的配额信息不完整，可能会导致误杀，但为了严格性，我们执行此过滤
-        tokens_snapshot.retain(|t| t.model_quotas.contains_key(&normalized_target));
+        // [NEW] 配额结构无法解析的模型组同样视为拥有该模型
+        tokens_snapshot.retain(|t| {
+            t.model_quotas.contains_key(&normalized_target) || t.unparsed_quota_models.contains(&normalized_target)
+        });
 
         if tokens_snapshot.is_empty() {
             if candidate_count_before > 0 {
@@ -1030,6 +1193,82 @@ impl TokenManager {
             return Err("Token pool is empty".to_string());
         }
 
+        // [NEW] 跳过内存中仍处于临时屏蔽期的账号 (VALIDATION_REQUIRED)
+        // 屏蔽写入磁盘后无需等待重新加载即可生效
+        let now_ts = chrono::Utc::now().timestamp();
+        tokens_snapshot.retain(|t| !(t.validation_blocked && t.validation_blocked_until > now_ts));
+        if tokens_snapshot.is_empty() {
+            return Err(format!(
+                "All accounts available for model {} are temporarily blocked",
+                normalized_target
+            ));
+        }
+
+        // [NEW] 新账号预热: 预热期内的账号只参与低成本模型组的选号
+        let warmup = crate::proxy::config::get_account_warmup_config();
+        Self::retain_warmup_eligible(&mut tokens_snapshot, &warmup, &normalized_target, now_ts);
+        if tokens_snapshot.is_empty() {
+            return Err(format!(
+                "All accounts available for model {} are still warming up",
+                normalized_target
+            ));
+        }
+        total = tokens_snapshot.len();
+
+        // [NEW] 最低健康分过滤: 使用实时健康分 (快照中的值仅在加载账号时更新)
+        let min_health_score = self.sticky_config.read().await.min_health_score;
+        if min_health_score > 0.0 {
+            for t in tokens_snapshot.iter_mut() {
+                t.health_score = self.current_health_score(&t.account_id, now_ts);
+            }
+            Self::retain_healthy(&mut tokens_snapshot, min_health_score);
+            if tokens_snapshot.is_empty() {
+                return Err(format!(
+                    "All accounts available for model {} are below the minimum health score {:.2}",
+                    normalized_target, min_health_score
+                ));
+            }
+            total = tokens_snapshot.len();
+        }
+
+        // [NEW] 月度 token 预算过滤 (独立于配额百分比，仅在存在设置了预算的账号时查询统计库)
+        if tokens_snapshot.iter().any(|t| t.monthly_token_budget.is_some()) {
+            match self.monthly_token_usage().await {
+                Ok(usage) => {
+                    Self::retain_within_budget(&mut tokens_snapshot, &usage);
+                    if tokens_snapshot.is_empty() {
+                        tracing::warn!("All candidate accounts exceeded their monthly token budget");
+                        return Err(format!(
+                            "All accounts available for model {} have exceeded their monthly token budget",
+                            normalized_target
+                        ));
+                    }
+                    total = tokens_snapshot.len();
+                }
+                Err(e) => {
+                    // 统计库不可用时不阻断请求
+                    tracing::warn!("Failed to load monthly token usage, skipping budget check: {}", e);
+                }
+            }
+        }
+
+        // [NEW] 并发流上限: 优先跳过已达上限的账号；全部饱和时保留所有候选，由 acquire_stream_slot 排队
+        // 预热期账号的上限按比例降低
+        let max_streams = crate::proxy::config::get_max_concurrent_streams_per_account();
+        let is_saturated = |t: &ProxyToken| {
+            let limit = Self::stream_limit_for(t, &warmup, max_streams, now_ts);
+            self.stream_slots.is_saturated(&t.account_id, limit)
+        };
+        if tokens_snapshot.iter().any(|t| !is_saturated(t)) {
+            tokens_snapshot.retain(|t| !is_saturated(t));
+            total = tokens_snapshot.len();
+        }
+
+        // [NEW] 所有候选都提供绝对剩余 token 时才按绝对值排序，否则统一按百分比，保证比较关系可传递
+        let use_absolute_quota = tokens_snapshot
+            .iter()
+            .all(|t| t.model_quota_tokens.contains_key(&normalized_target));
+
         tokens_snapshot.sort_by(|a, b| {
             // Priority 0: 严格的订阅等级排序 (ULTRA > PRO > FREE)
             // 用户要求：轮询应当遵循 Ultra -> Pro -> Free
@@ -1050,11 +1289,7 @@ impl TokenManager {
             }
 
             // Priority 1: 目标模型的 quota (higher is better) -> 保护低配额账号
-            // 经过过滤，key 肯定存在
-            let quota_a = a.model_quotas.get(&normalized_target).copied().unwrap_or(0);
-            let quota_b = b.model_quotas.get(&normalized_target).copied().unwrap_or(0);
-
-            let quota_cmp = quota_b.cmp(&quota_a);
+            let quota_cmp = Self::compare_model_quota(a, b, &normalized_target, use_absolute_quota);
             if quota_cmp != std::cmp::Ordering::Equal {
                 return quota_cmp;
             }
@@ -1173,32 +1408,10 @@ impl TokenManager {
                     // 直接使用优先账号，跳过轮询逻辑
                     let mut token = preferred_token.clone();
 
-                    // 检查 token 是否过期（提前5分钟刷新）
-                    let now = chrono::Utc::now().timestamp();
-                    if now >= token.timestamp - 300 {
-                        tracing::debug!("账号 {} 的 token 即将过期，正在刷新...", token.email);
-                        match crate::modules::oauth::refresh_access_token(&token.refresh_token, Some(&token.account_id))
-                            .await
-                        {
-                            Ok(token_response) => {
-                                token.access_token = token_response.access_token.clone();
-                                token.expires_in = token_response.expires_in;
-                                token.timestamp = now + token_response.expires_in;
-
-                                if let Some(mut entry) = self.tokens.get_mut(&token.account_id) {
-                                    entry.access_token = token.access_token.clone();
-                                    entry.expires_in = token.expires_in;
-                                    entry.timestamp = token.timestamp;
-                                }
-                                let _ = self
-                                    .save_refreshed_token(&token.account_id, &token_response)
-                                    .await;
-                            }
-                            Err(e) => {
-                                tracing::warn!("Preferred account token refresh failed: {}", e);
-                                // 继续使用旧 token，让后续逻辑处理失败
-                            }
-                        }
+                    // 检查 token 是否即将过期（提前量可配置）
+                    if let Err(e) = self.refresh_ahead(&mut token).await {
+                        tracing::warn!("Preferred account token refresh failed: {}", e);
+                        // 继续使用旧 token，让后续逻辑处理失败
                     }
 
                     // 确保有 project_id (filter empty strings to trigger re-fetch)
@@ -1252,10 +1465,22 @@ impl TokenManager {
         let mut attempted: HashSet<String> = HashSet::new();
         let mut last_error: Option<String> = None;
         let mut need_update_last_used: Option<(String, std::time::Instant)> = None;
+        let recently_failed = self.recently_failed_accounts(std::time::Duration::from_secs(
+            crate::proxy::config::get_recent_failure_window_secs(),
+        ));
 
         for attempt in 0..total {
             let rotate = force_rotate || attempt > 0;
 
+            // [NEW] 近期失败的账号暂不参与本轮选号；其余账号均已尝试过时再回退到它们
+            let excluded: HashSet<String> = if tokens_snapshot.iter().any(|t| {
+                !attempted.contains(&t.account_id) && !recently_failed.contains(&t.account_id)
+            }) {
+                attempted.union(&recently_failed).cloned().collect()
+            } else {
+                attempted.clone()
+            };
+
             // ===== 【核心】粘性会话与智能调度逻辑 =====
             let mut target_token: Option<ProxyToken> = None;
 
@@ -1263,8 +1488,22 @@ impl TokenManager {
             let normalized_target = crate::proxy::common::model_mapping::normalize_to_standard_id(target_model)
                 .unwrap_or_else(|| target_model.to_string());
 
+            // 模式 0: [NEW] 临时优先账号 (非独占，仍遵守限流与配额保护；轮换时让位于其他账号)
+            if !rotate {
+                target_token = self
+                    .preferred_override_candidate(
+                        &tokens_snapshot,
+                        &excluded,
+                        &normalized_target,
+                        quota_protection_enabled,
+                        chrono::Utc::now().timestamp(),
+                    )
+                    .await;
+            }
+
             // 模式 A: 粘性会话处理 (CacheFirst 或 Balance 且有 session_id)
-            if !rotate
+            if target_token.is_none()
+                && !rotate
                 && session_id.is_some()
                 && scheduling.mode != SchedulingMode::PerformanceFirst
             {
@@ -1290,7 +1529,18 @@ impl TokenManager {
                                 bound_token.email, reset_sec
                             );
                             self.session_accounts.remove(sid);
-                        } else if !attempted.contains(&bound_id)
+                        } else if crate::proxy::account_warmup::is_warming_up(
+                            &warmup,
+                            bound_token.created_at,
+                            now_ts,
+                        ) {
+                            // [NEW] 预热期账号不作为粘性绑定 (预热策略在绑定后开启时解绑)
+                            tracing::debug!(
+                                "Sticky Session: Bound account {} is warming up, unbinding",
+                                bound_token.email
+                            );
+                            self.session_accounts.remove(sid);
+                        } else if !excluded.contains(&bound_id)
                             && !(quota_protection_enabled
                                 && bound_token.protected_models.contains(&normalized_target))
                         {
@@ -1324,7 +1574,7 @@ impl TokenManager {
                 // 【优化】使用预先获取的快照，不再在循环内加锁
                 if let Some((account_id, last_time)) = &last_used_account_id {
                     // [FIX #3] 60s 锁定逻辑应检查 `attempted` 集合，避免重复尝试失败的账号
-                    if last_time.elapsed().as_secs() < 60 && !attempted.contains(account_id) {
+                    if last_time.elapsed().as_secs() < 60 && !excluded.contains(account_id) {
                         if let Some(found) =
                             tokens_snapshot.iter().find(|t| &t.account_id == account_id)
                         {
@@ -1368,14 +1618,21 @@ impl TokenManager {
                     }
 
                     if let Some(selected) = self.select_with_p2c(
-                        &non_limited, &attempted, &normalized_target, quota_protection_enabled
+                        &non_limited, &excluded, &normalized_target, quota_protection_enabled
                     ) {
                         target_token = Some(selected.clone());
                         need_update_last_used = Some((selected.account_id.clone(), std::time::Instant::now()));
 
                         // 如果是会话首次分配且需要粘性，在此建立绑定
                         if let Some(sid) = session_id {
-                            if scheduling.mode != SchedulingMode::PerformanceFirst {
+                            // [NEW] 预热期账号只服务单次请求，不为会话建立绑定
+                            if scheduling.mode != SchedulingMode::PerformanceFirst
+                                && !crate::proxy::account_warmup::is_warming_up(
+                                    &warmup,
+                                    selected.created_at,
+                                    now_ts,
+                                )
+                            {
                                 self.session_accounts
                                     .insert(sid.to_string(), selected.account_id.clone());
                                 tracing::debug!(
@@ -1403,7 +1660,7 @@ impl TokenManager {
                 }
 
                 if let Some(selected) = self.select_with_p2c(
-                    &non_limited, &attempted, &normalized_target, quota_protection_enabled
+                    &non_limited, &excluded, &normalized_target, quota_protection_enabled
                 ) {
                     tracing::debug!("  {} - SELECTED via P2C", selected.email);
                     target_token = Some(selected.clone());
@@ -1438,7 +1695,7 @@ impl TokenManager {
 
                             // 重新尝试选择账号
                             let retry_token = tokens_snapshot.iter()
-                                .find(|t| !attempted.contains(&t.account_id) 
+                                .find(|t| !excluded.contains(&t.account_id) 
                                     && !self.is_rate_limited_sync(&t.account_id, Some(&normalized_target))
                                     && !(quota_protection_enabled && t.protected_models.contains(&normalized_target)));
 
@@ -1461,7 +1718,7 @@ impl TokenManager {
                                 // 再次尝试选择账号
                                 let final_token = tokens_snapshot
                                     .iter()
-                                    .find(|t| !attempted.contains(&t.account_id)
+                                    .find(|t| !excluded.contains(&t.account_id)
                                         && !(quota_protection_enabled && t.protected_models.contains(&normalized_target)));
 
                                 if let Some(t) = final_token {
@@ -1508,67 +1765,36 @@ impl TokenManager {
                 OnDiskAccountState::Enabled => {}
             }
 
-            // 3. 检查 token 是否过期（提前5分钟刷新）
-            let now = chrono::Utc::now().timestamp();
-            if now >= token.timestamp - 300 {
-                tracing::debug!("账号 {} 的 token 即将过期，正在刷新...", token.email);
-
-                // 调用 OAuth 刷新 token
-                match crate::modules::oauth::refresh_access_token(&token.refresh_token, Some(&token.account_id)).await {
-                    Ok(token_response) => {
-                        tracing::debug!("Token 刷新成功！");
-
-                        // 更新本地内存对象供后续使用
-                        token.access_token = token_response.access_token.clone();
-                        token.expires_in = token_response.expires_in;
-                        token.timestamp = now + token_response.expires_in;
-
-                        // 同步更新跨线程共享的 DashMap
-                        if let Some(mut entry) = self.tokens.get_mut(&token.account_id) {
-                            entry.access_token = token.access_token.clone();
-                            entry.expires_in = token.expires_in;
-                            entry.timestamp = token.timestamp;
-                        }
-
-                        // 同步落盘（避免重启后继续使用过期 timestamp 导致频繁刷新）
-                        if let Err(e) = self
-                            .save_refreshed_token(&token.account_id, &token_response)
-                            .await
-                        {
-                            tracing::debug!("保存刷新后的 token 失败 ({}): {}", token.email, e);
-                        }
-                    }
-                    Err(e) => {
-                        tracing::error!("Token 刷新失败 ({}): {}，尝试下一个账号", token.email, e);
-                        if e.contains("\"invalid_grant\"") || e.contains("invalid_grant") {
-                            tracing::error!(
-                                "Disabling account due to invalid_grant ({}): refresh_token likely revoked/expired",
-                                token.email
-                            );
-                            let _ = self
-                                .disable_account(
-                                    &token.account_id,
-                                    &format!("invalid_grant: {}", e),
-                                )
-                                .await;
-                            self.tokens.remove(&token.account_id);
-                        }
-                        // Avoid leaking account emails to API clients; details are still in logs.
-                        last_error = Some(format!("Token refresh failed: {}", e));
-                        attempted.insert(token.account_id.clone());
+            // 3. 检查 token 是否即将过期（提前量可配置，避免使用中途 401）
+            if let Err(e) = self.refresh_ahead(&mut token).await {
+                tracing::error!("Token 刷新失败 ({}): {}，尝试下一个账号", token.email, e);
+                if e.contains("\"invalid_grant\"") || e.contains("invalid_grant") {
+                    tracing::error!(
+                        "Disabling account due to invalid_grant ({}): refresh_token likely revoked/expired",
+                        token.email
+                    );
+                    let _ = self
+                        .disable_account(
+                            &token.account_id,
+                            &format!("invalid_grant: {}", e),
+                        )
+                        .await;
+                    self.tokens.remove(&token.account_id);
+                }
+                // Avoid leaking account emails to API clients; details are still in logs.
+                last_error = Some(format!("Token refresh failed: {}", e));
+                attempted.insert(token.account_id.clone());
 
-                        // 【优化】标记需要清除锁定，避免在循环内加锁
-                        if quota_group != "image_gen" {
-                            if matches!(&last_used_account_id, Some((id, _)) if id == &token.account_id)
-                            {
-                                need_update_last_used =
-                                    Some((String::new(), std::time::Instant::now()));
-                                // 空字符串表示需要清除
-                            }
-                        }
-                        continue;
+                // 【优化】标记需要清除锁定，避免在循环内加锁
+                if quota_group != "image_gen" {
+                    if matches!(&last_used_account_id, Some((id, _)) if id == &token.account_id)
+                    {
+                        need_update_last_used =
+                            Some((String::new(), std::time::Instant::now()));
+                        // 空字符串表示需要清除
                     }
                 }
+                continue;
             }
 
             // 4. 确保有 project_id (filter empty strings to trigger re-fetch)
@@ -1628,18 +1854,13 @@ impl TokenManager {
                 .join(format!("{}.json", account_id))
         };
 
-        let mut content: serde_json::Value = serde_json::from_str(
-            &std::fs::read_to_string(&path).map_err(|e| format!("读取文件失败: {}", e))?,
-        )
-        .map_err(|e| format!("解析 JSON 失败: {}", e))?;
-
         let now = chrono::Utc::now().timestamp();
-        content["disabled"] = serde_json::Value::Bool(true);
-        content["disabled_at"] = serde_json::Value::Number(now.into());
-        content["disabled_reason"] = serde_json::Value::String(truncate_reason(reason, 800));
-
-        std::fs::write(&path, serde_json::to_string_pretty(&content).unwrap())
-            .map_err(|e| format!("写入文件失败: {}", e))?;
+        crate::modules::account::update_account_json_at(&path, |content| {
+            content["disabled"] = serde_json::Value::Bool(true);
+            content["disabled_at"] = serde_json::Value::Number(now.into());
+            content["disabled_reason"] = serde_json::Value::String(truncate_reason(reason, 800));
+            Ok(())
+        })?;
 
         // 【修复 Issue #3】从内存中移除禁用的账号，防止被60s锁定逻辑继续使用
         self.tokens.remove(account_id);
@@ -1653,16 +1874,13 @@ impl TokenManager {
         let entry = self.tokens.get(account_id)
             .ok_or("账号不存在")?;
 
-        let path = &entry.account_path;
-
-        let mut content: serde_json::Value = serde_json::from_str(
-            &std::fs::read_to_string(path).map_err(|e| format!("读取文件失败: {}", e))?
-        ).map_err(|e| format!("解析 JSON 失败: {}", e))?;
+        let path = entry.account_path.clone();
+        drop(entry);
 
-        content["token"]["project_id"] = serde_json::Value::String(project_id.to_string());
-
-        std::fs::write(path, serde_json::to_string_pretty(&content).unwrap())
-            .map_err(|e| format!("写入文件失败: {}", e))?;
+        crate::modules::account::update_account_json_at(&path, |content| {
+            content["token"]["project_id"] = serde_json::Value::String(project_id.to_string());
+            Ok(())
+        })?;
 
         tracing::debug!("已保存 project_id 到账号 {}", account_id);
         Ok(())
@@ -1673,111 +1891,121 @@ impl TokenManager {
         let entry = self.tokens.get(account_id)
             .ok_or("账号不存在")?;
 
-        let path = &entry.account_path;
-
-        let mut content: serde_json::Value = serde_json::from_str(
-            &std::fs::read_to_string(path).map_err(|e| format!("读取文件失败: {}", e))?
-        ).map_err(|e| format!("解析 JSON 失败: {}", e))?;
+        let path = entry.account_path.clone();
+        drop(entry);
 
         let now = chrono::Utc::now().timestamp();
 
-        content["token"]["access_token"] = serde_json::Value::String(token_response.access_token.clone());
-        content["token"]["expires_in"] = serde_json::Value::Number(token_response.expires_in.into());
-        content["token"]["expiry_timestamp"] = serde_json::Value::Number((now + token_response.expires_in).into());
-
-        std::fs::write(path, serde_json::to_string_pretty(&content).unwrap())
-            .map_err(|e| format!("写入文件失败: {}", e))?;
+        crate::modules::account::update_account_json_at(&path, |content| {
+            content["token"]["access_token"] = serde_json::Value::String(token_response.access_token.clone());
+            content["token"]["expires_in"] = serde_json::Value::Number(token_response.expires_in.into());
+            content["token"]["expiry_timestamp"] = serde_json::Value::Number((now + token_response.expires_in).into());
+            // [NEW] 上游轮换了 refresh_token 时一并落盘
+            if let Some(refresh_token) = &token_response.refresh_token {
+                content["token"]["refresh_token"] = serde_json::Value::String(refresh_token.clone());
+            }
+            Ok(())
+        })?;
 
         tracing::debug!("已保存刷新后的 token 到账号 {}", account_id);
         Ok(())
     }
 
+    /// [NEW] 预刷新: token 在 token_refresh_ahead_secs 窗口内到期时先刷新再使用，并落盘
+    /// 返回是否执行了刷新；刷新失败时返回错误，由调用方决定是否换号
+    async fn refresh_ahead(&self, token: &mut ProxyToken) -> Result<bool, String> {
+        let window_secs = crate::proxy::config::get_token_refresh_ahead_secs() as i64;
+        self.refresh_ahead_with(token, window_secs, |refresh_token, account_id| async move {
+            crate::modules::oauth::refresh_access_token(&refresh_token, Some(&account_id)).await
+        })
+        .await
+    }
+
+    async fn refresh_ahead_with<F, Fut>(
+        &self,
+        token: &mut ProxyToken,
+        window_secs: i64,
+        refresh: F,
+    ) -> Result<bool, String>
+    where
+        F: FnOnce(String, String) -> Fut,
+        Fut: std::future::Future<Output = Result<crate::modules::oauth::TokenResponse, String>>,
+    {
+        let now = chrono::Utc::now().timestamp();
+        if !token.expires_within(window_secs, now) {
+            return Ok(false);
+        }
+
+        tracing::debug!(
+            "账号 {} 的 token 将在 {}s 内过期，正在刷新...",
+            token.email,
+            token.timestamp - now
+        );
+        let token_response = refresh(token.refresh_token.clone(), token.account_id.clone()).await?;
+        tracing::debug!("Token 刷新成功！");
+
+        // 更新本地内存对象供后续使用
+        token.access_token = token_response.access_token.clone();
+        token.expires_in = token_response.expires_in;
+        token.timestamp = now + token_response.expires_in;
+        if let Some(refresh_token) = &token_response.refresh_token {
+            token.refresh_token = refresh_token.clone();
+        }
+
+        // 同步更新跨线程共享的 DashMap
+        if let Some(mut entry) = self.tokens.get_mut(&token.account_id) {
+            entry.access_token = token.access_token.clone();
+            entry.expires_in = token.expires_in;
+            entry.timestamp = token.timestamp;
+            entry.refresh_token = token.refresh_token.clone();
+        }
+
+        // 同步落盘（避免重启后继续使用过期 timestamp 导致频繁刷新）
+        if let Err(e) = self
+            .save_refreshed_token(&token.account_id, &token_response)
+            .await
+        {
+            tracing::debug!("保存刷新后的 token 失败 ({}): {}", token.email, e);
+        }
+        Ok(true)
+    }
+
     pub fn len(&self) -> usize {
         self.tokens.len()
     }
 
     /// 通过 email 获取指定账号的 Token（用于预热等需要指定账号的场景）
-    /// 此方法会自动刷新过期的 token
+    /// 此方法会自动刷新即将过期的 token
     pub async fn get_token_by_email(
         &self,
         email: &str,
     ) -> Result<(String, String, String, String, u64), String> {
         // 查找账号信息
-        let token_info = {
-            let mut found = None;
-            for entry in self.tokens.iter() {
-                let token = entry.value();
-                if token.email == email {
-                    found = Some((
-                        token.account_id.clone(),
-                        token.access_token.clone(),
-                        token.refresh_token.clone(),
-                        token.timestamp,
-                        token.expires_in,
-                        chrono::Utc::now().timestamp(),
-                        token.project_id.clone(),
-                    ));
-                    break;
-                }
-            }
-            found
-        };
-
-        let (
-            account_id,
-            current_access_token,
-            refresh_token,
-            timestamp,
-            expires_in,
-            now,
-            project_id_opt,
-        ) = match token_info {
-            Some(info) => info,
-            None => return Err(format!("未找到账号: {}", email)),
-        };
+        let mut token = self
+            .tokens
+            .iter()
+            .find(|entry| entry.value().email == email)
+            .map(|entry| entry.value().clone())
+            .ok_or_else(|| format!("未找到账号: {}", email))?;
 
-        let project_id = project_id_opt
+        let project_id = token
+            .project_id
+            .clone()
             .filter(|s| !s.is_empty())
             .unwrap_or_else(|| "bamboo-precept-lgxtn".to_string());
 
-        // 检查是否过期 (提前5分钟)
-        if now < timestamp + expires_in - 300 {
-            return Ok((current_access_token, project_id, email.to_string(), account_id, 0));
-        }
-
-        tracing::info!("[Warmup] Token for {} is expiring, refreshing...", email);
-
-        // 调用 OAuth 刷新 token
-        match crate::modules::oauth::refresh_access_token(&refresh_token, Some(&account_id)).await {
-            Ok(token_response) => {
-                tracing::info!("[Warmup] Token refresh successful for {}", email);
-                let new_now = chrono::Utc::now().timestamp();
-
-                // 更新缓存
-                if let Some(mut entry) = self.tokens.get_mut(&account_id) {
-                    entry.access_token = token_response.access_token.clone();
-                    entry.expires_in = token_response.expires_in;
-                    entry.timestamp = new_now;
-                }
-
-                // 保存到磁盘
-                let _ = self
-                    .save_refreshed_token(&account_id, &token_response)
-                    .await;
-
-                Ok((
-                    token_response.access_token,
-                    project_id,
-                    email.to_string(),
-                    account_id,
-                    0,
+        match self.refresh_ahead(&mut token).await {
+            Ok(true) => tracing::info!("[Warmup] Token refresh successful for {}", email),
+            Ok(false) => {}
+            Err(e) => {
+                return Err(format!(
+                    "[Warmup] Token refresh failed for {}: {}",
+                    email, e
                 ))
             }
-            Err(e) => Err(format!(
-                "[Warmup] Token refresh failed for {}: {}",
-                email, e
-            )),
         }
+
+        Ok((token.access_token, project_id, email.to_string(), token.account_id, 0))
     }
 
     // ===== 限流管理方法 =====
@@ -1810,6 +2038,22 @@ impl TokenManager {
         );
     }
 
+    /// [NEW] 429 轮换: 在内存中按模型临时锁定账号 (不落盘，不影响该账号的其他模型)
+    /// 已有更长的锁定 (如 mark_rate_limited_async 解析出的配额刷新时间) 时保持不变
+    pub fn lock_model_for_rotation(&self, account_id: &str, model: &str, secs: u64) {
+        let normalized = crate::proxy::common::model_mapping::normalize_to_standard_id(model)
+            .unwrap_or_else(|| model.to_string());
+        if self.rate_limit_tracker.get_remaining_wait(account_id, Some(&normalized)) >= secs {
+            return;
+        }
+        self.rate_limit_tracker.set_lockout_until(
+            account_id,
+            std::time::SystemTime::now() + std::time::Duration::from_secs(secs),
+            crate::proxy::rate_limit::RateLimitReason::RateLimitExceeded,
+            Some(normalized),
+        );
+    }
+
     /// 检查账号是否在限流中 (支持模型级)
     pub async fn is_rate_limited(&self, account_id: &str, model: Option<&str>) -> bool {
         // [NEW] 检查熔断是否启用
@@ -1867,6 +2111,21 @@ impl TokenManager {
     /// 下次失败时从最短的锁定时间开始（智能限流）。
     pub fn mark_account_success(&self, account_id: &str) {
         self.rate_limit_tracker.mark_success(account_id);
+        // [NEW] 成功后立即退出近期失败列表
+        self.recent_failures.remove(account_id);
+    }
+
+    /// [NEW] 记录账号刚刚失败，短期内选号时优先跳过 (与熔断锁定独立)
+    pub fn mark_recent_failure(&self, account_id: &str) {
+        self.recent_failures
+            .insert(account_id.to_string(), std::time::Instant::now());
+    }
+
+    /// [NEW] 窗口内失败过的账号集合 (顺带清理过期记录)
+    fn recently_failed_accounts(&self, window: std::time::Duration) -> HashSet<String> {
+        self.recent_failures
+            .retain(|_, failed_at| !window.is_zero() && failed_at.elapsed() < window);
+        self.recent_failures.iter().map(|e| e.key().clone()).collect()
     }
 
     /// 检查是否有可用的 Google 账号
@@ -2078,18 +2337,41 @@ impl TokenManager {
         error_body: &str,
         model: Option<&str>, // 🆕 新增模型参数
     ) {
+        // [FIX] Convert email to account_id for consistent tracking
+        let account_id = self.email_to_account_id(email).unwrap_or_else(|| email.to_string());
+
+        // [NEW] 近期失败回避不受熔断开关影响
+        self.mark_recent_failure(&account_id);
+
         // [NEW] 检查熔断是否启用
         let config = self.circuit_breaker_config.read().await.clone();
         if !config.enabled {
             return;
         }
 
-        // [FIX] Convert email to account_id for consistent tracking
-        let account_id = self.email_to_account_id(email).unwrap_or_else(|| email.to_string());
+        // [NEW] 解析结构化错误细节 (RetryInfo / QuotaFailure)
+        // 上游指明的受限模型优先，并归一化为候选筛选使用的标准 ID，保证锁定 Key 与检查 Key 一致
+        let quota_details = if status == 429 {
+            self.rate_limit_tracker.parse_quota_details(error_body)
+        } else {
+            Default::default()
+        };
+        let normalized_model = quota_details.model.as_deref().or(model).map(|m| {
+            crate::proxy::common::model_mapping::normalize_to_standard_id(m)
+                .unwrap_or_else(|| m.to_string())
+        });
+        let model = normalized_model.as_deref();
+
+        if quota_details.model.is_some() {
+            if let Some(m) = model {
+                self.record_model_quota_exhausted(&account_id, m);
+            }
+        }
 
         // 检查 API 是否返回了精确的重试时间
-        let has_explicit_retry_time = retry_after_header.is_some() ||
-            error_body.contains("quotaResetDelay");
+        let has_explicit_retry_time = retry_after_header.is_some()
+            || quota_details.retry_delay_secs.is_some()
+            || error_body.contains("quotaResetDelay");
 
         if has_explicit_retry_time {
             // API 返回了精确时间(quotaResetDelay),直接使用,无需实时刷新
@@ -2165,38 +2447,189 @@ impl TokenManager {
         );
     }
 
-    // ===== 调度配置相关方法 =====
-
-    /// 获取当前调度配置
-    pub async fn get_sticky_config(&self) -> StickySessionConfig {
-        self.sticky_config.read().await.clone()
+    /// [NEW] 目标模型剩余配额比较 (剩余多者排前)
+    /// `use_absolute` 由调用方对整个候选集统一决定 (全部提供绝对剩余 token 时为 true)，
+    /// 同一次排序只使用一种键，避免混合比较导致排序关系不可传递
+    fn compare_model_quota(
+        a: &ProxyToken,
+        b: &ProxyToken,
+        target: &str,
+        use_absolute: bool,
+    ) -> std::cmp::Ordering {
+        if use_absolute {
+            let tokens_a = a.model_quota_tokens.get(target).copied().unwrap_or(0);
+            let tokens_b = b.model_quota_tokens.get(target).copied().unwrap_or(0);
+            return tokens_b.cmp(&tokens_a);
+        }
+        let quota_a = a.model_quotas.get(target).copied().unwrap_or(0);
+        let quota_b = b.model_quotas.get(target).copied().unwrap_or(0);
+        quota_b.cmp(&quota_a)
     }
 
-    /// 更新调度配置
-    pub async fn update_sticky_config(&self, new_config: StickySessionConfig) {
-        let mut config = self.sticky_config.write().await;
-        *config = new_config;
-        tracing::debug!("Scheduling configuration updated: {:?}", *config);
+    /// 仅保留属于指定分组的账号
+    fn retain_in_group(tokens: &mut Vec<ProxyToken>, group: &str) {
+        tokens.retain(|t| crate::proxy::account_groups::account_in_group(t.group.as_deref(), group));
     }
 
-    /// [NEW] 更新熔断器配置
-    pub async fn update_circuit_breaker_config(&self, config: crate::models::CircuitBreakerConfig) {
-        let mut lock = self.circuit_breaker_config.write().await;
-        *lock = config;
-        tracing::debug!("Circuit breaker configuration updated");
-    }
+    /// 本月各账号已用 token 数 (缓存 BUDGET_USAGE_TTL，过期后在阻塞线程池中重新查询统计库)
+    async fn monthly_token_usage(&self) -> Result<Arc<HashMap<String, u64>>, String> {
+        let month_start = crate::modules::token_stats::month_start_ts(chrono::Utc::now());
+        if let Some(snapshot) = self.budget_usage.lock().as_ref() {
+            if snapshot.month_start == month_start && snapshot.fetched_at.elapsed() < BUDGET_USAGE_TTL {
+                return Ok(snapshot.usage.clone());
+            }
+        }
 
-    /// [NEW] 获取熔断器配置
-    pub async fn get_circuit_breaker_config(&self) -> crate::models::CircuitBreakerConfig {
-        self.circuit_breaker_config.read().await.clone()
+        let usage = tokio::task::spawn_blocking(move || {
+            crate::modules::token_stats::get_account_totals_since(month_start)
+        })
+        .await
+        .map_err(|e| format!("Token usage query task failed: {}", e))??;
+        let usage = Arc::new(usage);
+        *self.budget_usage.lock() = Some(BudgetUsageSnapshot {
+            month_start,
+            fetched_at: std::time::Instant::now(),
+            usage: usage.clone(),
+        });
+        Ok(usage)
     }
 
-    /// 清除特定会话的粘性映射
-    #[allow(dead_code)]
-    pub fn clear_session_binding(&self, session_id: &str) {
+    /// 移除本月用量已达到预算的账号
+    /// `usage`: account_email -> 本月已用 token 总数
+    fn retain_within_budget(tokens: &mut Vec<ProxyToken>, usage: &HashMap<String, u64>) {
+        tokens.retain(|t| match t.monthly_token_budget {
+            Some(budget) => {
+                let used = usage.get(&t.email).copied().unwrap_or(0);
+                if used >= budget {
+                    tracing::debug!(
+                        "Account {} exceeded monthly token budget ({}/{}), skipping",
+                        t.email, used, budget
+                    );
+                    false
+                } else {
+                    true
+                }
+            }
+            None => true,
+        });
+    }
+
+    /// 移除预热期内且目标模型组不在预热白名单中的账号
+    fn retain_warmup_eligible(
+        tokens: &mut Vec<ProxyToken>,
+        warmup: &crate::proxy::config::AccountWarmupConfig,
+        normalized_target: &str,
+        now: i64,
+    ) {
+        if !warmup.enabled || crate::proxy::account_warmup::allows_model(warmup, normalized_target) {
+            return;
+        }
+        tokens.retain(|t| {
+            let warming = crate::proxy::account_warmup::is_warming_up(warmup, t.created_at, now);
+            if warming {
+                tracing::debug!(
+                    "Account {} is warming up, skipping for model {}",
+                    t.email,
+                    normalized_target
+                );
+            }
+            !warming
+        });
+    }
+
+    /// 在内存配额缓存中将 (账号, 模型) 标记为耗尽
+    /// 锁定结束后由 restore_expired_quota_lockouts 恢复原值；期间从磁盘重载账号时会被真实配额覆盖
+    fn record_model_quota_exhausted(&self, account_id: &str, model: &str) {
+        if let Some(mut token) = self.tokens.get_mut(account_id) {
+            let lockout = QuotaLockout {
+                previous_percentage: token.model_quotas.get(model).copied(),
+                previous_tokens: token.model_quota_tokens.get(model).copied(),
+            };
+            // 重复 429 不覆盖首次记录的原值
+            self.quota_lockouts
+                .entry((account_id.to_string(), model.to_string()))
+                .or_insert(lockout);
+            token.model_quotas.insert(model.to_string(), 0);
+            if let Some(tokens) = token.model_quota_tokens.get_mut(model) {
+                *tokens = 0;
+            }
+            tracing::debug!("账号 {} 的模型 {} 已在配额缓存中标记为耗尽", account_id, model);
+        }
+    }
+
+    /// 限流窗口已结束的 (账号, 模型) 恢复锁定前的配额缓存 (选号前调用)
+    /// 缓存已被重载的真实配额覆盖 (不再为 0) 时保持不变
+    fn restore_expired_quota_lockouts(&self) {
+        if self.quota_lockouts.is_empty() {
+            return;
+        }
+        let expired: Vec<(String, String)> = self
+            .quota_lockouts
+            .iter()
+            .filter(|e| !self.rate_limit_tracker.is_rate_limited(&e.key().0, Some(&e.key().1)))
+            .map(|e| e.key().clone())
+            .collect();
+        for key in expired {
+            let Some(((account_id, model), lockout)) = self.quota_lockouts.remove(&key) else {
+                continue;
+            };
+            let Some(mut token) = self.tokens.get_mut(&account_id) else {
+                continue;
+            };
+            if token.model_quotas.get(&model) == Some(&0) {
+                match lockout.previous_percentage {
+                    Some(pct) => token.model_quotas.insert(model.clone(), pct),
+                    None => token.model_quotas.remove(&model),
+                };
+            }
+            if token.model_quota_tokens.get(&model) == Some(&0) {
+                if let Some(tokens) = lockout.previous_tokens {
+                    token.model_quota_tokens.insert(model.clone(), tokens);
+                }
+            }
+            tracing::debug!("账号 {} 的模型 {} 限流窗口已结束，恢复配额缓存", account_id, model);
+        }
+    }
+
+    // ===== 调度配置相关方法 =====
+
+    /// 获取当前调度配置
+    pub async fn get_sticky_config(&self) -> StickySessionConfig {
+        self.sticky_config.read().await.clone()
+    }
+
+    /// 更新调度配置
+    pub async fn update_sticky_config(&self, new_config: StickySessionConfig) {
+        let mut config = self.sticky_config.write().await;
+        self.health_recovery_enabled
+            .store(new_config.min_health_score > 0.0, Ordering::Relaxed);
+        *config = new_config;
+        tracing::debug!("Scheduling configuration updated: {:?}", *config);
+    }
+
+    /// [NEW] 更新熔断器配置
+    pub async fn update_circuit_breaker_config(&self, config: crate::models::CircuitBreakerConfig) {
+        let mut lock = self.circuit_breaker_config.write().await;
+        *lock = config;
+        tracing::debug!("Circuit breaker configuration updated");
+    }
+
+    /// [NEW] 获取熔断器配置
+    pub async fn get_circuit_breaker_config(&self) -> crate::models::CircuitBreakerConfig {
+        self.circuit_breaker_config.read().await.clone()
+    }
+
+    /// 清除特定会话的粘性映射
+    #[allow(dead_code)]
+    pub fn clear_session_binding(&self, session_id: &str) {
         self.session_accounts.remove(session_id);
     }
 
+    /// 回收特定会话的粘性映射，返回删除的条目数
+    pub fn evict_session_binding(&self, session_id: &str) -> usize {
+        self.session_accounts.remove(session_id).is_some() as usize
+    }
+
     /// 清除所有会话的粘性映射
     pub fn clear_all_sessions(&self) {
         self.session_accounts.clear();
@@ -2221,6 +2654,145 @@ impl TokenManager {
         self.preferred_account_id.read().await.clone()
     }
 
+    // ===== [NEW] 临时优先账号 (到期自动恢复) =====
+
+    /// 设置临时优先账号，在 expires_at 之前优先尝试该账号
+    pub fn set_preferred_override(
+        &self,
+        account_id: &str,
+        expires_at: i64,
+    ) -> Result<PreferredAccountOverride, String> {
+        if !self.tokens.contains_key(account_id) {
+            return Err(format!("Account {} is not in the proxy pool", account_id));
+        }
+        let entry = PreferredAccountOverride {
+            account_id: account_id.to_string(),
+            expires_at,
+        };
+        tracing::info!(
+            "🎯 Preferred account override set: {} (expires at {})",
+            account_id,
+            expires_at
+        );
+        *self.preferred_override.lock() = Some(entry.clone());
+        Ok(entry)
+    }
+
+    /// 清除临时优先账号
+    pub fn clear_preferred_override(&self) -> Option<PreferredAccountOverride> {
+        self.preferred_override.lock().take()
+    }
+
+    /// 获取当前生效的临时优先账号 (已到期的不返回)
+    pub fn get_preferred_override(&self) -> Option<PreferredAccountOverride> {
+        let now = chrono::Utc::now().timestamp();
+        self.preferred_override
+            .lock()
+            .clone()
+            .filter(|o| o.expires_at > now)
+    }
+
+    /// 若临时优先账号已到期则清除并返回 (用于发送到期事件)
+    pub fn take_expired_override(&self, now: i64) -> Option<PreferredAccountOverride> {
+        let mut slot = self.preferred_override.lock();
+        if slot.as_ref().is_some_and(|o| o.expires_at <= now) {
+            let expired = slot.take();
+            if let Some(ref o) = expired {
+                tracing::info!("🎯 Preferred account override expired: {}", o.account_id);
+            }
+            expired
+        } else {
+            None
+        }
+    }
+
+    /// [NEW] 账号当前活跃的上游流数量
+    pub fn active_streams(&self, account_id: &str) -> usize {
+        self.stream_slots.active(account_id)
+    }
+
+    /// [NEW] 为选中的账号占用一个并发流名额 (许可需跟随响应流释放)
+    /// 已达上限说明所有账号都已饱和 (选号时会优先跳过饱和账号)，排队等待空闲名额，超过 STREAM_SLOT_WAIT 仍未轮到时返回错误
+    pub async fn acquire_stream_slot(&self, account_id: &str) -> Result<StreamPermit, String> {
+        self.acquire_stream_slot_within(account_id, STREAM_SLOT_WAIT).await
+    }
+
+    async fn acquire_stream_slot_within(
+        &self,
+        account_id: &str,
+        wait: std::time::Duration,
+    ) -> Result<StreamPermit, String> {
+        let max_streams = crate::proxy::config::get_max_concurrent_streams_per_account();
+        let limit = match self.tokens.get(account_id) {
+            Some(token) => Self::stream_limit_for(
+                &token,
+                &crate::proxy::config::get_account_warmup_config(),
+                max_streams,
+                chrono::Utc::now().timestamp(),
+            ),
+            None => max_streams,
+        };
+        self.stream_slots
+            .acquire(account_id, limit, wait)
+            .await
+            .ok_or_else(|| {
+                format!(
+                    "All accounts are at the concurrent stream limit ({} per account) and no slot freed up within {}s; retry later or raise max_concurrent_streams_per_account",
+                    limit,
+                    wait.as_secs()
+                )
+            })
+    }
+
+    /// 账号的并发流上限 (预热期按比例降低)
+    fn stream_limit_for(
+        token: &ProxyToken,
+        warmup: &crate::proxy::config::AccountWarmupConfig,
+        max_streams: usize,
+        now: i64,
+    ) -> usize {
+        if crate::proxy::account_warmup::is_warming_up(warmup, token.created_at, now) {
+            crate::proxy::account_warmup::stream_limit(warmup, max_streams)
+        } else {
+            max_streams
+        }
+    }
+
+    /// 临时优先账号候选：未到期、在候选池中、本次未尝试过、未限流且未被配额保护
+    async fn preferred_override_candidate(
+        &self,
+        tokens_snapshot: &[ProxyToken],
+        attempted: &HashSet<String>,
+        normalized_target: &str,
+        quota_protection_enabled: bool,
+        now: i64,
+    ) -> Option<ProxyToken> {
+        let account_id = self
+            .preferred_override
+            .lock()
+            .as_ref()
+            .filter(|o| o.expires_at > now)
+            .map(|o| o.account_id.clone())?;
+        if attempted.contains(&account_id) {
+            return None;
+        }
+        let token = tokens_snapshot.iter().find(|t| t.account_id == account_id)?;
+
+        if self.is_rate_limited(&account_id, Some(normalized_target)).await {
+            tracing::debug!("Preferred override {} is rate-limited, falling back", token.email);
+            return None;
+        }
+        if quota_protection_enabled && token.protected_models.contains(normalized_target) {
+            tracing::debug!(
+                "Preferred override {} is quota-protected for {}, falling back",
+                token.email,
+                normalized_target
+            );
+            return None;
+        }
+        Some(token.clone())
+    }
+
     /// 使用 Authorization Code 交换 Refresh Token (Web OAuth)
     pub async fn exchange_code(&self, code: &str, redirect_uri: &str) -> Result<String, String> {
         crate::modules::oauth::exchange_code(code, redirect_uri)
@@ -2287,22 +2859,58 @@ impl TokenManager {
 
     /// 记录请求成功，增加健康分
     pub fn record_success(&self, account_id: &str) {
-        self.health_scores
-            .entry(account_id.to_string())
-            .and_modify(|s| *s = (*s + 0.05).min(1.0))
-            .or_insert(1.0);
+        self.adjust_health_score(account_id, 0.05);
         tracing::debug!("📈 Health score increased for account {}", account_id);
     }
 
     /// 记录请求失败，降低健康分
     pub fn record_failure(&self, account_id: &str) {
-        self.health_scores
-            .entry(account_id.to_string())
-            .and_modify(|s| *s = (*s - 0.2).max(0.0))
-            .or_insert(0.8);
+        self.adjust_health_score(account_id, -0.2);
         tracing::warn!("📉 Health score decreased for account {}", account_id);
     }
 
+    fn adjust_health_score(&self, account_id: &str, delta: f32) {
+        let now = chrono::Utc::now().timestamp();
+        let score = (self.current_health_score(account_id, now) + delta).clamp(0.0, 1.0);
+        self.health_scores.insert(account_id.to_string(), score);
+        self.health_updated_at.insert(account_id.to_string(), now);
+    }
+
+    /// [NEW] 当前健康分 (按健康分选号时含自上次更新以来的被动恢复)
+    pub fn current_health_score(&self, account_id: &str, now: i64) -> f32 {
+        let Some(score) = self.health_scores.get(account_id).map(|v| *v) else {
+            return 1.0;
+        };
+        if !self.health_recovery_enabled.load(Ordering::Relaxed) {
+            return score;
+        }
+        let updated_at = self.health_updated_at.get(account_id).map(|v| *v).unwrap_or(now);
+        Self::recovered_health_score(score, now - updated_at)
+    }
+
+    fn recovered_health_score(score: f32, elapsed_secs: i64) -> f32 {
+        (score + elapsed_secs.max(0) as f32 / 60.0 * HEALTH_RECOVERY_PER_MIN).min(1.0)
+    }
+
+    /// [NEW] 移除健康分低于阈值的账号 (阈值为 0 时不过滤)
+    fn retain_healthy(tokens: &mut Vec<ProxyToken>, min_health_score: f32) {
+        if min_health_score <= 0.0 {
+            return;
+        }
+        tokens.retain(|t| {
+            let healthy = t.health_score >= min_health_score;
+            if !healthy {
+                tracing::debug!(
+                    "Account {} health score {:.2} is below minimum {:.2}, skipping",
+                    t.email,
+                    t.health_score,
+                    min_health_score
+                );
+            }
+            healthy
+        });
+    }
+
     /// [NEW] 从账号配额信息中提取最近的刷新时间戳
     ///
     /// Claude 模型（sonnet/opus）共用同一个刷新时间，只需取 claude 系列的 reset_time
@@ -2366,6 +2974,62 @@ impl TokenManager {
         None
     }
 
+    /// [NEW] 获取账号的 v1internal 信封覆盖项 (未配置时返回默认值)
+    pub fn get_envelope_params(&self, account_id: &str) -> EnvelopeParams {
+        self.tokens
+            .get(account_id)
+            .map(|t| t.envelope.clone())
+            .unwrap_or_default()
+    }
+
+    /// [NEW] 按邮箱查找账号的 envelope 覆盖 (预热直接携带 token 时使用，未找到账号返回默认值)
+    pub fn get_envelope_params_by_email(&self, email: &str) -> EnvelopeParams {
+        self.tokens
+            .iter()
+            .find(|entry| entry.value().email == email)
+            .map(|entry| entry.value().envelope.clone())
+            .unwrap_or_default()
+    }
+
+    /// [NEW] 解析请求级 project 覆盖 (X-Antigravity-Project)
+    /// - 未提供覆盖时返回账号默认 project
+    /// - 格式非法，或账号声明了可用 project 列表但不包含该 project 时返回错误
+    pub fn resolve_project_override(
+        &self,
+        account_id: &str,
+        default_project: &str,
+        requested: Option<&str>,
+    ) -> Result<String, String> {
+        let requested = match requested.map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            Some(p) => p,
+            None => return Ok(default_project.to_string()),
+        };
+
+        crate::proxy::project_resolver::validate_project_id(requested)?;
+
+        if requested == default_project {
+            return Ok(requested.to_string());
+        }
+
+        if let Some(token) = self.tokens.get(account_id) {
+            let known = token.project_id.as_deref() == Some(requested)
+                || token.additional_project_ids.iter().any(|p| p == requested);
+            // 账号声明了可用 project 列表时才能校验归属，否则只做格式校验
+            if !known && !token.additional_project_ids.is_empty() {
+                return Err(format!(
+                    "Project '{}' is not associated with account {}",
+                    requested, token.email
+                ));
+            }
+        }
+
+        tracing::debug!(
+            "[Project-Override] Using project {} instead of {} for account {}",
+            requested, default_project, account_id
+        );
+        Ok(requested.to_string())
+    }
+
     /// Set validation blocked status for an account (internal)
     pub async fn set_validation_block(&self, account_id: &str, block_until: i64, reason: &str) -> Result<(), String> {
         // 1. Update memory
@@ -2380,24 +3044,16 @@ impl TokenManager {
              return Err(format!("Account file not found: {:?}", path));
         }
 
-        let content = std::fs::read_to_string(&path)
-             .map_err(|e| format!("Failed to read account file: {}", e))?;
-
-        let mut account: serde_json::Value = serde_json::from_str(&content)
-             .map_err(|e| format!("Failed to parse account JSON: {}", e))?;
-
-        account["validation_blocked"] = serde_json::Value::Bool(true);
-        account["validation_blocked_until"] = serde_json::Value::Number(serde_json::Number::from(block_until));
-        account["validation_blocked_reason"] = serde_json::Value::String(reason.to_string());
-
         // Clear sticky session if blocked
         self.session_accounts.retain(|_, v| *v != account_id);
 
-        let json_str = serde_json::to_string_pretty(&account)
-             .map_err(|e| format!("Failed to serialize account JSON: {}", e))?;
-
-        std::fs::write(&path, json_str)
-             .map_err(|e| format!("Failed to write account file: {}", e))?;
+        crate::modules::account::update_account_json_at(&path, |account| {
+            account["validation_blocked"] = serde_json::Value::Bool(true);
+            account["validation_blocked_until"] = serde_json::Value::Number(serde_json::Number::from(block_until));
+            account["validation_blocked_reason"] = serde_json::Value::String(reason.to_string());
+            Ok(())
+        })
+        .map_err(|e| format!("Failed to write account file: {}", e))?;
 
         tracing::info!(
              "🚫 Account {} validation blocked until {} (reason: {})",
@@ -2422,33 +3078,25 @@ impl TokenManager {
             return Err(format!("Account file not found: {:?}", path));
         }
 
-        let content = std::fs::read_to_string(&path)
-            .map_err(|e| format!("Failed to read account file: {}", e))?;
-
-        let mut account: serde_json::Value = serde_json::from_str(&content)
-            .map_err(|e| format!("Failed to parse account JSON: {}", e))?;
-
-        // Update quota.is_forbidden
-        if let Some(quota) = account.get_mut("quota") {
-            quota["is_forbidden"] = serde_json::Value::Bool(true);
-        } else {
-            // Create quota object if not exists
-            account["quota"] = serde_json::json!({
-                "models": [],
-                "last_updated": chrono::Utc::now().timestamp(),
-                "is_forbidden": true
-            });
-        }
+        crate::modules::account::update_account_json_at(&path, |account| {
+            // Update quota.is_forbidden
+            if let Some(quota) = account.get_mut("quota") {
+                quota["is_forbidden"] = serde_json::Value::Bool(true);
+            } else {
+                // Create quota object if not exists
+                account["quota"] = serde_json::json!({
+                    "models": [],
+                    "last_updated": chrono::Utc::now().timestamp(),
+                    "is_forbidden": true
+                });
+            }
+            Ok(())
+        })
+        .map_err(|e| format!("Failed to write account file: {}", e))?;
 
         // Clear sticky session if forbidden
         self.session_accounts.retain(|_, v| *v != account_id);
 
-        let json_str = serde_json::to_string_pretty(&account)
-            .map_err(|e| format!("Failed to serialize account JSON: {}", e))?;
-
-        std::fs::write(&path, json_str)
-            .map_err(|e| format!("Failed to write account file: {}", e))?;
-
         // [FIX] 从内存池中移除账号，避免重试时再次选中
         self.remove_account(account_id);
 
@@ -2460,6 +3108,20 @@ impl TokenManager {
 
         Ok(())
     }
+
+    /// [NEW] 上游返回 403 且提示 OAuth scope 不足时禁用账号
+    /// 账号需重新授权 (re-consent) 后才能恢复，upsert 新 token 时会自动清除该标记
+    pub async fn mark_insufficient_scope(&self, account_id: &str) -> Result<(), String> {
+        self.disable_account(account_id, crate::modules::account::INSUFFICIENT_SCOPE_REASON)
+            .await?;
+        // 清理粘性会话与优先账号等缓存
+        self.remove_account(account_id);
+        tracing::warn!(
+            "🔑 Account {} disabled: OAuth scopes insufficient, re-authorization required",
+            account_id
+        );
+        Ok(())
+    }
 }
 
 /// 截断过长的原因字符串
@@ -2471,6 +3133,16 @@ fn truncate_reason(reason: &str, max_len: usize) -> String {
     }
 }
 
+impl crate::proxy::session_registry::SessionStateStore for TokenManager {
+    fn name(&self) -> &'static str {
+        "sticky_sessions"
+    }
+
+    fn evict_session(&self, session_id: &str) -> usize {
+        self.evict_session_binding(session_id)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -2535,6 +3207,131 @@ mod tests {
         let _ = std::fs::remove_dir_all(&tmp_root);
     }
 
+    #[tokio::test]
+    async fn test_refresh_ahead_refreshes_near_expiry_token_and_persists() {
+        let tmp_root = std::env::temp_dir().join(format!(
+            "antigravity-token-manager-test-{}",
+            uuid::Uuid::new_v4()
+        ));
+        let accounts_dir = tmp_root.join("accounts");
+        std::fs::create_dir_all(&accounts_dir).unwrap();
+
+        let now = chrono::Utc::now().timestamp();
+        // near: 60s 后到期; fresh: 1 小时后到期
+        for (id, expires_at) in [("near", now + 60), ("fresh", now + 3600)] {
+            let account_json = serde_json::json!({
+                "id": id,
+                "email": format!("{}@test.com", id),
+                "token": {
+                    "access_token": "old-atk",
+                    "refresh_token": "rtk",
+                    "expires_in": 3600,
+                    "expiry_timestamp": expires_at
+                },
+                "disabled": false,
+                "proxy_disabled": false,
+                "created_at": now,
+                "last_used": now
+            });
+            std::fs::write(
+                accounts_dir.join(format!("{}.json", id)),
+                serde_json::to_string_pretty(&account_json).unwrap(),
+            )
+            .unwrap();
+        }
+
+        let manager = TokenManager::new(tmp_root.clone());
+        manager.load_accounts().await.unwrap();
+
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let fake_refresh = |refresh_token: String, _account_id: String| {
+            calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async move {
+                assert_eq!(refresh_token, "rtk");
+                Ok(crate::modules::oauth::TokenResponse {
+                    access_token: "new-atk".to_string(),
+                    expires_in: 3599,
+                    token_type: "Bearer".to_string(),
+                    refresh_token: Some("rotated-rtk".to_string()),
+                })
+            }
+        };
+
+        // 60s 到期 < 120s 窗口: 使用前刷新
+        let mut near = manager.tokens.get("near").unwrap().clone();
+        assert!(manager.refresh_ahead_with(&mut near, 120, fake_refresh).await.unwrap());
+        assert_eq!(near.access_token, "new-atk");
+        assert_eq!(manager.tokens.get("near").unwrap().access_token, "new-atk");
+        assert_eq!(manager.tokens.get("near").unwrap().refresh_token, "rotated-rtk");
+
+        let on_disk: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(accounts_dir.join("near.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(on_disk["token"]["access_token"], "new-atk");
+        assert_eq!(on_disk["token"]["refresh_token"], "rotated-rtk");
+        assert!(on_disk["token"]["expiry_timestamp"].as_i64().unwrap() > now + 3000);
+
+        // 1 小时后到期: 不刷新
+        let mut fresh = manager.tokens.get("fresh").unwrap().clone();
+        assert!(!manager.refresh_ahead_with(&mut fresh, 120, fake_refresh).await.unwrap());
+        assert_eq!(fresh.access_token, "old-atk");
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let _ = std::fs::remove_dir_all(&tmp_root);
+    }
+
+    #[tokio::test]
+    async fn test_mark_insufficient_scope_disables_account() {
+        let tmp_root = std::env::temp_dir().join(format!(
+            "antigravity-token-manager-test-{}",
+            uuid::Uuid::new_v4()
+        ));
+        let accounts_dir = tmp_root.join("accounts");
+        std::fs::create_dir_all(&accounts_dir).unwrap();
+
+        let account_id = "acc-scope";
+        let now = chrono::Utc::now().timestamp();
+        let account_path = accounts_dir.join(format!("{}.json", account_id));
+        let account_json = serde_json::json!({
+            "id": account_id,
+            "email": "scope@test.com",
+            "token": {
+                "access_token": "atk",
+                "refresh_token": "rtk",
+                "expires_in": 3600,
+                "expiry_timestamp": now + 3600
+            },
+            "disabled": false,
+            "proxy_disabled": false,
+            "created_at": now,
+            "last_used": now
+        });
+        std::fs::write(&account_path, serde_json::to_string_pretty(&account_json).unwrap()).unwrap();
+
+        let manager = TokenManager::new(tmp_root.clone());
+        manager.load_accounts().await.unwrap();
+        manager
+            .session_accounts
+            .insert("sid1".to_string(), account_id.to_string());
+
+        manager.mark_insufficient_scope(account_id).await.unwrap();
+
+        assert!(manager.tokens.get(account_id).is_none());
+        assert!(manager.session_accounts.get("sid1").is_none());
+
+        let saved: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&account_path).unwrap()).unwrap();
+        assert_eq!(saved["disabled"], true);
+        assert_eq!(saved["disabled_reason"], "insufficient_scope");
+
+        // 重新加载时禁用账号不会回到轮换池
+        manager.load_accounts().await.unwrap();
+        assert!(manager.tokens.get(account_id).is_none());
+
+        let _ = std::fs::remove_dir_all(&tmp_root);
+    }
+
     #[tokio::test]
     async fn test_fixed_account_mode_skips_preferred_when_disabled_on_disk_without_reload() {
         let tmp_root = std::env::temp_dir().join(format!(
@@ -2594,6 +3391,227 @@ mod tests {
         let _ = std::fs::remove_dir_all(&tmp_root);
     }
 
+    async fn setup_override_pool() -> (TokenManager, PathBuf) {
+        let tmp_root = std::env::temp_dir().join(format!(
+            "antigravity-token-manager-test-override-{}",
+            uuid::Uuid::new_v4()
+        ));
+        let accounts_dir = tmp_root.join("accounts");
+        std::fs::create_dir_all(&accounts_dir).unwrap();
+
+        let now = chrono::Utc::now().timestamp();
+        for (id, email, percentage) in [("acc1", "a@test.com", 90), ("acc2", "b@test.com", 10)] {
+            let json = serde_json::json!({
+                "id": id,
+                "email": email,
+                "token": {
+                    "access_token": format!("atk-{}", id),
+                    "refresh_token": format!("rtk-{}", id),
+                    "expires_in": 3600,
+                    "expiry_timestamp": now + 3600,
+                    "project_id": format!("pid-{}", id)
+                },
+                "quota": {
+                    "models": [
+                        { "name": "gemini-1.5-flash", "percentage": percentage }
+                    ]
+                },
+                "disabled": false,
+                "proxy_disabled": false,
+                "created_at": now,
+                "last_used": now
+            });
+            std::fs::write(
+                accounts_dir.join(format!("{}.json", id)),
+                serde_json::to_string_pretty(&json).unwrap(),
+            )
+            .unwrap();
+        }
+
+        let manager = TokenManager::new(tmp_root.clone());
+        manager.load_accounts().await.unwrap();
+        (manager, tmp_root)
+    }
+
+    #[tokio::test]
+    async fn test_saturated_account_overflows_to_next() {
+        let (manager, tmp_root) = setup_override_pool().await;
+        let previous = crate::proxy::config::get_max_concurrent_streams_per_account();
+        let max_streams = 2;
+        crate::proxy::update_max_concurrent_streams_per_account(max_streams);
+
+        // acc1 配额更高，正常情况下优先被选中；占满其并发流名额
+        let mut permits = Vec::new();
+        for _ in 0..max_streams {
+            permits.push(manager.acquire_stream_slot("acc1").await.unwrap());
+        }
+        assert_eq!(manager.active_streams("acc1"), max_streams);
+
+        let (_, _, _, account_id, _) = manager
+            .get_token("gemini", false, None, "gemini-1.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(account_id, "acc2");
+
+        // 全部饱和时排队等待，等待超时后返回明确的错误，而不是超限放行
+        for _ in 0..max_streams {
+            permits.push(manager.acquire_stream_slot("acc2").await.unwrap());
+        }
+        let wait = std::time::Duration::from_millis(50);
+        let err = manager.acquire_stream_slot_within("acc1", wait).await.err().unwrap();
+        assert!(err.contains("concurrent stream limit (2 per account)"), "{}", err);
+        assert_eq!(manager.active_streams("acc1"), max_streams);
+
+        // 排队中的请求在名额释放后获得名额
+        let queued = manager.acquire_stream_slot_within("acc1", std::time::Duration::from_secs(5));
+        let release = async {
+            tokio::time::sleep(wait).await;
+            permits.remove(0);
+        };
+        let (permit, _) = tokio::join!(queued, release);
+        assert!(permit.is_ok());
+        drop(permit);
+
+        // 流结束后名额释放
+        permits.clear();
+        assert_eq!(manager.active_streams("acc1"), 0);
+        crate::proxy::update_max_concurrent_streams_per_account(previous);
+
+        let _ = std::fs::remove_dir_all(&tmp_root);
+    }
+
+    #[tokio::test]
+    async fn test_recently_failed_account_skipped_within_window() {
+        let (manager, tmp_root) = setup_override_pool().await;
+        let window = crate::proxy::config::get_recent_failure_window_secs();
+        assert!(window > 0);
+
+        // acc1 配额更高，刚失败后在窗口内让位于 acc2
+        manager.mark_recent_failure("acc1");
+        let (_, _, _, account_id, _) = manager
+            .get_token("gemini", false, None, "gemini-1.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(account_id, "acc2");
+
+        // 窗口过后重新参与选号
+        manager.recent_failures.insert(
+            "acc1".to_string(),
+            std::time::Instant::now() - std::time::Duration::from_secs(window + 1),
+        );
+        let (_, _, _, account_id, _) = manager
+            .get_token("gemini", true, None, "gemini-1.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(account_id, "acc1");
+        assert!(manager.recent_failures.is_empty());
+
+        // 成功请求立即解除；全部账号都在窗口内时仍可兜底选中
+        manager.mark_recent_failure("acc1");
+        manager.mark_account_success("acc1");
+        assert!(!manager.recent_failures.contains_key("acc1"));
+        manager.mark_recent_failure("acc1");
+        manager.mark_recent_failure("acc2");
+        assert!(manager
+            .get_token("gemini", true, None, "gemini-1.5-flash")
+            .await
+            .is_ok());
+
+        let _ = std::fs::remove_dir_all(&tmp_root);
+    }
+
+    #[tokio::test]
+    async fn test_preferred_override_is_tried_first() {
+        let (manager, tmp_root) = setup_override_pool().await;
+        let now = chrono::Utc::now().timestamp();
+
+        // 未设置时按配额优先选 acc1
+        let (_, _, _, account_id, _) = manager
+            .get_token("gemini", false, None, "gemini-1.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(account_id, "acc1");
+
+        assert!(manager.set_preferred_override("missing", now + 60).is_err());
+        manager.set_preferred_override("acc2", now + 60).unwrap();
+        assert_eq!(manager.get_preferred_override().unwrap().account_id, "acc2");
+
+        let (_, _, email, account_id, _) = manager
+            .get_token("gemini", false, Some("sid-override"), "gemini-1.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(account_id, "acc2");
+        assert_eq!(email, "b@test.com");
+
+        // 非独占: 强制轮换时让位于其他账号
+        let (_, _, _, account_id, _) = manager
+            .get_token("gemini", true, None, "gemini-1.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(account_id, "acc1");
+
+        let _ = std::fs::remove_dir_all(&tmp_root);
+    }
+
+    #[tokio::test]
+    async fn test_preferred_override_skipped_when_protected() {
+        let (manager, tmp_root) = setup_override_pool().await;
+        let now = chrono::Utc::now().timestamp();
+        manager.set_preferred_override("acc2", now + 60).unwrap();
+
+        if let Some(mut token) = manager.tokens.get_mut("acc2") {
+            token.protected_models.insert("gemini-1.5-flash".to_string());
+        }
+        let snapshot: Vec<ProxyToken> = manager.tokens.iter().map(|e| e.value().clone()).collect();
+        let attempted = HashSet::new();
+
+        // 配额保护开启时回退到常规调度
+        assert!(manager
+            .preferred_override_candidate(&snapshot, &attempted, "gemini-1.5-flash", true, now)
+            .await
+            .is_none());
+        // 配额保护关闭时仍优先
+        let candidate = manager
+            .preferred_override_candidate(&snapshot, &attempted, "gemini-1.5-flash", false, now)
+            .await;
+        assert_eq!(candidate.map(|t| t.account_id).as_deref(), Some("acc2"));
+
+        // 本次请求已尝试过则不再优先
+        let attempted: HashSet<String> = ["acc2".to_string()].into_iter().collect();
+        assert!(manager
+            .preferred_override_candidate(&snapshot, &attempted, "gemini-1.5-flash", false, now)
+            .await
+            .is_none());
+
+        let _ = std::fs::remove_dir_all(&tmp_root);
+    }
+
+    #[tokio::test]
+    async fn test_preferred_override_expires() {
+        let (manager, tmp_root) = setup_override_pool().await;
+        let now = chrono::Utc::now().timestamp();
+        manager.set_preferred_override("acc2", now + 60).unwrap();
+
+        // 未到期时不清除
+        assert!(manager.take_expired_override(now).is_none());
+        assert!(manager.get_preferred_override().is_some());
+
+        // 到期后不再生效，并由到期检查清除
+        manager.set_preferred_override("acc2", now - 1).unwrap();
+        assert!(manager.get_preferred_override().is_none());
+        let (_, _, _, account_id, _) = manager
+            .get_token("gemini", false, None, "gemini-1.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(account_id, "acc1");
+
+        let expired = manager.take_expired_override(now).unwrap();
+        assert_eq!(expired.account_id, "acc2");
+        assert!(manager.take_expired_override(now + 3600).is_none());
+
+        let _ = std::fs::remove_dir_all(&tmp_root);
+    }
+
     #[tokio::test]
     async fn test_sticky_session_skips_bound_account_when_disabled_on_disk_without_reload() {
         let tmp_root = std::env::temp_dir().join(format!(
@@ -2669,6 +3687,282 @@ mod tests {
         let _ = std::fs::remove_dir_all(&tmp_root);
     }
 
+    #[tokio::test]
+    async fn test_retry_info_429_locks_model_and_records_quota() {
+        let manager = TokenManager::new(std::env::temp_dir());
+        let mut token = create_test_token("a@test.com", Some("PRO"), 1.0, None, Some(80));
+        token.model_quotas.insert("gemini-3-pro-high".to_string(), 80);
+        token.model_quotas.insert("claude".to_string(), 90);
+        manager.tokens.insert(token.account_id.clone(), token);
+
+        let body = r#"{"error":{"code":429,"status":"RESOURCE_EXHAUSTED","details":[
+            {"@type":"type.googleapis.com/google.rpc.QuotaFailure","violations":[{"quotaDimensions":{"model":"gemini-3-pro-high"}}]},
+            {"@type":"type.googleapis.com/google.rpc.RetryInfo","retryDelay":"120s"}
+        ]}}"#;
+        manager
+            .mark_rate_limited_async("a@test.com", 429, None, body, Some("gemini-3-pro-preview"))
+            .await;
+
+        // 仅 (账号, 受限模型) 被跳过，延时取自 RetryInfo
+        assert!(manager.is_rate_limited("a@test.com", Some("gemini-3-pro-high")).await);
+        assert!(!manager.is_rate_limited("a@test.com", Some("claude")).await);
+        let wait = manager.rate_limit_tracker.get_remaining_wait("a@test.com", Some("gemini-3-pro-high"));
+        assert!(wait > 110 && wait <= 120);
+
+        {
+            let cached = manager.tokens.get("a@test.com").unwrap();
+            assert_eq!(cached.model_quotas.get("gemini-3-pro-high"), Some(&0));
+            assert_eq!(cached.model_quotas.get("claude"), Some(&90));
+        }
+
+        // 限流窗口内不恢复；窗口结束后恢复锁定前的配额
+        manager.restore_expired_quota_lockouts();
+        assert_eq!(
+            manager.tokens.get("a@test.com").unwrap().model_quotas.get("gemini-3-pro-high"),
+            Some(&0)
+        );
+        manager.rate_limit_tracker.clear_all();
+        manager.restore_expired_quota_lockouts();
+        assert_eq!(
+            manager.tokens.get("a@test.com").unwrap().model_quotas.get("gemini-3-pro-high"),
+            Some(&80)
+        );
+        assert!(manager.quota_lockouts.is_empty());
+    }
+
+    #[test]
+    fn test_over_budget_account_skipped() {
+        let mut limited = create_test_token("limited@test.com", Some("PRO"), 1.0, None, Some(80));
+        limited.monthly_token_budget = Some(1_000);
+        let mut under = create_test_token("under@test.com", Some("PRO"), 1.0, None, Some(80));
+        under.monthly_token_budget = Some(5_000);
+        let unlimited = create_test_token("unlimited@test.com", Some("FREE"), 1.0, None, Some(80));
+
+        let mut usage = HashMap::new();
+        usage.insert("limited@test.com".to_string(), 1_000u64);
+        usage.insert("under@test.com".to_string(), 4_999u64);
+        usage.insert("unlimited@test.com".to_string(), 1_000_000u64);
+
+        let mut tokens = vec![limited.clone(), under, unlimited];
+        TokenManager::retain_within_budget(&mut tokens, &usage);
+        let emails: Vec<&str> = tokens.iter().map(|t| t.email.as_str()).collect();
+        assert_eq!(emails, vec!["under@test.com", "unlimited@test.com"]);
+
+        // 新的月份窗口内无用量，账号重新可用
+        let mut tokens = vec![limited];
+        TokenManager::retain_within_budget(&mut tokens, &HashMap::new());
+        assert_eq!(tokens.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_monthly_usage_served_from_cache() {
+        let manager = TokenManager::new(std::env::temp_dir());
+        let mut usage = HashMap::new();
+        usage.insert("limited@test.com".to_string(), 1_000u64);
+        *manager.budget_usage.lock() = Some(BudgetUsageSnapshot {
+            month_start: crate::modules::token_stats::month_start_ts(chrono::Utc::now()),
+            fetched_at: std::time::Instant::now(),
+            usage: Arc::new(usage),
+        });
+
+        // 有效期内直接使用快照，不查询统计库
+        let cached = manager.monthly_token_usage().await.unwrap();
+        assert_eq!(cached.get("limited@test.com"), Some(&1_000));
+    }
+
+    #[test]
+    fn test_unparsed_quota_models_kept_out_of_percentage_cache() {
+        let account = serde_json::json!({
+            "quota": {
+                "models": [
+                    { "name": "gemini-3-flash", "percentage": 45 },
+                    { "name": "claude-sonnet-4-5", "quota": { "left": "plenty" } },
+                    { "name": "gemini-3-pro-image", "percentage": 0, "unparsed": true },
+                    // 同组另一个模型可解析时以其百分比为准
+                    { "name": "gemini-3-pro-high", "unparsed": true },
+                    { "name": "gemini-3-pro-low", "percentage": 30 }
+                ]
+            }
+        });
+        let (quotas, _, unparsed) = TokenManager::build_model_quota_cache(&account);
+
+        assert_eq!(quotas.get("gemini-3-flash"), Some(&45));
+        assert_eq!(quotas.get("gemini-3-pro-high"), Some(&30));
+        // 未知百分比不按 0% 缓存，避免排序与配额保护把它当作耗尽
+        assert_eq!(quotas.get("claude"), None);
+        assert_eq!(quotas.get("gemini-3-pro-image"), None);
+        let mut unparsed: Vec<&str> = unparsed.iter().map(|s| s.as_str()).collect();
+        unparsed.sort();
+        assert_eq!(unparsed, vec!["claude", "gemini-3-pro-image"]);
+    }
+
+    #[test]
+    fn test_warmup_restricts_new_accounts_until_window_ends() {
+        let warmup = crate::proxy::config::AccountWarmupConfig {
+            enabled: true,
+            hours: 24,
+            max_cost_tier: crate::proxy::common::model_mapping::ModelCostTier::Low,
+            concurrency_percent: 25,
+        };
+        let created_at = 1_767_225_600;
+        let mut fresh = create_test_token("fresh@test.com", Some("PRO"), 1.0, None, Some(80));
+        fresh.created_at = created_at;
+        let veteran = create_test_token("veteran@test.com", Some("PRO"), 1.0, None, Some(80));
+
+        // 预热期内: 高成本模型组跳过新账号，低成本模型组照常参与，并发上限降低
+        let during = created_at + 3600;
+        let mut tokens = vec![fresh.clone(), veteran.clone()];
+        TokenManager::retain_warmup_eligible(&mut tokens, &warmup, "claude", during);
+        let emails: Vec<&str> = tokens.iter().map(|t| t.email.as_str()).collect();
+        assert_eq!(emails, vec!["veteran@test.com"]);
+
+        let mut tokens = vec![fresh.clone(), veteran.clone()];
+        TokenManager::retain_warmup_eligible(&mut tokens, &warmup, "gemini-3-flash", during);
+        assert_eq!(tokens.len(), 2);
+
+        assert_eq!(TokenManager::stream_limit_for(&fresh, &warmup, 8, during), 2);
+        assert_eq!(TokenManager::stream_limit_for(&veteran, &warmup, 8, during), 8);
+        // 常规上限不限制时，预热期账号仍按默认上限的比例受限
+        assert_eq!(TokenManager::stream_limit_for(&fresh, &warmup, 0, during), 1);
+        assert_eq!(TokenManager::stream_limit_for(&veteran, &warmup, 0, during), 0);
+        assert_eq!(
+            crate::proxy::account_warmup::remaining_secs(&warmup, fresh.created_at, during),
+            Some(23 * 3600)
+        );
+
+        // 预热期结束后自动解除限制
+        let after = created_at + 24 * 3600;
+        let mut tokens = vec![fresh.clone(), veteran];
+        TokenManager::retain_warmup_eligible(&mut tokens, &warmup, "claude", after);
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(TokenManager::stream_limit_for(&fresh, &warmup, 8, after), 8);
+        assert_eq!(crate::proxy::account_warmup::remaining_secs(&warmup, fresh.created_at, after), None);
+    }
+
+    #[tokio::test]
+    async fn test_min_health_score_excludes_flaky_accounts_until_recovered() {
+        let manager = TokenManager::new(PathBuf::from("/tmp/test"));
+        manager
+            .update_sticky_config(StickySessionConfig {
+                min_health_score: 0.5,
+                ..Default::default()
+            })
+            .await;
+        manager.record_failure("flaky@test.com");
+        manager.record_failure("flaky@test.com");
+        manager.record_failure("flaky@test.com");
+        manager.record_failure("steady@test.com");
+
+        let now = chrono::Utc::now().timestamp();
+        let flaky_score = manager.current_health_score("flaky@test.com", now);
+        let steady_score = manager.current_health_score("steady@test.com", now);
+        assert!((flaky_score - 0.4).abs() < 0.01);
+        assert!((steady_score - 0.8).abs() < 0.01);
+
+        let flaky = create_test_token("flaky@test.com", Some("PRO"), flaky_score, None, Some(80));
+        let steady = create_test_token("steady@test.com", Some("PRO"), steady_score, None, Some(80));
+
+        let mut tokens = vec![flaky.clone(), steady.clone()];
+        TokenManager::retain_healthy(&mut tokens, 0.5);
+        let emails: Vec<&str> = tokens.iter().map(|t| t.email.as_str()).collect();
+        assert_eq!(emails, vec!["steady@test.com"]);
+
+        // 默认阈值 0.0 不过滤
+        let mut tokens = vec![flaky, steady];
+        TokenManager::retain_healthy(&mut tokens, 0.0);
+        assert_eq!(tokens.len(), 2);
+
+        // 被跳过的账号随时间被动恢复，超过阈值后重新参与选号
+        let later = now + 15 * 60;
+        let recovered = manager.current_health_score("flaky@test.com", later);
+        assert!(recovered >= 0.5);
+        let mut tokens = vec![create_test_token("flaky@test.com", Some("PRO"), recovered, None, Some(80))];
+        TokenManager::retain_healthy(&mut tokens, 0.5);
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(manager.current_health_score("unknown@test.com", now), 1.0);
+
+        // 未启用按健康分选号时不做被动恢复
+        manager.update_sticky_config(StickySessionConfig::default()).await;
+        assert!((manager.current_health_score("flaky@test.com", later) - 0.4).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_quota_sort_prefers_absolute_tokens_over_percentage() {
+        use std::cmp::Ordering;
+
+        // 百分比更高但绝对剩余更少 (小额度窗口)
+        let mut small = create_test_token("small@test.com", Some("PRO"), 1.0, None, None);
+        small.model_quotas.insert("claude".to_string(), 90);
+        small.model_quota_tokens.insert("claude".to_string(), 9_000);
+        let mut large = create_test_token("large@test.com", Some("PRO"), 1.0, None, None);
+        large.model_quotas.insert("claude".to_string(), 40);
+        large.model_quota_tokens.insert("claude".to_string(), 400_000);
+
+        assert_eq!(TokenManager::compare_model_quota(&large, &small, "claude", true), Ordering::Less);
+
+        // 候选集中有账号缺少绝对额度时，整个排序统一使用百分比 (保持可传递)
+        let mut pct_only = create_test_token("pct@test.com", Some("PRO"), 1.0, None, None);
+        pct_only.model_quotas.insert("claude".to_string(), 60);
+        let mut tokens = vec![large.clone(), pct_only.clone(), small.clone()];
+        let use_absolute = tokens.iter().all(|t| t.model_quota_tokens.contains_key("claude"));
+        assert!(!use_absolute);
+        tokens.sort_by(|a, b| TokenManager::compare_model_quota(a, b, "claude", use_absolute));
+        let order: Vec<_> = tokens.iter().map(|t| t.email.as_str()).collect();
+        assert_eq!(order, vec!["small@test.com", "pct@test.com", "large@test.com"]);
+    }
+
+    fn grouped_token(email: &str, group: Option<&str>, quota: i32) -> ProxyToken {
+        let mut token = create_test_token(email, Some("PRO"), 1.0, None, Some(quota));
+        token.project_id = Some("test-project".to_string());
+        token.model_quotas.insert("gemini-3-flash".to_string(), quota);
+        token.group = group.map(|g| g.to_string());
+        token
+    }
+
+    #[tokio::test]
+    async fn test_group_scoped_selection_excludes_other_groups() {
+        use crate::proxy::account_groups::scope;
+
+        let manager = TokenManager::new(std::env::temp_dir());
+        for token in [
+            grouped_token("a@test.com", Some("team-a"), 20),
+            grouped_token("b@test.com", Some("team-b"), 90),
+            grouped_token("ungrouped@test.com", None, 100),
+        ] {
+            manager.tokens.insert(token.account_id.clone(), token);
+        }
+
+        // 其他分组 / 未分组账号的配额更高，也不会被 team-a 的请求选中 (包括强制轮换)
+        for force_rotate in [false, true] {
+            let (_, _, email, _, _) = scope(
+                Some(Arc::from("team-a")),
+                manager.get_token("gemini", force_rotate, None, "gemini-3-flash"),
+            )
+            .await
+            .unwrap();
+            assert_eq!(email, "a@test.com");
+        }
+
+        // 分组内没有账号时报错，而不是回退到其他分组
+        let err = scope(
+            Some(Arc::from("team-c")),
+            manager.get_token("gemini", false, None, "gemini-3-flash"),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.contains("team-c"));
+
+        // 未指定分组的请求使用全部账号 (包括已分组的账号)
+        let manager = TokenManager::new(std::env::temp_dir());
+        let token = grouped_token("b@test.com", Some("team-b"), 90);
+        manager.tokens.insert(token.account_id.clone(), token);
+        let (_, _, email, _, _) = manager
+            .get_token("gemini", false, None, "gemini-3-flash")
+            .await
+            .unwrap();
+        assert_eq!(email, "b@test.com");
+    }
+
     /// 创建测试用的 ProxyToken
     fn create_test_token(
         email: &str,
@@ -2694,6 +3988,13 @@ mod tests {
             validation_blocked: false,
             validation_blocked_until: 0,
             model_quotas: HashMap::new(),
+            model_quota_tokens: HashMap::new(),
+            unparsed_quota_models: HashSet::new(),
+            envelope: EnvelopeParams::default(),
+            additional_project_ids: Vec::new(),
+            monthly_token_budget: None,
+            group: None,
+            created_at: 0,
         }
     }
 
@@ -2925,6 +4226,49 @@ mod tests {
         assert!(manager.extract_earliest_reset_time(&account_no_quota).is_none());
     }
 
+    #[test]
+    fn test_project_override_honored_and_validated() {
+        let manager = TokenManager::new(PathBuf::from("/tmp/test"));
+
+        let mut token = create_test_token("multi@test.com", Some("PRO"), 1.0, None, Some(80));
+        token.project_id = Some("primary-project-1".to_string());
+        token.additional_project_ids = vec!["secondary-project-2".to_string()];
+        manager.tokens.insert(token.account_id.clone(), token);
+
+        let account_id = "multi@test.com";
+
+        // 未提供覆盖: 使用默认 project
+        assert_eq!(
+            manager.resolve_project_override(account_id, "primary-project-1", None).unwrap(),
+            "primary-project-1"
+        );
+        // 覆盖为账号声明的 project: 生效
+        assert_eq!(
+            manager
+                .resolve_project_override(account_id, "primary-project-1", Some("secondary-project-2"))
+                .unwrap(),
+            "secondary-project-2"
+        );
+        // 不属于该账号的 project: 拒绝
+        assert!(manager
+            .resolve_project_override(account_id, "primary-project-1", Some("someone-elses-proj"))
+            .is_err());
+        // 格式非法: 拒绝
+        assert!(manager
+            .resolve_project_override(account_id, "primary-project-1", Some("Bad_Project!"))
+            .is_err());
+
+        // 未声明 project 列表的账号: 仅做格式校验
+        let plain = create_test_token("plain@test.com", Some("PRO"), 1.0, None, Some(80));
+        manager.tokens.insert(plain.account_id.clone(), plain);
+        assert_eq!(
+            manager
+                .resolve_project_override("plain@test.com", "default-proj-x", Some("other-project-9"))
+                .unwrap(),
+            "other-project-9"
+        );
+    }
+
     // ===== P2C 算法测试 =====
 
     /// 创建带 protected_models 的测试 Token
@@ -2950,6 +4294,13 @@ mod tests {
             validation_blocked: false,
             validation_blocked_until: 0,
             model_quotas: HashMap::new(),
+            model_quota_tokens: HashMap::new(),
+            unparsed_quota_models: HashSet::new(),
+            envelope: EnvelopeParams::default(),
+            additional_project_ids: Vec::new(),
+            monthly_token_budget: None,
+            group: None,
+            created_at: 0,
         }
     }
 
@@ -61,6 +61,7 @@ pub struct UpstreamClient {
     proxy_pool: Option<Arc<crate::proxy::proxy_pool::ProxyPoolManager>>,
     client_cache: DashMap<String, Client>, // proxy_id -> Client
     user_agent_override: RwLock<Option<String>>,
+    base_url_override: RwLock<Option<String>>, // [NEW] 覆盖 v1internal 基础 URL (禁用降级链)
 }
 
 impl UpstreamClient {
@@ -76,6 +77,7 @@ impl UpstreamClient {
             proxy_pool,
             client_cache: DashMap::new(),
             user_agent_override: RwLock::new(None),
+            base_url_override: RwLock::new(None),
         }
     }
 
@@ -138,6 +140,34 @@ impl UpstreamClient {
             .unwrap_or_else(|| crate::constants::USER_AGENT.clone())
     }
 
+    /// Set upstream base URL override (None or invalid value restores the built-in fallback chain)
+    pub async fn set_base_url_override(&self, base_url: Option<String>) {
+        let normalized = base_url
+            .map(|u| u.trim().trim_end_matches('/').to_string())
+            .filter(|u| !u.is_empty())
+            .filter(|u| {
+                let valid = u.starts_with("http://") || u.starts_with("https://");
+                if !valid {
+                    tracing::warn!("Ignoring invalid upstream base URL override: {}", u);
+                }
+                valid
+            });
+        let mut lock = self.base_url_override.write().await;
+        *lock = normalized;
+        tracing::debug!("UpstreamClient base URL override updated: {:?}", lock);
+    }
+
+    /// Get v1internal base URLs in fallback order
+    async fn get_base_urls(&self) -> Vec<String> {
+        match self.base_url_override.read().await.as_ref() {
+            Some(url) => vec![url.clone()],
+            None => V1_INTERNAL_BASE_URL_FALLBACKS
+                .iter()
+                .map(|u| u.to_string())
+                .collect(),
+        }
+    }
+
     /// Get client for a specific account (or default if no proxy bound)
     pub async fn get_client(&self, account_id: Option<&str>) -> Client {
         if let Some(pool) = &self.proxy_pool {
@@ -233,6 +263,29 @@ impl UpstreamClient {
         query_string: Option<&str>,
         extra_headers: std::collections::HashMap<String, String>,
         account_id: Option<&str>, // [NEW] Account ID
+    ) -> Result<UpstreamCallResult, String> {
+        let payload = serde_json::to_vec(&body).map_err(|e| format!("Failed to serialize request body: {}", e))?;
+        self.call_v1_internal_raw(
+            method,
+            access_token,
+            bytes::Bytes::from(payload),
+            query_string,
+            extra_headers,
+            account_id,
+        )
+        .await
+    }
+
+    /// [NEW] 使用已序列化的请求体调用 v1internal API
+    /// 请求体只序列化一次，多端点降级时共享同一缓冲区 (Bytes clone 不复制数据)
+    pub async fn call_v1_internal_raw(
+        &self,
+        method: &str,
+        access_token: &str,
+        payload: bytes::Bytes,
+        query_string: Option<&str>,
+        extra_headers: std::collections::HashMap<String, String>,
+        account_id: Option<&str>,
     ) -> Result<UpstreamCallResult, String> {
         // [NEW] Get client based on account (cached in proxy pool manager)
         let client = self.get_client(account_id).await;
@@ -272,14 +325,15 @@ impl UpstreamClient {
         let mut fallback_attempts: Vec<FallbackAttemptLog> = Vec::new();
 
         // 遍历所有端点，失败时自动切换
-        for (idx, base_url) in V1_INTERNAL_BASE_URL_FALLBACKS.iter().enumerate() {
+        let base_urls = self.get_base_urls().await;
+        for (idx, base_url) in base_urls.iter().enumerate() {
             let url = Self::build_url(base_url, method, query_string);
-            let has_next = idx + 1 < V1_INTERNAL_BASE_URL_FALLBACKS.len();
+            let has_next = idx + 1 < base_urls.len();
 
             let response = client
                 .post(&url)
                 .headers(headers.clone())
-                .json(&body)
+                .body(payload.clone())
                 .send()
                 .await;
 
@@ -292,7 +346,7 @@ impl UpstreamClient {
                                 "✓ Upstream fallback succeeded | Endpoint: {} | Status: {} | Next endpoints available: {}",
                                 base_url,
                                 status,
-                                V1_INTERNAL_BASE_URL_FALLBACKS.len() - idx - 1
+                                base_urls.len() - idx - 1
                             );
                         } else {
                             tracing::debug!(
@@ -422,4 +476,53 @@ mod tests {
             "https://cloudcode-pa.googleapis.com/v1internal:streamGenerateContent?alt=sse"
         );
     }
+
+    #[tokio::test]
+    async fn test_base_url_override_targets_mock_server() {
+        use axum::{extract::OriginalUri, Json, Router};
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<(String, Value)>();
+        let app = Router::new().fallback(move |OriginalUri(uri): OriginalUri, Json(body): Json<Value>| {
+            let tx = tx.clone();
+            async move {
+                let _ = tx.send((uri.to_string(), body));
+                Json(serde_json::json!({ "ok": true }))
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        let client = UpstreamClient::new(None, None);
+        assert_eq!(client.get_base_urls().await.len(), V1_INTERNAL_BASE_URL_FALLBACKS.len());
+
+        // 末尾斜杠会被去除
+        client
+            .set_base_url_override(Some(format!("http://{}/v1internal/", addr)))
+            .await;
+        assert_eq!(client.get_base_urls().await, vec![format!("http://{}/v1internal", addr)]);
+
+        let result = client
+            .call_v1_internal(
+                "streamGenerateContent",
+                "test-token",
+                serde_json::json!({ "model": "gemini-3-flash" }),
+                Some("alt=sse"),
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(result.response.status().is_success());
+        assert!(result.fallback_attempts.is_empty());
+
+        let (path, body) = rx.recv().await.unwrap();
+        assert_eq!(path, "/v1internal:streamGenerateContent?alt=sse");
+        assert_eq!(body["model"], "gemini-3-flash");
+
+        // 非法值被忽略，恢复内置降级链
+        client.set_base_url_override(Some("ftp://invalid".to_string())).await;
+        assert_eq!(client.get_base_urls().await.len(), V1_INTERNAL_BASE_URL_FALLBACKS.len());
+    }
 }
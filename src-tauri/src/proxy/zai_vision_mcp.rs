@@ -1,6 +1,6 @@
+use parking_lot::Mutex;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
 #[derive(Debug, Clone, Default)]
 pub struct ZaiVisionMcpState {
@@ -20,7 +20,8 @@ impl ZaiVisionMcpState {
 
     pub async fn create_session(&self) -> String {
         let session_id = uuid::Uuid::new_v4().to_string();
-        let mut sessions = self.sessions.lock().await;
+        crate::proxy::session_registry::touch(&session_id);
+        let mut sessions = self.sessions.lock();
         sessions.insert(
             session_id.clone(),
             ZaiVisionSession {
@@ -31,13 +32,44 @@ impl ZaiVisionMcpState {
     }
 
     pub async fn has_session(&self, session_id: &str) -> bool {
-        let sessions = self.sessions.lock().await;
-        sessions.contains_key(session_id)
+        let sessions = self.sessions.lock();
+        let found = sessions.contains_key(session_id);
+        if found {
+            crate::proxy::session_registry::touch(session_id);
+        }
+        found
     }
 
     pub async fn remove_session(&self, session_id: &str) {
-        let mut sessions = self.sessions.lock().await;
+        let mut sessions = self.sessions.lock();
         sessions.remove(session_id);
     }
 }
 
+// [NEW] 客户端未发送 DELETE 就断开的 Vision MCP 会话由空闲会话回收清理
+impl crate::proxy::session_registry::SessionStateStore for ZaiVisionMcpState {
+    fn name(&self) -> &'static str {
+        "zai_vision_mcp"
+    }
+
+    fn evict_session(&self, session_id: &str) -> usize {
+        self.sessions.lock().remove(session_id).map_or(0, |_| 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::session_registry::SessionStateStore;
+
+    #[tokio::test]
+    async fn test_abandoned_session_is_evicted() {
+        let state = ZaiVisionMcpState::new();
+        let session_id = state.create_session().await;
+        assert!(state.has_session(&session_id).await);
+
+        assert_eq!(state.evict_session(&session_id), 1);
+        assert!(!state.has_session(&session_id).await);
+        assert_eq!(state.evict_session(&session_id), 0);
+    }
+}
@@ -20,6 +20,8 @@ import { listen } from '@tauri-apps/api/event';
 import { isTauri } from './utils/env';
 import { request as invoke } from './utils/request';
 import { AdminAuthGuard } from './components/common/AdminAuthGuard';
+import { showToast } from './components/common/ToastContainer';
+import { prepareReauthOAuth, startOAuthLogin } from './services/accountService';
 
 const router = createBrowserRouter([
   {
@@ -65,7 +67,7 @@ const router = createBrowserRouter([
 function App() {
   const { config, loadConfig } = useConfigStore();
   const { fetchCurrentAccount, fetchAccounts } = useAccountStore();
-  const { i18n } = useTranslation();
+  const { t, i18n } = useTranslation();
 
   useEffect(() => {
     loadConfig();
@@ -107,13 +109,40 @@ function App() {
       })
     );
 
+    // [NEW] 监听账号 OAuth 权限不足事件，提示用户重新授权
+    unlistenPromises.push(
+      listen<{ account_id: string; email: string; reason: string }>('proxy://reauth-required', async (event) => {
+        const { email } = event.payload;
+        fetchAccounts();
+        if (!confirm(t('accounts.reauth.confirm', { email, defaultValue: `Account ${email} is missing required OAuth scopes and has been disabled. Re-authorize now?` }))) {
+          return;
+        }
+        try {
+          await prepareReauthOAuth(email);
+          await startOAuthLogin();
+          showToast(t('accounts.reauth.success', 'Re-authorization completed'), 'success');
+        } catch (error) {
+          showToast(String(error), 'error');
+        } finally {
+          fetchAccounts();
+        }
+      })
+    );
+
+    // [NEW] 临时优先账号到期提示
+    unlistenPromises.push(
+      listen<{ account_id: string; expires_at: number }>('proxy://preferred-account-expired', (event) => {
+        showToast(t('proxy.preferred_override.expired', { id: event.payload.account_id, defaultValue: `Temporary preferred account ${event.payload.account_id} expired` }), 'info');
+      })
+    );
+
     // Cleanup
     return () => {
       Promise.all(unlistenPromises).then(unlisteners => {
         unlisteners.forEach(unlisten => unlisten());
       });
     };
-  }, [fetchCurrentAccount, fetchAccounts]);
+  }, [fetchCurrentAccount, fetchAccounts, t]);
 
   // Update notification state
   const [showUpdateNotification, setShowUpdateNotification] = useState(false);
@@ -1,11 +1,12 @@
 import { useMemo, useState } from 'react';
-import { ArrowRightLeft, RefreshCw, Trash2, Download, Info, Lock, Ban, Diamond, Gem, Circle, ToggleLeft, ToggleRight, Fingerprint, Sparkles, Tag, X, Check } from 'lucide-react';
+import { ArrowRightLeft, RefreshCw, Trash2, Download, Info, Lock, Ban, Diamond, Gem, Circle, Clock, ToggleLeft, ToggleRight, Fingerprint, Sparkles, Tag, X, Check } from 'lucide-react';
 import { Account } from '../../types/account';
 import { cn } from '../../utils/cn';
 import { useTranslation } from 'react-i18next';
 import { useConfigStore } from '../../stores/useConfigStore';
 import { QuotaItem } from './QuotaItem';
 import { MODEL_CONFIG, sortModels } from '../../config/modelConfig';
+import { formatTimeRemaining } from '../../utils/format';
 
 interface AccountCardProps {
     account: Account;
@@ -164,6 +165,18 @@ function AccountCard({ account, selected, onSelect, isCurrent: propIsCurrent, is
                                     {t('accounts.forbidden').toUpperCase()}
                                 </span>
                             )}
+                            {account.quota?.models.some(m => m.unparsed) && (
+                                <span className="px-1.5 py-0.5 rounded-md bg-yellow-100 dark:bg-yellow-900/40 text-yellow-700 dark:text-yellow-300 text-[9px] font-bold flex items-center gap-1 shadow-sm border border-yellow-200/50" title={t('accounts.quota_unparsed_tooltip')}>
+                                    <Info className="w-2.5 h-2.5" />
+                                    {t('accounts.quota_unparsed').toUpperCase()}
+                                </span>
+                            )}
+                            {account.warmup_remaining_secs !== undefined && account.warmup_remaining_secs > 0 && (
+                                <span className="px-1.5 py-0.5 rounded-md bg-amber-100 dark:bg-amber-900/40 text-amber-700 dark:text-amber-300 text-[9px] font-bold flex items-center gap-1 shadow-sm border border-amber-200/50" title={t('accounts.warming_up_tooltip')}>
+                                    <Clock className="w-2.5 h-2.5" />
+                                    {t('accounts.warming_up', { time: formatTimeRemaining(new Date(Date.now() + account.warmup_remaining_secs * 1000).toISOString()) }).toUpperCase()}
+                                </span>
+                            )}
                             {/* 订阅类型徽章 */}
                             {account.quota?.subscription_tier && (() => {
                                 const tier = account.quota.subscription_tier.toLowerCase();
@@ -123,6 +123,20 @@ function AccountRow({ account, selected, onSelect, isCurrent, isRefreshing, isSw
                             </span>
                         )}
 
+                        {account.quota?.models.some(m => m.unparsed) && (
+                            <span className="px-2 py-0.5 rounded-md bg-yellow-100 dark:bg-yellow-900/50 text-yellow-700 dark:text-yellow-300 text-[10px] font-bold flex items-center gap-1 shadow-sm border border-yellow-200/50" title={t('accounts.quota_unparsed_tooltip')}>
+                                <Info className="w-2.5 h-2.5" />
+                                <span>{t('accounts.quota_unparsed')}</span>
+                            </span>
+                        )}
+
+                        {account.warmup_remaining_secs !== undefined && account.warmup_remaining_secs > 0 && (
+                            <span className="px-2 py-0.5 rounded-md bg-amber-100 dark:bg-amber-900/50 text-amber-700 dark:text-amber-300 text-[10px] font-bold flex items-center gap-1 shadow-sm border border-amber-200/50" title={t('accounts.warming_up_tooltip')}>
+                                <Clock className="w-2.5 h-2.5" />
+                                <span>{t('accounts.warming_up', { time: formatTimeRemaining(new Date(Date.now() + account.warmup_remaining_secs * 1000).toISOString()) })}</span>
+                            </span>
+                        )}
+
                         {/* 订阅类型徽章 */}
                         {account.quota?.subscription_tier && (() => {
                             const tier = account.quota.subscription_tier.toLowerCase();
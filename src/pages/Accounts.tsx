@@ -1,6 +1,7 @@
 
 
 import {
+  AlertTriangle,
   Download,
   LayoutGrid,
   List,
@@ -21,10 +22,14 @@ import DeviceFingerprintDialog from "../components/accounts/DeviceFingerprintDia
 import ModalDialog from "../components/common/ModalDialog";
 import Pagination from "../components/common/Pagination";
 import { showToast } from "../components/common/ToastContainer";
-import { exportAccounts } from "../services/accountService";
+import {
+  checkDeviceProfileConsistency,
+  exportAccounts,
+  regenerateCollidedDeviceProfiles,
+} from "../services/accountService";
 import { useAccountStore } from "../stores/useAccountStore";
 import { useConfigStore } from "../stores/useConfigStore";
-import { Account } from "../types/account";
+import { Account, DeviceConsistencyReport } from "../types/account";
 import { cn } from "../utils/cn";
 import { isTauri } from "../utils/env";
 import { request as invoke } from "../utils/request";
@@ -226,6 +231,38 @@ function Accounts() {
     fetchAccounts();
   }, []);
 
+  // 设备指纹冲突检查 (账号数量变化后重新检查)
+  const [deviceReport, setDeviceReport] = useState<DeviceConsistencyReport | null>(null);
+  const [regeneratingProfiles, setRegeneratingProfiles] = useState(false);
+  useEffect(() => {
+    checkDeviceProfileConsistency()
+      .then(setDeviceReport)
+      .catch((error) => console.error("Device consistency check failed:", error));
+  }, [accounts.length]);
+
+  const handleRegenerateCollidedProfiles = async () => {
+    if (!confirm(t("accounts.device_collision.confirm", "Generate new unique device fingerprints for all accounts sharing one? One account per shared fingerprint keeps its current profile."))) {
+      return;
+    }
+    setRegeneratingProfiles(true);
+    try {
+      const result = await regenerateCollidedDeviceProfiles();
+      setDeviceReport(result.report);
+      await fetchAccounts();
+      showToast(
+        t("accounts.device_collision.regenerated", {
+          count: result.regenerated_account_ids.length,
+          defaultValue: "Regenerated device fingerprints for {{count}} accounts",
+        }),
+        "success",
+      );
+    } catch (error) {
+      showToast(`${t("common.error")}: ${error}`, "error");
+    } finally {
+      setRegeneratingProfiles(false);
+    }
+  };
+
   // Reset pagination when view mode changes to avoid empty pages or confusion
   useEffect(() => {
     setCurrentPage(1);
@@ -1046,6 +1083,31 @@ function Accounts() {
         </div>
       </div>
 
+      {/* 设备指纹冲突告警 */}
+      {deviceReport && deviceReport.collisions.length > 0 && (
+        <div className="flex-none flex items-start gap-3 px-4 py-3 rounded-xl border border-amber-200 dark:border-amber-900/50 bg-amber-50 dark:bg-amber-900/20 text-amber-800 dark:text-amber-200 text-sm">
+          <AlertTriangle className="w-4 h-4 mt-0.5 shrink-0" />
+          <div className="flex-1 min-w-0 space-y-1">
+            {deviceReport.collisions.map((collision) => (
+              <div key={collision.fingerprint} className="truncate" title={collision.emails.join(", ")}>
+                {t("accounts.device_collision.shared", {
+                  count: collision.account_ids.length,
+                  defaultValue: "Device fingerprint shared by {{count}} accounts",
+                })}
+                <span className="ml-2 opacity-70">{collision.emails.join(", ")}</span>
+              </div>
+            ))}
+          </div>
+          <button
+            className="shrink-0 px-3 py-1 rounded-lg text-xs font-medium bg-amber-500 hover:bg-amber-600 text-white disabled:opacity-50"
+            onClick={handleRegenerateCollidedProfiles}
+            disabled={regeneratingProfiles}
+          >
+            {t("accounts.device_collision.regenerate", "Regenerate unique fingerprints")}
+          </button>
+        </div>
+      )}
+
       {/* 账号列表内容区域 */}
       <div className="flex-1 min-h-0 relative" ref={containerRef}>
         {viewMode === "list" ? (
@@ -43,6 +43,11 @@ interface ProxyStatus {
     port: number;
     base_url: string;
     active_accounts: number;
+    latency_slo?: {
+        degraded: boolean;
+        threshold_ms: number;
+        models: { model: string; p90_ms: number }[];
+    };
 }
 
 interface CustomPreset {
@@ -1107,6 +1112,14 @@ print(response.text)`;
                                             ? `${t('proxy.status.running')} (${status.active_accounts} ${t('common.accounts')})`
                                             : t('proxy.status.stopped')}
                                     </span>
+                                    {status.running && status.latency_slo?.degraded && (
+                                        <span
+                                            className="text-xs font-medium text-amber-600"
+                                            title={status.latency_slo.models.map(m => `${m.model}: p90 ${(m.p90_ms / 1000).toFixed(1)}s`).join('\n')}
+                                        >
+                                            {t('proxy.status.degraded')}
+                                        </span>
+                                    )}
                                 </div>
                             </div>
 
@@ -1,5 +1,6 @@
 import React, { useEffect, useState, useRef, useCallback } from 'react';
 import { request as invoke } from '../utils/request';
+import type { QuotaForecast } from '../types/account';
 import { useTranslation } from 'react-i18next';
 import { AreaChart, Area, BarChart, Bar, XAxis, YAxis, CartesianGrid, Tooltip, ResponsiveContainer, PieChart, Pie, Cell, Legend } from 'recharts';
 import { Clock, Calendar, CalendarDays, Users, Zap, TrendingUp, RefreshCw, Cpu } from 'lucide-react';
@@ -44,6 +45,11 @@ interface TokenStatsSummary {
     total_tokens: number;
     total_requests: number;
     unique_accounts: number;
+    deduped_input_tokens: number;
+    deduped_output_tokens: number;
+    deduped_total_tokens: number;
+    deduped_requests: number;
+    quota_forecasts?: QuotaForecast[];
 }
 
 type TimeRange = 'hourly' | 'daily' | 'weekly';
@@ -382,6 +388,11 @@ const TokenStats: React.FC = () => {
                             <div className="text-2xl font-bold text-blue-600 dark:text-blue-400">
                                 {formatNumber(summary.total_input_tokens)}
                             </div>
+                            {summary.deduped_input_tokens < summary.total_input_tokens && (
+                                <div className="text-xs text-gray-400 dark:text-gray-500 mt-1">
+                                    {t('token_stats.deduped', '去重后')}: {formatNumber(summary.deduped_input_tokens)}
+                                </div>
+                            )}
                         </div>
                         <div className="bg-gradient-to-br from-purple-50/50 to-white dark:from-purple-900/10 dark:to-gray-800 rounded-xl p-4 shadow-sm border border-purple-100 dark:border-purple-900/30 hover:shadow-md transition-shadow">
                             <div className="flex items-center gap-2 text-purple-600/80 dark:text-purple-400/80 text-sm mb-2">
@@ -22,6 +22,10 @@ interface UserToken {
     last_used_at?: number;
     total_requests: number;
     total_tokens_used: number;
+    scope?: 'read' | 'admin';
+    admin_api_access?: boolean;
+    rate_limit_rpm?: number;
+    rate_limit_tpm?: number;
 }
 
 interface UserTokenStats {
@@ -49,6 +53,8 @@ const UserToken: React.FC = () => {
     const [editMaxIps, setEditMaxIps] = useState(0);
     const [editCurfewStart, setEditCurfewStart] = useState('');
     const [editCurfewEnd, setEditCurfewEnd] = useState('');
+    const [editRateLimitRpm, setEditRateLimitRpm] = useState(0);
+    const [editRateLimitTpm, setEditRateLimitTpm] = useState(0);
     const [updating, setUpdating] = useState(false);
 
     // Create Form State
@@ -147,6 +153,8 @@ const UserToken: React.FC = () => {
         setEditMaxIps(token.max_ips ?? 0);  // 使用 ?? 确保 null/undefined 变为 0
         setEditCurfewStart(token.curfew_start ?? '');
         setEditCurfewEnd(token.curfew_end ?? '');
+        setEditRateLimitRpm(token.rate_limit_rpm ?? 0);
+        setEditRateLimitTpm(token.rate_limit_tpm ?? 0);
         setShowEditModal(true);
     };
 
@@ -167,7 +175,10 @@ const UserToken: React.FC = () => {
                     max_ips: editMaxIps,
                     // 使用双层包装: undefined = 不更新, null = 清空, string = 设置值
                     curfew_start: editCurfewStart === '' ? null : editCurfewStart,
-                    curfew_end: editCurfewEnd === '' ? null : editCurfewEnd
+                    curfew_end: editCurfewEnd === '' ? null : editCurfewEnd,
+                    // 0 = 不限 (null 会被当作"不更新")
+                    rate_limit_rpm: editRateLimitRpm,
+                    rate_limit_tpm: editRateLimitTpm
                 }
             });
             showToast(t('common.update_success') || 'Updated successfully', 'success');
@@ -632,6 +643,33 @@ const UserToken: React.FC = () => {
                             </label>
                         </div>
 
+                        <div className="form-control w-full mb-3">
+                            <label className="label">
+                                <span className="label-text">{t('user_token.rate_limit', { defaultValue: 'Rate Limit (per minute)' })}</span>
+                            </label>
+                            <div className="flex gap-2 items-center">
+                                <input
+                                    type="number"
+                                    className="input input-bordered w-full"
+                                    value={editRateLimitRpm}
+                                    onChange={e => setEditRateLimitRpm(parseInt(e.target.value) || 0)}
+                                    min="0"
+                                    placeholder={t('user_token.placeholder_rate_limit_rpm', { defaultValue: 'Requests / min' })}
+                                />
+                                <input
+                                    type="number"
+                                    className="input input-bordered w-full"
+                                    value={editRateLimitTpm}
+                                    onChange={e => setEditRateLimitTpm(parseInt(e.target.value) || 0)}
+                                    min="0"
+                                    placeholder={t('user_token.placeholder_rate_limit_tpm', { defaultValue: 'Tokens / min' })}
+                                />
+                            </div>
+                            <label className="label">
+                                <span className="label-text-alt text-gray-500">{t('user_token.hint_rate_limit', { defaultValue: 'Requests / tokens per minute, 0 = Unlimited' })}</span>
+                            </label>
+                        </div>
+
                         <div className="form-control w-full mb-3">
                             <label className="label">
                                 <span className="label-text">{t('user_token.curfew', { defaultValue: 'Curfew (Service Unavailable Time)' })}</span>
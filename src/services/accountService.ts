@@ -1,5 +1,5 @@
 import i18n from '../i18n';
-import { Account, DeviceProfile, DeviceProfileVersion, QuotaData } from '../types/account';
+import { Account, DeviceConsistencyReport, DeviceProfile, DeviceProfileVersion, DeviceRegenerationResult, QuotaData } from '../types/account';
 import { request as invoke } from '../utils/request';
 
 // 检查环境 (可选)
@@ -75,6 +75,12 @@ export async function startOAuthLogin(): Promise<Account> {
     }
 }
 
+// 为权限不足的账号准备重新授权 (预填 login_hint)
+export async function prepareReauthOAuth(email: string): Promise<void> {
+    ensureTauriEnvironment();
+    return await invoke('prepare_reauth_oauth', { email });
+}
+
 export async function completeOAuthLogin(): Promise<Account> {
     ensureTauriEnvironment();
     try {
@@ -108,6 +114,34 @@ export async function importFromCustomDb(path: string): Promise<Account> {
     return await invoke('import_custom_db', { path });
 }
 
+export interface RefreshTokenImportFailure {
+    line: number;
+    email?: string | null;
+    reason: string;
+}
+
+export interface RefreshTokenImportReport {
+    imported: string[];
+    updated: string[];
+    failed: RefreshTokenImportFailure[];
+    skipped: RefreshTokenImportFailure[];
+    log_path?: string | null;
+}
+
+export async function importRefreshTokens(path: string, format?: 'csv' | 'json'): Promise<RefreshTokenImportReport> {
+    return await invoke('import_refresh_tokens', { path, format });
+}
+
+export interface OrphanCleanupReport {
+    dry_run: boolean;
+    temp_files: string[];
+    orphaned_accounts: string[];
+}
+
+export async function cleanupOrphanFiles(dryRun = false): Promise<OrphanCleanupReport> {
+    return await invoke('cleanup_orphan_files', { dryRun });
+}
+
 export async function syncAccountFromDb(): Promise<Account | null> {
     return await invoke('sync_account_from_db');
 }
@@ -167,6 +201,14 @@ export async function bindDeviceProfileWithProfile(accountId: string, profile: D
     return await invoke('bind_device_profile_with_profile', { accountId, profile });
 }
 
+export async function checkDeviceProfileConsistency(): Promise<DeviceConsistencyReport> {
+    return await invoke('check_device_profile_consistency');
+}
+
+export async function regenerateCollidedDeviceProfiles(): Promise<DeviceRegenerationResult> {
+    return await invoke('regenerate_collided_device_profiles');
+}
+
 // 预热相关
 export async function warmUpAllAccounts(): Promise<string> {
     return await invoke('warm_up_all_accounts');
@@ -14,6 +14,12 @@ export interface Account {
     proxy_disabled_at?: number;
     protected_models?: string[];
     custom_label?: string;  // 用户自定义标签
+    user_agent_override?: string;  // v1internal 信封 userAgent 覆盖
+    request_type_override?: string;  // v1internal 信封 requestType 覆盖
+    additional_project_ids?: string[];  // 可通过 X-Antigravity-Project 指定的其他 project
+    monthly_token_budget?: number;  // 每月 token 预算 (UTC 每月 1 日重置)
+    group?: string;  // 账号分组 (按分组隔离反代选号)
+    warmup_remaining_secs?: number;  // 新账号预热剩余时间 (秒，仅预热期内返回)
     created_at: number;
     last_used: number;
 }
@@ -32,12 +38,30 @@ export interface QuotaData {
     last_updated: number;
     is_forbidden?: boolean;
     subscription_tier?: string;  // 订阅类型: FREE/PRO/ULTRA
+    unparsed?: boolean;  // 存在无法解析剩余百分比的模型 (仅管理接口返回)
 }
 
 export interface ModelQuota {
     name: string;
     percentage: number;
     reset_time: string;
+    remaining_tokens?: number;  // 绝对剩余 token (上游提供时)
+    limit_tokens?: number;
+    reset_window_secs?: number;
+    unparsed?: boolean;  // 上游配额结构无法解析，percentage 无意义
+}
+
+export interface QuotaForecast {
+    account_id: string;
+    email: string;
+    model: string;
+    percentage: number;
+    remaining_tokens?: number | null;
+    limit_tokens?: number | null;
+    reset_time: string;
+    tokens_per_hour: number;
+    estimated_exhaustion_at?: number | null;
+    exhausts_before_reset?: boolean | null;
 }
 
 export interface DeviceProfile {
@@ -47,6 +71,24 @@ export interface DeviceProfile {
     sqm_id: string;
 }
 
+/** 被多个账号共用的设备指纹 */
+export interface DeviceProfileCollision {
+    fingerprint: string;
+    account_ids: string[];
+    emails: string[];
+}
+
+export interface DeviceConsistencyReport {
+    checked_at: number;
+    bound_accounts: number;
+    collisions: DeviceProfileCollision[];
+}
+
+export interface DeviceRegenerationResult {
+    regenerated_account_ids: string[];
+    report: DeviceConsistencyReport;
+}
+
 export interface DeviceProfileVersion {
     id: string;
     created_at: number;
@@ -20,10 +20,42 @@ export interface ProxyConfig {
     scheduling?: StickySessionConfig;
     experimental?: ExperimentalConfig;
     user_agent_override?: string;
+    upstream_base_url?: string; // [NEW] 上游 v1internal 基础 URL 覆盖 (为空则使用内置降级链)
     saved_user_agent?: string;
     thinking_budget?: ThinkingBudgetConfig;
     global_system_prompt?: GlobalSystemPromptConfig;
     image_thinking_mode?: 'enabled' | 'disabled'; // [NEW] 图像思维模式开关
+    image_text_fallback_model?: string; // [NEW] 文本请求误映射到图像模型时的回退模型
+    forced_response_language?: string; // [NEW] 强制回复语言 (BCP-47 标签，如 ja；为空则不注入)
+    image_multimodal_output?: boolean; // [NEW] 图像模型同时输出文本与图片 (responseModalities)
+    strict_role_alternation?: boolean; // [NEW] 合并连续同角色消息 (默认开启)
+    drop_code_execution_parts?: boolean; // [NEW] 丢弃 executableCode / codeExecutionResult (默认渲染为代码块)
+    citation_style?: 'markdown' | 'footnotes' | 'inline_links' | 'off'; // [NEW] 联网搜索引文渲染样式
+    tool_limit?: ToolLimitConfig; // [NEW] 工具数量上限
+    envelope_sanitize_action?: EnvelopeSanitizeAction; // [NEW] 客户端字段写入信封时的违规处理 (默认截断)
+    latency_slo?: LatencySloConfig; // [NEW] 流式首字延迟 SLO 告警
+    max_account_rotations?: number; // [NEW] 429 等账号级错误时最多轮换账号次数
+    max_concurrent_streams_per_account?: number; // [NEW] 每账号并发流上限 (默认 4，0 不限制；全部饱和时排队，超时返回 429)
+    session_idle_ttl_secs?: number; // [NEW] 会话空闲回收 TTL (秒，默认 6 小时)
+    stream_resumption?: boolean; // [NEW] 上游流中途断开时自动续写 (默认关闭)
+    thinking_loop_nudge?: boolean; // [NEW] 只输出思考即触达 MAX_TOKENS 时追问一次最终答案 (默认关闭)
+    poison_quarantine?: boolean; // [NEW] 重试耗尽后的 400 二分探测并替换问题历史消息 (默认关闭)
+    strip_historical_thinking_models?: string[]; // [NEW] 剥离历史 assistant 思考内容的模型 (子串匹配，空 = 关闭)
+    max_json_clean_depth?: number; // [NEW] 递归 JSON 清理最大深度 (默认 64，超出后停止深入)
+    token_refresh_ahead_secs?: number; // [NEW] token 预刷新提前量 (秒，默认 300)
+    recent_failure_window_secs?: number; // [NEW] 近期失败账号回避窗口 (秒，默认 10，0 = 关闭)
+    account_warmup?: AccountWarmupConfig; // [NEW] 新账号预热策略 (默认关闭)
+    dummy_thought?: DummyThoughtConfig; // [NEW] 历史 assistant 消息占位思考块文本
+    task_echo_dedup?: TaskEchoDedupConfig; // [NEW] 工具结果后重复任务文本去重 (默认开启，最小长度 20)
+    report_web_search_usage?: boolean; // [NEW] 联网搜索时上报 usage.server_tool_use.web_search_requests (默认开启)
+    delta_coalescing?: DeltaCoalescingConfig; // [NEW] 流式 delta 合并 (默认关闭)
+    request_id_prefix?: RequestIdPrefixConfig; // [NEW] v1internal requestId 前缀 (按协议)
+    protective_stop_sequences?: string[]; // [NEW] 始终注入的保护性停止序列 (与客户端停止序列合并，最多 5 个)
+    stream_redactions?: StreamRedactionRule[]; // [NEW] 流式输出脱敏规则 (默认无)
+    listener_profiles?: ListenerProfile[]; // [NEW] 额外监听配置档 (共享账号池)
+    client_profiles?: ClientProfileConfig[]; // [NEW] 自定义客户端配置档 (x-abv-client-profile 按名称选用)
+    feature_flags?: FeatureFlagConfig[]; // [NEW] 功能开关 (按会话百分比灰度)
+    api_key_groups?: Record<string, string>; // [NEW] API Key -> 账号分组映射 (映射的 Key 只使用该分组的账号)
     proxy_pool?: ProxyPoolConfig;
 }
 
@@ -45,6 +77,149 @@ export interface ThinkingBudgetConfig {
     custom_value: number;
     /** 思考强度 (仅在 mode=adaptive 时生效) */
     effort?: ThinkingEffort;
+    /** effort -> thinking budget 映射 (未显式指定 budget 时使用) */
+    effort_budgets?: EffortBudgetMap;
+    /** 未指定 budget 且无 effort 时的默认 thinking budget (未设置时 Claude 16000，OpenAI 24576) */
+    default_thinking_budget?: number;
+    /** 开启 thinking 时的最小预算 (默认 256，0 表示不限制) */
+    min_thinking_budget?: number;
+}
+
+/** effort 等级对应的 thinking budget */
+export interface EffortBudgetMap {
+    low: number;
+    medium: number;
+    high: number;
+}
+
+// ============================================================================
+// 工具数量上限配置
+// ============================================================================
+
+/** 超出上限时的处理方式 */
+export type ToolLimitAction = 'truncate' | 'error';
+
+/** 客户端字段写入信封时的违规处理方式 */
+export type EnvelopeSanitizeAction = 'truncate' | 'reject';
+
+/** 流式首字延迟 SLO 监控配置 */
+export interface LatencySloConfig {
+    enabled: boolean;
+    /** p90 首字延迟阈值 (毫秒) */
+    threshold_ms: number;
+    /** 滚动窗口大小 (请求数) */
+    window_size: number;
+    /** 告警冷却时间 (秒) */
+    cooldown_secs: number;
+}
+
+/** 模型组成本档位 */
+export type ModelCostTier = 'low' | 'high';
+
+/** 新账号预热策略 */
+export interface AccountWarmupConfig {
+    enabled: boolean;
+    /** 预热时长 (小时) */
+    hours: number;
+    /** 预热期允许的最高模型成本档位 (low = 仅 flash 组) */
+    max_cost_tier: ModelCostTier;
+    /** 预热期并发流上限占常规上限的百分比 (常规上限不限制时按默认上限计算) */
+    concurrency_percent: number;
+}
+
+/** 历史 assistant 消息占位思考块 (是否注入由 dummy_thought_injection 功能开关控制) */
+export interface DummyThoughtConfig {
+    /** 占位思考块文本 (默认 "Thinking...") */
+    text: string;
+}
+
+/** 工具结果之后重复的任务文本去重 */
+export interface TaskEchoDedupConfig {
+    enabled: boolean;
+    /** 参与去重的最小文本长度 (去除空白后的字符数，更短的重复文本保留) */
+    min_length: number;
+}
+
+/** 工具数量上限配置 */
+export interface ToolLimitConfig {
+    /** 最大工具声明数量 (未设置表示不限制) */
+    max_tools?: number;
+    /** 超出上限时截断或报错 */
+    on_exceed: ToolLimitAction;
+}
+
+/** 身份指令注入模式 */
+export type IdentityInjectionMode = 'auto' | 'off';
+
+/** 监听配置档：独立端口 + 独立默认行为，共享账号池 */
+export interface ListenerProfile {
+    name: string;
+    port: number;
+    enabled: boolean;
+    /** 客户端未指定 thinking 时的默认值 (未设置表示按模型默认) */
+    default_thinking?: boolean;
+    /** OFF / LOW / MEDIUM / HIGH / NONE */
+    safety_threshold?: string;
+    identity_injection: IdentityInjectionMode;
+    /** 允许访问该端口的客户端 Key (为空则不额外限制) */
+    allowed_client_keys?: string[];
+    /** 流式 delta 合并 (未设置表示使用全局配置) */
+    delta_coalescing?: DeltaCoalescingConfig;
+}
+
+/** 客户端能力标记 (未设置表示沿用基础适配器 / 默认行为) */
+export interface ClientCapabilities {
+    citations?: boolean;
+    grounding_text?: boolean;
+    reasoning_content?: boolean;
+    normalize_empty_system_prompt?: boolean;
+    min_text_delta_chars?: number;
+}
+
+/** 自定义客户端配置档：名称 + 能力标记，可继承内置适配器 */
+export interface ClientProfileConfig {
+    name: string;
+    /** 继承的内置适配器 (zed / opencode) */
+    extends?: string;
+    capabilities: ClientCapabilities;
+}
+
+export interface FeatureFlagConfig {
+    name: string;
+    enabled: boolean;
+    /** 灰度百分比 (0-100，按会话分桶)；未设置表示全量 */
+    rollout_percent?: number;
+}
+
+export interface RequestIdPrefixConfig {
+    /** 默认 agent */
+    claude: string;
+    /** 默认 openai */
+    openai: string;
+    /** 默认 agent */
+    gemini: string;
+}
+
+/** 流式脱敏规则作用的 delta 类别 */
+export type RedactionClass = 'thinking' | 'visible' | 'tool_input';
+
+/** 流式输出脱敏规则 (正则替换) */
+export interface StreamRedactionRule {
+    pattern: string;
+    /** 默认 "[REDACTED]" */
+    replacement?: string;
+    /** 默认只作用于可见文本 */
+    classes?: RedactionClass[];
+}
+
+export interface DeltaCoalescingConfig {
+    enabled: boolean;
+    /** 可见文本合并阈值 (字节) */
+    max_bytes: number;
+    /** 思考内容合并阈值 (字节) */
+    thinking_max_bytes: number;
+    /** 最长缓冲时间 (毫秒) */
+    max_delay_ms: number;
 }
 
 // ============================================================================
@@ -62,6 +237,10 @@ export interface GlobalSystemPromptConfig {
 export interface DebugLoggingConfig {
     enabled: boolean;
     output_dir?: string;
+    encrypt_at_rest?: boolean; // 抓包文件静态加密 (默认开启)
+    allow_query_overrides?: boolean; // [NEW] 允许 URL 查询参数覆盖 thinking/temperature/top_p/safety/model (默认关闭)
+    record_streams?: boolean; // [NEW] 录制 Claude 流式请求的上游原始 SSE 用于离线回放 (默认关闭)
+    raw_passthrough_max_bytes?: number; // [NEW] x-abv-include-raw 附带的原始 Gemini 响应大小上限 (默认 256 KiB)
 }
 
 export type SchedulingMode = 'CacheFirst' | 'Balance' | 'PerformanceFirst';
@@ -69,6 +248,7 @@ export type SchedulingMode = 'CacheFirst' | 'Balance' | 'PerformanceFirst';
 export interface StickySessionConfig {
     mode: SchedulingMode;
     max_wait_seconds: number;
+    min_health_score?: number; // [NEW] 最低健康分，低于该值的账号不参与选号 (默认 0.0)
 }
 
 export type ZaiDispatchMode = 'off' | 'exclusive' | 'pooled' | 'fallback';
@@ -116,6 +296,7 @@ export interface ExperimentalConfig {
     context_compression_threshold_l1?: number;
     context_compression_threshold_l2?: number;
     context_compression_threshold_l3?: number;
+    defer_message_start?: boolean; // [NEW] 收到首个真实内容前不发送 message_start (默认关闭)
 }
 
 export interface CircuitBreakerConfig {
@@ -123,6 +304,30 @@ export interface CircuitBreakerConfig {
     backoff_steps: number[];
 }
 
+export interface UsageExportConfig {
+    enabled: boolean;
+    run_at: string; // 每日执行时间 (本地时间 HH:MM)，导出前一天 (UTC)
+    export_dir?: string;
+    retention_days: number; // 0 表示永久保留
+    mask_emails: boolean;
+}
+
+export interface DbMaintenanceConfig {
+    enabled: boolean; // 定时维护开关 (手动执行不受影响)
+    interval_days: number;
+    token_stats_retention_days: number; // 以下保留天数 0 表示永久保留
+    request_log_retention_days: number;
+    ip_log_retention_days: number;
+    token_usage_log_retention_days: number;
+}
+
+export interface LogConfig {
+    max_file_size_mb: number; // app.log 超过该大小后轮转 (重启生效)
+    max_files: number; // 保留的轮转文件数 (app.log.1 .. app.log.N)
+    format: 'text' | 'json'; // json: 每行一个结构化事件
+    buffer_size: number; // 实时日志视图在内存中保留的条数
+}
+
 export interface AppConfig {
     language: string;
     theme: string;
@@ -144,6 +349,9 @@ export interface AppConfig {
     circuit_breaker: CircuitBreakerConfig; // [NEW] 熔断器配置
     proxy: ProxyConfig;
     cloudflared: CloudflaredConfig; // [NEW] Cloudflared 配置
+    usage_export?: UsageExportConfig; // [NEW] 用量定时导出配置
+    db_maintenance?: DbMaintenanceConfig; // [NEW] 代理数据库维护配置
+    logging?: LogConfig; // [NEW] 日志轮转与格式配置
 }
 
 // ============================================================================
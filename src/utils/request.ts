@@ -28,6 +28,8 @@ const COMMAND_MAPPING: Record<string, { url: string; method: 'GET' | 'POST' | 'D
   'restore_original_device': { url: '/api/accounts/restore-original', method: 'POST' },
   'restore_device_version': { url: '/api/accounts/:accountId/device-versions/:versionId/restore', method: 'POST' },
   'delete_device_version': { url: '/api/accounts/:accountId/device-versions/:versionId', method: 'DELETE' },
+  'check_device_profile_consistency': { url: '/api/accounts/device-consistency', method: 'GET' },
+  'regenerate_collided_device_profiles': { url: '/api/accounts/device-consistency/regenerate', method: 'POST' },
   'open_device_folder': { url: '/api/system/open-folder', method: 'POST' },
 
   // Proxy Control & Status
@@ -42,6 +44,7 @@ const COMMAND_MAPPING: Record<string, { url: string; method: 'GET' | 'POST' | 'D
   'check_proxy_health': { url: '/api/proxy/health-check/trigger', method: 'POST' },
   'get_preferred_account': { url: '/api/proxy/preferred-account', method: 'GET' },
   'set_preferred_account': { url: '/api/proxy/preferred-account', method: 'POST' },
+  'set_proxy_preferred_account': { url: '/api/proxy/preferred-account/override', method: 'POST' },
   'fetch_zai_models': { url: '/api/zai/models/fetch', method: 'POST' },
   'load_config': { url: '/api/config', method: 'GET' },
   'save_config': { url: '/api/config', method: 'POST' },
@@ -60,6 +63,8 @@ const COMMAND_MAPPING: Record<string, { url: string; method: 'GET' | 'POST' | 'D
   'is_debug_console_enabled': { url: '/api/debug/enabled', method: 'GET' },
   'get_debug_console_logs': { url: '/api/debug/logs', method: 'GET' },
   'clear_debug_console_logs': { url: '/api/debug/logs/clear', method: 'POST' },
+  'decrypt_debug_capture': { url: '/api/debug/captures/decrypt', method: 'POST' },
+  'rotate_debug_capture_key': { url: '/api/debug/captures/rotate-key', method: 'POST' },
 
   // CLI Sync
   'get_cli_sync_status': { url: '/api/proxy/cli/status', method: 'POST' },
@@ -85,6 +90,7 @@ const COMMAND_MAPPING: Record<string, { url: string; method: 'GET' | 'POST' | 'D
   'get_token_stats_model_trend_daily': { url: '/api/stats/token/model-trend/daily', method: 'GET' },
   'get_token_stats_account_trend_hourly': { url: '/api/stats/token/account-trend/hourly', method: 'GET' },
   'get_token_stats_account_trend_daily': { url: '/api/stats/token/account-trend/daily', method: 'GET' },
+  'get_quota_forecasts': { url: '/api/stats/quota-forecast', method: 'GET' },
   'clear_token_stats': { url: '/api/stats/token/clear', method: 'POST' },
 
   // System
@@ -152,6 +158,8 @@ const COMMAND_MAPPING: Record<string, { url: string; method: 'GET' | 'POST' | 'D
   'renew_user_token': { url: '/api/user-tokens/:id/renew', method: 'POST' },
   'delete_user_token': { url: '/api/user-tokens/:id', method: 'DELETE' },
   'update_user_token': { url: '/api/user-tokens/:id', method: 'PATCH' },
+  'mint_scoped_token': { url: '/api/user-tokens/scoped', method: 'POST' },
+  'revoke_scoped_token': { url: '/api/user-tokens/scoped/:id', method: 'DELETE' },
 
   // Proxy Pool (Web Mode Fix)
   'get_proxy_pool_config': { url: '/api/proxy/pool/config', method: 'GET' },